@@ -0,0 +1,189 @@
+// Version information for the About dialog and bug reports, and a manual update check
+// against a JSON release manifest. Nothing is downloaded; the release page is opened
+// through `open_url`.
+
+use crate::config;
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+// The update check gives up after this long
+const UPDATE_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Payload of `get_app_info`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfo {
+    pub version: String, // Crate version
+    pub git_hash: &'static str, // Short commit hash baked in at build time, or "unknown"
+    pub schema_version: usize,
+    pub database_path: String,
+    pub database_size: u64, // Bytes, including the write-ahead log
+    pub os: &'static str,
+    pub arch: &'static str,
+    pub offline_mode: bool,
+}
+
+pub async fn app_info(state: &AppState) -> AppInfo {
+    let (database_path, database_size) = {
+        let storage = state.storage.lock().await;
+        (storage.db_path().display().to_string(), storage.database_size().await)
+    };
+    AppInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: env!("LOCALCHAT_GIT_HASH"),
+        schema_version: crate::storage::schema_version(),
+        database_path,
+        database_size,
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        offline_mode: state.is_offline(),
+    }
+}
+
+// The release manifest: `{"version": "1.2.0", "notesUrl": "https://..."}`
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ReleaseManifest {
+    version: String,
+    #[serde(default, alias = "notes_url", alias = "url")]
+    notes_url: Option<String>,
+}
+
+/// Payload of `check_for_updates`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum UpdateCheck {
+    Disabled, // No manifest URL is set
+    #[serde(rename_all = "camelCase")]
+    Checked {
+        current_version: String,
+        latest_version: String,
+        release_notes_url: Option<String>,
+        update_available: bool,
+    },
+}
+
+/// Fetches the manifest at the `update_manifest_url` setting and compares its version with
+/// the running one.
+pub async fn check_for_updates(state: &AppState) -> Result<UpdateCheck, String> {
+    let manifest_url = {
+        let storage = state.storage.lock().await;
+        storage.get_setting(config::UPDATE_MANIFEST_URL_KEY).await
+            .map_err(|e| format!("Failed to read setting '{}': {}", config::UPDATE_MANIFEST_URL_KEY, e))?
+    };
+    let Some(manifest_url) = manifest_url.map(|url| url.trim().to_string()).filter(|url| !url.is_empty()) else {
+        return Ok(UpdateCheck::Disabled);
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(UPDATE_CHECK_TIMEOUT)
+        .build()
+        .map_err(|e| format!("Failed to set up the update check: {}", e))?;
+    let response = client.get(&manifest_url).send().await
+        .map_err(|e| if e.is_timeout() {
+            format!("No response from {} within {}s", manifest_url, UPDATE_CHECK_TIMEOUT.as_secs())
+        } else {
+            format!("Failed to reach {}: {}", manifest_url, e)
+        })?;
+    if !response.status().is_success() {
+        return Err(format!("{} returned {}", manifest_url, response.status()));
+    }
+    let manifest: ReleaseManifest = response.json().await
+        .map_err(|e| format!("The release manifest at {} is not valid: {}", manifest_url, e))?;
+
+    let current = parse_version(env!("CARGO_PKG_VERSION"))
+        .map_err(|e| format!("Invalid app version: {}", e))?;
+    let latest = parse_version(&manifest.version)
+        .map_err(|e| format!("The release manifest has an invalid version '{}': {}", manifest.version, e))?;
+    log::info!("Update check: running {}, latest {}", current, latest);
+    Ok(UpdateCheck::Checked {
+        update_available: is_newer(&current, &latest),
+        current_version: current.to_string(),
+        latest_version: latest.to_string(),
+        release_notes_url: manifest.notes_url,
+    })
+}
+
+// A version as manifests write it, with or without a leading "v"
+fn parse_version(version: &str) -> Result<semver::Version, semver::Error> {
+    semver::Version::parse(version.trim().trim_start_matches('v'))
+}
+
+// Semver precedence: a pre-release sorts before its release, so `1.2.0-beta.1` is no update
+// for `1.2.0`, and build metadata is ignored
+fn is_newer(current: &semver::Version, latest: &semver::Version) -> bool {
+    latest.cmp_precedence(current).is_gt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockProvider;
+    use crate::test_support::{MockResponse, MockServer, TestApp};
+
+    fn newer(current: &str, latest: &str) -> bool {
+        is_newer(&parse_version(current).unwrap(), &parse_version(latest).unwrap())
+    }
+
+    #[test]
+    fn versions_compare_by_semver_precedence() {
+        assert!(newer("1.2.3", "1.2.4"));
+        assert!(newer("1.2.3", "v1.10.0"));
+        assert!(newer("0.9.9", "1.0.0"));
+        assert!(!newer("1.2.3", "1.2.3"));
+        assert!(!newer("1.2.3", " v1.2.3 "));
+        assert!(!newer("1.2.3", "1.2.3+build.7"));
+        assert!(!newer("1.10.0", "1.9.9"));
+        assert!(!newer("2.0.0", "1.99.0"));
+
+        assert!(!newer("1.2.0", "1.2.0-beta.1"));
+        assert!(newer("1.2.0-beta.1", "1.2.0"));
+        assert!(newer("1.2.0-beta.1", "1.2.0-beta.2"));
+        assert!(newer("1.1.9", "1.2.0-rc.1"));
+        assert!(parse_version("1.2").is_err());
+        assert!(parse_version("latest").is_err());
+    }
+
+    #[tokio::test]
+    async fn update_checks_are_disabled_without_a_manifest_url() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        assert!(matches!(check_for_updates(&app.state).await.unwrap(), UpdateCheck::Disabled));
+        app.state.storage.lock().await.set_setting(config::UPDATE_MANIFEST_URL_KEY, "  ").await.unwrap();
+        assert!(matches!(check_for_updates(&app.state).await.unwrap(), UpdateCheck::Disabled));
+        let json = serde_json::to_value(check_for_updates(&app.state).await.unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({ "status": "disabled" }));
+    }
+
+    #[tokio::test]
+    async fn update_checks_compare_the_manifest_version() {
+        let server = MockServer::start(vec![
+            MockResponse::json(serde_json::json!({ "version": "v999.0.0", "notesUrl": "https://example.com/notes" })),
+            MockResponse::json(serde_json::json!({ "version": env!("CARGO_PKG_VERSION") })),
+        ])
+        .await;
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        app.state.storage.lock().await.set_setting(config::UPDATE_MANIFEST_URL_KEY, &server.url).await.unwrap();
+
+        let UpdateCheck::Checked { latest_version, release_notes_url, update_available, .. } = check_for_updates(&app.state).await.unwrap() else {
+            panic!("a manifest URL is set");
+        };
+        assert_eq!((latest_version.as_str(), release_notes_url.as_deref(), update_available), ("999.0.0", Some("https://example.com/notes"), true));
+        let UpdateCheck::Checked { update_available, .. } = check_for_updates(&app.state).await.unwrap() else {
+            panic!("a manifest URL is set");
+        };
+        assert!(!update_available);
+    }
+
+    #[tokio::test]
+    async fn update_checks_are_refused_offline() {
+        let server = MockServer::start(Vec::new()).await;
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        app.state.storage.lock().await.set_setting(config::UPDATE_MANIFEST_URL_KEY, &server.url).await.unwrap();
+        app.state.set_offline(true);
+
+        let refused = crate::commands::check_for_updates(app.command_state()).await.unwrap_err();
+        assert_eq!(refused.kind, crate::error::ErrorKind::OfflineMode);
+        assert!(server.requests().is_empty());
+    }
+}
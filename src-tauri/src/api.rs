@@ -1,19 +1,792 @@
 use crate::models::{Message, ModelConfig};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use futures::{stream, Stream, StreamExt};
-use eventsource_stream::Eventsource;
+use eventsource_stream::{EventStreamError, Eventsource};
+use std::collections::BTreeMap;
 use std::pin::Pin;
+use std::time::Duration;
+
+// Items yielded by a streaming chat request
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    Delta(String), // A piece of assistant content
+    ToolCalls(Vec<ToolCall>), // Fully assembled tool calls, sent just before Finished
+    Usage(TokenUsage), // Token counts, reported by the provider after the last choice chunk
+    Annotations(Vec<serde_json::Value>), // Citations or source annotations, passed through as sent
+    Finished(String), // The finish_reason reported by the provider (e.g. "stop", "length")
+}
+
+// Token counts reported for one request
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+// A function call requested by the model
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ToolCall {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String, // Always "function" for now
+    pub function: ToolCallFunction,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct ToolCallFunction {
+    pub name: String,
+    pub arguments: String, // JSON-encoded arguments, as produced by the model
+}
+
+// Assembles tool calls from the fragments spread across stream chunks.
+// The first fragment of a call carries its id and name; later ones only
+// append to `arguments`. Fragments are matched up by their `index`.
+#[derive(Debug, Default)]
+struct ToolCallAccumulator {
+    calls: Vec<ToolCall>,
+}
+
+impl ToolCallAccumulator {
+    fn push(&mut self, fragment: &OpenAIToolCallDelta) {
+        let index = fragment.index as usize;
+        if self.calls.len() <= index {
+            self.calls.resize_with(index + 1, ToolCall::default);
+        }
+        let call = &mut self.calls[index];
+        if let Some(id) = &fragment.id {
+            call.id = id.clone();
+        }
+        if let Some(kind) = &fragment.kind {
+            call.kind = kind.clone();
+        }
+        if let Some(function) = &fragment.function {
+            if let Some(name) = &function.name {
+                call.function.name.push_str(name);
+            }
+            if let Some(arguments) = &function.arguments {
+                call.function.arguments.push_str(arguments);
+            }
+        }
+    }
+
+    fn take(&mut self) -> Vec<ToolCall> {
+        std::mem::take(&mut self.calls)
+            .into_iter()
+            .map(|mut call| {
+                if call.kind.is_empty() {
+                    call.kind = "function".to_string();
+                }
+                call
+            })
+            .collect()
+    }
+}
+
+/// An error the server reported inside the stream body (`{"error": {...}}`) rather than
+/// as an HTTP status. Streams yield it as an `Err`; recover it with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProviderStreamError {
+    pub message: String,
+    pub code: Option<String>, // Provider's error code, or its error type when no code is given
+}
+
+impl std::fmt::Display for ProviderStreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{} ({})", self.message, code),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for ProviderStreamError {}
+
+/// A request the server answered with a non-success HTTP status. Recover it with `downcast_ref`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpStatusError {
+    pub request: &'static str, // "stream" or "non-stream", for the message
+    pub status: u16,
+    pub body: String, // Redacted error body
+    pub retry_after: Option<Duration>, // From the `Retry-After` header, see `parse_retry_after`
+}
+
+impl std::fmt::Display for HttpStatusError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = reqwest::StatusCode::from_u16(self.status).map_or_else(|_| self.status.to_string(), |status| status.to_string());
+        write!(f, "API {} request failed with status {}: {}", self.request, status, self.body)
+    }
+}
+
+impl std::error::Error for HttpStatusError {}
+
+// Longest wait a `Retry-After` header is trusted for
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// Reads a `Retry-After` header value: a number of seconds or an HTTP date. Dates already
+/// past mean no wait; anything longer than `MAX_RETRY_AFTER` is clamped to it.
+pub fn parse_retry_after(value: &str, now: chrono::DateTime<chrono::Utc>) -> Option<Duration> {
+    let value = value.trim();
+    let delay = match value.parse::<u64>() {
+        Ok(seconds) => Duration::from_secs(seconds),
+        Err(_) => {
+            let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+            (date.with_timezone(&chrono::Utc) - now).to_std().unwrap_or(Duration::ZERO)
+        }
+    };
+    Some(delay.min(MAX_RETRY_AFTER))
+}
+
+fn retry_after_header(response: &reqwest::Response) -> Option<Duration> {
+    let value = response.headers().get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    parse_retry_after(value, chrono::Utc::now())
+}
+
+/// What kind of failure a provider request ran into, as far as retrying is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiError {
+    Status(u16), // The server answered with this non-success HTTP status
+    Network, // Connecting, a timeout, or the connection dropping mid-response
+    Server, // An error the server reported inside the stream body
+    Parse, // A response that could not be read as the expected JSON or event stream
+    Other,
+}
+
+impl ApiError {
+    pub fn classify(e: &anyhow::Error) -> Self {
+        if let Some(status) = e.downcast_ref::<HttpStatusError>() {
+            return ApiError::Status(status.status);
+        }
+        if e.downcast_ref::<ProviderStreamError>().is_some() {
+            return ApiError::Server;
+        }
+        for cause in e.chain() {
+            if let Some(stream_error) = cause.downcast_ref::<EventStreamError<reqwest::Error>>() {
+                return match stream_error {
+                    EventStreamError::Transport(_) => ApiError::Network,
+                    EventStreamError::Utf8(_) | EventStreamError::Parser(_) => ApiError::Parse,
+                };
+            }
+            if let Some(request_error) = cause.downcast_ref::<reqwest::Error>() {
+                return if request_error.is_decode() { ApiError::Parse } else { ApiError::Network };
+            }
+            if cause.is::<serde_json::Error>() {
+                return ApiError::Parse;
+            }
+        }
+        ApiError::Other
+    }
+
+    /// How long the server asked to wait before sending the request again, if it said.
+    pub fn retry_after(e: &anyhow::Error) -> Option<Duration> {
+        e.downcast_ref::<HttpStatusError>().and_then(|status| status.retry_after)
+    }
+
+    /// Whether the same request may succeed if sent again: rate limits, server-side (5xx)
+    /// failures and network trouble. Rejected requests, auth failures and unreadable
+    /// responses will fail the same way again.
+    pub fn is_retryable(self) -> bool {
+        match self {
+            ApiError::Status(status) => status == 408 || status == 429 || (500..600).contains(&status),
+            ApiError::Network => true,
+            ApiError::Server | ApiError::Parse | ApiError::Other => false,
+        }
+    }
+}
+
+// Recognizes an error payload in a stream chunk. OpenAI and Together nest it under
+// `error`, some vLLM versions send it at the top level with `"object": "error"`, and a
+// few servers send `error` as a bare string.
+fn stream_error_from_json(value: &serde_json::Value) -> Option<ProviderStreamError> {
+    let error = match value.get("error") {
+        Some(serde_json::Value::String(message)) => {
+            return Some(ProviderStreamError { message: message.clone(), code: None });
+        }
+        Some(error) if error.is_object() => error,
+        _ if value.get("object").and_then(|o| o.as_str()) == Some("error") => value,
+        _ => return None,
+    };
+    let message = error
+        .get("message")
+        .and_then(|m| m.as_str())
+        .filter(|m| !m.is_empty())
+        .unwrap_or("The server reported an error during streaming")
+        .to_string();
+    let code = [error.get("code"), error.get("type")]
+        .into_iter()
+        .flatten()
+        .find_map(|value| match value {
+            serde_json::Value::String(code) if !code.is_empty() => Some(code.clone()),
+            serde_json::Value::Number(code) => Some(code.to_string()),
+            _ => None,
+        });
+    Some(ProviderStreamError { message, code })
+}
+
+// Turns the data of each SSE event into the stream events it carries. Keeps the state
+// that spans chunks: tool call fragments and the field content was last found in.
+struct StreamChunkParser {
+    delta_path: Option<String>,
+    content_field: Option<&'static str>,
+    tool_calls: ToolCallAccumulator,
+}
+
+impl StreamChunkParser {
+    fn new(options: &ParsedProviderOptions) -> Self {
+        Self {
+            delta_path: options.delta_path.clone().filter(|path| !path.is_empty()),
+            content_field: None,
+            tool_calls: ToolCallAccumulator::default(),
+        }
+    }
+
+    fn parse(&mut self, event_data: &str) -> Result<Vec<StreamEvent>> {
+        let event_data = event_data.trim();
+
+        // Keep-alives: SSE comments never become events, but some servers send empty ones
+        if event_data.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Check for the special [DONE] message
+        if event_data == "[DONE]" {
+            log::info!("Stream finished with [DONE]");
+            return Ok(Vec::new()); // Signal end of content stream
+        }
+
+        let json_value = match serde_json::from_str::<serde_json::Value>(event_data) {
+            Ok(json_value) => json_value,
+            Err(e) => {
+                log::warn!("Failed to parse stream chunk as JSON: {} - Data: {}", e, event_data);
+                return Err(anyhow::Error::from(e).context(format!("Failed to parse stream chunk as JSON: {}", event_data)));
+            }
+        };
+        // Every chunk field is optional, so error payloads would also pass as (empty) chunks
+        if let Some(error) = stream_error_from_json(&json_value) {
+            log::error!("Server reported an error mid-stream: {}", error);
+            return Err(anyhow::Error::new(error));
+        }
+        if json_value.get("type") == Some(&serde_json::Value::String("ping".to_string())) {
+            log::debug!("Received stream ping event, skipping.");
+            return Ok(Vec::new()); // Skip ping
+        }
+
+        let chunk = match serde_json::from_value::<OpenAIStreamChunk>(json_value) {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                // Valid JSON of some other shape (server status events and the like)
+                log::debug!("Skipping stream event that isn't a chunk ({}): {}", e, event_data);
+                return Ok(Vec::new());
+            }
+        };
+
+        // Extract content and finish reason. Role-only first chunks and chunks without
+        // choices carry nothing.
+        let mut events = Vec::new();
+        let delta = chunk.choices.first().map(|choice| &choice.delta);
+        if let Some((content, field)) = delta_content(delta, event_data, self.delta_path.as_deref()) {
+            if self.content_field != Some(field) {
+                log::debug!("Stream content found in {}", field);
+                self.content_field = Some(field);
+            }
+            events.push(StreamEvent::Delta(content));
+        }
+        let annotations: Vec<serde_json::Value> = delta.and_then(|delta| delta.annotations.clone()).into_iter().flatten()
+            .chain(chunk.citations.iter().flatten().cloned())
+            .collect();
+        if !annotations.is_empty() {
+            events.push(StreamEvent::Annotations(annotations));
+        }
+        if let Some(choice) = chunk.choices.first() {
+            for fragment in choice.delta.tool_calls.iter().flatten() {
+                self.tool_calls.push(fragment);
+            }
+            if let Some(reason) = choice.finish_reason.clone() {
+                log::info!("Stream reported finish_reason: {}", reason);
+                let calls = self.tool_calls.take();
+                if !calls.is_empty() {
+                    events.push(StreamEvent::ToolCalls(calls));
+                }
+                events.push(StreamEvent::Finished(reason));
+            }
+        }
+        if let Some(usage) = chunk.usage {
+            events.push(StreamEvent::Usage(usage));
+        }
+        Ok(events)
+    }
+}
 
 // Alias for the stream type we'll return
-pub type DeltaStream = Pin<Box<dyn Stream<Item = Result<String>> + Send>>;
+pub type DeltaStream = Pin<Box<dyn Stream<Item = Result<StreamEvent>> + Send>>;
+
+// Typed view of the `provider_options` JSON stored on a ModelConfig
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct ParsedProviderOptions {
+    pub model: Option<String>,
+    pub max_tokens: Option<u32>,
+    // End-user identifier forwarded as the request `user` field (abuse monitoring)
+    pub user_id: Option<String>,
+    // Tool definitions passed through verbatim as the request `tools` array
+    pub tools: Option<serde_json::Value>,
+    // Pricing in USD per million tokens; when set, usage is requested and costed
+    pub input_cost_per_mtok: Option<f64>,
+    pub output_cost_per_mtok: Option<f64>,
+    // Model context size in tokens; when set, old history is trimmed to fit
+    pub context_window: Option<u32>,
+    // Retry once when a stream finishes without any content
+    pub retry_on_empty: Option<bool>,
+    // Re-request with the partial answer as context when the connection drops mid-stream
+    pub resume_on_disconnect: Option<bool>,
+    // Role system messages are sent as, one of SYSTEM_ROLES; "system" when unset
+    pub system_role: Option<String>,
+    // Sent as a `developer` message right after the system prompt of every request
+    pub developer_instruction: Option<String>,
+    // Chat completions path joined to `api_url`; DEFAULT_CHAT_PATH when unset
+    pub chat_path: Option<String>,
+    // Extra request headers; these win over the provider's default headers
+    pub headers: Option<BTreeMap<String, String>>,
+    // JSON pointer to stream chunk content, tried when neither `delta.content` nor `delta.text` has any
+    pub delta_path: Option<String>,
+    // Mark the system prompt with `cache_control` for gateways that pass it on to Anthropic models
+    pub enable_prompt_caching: Option<bool>,
+    // Join back-to-back user (or assistant) messages into one before sending
+    pub merge_consecutive_roles: Option<bool>,
+    // Token ID (as a string) -> bias in LOGIT_BIAS_RANGE, sent as the request `logit_bias`
+    pub logit_bias: Option<BTreeMap<String, f64>>,
+}
+
+// Roles the system prompt may be sent as
+pub const SYSTEM_ROLES: &[&str] = &["system", "developer"];
+
+// Biases OpenAI accepts in `logit_bias`
+pub const LOGIT_BIAS_RANGE: std::ops::RangeInclusive<f64> = -100.0..=100.0;
+
+// Path appended to `api_url` for chat requests unless the config sets `chat_path`
+pub const DEFAULT_CHAT_PATH: &str = "/chat/completions";
+
+impl ParsedProviderOptions {
+    pub fn from_config(config: &ModelConfig) -> Result<Self> {
+        let options_json = config.provider_options.as_deref().unwrap_or("{}");
+        serde_json::from_str(options_json).context("Failed to parse provider_options JSON")
+    }
+
+    /// URL chat requests are posted to: `api_url` joined with `chat_path`, or DEFAULT_CHAT_PATH.
+    pub fn chat_url(&self, api_url: &str) -> Result<String> {
+        let path = self.chat_path.as_deref().map(str::trim).filter(|path| !path.is_empty());
+        join_relative_path(api_url, path.unwrap_or(DEFAULT_CHAT_PATH))
+    }
+
+    /// The `logit_bias` map to send: `None` when unset or empty, an error when a bias is
+    /// outside LOGIT_BIAS_RANGE or a key isn't a token ID.
+    pub fn checked_logit_bias(&self) -> Result<Option<BTreeMap<String, f64>>> {
+        let Some(bias) = self.logit_bias.as_ref().filter(|bias| !bias.is_empty()) else {
+            return Ok(None);
+        };
+        for (token, value) in bias {
+            if let Some(problem) = logit_bias_entry_error(token, *value) {
+                return Err(anyhow::anyhow!("Invalid provider_options: logit_bias: {}", problem));
+            }
+        }
+        Ok(Some(bias.clone()))
+    }
+
+    /// The options that shape the answer as the request body carries them, for recording with
+    /// the message they produced. Unset ones are left out.
+    pub fn request_params(&self) -> serde_json::Map<String, serde_json::Value> {
+        let mut params = serde_json::Map::new();
+        let mut insert = |key: &str, value: Option<serde_json::Value>| {
+            if let Some(value) = value {
+                params.insert(key.to_string(), value);
+            }
+        };
+        insert("model", self.model.clone().map(serde_json::Value::from));
+        insert("max_tokens", self.max_tokens.map(serde_json::Value::from));
+        insert("system_role", self.system_role.clone().map(serde_json::Value::from));
+        insert("developer_instruction", self.developer_instruction.clone().map(serde_json::Value::from));
+        insert("tools", self.tools.clone());
+        insert("logit_bias", self.logit_bias.clone().filter(|bias| !bias.is_empty()).map(|bias| serde_json::json!(bias)));
+        params
+    }
+
+    /// The `user` field to send: `user_id`, or `None` when unset or empty.
+    pub fn user(&self) -> Option<String> {
+        self.user_id.clone().filter(|id| !id.is_empty())
+    }
+
+    // Whether the config carries pricing, i.e. whether usage is worth requesting
+    pub fn has_pricing(&self) -> bool {
+        self.input_cost_per_mtok.is_some() || self.output_cost_per_mtok.is_some()
+    }
+
+    /// Cost of `usage` in USD, or `None` when the config has no pricing.
+    pub fn cost_usd(&self, usage: &TokenUsage) -> Option<f64> {
+        if !self.has_pricing() {
+            return None;
+        }
+        let input = usage.prompt_tokens as f64 * self.input_cost_per_mtok.unwrap_or(0.0);
+        let output = usage.completion_tokens as f64 * self.output_cost_per_mtok.unwrap_or(0.0);
+        Some((input + output) / 1_000_000.0)
+    }
+}
+
+// Describes one known key of a provider's `provider_options`, for validation and form rendering
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderOptionField {
+    pub key: &'static str,
+    pub kind: &'static str, // "string" | "integer" | "number" | "boolean" | "array" | "object"
+    pub required: bool,
+    pub default: Option<serde_json::Value>,
+    pub description: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<&'static [&'static str]>, // The only accepted values, for string options
+}
+
+/// Known `provider_options` keys for `provider`.
+/// Keep in sync with `ParsedProviderOptions` when adding options.
+pub fn provider_options_schema(provider: &str) -> Result<Vec<ProviderOptionField>> {
+    match provider {
+        "openai_compatible" => Ok(vec![
+            ProviderOptionField {
+                key: "model",
+                kind: "string",
+                required: true,
+                default: None,
+                description: "Model identifier sent with each request",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "max_tokens",
+                kind: "integer",
+                required: false,
+                default: None,
+                description: "Upper bound on completion tokens",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "user_id",
+                kind: "string",
+                required: false,
+                default: None,
+                description: "End-user identifier sent as the `user` field",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "tools",
+                kind: "array",
+                required: false,
+                default: None,
+                description: "Tool definitions the model may call",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "input_cost_per_mtok",
+                kind: "number",
+                required: false,
+                default: None,
+                description: "Prompt price in USD per million tokens",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "output_cost_per_mtok",
+                kind: "number",
+                required: false,
+                default: None,
+                description: "Completion price in USD per million tokens",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "context_window",
+                kind: "integer",
+                required: false,
+                default: None,
+                description: "Context size in tokens; older history is trimmed to fit",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "retry_on_empty",
+                kind: "boolean",
+                required: false,
+                default: Some(serde_json::json!(false)),
+                description: "Retry once when the response comes back empty",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "resume_on_disconnect",
+                kind: "boolean",
+                required: false,
+                default: Some(serde_json::json!(false)),
+                description: "When the connection drops mid-answer, ask again with the partial answer so the model continues it (for servers that continue a trailing assistant message)",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "enable_prompt_caching",
+                kind: "boolean",
+                required: false,
+                default: Some(serde_json::json!(false)),
+                description: "Mark the system prompt as cacheable (cache_control), for gateways serving Anthropic models; OpenAI caches automatically",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "merge_consecutive_roles",
+                kind: "boolean",
+                required: false,
+                default: Some(serde_json::json!(false)),
+                description: "Join consecutive user or assistant messages into one, for servers that require strict alternation",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "system_role",
+                kind: "string",
+                required: false,
+                default: Some(serde_json::json!("system")),
+                description: "Role the system prompt is sent as; newer OpenAI models use \"developer\"",
+                allowed: Some(SYSTEM_ROLES),
+            },
+            ProviderOptionField {
+                key: "developer_instruction",
+                kind: "string",
+                required: false,
+                default: None,
+                description: "Extra instruction sent as a developer message after the system prompt",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "chat_path",
+                kind: "string",
+                required: false,
+                default: Some(serde_json::json!(DEFAULT_CHAT_PATH)),
+                description: "Path of the chat completions endpoint, relative to the API URL",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "headers",
+                kind: "object",
+                required: false,
+                default: None,
+                description: "Extra HTTP headers sent with every request, as name/value strings",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "logit_bias",
+                kind: "object",
+                required: false,
+                default: None,
+                description: "Token ID to bias (-100 to 100) map, sent as logit_bias to steer which tokens are picked",
+                allowed: None,
+            },
+            ProviderOptionField {
+                key: "delta_path",
+                kind: "string",
+                required: false,
+                default: None,
+                description: "JSON pointer to streamed content for servers that don't use delta.content or delta.text, e.g. /choices/0/message/content",
+                allowed: None,
+            },
+        ]),
+        #[cfg(any(test, feature = "testing"))]
+        crate::mock::MOCK_PROVIDER => Ok(vec![ProviderOptionField {
+            key: "script",
+            kind: "array",
+            required: false,
+            default: None,
+            description: "Steps the mock stream plays back",
+            allowed: None,
+        }]),
+        other => Err(anyhow::anyhow!("Unsupported provider: {}", other)),
+    }
+}
+
+/// Provider names `provider_options_schema` knows about.
+pub fn supported_providers() -> Vec<&'static str> {
+    vec![
+        "openai_compatible",
+        #[cfg(any(test, feature = "testing"))]
+        crate::mock::MOCK_PROVIDER,
+    ]
+}
+
+/// `provider_options_schema` for every supported provider, keyed by provider name.
+pub fn all_provider_options_schemas() -> BTreeMap<&'static str, Vec<ProviderOptionField>> {
+    supported_providers()
+        .into_iter()
+        .filter_map(|provider| provider_options_schema(provider).ok().map(|schema| (provider, schema)))
+        .collect()
+}
+
+/// Result of checking provider_options without saving them.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderOptionsCheck {
+    pub ok: bool,
+    pub errors: Vec<String>, // One per offending field, as `validate_provider_options` words them
+}
+
+impl ProviderOptionsCheck {
+    /// Schema validation followed by parsing into `ParsedProviderOptions`, which catches
+    /// values the schema lets through but requests can't use.
+    pub fn run(provider: &str, provider_options: Option<&str>) -> Self {
+        let errors = match validate_provider_options(provider, provider_options) {
+            Ok(()) => {
+                let options_json = provider_options.unwrap_or("{}");
+                match serde_json::from_str::<ParsedProviderOptions>(options_json) {
+                    Ok(_) => Vec::new(),
+                    Err(e) => vec![format!("provider_options could not be read: {}", e)],
+                }
+            }
+            Err(errors) => errors,
+        };
+        Self { ok: errors.is_empty(), errors }
+    }
+}
+
+/// Checks `provider_options` against the provider's schema.
+/// Returns one message per offending field.
+pub fn validate_provider_options(provider: &str, provider_options: Option<&str>) -> std::result::Result<(), Vec<String>> {
+    let schema = provider_options_schema(provider).map_err(|e| vec![e.to_string()])?;
+    let options: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(provider_options.unwrap_or("{}"))
+            .map_err(|e| vec![format!("provider_options must be a JSON object: {}", e)])?;
+
+    let mut errors = Vec::new();
+    for (key, value) in &options {
+        let Some(field) = schema.iter().find(|f| f.key == key) else {
+            errors.push(format!("{}: unknown option", key));
+            continue;
+        };
+        if field.required && value.as_str().is_some_and(|v| v.trim().is_empty()) {
+            errors.push(format!("{}: cannot be empty ({})", key, field.description));
+            continue;
+        }
+        if let Some(allowed) = field.allowed {
+            if !value.as_str().is_some_and(|v| allowed.contains(&v)) {
+                errors.push(format!("{}: expected one of {}", key, allowed.join(", ")));
+                continue;
+            }
+        }
+        let valid = match field.kind {
+            "string" => value.is_string(),
+            "integer" => value.as_u64().is_some_and(|n| n > 0 && n <= u64::from(u32::MAX)),
+            "number" => value.as_f64().is_some_and(|n| n >= 0.0),
+            "boolean" => value.is_boolean(),
+            "array" => value.is_array(),
+            "object" => value.is_object(),
+            _ => true,
+        };
+        if !valid {
+            let expected = match field.kind {
+                "integer" => "a positive integer",
+                "number" => "a non-negative number",
+                "boolean" => "true or false",
+                "array" => "an array",
+                "object" => "an object",
+                _ => "a string",
+            };
+            errors.push(format!("{}: expected {}", key, expected));
+            continue;
+        }
+        if key == "chat_path" {
+            if let Err(e) = join_relative_path("", value.as_str().unwrap_or_default()) {
+                errors.push(format!("{}: {}", key, e));
+            }
+        }
+        if key == "delta_path" && value.as_str().is_some_and(|path| !path.is_empty() && !path.starts_with('/')) {
+            errors.push(format!("{}: must be a JSON pointer starting with '/'", key));
+        }
+        if key == "logit_bias" {
+            for (token, bias) in value.as_object().into_iter().flatten() {
+                let problem = match bias.as_f64() {
+                    Some(bias) => logit_bias_entry_error(token, bias),
+                    None => Some(format!("bias for token {} must be a number", token)),
+                };
+                if let Some(problem) = problem {
+                    errors.push(format!("{}: {}", key, problem));
+                }
+            }
+        }
+        if key == "headers" {
+            for (name, header_value) in value.as_object().into_iter().flatten() {
+                if let Err(e) = header_pair(name, header_value.as_str().unwrap_or_default()) {
+                    errors.push(format!("{}: {}", key, e));
+                } else if !header_value.is_string() {
+                    errors.push(format!("{}: value of '{}' must be a string", key, name));
+                }
+            }
+        }
+    }
+    for field in schema.iter().filter(|f| f.required) {
+        if !options.contains_key(field.key) {
+            errors.push(format!("{}: required ({})", field.key, field.description));
+        }
+    }
+
+    if errors.is_empty() { Ok(()) } else { Err(errors) }
+}
+
+// What is wrong with one `logit_bias` entry, if anything
+fn logit_bias_entry_error(token: &str, bias: f64) -> Option<String> {
+    if token.parse::<u32>().is_err() {
+        return Some(format!("'{}' is not a token ID", token));
+    }
+    if !LOGIT_BIAS_RANGE.contains(&bias) {
+        return Some(format!("bias {} for token {} is outside -100 to 100", bias, token));
+    }
+    None
+}
+
+// Parses one header, naming it in the error
+fn header_pair(name: &str, value: &str) -> Result<(HeaderName, HeaderValue)> {
+    let header_name = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("'{}' is not a valid header name", name))?;
+    let header_value = HeaderValue::from_str(value)
+        .with_context(|| format!("value of '{}' is not a valid header value", name))?;
+    Ok((header_name, header_value))
+}
+
+/// The provider's default headers overlaid with the config's `headers` option.
+/// Both streaming and non-streaming requests send this set.
+pub fn request_headers(provider: &dyn LLMApiProvider, options: &ParsedProviderOptions) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in provider.default_headers() {
+        headers.insert(name, HeaderValue::from_static(value));
+    }
+    for (name, value) in options.headers.iter().flatten() {
+        let (name, value) = header_pair(name, value).context("Invalid 'headers' in provider_options")?;
+        headers.insert(name, value);
+    }
+    Ok(headers)
+}
+
+/// What a provider implementation can do, so the UI only offers controls that will work.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderCapabilities {
+    pub streaming: bool, // Answers arrive as deltas
+    pub tools: bool, // Tool definitions are sent and tool calls come back
+    pub vision: bool, // Images can be sent with messages
+    pub embeddings: bool, // Text can be embedded
+    pub reasoning: bool, // Reasoning output is streamed separately from the answer
+}
 
 // Trait defining the interface for LLM API providers
 #[async_trait]
 pub trait LLMApiProvider: Send + Sync { 
-    // Returns a stream of content deltas.
+    // Headers every request to this provider needs, declared once per implementation.
+    // Names must be lowercase; user `headers` options override them.
+    fn default_headers(&self) -> Vec<(&'static str, &'static str)> {
+        Vec::new()
+    }
+
+    // Declared by each implementation; the default is a provider that only streams text
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities { streaming: true, tools: false, vision: false, embeddings: false, reasoning: false }
+    }
+
+    // Returns a stream of content deltas followed by the finish reason.
     async fn send_chat_stream_request(
         &self,
         config: &ModelConfig,
@@ -28,6 +801,51 @@ pub trait LLMApiProvider: Send + Sync {
         api_key: &str,
         messages: &[Message],
     ) -> Result<String>;
+
+    // Executes an arbitrary GET/POST against a path relative to the config's base URL.
+    async fn send_raw_request(
+        &self,
+        config: &ModelConfig,
+        api_key: &str,
+        method: RawMethod,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<RawResponse>;
+}
+
+// HTTP methods allowed through the raw passthrough
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RawMethod {
+    Get,
+    Post,
+}
+
+impl RawMethod {
+    pub fn parse(method: &str) -> Result<Self> {
+        match method.to_ascii_uppercase().as_str() {
+            "GET" => Ok(RawMethod::Get),
+            "POST" => Ok(RawMethod::Post),
+            other => Err(anyhow::anyhow!("Unsupported HTTP method '{}': only GET and POST are allowed", other)),
+        }
+    }
+}
+
+// Status and body returned by a raw passthrough request
+#[derive(Serialize, Debug, Clone)]
+pub struct RawResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// Joins a relative endpoint path onto a base URL.
+/// Absolute and protocol-relative URLs are rejected so the passthrough can only
+/// reach the configured provider host.
+pub fn join_relative_path(base_url: &str, path: &str) -> Result<String> {
+    let path = path.trim();
+    if path.contains("://") || path.starts_with("//") || path.contains('\\') {
+        return Err(anyhow::anyhow!("Only paths relative to the API URL are allowed, got '{}'", path));
+    }
+    Ok(format!("{}/{}", base_url.trim_end_matches('/'), path.trim_start_matches('/')))
 }
 
 // --- OpenAI Compatible Provider Implementation ---
@@ -38,37 +856,133 @@ struct OpenAIRequestBody {
     model: String, 
     messages: Vec<OpenAIMessage>,
     stream: bool, // Set to true
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<BTreeMap<String, f64>>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct OpenAIMessage {
     role: String,
-    content: String,
+    content: OpenAIContent,
+    // Participant name for multi-agent or `tool` messages; omitted when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
-// Response structure for STREAMING chunks
+// Message content: plain text, or text parts when a part needs extra fields such as
+// `cache_control`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum OpenAIContent {
+    Text(String),
+    Parts(Vec<OpenAIContentPart>),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct OpenAIContentPart {
+    #[serde(rename = "type")]
+    kind: String, // "text"
+    #[serde(default)]
+    text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cache_control: Option<serde_json::Value>,
+}
+
+impl OpenAIContent {
+    fn into_text(self) -> String {
+        match self {
+            OpenAIContent::Text(text) => text,
+            OpenAIContent::Parts(parts) => parts.into_iter().map(|part| part.text).collect(),
+        }
+    }
+}
+
+// Response structure for STREAMING chunks. Local servers (vLLM, llama.cpp, LM Studio) leave out
+// some of these fields or send chunks with no choices, so only the delta matters.
 #[derive(Deserialize, Debug)]
 struct OpenAIStreamChunk {
-    id: String,
-    object: String,
-    created: i64,
-    model: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    object: Option<String>,
+    #[serde(default)]
+    created: Option<i64>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
     choices: Vec<OpenAIStreamChoice>,
+    // Only on the final chunk, when usage was requested via stream_options
+    #[serde(default)]
+    usage: Option<TokenUsage>,
+    // Source links some search-backed providers attach to the chunk rather than the delta
+    #[serde(default)]
+    citations: Option<Vec<serde_json::Value>>,
 }
 
 #[derive(Deserialize, Debug)]
 struct OpenAIStreamChoice {
+    #[serde(default)]
     index: u32,
+    #[serde(default)]
     delta: OpenAIStreamDelta,
     finish_reason: Option<String>, // Nullable for stream
 }
 
-#[derive(Deserialize, Debug, Clone)] // Clone needed
+#[derive(Deserialize, Debug, Clone, Default)] // Clone needed
 struct OpenAIStreamDelta {
     // Role might appear in the first chunk
     role: Option<String>,
     // Content is the important part
     content: Option<String>,
+    // Where some compatible servers put the content instead
+    #[serde(default)]
+    text: Option<String>,
+    // Tool call fragments, present when the model is calling tools
+    #[serde(default)]
+    tool_calls: Option<Vec<OpenAIToolCallDelta>>,
+    // Citations from web search or retrieval, e.g. `{"type": "url_citation", "url_citation": {...}}`
+    #[serde(default)]
+    annotations: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct OpenAIToolCallDelta {
+    index: u32,
+    id: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    function: Option<OpenAIFunctionDelta>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct OpenAIFunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+// Content carried by a stream chunk and the field it came from: `delta.content`, else
+// `delta.text`, else the value at the config's `delta_path` pointer into the raw chunk
+fn delta_content(delta: Option<&OpenAIStreamDelta>, event_data: &str, delta_path: Option<&str>) -> Option<(String, &'static str)> {
+    if let Some(delta) = delta {
+        if let Some(content) = delta.content.as_ref().filter(|c| !c.is_empty()) {
+            return Some((content.clone(), "delta.content"));
+        }
+        if let Some(text) = delta.text.as_ref().filter(|t| !t.is_empty()) {
+            return Some((text.clone(), "delta.text"));
+        }
+    }
+    let delta_path = delta_path?;
+    let chunk: serde_json::Value = serde_json::from_str(event_data).ok()?;
+    let content = chunk.pointer(delta_path)?.as_str().filter(|c| !c.is_empty())?;
+    Some((content.to_string(), "delta_path"))
 }
 
 // Standard non-streaming response format
@@ -107,17 +1021,86 @@ impl OpenAICompatibleProvider {
         Self { client: Client::new() }
     }
 
-    fn get_model_name(&self, config: &ModelConfig) -> Result<String> {
-        let options_json = config.provider_options.as_deref().unwrap_or("{}");
-        let options: serde_json::Value = serde_json::from_str(options_json)
-            .context("Failed to parse provider_options JSON")?;
-        options["model"].as_str().map(|s| s.to_string())
+    fn get_model_name(&self, options: &ParsedProviderOptions) -> Result<String> {
+        options.model.clone()
             .context("Missing or invalid 'model' field in provider_options")
     }
 }
 
+// Request messages with the config's `system_role` applied to system messages and its
+// `developer_instruction`, if any, inserted after the leading system messages. Consecutive
+// turns are merged when `merge_consecutive_roles` is set, and the prompt caching marker is
+// added when `enable_prompt_caching` is.
+fn to_openai_messages(messages: &[Message], options: &ParsedProviderOptions) -> Vec<OpenAIMessage> {
+    let system_role = options.system_role.as_deref()
+        .filter(|role| SYSTEM_ROLES.contains(role))
+        .unwrap_or("system");
+    let mut api_messages: Vec<OpenAIMessage> = messages
+        .iter()
+        .map(|msg| OpenAIMessage {
+            role: if msg.role == "system" { system_role.to_string() } else { msg.role.clone() },
+            content: OpenAIContent::Text(msg.content.clone()),
+            name: msg.name.clone(),
+        })
+        .collect();
+    if let Some(instruction) = options.developer_instruction.as_deref().map(str::trim).filter(|i| !i.is_empty()) {
+        let position = messages.iter().take_while(|msg| msg.role == "system").count();
+        api_messages.insert(position, OpenAIMessage {
+            role: "developer".to_string(),
+            content: OpenAIContent::Text(instruction.to_string()),
+            name: None,
+        });
+    }
+    if options.merge_consecutive_roles.unwrap_or(false) {
+        api_messages = merge_consecutive_roles(api_messages);
+    }
+    // The cache breakpoint goes on the last leading system message, so the system prompt and
+    // any few-shot block in it are cached as one prefix. Message order is otherwise kept as is,
+    // which is also what OpenAI's automatic prefix caching relies on.
+    if options.enable_prompt_caching.unwrap_or(false) {
+        let leading_system = messages.iter().take_while(|msg| msg.role == "system").count();
+        if let Some(message) = leading_system.checked_sub(1).and_then(|last| api_messages.get_mut(last)) {
+            let text = std::mem::replace(&mut message.content, OpenAIContent::Parts(Vec::new())).into_text();
+            message.content = OpenAIContent::Parts(vec![OpenAIContentPart {
+                kind: "text".to_string(),
+                text,
+                cache_control: Some(serde_json::json!({ "type": "ephemeral" })),
+            }]);
+        }
+    }
+    api_messages
+}
+
+// Joins each run of user or assistant messages from the same sender into one message, its
+// contents separated by a blank line. System, developer and tool messages are left alone.
+fn merge_consecutive_roles(messages: Vec<OpenAIMessage>) -> Vec<OpenAIMessage> {
+    let mut merged: Vec<OpenAIMessage> = Vec::with_capacity(messages.len());
+    for message in messages {
+        if let Some(previous) = merged.last_mut() {
+            let alternating = message.role == "user" || message.role == "assistant";
+            if alternating && previous.role == message.role && previous.name == message.name {
+                let text = std::mem::replace(&mut previous.content, OpenAIContent::Parts(Vec::new())).into_text();
+                previous.content = OpenAIContent::Text(format!("{}\n\n{}", text, message.content.into_text()));
+                continue;
+            }
+        }
+        merged.push(message);
+    }
+    merged
+}
+
 #[async_trait]
 impl LLMApiProvider for OpenAICompatibleProvider {
+    fn default_headers(&self) -> Vec<(&'static str, &'static str)> {
+        vec![("content-type", "application/json")]
+    }
+
+    // Tools go out as the `tools` option and calls are assembled from the stream. Messages
+    // are text only and there is no embeddings or reasoning support yet.
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities { streaming: true, tools: true, vision: false, embeddings: false, reasoning: false }
+    }
+
     // Implement the new streaming method
     async fn send_chat_stream_request(
         &self,
@@ -125,27 +1108,29 @@ impl LLMApiProvider for OpenAICompatibleProvider {
         api_key: &str,
         messages: &[Message],
     ) -> Result<DeltaStream> {
-        let model_name = self.get_model_name(config)?;
+        let options = ParsedProviderOptions::from_config(config)?;
+        let model_name = self.get_model_name(&options)?;
         log::info!("Sending STREAM request to OpenAI compatible API: {} using model: {}", config.api_url, model_name);
 
-        let api_messages: Vec<OpenAIMessage> = messages
-            .iter()
-            .map(|msg| OpenAIMessage {
-                role: msg.role.clone(),
-                content: msg.content.clone(),
-            })
-            .collect();
+        let api_messages = to_openai_messages(messages, &options);
 
         let request_body = OpenAIRequestBody {
             model: model_name,
             messages: api_messages,
             stream: true, // Enable streaming
+            max_tokens: options.max_tokens,
+            user: options.user(),
+            tools: options.tools.clone(),
+            // Only ask for usage when it can be costed; not every compatible server accepts this
+            stream_options: options.has_pricing().then(|| serde_json::json!({ "include_usage": true })),
+            logit_bias: options.checked_logit_bias()?,
         };
 
-        let request_url = format!("{}/chat/completions", config.api_url.trim_end_matches('/'));
+        let request_url = options.chat_url(&config.api_url)?;
 
         let response = self.client
             .post(&request_url)
+            .headers(request_headers(self, &options)?)
             .bearer_auth(api_key)
             .json(&request_body)
             .send()
@@ -154,64 +1139,29 @@ impl LLMApiProvider for OpenAICompatibleProvider {
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = retry_after_header(&response);
             let error_body = response.text().await.unwrap_or_else(|_| "<Failed to read error body>".to_string());
+            // Some servers echo the submitted key in their error message
+            let error_body = crate::logs::redact_secrets(&error_body).into_owned();
             log::error!("OpenAI API stream request failed with status {}: {}", status, error_body);
-            return Err(anyhow::anyhow!("API stream request failed with status {}: {}", status, error_body));
+            return Err(HttpStatusError { request: "stream", status: status.as_u16(), body: error_body, retry_after }.into());
         }
 
         // Process the SSE stream
         let event_stream = response.bytes_stream().eventsource();
 
+        let mut parser = StreamChunkParser::new(&options);
         let delta_stream = event_stream
-            .map(|event_result| -> Result<Option<String>> { // Map Result<Event, _> to Result<Option<String>, _>
+            .map(move |event_result| -> Result<Vec<StreamEvent>> { // Map Result<Event, _> to the events it carries
                 let event = event_result.context("Error reading stream event")?;
-                let event_data = event.data.trim();
-                
-                // Check for the special [DONE] message
-                if event_data == "[DONE]" {
-                    log::info!("Stream finished with [DONE]");
-                    return Ok(None); // Signal end of content stream
-                }
-
-                // Attempt to parse the JSON data
-                match serde_json::from_str::<OpenAIStreamChunk>(event_data) {
-                    Ok(chunk) => {
-                        // Successfully parsed a chunk, extract content
-                        let delta_content = chunk.choices
-                            .get(0)
-                            .and_then(|choice| choice.delta.content.clone()); 
-                        Ok(delta_content)
-                    },
-                    Err(e) => {
-                        // Parsing as OpenAIStreamChunk failed.
-                        // Try parsing as generic JSON to check for known event types like ping.
-                        match serde_json::from_str::<serde_json::Value>(event_data) {
-                            Ok(json_value) => {
-                                if json_value.get("type") == Some(&serde_json::Value::String("ping".to_string())) {
-                                    log::debug!("Received stream ping event, skipping.");
-                                    Ok(None) // Skip ping
-                                } else {
-                                    // Parsed as JSON, but not a known type to ignore.
-                                    log::warn!("Failed to parse stream chunk as OpenAIStreamChunk, but it was valid JSON: {} - Data: {}", e, event_data);
-                                    Err(anyhow::Error::from(e).context(format!("Parsed as JSON but not a valid OpenAIStreamChunk: {}", event_data)))
-                                }
-                            }
-                            Err(_) => {
-                                // Failed to parse as generic JSON either. Propagate original OpenAIStreamChunk error.
-                                log::warn!("Failed to parse stream chunk as JSON: {} - Data: {}", e, event_data);
-                                Err(anyhow::Error::from(e).context(format!("Failed to parse stream chunk as JSON: {}", event_data)))
-                            }
-                        }
-                    }
-                }
+                parser.parse(&event.data)
             })
-            .filter_map(|result| async move { // Filter out errors and None values, return only content strings
+            .flat_map(|result| { // Flatten each chunk's events into the output stream
                 match result {
-                    Ok(Some(content)) => Some(Ok(content)), // Pass through the content string wrapped in Ok
-                    Ok(None) => None, // Filter out the end-of-stream signal
+                    Ok(events) => stream::iter(events.into_iter().map(Ok).collect::<Vec<_>>()), // Empty for [DONE] and pings
                     Err(e) => {
                         log::error!("Error processing stream chunk: {:?}", e);
-                        Some(Err(e)) // Pass through the error
+                        stream::iter(vec![Err(e)]) // Pass through the error
                     }
                 }
              });
@@ -227,27 +1177,28 @@ impl LLMApiProvider for OpenAICompatibleProvider {
         api_key: &str,
         messages: &[Message],
     ) -> Result<String> {
-        let model_name = self.get_model_name(config)?;
+        let options = ParsedProviderOptions::from_config(config)?;
+        let model_name = self.get_model_name(&options)?;
         log::info!("Sending NON-STREAM request to OpenAI compatible API: {} using model: {}", config.api_url, model_name);
 
-        let api_messages: Vec<OpenAIMessage> = messages
-            .iter()
-            .map(|msg| OpenAIMessage {
-                role: msg.role.clone(),
-                content: msg.content.clone(),
-            })
-            .collect();
+        let api_messages = to_openai_messages(messages, &options);
 
         let request_body = OpenAIRequestBody {
             model: model_name,
             messages: api_messages,
             stream: false, // <<< Ensure streaming is false >>>
+            max_tokens: options.max_tokens,
+            user: options.user(),
+            tools: options.tools.clone(),
+            stream_options: None,
+            logit_bias: options.checked_logit_bias()?,
         };
 
-        let request_url = format!("{}/chat/completions", config.api_url.trim_end_matches('/'));
+        let request_url = options.chat_url(&config.api_url)?;
 
         let response = self.client
             .post(&request_url)
+            .headers(request_headers(self, &options)?)
             .bearer_auth(api_key)
             .json(&request_body)
             .send()
@@ -256,9 +1207,12 @@ impl LLMApiProvider for OpenAICompatibleProvider {
 
         if !response.status().is_success() {
             let status = response.status();
+            let retry_after = retry_after_header(&response);
             let error_body = response.text().await.unwrap_or_else(|_| "<Failed to read error body>".to_string());
+            // Some servers echo the submitted key in their error message
+            let error_body = crate::logs::redact_secrets(&error_body).into_owned();
             log::error!("OpenAI API non-stream request failed with status {}: {}", status, error_body);
-            return Err(anyhow::anyhow!("API non-stream request failed with status {}: {}", status, error_body));
+            return Err(HttpStatusError { request: "non-stream", status: status.as_u16(), body: error_body, retry_after }.into());
         }
 
         // Parse the response and extract the content
@@ -268,7 +1222,575 @@ impl LLMApiProvider for OpenAICompatibleProvider {
         // Extract content from the first choice's message
         response_body.choices
             .get(0)
-            .map(|choice| choice.message.content.clone())
+            .map(|choice| choice.message.content.clone().into_text())
             .context("No message content found in OpenAI non-stream response")
     }
-} 
\ No newline at end of file
+
+    async fn send_raw_request(
+        &self,
+        config: &ModelConfig,
+        api_key: &str,
+        method: RawMethod,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Result<RawResponse> {
+        let options = ParsedProviderOptions::from_config(config)?;
+        let request_url = join_relative_path(&config.api_url, path)?;
+        log::info!("Sending RAW {:?} request to {}", method, request_url);
+
+        let mut request = match method {
+            RawMethod::Get => self.client.get(&request_url),
+            RawMethod::Post => self.client.post(&request_url),
+        }
+        .headers(request_headers(self, &options)?)
+        .bearer_auth(api_key);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+
+        let response = request.send().await.context("Failed to send raw request")?;
+        let status = response.status().as_u16();
+        let body = response.text().await.context("Failed to read raw response body")?;
+        Ok(RawResponse { status, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{message, MockResponse, MockServer};
+    use uuid::Uuid;
+
+    fn options(json: &str) -> ParsedProviderOptions {
+        ParsedProviderOptions::from_config(&crate::test_support::model_config("Test", json)).unwrap()
+    }
+
+    // The request messages for `messages` under a config with `options_json`, as sent
+    fn outgoing(options_json: &str, messages: &[Message]) -> serde_json::Value {
+        serde_json::to_value(to_openai_messages(messages, &options(options_json))).unwrap()
+    }
+
+    #[test]
+    fn message_name_is_serialized_only_when_set() {
+        let conversation_id = Uuid::new_v4();
+        let named = Message { name: Some("lookup_weather".to_string()), ..message(conversation_id, "tool", "Sunny") };
+        let unnamed = message(conversation_id, "user", "Weather?");
+
+        let sent = serde_json::to_value(to_openai_messages(&[unnamed, named], &options("{}"))).unwrap();
+        assert_eq!(sent[0], serde_json::json!({ "role": "user", "content": "Weather?" }));
+        assert_eq!(sent[1], serde_json::json!({ "role": "tool", "content": "Sunny", "name": "lookup_weather" }));
+    }
+
+    #[test]
+    fn relative_paths_are_joined_onto_the_api_url() {
+        assert_eq!(join_relative_path("https://api.example.com/v1", "models").unwrap(), "https://api.example.com/v1/models");
+        assert_eq!(join_relative_path("https://api.example.com/v1/", "/models/gpt-4o").unwrap(), "https://api.example.com/v1/models/gpt-4o");
+        assert_eq!(join_relative_path("http://localhost:8080", " fine_tuning/jobs?limit=5 ").unwrap(), "http://localhost:8080/fine_tuning/jobs?limit=5");
+    }
+
+    #[test]
+    fn absolute_urls_are_rejected() {
+        for path in ["https://evil.example.com/steal", "//evil.example.com/steal", "file:///etc/passwd", "\\\\host\\share"] {
+            assert!(join_relative_path("https://api.example.com/v1", path).is_err(), "{} was accepted", path);
+        }
+    }
+
+    #[test]
+    fn user_field_is_sent_only_when_configured() {
+        let body = |json: &str| {
+            let options = options(json);
+            serde_json::to_value(OpenAIRequestBody {
+                model: "gpt-4o".to_string(),
+                messages: Vec::new(),
+                stream: true,
+                max_tokens: None,
+                user: options.user(),
+                tools: None,
+                stream_options: None,
+                logit_bias: None,
+            })
+            .unwrap()
+        };
+        assert_eq!(body(r#"{"user_id": "user-42"}"#)["user"], "user-42");
+        assert!(body(r#"{"user_id": ""}"#).get("user").is_none());
+        assert!(body("{}").get("user").is_none());
+    }
+
+    #[test]
+    fn tool_calls_are_reassembled_from_fragments() {
+        let fragments = [
+            r#"{"index": 0, "id": "call_a", "type": "function", "function": {"name": "get_weather", "arguments": ""}}"#,
+            r#"{"index": 0, "function": {"arguments": "{\"city\": "}}"#,
+            // A second call starts before the first one's arguments are complete
+            r#"{"index": 1, "id": "call_b", "function": {"name": "get_time"}}"#,
+            r#"{"index": 0, "function": {"arguments": "\"Paris\"}"}}"#,
+            r#"{"index": 1, "function": {"arguments": "{}"}}"#,
+        ];
+        let mut accumulator = ToolCallAccumulator::default();
+        for fragment in fragments {
+            accumulator.push(&serde_json::from_str(fragment).unwrap());
+        }
+
+        let calls = accumulator.take();
+        assert_eq!(calls.len(), 2);
+        assert_eq!((calls[0].id.as_str(), calls[0].kind.as_str()), ("call_a", "function"));
+        assert_eq!(calls[0].function.name, "get_weather");
+        assert_eq!(calls[0].function.arguments, r#"{"city": "Paris"}"#);
+        // The type defaults to "function" when the server leaves it out
+        assert_eq!((calls[1].id.as_str(), calls[1].kind.as_str()), ("call_b", "function"));
+        assert_eq!(calls[1].function.arguments, "{}");
+        assert!(accumulator.take().is_empty());
+    }
+
+    // Feeds `events` (SSE data lines) through one parser, stopping at the first error
+    fn parse_stream(options_json: &str, events: &[&str]) -> Result<Vec<StreamEvent>> {
+        let mut parser = StreamChunkParser::new(&options(options_json));
+        let mut parsed = Vec::new();
+        for event in events {
+            parsed.extend(parser.parse(event)?);
+        }
+        Ok(parsed)
+    }
+
+    fn provider_error(events: &[&str]) -> ProviderStreamError {
+        let error = parse_stream("{}", events).unwrap_err();
+        error.downcast_ref::<ProviderStreamError>().expect("a typed provider error").clone()
+    }
+
+    #[test]
+    fn openai_error_payloads_become_provider_errors() {
+        let error = provider_error(&[
+            r#"{"id":"chatcmpl-9x","object":"chat.completion.chunk","created":1718000000,"model":"gpt-4o-2024-05-13","choices":[{"index":0,"delta":{"content":"Hel"},"logprobs":null,"finish_reason":null}]}"#,
+            r#"{"error":{"message":"The server had an error while processing your request. Sorry about that!","type":"server_error","param":null,"code":null}}"#,
+        ]);
+        assert_eq!(error.message, "The server had an error while processing your request. Sorry about that!");
+        assert_eq!(error.code.as_deref(), Some("server_error"));
+    }
+
+    #[test]
+    fn together_error_payloads_become_provider_errors() {
+        let error = provider_error(&[
+            r#"{"id":"8a1b","object":"chat.completion.chunk","created":1718000000,"model":"meta-llama/Llama-3-70b-chat-hf","choices":[{"index":0,"text":"","logprobs":null,"finish_reason":null,"delta":{"token_id":128000,"role":"assistant","content":""}}]}"#,
+            r#"{"error":{"message":"Request timed out while generating. Please retry.","type":"timeout_error","param":null,"code":"request_timeout"}}"#,
+        ]);
+        assert_eq!(error.message, "Request timed out while generating. Please retry.");
+        assert_eq!(error.code.as_deref(), Some("request_timeout"));
+    }
+
+    #[test]
+    fn vllm_error_payloads_become_provider_errors() {
+        // Top-level errors from older vLLM releases, nested ones from newer
+        let top_level = provider_error(&[
+            r#"{"object":"error","message":"This model's maximum context length is 4096 tokens. However, you requested 5000 tokens.","type":"BadRequestError","param":null,"code":400}"#,
+        ]);
+        assert!(top_level.message.starts_with("This model's maximum context length"));
+        assert_eq!(top_level.code.as_deref(), Some("400"));
+        let nested = provider_error(&[
+            r#"{"error":{"object":"error","message":"The engine is dead","type":"InternalServerError","param":null,"code":500}}"#,
+        ]);
+        assert_eq!((nested.message.as_str(), nested.code.as_deref()), ("The engine is dead", Some("500")));
+        assert_eq!(provider_error(&[r#"{"error":"Model overloaded"}"#]).message, "Model overloaded");
+    }
+
+    #[test]
+    fn ordinary_chunks_still_stream() {
+        let events = parse_stream(
+            "{}",
+            &[
+                r#"{"id":"chatcmpl-9x","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}"#,
+                "",
+                r#"{"type":"ping"}"#,
+                r#"{"id":"chatcmpl-9x","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"content":"Hi"},"finish_reason":null}]}"#,
+                r#"{"id":"chatcmpl-9x","object":"chat.completion.chunk","choices":[{"index":0,"delta":{},"finish_reason":"stop"}],"usage":{"prompt_tokens":5,"completion_tokens":1,"total_tokens":6}}"#,
+                "[DONE]",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta("Hi".to_string()),
+                StreamEvent::Finished("stop".to_string()),
+                StreamEvent::Usage(TokenUsage { prompt_tokens: 5, completion_tokens: 1 }),
+            ]
+        );
+        assert!(parse_stream("{}", &["{not json"]).unwrap_err().downcast_ref::<ProviderStreamError>().is_none());
+    }
+
+    #[test]
+    fn openai_compatible_schema_lists_every_option() {
+        let schema = provider_options_schema("openai_compatible").unwrap();
+        let mut keys: Vec<&str> = schema.iter().map(|field| field.key).collect();
+        keys.sort_unstable();
+        assert_eq!(
+            keys,
+            [
+                "chat_path", "context_window", "delta_path", "developer_instruction", "enable_prompt_caching", "headers",
+                "input_cost_per_mtok", "logit_bias", "max_tokens", "merge_consecutive_roles", "model", "output_cost_per_mtok",
+                "resume_on_disconnect", "retry_on_empty", "system_role", "tools", "user_id",
+            ]
+        );
+        assert_eq!(schema.iter().filter(|field| field.required).map(|field| field.key).collect::<Vec<_>>(), ["model"]);
+        for field in &schema {
+            assert!(["string", "integer", "number", "boolean", "array", "object"].contains(&field.kind), "{}", field.key);
+        }
+        // The documented defaults are values the options parser accepts
+        let defaults: serde_json::Map<String, serde_json::Value> =
+            schema.iter().filter_map(|field| Some((field.key.to_string(), field.default.clone()?))).collect();
+        ParsedProviderOptions::from_config(&crate::test_support::model_config("Test", &serde_json::Value::Object(defaults).to_string())).unwrap();
+
+        assert!(provider_options_schema("bogus").is_err());
+    }
+
+    #[test]
+    fn vllm_role_only_and_empty_choice_chunks_are_skipped() {
+        let events = parse_stream(
+            "{}",
+            &[
+                r#"{"id":"chatcmpl-b2f1","object":"chat.completion.chunk","created":1718000000,"model":"Qwen/Qwen2.5-7B-Instruct","choices":[{"index":0,"delta":{"role":"assistant","content":""},"logprobs":null,"finish_reason":null}]}"#,
+                r#"{"id":"chatcmpl-b2f1","object":"chat.completion.chunk","created":1718000000,"model":"Qwen/Qwen2.5-7B-Instruct","choices":[{"index":0,"delta":{"content":"Hello"},"logprobs":null,"finish_reason":null}]}"#,
+                r#"{"id":"chatcmpl-b2f1","object":"chat.completion.chunk","created":1718000000,"model":"Qwen/Qwen2.5-7B-Instruct","choices":[{"index":0,"delta":{"content":""},"logprobs":null,"finish_reason":"stop","stop_reason":null}]}"#,
+                r#"{"id":"chatcmpl-b2f1","object":"chat.completion.chunk","created":1718000000,"model":"Qwen/Qwen2.5-7B-Instruct","choices":[],"usage":{"prompt_tokens":9,"total_tokens":11,"completion_tokens":2}}"#,
+                "[DONE]",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta("Hello".to_string()),
+                StreamEvent::Finished("stop".to_string()),
+                StreamEvent::Usage(TokenUsage { prompt_tokens: 9, completion_tokens: 2 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn llama_cpp_chunks_without_ids_stream() {
+        // Older llama.cpp servers leave out id, created and model
+        let events = parse_stream(
+            "{}",
+            &[
+                r#"{"choices":[{"finish_reason":null,"index":0,"delta":{"role":"assistant","content":null}}],"object":"chat.completion.chunk"}"#,
+                r#"{"choices":[{"finish_reason":null,"index":0,"delta":{"content":"Hi"}}],"object":"chat.completion.chunk"}"#,
+                r#"{"choices":[{"finish_reason":null,"index":0,"delta":{"content":" there"}}],"created":1718000000,"id":"chatcmpl-Xk2","model":"gpt-3.5-turbo","system_fingerprint":"b3600-2fb92678","object":"chat.completion.chunk"}"#,
+                r#"{"choices":[{"finish_reason":"stop","index":0,"delta":{}}],"created":1718000000,"id":"chatcmpl-Xk2","model":"gpt-3.5-turbo","object":"chat.completion.chunk","usage":{"completion_tokens":2,"prompt_tokens":14,"total_tokens":16},"timings":{"prompt_n":14,"predicted_n":2,"predicted_per_second":41.2}}"#,
+                "[DONE]",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Delta("Hi".to_string()),
+                StreamEvent::Delta(" there".to_string()),
+                StreamEvent::Finished("stop".to_string()),
+                StreamEvent::Usage(TokenUsage { prompt_tokens: 14, completion_tokens: 2 }),
+            ]
+        );
+    }
+
+    #[test]
+    fn lm_studio_chunks_stream() {
+        let events = parse_stream(
+            "{}",
+            &[
+                r#"{"id":"chatcmpl-rk8d","object":"chat.completion.chunk","created":1718000000,"model":"lmstudio-community/meta-llama-3.1-8b-instruct","system_fingerprint":"lmstudio-community/meta-llama-3.1-8b-instruct","choices":[{"index":0,"delta":{"role":"assistant","content":""},"logprobs":null,"finish_reason":null}]}"#,
+                "",
+                r#"{"id":"chatcmpl-rk8d","object":"chat.completion.chunk","created":1718000000,"model":"lmstudio-community/meta-llama-3.1-8b-instruct","system_fingerprint":"lmstudio-community/meta-llama-3.1-8b-instruct","choices":[{"index":0,"delta":{"content":"Sure."},"logprobs":null,"finish_reason":null}]}"#,
+                r#"{"id":"chatcmpl-rk8d","object":"chat.completion.chunk","created":1718000000,"model":"lmstudio-community/meta-llama-3.1-8b-instruct","system_fingerprint":"lmstudio-community/meta-llama-3.1-8b-instruct","choices":[{"index":0,"delta":{},"logprobs":null,"finish_reason":"stop"}]}"#,
+                "[DONE]",
+            ],
+        )
+        .unwrap();
+        assert_eq!(events, vec![StreamEvent::Delta("Sure.".to_string()), StreamEvent::Finished("stop".to_string())]);
+        assert!(parse_stream("{}", &[r#"{"choices":[{"delta":{"content":"Hi"}"#]).is_err());
+    }
+
+    #[test]
+    fn system_prompts_go_out_under_the_configured_role() {
+        let conversation_id = Uuid::new_v4();
+        let messages = [message(conversation_id, "system", "Be brief."), message(conversation_id, "user", "Hi")];
+        let roles = |options_json: &str| -> Vec<String> {
+            let outgoing = outgoing(options_json, &messages);
+            outgoing.as_array().unwrap().iter().map(|m| m["role"].as_str().unwrap().to_string()).collect()
+        };
+
+        assert_eq!(roles("{}"), ["system", "user"]);
+        assert_eq!(roles(r#"{"system_role": "developer"}"#), ["developer", "user"]);
+        let with_instruction = outgoing(r#"{"developer_instruction": " Answer in French. "}"#, &messages);
+        assert_eq!(with_instruction[1], serde_json::json!({"role": "developer", "content": "Answer in French."}));
+        assert_eq!(roles(r#"{"developer_instruction": " Answer in French. "}"#), ["system", "developer", "user"]);
+
+        let invalid = validate_provider_options("openai_compatible", Some(r#"{"model": "gpt-4o", "system_role": "admin"}"#)).unwrap_err();
+        assert_eq!(invalid, ["system_role: expected one of system, developer"]);
+    }
+
+    #[tokio::test]
+    async fn requests_go_to_the_configured_chat_path() {
+        let server = MockServer::start(vec![MockResponse::json(serde_json::json!({
+            "id": "chatcmpl-1", "object": "chat.completion", "created": 1718000000, "model": "gpt-4o",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}]
+        }))])
+        .await;
+        let provider = OpenAICompatibleProvider::new();
+        let messages = [message(Uuid::new_v4(), "user", "Hello")];
+        let config = |options: &str| ModelConfig {
+            api_url: format!("{}/gateway/", server.url),
+            ..crate::test_support::model_config("Gateway", options)
+        };
+
+        provider.send_chat_request(&config(r#"{"model": "gpt-4o"}"#), "key", &messages).await.unwrap();
+        provider.send_chat_request(&config(r#"{"model": "gpt-4o", "chat_path": "/v2/chat?api-version=3"}"#), "key", &messages).await.unwrap();
+        provider.send_chat_request(&config(r#"{"model": "gpt-4o", "chat_path": "  "}"#), "key", &messages).await.unwrap();
+
+        let paths: Vec<String> = server.requests().into_iter().map(|request| request.path).collect();
+        assert_eq!(paths, ["/gateway/chat/completions", "/gateway/v2/chat?api-version=3", "/gateway/chat/completions"]);
+        let absolute = config(r#"{"model": "gpt-4o", "chat_path": "https://elsewhere.example.com/chat"}"#);
+        assert!(provider.send_chat_request(&absolute, "key", &messages).await.is_err());
+        assert_eq!(server.requests().len(), 3);
+    }
+
+
+    #[test]
+    fn user_headers_overlay_the_provider_defaults() {
+        let provider = OpenAICompatibleProvider::new();
+        let headers = request_headers(&provider, &options(r#"{"model": "m"}"#)).unwrap();
+        assert_eq!(headers.get("content-type").unwrap(), "application/json");
+
+        let headers = request_headers(&provider, &options(r#"{"model": "m", "headers": {"Content-Type": "application/json; charset=utf-8", "X-Team": "blue"}}"#)).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert_eq!(headers.get("content-type").unwrap(), "application/json; charset=utf-8");
+        assert_eq!(headers.get("x-team").unwrap(), "blue");
+
+        let error = request_headers(&provider, &options(r#"{"model": "m", "headers": {"Bad Name": "x"}}"#)).unwrap_err();
+        assert!(format!("{:#}", error).contains("'Bad Name' is not a valid header name"), "{:#}", error);
+    }
+
+    #[tokio::test]
+    async fn streaming_and_plain_requests_send_the_same_headers() {
+        let completion = MockResponse::json(serde_json::json!({
+            "id": "chatcmpl-1", "object": "chat.completion", "created": 1718000000, "model": "m",
+            "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}]
+        }));
+        let stream = MockResponse::sse(&[serde_json::json!({
+            "id": "chatcmpl-2", "object": "chat.completion.chunk", "created": 1718000000, "model": "m",
+            "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": "stop"}]
+        })]);
+        let server = MockServer::start(vec![completion, stream]).await;
+        let provider = OpenAICompatibleProvider::new();
+        let config = ModelConfig {
+            api_url: server.url.clone(),
+            ..crate::test_support::model_config("Headers", r#"{"model": "m", "headers": {"X-Team": "blue"}}"#)
+        };
+        let messages = [message(Uuid::new_v4(), "user", "Hello")];
+
+        provider.send_chat_request(&config, "key", &messages).await.unwrap();
+        let mut deltas = provider.send_chat_stream_request(&config, "key", &messages).await.unwrap();
+        while deltas.next().await.is_some() {}
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            assert_eq!(request.header("content-type"), Some("application/json"));
+            assert_eq!(request.header("x-team"), Some("blue"));
+            assert_eq!(request.header("authorization"), Some("Bearer key"));
+        }
+    }
+
+
+    #[test]
+    fn content_falls_back_to_delta_text_then_the_configured_path() {
+        let chunk = |delta: &str| format!(r#"{{"id":"c1","object":"chat.completion.chunk","choices":[{{"index":0,"delta":{}}}]}}"#, delta);
+        let extract = |event: &str, delta_path: Option<&str>| {
+            let chunk: OpenAIStreamChunk = serde_json::from_str(event).unwrap();
+            delta_content(chunk.choices.first().map(|choice| &choice.delta), event, delta_path)
+        };
+
+        let both = chunk(r#"{"content":"from content","text":"from text"}"#);
+        assert_eq!(extract(&both, None), Some(("from content".to_string(), "delta.content")));
+        let text_only = chunk(r#"{"content":"","text":"from text"}"#);
+        assert_eq!(extract(&text_only, None), Some(("from text".to_string(), "delta.text")));
+
+        let nested = r#"{"id":"c1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{},"message":{"content":"from message"}}]}"#;
+        assert_eq!(extract(nested, None), None);
+        assert_eq!(extract(nested, Some("/choices/0/message/content")), Some(("from message".to_string(), "delta_path")));
+        assert_eq!(extract(nested, Some("/choices/0/missing")), None);
+    }
+
+    #[test]
+    fn each_fallback_streams_deltas() {
+        let text_events = parse_stream("{}", &[
+            r#"{"id":"c1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"text":"Hel"}}]}"#,
+            r#"{"id":"c1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"text":"lo"},"finish_reason":"stop"}]}"#,
+        ])
+        .unwrap();
+        assert_eq!(text_events, vec![StreamEvent::Delta("Hel".to_string()), StreamEvent::Delta("lo".to_string()), StreamEvent::Finished("stop".to_string())]);
+
+        let nested = r#"{"id":"c1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{},"message":{"content":"Hi"}}]}"#;
+        assert!(parse_stream("{}", &[nested]).unwrap().is_empty());
+        let path_events = parse_stream(r#"{"delta_path": "/choices/0/message/content"}"#, &[nested]).unwrap();
+        assert_eq!(path_events, vec![StreamEvent::Delta("Hi".to_string())]);
+
+        let errors = validate_provider_options("openai_compatible", Some(r#"{"model": "m", "delta_path": "choices/0"}"#)).unwrap_err();
+        assert_eq!(errors, ["delta_path: must be a JSON pointer starting with '/'"]);
+    }
+
+
+    #[test]
+    fn citations_stream_alongside_content() {
+        let events = parse_stream("{}", &[
+            r#"{"id":"c1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{"content":"Paris","annotations":[{"type":"url_citation","url_citation":{"url":"https://example.com/paris"}}]}}]}"#,
+            r#"{"id":"c1","object":"chat.completion.chunk","choices":[{"index":0,"delta":{},"finish_reason":"stop"}],"citations":["https://example.com/france"]}"#,
+        ])
+        .unwrap();
+        assert_eq!(events, vec![
+            StreamEvent::Delta("Paris".to_string()),
+            StreamEvent::Annotations(vec![serde_json::json!({"type": "url_citation", "url_citation": {"url": "https://example.com/paris"}})]),
+            StreamEvent::Annotations(vec![serde_json::json!("https://example.com/france")]),
+            StreamEvent::Finished("stop".to_string()),
+        ]);
+    }
+
+
+    #[tokio::test]
+    async fn errors_are_classified_for_retrying() {
+        let status = |status: u16| anyhow::Error::new(HttpStatusError { request: "stream", status, body: String::new(), retry_after: None });
+        for (code, retryable) in [(400, false), (401, false), (403, false), (404, false), (408, true), (429, true), (500, true), (503, true)] {
+            let classified = ApiError::classify(&status(code));
+            assert_eq!(classified, ApiError::Status(code));
+            assert_eq!(classified.is_retryable(), retryable, "status {}", code);
+        }
+
+        let server = anyhow::Error::new(ProviderStreamError { message: "overloaded".to_string(), code: None });
+        assert_eq!(ApiError::classify(&server), ApiError::Server);
+        let parse = anyhow::Error::new(serde_json::from_str::<OpenAIResponse>("{").unwrap_err()).context("Failed to parse response");
+        assert_eq!(ApiError::classify(&parse), ApiError::Parse);
+        assert_eq!(ApiError::classify(&anyhow::anyhow!("no model configured")), ApiError::Other);
+
+        // Nothing listens on the discard port
+        let refused = reqwest::Client::new().get("http://127.0.0.1:9/").send().await.unwrap_err();
+        let network = anyhow::Error::new(refused).context("Failed to send request");
+        assert_eq!(ApiError::classify(&network), ApiError::Network);
+
+        assert!(ApiError::Network.is_retryable());
+        assert!(!ApiError::Server.is_retryable() && !ApiError::Parse.is_retryable() && !ApiError::Other.is_retryable());
+    }
+
+
+    #[test]
+    fn prompt_caching_marks_the_last_leading_system_message() {
+        let conversation_id = Uuid::new_v4();
+        let messages = [
+            message(conversation_id, "system", "You are a helpful assistant."),
+            message(conversation_id, "system", "Example: Q: 2+2 A: 4"),
+            message(conversation_id, "user", "Hi"),
+        ];
+
+        let cached = outgoing(r#"{"enable_prompt_caching": true}"#, &messages);
+        assert_eq!(cached, serde_json::json!([
+            {"role": "system", "content": "You are a helpful assistant."},
+            {"role": "system", "content": [{"type": "text", "text": "Example: Q: 2+2 A: 4", "cache_control": {"type": "ephemeral"}}]},
+            {"role": "user", "content": "Hi"},
+        ]));
+
+        let plain = outgoing("{}", &messages);
+        assert!(plain.as_array().unwrap().iter().all(|m| m["content"].is_string()));
+        let without_system = outgoing(r#"{"enable_prompt_caching": true}"#, &messages[2..]);
+        assert_eq!(without_system, serde_json::json!([{"role": "user", "content": "Hi"}]));
+    }
+
+
+    #[test]
+    fn provider_options_checks_report_each_problem() {
+        let check = |json: &str| ProviderOptionsCheck::run("openai_compatible", Some(json));
+
+        let valid = check(r#"{"model": "gpt-4o", "max_tokens": 1024, "input_cost_per_mtok": 2.5, "tools": [], "logit_bias": {"50256": -100}}"#);
+        assert!(valid.ok);
+        assert!(valid.errors.is_empty());
+
+        let malformed = check(r#"{"model": "gpt-4o",}"#);
+        assert!(!malformed.ok);
+        assert_eq!(malformed.errors.len(), 1);
+        assert!(malformed.errors[0].starts_with("provider_options must be a JSON object"), "{:?}", malformed.errors);
+        assert!(!check(r#"["model"]"#).ok);
+
+        let out_of_range = check(r#"{"model": "gpt-4o", "max_tokens": 0, "input_cost_per_mtok": -1, "logit_bias": {"50256": 150}, "tools": {}, "temprature": 0.2}"#);
+        assert!(!out_of_range.ok);
+        let mut errors = out_of_range.errors;
+        errors.sort();
+        assert_eq!(errors, [
+            "input_cost_per_mtok: expected a non-negative number",
+            "logit_bias: bias 150 for token 50256 is outside -100 to 100",
+            "max_tokens: expected a positive integer",
+            "temprature: unknown option",
+            "tools: expected an array",
+        ]);
+
+        let missing_model = check("{}");
+        assert!(missing_model.errors[0].starts_with("model: required"), "{:?}", missing_model.errors);
+        assert!(!ProviderOptionsCheck::run("bogus", None).ok);
+    }
+
+
+    #[test]
+    fn consecutive_turns_are_merged_when_configured() {
+        let conversation_id = Uuid::new_v4();
+        let messages = [
+            message(conversation_id, "system", "Be brief."),
+            message(conversation_id, "system", "Use metric units."),
+            message(conversation_id, "user", "How tall is Everest?"),
+            message(conversation_id, "user", "And K2?"),
+            message(conversation_id, "assistant", "8849 m."),
+            Message { name: Some("critic".to_string()), ..message(conversation_id, "assistant", "K2 is 8611 m.") },
+        ];
+
+        assert_eq!(outgoing("{}", &messages).as_array().unwrap().len(), 6);
+        assert_eq!(outgoing(r#"{"merge_consecutive_roles": true}"#, &messages), serde_json::json!([
+            {"role": "system", "content": "Be brief."},
+            {"role": "system", "content": "Use metric units."},
+            {"role": "user", "content": "How tall is Everest?\n\nAnd K2?"},
+            {"role": "assistant", "content": "8849 m."},
+            {"role": "assistant", "content": "K2 is 8611 m.", "name": "critic"},
+        ]));
+    }
+
+
+    #[test]
+    fn retry_after_accepts_seconds_and_dates_and_clamps_long_waits() {
+        let now = chrono::DateTime::parse_from_rfc2822("Wed, 21 Oct 2026 07:28:00 GMT").unwrap().with_timezone(&chrono::Utc);
+        assert_eq!(parse_retry_after("2", now), Some(Duration::from_secs(2)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2026 07:28:05 GMT", now), Some(Duration::from_secs(5)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2026 07:27:00 GMT", now), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("86400", now), Some(MAX_RETRY_AFTER));
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+
+    #[test]
+    fn logit_bias_is_sent_only_when_set_and_in_range() {
+        let body = |json: &str| {
+            serde_json::to_value(OpenAIRequestBody {
+                model: "gpt-4o".to_string(),
+                messages: Vec::new(),
+                stream: true,
+                max_tokens: None,
+                user: None,
+                tools: None,
+                stream_options: None,
+                logit_bias: options(json).checked_logit_bias().unwrap(),
+            })
+            .unwrap()
+        };
+        assert_eq!(body(r#"{"logit_bias": {"50256": -100, "1234": 2.5}}"#)["logit_bias"], serde_json::json!({"1234": 2.5, "50256": -100.0}));
+        assert!(body(r#"{"logit_bias": {}}"#).get("logit_bias").is_none());
+        assert!(body("{}").get("logit_bias").is_none());
+
+        assert!(options(r#"{"logit_bias": {"1234": 100}}"#).checked_logit_bias().is_ok());
+        let error = |json: &str| options(json).checked_logit_bias().unwrap_err().to_string();
+        assert!(error(r#"{"logit_bias": {"1234": 100.5}}"#).contains("outside -100 to 100"));
+        assert!(error(r#"{"logit_bias": {"1234": -101}}"#).contains("outside -100 to 100"));
+        assert!(error(r#"{"logit_bias": {"hello": 1}}"#).contains("not a token ID"));
+    }
+}
+
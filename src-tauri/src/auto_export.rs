@@ -0,0 +1,213 @@
+// Scheduled export of conversations to a folder, for the user's own backup tooling. Once a
+// day (or on demand) every conversation changed since the last run is written to
+// `<slugified-title>-<short-id>.<ext>`; files of conversations that were deleted since are
+// removed. A missing folder (an unplugged drive) skips the run and it is tried again later.
+
+use crate::config;
+use crate::export::{self, ExportFormat};
+use crate::state::AppState;
+use crate::storage::ConversationSort;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use uuid::Uuid;
+
+// How often the loop checks whether a run is due, and how far apart runs are
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const EXPORT_INTERVAL: chrono::Duration = chrono::Duration::hours(24);
+
+// Hex characters of the conversation ID kept in file names
+const SHORT_ID_LEN: usize = 8;
+const MAX_SLUG_CHARS: usize = 60;
+
+/// Auto-export settings as stored in the settings table.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoExportSettings {
+    pub enabled: bool,
+    pub dir: Option<String>,
+    pub format: String, // "markdown" or "json"
+    pub last_export_at: Option<DateTime<Utc>>, // Start of the last completed run
+}
+
+/// What one run did.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct AutoExportSummary {
+    pub written: usize,
+    pub removed: usize,
+    pub skipped_reason: Option<String>, // Set when the run didn't happen, e.g. the folder is missing
+}
+
+/// The export format for a setting value; only Markdown and JSON are written.
+pub fn parse_format(value: &str) -> Result<ExportFormat, String> {
+    match ExportFormat::parse(value)? {
+        format @ (ExportFormat::Markdown | ExportFormat::Json) => Ok(format),
+        _ => Err(format!("Auto-export supports markdown and json, not {}", value)),
+    }
+}
+
+fn extension(format: ExportFormat) -> &'static str {
+    match format {
+        ExportFormat::Json => "json",
+        _ => "md",
+    }
+}
+
+pub async fn load_settings(state: &AppState) -> Result<AutoExportSettings, String> {
+    let storage = state.storage.lock().await;
+    let storage = &*storage;
+    let read = |key: &'static str| async move {
+        storage.get_setting(key).await.map_err(|e| format!("Failed to read setting '{}': {}", key, e))
+    };
+    Ok(AutoExportSettings {
+        enabled: read(config::AUTO_EXPORT_ENABLED_KEY).await?.is_some_and(|value| value == "true"),
+        dir: read(config::AUTO_EXPORT_DIR_KEY).await?.filter(|dir| !dir.trim().is_empty()),
+        format: read(config::AUTO_EXPORT_FORMAT_KEY).await?
+            .filter(|format| parse_format(format).is_ok())
+            .unwrap_or_else(|| "markdown".to_string()),
+        last_export_at: read(config::AUTO_EXPORT_LAST_RUN_KEY).await?
+            .and_then(|value| DateTime::parse_from_rfc3339(&value).ok())
+            .map(|time| time.with_timezone(&Utc)),
+    })
+}
+
+/// Checks every CHECK_INTERVAL for the life of the app and runs the export when it is enabled
+/// and the last run is a day old. Checking often (rather than sleeping a day) keeps the
+/// schedule across sleep and restarts.
+pub async fn run_auto_export_loop(state: AppState) {
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        let settings = match load_settings(&state).await {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("Auto-export: {}", e);
+                continue;
+            }
+        };
+        let due = settings.last_export_at.is_none_or(|last| Utc::now() - last >= EXPORT_INTERVAL);
+        if !settings.enabled || !due {
+            continue;
+        }
+        match run_export(&state).await {
+            Ok(summary) => log::info!("Auto-export finished: {:?}", summary),
+            Err(e) => log::error!("Auto-export failed: {}", e),
+        }
+    }
+}
+
+/// Writes conversations changed since the last run to the export folder and removes files of
+/// conversations that no longer exist. A missing folder skips the run without recording it,
+/// so the next check tries again.
+pub async fn run_export(state: &AppState) -> Result<AutoExportSummary, String> {
+    let settings = load_settings(state).await?;
+    let Some(dir) = settings.dir.as_deref().map(PathBuf::from) else {
+        return Err("No auto-export folder is set.".to_string());
+    };
+    if !dir.is_dir() {
+        log::warn!("Auto-export folder {} is not available; skipping this run", dir.display());
+        return Ok(AutoExportSummary {
+            skipped_reason: Some(format!("Folder {} is not available", dir.display())),
+            ..Default::default()
+        });
+    }
+    let format = parse_format(&settings.format)?;
+    let started_at = Utc::now();
+
+    let conversations = {
+        let storage = state.storage.lock().await;
+        storage.list_conversations(ConversationSort::LastUpdated, false).await
+            .map_err(|e| format!("Failed to list conversations: {}", e))?
+    };
+    let existing = exported_files(&dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+
+    let mut summary = AutoExportSummary::default();
+    let mut expected: HashMap<String, String> = HashMap::new(); // Short ID -> file name
+    for conversation in conversations.iter().filter(|c| !c.ephemeral) {
+        let file_name = file_name(&conversation.title, conversation.id, format);
+        let short_id = short_id(conversation.id);
+        let changed = settings.last_export_at.is_none_or(|last| conversation.last_updated_at > last);
+        let present = existing.get(&short_id).is_some_and(|names| names.contains(&file_name));
+        expected.insert(short_id, file_name.clone());
+        if !changed && present {
+            continue;
+        }
+
+        let messages = {
+            let storage = state.storage.lock().await;
+            storage.get_conversation_messages(conversation.id).await
+                .map_err(|e| format!("Failed to load messages of {}: {}", conversation.id, e))?
+        };
+        let rendered = export::render(format, conversation, &messages, false, None)?;
+        tokio::fs::write(dir.join(&file_name), rendered).await
+            .map_err(|e| format!("Failed to write {}: {}", file_name, e))?;
+        summary.written += 1;
+    }
+
+    // Files of deleted conversations, and ones left under an old title or format
+    for (short_id, names) in &existing {
+        for name in names {
+            if expected.get(short_id) == Some(name) {
+                continue;
+            }
+            match tokio::fs::remove_file(dir.join(name)).await {
+                Ok(()) => summary.removed += 1,
+                Err(e) => log::warn!("Auto-export: failed to remove {}: {}", name, e),
+            }
+        }
+    }
+
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::AUTO_EXPORT_LAST_RUN_KEY, &started_at.to_rfc3339()).await
+        .map_err(|e| format!("Failed to record the export time: {}", e))?;
+    Ok(summary)
+}
+
+fn short_id(id: Uuid) -> String {
+    id.simple().to_string()[..SHORT_ID_LEN].to_string()
+}
+
+fn file_name(title: &str, id: Uuid, format: ExportFormat) -> String {
+    let slug = slugify(title);
+    let slug = if slug.is_empty() { "conversation".to_string() } else { slug };
+    format!("{}-{}.{}", slug, short_id(id), extension(format))
+}
+
+// Lowercase ASCII letters and digits, with every other run of characters turned into one dash
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.len() >= MAX_SLUG_CHARS {
+            break;
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+// Files in `dir` that look like this exporter's output, by short ID. Anything else in the
+// folder is left alone.
+fn exported_files(dir: &Path) -> std::io::Result<HashMap<String, Vec<String>>> {
+    let mut files: HashMap<String, Vec<String>> = HashMap::new();
+    for entry in std::fs::read_dir(dir)? {
+        let Ok(name) = entry?.file_name().into_string() else {
+            continue;
+        };
+        let Some(stem) = name.strip_suffix(".md").or_else(|| name.strip_suffix(".json")) else {
+            continue;
+        };
+        let Some((_, short_id)) = stem.rsplit_once('-') else {
+            continue;
+        };
+        if short_id.len() == SHORT_ID_LEN && short_id.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase()) {
+            files.entry(short_id.to_string()).or_default().push(name);
+        }
+    }
+    Ok(files)
+}
@@ -0,0 +1,485 @@
+// Opt-in HTTP API on 127.0.0.1 so scripts can drive the app. Every request needs
+// `Authorization: Bearer <token>` (see `config::automation_token`). Actions go through the
+// same Tauri commands the frontend calls, so open windows see them via the usual events.
+//
+//   POST /v1/conversations                       {"title"?}   -> 201 Conversation
+//   POST /v1/conversations/{id}/messages         {"content"}  -> 202 the saved user Message
+//   GET  /v1/conversations/{id}/messages/{message_id}/reply?wait=SECS
+//        -> 200 the assistant Message answering it once it has finished streaming,
+//           202 {"status": "pending"} until then. `wait` long-polls for up to MAX_WAIT_SECS.
+//
+// One request per connection; bodies need a Content-Length.
+
+use crate::commands;
+use crate::config;
+use crate::error::{CommandError, ErrorKind};
+use crate::models::Message;
+use crate::state::AppState;
+use crate::storage::StorageManager;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::Manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+pub const DEFAULT_PORT: u16 = 47321;
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+const MAX_WAIT_SECS: u64 = 60;
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+// A client that hasn't sent its whole request by then is disconnected
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A running listener, kept in `AppState::automation`.
+pub struct AutomationListener {
+    port: u16,
+    shutdown: watch::Sender<bool>,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+/// Payload of `get_automation_settings`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AutomationSettings {
+    pub enabled: bool,
+    pub port: u16,
+    pub listening: bool, // False when enabled but the port couldn't be bound or the keyring failed
+}
+
+/// Whether the listener is turned on and the port it uses, from settings.
+pub async fn load_settings(storage: &StorageManager) -> (bool, u16) {
+    let enabled = match storage.get_setting(config::AUTOMATION_ENABLED_KEY).await {
+        Ok(value) => value.as_deref() == Some("true"),
+        Err(e) => {
+            log::warn!("Failed to read setting '{}': {:?}", config::AUTOMATION_ENABLED_KEY, e);
+            false
+        }
+    };
+    let port = match storage.get_setting(config::AUTOMATION_PORT_KEY).await {
+        Ok(Some(value)) => value.trim().parse::<u16>().ok().filter(|port| *port > 0).unwrap_or_else(|| {
+            log::warn!("Ignoring invalid automation port '{}'", value);
+            DEFAULT_PORT
+        }),
+        Ok(None) => DEFAULT_PORT,
+        Err(e) => {
+            log::warn!("Failed to read setting '{}': {:?}", config::AUTOMATION_PORT_KEY, e);
+            DEFAULT_PORT
+        }
+    };
+    (enabled, port)
+}
+
+pub fn is_listening(state: &AppState) -> bool {
+    state.automation.lock().map(|listener| listener.is_some()).unwrap_or(false)
+}
+
+/// Stops any running listener, then starts one when settings turn it on.
+/// Returns the port listened on, or None when it is off.
+pub async fn restart(state: &AppState) -> Result<Option<u16>, String> {
+    stop(state).await;
+    let (enabled, port) = {
+        let storage = state.storage.lock().await;
+        load_settings(&storage).await
+    };
+    if !enabled {
+        return Ok(None);
+    }
+    let token = config::automation_token().map_err(|e| format!("Failed to get the automation token: {}", e))?;
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .map_err(|e| format!("Failed to listen on 127.0.0.1:{}: {}", port, e))?;
+
+    let (shutdown, shutdown_rx) = watch::channel(false);
+    let task = tauri::async_runtime::spawn(accept_loop(state.clone(), listener, Arc::new(token), shutdown_rx));
+    if let Ok(mut running) = state.automation.lock() {
+        *running = Some(AutomationListener { port, shutdown, task });
+    }
+    log::info!("Automation API listening on 127.0.0.1:{}", port);
+    Ok(Some(port))
+}
+
+/// Stops accepting connections and ends open long-polls. Safe to call when nothing runs.
+pub async fn stop(state: &AppState) {
+    let running = state.automation.lock().ok().and_then(|mut running| running.take());
+    if let Some(listener) = running {
+        let _ = listener.shutdown.send(true);
+        let _ = listener.task.await;
+        log::info!("Automation API on port {} stopped", listener.port);
+    }
+}
+
+async fn accept_loop(state: AppState, listener: TcpListener, token: Arc<String>, mut shutdown: watch::Receiver<bool>) {
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    tauri::async_runtime::spawn(handle_connection(state.clone(), stream, token.clone(), shutdown.clone()));
+                }
+                Err(e) => log::warn!("Automation API failed to accept a connection: {}", e),
+            },
+            _ = shutdown.changed() => break,
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    authorization: Option<String>,
+    body: Vec<u8>,
+}
+
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        let body = serde_json::to_string(value).unwrap_or_else(|_| "{}".to_string());
+        Self { status, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &serde_json::json!({ "message": message }))
+    }
+
+    fn from_command_error(error: CommandError) -> Self {
+        let status = match error.kind {
+            ErrorKind::NotFound => 404,
+            ErrorKind::Validation => 400,
+            ErrorKind::Locked => 409,
+            ErrorKind::OfflineMode | ErrorKind::StorageUnavailable => 503,
+            ErrorKind::ApiKey | ErrorKind::Provider => 502,
+            _ => 500,
+        };
+        Self::json(status, &error)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            413 => "Payload Too Large",
+            431 => "Request Header Fields Too Large",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            reason,
+            self.body.len(),
+            self.body
+        )
+        .into_bytes()
+    }
+}
+
+async fn handle_connection(state: AppState, mut stream: TcpStream, token: Arc<String>, shutdown: watch::Receiver<bool>) {
+    let response = match tokio::time::timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Ok(Ok(request)) => respond(&state, &token, request, shutdown).await,
+        Ok(Err(response)) => response,
+        Err(_) => Response::error(408, "Timed out reading the request"),
+    };
+    if let Err(e) = stream.write_all(&response.to_bytes()).await {
+        log::debug!("Automation API failed to write a response: {}", e);
+    }
+    let _ = stream.shutdown().await;
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request, Response> {
+    let mut buf = Vec::with_capacity(1024);
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut chunk).await.map_err(|_| Response::error(400, "Failed to read the request"))?;
+        if read == 0 {
+            return Err(Response::error(400, "Incomplete request"));
+        }
+        buf.extend_from_slice(&chunk[..read]);
+
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let header_len = match parsed.parse(&buf) {
+            Ok(httparse::Status::Complete(len)) => len,
+            Ok(httparse::Status::Partial) if buf.len() > MAX_HEADER_BYTES => {
+                return Err(Response::error(431, "Request headers are too large"));
+            }
+            Ok(httparse::Status::Partial) => continue,
+            Err(_) => return Err(Response::error(400, "Malformed request")),
+        };
+
+        let header = |name: &str| {
+            parsed.headers.iter()
+                .find(|h| h.name.eq_ignore_ascii_case(name))
+                .and_then(|h| std::str::from_utf8(h.value).ok())
+        };
+        let content_length = match header("content-length") {
+            Some(value) => value.trim().parse::<usize>().map_err(|_| Response::error(400, "Invalid Content-Length"))?,
+            None => 0,
+        };
+        if content_length > MAX_BODY_BYTES {
+            return Err(Response::error(413, "Request body is too large"));
+        }
+        let target = parsed.path.unwrap_or("/");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut request = Request {
+            method: parsed.method.unwrap_or_default().to_string(),
+            path: path.to_string(),
+            query: query.to_string(),
+            authorization: header("authorization").map(str::to_string),
+            body: buf[header_len..].to_vec(),
+        };
+
+        while request.body.len() < content_length {
+            let read = stream.read(&mut chunk).await.map_err(|_| Response::error(400, "Failed to read the request body"))?;
+            if read == 0 {
+                return Err(Response::error(400, "Incomplete request body"));
+            }
+            request.body.extend_from_slice(&chunk[..read]);
+        }
+        request.body.truncate(content_length);
+        return Ok(request);
+    }
+}
+
+// Compares in constant time so the token can't be guessed byte by byte
+fn authorized(authorization: Option<&str>, token: &str) -> bool {
+    let Some(given) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    let given = given.trim();
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+async fn respond(state: &AppState, token: &str, request: Request, shutdown: watch::Receiver<bool>) -> Response {
+    if !authorized(request.authorization.as_deref(), token) {
+        return Response::error(401, "Missing or invalid bearer token");
+    }
+    log::info!("Automation API: {} {}", request.method, request.path);
+
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let result = match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["v1", "conversations"]) => create_conversation(state, &request.body).await,
+        ("POST", ["v1", "conversations", conversation_id, "messages"]) => {
+            send_message(state, conversation_id, &request.body).await
+        }
+        ("GET", ["v1", "conversations", conversation_id, "messages", message_id, "reply"]) => {
+            reply(state, conversation_id, message_id, &request.query, shutdown).await
+        }
+        _ => return Response::error(404, "No such endpoint"),
+    };
+    result.unwrap_or_else(Response::from_command_error)
+}
+
+fn parse_body<'a, T: Deserialize<'a> + Default>(body: &'a [u8]) -> Result<T, CommandError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(body).map_err(|e| CommandError::validation(format!("Invalid JSON body: {}", e)))
+}
+
+#[derive(Deserialize, Default)]
+struct CreateConversationBody {
+    title: Option<String>,
+}
+
+async fn create_conversation(state: &AppState, body: &[u8]) -> Result<Response, CommandError> {
+    let body: CreateConversationBody = parse_body(body)?;
+    let mut conversation = commands::create_conversation(state.app_handle.state::<AppState>()).await?;
+    if let Some(title) = body.title.map(|title| title.trim().to_string()).filter(|title| !title.is_empty()) {
+        commands::rename_conversation(state.app_handle.state::<AppState>(), conversation.id.to_string(), title.clone()).await?;
+        conversation.title = title;
+    }
+    state.notify_conversation_updated(conversation.id);
+    Ok(Response::json(201, &conversation))
+}
+
+#[derive(Deserialize, Default)]
+struct SendMessageBody {
+    content: String,
+}
+
+async fn send_message(state: &AppState, conversation_id: &str, body: &[u8]) -> Result<Response, CommandError> {
+    let body: SendMessageBody = parse_body(body)?;
+    if body.content.trim().is_empty() {
+        return Err(CommandError::validation("content cannot be empty"));
+    }
+    let message = commands::send_message(state.app_handle.state::<AppState>(), conversation_id.to_string(), body.content, None).await?;
+    Ok(Response::json(202, &message))
+}
+
+async fn reply(
+    state: &AppState,
+    conversation_id: &str,
+    message_id: &str,
+    query: &str,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<Response, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let Ok(msg_uuid) = Uuid::parse_str(message_id) else {
+        return Err(CommandError::validation(format!("Invalid message ID format: {}", message_id)));
+    };
+    let wait = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("wait="))
+        .map(|value| value.parse::<u64>().map_err(|_| CommandError::validation("wait must be a number of seconds")))
+        .transpose()?
+        .unwrap_or(0)
+        .min(MAX_WAIT_SECS);
+
+    let deadline = Instant::now() + Duration::from_secs(wait);
+    loop {
+        if let Some(answer) = finished_reply(state, conv_uuid, msg_uuid).await? {
+            return Ok(Response::json(200, &answer));
+        }
+        if Instant::now() >= deadline || *shutdown.borrow() {
+            return Ok(Response::json(202, &serde_json::json!({ "status": "pending" })));
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            _ = shutdown.changed() => {}
+        }
+    }
+}
+
+// The assistant message right after `message_id`, once it is no longer streaming
+async fn finished_reply(state: &AppState, conversation_id: Uuid, message_id: Uuid) -> Result<Option<Message>, CommandError> {
+    let messages = {
+        let storage = state.storage.lock().await;
+        storage.get_conversation_messages(conversation_id).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?
+    };
+    let messages = state.with_ephemeral_messages(conversation_id, messages);
+    let Some(index) = messages.iter().position(|m| m.id == message_id) else {
+        return Err(CommandError::not_found(format!("Message {} is not in conversation {}", message_id, conversation_id)));
+    };
+    let answer = messages[index + 1..]
+        .iter()
+        .take_while(|m| m.role != "user")
+        .find(|m| m.role == "assistant" && !m.is_unselected_variant());
+    Ok(answer.filter(|m| !state.active_streams.contains_key(&m.id)).cloned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockProvider;
+    use crate::storage::ConversationSort;
+    use crate::test_support::TestApp;
+
+    const TOKEN: &str = "test-token";
+
+    // Sends `request` as raw bytes to a one-shot handler, then closes the write side, and
+    // returns the status and JSON body
+    async fn exchange(app: &TestApp, request: &[u8]) -> (u16, serde_json::Value) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = app.state.clone();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (_shutdown, shutdown_rx) = watch::channel(false);
+            handle_connection(state, stream, Arc::new(TOKEN.to_string()), shutdown_rx).await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client.write_all(request).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut raw = Vec::new();
+        client.read_to_end(&mut raw).await.unwrap();
+        server.await.unwrap();
+
+        let raw = String::from_utf8(raw).unwrap();
+        let (head, body) = raw.split_once("\r\n\r\n").unwrap();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    fn post(authorization: Option<&str>, body: &str) -> Vec<u8> {
+        let authorization = authorization.map(|value| format!("Authorization: {}\r\n", value)).unwrap_or_default();
+        format!(
+            "POST /v1/conversations HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}",
+            authorization,
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn requests_without_the_bearer_token_are_unauthorized() {
+        let app = TestApp::new(MockProvider::new(vec![])).await;
+
+        for authorization in [None, Some("Bearer wrong-token"), Some("Bearer test-tokenX"), Some(TOKEN)] {
+            let (status, body) = exchange(&app, &post(authorization, "{}")).await;
+            assert_eq!(status, 401, "{:?}", authorization);
+            assert_eq!(body["message"], "Missing or invalid bearer token");
+        }
+        let storage = app.state.storage.lock().await;
+        assert!(storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn authorized_requests_reach_the_commands() {
+        let app = TestApp::new(MockProvider::new(vec![])).await;
+
+        let (status, body) = exchange(&app, &post(Some("Bearer test-token"), r#"{"title": "Scripted"}"#)).await;
+        assert_eq!(status, 201);
+        assert_eq!(body["title"], "Scripted");
+    }
+
+    #[tokio::test]
+    async fn malformed_requests_are_rejected() {
+        let app = TestApp::new(MockProvider::new(vec![])).await;
+
+        let (status, body) = exchange(&app, b"NOT A REQUEST\r\n\r\n").await;
+        assert_eq!((status, body["message"].as_str()), (400, Some("Malformed request")));
+
+        let bad_length = b"POST /v1/conversations HTTP/1.1\r\nContent-Length: lots\r\n\r\n";
+        let (status, body) = exchange(&app, bad_length).await;
+        assert_eq!((status, body["message"].as_str()), (400, Some("Invalid Content-Length")));
+
+        let truncated = b"POST /v1/conversations HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}";
+        let (status, body) = exchange(&app, truncated).await;
+        assert_eq!((status, body["message"].as_str()), (400, Some("Incomplete request body")));
+
+        let (status, _) = exchange(&app, &post(Some("Bearer test-token"), "{not json")).await;
+        assert_eq!(status, 400);
+        let (status, _) = exchange(&app, b"GET /v1/nowhere HTTP/1.1\r\nAuthorization: Bearer test-token\r\n\r\n").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn oversized_requests_are_rejected() {
+        let app = TestApp::new(MockProvider::new(vec![])).await;
+
+        let too_long = format!(
+            "POST /v1/conversations HTTP/1.1\r\nAuthorization: Bearer test-token\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY_BYTES + 1
+        );
+        let (status, body) = exchange(&app, too_long.as_bytes()).await;
+        assert_eq!((status, body["message"].as_str()), (413, Some("Request body is too large")));
+
+        let huge_header = format!("GET / HTTP/1.1\r\nX-Padding: {}", "a".repeat(MAX_HEADER_BYTES + 1));
+        let (status, _) = exchange(&app, huge_header.as_bytes()).await;
+        assert_eq!(status, 431);
+
+        let storage = app.state.storage.lock().await;
+        assert!(storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap().is_empty());
+    }
+}
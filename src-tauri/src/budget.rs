@@ -0,0 +1,75 @@
+// Monthly spend tracking against the user's budget
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeZone, Utc};
+use serde::Serialize;
+
+// Fraction of the budget at which a `budget_warning` is emitted
+pub const WARNING_THRESHOLD: f64 = 0.8;
+
+/// What happens once the month's spend reaches the budget.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetEnforcement {
+    Warn,
+    Block,
+}
+
+impl BudgetEnforcement {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "warn" => Ok(Self::Warn),
+            "block" => Ok(Self::Block),
+            other => Err(format!("Unknown budget enforcement: {}", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Warn => "warn",
+            Self::Block => "block",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetStatus {
+    pub spent_usd: f64,
+    pub budget_usd: Option<f64>, // None when no budget is set
+    pub days_remaining: u32, // Including today
+    // Assistant messages this month without cost data; they count as zero, so the spend is partial
+    pub untracked_messages: i64,
+    pub enforcement: BudgetEnforcement,
+}
+
+impl BudgetStatus {
+    // Share of the budget spent so far, when a budget is set
+    pub fn fraction_spent(&self) -> Option<f64> {
+        self.budget_usd
+            .filter(|budget| *budget > 0.0)
+            .map(|budget| self.spent_usd / budget)
+    }
+}
+
+fn first_of_month(year: i32, month: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, 1).expect("first of month is always valid")
+}
+
+/// Start of the current calendar month in local time, as UTC.
+pub fn month_start(now: DateTime<Local>) -> DateTime<Utc> {
+    let start = first_of_month(now.year(), now.month())
+        .and_hms_opt(0, 0, 0)
+        .expect("midnight is always valid");
+    Local
+        .from_local_datetime(&start)
+        .earliest()
+        .map(|dt| dt.with_timezone(&Utc))
+        .unwrap_or_else(|| start.and_utc())
+}
+
+/// Days left in the current month, counting today.
+pub fn days_remaining(now: DateTime<Local>) -> u32 {
+    let (year, month) = if now.month() == 12 { (now.year() + 1, 1) } else { (now.year(), now.month() + 1) };
+    let next_month = first_of_month(year, month);
+    (next_month - now.date_naive()).num_days() as u32
+}
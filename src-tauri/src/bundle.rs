@@ -0,0 +1,378 @@
+// `.localchat` bundles for moving a conversation between devices: a zip holding
+// `conversation.json` (the JSON export), an `attachments/` directory for files the messages
+// reference, and `manifest.json` listing every other entry with its SHA-256. The schema has
+// no attachments yet, so exports carry none and imports report any they find as skipped.
+// Imports keep what verifies and report the rest instead of failing outright.
+
+use crate::diagnostics;
+use crate::export::{self, ExportFormat};
+use crate::models::{Conversation, Message};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+const BUNDLE_VERSION: u32 = 1;
+const MANIFEST_PATH: &str = "manifest.json";
+const CONVERSATION_PATH: &str = "conversation.json";
+const ATTACHMENTS_DIR: &str = "attachments/";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleManifest {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub conversation_id: String, // ID on the exporting device
+    pub files: Vec<BundleFile>,
+}
+
+/// One entry of the bundle, as listed in the manifest.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleFile {
+    pub path: String,
+    pub sha256: String, // Lowercase hex
+    pub size: u64,
+}
+
+// Shape of `conversation.json`, as written by `export::render` in JSON format
+#[derive(Deserialize)]
+struct BundleConversation {
+    conversation: Conversation,
+    messages: Vec<Message>,
+}
+
+/// An entry left out of an import, and why.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SkippedEntry {
+    pub path: String,
+    pub reason: String,
+}
+
+/// Result of `import_conversation_bundle`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleImportSummary {
+    pub conversation: Conversation,
+    pub imported_messages: usize,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+/// What `read` recovered from a bundle.
+#[derive(Debug)]
+pub struct BundleContents {
+    pub conversation: Conversation,
+    pub messages: Vec<Message>,
+    pub skipped: Vec<SkippedEntry>,
+}
+
+fn sha256_hex(contents: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(contents))
+}
+
+/// Builds the bundle for a conversation and its messages.
+pub fn write(conversation: &Conversation, messages: &[Message]) -> Result<Vec<u8>, String> {
+    let json = export::render(ExportFormat::Json, conversation, messages, false, None)?;
+    let files = vec![(CONVERSATION_PATH.to_string(), json.into_bytes())];
+    let manifest = BundleManifest {
+        version: BUNDLE_VERSION,
+        exported_at: Utc::now(),
+        conversation_id: conversation.id.to_string(),
+        files: files
+            .iter()
+            .map(|(path, contents)| BundleFile {
+                path: path.clone(),
+                sha256: sha256_hex(contents),
+                size: contents.len() as u64,
+            })
+            .collect(),
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+
+    let mut entries = vec![(MANIFEST_PATH.to_string(), manifest)];
+    entries.extend(files);
+    diagnostics::zip(&entries).map_err(|e| format!("Failed to build bundle: {}", e))
+}
+
+/// Reads a bundle, checking each entry against its zip checksum and manifest hash. Fails
+/// only when the conversation itself can't be recovered; anything else unusable is skipped.
+pub fn read(archive: &[u8]) -> Result<BundleContents, String> {
+    let (mut entries, mut skipped) = unzip(archive, &LIMITS)?;
+
+    let manifest: Option<BundleManifest> = match entries.remove(MANIFEST_PATH) {
+        Some(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(manifest) => Some(manifest),
+            Err(e) => {
+                skipped.push(SkippedEntry { path: MANIFEST_PATH.to_string(), reason: format!("Unreadable manifest: {}", e) });
+                None
+            }
+        },
+        // Reported once, with the extraction failure when there was one
+        None => {
+            match skipped.iter_mut().find(|s| s.path == MANIFEST_PATH) {
+                Some(failed) => failed.reason.push_str("; contents not verified"),
+                None => skipped.push(SkippedEntry { path: MANIFEST_PATH.to_string(), reason: "Missing; contents not verified".to_string() }),
+            }
+            None
+        }
+    };
+    if let Some(manifest) = manifest.as_ref().filter(|m| m.version > BUNDLE_VERSION) {
+        return Err(format!("Bundle version {} is newer than this app supports ({})", manifest.version, BUNDLE_VERSION));
+    }
+
+    if let Some(manifest) = &manifest {
+        for file in &manifest.files {
+            let Some(contents) = entries.get(&file.path) else {
+                if !skipped.iter().any(|s| s.path == file.path) {
+                    skipped.push(SkippedEntry { path: file.path.clone(), reason: "Listed in the manifest but missing".to_string() });
+                }
+                continue;
+            };
+            if sha256_hex(contents) != file.sha256 {
+                entries.remove(&file.path);
+                skipped.push(SkippedEntry { path: file.path.clone(), reason: "Hash does not match the manifest".to_string() });
+            }
+        }
+    }
+
+    // No attachment storage to copy them into yet
+    let mut attachment_paths: Vec<String> = entries.keys().filter(|path| path.starts_with(ATTACHMENTS_DIR)).cloned().collect();
+    attachment_paths.sort();
+    for path in attachment_paths {
+        entries.remove(&path);
+        skipped.push(SkippedEntry { path, reason: "Attachments are not supported by this version".to_string() });
+    }
+
+    let Some(json) = entries.remove(CONVERSATION_PATH) else {
+        return Err(format!("The bundle has no valid {}", CONVERSATION_PATH));
+    };
+    let parsed: BundleConversation = serde_json::from_slice(&json)
+        .map_err(|e| format!("Failed to parse {}: {}", CONVERSATION_PATH, e))?;
+    let mut unknown: Vec<String> = entries.into_keys().collect();
+    unknown.sort();
+    skipped.extend(unknown.into_iter().map(|path| SkippedEntry { path, reason: "Not part of the bundle format".to_string() }));
+
+    Ok(BundleContents { conversation: parsed.conversation, messages: parsed.messages, skipped })
+}
+
+// Extracted zip entries by name
+type ZipEntries = HashMap<String, Vec<u8>>;
+
+// Caps on what an import inflates, so a malformed or hostile bundle can't exhaust memory
+struct ArchiveLimits {
+    entries: usize,
+    entry_bytes: u64,
+    total_bytes: u64,
+}
+
+const LIMITS: ArchiveLimits = ArchiveLimits {
+    entries: 10_000,
+    entry_bytes: 512 * 1024 * 1024,
+    total_bytes: 1024 * 1024 * 1024,
+};
+
+// Entries of a zip archive by name, plus those that failed to extract. A missing or broken
+// central directory, or an archive over `limits`, is an error.
+fn unzip(archive: &[u8], limits: &ArchiveLimits) -> Result<(ZipEntries, Vec<SkippedEntry>), String> {
+    let mut archive = ZipArchive::new(Cursor::new(archive)).map_err(|e| format!("Not a valid bundle ({})", e))?;
+    if archive.len() > limits.entries {
+        return Err(format!("The bundle has more than {} entries", limits.entries));
+    }
+
+    let mut entries = HashMap::new();
+    let mut skipped = Vec::new();
+    let mut total_bytes = 0;
+    for index in 0..archive.len() {
+        let path = archive.name_for_index(index).unwrap_or_default().to_string();
+        let mut entry = match archive.by_index(index) {
+            Ok(entry) => entry,
+            Err(e) => {
+                skipped.push(SkippedEntry { path, reason: extraction_failure(e) });
+                continue;
+            }
+        };
+        if entry.is_dir() {
+            continue;
+        }
+        // Sizes in the headers can lie, so the caps apply to what actually inflates
+        let budget = limits.entry_bytes.min(limits.total_bytes - total_bytes);
+        let declared_size = entry.size();
+        let mut contents = Vec::new();
+        match (&mut entry).take(budget + 1).read_to_end(&mut contents) {
+            Ok(_) if contents.len() as u64 > budget && budget < limits.entry_bytes => {
+                return Err(format!("The bundle inflates to more than {} bytes", limits.total_bytes));
+            }
+            Ok(_) if contents.len() as u64 > budget => {
+                skipped.push(SkippedEntry { path, reason: "Entry is too large".to_string() });
+            }
+            Ok(_) => {
+                total_bytes += contents.len() as u64;
+                entries.insert(path, contents);
+            }
+            // Data ending early fails its checksum too; report the cause
+            Err(_) if (contents.len() as u64) < declared_size => {
+                skipped.push(SkippedEntry { path, reason: "Entry is truncated".to_string() });
+            }
+            Err(e) => skipped.push(SkippedEntry { path, reason: extraction_failure(e.into()) }),
+        }
+    }
+    Ok((entries, skipped))
+}
+
+fn extraction_failure(error: ZipError) -> String {
+    match error {
+        ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED) => "Entry is encrypted".to_string(),
+        ZipError::UnsupportedArchive(_) => "Unsupported compression method".to_string(),
+        ZipError::Io(e) if e.to_string() == "Invalid checksum" => "Checksum mismatch".to_string(),
+        other => format!("Failed to extract: {}", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+    use uuid::Uuid;
+
+    fn conversation() -> (Conversation, Vec<Message>) {
+        let conversation: Conversation =
+            serde_json::from_value(serde_json::json!({ "title": "Trip plans", "model_config_id": Uuid::new_v4() })).unwrap();
+        let messages = vec![
+            test_support::message_at(conversation.id, "user", "Where should we go?", 1_000),
+            test_support::message_at(conversation.id, "assistant", "Lisbon, in May.", 1_001),
+        ];
+        (conversation, messages)
+    }
+
+    // The entries `write` produces, by name
+    fn entries() -> ZipEntries {
+        let (conversation, messages) = conversation();
+        unzip(&write(&conversation, &messages).unwrap(), &LIMITS).unwrap().0
+    }
+
+    fn zip(entries: &ZipEntries) -> Vec<u8> {
+        let mut files: Vec<(String, Vec<u8>)> = entries.iter().map(|(path, contents)| (path.clone(), contents.clone())).collect();
+        files.sort();
+        diagnostics::zip(&files).unwrap()
+    }
+
+    // Overwrites a field of `name`'s central directory header
+    fn patch_header(archive: &mut [u8], name: &str, field: usize, value: &[u8]) {
+        let header = (0..archive.len())
+            .find(|&i| archive[i..].starts_with(&0x02014b50u32.to_le_bytes()) && archive[i + 46..].starts_with(name.as_bytes()))
+            .expect("entry is in the archive");
+        archive[header + field..header + field + value.len()].copy_from_slice(value);
+    }
+
+    fn skipped(contents: &BundleContents) -> Vec<(&str, &str)> {
+        contents.skipped.iter().map(|s| (s.path.as_str(), s.reason.as_str())).collect()
+    }
+
+    #[test]
+    fn bundles_round_trip() {
+        let (conversation, messages) = conversation();
+        let contents = read(&write(&conversation, &messages).unwrap()).unwrap();
+        assert_eq!(contents.conversation.id, conversation.id);
+        assert_eq!(contents.conversation.title, "Trip plans");
+        let read_back: Vec<(Uuid, &str, &str)> =
+            contents.messages.iter().map(|m| (m.id, m.role.as_str(), m.content.as_str())).collect();
+        let written: Vec<(Uuid, &str, &str)> = messages.iter().map(|m| (m.id, m.role.as_str(), m.content.as_str())).collect();
+        assert_eq!(read_back, written);
+        assert!(contents.skipped.is_empty());
+    }
+
+    #[test]
+    fn truncated_entries_are_skipped() {
+        let mut archive = zip(&entries());
+        patch_header(&mut archive, MANIFEST_PATH, 20, &10u32.to_le_bytes());
+        let contents = read(&archive).unwrap();
+        assert_eq!(skipped(&contents), [(MANIFEST_PATH, "Entry is truncated; contents not verified")]);
+        assert_eq!(contents.messages.len(), 2);
+
+        let mut archive = zip(&entries());
+        patch_header(&mut archive, CONVERSATION_PATH, 20, &10u32.to_le_bytes());
+        assert_eq!(read(&archive).unwrap_err(), "The bundle has no valid conversation.json");
+        assert!(read(&archive[..archive.len() / 2]).unwrap_err().starts_with("Not a valid bundle"));
+    }
+
+    #[test]
+    fn entries_failing_their_checksum_are_skipped() {
+        let mut archive = zip(&entries());
+        patch_header(&mut archive, MANIFEST_PATH, 16, &0u32.to_le_bytes());
+        let contents = read(&archive).unwrap();
+        assert_eq!(skipped(&contents), [(MANIFEST_PATH, "Checksum mismatch; contents not verified")]);
+
+        let mut archive = zip(&entries());
+        patch_header(&mut archive, CONVERSATION_PATH, 16, &0u32.to_le_bytes());
+        assert!(read(&archive).is_err());
+    }
+
+    #[test]
+    fn entries_not_matching_the_manifest_are_skipped() {
+        let mut entries = entries();
+        entries.insert("notes.txt".to_string(), b"stray".to_vec());
+        entries.insert(format!("{}photo.png", ATTACHMENTS_DIR), b"png".to_vec());
+        let contents = read(&zip(&entries)).unwrap();
+        assert_eq!(
+            skipped(&contents),
+            [
+                ("attachments/photo.png", "Attachments are not supported by this version"),
+                ("notes.txt", "Not part of the bundle format"),
+            ]
+        );
+
+        // A conversation edited after export no longer matches its hash and can't be trusted
+        let json = String::from_utf8(entries[CONVERSATION_PATH].clone()).unwrap().replace("Lisbon", "Berlin");
+        entries.insert(CONVERSATION_PATH.to_string(), json.into_bytes());
+        assert_eq!(read(&zip(&entries)).unwrap_err(), "The bundle has no valid conversation.json");
+    }
+
+    #[test]
+    fn bundles_without_a_manifest_import_unverified() {
+        let mut entries = entries();
+        entries.remove(MANIFEST_PATH);
+        let contents = read(&zip(&entries)).unwrap();
+        assert_eq!(skipped(&contents), [(MANIFEST_PATH, "Missing; contents not verified")]);
+        assert_eq!(contents.messages.len(), 2);
+    }
+
+    #[test]
+    fn unsupported_compression_methods_are_skipped() {
+        let mut archive = zip(&entries());
+        patch_header(&mut archive, MANIFEST_PATH, 10, &12u16.to_le_bytes()); // bzip2
+        let contents = read(&archive).unwrap();
+        assert_eq!(skipped(&contents), [(MANIFEST_PATH, "Unsupported compression method; contents not verified")]);
+        assert_eq!(contents.conversation.title, "Trip plans");
+    }
+
+
+    #[test]
+    fn oversized_archives_are_refused() {
+        let limits = ArchiveLimits { entries: 3, entry_bytes: 1024 * 1024, total_bytes: 3 * 512 * 1024 };
+        // Zeros deflate about a thousandfold, so each archive is a few kilobytes
+        let zeros = |name: &str, len: usize| (name.to_string(), vec![0u8; len]);
+
+        let archive = diagnostics::zip(&[zeros("big.bin", 1024 * 1024 + 1), zeros("small.bin", 1024)]).unwrap();
+        assert!(archive.len() < 16 * 1024);
+        let (entries, skipped) = unzip(&archive, &limits).unwrap();
+        assert_eq!(entries.keys().collect::<Vec<_>>(), ["small.bin"]);
+        let skipped: Vec<(&str, &str)> = skipped.iter().map(|s| (s.path.as_str(), s.reason.as_str())).collect();
+        assert_eq!(skipped, [("big.bin", "Entry is too large")]);
+
+        // Headers claiming tiny sizes don't get entries past the caps
+        let honest = diagnostics::zip(&[zeros("a.bin", 1024 * 1024), zeros("b.bin", 1024 * 1024)]).unwrap();
+        let mut lying = honest.clone();
+        patch_header(&mut lying, "a.bin", 24, &1u32.to_le_bytes());
+        patch_header(&mut lying, "b.bin", 24, &1u32.to_le_bytes());
+        for archive in [honest, lying] {
+            assert_eq!(unzip(&archive, &limits).unwrap_err(), "The bundle inflates to more than 1572864 bytes");
+        }
+
+        let archive = diagnostics::zip(&(0..4).map(|i| zeros(&format!("{}.bin", i), 1)).collect::<Vec<_>>()).unwrap();
+        assert_eq!(unzip(&archive, &limits).unwrap_err(), "The bundle has more than 3 entries");
+    }
+}
@@ -0,0 +1,81 @@
+// Append-only log of conversation changes that can't otherwise be taken back: renames, model
+// switches, message deletes, message edits and role changes. `StorageManager` records each event in the
+// same transaction as the change itself. `undo_last_event` reverses the newest event that
+// hasn't been undone yet when it is a rename, model change or message delete, and refuses
+// when later changes make the reversal ambiguous. Events older than the retention setting
+// are pruned by `run_changelog_prune_loop`.
+
+use crate::config;
+use crate::state::AppState;
+use crate::storage::StorageManager;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const EVENT_RENAME: &str = "rename"; // {title}
+pub const EVENT_MODEL_CHANGE: &str = "model_change"; // {modelConfigId, modelOverride}
+pub const EVENT_MESSAGE_DELETE: &str = "message_delete"; // Old: the message; new: {latestSeq} left behind and its {seq}
+pub const EVENT_MESSAGE_EDIT: &str = "message_edit"; // {content, metadata}; recorded but not undoable
+pub const EVENT_MESSAGE_ROLE: &str = "message_role"; // {messageId, role}; recorded but not undoable
+
+pub const DEFAULT_RETENTION_DAYS: u32 = 30;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// One recorded change, as returned by `get_conversation_history`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationEvent {
+    pub id: i64,
+    pub conversation_id: Uuid,
+    pub event_type: String,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub undone_at: Option<DateTime<Utc>>,
+}
+
+/// Result of `StorageManager::undo_last_event`.
+#[derive(Debug)]
+pub enum UndoOutcome {
+    Undone(ConversationEvent),
+    NothingToUndo,
+    Refused(String), // Why the newest event can't be reversed
+}
+
+/// Days events are kept, from settings; 0 keeps them forever. Malformed values fall back to the default.
+pub async fn load_retention_days(storage: &StorageManager) -> u32 {
+    match storage.get_setting(config::CHANGELOG_RETENTION_DAYS_KEY).await {
+        Ok(Some(value)) => value.trim().parse::<u32>().unwrap_or_else(|_| {
+            log::warn!("Ignoring invalid changelog retention '{}'", value);
+            DEFAULT_RETENTION_DAYS
+        }),
+        Ok(None) => DEFAULT_RETENTION_DAYS,
+        Err(e) => {
+            log::warn!("Failed to read setting '{}': {:?}", config::CHANGELOG_RETENTION_DAYS_KEY, e);
+            DEFAULT_RETENTION_DAYS
+        }
+    }
+}
+
+/// Prunes expired events every PRUNE_INTERVAL for the life of the app.
+pub async fn run_changelog_prune_loop(state: AppState) {
+    loop {
+        prune(&state).await;
+        tokio::time::sleep(PRUNE_INTERVAL).await;
+    }
+}
+
+async fn prune(state: &AppState) {
+    let storage = state.storage.lock().await;
+    let retention_days = load_retention_days(&storage).await;
+    if retention_days == 0 {
+        return;
+    }
+    let cutoff = Utc::now() - chrono::Duration::days(i64::from(retention_days));
+    match storage.prune_conversation_events(cutoff).await {
+        Ok(0) => {}
+        Ok(pruned) => log::info!("Pruned {} conversation events older than {} days", pruned, retention_days),
+        Err(e) => log::error!("Failed to prune conversation events: {:?}", e),
+    }
+}
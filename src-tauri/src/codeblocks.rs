@@ -0,0 +1,289 @@
+// Fenced code blocks in a message's Markdown, for saving them as files. Fences follow
+// CommonMark: ``` or ~~~, at least three long, closed by a fence of the same character that
+// is at least as long. Fences may be indented by up to three spaces (more makes an indented
+// code line); that indentation is removed from the block's lines. A block left open runs to
+// the end of the message.
+
+use serde::Serialize;
+
+// File name used when the answer gives no hint, before the extension
+const DEFAULT_STEM: &str = "snippet";
+// Longest file name taken from a hint
+const MAX_FILENAME_CHARS: usize = 100;
+// Most spaces a fence may be indented by
+const MAX_FENCE_INDENT: usize = 3;
+
+/// One fenced block.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeBlock {
+    pub index: usize,
+    pub language: Option<String>, // First word of the fence's info string
+    pub code: String, // Lines joined with "\n"
+    pub line_count: usize,
+    pub suggested_filename: String,
+    pub terminated: bool, // False when the message ended before the closing fence
+}
+
+// An open fence: its character, length and indentation
+struct Fence {
+    marker: char,
+    len: usize,
+    indent: usize,
+}
+
+// The fence opened or closed by `line`, with the rest of the line (the info string)
+fn parse_fence(line: &str) -> Option<(Fence, &str)> {
+    let trimmed = line.trim_start_matches(' ');
+    let indent = line.len() - trimmed.len();
+    if indent > MAX_FENCE_INDENT {
+        return None;
+    }
+    let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = trimmed.chars().take_while(|c| *c == marker).count();
+    if len < 3 {
+        return None;
+    }
+    let info = trimmed[len..].trim();
+    // A backtick fence's info string can't contain backticks (that's inline code)
+    if marker == '`' && info.contains('`') {
+        return None;
+    }
+    Some((Fence { marker, len, indent }, info))
+}
+
+fn closes(open: &Fence, line: &str) -> bool {
+    parse_fence(line).is_some_and(|(fence, info)| fence.marker == open.marker && fence.len >= open.len && info.is_empty())
+}
+
+// Removes up to `indent` leading spaces
+fn strip_indent(line: &str, indent: usize) -> &str {
+    let strip = line.chars().take(indent).take_while(|c| *c == ' ').count();
+    &line[strip..]
+}
+
+/// Every fenced block in `content`, in order. CRLF line endings are read as LF.
+pub fn extract(content: &str) -> Vec<CodeBlock> {
+    let lines: Vec<&str> = content.lines().collect();
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let Some((fence, info)) = parse_fence(lines[i]) else {
+            i += 1;
+            continue;
+        };
+        let hint = filename_hint(info).or_else(|| preceding_hint(&lines[..i]));
+        let language = info.split_whitespace().next()
+            .map(|word| word.split(':').next().unwrap_or(word))
+            .filter(|language| !language.is_empty())
+            .map(str::to_string);
+
+        let start = i + 1;
+        let end = (start..lines.len()).find(|&j| closes(&fence, lines[j]));
+        let body = &lines[start..end.unwrap_or(lines.len())];
+        let code = body.iter().map(|line| strip_indent(line, fence.indent)).collect::<Vec<_>>().join("\n");
+
+        let index = blocks.len();
+        blocks.push(CodeBlock {
+            index,
+            suggested_filename: suggested_filename(hint.as_deref(), language.as_deref(), index),
+            language,
+            line_count: body.len(),
+            code,
+            terminated: end.is_some(),
+        });
+        i = end.map_or(lines.len(), |end| end + 1);
+    }
+    blocks
+}
+
+// A file name in the info string: ```rust title="main.rs"```, ```rust filename=main.rs```
+// or ```rust:src/main.rs```
+fn filename_hint(info: &str) -> Option<String> {
+    for word in info.split_whitespace() {
+        for key in ["title=", "filename=", "file="] {
+            if let Some(value) = word.strip_prefix(key) {
+                return clean_filename(value.trim_matches(['"', '\'']));
+            }
+        }
+    }
+    let (_, path) = info.split_whitespace().next()?.split_once(':')?;
+    clean_filename(path)
+}
+
+// A file name on the line right before the fence (blank lines skipped): "filename: x.py",
+// "File: `x.py`", "**x.py**" or "`x.py`"
+fn preceding_hint(before: &[&str]) -> Option<String> {
+    let line = before.iter().rev().map(|line| line.trim()).find(|line| !line.is_empty())?;
+    let line = line.trim_start_matches(['#', '>', '-', '*', ' ']).trim();
+    let lower = line.to_lowercase();
+    for prefix in ["filename:", "file name:", "file:"] {
+        if lower.starts_with(prefix) {
+            return clean_filename(&line[prefix.len()..]);
+        }
+    }
+    // A lone emphasized or code-formatted name that looks like a file
+    let bare = line.trim_end_matches(':').trim_matches(['*', '`', '_']);
+    if bare.contains('.') && !bare.contains(char::is_whitespace) && bare.len() < line.len() {
+        return clean_filename(bare);
+    }
+    None
+}
+
+// The file name part of a hinted path, without characters file systems reject
+fn clean_filename(value: &str) -> Option<String> {
+    let value = value.trim().trim_matches(['*', '`', '"', '\'', ':']).trim();
+    let name = value.rsplit(['/', '\\']).next().unwrap_or(value);
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, '<' | '>' | ':' | '"' | '|' | '?' | '*'))
+        .take(MAX_FILENAME_CHARS)
+        .collect();
+    let name = name.trim().trim_start_matches('.').to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+fn suggested_filename(hint: Option<&str>, language: Option<&str>, index: usize) -> String {
+    if let Some(hint) = hint {
+        return hint.to_string();
+    }
+    let extension = language.map(extension_for).unwrap_or("txt");
+    if index == 0 {
+        format!("{}.{}", DEFAULT_STEM, extension)
+    } else {
+        format!("{}-{}.{}", DEFAULT_STEM, index + 1, extension)
+    }
+}
+
+fn extension_for(language: &str) -> &'static str {
+    match language.to_lowercase().as_str() {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "javascript" | "js" | "node" => "js",
+        "typescript" | "ts" => "ts",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "html" => "html",
+        "css" => "css",
+        "scss" => "scss",
+        "bash" | "sh" | "shell" | "zsh" => "sh",
+        "powershell" | "ps1" => "ps1",
+        "sql" => "sql",
+        "go" | "golang" => "go",
+        "java" => "java",
+        "kotlin" | "kt" => "kt",
+        "swift" => "swift",
+        "c" => "c",
+        "cpp" | "c++" | "cxx" => "cpp",
+        "csharp" | "cs" | "c#" => "cs",
+        "ruby" | "rb" => "rb",
+        "php" => "php",
+        "markdown" | "md" => "md",
+        "xml" => "xml",
+        "dockerfile" => "dockerfile",
+        "makefile" | "make" => "mk",
+        "lua" => "lua",
+        "r" => "r",
+        _ => "txt",
+    }
+}
+
+/// `code` with the platform's line endings, for writing to disk.
+pub fn with_platform_line_endings(code: &str) -> String {
+    let code = code.replace("\r\n", "\n");
+    let mut code = if cfg!(windows) { code.replace('\n', "\r\n") } else { code };
+    if !code.is_empty() {
+        code.push_str(if cfg!(windows) { "\r\n" } else { "\n" });
+    }
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(content: &str) -> Vec<String> {
+        extract(content).into_iter().map(|block| block.code).collect()
+    }
+
+    #[test]
+    fn blocks_carry_language_code_and_a_file_name() {
+        let blocks = extract("Here:\n\n```python\nprint('hi')\n```\n\n**setup.sh**\n```bash\necho ok\n```\n\n~~~\nplain\n~~~");
+        let summary: Vec<(Option<&str>, &str, &str, bool)> = blocks
+            .iter()
+            .map(|b| (b.language.as_deref(), b.code.as_str(), b.suggested_filename.as_str(), b.terminated))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some("python"), "print('hi')", "snippet.py", true),
+                (Some("bash"), "echo ok", "setup.sh", true),
+                (None, "plain", "snippet-3.txt", true),
+            ]
+        );
+        assert_eq!(extract("```rust:src/main.rs\nfn main() {}\n```")[0].suggested_filename, "main.rs");
+    }
+
+    #[test]
+    fn indented_and_nested_fences() {
+        // Indentation up to three spaces is stripped from the block's lines
+        assert_eq!(codes("1. Install:\n   ```sh\n   npm i\n     --save\n   ```"), ["npm i\n  --save"]);
+        // Four spaces make an indented code line, not a fence
+        assert!(extract("    ```\n    not fenced\n    ```").is_empty());
+        assert!(extract("\t```\n\tnot fenced\n\t```").is_empty());
+
+        // A longer fence or the other marker holds shorter fences as content
+        assert_eq!(codes("````md\n```py\nx = 1\n```\n````"), ["```py\nx = 1\n```"]);
+        assert_eq!(codes("~~~\n```\ninner\n```\n~~~"), ["```\ninner\n```"]);
+        // A closing fence can't carry an info string
+        assert_eq!(codes("```\na\n```js\nb\n```"), ["a\n```js\nb"]);
+    }
+
+    #[test]
+    fn unterminated_blocks_run_to_the_end() {
+        let blocks = extract("```rust\nfn main() {\n}");
+        assert_eq!(blocks.len(), 1);
+        assert!(!blocks[0].terminated);
+        assert_eq!((blocks[0].code.as_str(), blocks[0].line_count), ("fn main() {\n}", 2));
+
+        // Shorter fences don't close a longer one
+        let blocks = extract("````\na\n```");
+        assert_eq!((blocks[0].code.as_str(), blocks[0].terminated), ("a\n```", false));
+        let blocks = extract("```\n");
+        assert_eq!((blocks[0].code.as_str(), blocks[0].line_count, blocks[0].terminated), ("", 0, false));
+    }
+
+    #[test]
+    fn line_endings() {
+        assert_eq!(codes("```\r\na\r\nb\r\n```\r\n"), ["a\nb"]);
+
+        let written = with_platform_line_endings("a\r\nb\nc");
+        if cfg!(windows) {
+            assert_eq!(written, "a\r\nb\r\nc\r\n");
+        } else {
+            assert_eq!(written, "a\nb\nc\n");
+        }
+        assert_eq!(with_platform_line_endings(""), "");
+    }
+
+    #[test]
+    fn file_name_hints_cannot_leave_the_target_directory() {
+        for hint in [
+            "```sh title=\"../../.bashrc\"\nx\n```",
+            "```sh:/etc/../.bashrc\nx\n```",
+            "File: `..\\..\\.bashrc`\n```sh\nx\n```",
+        ] {
+            assert_eq!(extract(hint)[0].suggested_filename, "bashrc", "{}", hint);
+        }
+        for hint in ["```py filename=..\nx\n```", "```py file=../\nx\n```", "filename: /\n```py\nx\n```"] {
+            assert_eq!(extract(hint)[0].suggested_filename, "snippet.py", "{}", hint);
+        }
+        let long = format!("```txt filename={}.txt\nx\n```", "a".repeat(300));
+        let name = &extract(&long)[0].suggested_filename;
+        assert_eq!(name.chars().count(), MAX_FILENAME_CHARS);
+        assert!(!name.contains(['/', '\\']));
+    }
+}
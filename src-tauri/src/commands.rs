@@ -1,38 +1,154 @@
 // Placeholder for Tauri commands exposed to frontend 
 
-use crate::models::{Conversation, Message, ModelConfig};
-use crate::state::AppState;
-use tauri::State;
+use crate::models::{Conversation, ConversationActivity, ConversationSnapshot, Message, MessageWithTitle, MessagesSince, ModelConfig, Persona};
+use crate::state::{ActiveStream, AppState};
+use crate::storage::{CompactionReport, ConversationSize, ConversationSort, MaintenanceReport, MessageSizeLimit, OversizedMessagePolicy, RawColumn, StorageManager, WipeReport};
+use tauri::{Manager, State};
 use uuid::Uuid;
 use chrono::Utc;
+use crate::about;
+use crate::automation;
 #[allow(unused_imports)]
 use crate::api::{LLMApiProvider, OpenAICompatibleProvider}; // Import API provider
+use crate::auto_export::{self, AutoExportSettings, AutoExportSummary};
+use crate::api::{ParsedProviderOptions, RawMethod, RawResponse};
+use crate::api::{all_provider_options_schemas, provider_options_schema, supported_providers, ProviderCapabilities, ProviderOptionField, ProviderOptionsCheck};
 use crate::config; // Import config module for API key retrieval
+use crate::events::{self, BudgetWarning, StreamKind, TokenBudgetExceeded};
+use crate::budget::{self, BudgetEnforcement, BudgetStatus};
+use crate::diagnostics;
+use crate::bundle::{self, BundleImportSummary};
+use crate::changelog::{ConversationEvent, UndoOutcome};
+use crate::codeblocks::{self, CodeBlock};
+use crate::error::{CommandError, ErrorKind};
+use crate::export::{self, ExportFormat};
+use crate::health::{self, ConnectionDiagnostics, HealthReport};
+use crate::ingest::{self, IngestSummary, ProjectContext, ProjectManifest};
+use crate::integrity::{DatabaseIntegrityReport, IntegrityReport, RepairActions, RepairSummary};
+use crate::jobs::{self, JobFailure, PendingJob, TitleJob};
+use crate::language;
+use crate::logs::RecentError;
+use crate::memory::{self, ConversationMemory};
+use crate::generation::{self, ComparisonVariant, ExtendedMessage, GenerationRequest, ReplacedMessage};
+use crate::prompt; // System prompt assembly
+use crate::prompt_files::{self, FilePrompt};
+use crate::redaction::{self, RedactionPreview, RedactionSettings, SecretPattern};
+use crate::safe_mode::{self, StorageStatus};
+use crate::search::{self, FindResult, MessageMatches, SearchHit};
+use crate::smoothing::{self, StreamSmoothing};
+use crate::transcript::{self, DelimiterPattern, MarkdownImportSummary, TranscriptEntry, TranscriptFormat};
+use crate::theme::{self, ThemeInfo, ThemePreference, TitleBarPreference};
+use crate::title::{self, TitleCase, TitleSettings, TitleStyle};
+use crate::tools::{ToolPermission, ToolPolicy};
+use crate::usage::{self, UsageGrouping};
 #[allow(unused_imports)]
 use std::sync::Arc; // To hold the API provider
+use std::collections::{BTreeMap, HashMap, HashSet};
 use tauri::Emitter; // For app_handle.emit
-use futures::StreamExt; // Added for stream processing
 use tauri_plugin_opener::OpenerExt; // <<< ADD THIS IMPORT >>>
 use tauri_plugin_dialog::DialogExt; // Needed for AppHandle dialog method
+use tauri_plugin_clipboard_manager::ClipboardExt; // For copying exports to the clipboard
 
-// Tauri command to list all conversations
+// Tauri command to list all conversations.
+// A given `sort_by`/`ascending` is remembered; when omitted, the last persisted sort is used.
 #[tauri::command]
-pub async fn list_conversations(state: State<'_, AppState>) -> Result<Vec<Conversation>, String> {
+pub async fn list_conversations(
+    state: State<'_, AppState>,
+    sort_by: Option<String>,
+    ascending: Option<bool>,
+) -> Result<Vec<Conversation>, CommandError> {
     log::info!("Frontend requested to list conversations");
     let storage_manager = state.storage.lock().await; // Lock the mutex to access StorageManager
-    match storage_manager.list_conversations().await {
+
+    let sort = match sort_by.as_deref() {
+        Some(value) => {
+            let sort = ConversationSort::parse(value).map_err(CommandError::validation)?;
+            storage_manager.set_setting(config::CONVERSATION_SORT_KEY, sort.as_str()).await
+                .map_err(|e| CommandError::storage(format!("Failed to save conversation sort: {}", e)))?;
+            sort
+        }
+        None => saved_conversation_sort(&storage_manager).await?,
+    };
+    let ascending = match ascending {
+        Some(value) => {
+            storage_manager.set_setting(config::CONVERSATION_SORT_ASCENDING_KEY, &value.to_string()).await
+                .map_err(|e| CommandError::storage(format!("Failed to save conversation sort: {}", e)))?;
+            value
+        }
+        None => storage_manager.get_setting(config::CONVERSATION_SORT_ASCENDING_KEY).await
+            .map_err(|e| CommandError::storage(format!("Failed to read conversation sort: {}", e)))?
+            .map(|value| value == "true")
+            .unwrap_or(false),
+    };
+
+    match storage_manager.list_conversations(sort, ascending).await {
         Ok(conversations) => Ok(conversations),
         Err(e) => {
             log::error!("Failed to list conversations: {:?}", e);
             // Convert the detailed error into a user-friendly string for the frontend
-            Err(format!("Failed to load conversations: {}", e))
+            Err(CommandError::storage(format!("Failed to load conversations: {}", e)))
+        }
+    }
+}
+
+// Tauri command to list conversations last updated within a range of ISO 8601 timestamps,
+// most recent first. Both bounds are inclusive and either may be omitted.
+#[tauri::command]
+pub async fn list_conversations_between(
+    state: State<'_, AppState>,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Vec<Conversation>, CommandError> {
+    log::info!("Frontend requested conversations updated between {:?} and {:?}", from, to);
+    let from = from.as_deref().map(parse_timestamp).transpose()?;
+    let to = to.as_deref().map(parse_timestamp).transpose()?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return Err(CommandError::validation("The start of the range is after its end."));
         }
     }
+    let storage = state.storage.lock().await;
+    storage.list_conversations_between(from, to).await
+        .map_err(|e| CommandError::storage(format!("Failed to load conversations: {}", e)))
+}
+
+// Parses an ISO 8601 / RFC 3339 timestamp from the frontend
+fn parse_timestamp(value: &str) -> Result<chrono::DateTime<Utc>, CommandError> {
+    chrono::DateTime::parse_from_rfc3339(value.trim())
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| CommandError::validation(format!("Invalid timestamp '{}': {}", value, e)))
+}
+
+// The persisted conversation list sort, defaulting to most recently updated
+async fn saved_conversation_sort(storage: &StorageManager) -> Result<ConversationSort, CommandError> {
+    Ok(storage.get_setting(config::CONVERSATION_SORT_KEY).await
+        .map_err(|e| CommandError::storage(format!("Failed to read conversation sort: {}", e)))?
+        .and_then(|value| ConversationSort::parse(&value).ok())
+        .unwrap_or(ConversationSort::LastUpdated))
+}
+
+// Tauri command to read the default conversation list sort ("last_updated", "created", ...)
+#[tauri::command]
+pub async fn get_conversation_sort(state: State<'_, AppState>) -> Result<String, CommandError> {
+    log::info!("Frontend requested the conversation sort");
+    let storage = state.storage.lock().await;
+    saved_conversation_sort(&storage).await.map(|sort| sort.as_str().to_string())
+}
+
+// Tauri command to set the default conversation list sort; "recent" is accepted
+// as an alias for "last_updated"
+#[tauri::command]
+pub async fn set_conversation_sort(state: State<'_, AppState>, sort: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set the conversation sort to {}", sort);
+    let sort = ConversationSort::parse(sort.trim()).map_err(CommandError::validation)?;
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::CONVERSATION_SORT_KEY, sort.as_str()).await
+        .map_err(|e| CommandError::storage(format!("Failed to save conversation sort: {}", e)))
 }
 
 // Tauri command to create a new conversation
 #[tauri::command]
-pub async fn create_conversation(state: State<'_, AppState>) -> Result<Conversation, String> {
+pub async fn create_conversation(state: State<'_, AppState>) -> Result<Conversation, CommandError> {
     println!("RUST_CMD: create_conversation entered"); // Added log
     let storage_manager = state.storage.lock().await;
     println!("RUST_CMD: create_conversation got storage lock"); // Added log
@@ -43,753 +159,4936 @@ pub async fn create_conversation(state: State<'_, AppState>) -> Result<Conversat
         },
         Err(e) => {
             println!("RUST_CMD: create_conversation storage error: {}", e); // Added log
-            Err(format!("Failed to create conversation: {}", e))
+            Err(CommandError::storage(format!("Failed to create conversation: {}", e)))
         },
     }
 }
 
-// Tauri command to get messages for a specific conversation
+// Tauri command to import a Markdown/plain-text transcript as a new conversation.
+// `format` overrides the role headings (defaults to `## User` / `## Assistant`).
+#[tauri::command]
+pub async fn import_transcript(
+    state: State<'_, AppState>,
+    text: String,
+    format: Option<TranscriptFormat>,
+    title: Option<String>,
+) -> Result<Conversation, CommandError> {
+    log::info!("Frontend requested transcript import ({} bytes)", text.len());
+    let parsed = transcript::parse_transcript(&text, &format.unwrap_or_default());
+    if parsed.entries.is_empty() {
+        return Err(CommandError::validation("No user or assistant messages found in the transcript."));
+    }
+
+    let messages = transcript_messages(parsed.entries);
+
+    let title = title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| "imported chat".to_string());
+    let storage = state.storage.lock().await;
+    storage.create_conversation_with_messages(&title, parsed.system_prompt, messages).await
+        .map_err(|e| CommandError::storage(format!("Failed to import transcript: {}", e)))
+}
+
+// Tauri command to import a Markdown chat export from disk as a new conversation.
+// `patterns` picks the role delimiters to recognize (defaults to bold prefixes and headings).
+// The title comes from the first top-level heading, else the file name.
+#[tauri::command]
+pub async fn import_markdown_conversation(
+    state: State<'_, AppState>,
+    path: String,
+    patterns: Option<Vec<DelimiterPattern>>,
+) -> Result<MarkdownImportSummary, CommandError> {
+    log::info!("Frontend requested Markdown import from {}", path);
+    let text = tokio::fs::read_to_string(&path).await
+        .map_err(|e| CommandError::internal(format!("Failed to read {}: {}", path, e)))?;
+    let patterns = match patterns {
+        Some(patterns) if !patterns.is_empty() => patterns,
+        _ => transcript::DEFAULT_DELIMITER_PATTERNS.to_vec(),
+    };
+
+    let parsed = transcript::parse_markdown_transcript(&text, &patterns);
+    if parsed.entries.is_empty() {
+        return Err(CommandError::validation("No user or assistant messages found in the file."));
+    }
+    for warning in &parsed.warnings {
+        log::warn!("Markdown import of {}: {}", path, warning);
+    }
+
+    let user_messages = parsed.entries.iter().filter(|e| e.role == "user").count();
+    let assistant_messages = parsed.entries.len() - user_messages;
+    let title = parsed.title
+        .or_else(|| {
+            std::path::Path::new(&path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().trim().to_string())
+        })
+        .filter(|t| !t.is_empty())
+        .unwrap_or_else(|| "imported chat".to_string());
+    let messages = transcript_messages(parsed.entries);
+
+    let storage = state.storage.lock().await;
+    let conversation = storage.create_conversation_with_messages(&title, parsed.system_prompt, messages).await
+        .map_err(|e| CommandError::internal(format!("Failed to import {}: {}", path, e)))?;
+    Ok(MarkdownImportSummary {
+        conversation,
+        user_messages,
+        assistant_messages,
+        warnings: parsed.warnings,
+    })
+}
+
+// Space imported turns a second apart, ending now, so they keep their order
+fn transcript_messages(entries: Vec<TranscriptEntry>) -> Vec<Message> {
+    let count = entries.len() as i64;
+    let start = Utc::now() - chrono::Duration::seconds(count);
+    entries
+        .into_iter()
+        .enumerate()
+        .map(|(i, entry)| Message {
+            id: Uuid::new_v4(),
+            conversation_id: Uuid::nil(), // Assigned by storage
+            role: entry.role,
+            content: entry.content,
+            timestamp: start + chrono::Duration::seconds(i as i64 + 1),
+            metadata: None,
+            name: None,
+            variant_group: None,
+        })
+        .collect()
+}
+
+// Tauri command to get messages for a specific conversation. With `collapse_variants` only the
+// selected answer of each regeneration variant group is returned.
 #[tauri::command]
 pub async fn get_conversation_messages(
     state: State<'_, AppState>,
     conversation_id: String, // Receive ID as String from frontend
-) -> Result<Vec<Message>, String> {
+    collapse_variants: Option<bool>,
+) -> Result<Vec<Message>, CommandError> {
     log::info!("Frontend requested messages for conversation ID: {}", conversation_id);
     
     // Parse the UUID from the string
     let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
         let err_msg = format!("Invalid conversation ID format: {}", conversation_id);
         log::error!("{}", err_msg);
-        return Err(err_msg);
+        return Err(CommandError::validation(err_msg));
     };
 
     let storage_manager = state.storage.lock().await;
     match storage_manager.get_conversation_messages(conv_uuid).await {
-        Ok(messages) => Ok(messages),
+        Ok(mut messages) => {
+            if collapse_variants.unwrap_or(false) {
+                messages.retain(|m| !m.is_unselected_variant());
+            }
+            Ok(state.with_ephemeral_messages(conv_uuid, messages))
+        }
         Err(e) => {
             log::error!("Failed to get messages for conversation {}: {:?}", conversation_id, e);
-            Err(format!("Failed to load messages: {}", e))
+            Err(CommandError::storage(format!("Failed to load messages: {}", e)))
         }
     }
 }
 
-// Tauri command to delete a conversation
+// Default and largest page of `get_all_messages_since`
+const DEFAULT_MESSAGES_SINCE_LIMIT: u32 = 100;
+const MAX_MESSAGES_SINCE_LIMIT: u32 = 500;
+
+// Tauri command for live-updating views: a conversation's messages saved after the `after`
+// cursor. Without a cursor nothing is returned, only the cursor to poll from.
 #[tauri::command]
-pub async fn delete_conversation(state: State<'_, AppState>, conversation_id: String) -> Result<(), String> {
-    log::warn!("[CMD] Frontend requested to delete conversation ID: {}", conversation_id);
-    
+pub async fn get_messages_since(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    after: Option<i64>,
+) -> Result<MessagesSince<Message>, CommandError> {
     let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
-        let err_msg = format!("Invalid conversation ID format for delete: {}", conversation_id);
-        log::error!("{}", err_msg);
-        return Err(err_msg);
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    let result = match after {
+        Some(after) => storage.get_messages_since(conv_uuid, after).await,
+        None => storage.latest_message_seq().await.map(|cursor| MessagesSince { messages: Vec::new(), cursor, has_more: false }),
     };
+    result.map_err(|e| CommandError::storage(format!("Failed to load new messages: {}", e)))
+}
 
-    let storage_manager = state.storage.lock().await;
-    log::info!("[CMD] Calling storage_manager.delete_conversation for {}", conv_uuid);
-    match storage_manager.delete_conversation(conv_uuid).await {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            log::error!("[CMD] Failed to delete conversation {}: {:?}", conversation_id, e);
-            Err(format!("Failed to delete conversation: {}", e))
-        }
-    }
+// Tauri command for the recent activity panel: messages from every conversation saved after
+// the `after` cursor, with their conversation titles, `limit` at a time
+#[tauri::command]
+pub async fn get_all_messages_since(
+    state: State<'_, AppState>,
+    after: Option<i64>,
+    limit: Option<u32>,
+) -> Result<MessagesSince<MessageWithTitle>, CommandError> {
+    let limit = limit.unwrap_or(DEFAULT_MESSAGES_SINCE_LIMIT).clamp(1, MAX_MESSAGES_SINCE_LIMIT);
+    let storage = state.storage.lock().await;
+    let result = match after {
+        Some(after) => storage.get_all_messages_since(after, limit).await,
+        None => storage.latest_message_seq().await.map(|cursor| MessagesSince { messages: Vec::new(), cursor, has_more: false }),
+    };
+    result.map_err(|e| CommandError::storage(format!("Failed to load new messages: {}", e)))
 }
 
-// Helper function to get ModelConfig from storage
-async fn get_model_config(
-    storage_manager: &crate::storage::StorageManager,
-    config_id: Uuid,
-) -> Result<ModelConfig, String> {
-    // Need to add a method to StorageManager to get a single config by ID
-    // For now, we'll fetch all and filter - replace later
-    storage_manager.list_model_configs().await // Assuming list_model_configs exists
-        .map_err(|e| format!("Failed to fetch model configs: {}", e))?
+// Tauri command for the sidebar badges: conversation ID -> message count, for all conversations at once
+#[tauri::command]
+pub async fn get_conversation_message_counts(
+    state: State<'_, AppState>,
+) -> Result<HashMap<String, i64>, CommandError> {
+    log::info!("Frontend requested message counts");
+    let storage = state.storage.lock().await;
+    let counts = storage.get_conversation_message_counts().await
+        .map_err(|e| CommandError::storage(format!("Failed to load message counts: {}", e)))?;
+    Ok(counts
         .into_iter()
-        .find(|mc| mc.id == config_id)
-        .ok_or_else(|| format!("Model config with ID {} not found", config_id))
+        .map(|(id, count)| {
+            // Ephemeral conversations keep their messages in memory only
+            let unsaved = state.ephemeral_messages.get(&id).map_or(0, |m| m.len() as i64);
+            (id.to_string(), count + unsaved)
+        })
+        .collect())
 }
 
-// Tauri command to send a message (NOW includes API call and event emit)
+// Tauri command backing Cmd+F within a conversation: returns matching message IDs
+// in order, with per-message match offsets for highlighting
 #[tauri::command]
-pub async fn send_message(
+pub async fn find_in_conversation(
     state: State<'_, AppState>,
     conversation_id: String,
-    content: String,
-) -> Result<Message, String> { // Still returns the user message initially
-    log::info!("[send_message] Handler Entered for conversation ID: {}", conversation_id);
-    
+    query: String,
+) -> Result<FindResult, CommandError> {
+    log::info!("Frontend requested find in conversation {}", conversation_id);
+
     let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
-        let err_msg = format!("Invalid conversation ID format for send: {}", conversation_id);
-        log::error!("{}", err_msg);
-        return Err(err_msg);
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
     };
-    log::info!("[send_message] Parsed conv_uuid: {}", conv_uuid);
+    if query.is_empty() {
+        return Ok(FindResult { matches: Vec::new(), total_matches: 0, truncated: false });
+    }
 
-    let user_message = Message {
-        id: Uuid::new_v4(),
-        conversation_id: conv_uuid,
-        role: "user".to_string(),
-        content, // content is passed directly as arg, ok
-        timestamp: Utc::now(),
-        metadata: None,
+    let candidates = {
+        let storage = state.storage.lock().await;
+        storage.list_message_contents(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to search conversation: {}", e)))?
     };
-    log::info!("[send_message] Created user_message with ID: {}", user_message.id);
-    
-    let user_message_clone = user_message.clone();
 
-    // <<< UNCOMMENT Logic >>>
-    // /*
-    // --- Save user message ---
-    {
-        let storage = state.storage.lock().await;
-        if let Err(e) = storage.save_message(&user_message).await {
-            log::error!("Failed to save user message for conversation {}: {:?}", conversation_id, e);
-            return Err(format!("Failed to save message: {}", e));
+    let mut matches = Vec::new();
+    let mut total_matches = 0;
+    let mut returned = 0;
+    for (message_id, content) in candidates {
+        let mut offsets = search::find_match_offsets(&content, &query);
+        if offsets.is_empty() {
+            continue;
+        }
+        total_matches += offsets.len();
+        if returned < search::MAX_FIND_RESULTS {
+            offsets.truncate(search::MAX_FIND_RESULTS - returned);
+            returned += offsets.len();
+            matches.push(MessageMatches { message_id, offsets });
         }
-        log::info!("[send_message] User message {} saved successfully.", user_message.id);
     }
 
-    // --- Trigger API call in background ---
-    let app_state_clone = state.inner().clone();
-    let conversation_id_clone = conversation_id.clone();
-
-    log::info!("[send_message] Preparing to spawn background task for conv {}", conversation_id_clone);
-    tauri::async_runtime::spawn(async move {
-        log::info!("Background task started for conversation {}", conversation_id_clone);
-        // ... (Keep the rest of the background task logic uncommented) ...
-        // 1. Get conversation history (acquire lock temporarily)
-        let messages = {
-            let storage = app_state_clone.storage.lock().await; // Acquire lock for history
-            match storage.get_conversation_messages(conv_uuid).await {
-                Ok(m) => m,
-                Err(e) => {
-                    log::error!("BG Task: Failed to get messages for {}: {:?}", conversation_id_clone, e);
-                    return; // Exit task
-                }
-            }
-        };
+    Ok(FindResult { matches, total_matches, truncated: total_matches > returned })
+}
 
-        // 2. Get ModelConfig for this conversation (acquire lock temporarily)
-        let model_config = {
-            let storage = app_state_clone.storage.lock().await; // Acquire lock for config
-            let conversation = match storage.get_conversation(conv_uuid).await { 
-                Ok(Some(c)) => c,
-                Ok(None) => {
-                     log::error!("BG Task: Conversation {} not found", conversation_id_clone);
-                     return;
-                }
-                Err(e) => {
-                     log::error!("BG Task: Failed to get conversation {}: {:?}", conversation_id_clone, e);
-                     return;
-                }
-            };
-             match get_model_config(&storage, conversation.model_config_id).await {
-                Ok(mc) => mc,
-                Err(e) => {
-                    log::error!("BG Task: Failed to get model config for {}: {}", conversation_id_clone, e);
-                    return; // Exit task if model config fails
-                }
-            }
-        };
+// Tauri command listing the messages of one conversation that contain `query`, oldest
+// first, each with a snippet around its first match for a results list
+#[tauri::command]
+pub async fn search_in_conversation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    query: String,
+) -> Result<Vec<SearchHit>, CommandError> {
+    log::info!("Frontend requested search in conversation {}", conversation_id);
 
-        // --- Create System Prompt ---
-        let system_prompt_content = format!("You are {}.", model_config.name);
-        let system_prompt = Message {
-            id: Uuid::nil(),
-            conversation_id: conv_uuid,
-            role: "system".to_string(),
-            content: system_prompt_content,
-            timestamp: Utc::now(),
-            metadata: None,
-        };
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let query = query.trim();
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
 
-        // --- Get API Key ---
-        let api_key = match config::get_api_key(&model_config) {
-            Ok(key) => key,
-            Err(e) => {
-                 log::error!("BG Task: Failed to get API key for {}: {:?}", conversation_id_clone, e);
-                 return;
-            }
-        };
-        
-        // --- Prepare messages for API (including system prompt) ---
-        let mut api_messages = vec![system_prompt];
-        api_messages.extend(messages.iter().cloned()); 
-
-        // --- Get API Provider ---
-        let api_provider = app_state_clone.api_provider.clone();
-
-        // --- Make the API call (Streaming) ---
-        log::info!("BG Task: Starting stream request for conversation {}", conversation_id_clone);
-        let delta_stream_result = api_provider
-            .send_chat_stream_request(&model_config, &api_key, &api_messages)
-            .await;
-
-        let mut delta_stream = match delta_stream_result {
-            Ok(stream) => stream,
-            Err(e) => {
-                log::error!("BG Task: Failed to initiate stream request for {}: {:?}", conversation_id_clone, e);
-                return;
-            }
-        };
-        
-        // --- Process Stream and Emit Chunks ---
-        let mut full_content = String::new();
-        let assistant_message_id = Uuid::new_v4();
-
-        // Emit stream started event
-        log::info!("BG Task [{}]: Emitting stream started event.", assistant_message_id);
-        if let Err(e) = app_state_clone.app_handle.emit(
-            "assistant_stream_started",
-            serde_json::json!({
-                "conversationId": conversation_id_clone,
-                "messageId": assistant_message_id.to_string(),
-            })
-        ) {
-            log::error!("BG Task [{}]: Failed to emit stream started event: {:?}. Aborting stream.", assistant_message_id, e);
-            return;
-        }
-
-        // Process stream loop
-        log::info!("BG Task [{}]: Starting stream processing loop.", assistant_message_id);
-        while let Some(delta_result) = delta_stream.next().await {
-            if app_state_clone.cancelled_streams.contains_key(&assistant_message_id) {
-                log::warn!("BG Task: Cancellation requested for message {}. Stopping stream.", assistant_message_id);
-                app_state_clone.cancelled_streams.remove(&assistant_message_id);
-                break;
-            }
-            match delta_result {
-                Ok(delta_content) => {
-                    log::debug!("BG Task [{}]: Received chunk.", assistant_message_id);
-                    full_content.push_str(&delta_content);
-                    let chunk_payload = serde_json::json!({
-                        "conversationId": conversation_id_clone,
-                        "messageId": assistant_message_id.to_string(),
-                        "delta": delta_content,
-                    });
-                    if let Err(e) = app_state_clone.app_handle.emit("assistant_message_chunk", chunk_payload) {
-                         log::error!("BG Task [{}]: Failed to emit chunk event: {:?}", assistant_message_id, e);
-                    }
-                },
-                Err(e) => {
-                    log::error!("BG Task [{}]: Error receiving stream delta: {:?}. Breaking loop.", assistant_message_id, e);
-                    break;
-                }
-            }
-        }
-        log::info!("BG Task [{}]: Exited stream processing loop.", assistant_message_id);
+    let candidates = {
+        let storage = state.storage.lock().await;
+        storage.list_message_contents(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to search conversation: {}", e)))?
+    };
+    Ok(candidates
+        .into_iter()
+        .filter_map(|(message_id, content)| search::search_hit(message_id, &content, query))
+        .take(search::MAX_FIND_RESULTS)
+        .collect())
+}
 
-        // Save assistant message
-        let assistant_message = Message {
-            id: assistant_message_id,
-            conversation_id: conv_uuid,
-            role: "assistant".to_string(),
-            content: full_content,
-            timestamp: Utc::now(),
-            metadata: None,
-        };
-        log::info!("BG Task [{}]: Attempting to save final message...", assistant_message_id);
-        {
-            let storage = app_state_clone.storage.lock().await;
-            if let Err(e) = storage.save_message(&assistant_message).await {
-                 log::error!("BG Task: Failed to save final assistant message {}: {:?}", assistant_message_id, e);
-            } else {
-                 log::info!("BG Task: Successfully saved final assistant message {}", assistant_message_id);
-            }
-        }
+// Renders a conversation (or a selection of its messages) for export
+async fn render_conversation_export(
+    state: &AppState,
+    conversation_id: &str,
+    format: &str,
+    message_ids: Option<Vec<String>>,
+) -> Result<String, CommandError> {
+    let format = ExportFormat::parse(format).map_err(CommandError::validation)?;
+    let Ok(conv_uuid) = Uuid::parse_str(conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
 
-        // Emit finished event
-        log::info!("BG Task [{}]: Attempting to emit finished event...", assistant_message_id);
-        if let Err(e) = app_state_clone.app_handle.emit(
-                "assistant_stream_finished",
-                serde_json::json!({ "messageId": assistant_message_id.to_string() })
-            ) {
-            log::error!("BG Task: Failed to emit finished event for {}: {:?}", conversation_id_clone, e);
+    let (conversation, messages, system_prompt) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to load conversation: {}", e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conv_uuid)))?;
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to load messages: {}", e)))?;
+        // The prompt export carries the system prompt a request would be sent with
+        let system_prompt = if format == ExportFormat::Prompt {
+            let model_config = get_conversation_model_config(&storage, &conversation).await?;
+            let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+            Some(prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation))
         } else {
-            log::info!("BG Task: Successfully emitted finished event for message ID: {}", assistant_message_id);
-        }
-
-        log::info!("BG Task [{}]: Background task finished normally for conversation {}", assistant_message_id, conversation_id_clone);
-        
-    }); // End of tauri::async_runtime::spawn
-    // */
+            None
+        };
+        (conversation, messages, system_prompt)
+    };
 
-    log::info!("[send_message] Returning user message clone immediately (End of main thread)."); // Adjusted log message
-    Ok(user_message_clone)
+    let excerpt = message_ids.is_some();
+    let messages = export::select_messages(messages, message_ids.as_deref()).map_err(CommandError::validation)?;
+    export::render(format, &conversation, &messages, excerpt, system_prompt.as_deref()).map_err(CommandError::internal)
 }
 
-// Tauri command to rename a conversation
+// Tauri command to export a conversation as Markdown, JSON, HTML, plain text or a
+// replayable messages array ("prompt").
+// `message_ids` limits the export to an excerpt of those messages.
 #[tauri::command]
-pub async fn rename_conversation(
+pub async fn export_conversation(
     state: State<'_, AppState>,
     conversation_id: String,
-    new_title: String,
-) -> Result<(), String> {
-    log::info!(
-        "Frontend requested to rename conversation {} to: {}",
-        conversation_id,
-        new_title
-    );
+    format: String,
+    message_ids: Option<Vec<String>>,
+) -> Result<String, CommandError> {
+    log::info!("Frontend requested {} export of conversation {}", format, conversation_id);
+    render_conversation_export(&state, &conversation_id, &format, message_ids).await
+}
 
-    if new_title.trim().is_empty() {
-        return Err("New title cannot be empty.".to_string());
-    }
-    
+// Tauri command returning a conversation as one line of an OpenAI fine-tuning JSONL file.
+// `include_system_prompt` prepends the system prompt a request would be sent with.
+#[tauri::command]
+pub async fn export_conversation_jsonl(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    include_system_prompt: Option<bool>,
+) -> Result<String, CommandError> {
+    log::info!("Frontend requested a fine-tuning JSONL export of conversation {}", conversation_id);
     let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
-        let err_msg = format!("Invalid conversation ID format for rename: {}", conversation_id);
-        log::error!("{}", err_msg);
-        return Err(err_msg);
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
     };
 
-    let storage_manager = state.storage.lock().await;
-    match storage_manager
-        .rename_conversation(conv_uuid, new_title.trim().to_string())
-        .await
-    {
-        Ok(_) => Ok(()),
-        Err(e) => {
-            log::error!("Failed to rename conversation {}: {:?}", conversation_id, e);
-            Err(format!("Failed to rename conversation: {}", e))
-        }
-    }
+    let (messages, system_prompt) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to load conversation: {}", e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conv_uuid)))?;
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to load messages: {}", e)))?;
+        let system_prompt = if include_system_prompt.unwrap_or(false) {
+            let model_config = get_conversation_model_config(&storage, &conversation).await?;
+            let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+            Some(prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation))
+        } else {
+            None
+        };
+        (messages, system_prompt)
+    };
+
+    export::render_fine_tune_line(&messages, system_prompt.as_deref()).map_err(CommandError::internal)
 }
 
-// Tauri command to update a conversation's model
+// Tauri command writing a conversation to `path` as a `.localchat` bundle (see `bundle`)
 #[tauri::command]
-pub async fn update_conversation_model(
+pub async fn export_conversation_bundle(
     state: State<'_, AppState>,
-    conversation_id: String, // ID of conversation to update
-    model_config_id: String, // ID of the new model config
-) -> Result<(), String> {
-    log::info!(
-        "Frontend requested to update model for conversation {} to model {}",
-        conversation_id,
-        model_config_id
-    );
-    
+    conversation_id: String,
+    path: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested a bundle export of conversation {} to {}", conversation_id, path);
     let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
-        return Err(format!("Invalid conversation ID format: {}", conversation_id));
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
     };
-    let Ok(model_uuid) = Uuid::parse_str(&model_config_id) else {
-        return Err(format!("Invalid model config ID format: {}", model_config_id));
+    let (conversation, messages) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?;
+        (conversation, messages)
     };
-    
-    let storage = state.storage.lock().await;
-    // Need an update_conversation_model_id method in StorageManager
-    match storage.update_conversation_model_id(conv_uuid, model_uuid).await { // Call new storage method
-        Ok(_) => Ok(()),
-        Err(e) => {
-            log::error!("Failed to update model for conversation {}: {:?}", conversation_id, e);
-            Err(format!("Failed to update conversation model: {}", e))
-        }
-    }
-}
-
-// --- Model Config Commands ---
-
-#[tauri::command]
-pub async fn list_model_configs(state: State<'_, AppState>) -> Result<Vec<ModelConfig>, String> {
-    log::info!("Frontend requested to list model configs");
-    let storage = state.storage.lock().await;
-    storage.list_model_configs().await
-        .map_err(|e| format!("Failed to list model configs: {}", e))
+    let archive = bundle::write(&conversation, &messages).map_err(CommandError::internal)?;
+    tokio::fs::write(&path, archive).await
+        .map_err(|e| CommandError::internal(format!("Failed to write {}: {}", path, e)))
 }
 
+// Tauri command importing a `.localchat` bundle as a new conversation. Messages get new IDs so
+// a bundle can be imported next to the conversation it came from. Entries that fail their
+// checksum or manifest hash are skipped and reported.
 #[tauri::command]
-pub async fn add_model_config(state: State<'_, AppState>, config: ModelConfig) -> Result<(), String> {
-    log::info!("Frontend requested to add model config: {}", config.name);
-    // Basic validation (can add more)
-    if config.name.trim().is_empty() || config.api_url.trim().is_empty() || config.provider.trim().is_empty() {
-        return Err("Name, API URL, and Provider cannot be empty.".to_string());
+pub async fn import_conversation_bundle(state: State<'_, AppState>, path: String) -> Result<BundleImportSummary, CommandError> {
+    log::info!("Frontend requested a bundle import from {}", path);
+    let archive = tokio::fs::read(&path).await
+        .map_err(|e| CommandError::internal(format!("Failed to read {}: {}", path, e)))?;
+    let contents = bundle::read(&archive).map_err(CommandError::validation)?;
+    for skipped in &contents.skipped {
+        log::warn!("Bundle import of {}: skipped {}: {}", path, skipped.path, skipped.reason);
     }
-    // The `config` object received already has a default ID generated by serde.
-    // Remove the redundant creation of `config_with_id`
-    // let config_with_id = ModelConfig { id: Uuid::new_v4(), ..config };
 
+    let messages: Vec<Message> = contents.messages
+        .into_iter()
+        .map(|message| Message { id: Uuid::new_v4(), conversation_id: Uuid::nil(), ..message })
+        .collect();
+    let imported_messages = messages.len();
     let storage = state.storage.lock().await;
-    // Use the received `config` directly
-    storage.add_model_config(&config).await
-        .map_err(|e| format!("Failed to add model config: {}", e))
+    let conversation = storage
+        .create_conversation_with_messages(&contents.conversation.title, contents.conversation.system_prompt, messages)
+        .await
+        .map_err(|e| CommandError::storage(format!("Failed to import {}: {}", path, e)))?;
+    drop(storage);
+    state.notify_conversation_updated(conversation.id);
+    Ok(BundleImportSummary { conversation, imported_messages, skipped: contents.skipped })
 }
 
+// Tauri command listing the fenced code blocks in a message, each with its language, line
+// count and a suggested file name
 #[tauri::command]
-pub async fn update_model_config(state: State<'_, AppState>, config: ModelConfig) -> Result<(), String> {
-    log::info!("Frontend requested to update model config: {}", config.id);
-    // Basic validation
-    if config.name.trim().is_empty() || config.api_url.trim().is_empty() || config.provider.trim().is_empty() {
-        return Err("Name, API URL, and Provider cannot be empty.".to_string());
-    }
-
-    let storage = state.storage.lock().await;
-    storage.update_model_config(&config).await
-        .map_err(|e| format!("Failed to update model config: {}", e))
+pub async fn extract_code_blocks(state: State<'_, AppState>, message_id: String) -> Result<Vec<CodeBlock>, CommandError> {
+    let message = find_message(&state, &message_id).await?;
+    Ok(codeblocks::extract(&message.content))
 }
 
+// Tauri command writing one of a message's code blocks to a file, with the platform's line
+// endings. Without `path` a save dialog asks where, starting from the suggested file name.
+// Returns the written path, or None when the dialog was dismissed.
 #[tauri::command]
-pub async fn delete_model_config(state: State<'_, AppState>, config_id: String) -> Result<(), String> {
-    log::warn!("Frontend requested to delete model config ID: {}", config_id);
-    
-    let Ok(uuid) = Uuid::parse_str(&config_id) else {
-        return Err(format!("Invalid model config ID format: {}", config_id));
+pub async fn save_code_block(
+    state: State<'_, AppState>,
+    message_id: String,
+    block_index: usize,
+    path: Option<String>,
+) -> Result<Option<String>, CommandError> {
+    log::info!("Frontend requested to save code block {} of message {}", block_index, message_id);
+    let message = find_message(&state, &message_id).await?;
+    let block = codeblocks::extract(&message.content)
+        .into_iter()
+        .nth(block_index)
+        .ok_or_else(|| CommandError::not_found(format!("Message {} has no code block {}", message_id, block_index)))?;
+
+    let path = match path.filter(|path| !path.trim().is_empty()) {
+        Some(path) => std::path::PathBuf::from(path),
+        None => {
+            let (path_tx, path_rx) = tokio::sync::oneshot::channel();
+            state.app_handle.dialog().file()
+                .set_file_name(&block.suggested_filename)
+                .save_file(move |path| {
+                    let _ = path_tx.send(path);
+                });
+            let Some(path) = path_rx.await.ok().flatten() else {
+                return Ok(None);
+            };
+            path.into_path()
+                .map_err(|e| CommandError::internal(format!("Unsupported save location: {}", e)))?
+        }
     };
+    tokio::fs::write(&path, codeblocks::with_platform_line_endings(&block.code)).await
+        .map_err(|e| CommandError::internal(format!("Failed to write {}: {}", path.display(), e)))?;
+    Ok(Some(path.display().to_string()))
+}
 
-    let storage = state.storage.lock().await;
-    storage.delete_model_config(uuid).await
-        .map_err(|e| format!("Failed to delete model config: {}", e))
+// A message by ID, from storage or the unsaved messages of ephemeral chats
+async fn find_message(state: &AppState, message_id: &str) -> Result<Message, CommandError> {
+    let Ok(msg_uuid) = Uuid::parse_str(message_id) else {
+        return Err(CommandError::validation(format!("Invalid message ID format: {}", message_id)));
+    };
+    let stored = {
+        let storage = state.storage.lock().await;
+        storage.get_message(msg_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get message {}: {}", message_id, e)))?
+    };
+    stored
+        .or_else(|| {
+            state.ephemeral_messages.iter()
+                .find_map(|unsaved| unsaved.iter().find(|m| m.id == msg_uuid).cloned())
+        })
+        .ok_or_else(|| CommandError::not_found(format!("Message {} not found", message_id)))
 }
 
-// TODO: Commands for getting/setting API keys via keyring
+// Tauri command to copy a rendered conversation (or excerpt) to the clipboard
+#[tauri::command]
+pub async fn copy_conversation_to_clipboard(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    format: String,
+    message_ids: Option<Vec<String>>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested clipboard copy of conversation {}", conversation_id);
+    let rendered = render_conversation_export(&state, &conversation_id, &format, message_ids).await?;
+    state.app_handle.clipboard().write_text(rendered)
+        .map_err(|e| CommandError::internal(format!("Failed to copy to clipboard: {}", e)))
+}
 
-// Tauri command to signal stopping a specific stream
+// Tauri command to delete a conversation. Moves it to the recycle bin unless `hard` is set.
 #[tauri::command]
-pub async fn stop_generation(state: State<'_, AppState>, message_id: String) -> Result<(), String> {
-    log::warn!("Frontend requested to stop generation for message ID: {}", message_id);
+pub async fn delete_conversation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    hard: Option<bool>,
+) -> Result<(), CommandError> {
+    let hard = hard.unwrap_or(false);
+    log::warn!("[CMD] Frontend requested to delete conversation ID: {} (hard: {})", conversation_id, hard);
     
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        let err_msg = format!("Invalid conversation ID format for delete: {}", conversation_id);
+        log::error!("{}", err_msg);
+        return Err(CommandError::validation(err_msg));
+    };
+
+    let storage_manager = state.storage.lock().await;
+    state.ephemeral_messages.remove(&conv_uuid);
+    state.utility_queue.cancel_conversation(conv_uuid);
+    let result = if hard {
+        log::info!("[CMD] Calling storage_manager.delete_conversation for {}", conv_uuid);
+        storage_manager.delete_conversation(conv_uuid).await
+    } else {
+        storage_manager.soft_delete_conversation(conv_uuid).await
+    };
+    match result {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(CommandError::not_found(format!("Conversation {} not found or already deleted", conversation_id))),
+        Err(e) => {
+            log::error!("[CMD] Failed to delete conversation {}: {:?}", conversation_id, e);
+            Err(CommandError::storage(format!("Failed to delete conversation: {}", e)))
+        }
+    }
+}
+
+// Tauri command to merge the source conversation into the target (the source is removed)
+#[tauri::command]
+pub async fn merge_conversations(
+    state: State<'_, AppState>,
+    source_id: String,
+    target_id: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to merge conversation {} into {}", source_id, target_id);
+
+    let Ok(source_uuid) = Uuid::parse_str(&source_id) else {
+        return Err(CommandError::validation(format!("Invalid source conversation ID format: {}", source_id)));
+    };
+    let Ok(target_uuid) = Uuid::parse_str(&target_id) else {
+        return Err(CommandError::validation(format!("Invalid target conversation ID format: {}", target_id)));
+    };
+    if source_uuid == target_uuid {
+        return Err(CommandError::validation("Cannot merge a conversation into itself."));
+    }
+    // Don't pull messages out from under an in-flight generation
+    if state.active_streams.iter().any(|s| s.conversation_id == source_uuid) {
+        return Err(CommandError::validation("Cannot merge a conversation while a response is still generating."));
+    }
+
+    let storage = state.storage.lock().await;
+    storage.merge_conversations(source_uuid, target_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to merge conversations: {}", e)))?;
+    state.notify_conversation_updated(target_uuid);
+    Ok(())
+}
+
+// Heading of the summary message a split-off conversation starts with
+const SPLIT_SUMMARY_HEADING: &str = "[Summary of the conversation this continues]";
+
+// Tauri command to move a conversation's messages from `at_message_id` onward into a new
+// "<title> (continued)" conversation with the same settings. With `summarize`, the new
+// conversation starts with a pinned system message summarizing what came before.
+#[tauri::command]
+pub async fn split_conversation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    at_message_id: String,
+    summarize: Option<bool>,
+) -> Result<Conversation, CommandError> {
+    log::info!("Frontend requested to split conversation {} at message {}", conversation_id, at_message_id);
+
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let Ok(msg_uuid) = Uuid::parse_str(&at_message_id) else {
+        return Err(CommandError::validation(format!("Invalid message ID format: {}", at_message_id)));
+    };
+    if state.active_streams.iter().any(|s| s.conversation_id == conv_uuid) {
+        return Err(CommandError::validation("Cannot split a conversation while a response is still generating."));
+    }
+
+    let (conversation, earlier, summarizer) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        if conversation.ephemeral {
+            return Err(CommandError::validation("Ephemeral conversations can't be split."));
+        }
+        if conversation.locked {
+            return Err(CommandError::locked(format!(
+                "Conversation \"{}\" is locked. Unlock it to split it.",
+                conversation.title
+            )));
+        }
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?;
+        let Some(index) = messages.iter().position(|m| m.id == msg_uuid) else {
+            return Err(CommandError::not_found(format!("Message {} is not in conversation {}", at_message_id, conversation_id)));
+        };
+        if index == 0 {
+            return Err(CommandError::validation("Splitting at the first message would leave the original conversation empty."));
+        }
+        let summarizer = if summarize.unwrap_or(false) {
+            match load_summarizer(&storage).await {
+                Some(config) => Some(config),
+                None => Some(get_conversation_model_config(&storage, &conversation).await?),
+            }
+        } else {
+            None
+        };
+        let mut messages = messages;
+        messages.truncate(index);
+        (conversation, prompt::filter_history(messages), summarizer)
+    };
+
+    // Summarize before touching anything, so a failed summary leaves the conversation as it was
+    let seed = match summarizer {
+        Some(summarizer) => {
+            let summary = memory::summarize_for_split(&state, conv_uuid, &earlier, &summarizer).await
+                .map_err(|e| CommandError::provider(format!("Failed to summarize the conversation: {}", e)))?;
+            let mut seed = prompt::system_message(conv_uuid, format!("{}\n{}", SPLIT_SUMMARY_HEADING, summary));
+            seed.id = Uuid::new_v4();
+            seed.set_metadata_field("context_pinned", serde_json::json!(true));
+            Some(seed)
+        }
+        None => None,
+    };
+
+    let storage = state.storage.lock().await;
+    let title = format!("{} (continued)", conversation.title);
+    let new_id = storage.split_conversation(conv_uuid, msg_uuid, &title, seed).await
+        .map_err(|e| CommandError::storage(format!("Failed to split conversation: {}", e)))?;
+    let new_conversation = storage.get_conversation(new_id).await
+        .map_err(|e| CommandError::storage(format!("Failed to load the new conversation: {}", e)))?
+        .ok_or_else(|| CommandError::storage(format!("Conversation {} vanished after the split", new_id)))?;
+    state.notify_conversation_updated(conv_uuid);
+    state.notify_conversation_updated(new_id);
+    Ok(new_conversation)
+}
+
+// Tauri command to list conversations in the recycle bin
+#[tauri::command]
+pub async fn list_deleted_conversations(state: State<'_, AppState>) -> Result<Vec<Conversation>, CommandError> {
+    log::info!("Frontend requested to list deleted conversations");
+    let storage = state.storage.lock().await;
+    storage.list_deleted_conversations().await
+        .map_err(|e| CommandError::storage(format!("Failed to load deleted conversations: {}", e)))
+}
+
+// Most conversations `list_oldest_conversations` returns at once
+const MAX_OLDEST_CONVERSATIONS: i64 = 500;
+
+// Tauri command returning the `limit` least recently updated conversations with their message
+// counts, oldest first, as candidates for archiving or deleting
+#[tauri::command]
+pub async fn list_oldest_conversations(state: State<'_, AppState>, limit: i64) -> Result<Vec<ConversationActivity>, CommandError> {
+    log::info!("Frontend requested the {} oldest conversations", limit);
+    if !(1..=MAX_OLDEST_CONVERSATIONS).contains(&limit) {
+        return Err(CommandError::validation(format!("The limit must be between 1 and {}.", MAX_OLDEST_CONVERSATIONS)));
+    }
+    let storage = state.storage.lock().await;
+    storage.list_oldest_conversations(limit).await
+        .map_err(|e| CommandError::storage(format!("Failed to load conversations: {}", e)))
+}
+
+// Tauri command listing conversations, trashed ones included, whose model config has been
+// deleted; `repair_data_integrity` can point them back at the default config
+#[tauri::command]
+pub async fn list_conversations_with_missing_model(state: State<'_, AppState>) -> Result<Vec<Conversation>, CommandError> {
+    log::info!("Frontend requested conversations with a missing model config");
+    let storage = state.storage.lock().await;
+    storage.list_conversations_with_missing_model().await
+        .map_err(|e| CommandError::storage(format!("Failed to load conversations: {}", e)))
+}
+
+// Tauri command to restore a conversation from the recycle bin
+#[tauri::command]
+pub async fn restore_conversation(state: State<'_, AppState>, conversation_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to restore conversation ID: {}", conversation_id);
+
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format for restore: {}", conversation_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    storage.restore_conversation(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to restore conversation: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command to permanently remove conversations deleted more than `older_than_days` ago.
+// Returns how many were purged.
+#[tauri::command]
+pub async fn purge_deleted_conversations(state: State<'_, AppState>, older_than_days: u32) -> Result<u64, CommandError> {
+    log::warn!("Frontend requested to purge conversations deleted over {} days ago", older_than_days);
+    let storage = state.storage.lock().await;
+    storage.purge_deleted_conversations(older_than_days).await
+        .map_err(|e| CommandError::storage(format!("Failed to purge deleted conversations: {}", e)))
+}
+
+// Tauri command reporting orphaned messages, dangling model config references and invalid metadata
+#[tauri::command]
+pub async fn check_data_integrity(state: State<'_, AppState>) -> Result<IntegrityReport, CommandError> {
+    log::info!("Frontend requested a data integrity check");
+    let storage = state.storage.lock().await;
+    storage.check_data_integrity().await
+        .map_err(|e| CommandError::storage(format!("Failed to check data integrity: {}", e)))
+}
+
+// --- Safe Mode Commands ---
+
+// Tauri command reporting whether the database opened. In safe mode the frontend shows a
+// recovery screen offering `retry_storage_init` and `reset_database`.
+#[tauri::command]
+pub async fn get_storage_status(state: State<'_, AppState>) -> Result<StorageStatus, CommandError> {
+    Ok(state.storage_status.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone())
+}
+
+// Swaps a freshly opened database in for safe mode's placeholder, reapplying the settings
+// startup would have read from it
+async fn leave_safe_mode(state: &AppState, storage_manager: StorageManager) {
+    let redaction_settings = redaction::load_settings(&storage_manager).await;
+    crate::logs::set_pattern_redactor(redaction::compile(&redaction_settings));
+    let theme_preference = theme::load_preference(&storage_manager).await;
+    *state.storage.lock().await = storage_manager;
+    *state.storage_status.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = StorageStatus::Ready;
+    theme::apply(&state.app_handle, theme_preference);
+    log::info!("Database opened; leaving safe mode");
+}
+
+// Tauri command trying to open the database again from safe mode, e.g. after freeing disk
+// space. Returns the new status; if it fails again the reason is updated.
+#[tauri::command]
+pub async fn retry_storage_init(state: State<'_, AppState>) -> Result<StorageStatus, CommandError> {
+    log::info!("Frontend requested to retry opening the database");
+    let db_path = match &*state.storage_status.read().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+        StorageStatus::Ready => return Ok(StorageStatus::Ready),
+        StorageStatus::Failed { db_path, .. } => std::path::PathBuf::from(db_path),
+    };
+    match safe_mode::open(&db_path).await {
+        Ok(storage_manager) => leave_safe_mode(&state, storage_manager).await,
+        Err(e) => {
+            log::error!("Failed to open the database at {:?} again: {:#}", db_path, e);
+            let mut status = state.storage_status.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let StorageStatus::Failed { reason, .. } = &mut *status {
+                *reason = format!("{:#}", e);
+            }
+        }
+    }
+    Ok(state.storage_status.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone())
+}
+
+// Tauri command starting over with an empty database from safe mode. The old file is renamed
+// to `localchat.sqlite.corrupt-<timestamp>` next to it, never deleted. `confirm_token` must be
+// the `resetToken` from `get_storage_status`. Returns the path the old file was moved to.
+#[tauri::command]
+pub async fn reset_database(state: State<'_, AppState>, confirm_token: String) -> Result<String, CommandError> {
+    log::warn!("Frontend requested to reset the database");
+    let db_path = match &*state.storage_status.read().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+        StorageStatus::Ready => return Err(CommandError::validation("The database opened normally; there is nothing to reset.")),
+        StorageStatus::Failed { reset_token, .. } if *reset_token != confirm_token => {
+            return Err(CommandError::validation("Reset confirmation does not match. Reload the storage status and try again."));
+        }
+        StorageStatus::Failed { db_path, .. } => std::path::PathBuf::from(db_path),
+    };
+    let moved_to = safe_mode::quarantine(&db_path)
+        .map_err(|e| CommandError::internal(format!("Failed to move the database aside: {}", e)))?;
+    let storage_manager = safe_mode::open(&db_path).await
+        .map_err(|e| CommandError::storage_unavailable(format!("Failed to create a new database: {:#}", e)))?;
+    leave_safe_mode(&state, storage_manager).await;
+    Ok(moved_to.display().to_string())
+}
+
+// The exact text `wipe_all_data` must be given before it deletes anything
+const WIPE_CONFIRMATION: &str = "DELETE EVERYTHING";
+
+// Tauri command deleting all conversations, settings, personas and model configs, along with
+// the API key of every model config and the automation token in the keyring. Stops the
+// automation API. Refused unless `confirmation` is exactly "DELETE EVERYTHING" and while answers
+// are still streaming. A key that cannot be removed is reported in `keyringErrors` without
+// stopping the wipe.
+#[tauri::command]
+pub async fn wipe_all_data(state: State<'_, AppState>, confirmation: String) -> Result<WipeReport, CommandError> {
+    if confirmation != WIPE_CONFIRMATION {
+        return Err(CommandError::validation(format!("Type \"{}\" to confirm deleting all data.", WIPE_CONFIRMATION)));
+    }
+    if !state.active_streams.is_empty() {
+        return Err(CommandError::validation("Stop the running generations before deleting all data."));
+    }
+    log::warn!("Frontend requested to wipe all data");
+    let storage = state.storage.lock().await;
+    let configs = storage.list_model_configs().await
+        .map_err(|e| CommandError::storage(format!("Failed to list model configs: {}", e)))?;
+    let mut report = storage.wipe_all_data().await
+        .map_err(|e| CommandError::storage(format!("Failed to delete all data: {}", e)))?;
+    drop(storage);
+    automation::stop(&state).await;
+
+    // Keys go only once the rows referencing them are gone, so a failed wipe keeps them
+    report.keyring_errors = configs.iter()
+        .filter_map(|model_config| config::delete_keyring_entry(model_config).err())
+        .chain(config::delete_automation_token().err())
+        .map(|e| {
+            log::error!("Wipe: {:#}", e);
+            format!("{:#}", e)
+        })
+        .collect();
+
+    // The settings behind these are gone too, so reset them to match the fresh store
+    state.set_offline(false);
+    state.ephemeral_messages.clear();
+    state.size_warned_conversations.clear();
+    prompt_files::rescan(&state).await;
+    Ok(report)
+}
+
+// Tauri command running SQLite's integrity and foreign key checks on the database file, for
+// diagnosing corruption
+#[tauri::command]
+pub async fn check_database_integrity(state: State<'_, AppState>) -> Result<DatabaseIntegrityReport, CommandError> {
+    log::info!("Frontend requested a database integrity check");
+    let storage = state.storage.lock().await;
+    let report = storage.check_database_integrity().await
+        .map_err(|e| CommandError::storage(format!("Failed to check database integrity: {}", e)))?;
+    if !report.ok {
+        log::warn!("Database integrity check found problems: {:?}", report);
+    }
+    Ok(report)
+}
+
+// Tauri command returning a message's database row column by column, unparsed, for
+// inspecting what is actually stored. Unsaved messages of ephemeral chats have no row.
+#[tauri::command]
+pub async fn get_message_debug(state: State<'_, AppState>, message_id: String) -> Result<Vec<RawColumn>, CommandError> {
     let Ok(msg_uuid) = Uuid::parse_str(&message_id) else {
-        let err_msg = format!("Invalid message ID format for stop: {}", message_id);
+        return Err(CommandError::validation(format!("Invalid message ID format: {}", message_id)));
+    };
+    let storage = state.storage.lock().await;
+    storage.get_message_row(msg_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to read message {}: {}", message_id, e)))?
+        .ok_or_else(|| CommandError::not_found(format!("Message {} not found", message_id)))
+}
+
+// Tauri command shrinking the database file after large deletes. `optimize` (default true)
+// also refreshes the query planner statistics. Holds the storage lock throughout, so other
+// database work waits until it is done.
+#[tauri::command]
+pub async fn compact_database(state: State<'_, AppState>, optimize: Option<bool>) -> Result<CompactionReport, CommandError> {
+    log::info!("Frontend requested database compaction");
+    let storage = state.storage.lock().await;
+    storage.compact_database(optimize.unwrap_or(true)).await
+        .map_err(|e| CommandError::storage(format!("Failed to compact database: {}", e)))
+}
+
+// Tauri command listing how many bytes of message content and metadata each conversation
+// holds, largest first, to find what is growing the database
+#[tauri::command]
+pub async fn get_conversation_sizes(state: State<'_, AppState>) -> Result<Vec<ConversationSize>, CommandError> {
+    log::info!("Frontend requested conversation sizes");
+    let storage = state.storage.lock().await;
+    storage.conversation_sizes().await
+        .map_err(|e| CommandError::storage(format!("Failed to measure conversations: {}", e)))
+}
+
+// Tauri command re-running the migrations and backfills, for a database left half-upgraded
+// when the app was closed mid-upgrade. Safe to repeat; returns what it changed.
+#[tauri::command]
+pub async fn run_maintenance(state: State<'_, AppState>) -> Result<MaintenanceReport, CommandError> {
+    log::info!("Frontend requested database maintenance");
+    let storage = state.storage.lock().await;
+    let report = storage.run_maintenance().await
+        .map_err(|e| CommandError::storage(format!("Database maintenance failed: {:#}", e)))?;
+    log::info!("Database maintenance finished: {:?}", report);
+    Ok(report)
+}
+
+// Tauri command applying the selected integrity repairs; returns what was changed
+#[tauri::command]
+pub async fn repair_data_integrity(
+    state: State<'_, AppState>,
+    actions: RepairActions,
+) -> Result<RepairSummary, CommandError> {
+    log::info!("Frontend requested data integrity repair: {:?}", actions);
+    let storage = state.storage.lock().await;
+    storage.repair_data_integrity(&actions).await
+        .map_err(|e| CommandError::storage(format!("Failed to repair data: {}", e)))
+}
+
+// Helper function to get ModelConfig from storage
+async fn get_model_config(
+    storage_manager: &crate::storage::StorageManager,
+    config_id: Uuid,
+) -> Result<ModelConfig, CommandError> {
+    // Need to add a method to StorageManager to get a single config by ID
+    // For now, we'll fetch all and filter - replace later
+    storage_manager.list_model_configs().await // Assuming list_model_configs exists
+        .map_err(|e| CommandError::storage(format!("Failed to fetch model configs: {}", e)))?
+        .into_iter()
+        .find(|mc| mc.id == config_id)
+        .ok_or_else(|| CommandError::not_found(format!("Model config with ID {} not found", config_id)))
+}
+
+// Like `get_model_config`, with app-wide request settings applied for sending chat requests
+async fn get_request_model_config(
+    storage_manager: &crate::storage::StorageManager,
+    config_id: Uuid,
+) -> Result<ModelConfig, CommandError> {
+    let model_config = get_model_config(storage_manager, config_id).await?;
+    let options = ParsedProviderOptions::from_config(&model_config)
+        .map_err(|e| CommandError::validation(format!("Invalid provider options: {}", e)))?;
+    if options.user_id.is_some() {
+        return Ok(model_config);
+    }
+    let default_user_id = storage_manager.get_setting(config::DEFAULT_USER_ID_KEY).await
+        .map_err(|e| CommandError::storage(format!("Failed to read default user ID: {}", e)))?;
+    match default_user_id {
+        Some(user_id) if !user_id.is_empty() => model_config
+            .with_provider_option("user_id", serde_json::json!(user_id))
+            .map_err(|e| CommandError::validation(format!("Failed to apply default user ID: {}", e))),
+        _ => Ok(model_config),
+    }
+}
+
+// The config a conversation's requests go out with: `get_request_model_config` with the
+// persona's params applied, then the conversation's model override, if any
+async fn get_conversation_model_config(
+    storage_manager: &crate::storage::StorageManager,
+    conversation: &Conversation,
+) -> Result<ModelConfig, CommandError> {
+    let mut model_config = get_request_model_config(storage_manager, conversation.model_config_id).await?;
+    if let Some(persona) = load_persona(storage_manager, conversation).await {
+        model_config = apply_persona_params(model_config, &persona)?;
+    }
+    match conversation.model_override.as_deref() {
+        Some(model) if !model.trim().is_empty() => model_config
+            .with_provider_option("model", serde_json::json!(model.trim()))
+            .map_err(|e| CommandError::validation(format!("Failed to apply model override: {}", e))),
+        _ => Ok(model_config),
+    }
+}
+
+// `model_config` with the persona's params layered over its provider_options
+fn apply_persona_params(mut model_config: ModelConfig, persona: &Persona) -> Result<ModelConfig, CommandError> {
+    for (key, value) in parse_persona_params(persona.params.as_deref())? {
+        model_config = model_config
+            .with_provider_option(&key, value)
+            .map_err(|e| CommandError::validation(format!("Failed to apply persona '{}': {}", persona.name, e)))?;
+    }
+    crate::api::validate_provider_options(&model_config.provider, model_config.provider_options.as_deref())
+        .map_err(|errors| CommandError::validation(format!("Persona '{}' sets invalid parameters: {}", persona.name, errors.join("; "))))?;
+    Ok(model_config)
+}
+
+fn parse_persona_params(params: Option<&str>) -> Result<serde_json::Map<String, serde_json::Value>, CommandError> {
+    match params.map(str::trim).filter(|params| !params.is_empty()) {
+        Some(params) => serde_json::from_str(params)
+            .map_err(|e| CommandError::validation(format!("Persona params must be a JSON object: {}", e))),
+        None => Ok(serde_json::Map::new()),
+    }
+}
+
+// Model config that summarizes long history: the utility model when one is set and usable
+async fn load_summarizer(storage: &StorageManager) -> Option<ModelConfig> {
+    load_utility_model_config(storage).await
+        .map_err(|e| log::warn!("Utility model unavailable, summarizing with the chat model: {}", e))
+        .ok()
+        .flatten()
+}
+
+// Stream smoothing settings; unreadable or malformed values fall back to the defaults (off)
+async fn load_stream_smoothing(storage: &StorageManager) -> StreamSmoothing {
+    let read = |key: &'static str| async move {
+        storage.get_setting(key).await
+            .map_err(|e| log::warn!("Failed to read setting '{}', using the default: {:?}", key, e))
+            .ok()
+            .flatten()
+    };
+    let defaults = StreamSmoothing::default();
+    StreamSmoothing {
+        enabled: read(config::STREAM_SMOOTHING_KEY).await.is_some_and(|value| value == "true"),
+        chars_per_event: read(config::STREAM_SMOOTHING_CHARS_KEY).await
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|chars| (1..=smoothing::MAX_CHARS_PER_EVENT).contains(chars))
+            .unwrap_or(defaults.chars_per_event),
+    }
+}
+
+// Whether leading whitespace-only deltas are dropped; on unless turned off
+async fn load_trim_leading_whitespace(storage: &StorageManager) -> bool {
+    storage.get_setting(config::TRIM_LEADING_WHITESPACE_KEY).await
+        .map_err(|e| log::warn!("Failed to read setting '{}', using the default: {:?}", config::TRIM_LEADING_WHITESPACE_KEY, e))
+        .ok()
+        .flatten()
+        .is_none_or(|value| value != "false")
+}
+
+// The utility model config chosen in settings, if one is set
+async fn load_utility_model_config(storage: &StorageManager) -> Result<Option<ModelConfig>, CommandError> {
+    let config_id = storage.get_setting(config::UTILITY_MODEL_CONFIG_ID_KEY).await
+        .map_err(|e| CommandError::storage(format!("Failed to read utility model setting: {}", e)))?
+        .filter(|id| !id.is_empty());
+    let Some(config_id) = config_id else {
+        return Ok(None);
+    };
+    let Ok(config_uuid) = Uuid::parse_str(&config_id) else {
+        return Err(CommandError::validation(format!("Invalid utility model ID in settings: {}", config_id)));
+    };
+    get_request_model_config(storage, config_uuid).await.map(Some)
+}
+
+// Model config that writes a conversation's title: the one the request names, else the
+// conversation's title model, else the app-wide title model, else the utility model. A
+// stored choice whose config was deleted is skipped with a warning.
+async fn resolve_title_model_config(
+    storage: &StorageManager,
+    conversation: &Conversation,
+    requested: Option<Uuid>,
+) -> Result<Option<ModelConfig>, CommandError> {
+    if let Some(config_id) = requested {
+        return get_request_model_config(storage, config_id).await.map(Some);
+    }
+    let app_wide = storage.get_setting(config::TITLE_MODEL_CONFIG_ID_KEY).await
+        .map_err(|e| CommandError::storage(format!("Failed to read title model setting: {}", e)))?
+        .and_then(|id| Uuid::parse_str(&id).ok());
+    for config_id in [conversation.title_model_config_id, app_wide].into_iter().flatten() {
+        match get_request_model_config(storage, config_id).await {
+            Ok(config) => return Ok(Some(config)),
+            Err(e) if e.kind == ErrorKind::NotFound => {
+                log::warn!("Title model {} no longer exists, falling back", config_id);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    load_utility_model_config(storage).await
+}
+
+// Reads the prompt settings used by `prompt::compose_system_prompt`, including the
+// conversation's persona. Read on every request so changes apply without a restart; a failed
+// read just drops that part.
+async fn load_prompt_settings(storage: &StorageManager, conversation: &Conversation) -> prompt::PromptSettings {
+    let read = |key: &'static str| async move {
+        match storage.get_setting(key).await {
+            Ok(value) => value,
+            Err(e) => {
+                log::warn!("Failed to read setting '{}', continuing without it: {:?}", key, e);
+                None
+            }
+        }
+    };
+    prompt::PromptSettings {
+        default_prompt: read(config::DEFAULT_SYSTEM_PROMPT_KEY).await,
+        suffix: read(config::SYSTEM_PROMPT_SUFFIX_KEY).await,
+        persona: load_persona(storage, conversation).await,
+        project_digest: ingest::load_digest(storage, conversation.id).await,
+    }
+}
+
+// The conversation's persona; None when it has none or the persona can't be read
+async fn load_persona(storage: &StorageManager, conversation: &Conversation) -> Option<Persona> {
+    let persona_id = conversation.persona_id?;
+    match storage.get_persona(persona_id).await {
+        Ok(persona) => persona,
+        Err(e) => {
+            log::warn!("Failed to read persona {}, continuing without it: {:?}", persona_id, e);
+            None
+        }
+    }
+}
+
+// Reads the budget settings and this month's spend
+async fn load_budget_status(storage: &StorageManager) -> Result<BudgetStatus, CommandError> {
+    let budget_usd = storage.get_setting(config::MONTHLY_BUDGET_USD_KEY).await
+        .map_err(|e| CommandError::storage(format!("Failed to read monthly budget: {}", e)))?
+        .and_then(|value| value.parse::<f64>().ok());
+    let enforcement = storage.get_setting(config::BUDGET_ENFORCEMENT_KEY).await
+        .map_err(|e| CommandError::storage(format!("Failed to read budget enforcement: {}", e)))?
+        .and_then(|value| BudgetEnforcement::parse(&value).ok())
+        .unwrap_or(BudgetEnforcement::Warn);
+
+    let now = chrono::Local::now();
+    let (spent_usd, untracked_messages) = storage.spend_since(budget::month_start(now)).await
+        .map_err(|e| CommandError::storage(format!("Failed to compute monthly spend: {}", e)))?;
+    Ok(BudgetStatus {
+        spent_usd,
+        budget_usd,
+        days_remaining: budget::days_remaining(now),
+        untracked_messages,
+        enforcement,
+    })
+}
+
+// Warns from 80% of the monthly budget and refuses new requests at 100% when blocking
+fn enforce_budget(state: &AppState, status: &BudgetStatus) -> Result<(), CommandError> {
+    let (Some(budget_usd), Some(fraction_spent)) = (status.budget_usd, status.fraction_spent()) else {
+        return Ok(());
+    };
+    if fraction_spent < budget::WARNING_THRESHOLD {
+        return Ok(());
+    }
+    let blocked = fraction_spent >= 1.0 && status.enforcement == BudgetEnforcement::Block;
+    let warning = BudgetWarning { spent_usd: status.spent_usd, budget_usd, fraction_spent, blocked };
+    if let Err(e) = state.app_handle.emit(events::BUDGET_WARNING, warning) {
+        log::error!("Failed to emit budget warning: {:?}", e);
+    }
+    if blocked {
+        return Err(CommandError::validation(format!(
+            "Monthly budget of ${:.2} reached (${:.2} spent). Raise the budget or switch enforcement to warn to continue.",
+            budget_usd, status.spent_usd
+        )));
+    }
+    Ok(())
+}
+
+// Refuses to add to (or regenerate in) a locked conversation
+fn ensure_unlocked(conversation: &Conversation) -> Result<(), CommandError> {
+    if conversation.locked {
+        return Err(CommandError::locked(format!(
+            "Conversation \"{}\" is locked. Unlock it to send or regenerate messages.",
+            conversation.title
+        )));
+    }
+    Ok(())
+}
+
+// Refuses a request once the conversation's recorded token usage, plus `incoming_tokens`
+// for what is about to be sent, would pass its token budget
+async fn enforce_token_budget(
+    state: &AppState,
+    storage: &StorageManager,
+    conversation: &Conversation,
+    incoming_tokens: usize,
+) -> Result<(), CommandError> {
+    let Some(token_budget) = conversation.token_budget else {
+        return Ok(());
+    };
+    let tokens_used = if conversation.ephemeral {
+        state
+            .with_ephemeral_messages(conversation.id, Vec::new())
+            .iter()
+            .map(|m| m.tokens_used() as i64)
+            .sum()
+    } else {
+        storage.conversation_tokens_used(conversation.id).await
+            .map_err(|e| CommandError::storage(format!("Failed to compute conversation token usage: {}", e)))?
+    };
+    if tokens_used + incoming_tokens as i64 <= token_budget {
+        return Ok(());
+    }
+
+    let payload = TokenBudgetExceeded {
+        conversation_id: conversation.id.to_string(),
+        tokens_used,
+        token_budget,
+    };
+    if let Err(e) = state.emit_to_conversation(conversation.id, events::BUDGET_EXCEEDED, payload) {
+        log::error!("Failed to emit budget exceeded event: {:?}", e);
+    }
+    Err(CommandError::validation(format!(
+        "This conversation's token budget of {} is used up ({} tokens used). Raise or clear the budget to continue.",
+        token_budget, tokens_used
+    )))
+}
+
+// Tauri command to send a message (NOW includes API call and event emit)
+#[tauri::command]
+pub async fn send_message(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    content: String,
+    preview_mode: Option<bool>, // Stop after the first sentence of the answer
+) -> Result<Message, CommandError> { // Still returns the user message initially
+    log::info!("[send_message] Handler Entered for conversation ID: {}", conversation_id);
+    
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        let err_msg = format!("Invalid conversation ID format for send: {}", conversation_id);
         log::error!("{}", err_msg);
-        return Err(err_msg);
+        return Err(CommandError::validation(err_msg));
+    };
+    log::info!("[send_message] Parsed conv_uuid: {}", conv_uuid);
+    let work = state.begin_background_work();
+
+    let user_message = Message {
+        id: Uuid::new_v4(),
+        conversation_id: conv_uuid,
+        role: "user".to_string(),
+        content, // content is passed directly as arg, ok
+        timestamp: Utc::now(),
+        metadata: None,
+        name: None,
+        variant_group: None,
+    };
+    log::info!("[send_message] Created user_message with ID: {}", user_message.id);
+    
+    let user_message_clone = user_message.clone();
+    let user_message_id = user_message.id;
+
+    // --- Save user message (kept in memory only for ephemeral conversations) ---
+    let request = {
+        let storage = state.storage.lock().await;
+        state.ensure_online()?;
+        enforce_budget(&state, &load_budget_status(&storage).await?)?;
+        let conversation = match storage.get_conversation(conv_uuid).await {
+            Ok(Some(c)) => c,
+            Ok(None) => return Err(CommandError::not_found(format!("Conversation {} not found", conversation_id))),
+            Err(e) => return Err(CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e))),
+        };
+        ensure_unlocked(&conversation)?;
+        enforce_token_budget(&state, &storage, &conversation, prompt::estimate_tokens(&user_message)).await?;
+        let model_config = get_conversation_model_config(&storage, &conversation).await
+            .map_err(|e| CommandError::storage(format!("Failed to get model config for {}: {}", conversation_id, e)))?;
+        if conversation.ephemeral {
+            state.remember_ephemeral(user_message.clone());
+        } else if let Err(e) = storage.save_message(&user_message).await {
+            log::error!("Failed to save user message for conversation {}: {:?}", conversation_id, e);
+            return Err(CommandError::storage(format!("Failed to save message: {}", e)));
+        } else {
+            log::info!("[send_message] User message {} saved successfully.", user_message.id);
+        }
+        state.notify_conversation_updated(conv_uuid);
+
+        let messages = match storage.get_conversation_messages(conv_uuid).await {
+            Ok(m) => state.with_ephemeral_messages(conv_uuid, m),
+            Err(e) => return Err(CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e))),
+        };
+        let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+        let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+        GenerationRequest {
+            system_message: prompt::system_message(conv_uuid, system_prompt_content),
+            persona: prompt_settings.persona,
+            summarizer: load_summarizer(&storage).await,
+            smoothing: load_stream_smoothing(&storage).await,
+            trim_leading_whitespace: load_trim_leading_whitespace(&storage).await,
+            preview: preview_mode.unwrap_or(false),
+            // Skip comparison variants that weren't kept; the engine trims to the context window
+            history: prompt::filter_history(messages),
+            conversation,
+            model_config,
+            kind: StreamKind::Send,
+            user_message_id: Some(user_message_id),
+            replaces: None,
+            extends: None,
+            comparison: None,
+        }
+    };
+
+    // --- Trigger API call in background ---
+    log::info!("[send_message] Spawning generation for conv {}", conversation_id);
+    state.spawn_background(work, generation::run_generation(state.inner().clone(), request));
+
+    log::info!("[send_message] Returning user message clone immediately (End of main thread)."); // Adjusted log message
+    Ok(user_message_clone)
+}
+
+// Number of models a single comparison may fan out to
+const MIN_COMPARISON_MODELS: usize = 2;
+const MAX_COMPARISON_MODELS: usize = 4;
+
+// Tauri command to send one prompt to several model configs at once for comparison.
+// The user message is saved once; each config streams into its own assistant message
+// tagged with the comparison ID. Returns the user message and the comparison ID.
+#[tauri::command]
+pub async fn send_message_multi(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    content: String,
+    model_config_ids: Vec<String>,
+) -> Result<(Message, String), CommandError> {
+    log::info!(
+        "[send_message_multi] Comparing {} models for conversation ID: {}",
+        model_config_ids.len(),
+        conversation_id
+    );
+
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format for send: {}", conversation_id)));
+    };
+    if model_config_ids.len() < MIN_COMPARISON_MODELS || model_config_ids.len() > MAX_COMPARISON_MODELS {
+        return Err(CommandError::validation(format!(
+            "Select between {} and {} models to compare.",
+            MIN_COMPARISON_MODELS, MAX_COMPARISON_MODELS
+        )));
+    }
+    let mut model_uuids = Vec::with_capacity(model_config_ids.len());
+    for id in &model_config_ids {
+        let Ok(uuid) = Uuid::parse_str(id) else {
+            return Err(CommandError::validation(format!("Invalid model config ID format: {}", id)));
+        };
+        model_uuids.push(uuid);
+    }
+    let work = state.begin_background_work();
+
+    let user_message = Message {
+        id: Uuid::new_v4(),
+        conversation_id: conv_uuid,
+        role: "user".to_string(),
+        content,
+        timestamp: Utc::now(),
+        metadata: None,
+        name: None,
+        variant_group: None,
+    };
+
+    // --- Save user message once and gather shared context ---
+    let (conversation, model_configs, history, prompt_settings, summarizer, smoothing, trim_leading_whitespace) = {
+        let storage = state.storage.lock().await;
+        let conversation = match storage.get_conversation(conv_uuid).await {
+            Ok(Some(c)) => c,
+            Ok(None) => return Err(CommandError::not_found(format!("Conversation {} not found", conversation_id))),
+            Err(e) => return Err(CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e))),
+        };
+        if conversation.ephemeral {
+            return Err(CommandError::validation("Model comparison is not available in ephemeral conversations."));
+        }
+        ensure_unlocked(&conversation)?;
+        state.ensure_online()?;
+        enforce_budget(&state, &load_budget_status(&storage).await?)?;
+        enforce_token_budget(&state, &storage, &conversation, prompt::estimate_tokens(&user_message)).await?;
+        let mut model_configs = Vec::with_capacity(model_uuids.len());
+        for model_uuid in &model_uuids {
+            model_configs.push(get_request_model_config(&storage, *model_uuid).await?);
+        }
+        if let Err(e) = storage.save_message(&user_message).await {
+            log::error!("Failed to save user message for conversation {}: {:?}", conversation_id, e);
+            return Err(CommandError::storage(format!("Failed to save message: {}", e)));
+        }
+        state.notify_conversation_updated(conv_uuid);
+        let history = match storage.get_conversation_messages(conv_uuid).await {
+            Ok(m) if conversation.single_turn => prompt::single_turn_history(prompt::filter_history(m)),
+            Ok(m) => prompt::filter_history(m),
+            Err(e) => return Err(CommandError::storage(format!("Failed to load messages: {}", e))),
+        };
+        let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+        (
+            conversation,
+            model_configs,
+            history,
+            prompt_settings,
+            load_summarizer(&storage).await,
+            load_stream_smoothing(&storage).await,
+            load_trim_leading_whitespace(&storage).await,
+        )
+    };
+
+    // --- Register the fan-out so it can be cancelled as a whole ---
+    let comparison_id = Uuid::new_v4();
+    let variants: Vec<(ModelConfig, Uuid)> = model_configs
+        .into_iter()
+        .map(|mc| (mc, Uuid::new_v4()))
+        .collect();
+    state.comparison_streams.insert(comparison_id, variants.iter().map(|(_, id)| *id).collect());
+
+    // Each model streams on its own (same engine as send_message), so a failing one doesn't
+    // stop the others
+    for (model_config, assistant_message_id) in variants {
+        let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+        let request = GenerationRequest {
+            system_message: prompt::system_message(conv_uuid, system_prompt_content),
+            persona: prompt_settings.persona.clone(),
+            summarizer: summarizer.clone(),
+            smoothing,
+            trim_leading_whitespace,
+            preview: false,
+            history: history.clone(),
+            conversation: conversation.clone(),
+            model_config,
+            kind: StreamKind::Compare,
+            user_message_id: Some(user_message.id),
+            replaces: None,
+            extends: None,
+            comparison: Some(ComparisonVariant { comparison_id, message_id: assistant_message_id }),
+        };
+        state.spawn_background(work.clone(), generation::run_generation(state.inner().clone(), request));
+    }
+
+    Ok((user_message, comparison_id.to_string()))
+}
+
+// Tauri command to mark one comparison variant as the answer that stays in the history.
+// Its sibling variants remain visible but are excluded from future requests.
+#[tauri::command]
+pub async fn keep_comparison_result(state: State<'_, AppState>, message_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to keep comparison result {}", message_id);
+
+    let Ok(msg_uuid) = Uuid::parse_str(&message_id) else {
+        return Err(CommandError::validation(format!("Invalid message ID format: {}", message_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    let kept = match storage.get_message(msg_uuid).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return Err(CommandError::not_found(format!("Message {} not found", message_id))),
+        Err(e) => return Err(CommandError::storage(format!("Failed to load message: {}", e))),
+    };
+    let Some(comparison_id) = kept.comparison_id() else {
+        return Err(CommandError::validation("Message is not part of a model comparison."));
+    };
+
+    let siblings = storage.get_conversation_messages(kept.conversation_id).await
+        .map_err(|e| CommandError::storage(format!("Failed to load messages: {}", e)))?
+        .into_iter()
+        .filter(|m| m.comparison_id().as_deref() == Some(comparison_id.as_str()));
+    for mut sibling in siblings {
+        sibling.set_metadata_field("comparison_kept", serde_json::json!(sibling.id == msg_uuid));
+        storage.update_message_metadata(sibling.id, sibling.metadata.as_deref()).await
+            .map_err(|e| CommandError::storage(format!("Failed to update comparison result: {}", e)))?;
+    }
+    Ok(())
+}
+
+// Tauri command to switch which answer of a regeneration variant group is shown and sent
+// with later requests. The other answers are kept.
+#[tauri::command]
+pub async fn select_message_variant(state: State<'_, AppState>, message_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to select message variant {}", message_id);
+
+    let Ok(msg_uuid) = Uuid::parse_str(&message_id) else {
+        return Err(CommandError::validation(format!("Invalid message ID format: {}", message_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    let selected = match storage.get_message(msg_uuid).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return Err(CommandError::not_found(format!("Message {} not found", message_id))),
+        Err(e) => return Err(CommandError::storage(format!("Failed to load message: {}", e))),
+    };
+    let Some(group) = selected.variant_group else {
+        return Err(CommandError::validation("Message has no alternative answers."));
+    };
+    storage.select_message_variant(group, msg_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to select answer: {}", e)))?;
+    drop(storage);
+    state.notify_conversation_updated(selected.conversation_id);
+    Ok(())
+}
+
+// Tauri command to pin a message so it is always sent with requests, even once
+// context trimming drops the history around it. Unpinning restores normal trimming.
+#[tauri::command]
+pub async fn set_message_context_pinned(
+    state: State<'_, AppState>,
+    message_id: String,
+    pinned: bool,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested context_pinned={} for message {}", pinned, message_id);
+    let Ok(msg_uuid) = Uuid::parse_str(&message_id) else {
+        return Err(CommandError::validation(format!("Invalid message ID format: {}", message_id)));
+    };
+
+    let set_pinned = |message: &mut Message| {
+        message.set_metadata_field("context_pinned", serde_json::json!(pinned));
+    };
+    if state.update_ephemeral(msg_uuid, set_pinned) {
+        return Ok(());
+    }
+
+    let storage = state.storage.lock().await;
+    let mut message = match storage.get_message(msg_uuid).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return Err(CommandError::not_found(format!("Message {} not found", message_id))),
+        Err(e) => return Err(CommandError::storage(format!("Failed to load message: {}", e))),
+    };
+    set_pinned(&mut message);
+    storage.update_message_metadata(msg_uuid, message.metadata.as_deref()).await
+        .map_err(|e| CommandError::storage(format!("Failed to update message: {}", e)))
+}
+
+// Roles `set_message_role` accepts
+const MESSAGE_ROLES: &[&str] = &["user", "assistant", "system"];
+
+// Tauri command correcting the role of one message, e.g. after a faulty import. The change is
+// made either way; the returned warnings say where user and assistant turns no longer
+// alternate around the message, which most APIs reject.
+#[tauri::command]
+pub async fn set_message_role(state: State<'_, AppState>, message_id: String, role: String) -> Result<Vec<String>, CommandError> {
+    log::info!("Frontend requested role '{}' for message {}", role, message_id);
+    let role = role.trim().to_lowercase();
+    if !MESSAGE_ROLES.contains(&role.as_str()) {
+        return Err(CommandError::validation(format!(
+            "Invalid role '{}'. Expected one of: {}.", role, MESSAGE_ROLES.join(", ")
+        )));
+    }
+    let message = find_message(&state, &message_id).await?;
+    if !state.update_ephemeral(message.id, |unsaved| unsaved.role = role.clone()) {
+        let storage = state.storage.lock().await;
+        storage.set_message_role(message.id, &role).await
+            .map_err(|e| CommandError::storage(format!("Failed to change the role of message {}: {}", message_id, e)))?;
+    }
+    state.notify_conversation_updated(message.conversation_id);
+
+    let stored = {
+        let storage = state.storage.lock().await;
+        storage.get_conversation_messages(message.conversation_id).await
+            .map_err(|e| CommandError::storage(format!("Failed to load messages: {}", e)))?
+    };
+    let messages = state.with_ephemeral_messages(message.conversation_id, stored);
+    Ok(alternation_warnings(&messages, message.id))
+}
+
+// Tauri command putting a conversation's messages in the given order, e.g. to move a note.
+// `ordered_ids` must list every message of the conversation exactly once. Only the display
+// order changes; every message keeps its timestamp.
+#[tauri::command]
+pub async fn reorder_messages(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    ordered_ids: Vec<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to reorder {} messages in conversation {}", ordered_ids.len(), conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let ordered_ids = ordered_ids
+        .iter()
+        .map(|id| Uuid::parse_str(id).map_err(|_| CommandError::validation(format!("Invalid message ID format: {}", id))))
+        .collect::<Result<Vec<Uuid>, CommandError>>()?;
+
+    {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        if conversation.ephemeral {
+            return Err(CommandError::validation("Messages of an ephemeral conversation can't be reordered."));
+        }
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?;
+        let stored: HashSet<Uuid> = messages.iter().map(|m| m.id).collect();
+        let listed: HashSet<Uuid> = ordered_ids.iter().copied().collect();
+        if listed.len() != ordered_ids.len() || listed != stored {
+            return Err(CommandError::validation(
+                "The new order must list each message of the conversation exactly once.",
+            ));
+        }
+        storage.reorder_messages(conv_uuid, &ordered_ids).await
+            .map_err(|e| CommandError::storage(format!("Failed to reorder messages: {}", e)))?;
+    }
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Where user and assistant turns stop alternating next to `message_id`. System and tool
+// messages and unselected alternative answers sit outside the alternation and are skipped.
+fn alternation_warnings(messages: &[Message], message_id: Uuid) -> Vec<String> {
+    let Some(position) = messages.iter().position(|m| m.id == message_id) else {
+        return Vec::new();
+    };
+    let is_turn = |m: &&Message| (m.role == "user" || m.role == "assistant") && !m.is_unselected_variant();
+    let previous = messages[..position].iter().rev().find(is_turn);
+    let current = Some(&messages[position]).filter(is_turn);
+    let next = messages[position + 1..].iter().find(is_turn);
+    let neighbourhood: Vec<&Message> = [previous, current, next].into_iter().flatten().collect();
+    let mut warnings: Vec<String> = neighbourhood
+        .windows(2)
+        .filter(|pair| pair[0].role == pair[1].role)
+        .map(|pair| format!("Two {} messages in a row ({} and {}).", pair[0].role, pair[0].id, pair[1].id))
+        .collect();
+    if previous.is_none() && current.is_some_and(|m| m.role == "assistant") {
+        warnings.push("The conversation's first turn is now from the assistant.".to_string());
+    }
+    warnings
+}
+
+// Tauri command to stop every stream of a comparison fan-out at once
+#[tauri::command]
+pub async fn stop_comparison(state: State<'_, AppState>, comparison_id: String) -> Result<(), CommandError> {
+    log::warn!("Frontend requested to stop comparison {}", comparison_id);
+
+    let Ok(comparison_uuid) = Uuid::parse_str(&comparison_id) else {
+        return Err(CommandError::validation(format!("Invalid comparison ID format: {}", comparison_id)));
+    };
+    if let Some(message_ids) = state.comparison_streams.get(&comparison_uuid) {
+        for message_id in message_ids.iter() {
+            state.cancelled_streams.insert(*message_id, true);
+        }
+    }
+    Ok(())
+}
+
+// Tauri command to rename a conversation
+#[tauri::command]
+pub async fn rename_conversation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    new_title: String,
+) -> Result<(), CommandError> {
+    log::info!(
+        "Frontend requested to rename conversation {} to: {}",
+        conversation_id,
+        new_title
+    );
+
+    if new_title.trim().is_empty() {
+        return Err(CommandError::validation("New title cannot be empty."));
+    }
+    
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        let err_msg = format!("Invalid conversation ID format for rename: {}", conversation_id);
+        log::error!("{}", err_msg);
+        return Err(CommandError::validation(err_msg));
+    };
+
+    let storage_manager = state.storage.lock().await;
+    match storage_manager
+        .rename_conversation(conv_uuid, new_title.trim().to_string())
+        .await
+    {
+        Ok(_) => {
+            state.notify_conversation_updated(conv_uuid);
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("Failed to rename conversation {}: {:?}", conversation_id, e);
+            Err(CommandError::storage(format!("Failed to rename conversation: {}", e)))
+        }
+    }
+}
+
+// Tauri command returning the model config a conversation's requests go out with, with its
+// model override and the default user ID applied. Not found when the config was deleted.
+#[tauri::command]
+pub async fn get_conversation_model(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<ModelConfig, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    let conversation = storage.get_conversation(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+        .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+    get_conversation_model_config(&storage, &conversation).await.map_err(|e| match e.kind {
+        ErrorKind::NotFound => CommandError::not_found(format!(
+            "Conversation {} uses model config {}, which no longer exists",
+            conversation_id, conversation.model_config_id
+        )),
+        _ => e,
+    })
+}
+
+// Tauri command to update a conversation's model
+#[tauri::command]
+pub async fn update_conversation_model(
+    state: State<'_, AppState>,
+    conversation_id: String, // ID of conversation to update
+    model_config_id: String, // ID of the new model config
+) -> Result<(), CommandError> {
+    log::info!(
+        "Frontend requested to update model for conversation {} to model {}",
+        conversation_id,
+        model_config_id
+    );
+    
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let Ok(model_uuid) = Uuid::parse_str(&model_config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", model_config_id)));
+    };
+    
+    let storage = state.storage.lock().await;
+    // Need an update_conversation_model_id method in StorageManager
+    match storage.update_conversation_model_id(conv_uuid, model_uuid).await { // Call new storage method
+        Ok(_) => {
+            state.notify_conversation_updated(conv_uuid);
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("Failed to update model for conversation {}: {:?}", conversation_id, e);
+            Err(CommandError::storage(format!("Failed to update conversation model: {}", e)))
+        }
+    }
+}
+
+// Tauri command to pick the model a conversation uses within its current config
+// (empty clears it, falling back to the config's `model`). Unlike `update_conversation_model`,
+// the endpoint and key stay the same.
+#[tauri::command]
+pub async fn set_conversation_model(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    model_name: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested model '{}' for conversation {}", model_name, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let model_name = model_name.trim();
+    let model_override = if model_name.is_empty() { None } else { Some(model_name.to_string()) };
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_model_override(conv_uuid, model_override).await
+        .map_err(|e| CommandError::storage(format!("Failed to set conversation model: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command to set a conversation's token budget (None or 0 clears it)
+#[tauri::command]
+pub async fn set_conversation_token_budget(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    token_budget: Option<i64>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested token budget {:?} for conversation {}", token_budget, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    if token_budget.is_some_and(|budget| budget < 0) {
+        return Err(CommandError::validation("Token budget cannot be negative."));
+    }
+    let token_budget = token_budget.filter(|budget| *budget > 0);
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_token_budget(conv_uuid, token_budget).await
+        .map_err(|e| CommandError::storage(format!("Failed to set conversation token budget: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command to override a conversation's language code (e.g. "de"); empty clears it
+// so the next title generation detects it again
+#[tauri::command]
+pub async fn set_conversation_language(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    language: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested language '{}' for conversation {}", language, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let language = language.trim();
+    if language.chars().count() > 35 {
+        return Err(CommandError::validation(format!("Invalid language: {}", language)));
+    }
+    let language = if language.is_empty() { None } else { Some(language) };
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_language(conv_uuid, language).await
+        .map_err(|e| CommandError::storage(format!("Failed to set conversation language: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command returning a conversation's metadata object (empty when none is set)
+#[tauri::command]
+pub async fn get_conversation_metadata(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<serde_json::Map<String, serde_json::Value>, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    storage.get_conversation_metadata(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to read conversation metadata: {}", e)))?
+        .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))
+}
+
+// Tauri command to merge keys into a conversation's metadata. Keys left out are kept;
+// a null value removes its key. Returns the merged metadata.
+#[tauri::command]
+pub async fn set_conversation_metadata(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    metadata: serde_json::Value,
+) -> Result<serde_json::Map<String, serde_json::Value>, CommandError> {
+    log::info!("Frontend requested a metadata update for conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let serde_json::Value::Object(update) = metadata else {
+        return Err(CommandError::validation("Conversation metadata must be a JSON object."));
+    };
+    let storage = state.storage.lock().await;
+    if storage.get_conversation(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+        .is_none()
+    {
+        return Err(CommandError::not_found(format!("Conversation {} not found", conversation_id)));
+    }
+    storage.merge_conversation_metadata(conv_uuid, &update).await
+        .map_err(|e| CommandError::storage(format!("Failed to update conversation metadata: {}", e)))
+}
+
+// Longest icon value accepted; icons are short names or a single emoji
+const MAX_ICON_CHARS: usize = 64;
+
+// Tauri command to set a conversation's sidebar color (#RRGGBB or #RGB) and icon.
+// `None` or an empty string clears either.
+#[tauri::command]
+pub async fn set_conversation_appearance(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    color: Option<String>,
+    icon: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested appearance {:?}/{:?} for conversation {}", color, icon, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let color = color.map(|color| color.trim().to_ascii_lowercase()).filter(|color| !color.is_empty());
+    if let Some(color) = color.as_deref().filter(|color| !is_hex_color(color)) {
+        return Err(CommandError::validation(format!("Invalid color '{}': use a hex color like #3b82f6.", color)));
+    }
+    let icon = icon.map(|icon| icon.trim().to_string()).filter(|icon| !icon.is_empty());
+    if icon.as_ref().is_some_and(|icon| icon.chars().count() > MAX_ICON_CHARS) {
+        return Err(CommandError::validation(format!("Icons are limited to {} characters.", MAX_ICON_CHARS)));
+    }
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_appearance(conv_uuid, color.as_deref(), icon.as_deref()).await
+        .map_err(|e| CommandError::storage(format!("Failed to set conversation appearance: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// `#` followed by 3 or 6 hex digits
+fn is_hex_color(color: &str) -> bool {
+    color.strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+// Tauri command to set a conversation's answer style: a preset key ("concise", "detailed",
+// "bullet_points", "eli5") or custom instruction text. `None` or an empty string clears it.
+#[tauri::command]
+pub async fn set_conversation_style_preset(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    style_preset: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested style preset {:?} for conversation {}", style_preset, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let style_preset = style_preset.map(|preset| preset.trim().to_string()).filter(|preset| !preset.is_empty());
+    if style_preset.as_ref().is_some_and(|preset| preset.chars().count() > prompt::MAX_CUSTOM_STYLE_CHARS) {
+        return Err(CommandError::validation(format!(
+            "Custom style instructions are limited to {} characters.",
+            prompt::MAX_CUSTOM_STYLE_CHARS
+        )));
+    }
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_style_preset(conv_uuid, style_preset.as_deref()).await
+        .map_err(|e| CommandError::storage(format!("Failed to set conversation style preset: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command to set (or clear, with an empty string) a conversation's system prompt
+#[tauri::command]
+pub async fn set_conversation_system_prompt(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    system_prompt: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set system prompt for conversation {}", conversation_id);
+
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let system_prompt = Some(system_prompt.trim().to_string()).filter(|p| !p.is_empty());
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_system_prompt(conv_uuid, system_prompt).await
+        .map_err(|e| CommandError::storage(format!("Failed to set conversation system prompt: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command to switch a conversation's persona (None for none). A persona with a default
+// model config also switches the conversation to it; the conversation's own system prompt and
+// model choices still take precedence over the persona's afterwards.
+#[tauri::command]
+pub async fn set_conversation_persona(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    persona_id: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested persona {:?} for conversation {}", persona_id, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let persona_uuid = match persona_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(id) => Some(Uuid::parse_str(id).map_err(|_| CommandError::validation(format!("Invalid persona ID format: {}", id)))?),
+        None => None,
+    };
+
+    let storage = state.storage.lock().await;
+    let persona = match persona_uuid {
+        Some(uuid) => Some(storage.get_persona(uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to load persona: {}", e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Persona {} not found", uuid)))?),
+        None => None,
+    };
+    storage.set_conversation_persona(conv_uuid, persona_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to set conversation persona: {}", e)))?;
+    if let Some(config_id) = persona.and_then(|persona| persona.model_config_id) {
+        get_model_config(&storage, config_id).await?;
+        storage.update_conversation_model_id(conv_uuid, config_id).await
+            .map_err(|e| CommandError::storage(format!("Failed to switch to the persona's model: {}", e)))?;
+    }
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command ingesting a folder into a conversation as project context. Text files that
+// pass `.gitignore` and the globs are included in the system prompt of later requests, within
+// the size budgets in `ingest`; binary and oversized files are listed in the manifest as
+// skipped. Running it again on the same folder only re-reads files that changed.
+#[tauri::command]
+pub async fn ingest_directory(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    path: String,
+    include_globs: Option<Vec<String>>,
+    exclude_globs: Option<Vec<String>>,
+) -> Result<IngestSummary, CommandError> {
+    log::info!("Frontend requested ingestion of {} into conversation {}", path, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let include_globs: Vec<String> = include_globs.unwrap_or_default().into_iter().filter(|glob| !glob.trim().is_empty()).collect();
+    let exclude_globs: Vec<String> = exclude_globs.unwrap_or_default().into_iter().filter(|glob| !glob.trim().is_empty()).collect();
+    let include = ingest::compile_globs(&include_globs).map_err(CommandError::validation)?;
+    let exclude = ingest::compile_globs(&exclude_globs).map_err(CommandError::validation)?;
+    let root = std::fs::canonicalize(path.trim())
+        .map_err(|e| CommandError::validation(format!("Folder {} is not accessible: {}", path, e)))?;
+    if !root.is_dir() {
+        return Err(CommandError::validation(format!("{} is not a folder", path)));
+    }
+    let root_text = root.display().to_string();
+
+    let previous = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conv_uuid, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conv_uuid)))?;
+        ensure_unlocked(&conversation)?;
+        match storage.get_project_context(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to read project context: {}", e)))?
+        {
+            // A different folder starts a new manifest
+            Some(context) if context.root == root_text => Some(storage.get_project_files(conv_uuid, false).await
+                .map_err(|e| CommandError::storage(format!("Failed to read project files: {}", e)))?),
+            _ => None,
+        }
+    };
+    let replace_all = previous.is_none();
+
+    // The walk reads the disk, so the storage lock isn't held for it
+    let walk_root = root.clone();
+    let result = tokio::task::spawn_blocking(move || ingest::ingest(&walk_root, &include, &exclude, &previous.unwrap_or_default()))
+        .await
+        .map_err(|e| CommandError::internal(format!("Ingestion failed: {}", e)))?;
+
+    let context = ProjectContext { conversation_id: conv_uuid, root: root_text, include_globs, exclude_globs, updated_at: Utc::now() };
+    let storage = state.storage.lock().await;
+    storage.save_project_context(&context, &result.changed, &result.removed, replace_all).await
+        .map_err(|e| CommandError::storage(format!("Failed to save project context: {}", e)))?;
+    drop(storage);
+    log::info!(
+        "Ingested {} into conversation {}: {} included, {} skipped, {} added, {} updated, {} removed",
+        context.root, conv_uuid, result.summary.included, result.summary.skipped,
+        result.summary.added, result.summary.updated, result.summary.removed
+    );
+    state.notify_conversation_updated(conv_uuid);
+    Ok(result.summary)
+}
+
+// Tauri command listing a conversation's project manifest (included and skipped files), or
+// nothing when no folder was ingested
+#[tauri::command]
+pub async fn get_project_manifest(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<Option<ProjectManifest>, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    let Some(context) = storage.get_project_context(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to read project context: {}", e)))?
+    else {
+        return Ok(None);
+    };
+    let files = storage.get_project_files(conv_uuid, false).await
+        .map_err(|e| CommandError::storage(format!("Failed to read project files: {}", e)))?;
+    Ok(Some(ProjectManifest { context, files }))
+}
+
+// Tauri command removing a conversation's project context, so later requests no longer include it
+#[tauri::command]
+pub async fn clear_project_context(state: State<'_, AppState>, conversation_id: String) -> Result<bool, CommandError> {
+    log::info!("Frontend requested to clear the project context of conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    let removed = storage.delete_project_context(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to clear project context: {}", e)))?;
+    drop(storage);
+    if removed {
+        state.notify_conversation_updated(conv_uuid);
+    }
+    Ok(removed)
+}
+
+// --- Settings Commands ---
+
+// Tauri command to lock a conversation (read-only: no sending, regenerating or continuing) or
+// unlock it. Locked conversations can still be read, exported and searched.
+#[tauri::command]
+pub async fn set_conversation_locked(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    locked: bool,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested locked={} for conversation {}", locked, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_locked(conv_uuid, locked).await
+        .map_err(|e| CommandError::storage(format!("Failed to update conversation: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command turning single-turn mode on or off: each message is then answered on its own,
+// with only the system prompt and that message sent. Earlier messages are kept but not sent.
+#[tauri::command]
+pub async fn set_conversation_single_turn(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    single_turn: bool,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested single_turn={} for conversation {}", single_turn, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_single_turn(conv_uuid, single_turn).await
+        .map_err(|e| CommandError::storage(format!("Failed to update conversation: {}", e)))?;
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command to toggle whether a conversation's messages are persisted.
+// Turning it off discards the unsaved in-memory messages; they are never written retroactively.
+#[tauri::command]
+pub async fn set_conversation_ephemeral(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    ephemeral: bool,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested ephemeral={} for conversation {}", ephemeral, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    storage.set_conversation_ephemeral(conv_uuid, ephemeral).await
+        .map_err(|e| CommandError::storage(format!("Failed to update conversation: {}", e)))?;
+    if !ephemeral {
+        state.ephemeral_messages.remove(&conv_uuid);
+    }
+    state.notify_conversation_updated(conv_uuid);
+    Ok(())
+}
+
+// Tauri command to read the app-wide default system prompt (empty when unset)
+#[tauri::command]
+pub async fn get_default_system_prompt(state: State<'_, AppState>) -> Result<String, CommandError> {
+    log::info!("Frontend requested the default system prompt");
+    let storage = state.storage.lock().await;
+    storage.get_setting(config::DEFAULT_SYSTEM_PROMPT_KEY).await
+        .map(|value| value.unwrap_or_default())
+        .map_err(|e| CommandError::storage(format!("Failed to read default system prompt: {}", e)))
+}
+
+// Tauri command to set the app-wide default system prompt (empty disables it)
+#[tauri::command]
+pub async fn set_default_system_prompt(state: State<'_, AppState>, prompt: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set the default system prompt");
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::DEFAULT_SYSTEM_PROMPT_KEY, prompt.trim()).await
+        .map_err(|e| CommandError::storage(format!("Failed to save default system prompt: {}", e)))
+}
+
+// Tauri command to read the standing instruction appended to every system prompt (empty when unset)
+#[tauri::command]
+pub async fn get_system_prompt_suffix(state: State<'_, AppState>) -> Result<String, CommandError> {
+    log::info!("Frontend requested the system prompt suffix");
+    let storage = state.storage.lock().await;
+    storage.get_setting(config::SYSTEM_PROMPT_SUFFIX_KEY).await
+        .map(|value| value.unwrap_or_default())
+        .map_err(|e| CommandError::storage(format!("Failed to read system prompt suffix: {}", e)))
+}
+
+// Tauri command to set the system prompt suffix (empty disables it)
+#[tauri::command]
+pub async fn set_system_prompt_suffix(state: State<'_, AppState>, suffix: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set the system prompt suffix");
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::SYSTEM_PROMPT_SUFFIX_KEY, suffix.trim()).await
+        .map_err(|e| CommandError::storage(format!("Failed to save system prompt suffix: {}", e)))
+}
+
+// Tauri command to read the utility model config ID (None when unset)
+#[tauri::command]
+pub async fn get_utility_model_config_id(state: State<'_, AppState>) -> Result<Option<String>, CommandError> {
+    log::info!("Frontend requested the utility model");
+    let storage = state.storage.lock().await;
+    storage.get_setting(config::UTILITY_MODEL_CONFIG_ID_KEY).await
+        .map(|value| value.filter(|id| !id.is_empty()))
+        .map_err(|e| CommandError::storage(format!("Failed to read utility model setting: {}", e)))
+}
+
+// Tauri command to choose the model config used for titles and summaries (None clears it)
+#[tauri::command]
+pub async fn set_utility_model_config_id(state: State<'_, AppState>, config_id: Option<String>) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set the utility model to {:?}", config_id);
+    let storage = state.storage.lock().await;
+    let value = match config_id.as_deref().map(str::trim).filter(|id| !id.is_empty()) {
+        Some(id) => {
+            let Ok(config_uuid) = Uuid::parse_str(id) else {
+                return Err(CommandError::validation(format!("Invalid model config ID format: {}", id)));
+            };
+            get_model_config(&storage, config_uuid).await?;
+            config_uuid.to_string()
+        }
+        None => String::new(),
+    };
+    storage.set_setting(config::UTILITY_MODEL_CONFIG_ID_KEY, &value).await
+        .map_err(|e| CommandError::storage(format!("Failed to save utility model setting: {}", e)))
+}
+
+// Tauri command to read the title model: the conversation's own when `conversation_id` is
+// given, else the app-wide one. None when unset (titles then use the utility model).
+#[tauri::command]
+pub async fn get_title_model_config_id(
+    state: State<'_, AppState>,
+    conversation_id: Option<String>,
+) -> Result<Option<String>, CommandError> {
+    log::info!("Frontend requested the title model for {:?}", conversation_id);
+    let storage = state.storage.lock().await;
+    if let Some(conversation_id) = conversation_id {
+        let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+            return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+        };
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        return Ok(conversation.title_model_config_id.map(|id| id.to_string()));
+    }
+    storage.get_setting(config::TITLE_MODEL_CONFIG_ID_KEY).await
+        .map(|value| value.filter(|id| !id.is_empty()))
+        .map_err(|e| CommandError::storage(format!("Failed to read title model setting: {}", e)))
+}
+
+// Tauri command to choose the model config used for generated titles, for one conversation
+// when `conversation_id` is given, else app-wide. None clears it.
+#[tauri::command]
+pub async fn set_title_model_config_id(
+    state: State<'_, AppState>,
+    conversation_id: Option<String>,
+    config_id: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set the title model for {:?} to {:?}", conversation_id, config_id);
+    let storage = state.storage.lock().await;
+    let config_uuid = match config_id.as_deref().map(str::trim).filter(|id| !id.is_empty()) {
+        Some(id) => {
+            let Ok(config_uuid) = Uuid::parse_str(id) else {
+                return Err(CommandError::validation(format!("Invalid model config ID format: {}", id)));
+            };
+            get_model_config(&storage, config_uuid).await?;
+            Some(config_uuid)
+        }
+        None => None,
+    };
+    match conversation_id {
+        Some(conversation_id) => {
+            let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+                return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+            };
+            storage.set_conversation_title_model(conv_uuid, config_uuid).await
+                .map_err(|e| CommandError::storage(format!("Failed to set conversation title model: {}", e)))?;
+            state.notify_conversation_updated(conv_uuid);
+            Ok(())
+        }
+        None => {
+            let value = config_uuid.map(|id| id.to_string()).unwrap_or_default();
+            storage.set_setting(config::TITLE_MODEL_CONFIG_ID_KEY, &value).await
+                .map_err(|e| CommandError::storage(format!("Failed to save title model setting: {}", e)))
+        }
+    }
+}
+
+// Tauri command to read the app-wide default `user` identifier (empty when unset)
+#[tauri::command]
+pub async fn get_default_user_id(state: State<'_, AppState>) -> Result<String, CommandError> {
+    log::info!("Frontend requested the default user ID");
+    let storage = state.storage.lock().await;
+    storage.get_setting(config::DEFAULT_USER_ID_KEY).await
+        .map(|value| value.unwrap_or_default())
+        .map_err(|e| CommandError::storage(format!("Failed to read default user ID: {}", e)))
+}
+
+// Tauri command to set the app-wide default `user` identifier (empty disables it)
+#[tauri::command]
+pub async fn set_default_user_id(state: State<'_, AppState>, user_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set the default user ID");
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::DEFAULT_USER_ID_KEY, user_id.trim()).await
+        .map_err(|e| CommandError::storage(format!("Failed to save default user ID: {}", e)))
+}
+
+// Tauri command returning this month's spend against the budget
+#[tauri::command]
+pub async fn get_budget_status(state: State<'_, AppState>) -> Result<BudgetStatus, CommandError> {
+    log::info!("Frontend requested budget status");
+    let storage = state.storage.lock().await;
+    load_budget_status(&storage).await
+}
+
+// Tauri command to set the monthly budget (`None` removes it) and what happens when it is reached
+#[tauri::command]
+pub async fn set_monthly_budget(
+    state: State<'_, AppState>,
+    budget_usd: Option<f64>,
+    enforcement: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set monthly budget: {:?} ({:?})", budget_usd, enforcement);
+    if budget_usd.is_some_and(|budget| !budget.is_finite() || budget < 0.0) {
+        return Err(CommandError::validation("Budget must be a non-negative amount."));
+    }
+    let enforcement = enforcement.as_deref().map(BudgetEnforcement::parse).transpose().map_err(CommandError::validation)?;
+
+    let storage = state.storage.lock().await;
+    let budget_value = budget_usd.map(|budget| budget.to_string()).unwrap_or_default();
+    storage.set_setting(config::MONTHLY_BUDGET_USD_KEY, &budget_value).await
+        .map_err(|e| CommandError::storage(format!("Failed to save monthly budget: {}", e)))?;
+    if let Some(enforcement) = enforcement {
+        storage.set_setting(config::BUDGET_ENFORCEMENT_KEY, enforcement.as_str()).await
+            .map_err(|e| CommandError::storage(format!("Failed to save budget enforcement: {}", e)))?;
+    }
+    Ok(())
+}
+
+// Tauri command returning the size limit applied to saved messages
+#[tauri::command]
+pub async fn get_message_size_limit(state: State<'_, AppState>) -> Result<MessageSizeLimit, CommandError> {
+    let storage = state.storage.lock().await;
+    storage.message_size_limit().await
+        .map_err(|e| CommandError::storage(format!("Failed to read message size limit: {}", e)))
+}
+
+// Tauri command to set the longest message content that is saved (`None` turns the limit off)
+// and whether longer messages are truncated or rejected; `policy` keeps its value when omitted
+#[tauri::command]
+pub async fn set_message_size_limit(
+    state: State<'_, AppState>,
+    max_chars: Option<usize>,
+    policy: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set message size limit: {:?} ({:?})", max_chars, policy);
+    if max_chars == Some(0) {
+        return Err(CommandError::validation("Message size limit must be at least 1 character; omit it to turn the limit off."));
+    }
+    let policy = policy.as_deref().map(OversizedMessagePolicy::parse).transpose().map_err(CommandError::validation)?;
+
+    let storage = state.storage.lock().await;
+    let max_chars_value = max_chars.unwrap_or(0).to_string();
+    storage.set_setting(config::MAX_MESSAGE_CHARS_KEY, &max_chars_value).await
+        .map_err(|e| CommandError::storage(format!("Failed to save message size limit: {}", e)))?;
+    if let Some(policy) = policy {
+        storage.set_setting(config::OVERSIZED_MESSAGE_POLICY_KEY, policy.as_str()).await
+            .map_err(|e| CommandError::storage(format!("Failed to save oversized message policy: {}", e)))?;
+    }
+    Ok(())
+}
+
+// Tauri command rendering usage totals as CSV, per conversation (the default) or per day
+#[tauri::command]
+pub async fn export_usage_csv(state: State<'_, AppState>, grouping: Option<String>) -> Result<String, CommandError> {
+    log::info!("Frontend requested a usage CSV export ({:?})", grouping);
+    render_usage_csv(&state, grouping.as_deref()).await
+}
+
+// Tauri command asking where to save the usage CSV and writing it there. Returns the
+// chosen path, or None when the dialog was dismissed.
+#[tauri::command]
+pub async fn save_usage_csv(state: State<'_, AppState>, grouping: Option<String>) -> Result<Option<String>, CommandError> {
+    log::info!("Frontend requested to save a usage CSV ({:?})", grouping);
+    let csv = render_usage_csv(&state, grouping.as_deref()).await?;
+
+    let (path_tx, path_rx) = tokio::sync::oneshot::channel();
+    state.app_handle.dialog().file()
+        .add_filter("CSV", &["csv"])
+        .set_file_name(format!("localchat-usage-{}.csv", chrono::Local::now().format("%Y-%m-%d")))
+        .save_file(move |path| {
+            let _ = path_tx.send(path);
+        });
+    let Some(path) = path_rx.await.ok().flatten() else {
+        return Ok(None);
+    };
+    let path = path.into_path()
+        .map_err(|e| CommandError::internal(format!("Unsupported save location: {}", e)))?;
+    tokio::fs::write(&path, csv).await
+        .map_err(|e| CommandError::internal(format!("Failed to write {}: {}", path.display(), e)))?;
+    Ok(Some(path.display().to_string()))
+}
+
+async fn render_usage_csv(state: &AppState, grouping: Option<&str>) -> Result<String, CommandError> {
+    let grouping = grouping.map(UsageGrouping::parse).transpose().map_err(CommandError::validation)?
+        .unwrap_or(UsageGrouping::Conversation);
+    let storage = state.storage.lock().await;
+    let rows = storage.usage_rows(grouping).await
+        .map_err(|e| CommandError::storage(format!("Failed to aggregate usage: {}", e)))?;
+    Ok(usage::to_csv(&rows, grouping))
+}
+
+// Tauri command returning per-day message and new-conversation counts for the history
+// heatmap. `from` and `to` are inclusive local dates (YYYY-MM-DD), at most two years apart;
+// `utc_offset_minutes` is the user's offset from UTC (e.g. -300 for UTC-5), so days follow
+// their calendar. Every day in the range is listed, with zeros when nothing happened.
+#[tauri::command]
+pub async fn get_activity_stats(
+    state: State<'_, AppState>,
+    from: String,
+    to: String,
+    utc_offset_minutes: i32,
+) -> Result<Vec<usage::ActivityDay>, CommandError> {
+    log::info!("Frontend requested activity stats from {} to {} (UTC{:+} min)", from, to, utc_offset_minutes);
+    load_activity(&state, &from, &to, utc_offset_minutes).await
+}
+
+// Tauri command returning the number of messages sent on each day from `from` to `to`,
+// keyed by local date (YYYY-MM-DD). Same arguments and limits as `get_activity_stats`.
+#[tauri::command]
+pub async fn get_activity_by_day(
+    state: State<'_, AppState>,
+    from: String,
+    to: String,
+    utc_offset_minutes: i32,
+) -> Result<BTreeMap<String, i64>, CommandError> {
+    log::info!("Frontend requested messages per day from {} to {} (UTC{:+} min)", from, to, utc_offset_minutes);
+    let days = load_activity(&state, &from, &to, utc_offset_minutes).await?;
+    Ok(days.into_iter().map(|day| (day.date, day.message_count)).collect())
+}
+
+// Validates the range and offset of an activity request and buckets it by local day
+async fn load_activity(
+    state: &AppState,
+    from: &str,
+    to: &str,
+    utc_offset_minutes: i32,
+) -> Result<Vec<usage::ActivityDay>, CommandError> {
+    let parse = |value: &str| {
+        chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map_err(|_| CommandError::validation(format!("Invalid date '{}': expected YYYY-MM-DD.", value)))
+    };
+    let (from, to) = (parse(from)?, parse(to)?);
+    if to < from {
+        return Err(CommandError::validation("The end date is before the start date."));
+    }
+    if (to - from).num_days() + 1 > usage::MAX_ACTIVITY_DAYS {
+        return Err(CommandError::validation(format!(
+            "Activity stats cover at most {} days per request.",
+            usage::MAX_ACTIVITY_DAYS
+        )));
+    }
+    // UTC offsets range from -12:00 to +14:00
+    if !(-12 * 60..=14 * 60).contains(&utc_offset_minutes) {
+        return Err(CommandError::validation(format!("Invalid UTC offset: {} minutes.", utc_offset_minutes)));
+    }
+
+    // Local midnight at the start of `from` up to local midnight after `to`, in Unix seconds
+    let offset_secs = i64::from(utc_offset_minutes) * 60;
+    let midnight = |day: chrono::NaiveDate| day.and_time(chrono::NaiveTime::MIN).and_utc().timestamp() - offset_secs;
+    let end = to.succ_opt().ok_or_else(|| CommandError::validation("The end date is out of range."))?;
+    let counts = {
+        let storage = state.storage.lock().await;
+        storage.activity_counts(midnight(from), midnight(end), offset_secs).await
+            .map_err(|e| CommandError::storage(format!("Failed to aggregate activity: {}", e)))?
+    };
+    Ok(usage::fill_activity(from, to, &counts))
+}
+
+// Tauri command returning the scheduled export settings and when it last ran
+#[tauri::command]
+pub async fn get_auto_export(state: State<'_, AppState>) -> Result<AutoExportSettings, CommandError> {
+    auto_export::load_settings(&state).await.map_err(CommandError::storage)
+}
+
+// Tauri command to turn the daily export on or off and pick its folder and format; omitted
+// values keep their current setting
+#[tauri::command]
+pub async fn set_auto_export(
+    state: State<'_, AppState>,
+    enabled: bool,
+    dir: Option<String>,
+    format: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set auto-export: enabled={} dir={:?} format={:?}", enabled, dir, format);
+    if let Some(format) = format.as_deref() {
+        auto_export::parse_format(format).map_err(CommandError::validation)?;
+    }
+    let storage = state.storage.lock().await;
+    let current_dir = storage.get_setting(config::AUTO_EXPORT_DIR_KEY).await
+        .map_err(|e| CommandError::storage(format!("Failed to read auto-export folder: {}", e)))?
+        .filter(|dir| !dir.trim().is_empty());
+    if enabled && dir.as_deref().or(current_dir.as_deref()).is_none_or(|dir| dir.trim().is_empty()) {
+        return Err(CommandError::validation("Choose a folder before turning on auto-export."));
+    }
+
+    storage.set_setting(config::AUTO_EXPORT_ENABLED_KEY, if enabled { "true" } else { "false" }).await
+        .map_err(|e| CommandError::storage(format!("Failed to save auto-export setting: {}", e)))?;
+    if let Some(dir) = dir {
+        storage.set_setting(config::AUTO_EXPORT_DIR_KEY, dir.trim()).await
+            .map_err(|e| CommandError::storage(format!("Failed to save auto-export folder: {}", e)))?;
+    }
+    if let Some(format) = format {
+        storage.set_setting(config::AUTO_EXPORT_FORMAT_KEY, &format.to_ascii_lowercase()).await
+            .map_err(|e| CommandError::storage(format!("Failed to save auto-export format: {}", e)))?;
+    }
+    Ok(())
+}
+
+// Tauri command running the scheduled export right away, whether or not it is enabled
+#[tauri::command]
+pub async fn run_auto_export_now(state: State<'_, AppState>) -> Result<AutoExportSummary, CommandError> {
+    log::info!("Frontend requested an auto-export run");
+    auto_export::run_export(&state).await.map_err(CommandError::internal)
+}
+
+// Tauri command returning whether outgoing messages are redacted, and the patterns used
+#[tauri::command]
+pub async fn get_redaction_settings(state: State<'_, AppState>) -> Result<RedactionSettings, CommandError> {
+    let storage = state.storage.lock().await;
+    Ok(redaction::load_settings(&storage).await)
+}
+
+// Tauri command to turn redaction of outgoing messages on or off and replace its patterns;
+// `patterns` keeps the current list when omitted. Log scrubbing picks up new patterns at once.
+#[tauri::command]
+pub async fn set_redaction_settings(
+    state: State<'_, AppState>,
+    enabled: bool,
+    patterns: Option<Vec<SecretPattern>>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set redaction: enabled={} ({:?} patterns)", enabled, patterns.as_ref().map(Vec::len));
+    let redactor = match patterns.as_deref() {
+        Some(patterns) => {
+            if let Some(pattern) = patterns.iter().find(|p| p.name.trim().is_empty()) {
+                return Err(CommandError::validation(format!("Redaction pattern '{}' needs a name.", pattern.pattern)));
+            }
+            Some(redaction::Redactor::new(patterns).map_err(CommandError::validation)?)
+        }
+        None => None,
+    };
+
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::REDACTION_ENABLED_KEY, if enabled { "true" } else { "false" }).await
+        .map_err(|e| CommandError::storage(format!("Failed to save redaction setting: {}", e)))?;
+    if let (Some(patterns), Some(redactor)) = (patterns, redactor) {
+        let json = serde_json::to_string(&patterns)
+            .map_err(|e| CommandError::internal(format!("Failed to serialize redaction patterns: {}", e)))?;
+        storage.set_setting(config::REDACTION_PATTERNS_KEY, &json).await
+            .map_err(|e| CommandError::storage(format!("Failed to save redaction patterns: {}", e)))?;
+        crate::logs::set_pattern_redactor(redactor);
+    }
+    Ok(())
+}
+
+// Tauri command reporting the secrets the current patterns find in `text`, so the composer
+// can warn before sending
+#[tauri::command]
+pub async fn preview_redaction(state: State<'_, AppState>, text: String) -> Result<RedactionPreview, CommandError> {
+    let settings = {
+        let storage = state.storage.lock().await;
+        redaction::load_settings(&storage).await
+    };
+    Ok(redaction::preview(&settings, &text))
+}
+
+// Tauri command returning whether oversized stream deltas are paced, and at what size
+#[tauri::command]
+pub async fn get_stream_smoothing(state: State<'_, AppState>) -> Result<StreamSmoothing, CommandError> {
+    let storage = state.storage.lock().await;
+    Ok(load_stream_smoothing(&storage).await)
+}
+
+// Tauri command to turn stream smoothing on or off; `chars_per_event` keeps its value when omitted
+#[tauri::command]
+pub async fn set_stream_smoothing(
+    state: State<'_, AppState>,
+    enabled: bool,
+    chars_per_event: Option<usize>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set stream smoothing: {} ({:?} chars per event)", enabled, chars_per_event);
+    if chars_per_event.is_some_and(|chars| chars == 0 || chars > smoothing::MAX_CHARS_PER_EVENT) {
+        return Err(CommandError::validation(format!(
+            "Characters per event must be between 1 and {}.",
+            smoothing::MAX_CHARS_PER_EVENT
+        )));
+    }
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::STREAM_SMOOTHING_KEY, if enabled { "true" } else { "false" }).await
+        .map_err(|e| CommandError::storage(format!("Failed to save stream smoothing: {}", e)))?;
+    if let Some(chars) = chars_per_event {
+        storage.set_setting(config::STREAM_SMOOTHING_CHARS_KEY, &chars.to_string()).await
+            .map_err(|e| CommandError::storage(format!("Failed to save stream smoothing rate: {}", e)))?;
+    }
+    Ok(())
+}
+
+// Tauri command returning the folder prompt files are read from, if one is set
+#[tauri::command]
+pub async fn get_prompt_dir(state: State<'_, AppState>) -> Result<Option<String>, CommandError> {
+    let storage = state.storage.lock().await;
+    storage.get_setting(config::PROMPT_DIR_KEY).await
+        .map(|dir| dir.filter(|dir| !dir.trim().is_empty()))
+        .map_err(|e| CommandError::storage(format!("Failed to read prompt folder setting: {}", e)))
+}
+
+// Tauri command to set (or with None, clear) the prompt folder. The folder is read at once.
+#[tauri::command]
+pub async fn set_prompt_dir(state: State<'_, AppState>, dir: Option<String>) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set the prompt folder to {:?}", dir);
+    let dir = dir.map(|dir| dir.trim().to_string()).filter(|dir| !dir.is_empty());
+    if let Some(dir) = &dir {
+        if !std::path::Path::new(dir).is_dir() {
+            return Err(CommandError::validation(format!("{} is not a folder.", dir)));
+        }
+    }
+    {
+        let storage = state.storage.lock().await;
+        storage.set_setting(config::PROMPT_DIR_KEY, dir.as_deref().unwrap_or("")).await
+            .map_err(|e| CommandError::storage(format!("Failed to save prompt folder: {}", e)))?;
+    }
+    prompt_files::rescan(&state).await;
+    Ok(())
+}
+
+// Tauri command listing the prompt files for the slash-command palette, sorted by name
+#[tauri::command]
+pub async fn list_file_prompts(state: State<'_, AppState>) -> Result<Vec<FilePrompt>, CommandError> {
+    Ok(prompt_files::list(&state))
+}
+
+// Tauri command returning the text of a prompt file, for inserting into the composer
+#[tauri::command]
+pub async fn get_file_prompt(state: State<'_, AppState>, name: String) -> Result<String, CommandError> {
+    prompt_files::body(&state, &name).ok_or_else(|| CommandError::not_found(format!("Prompt '{}' not found", name)))
+}
+
+// Tauri command returning the OS light/dark theme, the user's override and the theme in effect
+#[tauri::command]
+pub async fn get_system_theme(state: State<'_, AppState>) -> Result<ThemeInfo, CommandError> {
+    let storage = state.storage.lock().await;
+    Ok(theme::theme_info(theme::load_preference(&storage).await))
+}
+
+// Tauri command returning the appearance override: "system", "light" or "dark"
+#[tauri::command]
+pub async fn get_theme_preference(state: State<'_, AppState>) -> Result<ThemePreference, CommandError> {
+    let storage = state.storage.lock().await;
+    Ok(theme::load_preference(&storage).await)
+}
+
+// Tauri command to save the appearance override and apply it to the windows at once.
+// Emits `theme_changed` and returns the new theme info.
+#[tauri::command]
+pub async fn set_theme_preference(state: State<'_, AppState>, preference: String) -> Result<ThemeInfo, CommandError> {
+    log::info!("Frontend requested to set the theme to {}", preference);
+    let preference = ThemePreference::parse(&preference).map_err(CommandError::validation)?;
+    {
+        let storage = state.storage.lock().await;
+        storage.set_setting(config::THEME_KEY, preference.as_str()).await
+            .map_err(|e| CommandError::storage(format!("Failed to save theme: {}", e)))?;
+    }
+    theme::apply(&state.app_handle, preference);
+    theme::emit_changed(&state.app_handle, preference);
+    Ok(theme::theme_info(preference))
+}
+
+// Tauri command returning the saved title bar style: "overlay", "transparent" or "visible"
+#[tauri::command]
+pub async fn get_titlebar_style(state: State<'_, AppState>) -> Result<TitleBarPreference, CommandError> {
+    let storage = state.storage.lock().await;
+    Ok(theme::load_titlebar_preference(&storage).await)
+}
+
+// Tauri command saving the title bar style and applying it to the main window. Returns
+// whether it was applied, which only happens on macOS; elsewhere the choice is just saved.
+#[tauri::command]
+pub async fn set_titlebar_style(state: State<'_, AppState>, style: String) -> Result<bool, CommandError> {
+    log::info!("Frontend requested title bar style {}", style);
+    let preference = TitleBarPreference::parse(&style).map_err(CommandError::validation)?;
+    {
+        let storage = state.storage.lock().await;
+        storage.set_setting(config::TITLEBAR_STYLE_KEY, preference.as_str()).await
+            .map_err(|e| CommandError::storage(format!("Failed to save title bar style: {}", e)))?;
+    }
+    theme::apply_titlebar(&state.app_handle, preference).map_err(CommandError::internal)
+}
+
+// Tauri command returning whether whitespace an answer starts with is dropped
+#[tauri::command]
+pub async fn get_trim_leading_whitespace(state: State<'_, AppState>) -> Result<bool, CommandError> {
+    let storage = state.storage.lock().await;
+    Ok(load_trim_leading_whitespace(&storage).await)
+}
+
+// Tauri command to turn dropping of leading whitespace-only deltas on or off
+#[tauri::command]
+pub async fn set_trim_leading_whitespace(state: State<'_, AppState>, enabled: bool) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set leading whitespace trimming: {}", enabled);
+    let storage = state.storage.lock().await;
+    storage.set_setting(config::TRIM_LEADING_WHITESPACE_KEY, if enabled { "true" } else { "false" }).await
+        .map_err(|e| CommandError::storage(format!("Failed to save leading whitespace setting: {}", e)))
+}
+
+// Tauri command reporting whether offline mode is on
+#[tauri::command]
+pub async fn get_offline_mode(state: State<'_, AppState>) -> Result<bool, CommandError> {
+    Ok(state.is_offline())
+}
+
+// Tauri command to turn offline mode on or off. While it is on, every request that would go
+// over the network fails with an `offlineMode` error; reading, searching and exporting still work.
+#[tauri::command]
+pub async fn set_offline_mode(state: State<'_, AppState>, enabled: bool) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set offline mode: {}", enabled);
+    {
+        let storage = state.storage.lock().await;
+        storage.set_setting(config::OFFLINE_MODE_KEY, if enabled { "true" } else { "false" }).await
+            .map_err(|e| CommandError::storage(format!("Failed to save offline mode setting: {}", e)))?;
+    }
+    state.set_offline(enabled);
+    Ok(())
+}
+
+// Tauri command reporting the automation API settings and whether it is listening
+#[tauri::command]
+pub async fn get_automation_settings(state: State<'_, AppState>) -> Result<automation::AutomationSettings, CommandError> {
+    let (enabled, port) = {
+        let storage = state.storage.lock().await;
+        automation::load_settings(&storage).await
+    };
+    Ok(automation::AutomationSettings { enabled, port, listening: automation::is_listening(&state) })
+}
+
+// Tauri command to turn the automation API on or off and choose its port; applied immediately
+#[tauri::command]
+pub async fn set_automation_settings(
+    state: State<'_, AppState>,
+    enabled: bool,
+    port: Option<u16>,
+) -> Result<automation::AutomationSettings, CommandError> {
+    log::info!("Frontend requested automation API enabled={} port={:?}", enabled, port);
+    if port.is_some_and(|port| port < 1024) {
+        return Err(CommandError::validation("Choose a port between 1024 and 65535."));
+    }
+    {
+        let storage = state.storage.lock().await;
+        storage.set_setting(config::AUTOMATION_ENABLED_KEY, if enabled { "true" } else { "false" }).await
+            .map_err(|e| CommandError::storage(format!("Failed to save automation setting: {}", e)))?;
+        if let Some(port) = port {
+            storage.set_setting(config::AUTOMATION_PORT_KEY, &port.to_string()).await
+                .map_err(|e| CommandError::storage(format!("Failed to save automation port: {}", e)))?;
+        }
+    }
+    automation::restart(&state).await.map_err(CommandError::internal)?;
+    get_automation_settings(state).await
+}
+
+// Tauri command returning the bearer token scripts must send, creating it on first use
+#[tauri::command]
+pub async fn get_automation_token() -> Result<String, CommandError> {
+    config::automation_token().map_err(|e| CommandError::api_key(e.to_string()))
+}
+
+// Tauri command replacing the automation token; a running listener switches to the new one
+#[tauri::command]
+pub async fn regenerate_automation_token(state: State<'_, AppState>) -> Result<String, CommandError> {
+    log::info!("Frontend requested a new automation token");
+    let token = config::regenerate_automation_token().map_err(|e| CommandError::api_key(e.to_string()))?;
+    if automation::is_listening(&state) {
+        automation::restart(&state).await.map_err(CommandError::internal)?;
+    }
+    Ok(token)
+}
+
+// --- Model Config Commands ---
+
+#[tauri::command]
+pub async fn list_model_configs(state: State<'_, AppState>) -> Result<Vec<ModelConfig>, CommandError> {
+    log::info!("Frontend requested to list model configs");
+    let storage = state.storage.lock().await;
+    let configs = storage.list_model_configs().await
+        .map_err(|e| CommandError::storage(format!("Failed to list model configs: {}", e)))?;
+    // Older configs may predate validation; surface problems without refusing to load them
+    for config in &configs {
+        if let Err(errors) = crate::api::validate_provider_options(&config.provider, config.provider_options.as_deref()) {
+            log::warn!("Model config '{}' has invalid provider_options: {}", config.name, errors.join("; "));
+        }
+    }
+    Ok(configs)
+}
+
+// Checks a config's provider_options before it is stored
+fn check_provider_options(config: &ModelConfig) -> Result<(), CommandError> {
+    crate::api::validate_provider_options(&config.provider, config.provider_options.as_deref())
+        .map_err(|errors| CommandError::validation(format!("Invalid provider options: {}", errors.join("; "))))
+}
+
+// Tauri command describing the provider_options keys a provider understands
+#[tauri::command]
+pub async fn get_provider_options_schema(provider: String) -> Result<Vec<ProviderOptionField>, CommandError> {
+    provider_options_schema(&provider).map_err(|e| CommandError::validation(e.to_string()))
+}
+
+// Tauri command checking pasted provider_options JSON against the provider's schema without
+// saving anything, for inline validation in the settings form
+#[tauri::command]
+pub async fn validate_provider_options(provider: String, provider_options: String) -> Result<ProviderOptionsCheck, CommandError> {
+    let provider_options = Some(provider_options.trim()).filter(|options| !options.is_empty());
+    Ok(ProviderOptionsCheck::run(&provider, provider_options))
+}
+
+// Tauri command returning the provider_options schema of every supported provider, so
+// settings forms can offer a provider picker without a round trip per provider
+#[tauri::command]
+pub async fn get_provider_options_schemas() -> Result<BTreeMap<&'static str, Vec<ProviderOptionField>>, CommandError> {
+    Ok(all_provider_options_schemas())
+}
+
+// Tauri command returning what each supported provider can do, keyed by provider name, as
+// declared by its implementation
+#[tauri::command]
+pub async fn get_provider_capabilities(state: State<'_, AppState>) -> Result<BTreeMap<&'static str, ProviderCapabilities>, CommandError> {
+    Ok(supported_providers()
+        .into_iter()
+        .filter_map(|provider| state.provider_named(provider).map(|implementation| (provider, implementation.capabilities())))
+        .collect())
+}
+
+// Tauri command adding the starter model configs (OpenAI, Anthropic, local Ollama) for
+// onboarding, skipping any whose name is taken. Returns the ones added.
+#[tauri::command]
+pub async fn create_starter_configs(state: State<'_, AppState>) -> Result<Vec<ModelConfig>, CommandError> {
+    log::info!("Frontend requested the starter model configs");
+    let storage = state.storage.lock().await;
+    storage.create_starter_configs().await
+        .map_err(|e| CommandError::storage(format!("Failed to add starter model configs: {}", e)))
+}
+
+#[tauri::command]
+pub async fn add_model_config(state: State<'_, AppState>, config: ModelConfig) -> Result<(), CommandError> {
+    log::info!("Frontend requested to add model config: {}", config.name);
+    // Basic validation (can add more)
+    if config.name.trim().is_empty() || config.api_url.trim().is_empty() || config.provider.trim().is_empty() {
+        return Err(CommandError::validation("Name, API URL, and Provider cannot be empty."));
+    }
+    check_provider_options(&config)?;
+    // The `config` object received already has a default ID generated by serde.
+    // Remove the redundant creation of `config_with_id`
+    // let config_with_id = ModelConfig { id: Uuid::new_v4(), ..config };
+
+    let storage = state.storage.lock().await;
+    // Use the received `config` directly
+    storage.add_model_config(&config).await
+        .map_err(|e| CommandError::storage(format!("Failed to add model config: {}", e)))
+}
+
+#[tauri::command]
+pub async fn update_model_config(state: State<'_, AppState>, config: ModelConfig) -> Result<(), CommandError> {
+    log::info!("Frontend requested to update model config: {}", config.id);
+    // Basic validation
+    if config.name.trim().is_empty() || config.api_url.trim().is_empty() || config.provider.trim().is_empty() {
+        return Err(CommandError::validation("Name, API URL, and Provider cannot be empty."));
+    }
+    check_provider_options(&config)?;
+
+    let storage = state.storage.lock().await;
+    storage.update_model_config(&config).await
+        .map_err(|e| CommandError::storage(format!("Failed to update model config: {}", e)))
+}
+
+// Tauri command to rename a model config. Keyring-stored keys are filed under the config
+// name, so the key is moved to the new name as part of the rename.
+#[tauri::command]
+pub async fn rename_model_config(
+    state: State<'_, AppState>,
+    config_id: String,
+    new_name: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to rename model config {} to '{}'", config_id, new_name);
+    let Ok(uuid) = Uuid::parse_str(&config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", config_id)));
+    };
+    let new_name = new_name.trim();
+    if new_name.is_empty() {
+        return Err(CommandError::validation("Model config name cannot be empty."));
+    }
+
+    let storage = state.storage.lock().await;
+    let model_config = get_model_config(&storage, uuid).await?;
+    if model_config.name == new_name {
+        return Ok(());
+    }
+
+    let uses_keyring = model_config.api_key_ref.as_deref() == Some("keyring");
+    let key_copied = uses_keyring
+        && config::copy_keyring_entry_for_rename(&model_config, new_name)
+            .map_err(|e| CommandError::api_key(format!("Failed to move API key to the new name: {}", e)))?;
+
+    if let Err(e) = storage.rename_model_config(uuid, new_name).await {
+        if key_copied {
+            // Undo the copy so the keyring doesn't keep a key under a name that was never used
+            let renamed = ModelConfig { name: new_name.to_string(), ..model_config.clone() };
+            if let Err(cleanup) = config::delete_keyring_entry(&renamed) {
+                log::warn!("Failed to remove copied keyring entry after failed rename: {}", cleanup);
+            }
+        }
+        return Err(CommandError::storage(format!("Failed to rename model config: {}", e)));
+    }
+
+    if key_copied {
+        if let Err(e) = config::delete_keyring_entry(&model_config) {
+            log::warn!("Renamed model config but failed to remove the old keyring entry: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn delete_model_config(state: State<'_, AppState>, config_id: String) -> Result<(), CommandError> {
+    log::warn!("Frontend requested to delete model config ID: {}", config_id);
+    
+    let Ok(uuid) = Uuid::parse_str(&config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", config_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    storage.delete_model_config(uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to delete model config: {}", e)))
+}
+
+// --- Persona Commands ---
+
+#[tauri::command]
+pub async fn list_personas(state: State<'_, AppState>) -> Result<Vec<Persona>, CommandError> {
+    log::info!("Frontend requested to list personas");
+    let storage = state.storage.lock().await;
+    storage.list_personas().await
+        .map_err(|e| CommandError::storage(format!("Failed to list personas: {}", e)))
+}
+
+// Checks a persona before it is stored: a name, params that are a JSON object and, when it
+// names a default config, params that config's provider accepts
+async fn check_persona(storage: &StorageManager, persona: &Persona) -> Result<(), CommandError> {
+    if persona.name.trim().is_empty() {
+        return Err(CommandError::validation("Persona name cannot be empty."));
+    }
+    let params = parse_persona_params(persona.params.as_deref())?;
+    if let Some(config_id) = persona.model_config_id {
+        let model_config = get_model_config(storage, config_id).await?;
+        crate::api::validate_provider_options(&model_config.provider, Some(&serde_json::Value::Object(params).to_string()))
+            .map_err(|errors| CommandError::validation(format!("Invalid persona params: {}", errors.join("; "))))?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn add_persona(state: State<'_, AppState>, persona: Persona) -> Result<Persona, CommandError> {
+    log::info!("Frontend requested to add persona: {}", persona.name);
+    let persona = Persona { name: persona.name.trim().to_string(), ..persona };
+    let storage = state.storage.lock().await;
+    check_persona(&storage, &persona).await?;
+    storage.add_persona(&persona).await
+        .map_err(|e| CommandError::storage(format!("Failed to add persona: {}", e)))?;
+    Ok(persona)
+}
+
+#[tauri::command]
+pub async fn update_persona(state: State<'_, AppState>, persona: Persona) -> Result<(), CommandError> {
+    log::info!("Frontend requested to update persona: {}", persona.id);
+    let persona = Persona { name: persona.name.trim().to_string(), ..persona };
+    let storage = state.storage.lock().await;
+    check_persona(&storage, &persona).await?;
+    storage.update_persona(&persona).await
+        .map_err(|e| CommandError::storage(format!("Failed to update persona: {}", e)))
+}
+
+// Tauri command to delete a persona. Conversations using it go back to having none.
+#[tauri::command]
+pub async fn delete_persona(state: State<'_, AppState>, persona_id: String) -> Result<(), CommandError> {
+    log::warn!("Frontend requested to delete persona ID: {}", persona_id);
+    let Ok(uuid) = Uuid::parse_str(&persona_id) else {
+        return Err(CommandError::validation(format!("Invalid persona ID format: {}", persona_id)));
+    };
+    let storage = state.storage.lock().await;
+    let cleared = storage.delete_persona(uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to delete persona: {}", e)))?;
+    for conversation_id in cleared {
+        state.notify_conversation_updated(conversation_id);
+    }
+    Ok(())
+}
+
+// Tauri command for the settings screen: re-runs the health check and emits `health_report`.
+// `probe_endpoints` (default true) also sends an unbilled `GET /models` to each reachable config.
+#[tauri::command]
+pub async fn run_health_check(
+    state: State<'_, AppState>,
+    probe_endpoints: Option<bool>,
+) -> Result<HealthReport, CommandError> {
+    log::info!("Frontend requested a health check");
+    health::run_health_check(state.inner(), probe_endpoints.unwrap_or(true)).await.map_err(CommandError::storage)
+}
+
+// Tauri command timing `requests` (default 2, at most 5) back-to-back `GET /models` requests
+// to a config's endpoint, to tell whether keep-alive lets later requests skip the handshake
+#[tauri::command]
+pub async fn get_connection_diagnostics(
+    state: State<'_, AppState>,
+    model_config_id: String,
+    requests: Option<u32>,
+) -> Result<ConnectionDiagnostics, CommandError> {
+    log::info!("Frontend requested connection diagnostics for model config {}", model_config_id);
+    let Ok(config_uuid) = Uuid::parse_str(&model_config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", model_config_id)));
+    };
+    let requests = requests.unwrap_or(health::DEFAULT_DIAGNOSTIC_REQUESTS);
+    if !(2..=health::MAX_DIAGNOSTIC_REQUESTS).contains(&requests) {
+        return Err(CommandError::validation(format!(
+            "Connection diagnostics send between 2 and {} requests.",
+            health::MAX_DIAGNOSTIC_REQUESTS
+        )));
+    }
+    let model_config = {
+        let storage = state.storage.lock().await;
+        get_model_config(&storage, config_uuid).await?
+    };
+    health::connection_diagnostics(&state, &model_config, requests).await.map_err(CommandError::provider)
+}
+
+// Tauri command writing a zip of logs, redacted configs and health/integrity results to `path`,
+// for attaching to bug reports. Contains no message content or key material.
+#[tauri::command]
+pub async fn export_diagnostics(state: State<'_, AppState>, path: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested a diagnostics export to {}", path);
+    let app_version = state.app_handle.package_info().version.to_string();
+    let log_dir = state.app_handle.path().app_log_dir().ok();
+    let files = diagnostics::collect(state.inner(), &app_version, log_dir.as_deref()).await.map_err(CommandError::internal)?;
+    let archive = diagnostics::zip(&files)
+        .map_err(|e| CommandError::internal(format!("Failed to build diagnostics archive: {}", e)))?;
+    tokio::fs::write(&path, archive).await
+        .map_err(|e| CommandError::internal(format!("Failed to write {}: {}", path, e)))
+}
+
+// Tauri command returning a snapshot of the app's effective configuration (schema version,
+// row counts, database path, keyring availability, providers) for support. No secrets.
+#[tauri::command]
+pub async fn get_diagnostics(state: State<'_, AppState>) -> Result<diagnostics::DiagnosticsSnapshot, CommandError> {
+    log::info!("Frontend requested a diagnostics snapshot");
+    let app_version = state.app_handle.package_info().version.to_string();
+    diagnostics::snapshot(state.inner(), &app_version).await.map_err(CommandError::storage)
+}
+
+// Tauri command returning version, build and database information for the About dialog
+#[tauri::command]
+pub async fn get_app_info(state: State<'_, AppState>) -> Result<about::AppInfo, CommandError> {
+    Ok(about::app_info(state.inner()).await)
+}
+
+// Tauri command comparing the running version with the release manifest at the
+// `update_manifest_url` setting. Reports `disabled` when no URL is set; never downloads anything.
+#[tauri::command]
+pub async fn check_for_updates(state: State<'_, AppState>) -> Result<about::UpdateCheck, CommandError> {
+    log::info!("Frontend requested an update check");
+    state.ensure_online()?;
+    about::check_for_updates(state.inner()).await.map_err(CommandError::internal)
+}
+
+// Tauri command to report where a config's API key comes from, without revealing it
+#[tauri::command]
+pub async fn check_api_key(state: State<'_, AppState>, config_id: String) -> Result<config::ApiKeyStatus, CommandError> {
+    log::info!("Frontend requested API key check for model config {}", config_id);
+    let Ok(uuid) = Uuid::parse_str(&config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", config_id)));
+    };
+
+    let model_config = {
+        let storage = state.storage.lock().await;
+        get_model_config(&storage, uuid).await?
+    };
+    Ok(config::check_api_key(&model_config))
+}
+
+// Tauri command to move a config's env-var API key into the OS keyring.
+// The stored ref only switches to "keyring" once the key reads back correctly.
+#[tauri::command]
+pub async fn migrate_api_key_to_keyring(state: State<'_, AppState>, config_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested keyring migration for model config {}", config_id);
+    let Ok(uuid) = Uuid::parse_str(&config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", config_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    let model_config = get_model_config(&storage, uuid).await?;
+    let Some(env_var_name) = model_config.api_key_ref.as_deref().and_then(|r| r.strip_prefix("env:")) else {
+        return Err(CommandError::validation(format!(
+            "Model config '{}' does not read its API key from an environment variable.",
+            model_config.name
+        )));
+    };
+    if std::env::var(env_var_name).map(|v| v.is_empty()).unwrap_or(true) {
+        return Err(CommandError::api_key(format!("Environment variable '{}' is not set; nothing to migrate.", env_var_name)));
+    }
+
+    let api_key = config::get_api_key(&model_config)
+        .map_err(|e| CommandError::api_key(format!("Failed to get API key: {}", e)))?;
+    config::set_api_key_in_keyring(&model_config, &api_key)
+        .map_err(|e| CommandError::api_key(format!("Failed to store API key in keyring: {}", e)))?;
+
+    let migrated_config = ModelConfig {
+        api_key_ref: Some("keyring".to_string()),
+        ..model_config
+    };
+    match config::get_api_key(&migrated_config) {
+        Ok(stored) if stored == api_key => {}
+        Ok(_) => return Err(CommandError::api_key("API key read back from the keyring does not match; config left unchanged.")),
+        Err(e) => return Err(CommandError::api_key(format!("Failed to read API key back from keyring; config left unchanged: {}", e))),
+    }
+
+    storage.update_model_config(&migrated_config).await
+        .map_err(|e| CommandError::storage(format!("Failed to update model config: {}", e)))
+}
+
+// Tauri command to persist the user's ordering of the model list
+#[tauri::command]
+pub async fn reorder_model_configs(state: State<'_, AppState>, ordered_ids: Vec<String>) -> Result<(), CommandError> {
+    log::info!("Frontend requested to reorder {} model configs", ordered_ids.len());
+    let ordered_ids = ordered_ids
+        .iter()
+        .map(|id| Uuid::parse_str(id).map_err(|_| CommandError::validation(format!("Invalid model config ID format: {}", id))))
+        .collect::<Result<Vec<Uuid>, CommandError>>()?;
+
+    let storage = state.storage.lock().await;
+    storage.reorder_model_configs(&ordered_ids).await
+        .map_err(|e| CommandError::storage(format!("Failed to reorder model configs: {}", e)))
+}
+
+// Tauri command to choose the model config new conversations start with
+#[tauri::command]
+pub async fn set_default_model_config(state: State<'_, AppState>, config_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set default model config: {}", config_id);
+    let Ok(uuid) = Uuid::parse_str(&config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", config_id)));
+    };
+
+    let storage = state.storage.lock().await;
+    storage.set_default_model_config(uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to set default model config: {}", e)))
+}
+
+// Tauri command for advanced users to call a provider-specific endpoint
+// (e.g. `models/{id}`) relative to a config's base URL with its resolved auth
+#[tauri::command]
+pub async fn provider_raw_request(
+    state: State<'_, AppState>,
+    config_id: String,
+    method: String,
+    path: String,
+    body: Option<serde_json::Value>,
+) -> Result<RawResponse, CommandError> {
+    log::info!("Frontend requested raw {} request to '{}' for model config {}", method, path, config_id);
+
+    let Ok(config_uuid) = Uuid::parse_str(&config_id) else {
+        return Err(CommandError::validation(format!("Invalid model config ID format: {}", config_id)));
+    };
+    let method = RawMethod::parse(&method).map_err(|e| CommandError::validation(e.to_string()))?;
+
+    let model_config = {
+        let storage = state.storage.lock().await;
+        get_model_config(&storage, config_uuid).await?
+    };
+    let api_key = config::get_api_key(&model_config)
+        .map_err(|e| CommandError::api_key(format!("Failed to get API key: {}", e)))?;
+
+    state.provider_for(&model_config)?
+        .send_raw_request(&model_config, &api_key, method, &path, body)
+        .await
+        .map_err(|e| CommandError::provider(format!("Raw request failed: {}", e)))
+}
+
+// Tauri command to switch to another SQLite library file (e.g. separate work and
+// personal chats). The new database is opened and migrated before the swap, so a
+// bad path leaves the current library untouched.
+#[tauri::command]
+pub async fn open_library(state: State<'_, AppState>, path: String) -> Result<(), CommandError> {
+    log::warn!("Frontend requested to open library at: {}", path);
+
+    if path.trim().is_empty() {
+        return Err(CommandError::validation("Library path cannot be empty."));
+    }
+
+    let new_storage = StorageManager::open(std::path::Path::new(path.trim())).await
+        .map_err(|e| CommandError::storage(format!("Failed to open library: {}", e)))?;
+    new_storage.add_default_model_config_if_none().await
+        .map_err(|e| CommandError::storage(format!("Failed to initialize library: {}", e)))?;
+
+    // Holding the lock for the check and the swap blocks every other storage access until
+    // they complete, so no new work can start against the old library in between
+    let old_storage = {
+        let mut storage = state.storage.lock().await;
+        if state.has_background_work() {
+            drop(storage);
+            new_storage.close().await;
+            return Err(CommandError::validation(
+                "Cannot switch libraries while responses, summaries or titles are still being generated.",
+            ));
+        }
+        std::mem::replace(&mut *storage, new_storage)
+    };
+    old_storage.close().await;
+    log::info!("Switched library from {} to {}", old_storage.db_path().display(), path.trim());
+    // Each library has its own prompt folder setting
+    prompt_files::rescan(&state).await;
+
+    if let Err(e) = state.app_handle.emit("library_changed", serde_json::json!({ "path": path.trim() })) {
+        log::error!("Failed to emit library_changed event: {:?}", e);
+    }
+    Ok(())
+}
+
+// TODO: Commands for getting/setting API keys via keyring
+
+// Tauri command stopping a conversation's background work: queued or running title and
+// summary requests, title tasks between steps, and pending retries. Streams are stopped
+// separately with `stop_generation`.
+#[tauri::command]
+pub async fn cancel_conversation_tasks(state: State<'_, AppState>, conversation_id: String) -> Result<(), CommandError> {
+    log::warn!("Frontend requested to cancel background tasks of conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    state.utility_queue.cancel_conversation(conv_uuid);
+    let storage = state.storage.lock().await;
+    storage.delete_conversation_jobs(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to drop pending jobs: {}", e)))
+}
+
+// Tauri command to signal stopping a specific stream
+#[tauri::command]
+pub async fn stop_generation(state: State<'_, AppState>, message_id: String) -> Result<(), CommandError> {
+    log::warn!("Frontend requested to stop generation for message ID: {}", message_id);
+    
+    let Ok(msg_uuid) = Uuid::parse_str(&message_id) else {
+        let err_msg = format!("Invalid message ID format for stop: {}", message_id);
+        log::error!("{}", err_msg);
+        return Err(CommandError::validation(err_msg));
+    };
+
+    // Add the message ID to the cancellation map
+    state.cancelled_streams.insert(msg_uuid, true);
+    log::info!("Cancellation signal set for message ID: {}", msg_uuid);
+
+    Ok(())
+}
+
+// Tauri command stopping every generation streaming in a conversation, for callers that
+// don't know the assistant message IDs. Returns the IDs of the messages it stopped.
+#[tauri::command]
+pub async fn stop_conversation_generation(state: State<'_, AppState>, conversation_id: String) -> Result<Vec<String>, CommandError> {
+    log::warn!("Frontend requested to stop generation in conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let message_ids: Vec<Uuid> = state.active_streams.iter()
+        .filter(|stream| stream.conversation_id == conv_uuid)
+        .map(|stream| stream.message_id)
+        .collect();
+    for message_id in &message_ids {
+        state.cancelled_streams.insert(*message_id, true);
+    }
+    log::info!("Cancellation signal set for {} stream(s) of conversation {}", message_ids.len(), conv_uuid);
+    Ok(message_ids.iter().map(Uuid::to_string).collect())
+}
+
+// Tauri command returning every generation still streaming, so a reloaded
+// frontend can seed partial bubbles and resume applying chunks after `seq`
+#[tauri::command]
+pub async fn get_active_streams(state: State<'_, AppState>) -> Result<Vec<ActiveStream>, CommandError> {
+    log::info!("Frontend requested active streams");
+    let streams = state
+        .active_streams
+        .iter()
+        .map(|entry| entry.value().clone())
+        .collect();
+    Ok(streams)
+}
+
+// Tauri command routing a conversation's stream events to `window_label`. Windows call
+// it when they open a conversation and `unsubscribe_conversation` when they leave it.
+#[tauri::command]
+pub async fn subscribe_conversation(
+    state: State<'_, AppState>,
+    window_label: String,
+    conversation_id: String,
+) -> Result<(), CommandError> {
+    log::debug!("Window '{}' subscribed to conversation {}", window_label, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    state.subscribe_window(&window_label, conv_uuid);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn unsubscribe_conversation(
+    state: State<'_, AppState>,
+    window_label: String,
+    conversation_id: String,
+) -> Result<(), CommandError> {
+    log::debug!("Window '{}' unsubscribed from conversation {}", window_label, conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    state.unsubscribe_window(&window_label, conv_uuid);
+    Ok(())
+}
+
+// Tauri command returning the newest error log records (oldest first), for support requests.
+// `limit` keeps only the last N.
+#[tauri::command]
+pub async fn get_recent_errors(state: State<'_, AppState>, limit: Option<usize>) -> Result<Vec<RecentError>, CommandError> {
+    log::info!("Frontend requested recent errors (limit {:?})", limit);
+    let mut errors = state.recent_errors.snapshot();
+    if let Some(limit) = limit {
+        errors.drain(..errors.len().saturating_sub(limit));
+    }
+    Ok(errors)
+}
+
+// Tauri command returning the cached summary standing in for a long conversation's
+// oldest messages, if one has been made
+#[tauri::command]
+pub async fn get_conversation_memory(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<Option<ConversationMemory>, CommandError> {
+    log::info!("Frontend requested memory of conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    storage.get_conversation_memory(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to read conversation memory: {}", e)))
+}
+
+// Tauri command dropping a conversation's summary; the next request rebuilds it
+#[tauri::command]
+pub async fn clear_conversation_memory(state: State<'_, AppState>, conversation_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to clear memory of conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    storage.clear_conversation_memory(conv_uuid).await
+        .map(|_| ())
+        .map_err(|e| CommandError::storage(format!("Failed to clear conversation memory: {}", e)))
+}
+
+// Tauri command streaming a catch-up summary of a conversation as `summary_chunk` events,
+// ending with `summary_finished`. Written by `model_config_id`, else the utility model, else
+// the conversation's own model. Nothing is saved. Returns the summary ID, which
+// `stop_generation` accepts to cancel it.
+#[tauri::command]
+pub async fn summarize_conversation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    model_config_id: Option<String>,
+) -> Result<String, CommandError> {
+    log::info!("Frontend requested a summary of conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    state.ensure_online()?;
+    let work = state.begin_background_work();
+    let (summarizer, stored) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to load conversation: {}", e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        let summarizer = match model_config_id.as_deref().filter(|id| !id.is_empty()) {
+            Some(id) => {
+                let Ok(config_uuid) = Uuid::parse_str(id) else {
+                    return Err(CommandError::validation(format!("Invalid model config ID format: {}", id)));
+                };
+                get_request_model_config(&storage, config_uuid).await?
+            }
+            None => match load_summarizer(&storage).await {
+                Some(utility) => utility,
+                None => get_request_model_config(&storage, conversation.model_config_id).await?,
+            },
+        };
+        let stored = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to load messages: {}", e)))?;
+        (summarizer, stored)
+    };
+    let history = prompt::filter_history(state.with_ephemeral_messages(conv_uuid, stored));
+    if history.is_empty() {
+        return Err(CommandError::validation("The conversation has no messages to summarize."));
+    }
+
+    let summary_id = Uuid::new_v4();
+    state.spawn_background(work, memory::stream_catch_up_summary(state.inner().clone(), conv_uuid, summary_id, history, summarizer));
+    Ok(summary_id.to_string())
+}
+
+// Tauri command saving the conversation's current messages as a snapshot to restore later.
+// `label` defaults to the current date and time.
+#[tauri::command]
+pub async fn create_conversation_snapshot(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    label: Option<String>,
+) -> Result<ConversationSnapshot, CommandError> {
+    log::info!("Frontend requested a snapshot of conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let label = label
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .unwrap_or_else(|| chrono::Local::now().format("%Y-%m-%d %H:%M").to_string());
+
+    let storage = state.storage.lock().await;
+    let conversation = storage.get_conversation(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+        .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+    if conversation.ephemeral {
+        return Err(CommandError::validation("Snapshots are not available in ephemeral conversations."));
+    }
+    storage.create_conversation_snapshot(conv_uuid, &label).await
+        .map_err(|e| CommandError::storage(format!("Failed to save snapshot: {}", e)))
+}
+
+// Tauri command listing a conversation's snapshots, newest first
+#[tauri::command]
+pub async fn list_conversation_snapshots(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<Vec<ConversationSnapshot>, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    storage.list_conversation_snapshots(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to list snapshots: {}", e)))
+}
+
+// Tauri command replacing a conversation's messages with a snapshot's. The snapshot itself is
+// kept, so it can be restored again.
+#[tauri::command]
+pub async fn restore_conversation_snapshot(state: State<'_, AppState>, snapshot_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to restore snapshot {}", snapshot_id);
+    let Ok(snapshot_uuid) = Uuid::parse_str(&snapshot_id) else {
+        return Err(CommandError::validation(format!("Invalid snapshot ID format: {}", snapshot_id)));
+    };
+    let storage = state.storage.lock().await;
+    let snapshot = storage.get_conversation_snapshot(snapshot_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to read snapshot: {}", e)))?
+        .ok_or_else(|| CommandError::not_found(format!("Snapshot {} not found", snapshot_id)))?;
+    let conversation = storage.get_conversation(snapshot.conversation_id).await
+        .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", snapshot.conversation_id, e)))?
+        .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", snapshot.conversation_id)))?;
+    ensure_unlocked(&conversation)?;
+    if state.active_streams.iter().any(|stream| stream.conversation_id == conversation.id) {
+        return Err(CommandError::validation("Stop the running response before restoring a snapshot."));
+    }
+
+    storage.restore_conversation_snapshot(snapshot_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to restore snapshot: {}", e)))?;
+    state.notify_conversation_updated(conversation.id);
+    Ok(())
+}
+
+// Tauri command deleting a snapshot
+#[tauri::command]
+pub async fn delete_conversation_snapshot(state: State<'_, AppState>, snapshot_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to delete snapshot {}", snapshot_id);
+    let Ok(snapshot_uuid) = Uuid::parse_str(&snapshot_id) else {
+        return Err(CommandError::validation(format!("Invalid snapshot ID format: {}", snapshot_id)));
+    };
+    let storage = state.storage.lock().await;
+    let deleted = storage.delete_conversation_snapshot(snapshot_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to delete snapshot: {}", e)))?;
+    if !deleted {
+        return Err(CommandError::not_found(format!("Snapshot {} not found", snapshot_id)));
+    }
+    Ok(())
+}
+
+// Default and largest page of `get_conversation_history`
+const DEFAULT_HISTORY_LIMIT: u32 = 50;
+const MAX_HISTORY_LIMIT: u32 = 500;
+
+// Tauri command listing a conversation's recorded changes (renames, model switches, message
+// deletes and edits), newest first
+#[tauri::command]
+pub async fn get_conversation_history(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    limit: Option<u32>,
+) -> Result<Vec<ConversationEvent>, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let limit = limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT);
+    let storage = state.storage.lock().await;
+    storage.get_conversation_events(conv_uuid, limit).await
+        .map_err(|e| CommandError::storage(format!("Failed to load conversation history: {}", e)))
+}
+
+// Tauri command reversing the conversation's most recent change that hasn't been undone.
+// Renames, model switches and message deletes can be undone; anything else, or a change
+// that later edits have made ambiguous, is refused with a validation error.
+#[tauri::command]
+pub async fn undo_last_event(state: State<'_, AppState>, conversation_id: String) -> Result<ConversationEvent, CommandError> {
+    log::info!("Frontend requested to undo the last change to conversation {}", conversation_id);
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let storage = state.storage.lock().await;
+    let outcome = storage.undo_last_event(conv_uuid).await
+        .map_err(|e| CommandError::storage(format!("Failed to undo the last change: {}", e)))?;
+    drop(storage);
+    match outcome {
+        UndoOutcome::Undone(event) => {
+            state.notify_conversation_updated(conv_uuid);
+            Ok(event)
+        }
+        UndoOutcome::NothingToUndo => Err(CommandError::validation("There is nothing to undo in this conversation.")),
+        UndoOutcome::Refused(reason) => Err(CommandError::validation(format!("Can't undo: {}", reason))),
+    }
+}
+
+// Tauri command answering an `assistant_tool_request`. With `remember`, the answer is
+// stored as the tool's policy for that conversation so it isn't asked again.
+#[tauri::command]
+pub async fn respond_tool_permission(
+    state: State<'_, AppState>,
+    request_id: String,
+    allow: bool,
+    remember: Option<bool>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend answered tool request {}: allow={}", request_id, allow);
+    let Ok(request_uuid) = Uuid::parse_str(&request_id) else {
+        return Err(CommandError::validation(format!("Invalid request ID format: {}", request_id)));
+    };
+    let Some((_, pending)) = state.pending_tool_requests.remove(&request_uuid) else {
+        return Err(CommandError::validation(format!("Tool request {} is no longer pending", request_id)));
+    };
+
+    if remember.unwrap_or(false) {
+        let policy = if allow { ToolPolicy::Allow } else { ToolPolicy::Deny };
+        let storage = state.storage.lock().await;
+        if let Err(e) = storage.set_tool_policy(&pending.tool_name, Some(pending.conversation_id), policy).await {
+            log::error!("Failed to remember policy for tool '{}': {:?}", pending.tool_name, e);
+        }
+    }
+
+    pending
+        .responder
+        .send(allow)
+        .map_err(|_| CommandError::cancelled(format!("Tool request {} is no longer waiting", request_id)))
+}
+
+// Tauri command listing stored tool policies
+#[tauri::command]
+pub async fn list_tool_permissions(state: State<'_, AppState>) -> Result<Vec<ToolPermission>, CommandError> {
+    log::info!("Frontend requested tool permissions");
+    let storage = state.storage.lock().await;
+    storage.list_tool_permissions().await
+        .map_err(|e| CommandError::storage(format!("Failed to list tool permissions: {}", e)))
+}
+
+// Tauri command setting a tool's policy ("allow", "deny" or "ask"), globally or for one
+// conversation. A None policy removes the entry so the tool falls back to the next level.
+#[tauri::command]
+pub async fn set_tool_permission(
+    state: State<'_, AppState>,
+    tool_name: String,
+    policy: Option<String>,
+    conversation_id: Option<String>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set policy for tool '{}': {:?}", tool_name, policy);
+    let conv_uuid = match conversation_id.as_deref() {
+        Some(id) => match Uuid::parse_str(id) {
+            Ok(uuid) => Some(uuid),
+            Err(_) => return Err(CommandError::validation(format!("Invalid conversation ID format: {}", id))),
+        },
+        None => None,
+    };
+    let storage = state.storage.lock().await;
+    match policy {
+        Some(policy) => {
+            let policy = ToolPolicy::parse(&policy).map_err(CommandError::validation)?;
+            storage.set_tool_policy(&tool_name, conv_uuid, policy).await
+        }
+        None => storage.delete_tool_policy(&tool_name, conv_uuid).await,
+    }
+    .map_err(|e| CommandError::storage(format!("Failed to update policy for tool '{}': {}", tool_name, e)))
+}
+
+// Command to regenerate the last assistant response. With `keep_previous` the old answer is
+// kept as an alternative (see `select_message_variant`) instead of being replaced.
+#[tauri::command]
+pub async fn regenerate_last_response(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    keep_previous: Option<bool>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to regenerate last response for conversation ID: {}", conversation_id);
+
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        let err_msg = format!("Invalid conversation ID format for regenerate: {}", conversation_id);
+        log::error!("{}", err_msg);
+        return Err(CommandError::validation(err_msg));
+    };
+
+    let work = state.begin_background_work();
+    let storage = state.storage.lock().await;
+    state.ensure_online()?;
+    enforce_budget(&state, &load_budget_status(&storage).await?)?;
+
+    // --- Get conversation history (up to last user message) ---
+    let messages = match storage.get_conversation_messages(conv_uuid).await {
+        Ok(msgs) => state.with_ephemeral_messages(conv_uuid, msgs),
+        Err(e) => return Err(CommandError::storage(format!("Failed to get messages for regenerate: {}", e))),
+    };
+
+    // Find the index of the last assistant message
+    let last_assistant_index = messages.iter().rposition(|m| m.role == "assistant" && !m.is_unselected_variant());
+
+    let Some(last_assistant_idx) = last_assistant_index else {
+        return Err(CommandError::validation("No previous assistant message found to regenerate."));
+    };
+
+    let last_assistant_message = &messages[last_assistant_idx];
+    let last_assistant_message_id = last_assistant_message.id;
+    let previous_variant_group = last_assistant_message.variant_group.unwrap_or(last_assistant_message_id);
+    // Kept so the finished answer can be compared against the one it replaces
+    let previous_content = last_assistant_message.content.clone();
+
+    // Get messages up to (but not including) the last assistant message
+    let history_for_api = prompt::filter_history(messages[..last_assistant_idx].to_vec()); // Clone the relevant part
+    let user_message_id = history_for_api.iter().rev().find(|m| m.role == "user").map(|m| m.id);
+
+    // Unless kept, the last assistant message is deleted by the generation once the new answer
+    // produces output, so a failed regeneration leaves the previous answer in place
+
+    // --- Get ModelConfig for this conversation ---
+    let conversation = match storage.get_conversation(conv_uuid).await { // Assuming get_conversation exists
+        Ok(Some(c)) => c,
+        Ok(None) => return Err(CommandError::not_found(format!("Conversation {} not found for regenerate", conversation_id))),
+        Err(e) => return Err(CommandError::storage(format!("Failed to get conversation {} for regenerate: {}", conversation_id, e))),
+    };
+    ensure_unlocked(&conversation)?;
+
+    let model_config = match get_conversation_model_config(&storage, &conversation).await {
+        Ok(mc) => mc,
+        Err(e) => return Err(CommandError::storage(format!("Failed to get model config for {}: {}", conversation_id, e))),
+    };
+
+    // Ephemeral answers are never stored, so there is nothing to group them with
+    let variant_group = Some(previous_variant_group).filter(|_| keep_previous.unwrap_or(false) && !conversation.ephemeral);
+
+    let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+    let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+    let request = GenerationRequest {
+        system_message: prompt::system_message(conv_uuid, system_prompt_content),
+        persona: prompt_settings.persona,
+        summarizer: load_summarizer(&storage).await,
+        smoothing: load_stream_smoothing(&storage).await,
+        trim_leading_whitespace: load_trim_leading_whitespace(&storage).await,
+        preview: false,
+        history: history_for_api,
+        conversation,
+        model_config,
+        kind: StreamKind::Regenerate,
+        user_message_id,
+        replaces: Some(ReplacedMessage {
+            message_id: last_assistant_message_id,
+            content: previous_content,
+            variant_group,
+        }),
+        extends: None,
+        comparison: None,
+    };
+
+    drop(storage); // Release lock before potentially long API call
+
+    // --- Trigger API call in background (same engine as send_message) ---
+    state.spawn_background(work, generation::run_generation(state.inner().clone(), request));
+
+    Ok(())
+}
+
+// Completion budget used for a continuation when the config sets no max_tokens
+const CONTINUATION_FALLBACK_MAX_TOKENS: u32 = 4096;
+
+// Instruction appended (not saved) when asking the model to extend a finished answer
+const CONTINUE_WRITING_PROMPT: &str = "Continue writing from exactly where your previous response ended. Do not repeat or summarize what you already wrote.";
+
+// Why the last assistant response is being continued
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContinuationKind {
+    Truncated, // Stopped at its token limit: resume mid-text with double the max_tokens
+    Expand, // Finished normally: ask the model to keep writing
+}
+
+// Command to continue the last assistant response when the model stopped at its token limit.
+// Re-sends the history with the partial answer appended and double the max_tokens,
+// then merges the continuation into the existing message.
+#[tauri::command]
+pub async fn continue_truncated_response(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to continue truncated response for conversation ID: {}", conversation_id);
+    start_continuation(state.inner(), conversation_id, ContinuationKind::Truncated).await
+}
+
+// Command to extend the last assistant response, however it finished ("continue writing").
+// The continuation streams into the same message and is appended to its stored content.
+#[tauri::command]
+pub async fn continue_response(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to continue writing for conversation ID: {}", conversation_id);
+    start_continuation(state.inner(), conversation_id, ContinuationKind::Expand).await
+}
+
+async fn start_continuation(state: &AppState, conversation_id: String, kind: ContinuationKind) -> Result<(), CommandError> {
+
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        let err_msg = format!("Invalid conversation ID format for continue: {}", conversation_id);
+        log::error!("{}", err_msg);
+        return Err(CommandError::validation(err_msg));
+    };
+
+    let work = state.begin_background_work();
+    let storage = state.storage.lock().await;
+
+    let conversation = match storage.get_conversation(conv_uuid).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return Err(CommandError::not_found(format!("Conversation {} not found for continue", conversation_id))),
+        Err(e) => return Err(CommandError::storage(format!("Failed to get conversation {} for continue: {}", conversation_id, e))),
+    };
+    ensure_unlocked(&conversation)?;
+    if conversation.ephemeral {
+        return Err(CommandError::validation("Continuing responses is not available in ephemeral conversations."));
+    }
+    state.ensure_online()?;
+    enforce_token_budget(state, &storage, &conversation, 0).await?;
+
+    let messages = match storage.get_conversation_messages(conv_uuid).await {
+        Ok(msgs) => msgs,
+        Err(e) => return Err(CommandError::storage(format!("Failed to get messages for continue: {}", e))),
+    };
+
+    let last_assistant_idx = continuation_target(&messages, kind)?;
+    let truncated_message = messages[last_assistant_idx].clone();
+    let user_message_id = messages[..last_assistant_idx].iter().rev().find(|m| m.role == "user").map(|m| m.id);
+
+    // History includes the partial answer so the model picks up where it stopped
+    let mut history_for_api = prompt::filter_history(messages[..=last_assistant_idx].to_vec());
+    if kind == ContinuationKind::Expand {
+        history_for_api.push(Message {
+            id: Uuid::nil(),
+            conversation_id: conv_uuid,
+            role: "user".to_string(),
+            content: CONTINUE_WRITING_PROMPT.to_string(),
+            timestamp: Utc::now(),
+            metadata: None,
+            name: None,
+            variant_group: None,
+        });
+    }
+
+    let model_config = match get_conversation_model_config(&storage, &conversation).await {
+        Ok(mc) => mc,
+        Err(e) => return Err(CommandError::storage(format!("Failed to get model config for {}: {}", conversation_id, e))),
+    };
+
+    let request_config = continuation_config(&model_config, kind)?;
+    // An expansion starts a new paragraph unless the message already ends with a line break
+    let separator = match kind {
+        ContinuationKind::Expand if !truncated_message.content.is_empty() && !truncated_message.content.ends_with('\n') => "\n\n",
+        _ => "",
+    };
+
+    let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+    let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+    let request = GenerationRequest {
+        system_message: prompt::system_message(conv_uuid, system_prompt_content),
+        persona: prompt_settings.persona,
+        summarizer: load_summarizer(&storage).await,
+        smoothing: load_stream_smoothing(&storage).await,
+        // A truncated answer resumes mid-text, where leading whitespace belongs to it
+        trim_leading_whitespace: kind == ContinuationKind::Expand && load_trim_leading_whitespace(&storage).await,
+        preview: false,
+        history: history_for_api,
+        conversation,
+        model_config: request_config,
+        kind: StreamKind::Continue,
+        user_message_id,
+        replaces: None,
+        extends: Some(ExtendedMessage { message: truncated_message, separator }),
+        comparison: None,
+    };
+
+    drop(storage); // Release lock before the API call
+
+    // --- Trigger API call in background (same engine as send_message) ---
+    log::info!("Continuing the last answer of conversation {} ({:?})", conversation_id, kind);
+    state.spawn_background(work, generation::run_generation(state.clone(), request));
+
+    Ok(())
+}
+
+// Index of the answer a continuation extends: the last assistant message, which must have
+// stopped at its token limit to be continued as truncated, and must not be a failed generation
+fn continuation_target(messages: &[Message], kind: ContinuationKind) -> Result<usize, CommandError> {
+    let Some(last_assistant_idx) = messages.iter().rposition(|m| m.role == "assistant") else {
+        return Err(CommandError::validation("No previous assistant message found to continue."));
+    };
+    let last_assistant = &messages[last_assistant_idx];
+    match (kind, last_assistant.finish_reason().as_deref()) {
+        (ContinuationKind::Truncated, Some("length")) | (ContinuationKind::Expand, _) => {}
+        (ContinuationKind::Truncated, other) => {
+            return Err(CommandError::validation(format!(
+                "The last response was not truncated (finish reason: {}).",
+                other.unwrap_or("unknown")
+            )));
+        }
+    }
+    if last_assistant.is_error() {
+        return Err(CommandError::validation("The last response failed; regenerate it instead of continuing."));
+    }
+    Ok(last_assistant_idx)
+}
+
+// The config a continuation is requested with: a truncated answer gets double the completion
+// budget for this single request
+fn continuation_config(model_config: &ModelConfig, kind: ContinuationKind) -> Result<ModelConfig, CommandError> {
+    let options = ParsedProviderOptions::from_config(model_config)
+        .map_err(|e| CommandError::validation(format!("Invalid provider options for {}: {}", model_config.name, e)))?;
+    match kind {
+        ContinuationKind::Truncated => {
+            let max_tokens = options
+                .max_tokens
+                .map(|tokens| tokens.saturating_mul(2))
+                .unwrap_or(CONTINUATION_FALLBACK_MAX_TOKENS);
+            model_config
+                .with_provider_option("max_tokens", serde_json::json!(max_tokens))
+                .map_err(|e| CommandError::validation(format!("Invalid provider options for {}: {}", model_config.name, e)))
+        }
+        ContinuationKind::Expand => Ok(model_config.clone()),
+    }
+}
+
+// Tauri command to generate a title for a conversation (runs in background)
+#[tauri::command]
+pub async fn generate_conversation_title(
+    state: State<'_, AppState>,
+    conversation_id: String, 
+    utility_model_config_id: Option<String>, // Defaults to the stored title model, then the utility model
+) -> Result<(), CommandError> {
+    log::info!(
+        "Received request to generate title for conv: {} using model: {:?}",
+        conversation_id,
+        utility_model_config_id
+    );
+    start_title_generation(&state, conversation_id, utility_model_config_id, None, None).await
+}
+
+// Tauri command the frontend calls after each answer finishes. Starts title generation only
+// when the conversation still has the default title and its user and assistant messages
+// have just reached the `title_after_messages` setting; returns whether it did.
+#[tauri::command]
+pub async fn maybe_generate_title(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    utility_model_config_id: Option<String>,
+) -> Result<bool, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let (conversation, messages, settings) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?;
+        (conversation, messages, title::load_settings(&storage).await)
+    };
+    let message_count = prompt::filter_history(messages)
+        .iter()
+        .filter(|m| m.role == "user" || m.role == "assistant")
+        .count();
+    if !title::is_due_auto_title(&conversation.title, message_count, settings.after_messages) {
+        return Ok(false);
+    }
+    log::info!("Conversation {} reached {} messages; generating its title", conversation_id, message_count);
+    start_title_generation(&state, conversation_id, utility_model_config_id, None, None).await?;
+    Ok(true)
+}
+
+// Tauri command to regenerate a title in a custom style, e.g. "prefix an emoji" or
+// "in German". `max_chars` defaults to the title length setting.
+#[tauri::command]
+pub async fn generate_title_with_instruction(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    utility_model_config_id: Option<String>,
+    instruction: String,
+    max_chars: Option<usize>,
+) -> Result<(), CommandError> {
+    log::info!("Received request to generate title for conv: {} with a custom instruction", conversation_id);
+    if max_chars.is_some_and(|max| max == 0 || max > title::TITLE_MAX_CHARS_LIMIT) {
+        return Err(CommandError::validation(format!("Title length must be between 1 and {} characters.", title::TITLE_MAX_CHARS_LIMIT)));
+    }
+    start_title_generation(&state, conversation_id, utility_model_config_id, Some(instruction), max_chars).await
+}
+
+// Tauri command returning the settings generated titles follow
+#[tauri::command]
+pub async fn get_title_settings(state: State<'_, AppState>) -> Result<TitleSettings, CommandError> {
+    let storage = state.storage.lock().await;
+    Ok(title::load_settings(&storage).await)
+}
+
+// Tauri command to change the length, casing (lowercase, sentence or title) and emoji prefix
+// of generated titles, and optionally the message count that triggers the automatic title.
+// Existing titles are left as they are.
+#[tauri::command]
+pub async fn set_title_settings(
+    state: State<'_, AppState>,
+    max_chars: usize,
+    style: String,
+    emoji: bool,
+    after_messages: Option<usize>,
+) -> Result<(), CommandError> {
+    log::info!("Frontend requested to set title settings: {} chars, {} style, emoji={}, after {:?} messages", max_chars, style, emoji, after_messages);
+    if max_chars == 0 || max_chars > title::TITLE_MAX_CHARS_LIMIT {
+        return Err(CommandError::validation(format!("Title length must be between 1 and {} characters.", title::TITLE_MAX_CHARS_LIMIT)));
+    }
+    if after_messages.is_some_and(|count| count == 0 || count > title::TITLE_AFTER_MESSAGES_LIMIT) {
+        return Err(CommandError::validation(format!(
+            "Automatic titles can follow between 1 and {} messages.",
+            title::TITLE_AFTER_MESSAGES_LIMIT
+        )));
+    }
+    let style = TitleCase::parse(&style).map_err(CommandError::validation)?;
+
+    let storage = state.storage.lock().await;
+    let after_messages = after_messages.map(|count| (config::TITLE_AFTER_MESSAGES_KEY, count.to_string()));
+    for (key, value) in [
+        (config::TITLE_MAX_CHARS_KEY, max_chars.to_string()),
+        (config::TITLE_STYLE_KEY, style.as_str().to_string()),
+        (config::TITLE_EMOJI_KEY, emoji.to_string()),
+    ].into_iter().chain(after_messages) {
+        storage.set_setting(key, &value).await
+            .map_err(|e| CommandError::storage(format!("Failed to save setting '{}': {}", key, e)))?;
+    }
+    Ok(())
+}
+
+// Validates the request and spawns the background title generation. `max_chars` overrides
+// the title length setting.
+async fn start_title_generation(
+    state: &AppState,
+    conversation_id: String,
+    utility_model_config_id: Option<String>,
+    instruction: Option<String>,
+    max_chars: Option<usize>,
+) -> Result<(), CommandError> {
+
+    // Parse IDs
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let explicit_model_uuid = match utility_model_config_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(id) => match Uuid::parse_str(id) {
+            Ok(uuid) => Some(uuid),
+            Err(_) => return Err(CommandError::validation(format!("Invalid utility model ID format: {}", id))),
+        },
+        None => None,
+    };
+
+    let work = state.begin_background_work();
+    // Ephemeral chats leave no trace, so they don't get a generated title either
+    let conversation = {
+        let storage = state.storage.lock().await;
+        match storage.get_conversation(conv_uuid).await {
+            Ok(Some(conversation)) => conversation,
+            Ok(None) => return Err(CommandError::not_found(format!("Conversation {} not found", conversation_id))),
+            Err(e) => return Err(CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e))),
+        }
+    };
+    if conversation.ephemeral {
+        log::info!("Skipping title generation for ephemeral conversation {}", conversation_id);
+        return Ok(());
+    }
+    let title_job = TitleJob {
+        utility_model_config_id: explicit_model_uuid,
+        instruction,
+        max_chars,
+    };
+
+    // Clone necessary state parts for the background task
+    let app_state_clone = state.clone();
+
+    // Spawn the actual generation logic in a separate task; transient failures are retried later
+    state.spawn_background(work, async move {
+        match generate_title(&app_state_clone, conv_uuid, title_job.clone()).await {
+            Ok(()) => {}
+            Err(JobFailure::Retry(error)) => {
+                let payload = serde_json::to_value(&title_job).unwrap_or_default();
+                jobs::enqueue(&app_state_clone, jobs::JOB_TITLE, conv_uuid, payload, &error).await;
+            }
+            Err(JobFailure::Drop(reason)) => {
+                log::info!("[Title Gen BG Task {}] No title generated: {}", conv_uuid, reason);
+            }
+        }
+    });
+
+    Ok(()) // Return immediately, task runs in background
+}
+
+// Generates and stores a title for a conversation from its first exchange. Runs for
+// `generate_conversation_title` and again for retries from the job queue.
+pub(crate) async fn generate_title(state: &AppState, conv_uuid: Uuid, title_job: TitleJob) -> Result<(), JobFailure> {
+    log::info!("[Title Gen BG Task {}] Started", conv_uuid);
+    // Checked between steps so `cancel_conversation_tasks` stops the task before it renames
+    let token = state.utility_queue.task_token(conv_uuid);
+    let check_cancelled = || {
+        if state.utility_queue.is_cancelled(&token) {
+            log::info!("[Title Gen BG Task {}] Cancelled", conv_uuid);
+            return Err(JobFailure::Drop("cancelled".to_string()));
+        }
+        Ok(())
+    };
+    let settings = {
+        let storage = state.storage.lock().await;
+        title::load_settings(&storage).await
+    };
+    let mut style = TitleStyle {
+        instruction: title_job.instruction,
+        max_chars: title_job.max_chars.unwrap_or(settings.max_chars),
+        ..TitleStyle::from_settings(&settings)
+    };
+
+    // --- Get the conversation and its messages (prompt + response) ---
+    let (conversation, messages) = {
+        let storage = state.storage.lock().await;
+        let conversation = match storage.get_conversation(conv_uuid).await {
+            Ok(Some(conversation)) => conversation,
+            Ok(None) => return Err(JobFailure::Drop("conversation not found".to_string())),
+            Err(e) => return Err(JobFailure::Retry(format!("Failed to get conversation: {}", e))),
+        };
+        match storage.get_conversation_messages(conv_uuid).await {
+            Ok(msgs) => (conversation, msgs),
+            Err(e) => {
+                log::error!("[Title Gen BG Task {}] Failed to get messages: {:?}", conv_uuid, e);
+                return Err(JobFailure::Retry(format!("Failed to get messages: {}", e)));
+            }
+        }
+    };
+
+    check_cancelled()?;
+
+    // We expect exactly two messages (user prompt, assistant response)
+    if messages.len() < 2 {
+        log::warn!("[Title Gen BG Task {}] Expected >= 2 messages, found {}. Skipping title generation.", conv_uuid, messages.len());
+        return Err(JobFailure::Drop(format!("expected at least 2 messages, found {}", messages.len())));
+    }
+    let user_prompt = &messages[0];
+    let assistant_response = &messages[1]; // Assuming the first two are user/assistant
+
+    // --- Language: the conversation's own, else detected from the first user message ---
+    style.language = match conversation.language.clone() {
+        Some(language) => Some(language),
+        None => {
+            let detected = language::detect_language(&user_prompt.content);
+            if let Some(code) = detected {
+                let storage = state.storage.lock().await;
+                match storage.set_conversation_language(conv_uuid, Some(code)).await {
+                    Ok(()) => state.notify_conversation_updated(conv_uuid),
+                    Err(e) => log::error!("[Title Gen BG Task {}] Failed to store detected language: {:?}", conv_uuid, e),
+                }
+            }
+            detected.map(str::to_string)
+        }
+    };
+
+    // Truncate content (simple character limit for now)
+    const MAX_CHARS: usize = 1000;
+    let truncated_prompt = user_prompt.content.chars().take(MAX_CHARS).collect::<String>();
+    let truncated_response = assistant_response.content.chars().take(MAX_CHARS).collect::<String>();
+
+    // --- Get Utility Model Config and API Key ---
+    check_cancelled()?;
+    let utility_model_config = {
+        let storage = state.storage.lock().await;
+        match resolve_title_model_config(&storage, &conversation, title_job.utility_model_config_id).await {
+            Ok(Some(mc)) => mc,
+            Ok(None) => {
+                log::warn!("[Title Gen BG Task {}] No title or utility model set, keeping default title", conv_uuid);
+                return Err(JobFailure::Drop("no title or utility model set".to_string()));
+            }
+            Err(e) => {
+                log::error!("[Title Gen BG Task {}] Failed to get utility model config: {}", conv_uuid, e);
+                return Err(JobFailure::Drop(format!("Failed to get utility model config: {}", e)));
+            }
+        }
+    };
+
+    let api_key = match config::get_api_key(&utility_model_config) {
+        Ok(key) => key,
+        Err(e) => {
+            log::error!("[Title Gen BG Task {}] Failed to get API key for utility model: {:?}", conv_uuid, e);
+            return Err(JobFailure::Drop(format!("Failed to get API key: {}", e)));
+        }
+    };
+
+    // --- Construct Prompt for Title Generation ---
+    let title_gen_system_prompt = title::title_system_prompt(&style);
+    let title_gen_user_prompt = format!(
+        "User: {}\nAssistant: {}\n\nTitle:",
+        truncated_prompt,
+        truncated_response
+    );
+
+    let mut title_gen_messages = vec![
+        Message { // System Prompt
+            id: Uuid::nil(), conversation_id: conv_uuid, role: "system".to_string(),
+            content: title_gen_system_prompt, timestamp: Utc::now(), metadata: None, name: None, variant_group: None,
+        },
+        Message { // User Prompt containing the exchange
+            id: Uuid::nil(), conversation_id: conv_uuid, role: "user".to_string(),
+            content: title_gen_user_prompt, timestamp: Utc::now(), metadata: None, name: None, variant_group: None,
+        },
+    ];
+
+    // --- Call Utility Model (Non-Streaming) ---
+    let api_provider = match state.provider_for(&utility_model_config) {
+        Ok(provider) => provider,
+        Err(e) => {
+            log::error!("[Title Gen BG Task {}] {}", conv_uuid, e);
+            // Offline mode is temporary; an unsupported provider is not
+            return Err(match e.kind {
+                ErrorKind::OfflineMode => JobFailure::Retry(e.message),
+                _ => JobFailure::Drop(e.message),
+            });
+        }
+    };
+    redaction::redact_outgoing(state, &mut title_gen_messages).await;
+    let title_request = api_provider.send_chat_request(&utility_model_config, &api_key, &title_gen_messages);
+    let Some(title_result) = state.utility_queue.run(conv_uuid, "title", title_request).await else {
+        return Err(JobFailure::Drop("cancelled while queued".to_string()));
+    };
+    let generated_title_raw = match title_result {
+        Ok(raw) => raw,
+        Err(e) => {
+            log::error!("[Title Gen BG Task {}] Utility model API call failed: {:?}", conv_uuid, e);
+            return Err(JobFailure::Retry(format!("Utility model request failed: {:#}", e)));
+        }
     };
 
-    // Add the message ID to the cancellation map
-    state.cancelled_streams.insert(msg_uuid, true);
-    log::info!("Cancellation signal set for message ID: {}", msg_uuid);
+    // --- Sanitize and Update Title ---
+    log::info!("[Title Gen BG Task {}] Raw generated title: '{}'", conv_uuid, generated_title_raw);
+    let Some(generated_title) = title::sanitize_title(&generated_title_raw, &style) else {
+        log::warn!("[Title Gen BG Task {}] Generated title invalid (empty or longer than {} characters). Keeping current title.", conv_uuid, style.max_chars);
+        return Err(JobFailure::Drop("generated title was empty or too long".to_string()));
+    };
+    log::info!("[Title Gen BG Task {}] Sanitized generated title: '{}'", conv_uuid, generated_title);
 
+    // Rename the conversation in storage
+    check_cancelled()?;
+    let storage = state.storage.lock().await;
+    match storage.rename_conversation(conv_uuid, generated_title.clone()).await {
+        Ok(_) => {
+            log::info!("[Title Gen BG Task {}] Successfully renamed conversation to '{}'", conv_uuid, generated_title);
+            state.notify_conversation_updated(conv_uuid);
+        }
+        Err(e) => {
+            log::error!("[Title Gen BG Task {}] Failed to rename conversation in storage: {:?}", conv_uuid, e);
+            return Err(JobFailure::Retry(format!("Failed to rename conversation: {}", e)));
+        }
+    }
+    log::info!("[Title Gen BG Task {}] Finished", conv_uuid);
     Ok(())
 }
 
-// Command to regenerate the last assistant response
+// Rebuilds a conversation's summary for the job queue, from the same inputs a request
+// would use now
+pub(crate) async fn refresh_conversation_summary(state: &AppState, conv_uuid: Uuid) -> Result<(), JobFailure> {
+    let (conversation, history, model_config, summarizer, system_message) = {
+        let storage = state.storage.lock().await;
+        let conversation = match storage.get_conversation(conv_uuid).await {
+            Ok(Some(conversation)) => conversation,
+            Ok(None) => return Err(JobFailure::Drop("conversation not found".to_string())),
+            Err(e) => return Err(JobFailure::Retry(format!("Failed to get conversation: {}", e))),
+        };
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| JobFailure::Retry(format!("Failed to get messages: {}", e)))?;
+        let model_config = get_conversation_model_config(&storage, &conversation).await
+            .map_err(|e| JobFailure::Drop(format!("Failed to get model config: {}", e)))?;
+        let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+        let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+        let system_message = prompt::system_message(conv_uuid, system_prompt_content);
+        let summarizer = load_summarizer(&storage).await;
+        (conversation, prompt::filter_history(messages), model_config, summarizer, system_message)
+    };
+    let summarizer = summarizer.as_ref().unwrap_or(&model_config);
+    memory::refresh_summary(state, &conversation, &system_message, &history, &model_config, summarizer)
+        .await
+        .map_err(JobFailure::Retry)
+}
+
+// Tauri command estimating the size of the next request for a conversation: what a send
+// would include now, after trimming or summarizing, against the model's context window.
+// Message estimates are cached in metadata the first time, so this is cheap to call often.
 #[tauri::command]
-pub async fn regenerate_last_response(
+pub async fn get_context_usage(
     state: State<'_, AppState>,
     conversation_id: String,
-) -> Result<(), String> {
-    log::info!("Frontend requested to regenerate last response for conversation ID: {}", conversation_id);
+) -> Result<prompt::ContextUsage, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let (conversation, history, model_config, system_message) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        let mut messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?;
+        let updates: Vec<(Uuid, Option<String>)> = messages
+            .iter_mut()
+            .filter_map(|m| prompt::cache_token_estimate(m).then(|| (m.id, m.metadata.clone())))
+            .collect();
+        if !updates.is_empty() {
+            if let Err(e) = storage.update_messages_metadata(&updates).await {
+                log::warn!("Failed to cache token estimates for conversation {}: {:?}", conv_uuid, e);
+            }
+        }
+        let messages = state.with_ephemeral_messages(conv_uuid, messages);
+        let model_config = get_conversation_model_config(&storage, &conversation).await?;
+        let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+        let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+        let system_message = prompt::system_message(conv_uuid, system_prompt_content);
+        (conversation, prompt::filter_history(messages), model_config, system_message)
+    };
+    Ok(memory::context_usage(&state, &conversation, &system_message, &history, &model_config).await)
+}
 
+// Tauri command estimating what sending `content` next would cost: the prompt at the config's
+// input price, and a worst case for the answer from max_tokens at the output price
+#[tauri::command]
+pub async fn estimate_message_cost(
+    state: State<'_, AppState>,
+    conversation_id: String,
+    content: String,
+) -> Result<usage::CostEstimate, CommandError> {
     let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
-        let err_msg = format!("Invalid conversation ID format for regenerate: {}", conversation_id);
-        log::error!("{}", err_msg);
-        return Err(err_msg);
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let (conversation, mut history, model_config, system_message) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?;
+        let messages = state.with_ephemeral_messages(conv_uuid, messages);
+        let model_config = get_conversation_model_config(&storage, &conversation).await?;
+        let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+        let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+        let system_message = prompt::system_message(conv_uuid, system_prompt_content);
+        (conversation, prompt::filter_history(messages), model_config, system_message)
+    };
+    history.push(Message {
+        id: Uuid::nil(), conversation_id: conv_uuid, role: "user".to_string(),
+        content, timestamp: Utc::now(), metadata: None, name: None, variant_group: None,
+    });
+    let context = memory::context_usage(&state, &conversation, &system_message, &history, &model_config).await;
+    let options = ParsedProviderOptions::from_config(&model_config).unwrap_or_default();
+    Ok(usage::estimate_cost(context.prompt_tokens, &options))
+}
+
+// Tauri command showing which messages the next request would include and which would be
+// dropped (or summarized) to fit the model's context window, with the estimated token count
+#[tauri::command]
+pub async fn preview_truncation(
+    state: State<'_, AppState>,
+    conversation_id: String,
+) -> Result<prompt::TruncationPreview, CommandError> {
+    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
+        return Err(CommandError::validation(format!("Invalid conversation ID format: {}", conversation_id)));
+    };
+    let (conversation, messages, model_config, system_message) = {
+        let storage = state.storage.lock().await;
+        let conversation = storage.get_conversation(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get conversation {}: {}", conversation_id, e)))?
+            .ok_or_else(|| CommandError::not_found(format!("Conversation {} not found", conversation_id)))?;
+        let messages = storage.get_conversation_messages(conv_uuid).await
+            .map_err(|e| CommandError::storage(format!("Failed to get messages for {}: {}", conversation_id, e)))?;
+        let messages = state.with_ephemeral_messages(conv_uuid, messages);
+        let model_config = get_conversation_model_config(&storage, &conversation).await?;
+        let prompt_settings = load_prompt_settings(&storage, &conversation).await;
+        let system_prompt_content = prompt::compose_system_prompt(&prompt_settings, &model_config, &conversation);
+        let system_message = prompt::system_message(conv_uuid, system_prompt_content);
+        (conversation, messages, model_config, system_message)
     };
+    let history = prompt::filter_history(messages.clone());
+    let (sent, usage, summarized) = memory::plan_request(&state, &conversation, &system_message, &history, &model_config).await;
+    let included_message_ids: Vec<Uuid> = sent.iter().map(|m| m.id).collect();
+    let excluded_message_ids = messages
+        .iter()
+        .map(|m| m.id)
+        .filter(|id| !included_message_ids.contains(id))
+        .collect();
+    Ok(prompt::TruncationPreview {
+        included_message_ids,
+        excluded_message_ids,
+        summarized: summarized && usage.omitted_messages > 0,
+        estimated_tokens: usage.prompt_tokens,
+        usage,
+    })
+}
 
+// Tauri command listing failed utility requests waiting to be retried
+#[tauri::command]
+pub async fn list_pending_jobs(state: State<'_, AppState>) -> Result<Vec<PendingJob>, CommandError> {
     let storage = state.storage.lock().await;
+    storage.list_pending_jobs().await
+        .map_err(|e| CommandError::storage(format!("Failed to list pending jobs: {}", e)))
+}
 
-    // --- Get conversation history (up to last user message) ---
-    let messages = match storage.get_conversation_messages(conv_uuid).await {
-        Ok(msgs) => msgs,
-        Err(e) => return Err(format!("Failed to get messages for regenerate: {}", e)),
+// Tauri command to drop a pending job so it is not retried
+#[tauri::command]
+pub async fn cancel_pending_job(state: State<'_, AppState>, job_id: String) -> Result<(), CommandError> {
+    log::info!("Frontend requested to cancel pending job {}", job_id);
+    let Ok(job_uuid) = Uuid::parse_str(&job_id) else {
+        return Err(CommandError::validation(format!("Invalid job ID format: {}", job_id)));
     };
+    let storage = state.storage.lock().await;
+    match storage.delete_job(job_uuid).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(CommandError::not_found(format!("Pending job {} not found", job_id))),
+        Err(e) => Err(CommandError::storage(format!("Failed to cancel pending job: {}", e))),
+    }
+}
 
-    // Find the index of the last assistant message
-    let last_assistant_index = messages.iter().rposition(|m| m.role == "assistant");
+// Add other commands later (create_conversation, get_messages, etc.) 
 
-    let Some(last_assistant_idx) = last_assistant_index else {
-        return Err("No previous assistant message found to regenerate.".to_string());
-    };
+// Tauri command to open a URL in the default browser
+#[tauri::command]
+pub async fn open_url(app_handle: tauri::AppHandle<crate::state::AppRuntime>, url: String) -> Result<(), CommandError> {
+     log::info!("Frontend requested to open URL: {}", url);
+     // Use the method from tauri-plugin-opener
+     match app_handle.opener().open_url(&url, None::<&str>) { // Use plugin method
+         Ok(_) => {
+             log::info!("Successfully opened URL: {}", url);
+             Ok(())
+         }
+         Err(e) => {
+             log::error!("Failed to open URL {}: {:?}", url, e);
+             Err(CommandError::internal(format!("Failed to open URL: {}", e)))
+         }
+     }
+}
 
-    let last_assistant_message = &messages[last_assistant_idx];
-    let last_assistant_message_id = last_assistant_message.id;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockProvider, MockStep};
+    use crate::test_support::{self, message, message_at, model_config, TestApp};
 
-    // Get messages up to (but not including) the last assistant message
-    let history_for_api = messages[..last_assistant_idx].to_vec(); // Clone the relevant part
+    // A conversation answered by a fresh model config whose key resolves
+    async fn conversation_for(app: &TestApp) -> Conversation {
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        let storage = app.state.storage.lock().await;
+        let conversation = test_support::conversation(&storage).await;
+        storage.update_conversation_model_id(conversation.id, model_config.id).await.unwrap();
+        Conversation { model_config_id: model_config.id, ..conversation }
+    }
 
-    // --- Delete the last assistant message ---
-    if let Err(e) = storage.delete_message(last_assistant_message_id).await { // Assuming delete_message exists
-        log::error!("Failed to delete previous assistant message {}: {:?}. Continuing regeneration anyway.", last_assistant_message_id, e);
-        // Decide if we should stop or continue if deletion fails. Let's continue for now.
-        // return Err(format!("Failed to delete previous assistant message: {}", e));
-    } else {
-        log::info!("Successfully deleted previous assistant message {}", last_assistant_message_id);
+    fn answer(conversation_id: Uuid, content: &str, finish_reason: &str) -> Message {
+        let mut answer = message(conversation_id, "assistant", content);
+        answer.set_metadata_field("finish_reason", serde_json::json!(finish_reason));
+        answer
     }
 
-    // --- Get ModelConfig for this conversation ---
-    let conversation = match storage.get_conversation(conv_uuid).await { // Assuming get_conversation exists
-        Ok(Some(c)) => c,
-        Ok(None) => return Err(format!("Conversation {} not found for regenerate", conversation_id)),
-        Err(e) => return Err(format!("Failed to get conversation {} for regenerate: {}", conversation_id, e)),
-    };
+    #[test]
+    fn only_answers_cut_off_at_the_token_limit_continue_as_truncated() {
+        let conversation_id = Uuid::new_v4();
+        let truncated = vec![message(conversation_id, "user", "Tell me"), answer(conversation_id, "Once upon", "length")];
+        assert_eq!(continuation_target(&truncated, ContinuationKind::Truncated).unwrap(), 1);
 
-    let model_config = match get_model_config(&storage, conversation.model_config_id).await {
-        Ok(mc) => mc,
-        Err(e) => return Err(format!("Failed to get model config for {}: {}", conversation_id, e)),
-    };
+        let finished = vec![message(conversation_id, "user", "Tell me"), answer(conversation_id, "The end.", "stop")];
+        let err = continuation_target(&finished, ContinuationKind::Truncated).unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Validation);
+        assert!(err.message.contains("stop"));
+        // "Continue writing" extends any answer
+        assert_eq!(continuation_target(&finished, ContinuationKind::Expand).unwrap(), 1);
+    }
 
-    drop(storage); // Release lock before potentially long API call
+    #[test]
+    fn failed_or_missing_answers_are_not_continued() {
+        let conversation_id = Uuid::new_v4();
+        let mut failed = answer(conversation_id, "Once", "length");
+        failed.mark_failed(generation::ERROR_STREAM, "connection reset");
+        assert!(continuation_target(&[failed], ContinuationKind::Truncated).is_err());
+        assert!(continuation_target(&[message(conversation_id, "user", "Hi")], ContinuationKind::Expand).is_err());
+    }
 
-    // --- Trigger API call in background (similar to send_message) ---
-    let app_state_clone = state.inner().clone();
-    let conversation_id_clone = conversation_id.clone();
+    #[test]
+    fn truncated_continuations_double_max_tokens() {
+        let config = model_config("Test", r#"{"model": "m", "max_tokens": 300}"#);
+        let doubled = ParsedProviderOptions::from_config(&continuation_config(&config, ContinuationKind::Truncated).unwrap()).unwrap();
+        assert_eq!(doubled.max_tokens, Some(600));
+        assert_eq!(doubled.model.as_deref(), Some("m"));
 
-    tauri::async_runtime::spawn(async move {
-        log::info!("Regeneration BG task started for conversation {}", conversation_id_clone);
+        let unlimited = model_config("Test", r#"{"model": "m"}"#);
+        let fallback = ParsedProviderOptions::from_config(&continuation_config(&unlimited, ContinuationKind::Truncated).unwrap()).unwrap();
+        assert_eq!(fallback.max_tokens, Some(CONTINUATION_FALLBACK_MAX_TOKENS));
 
-        // --- Create System Prompt ---
-        let system_prompt_content = format!("You are {}.", model_config.name);
-        let system_prompt = Message {
-            id: Uuid::nil(), // API usually ignores system ID
-            conversation_id: conv_uuid,
-            role: "system".to_string(),
-            content: system_prompt_content,
-            timestamp: Utc::now(),
-            metadata: None,
-        };
+        let expanded = continuation_config(&config, ContinuationKind::Expand).unwrap();
+        assert_eq!(expanded.provider_options, config.provider_options);
+    }
 
-        // --- Get API Key ---
-        let api_key = match config::get_api_key(&model_config) {
-            Ok(key) => key,
-            Err(e) => {
-                 log::error!("Regeneration BG Task: Failed to get API key for {}: {:?}", conversation_id_clone, e);
-                 return;
+    #[tokio::test]
+    async fn opening_another_library_shows_its_own_conversations() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let dir = test_support::temp_dir();
+        let work = dir.join("work.db").to_string_lossy().into_owned();
+        let personal = dir.join("personal.db").to_string_lossy().into_owned();
+        let titles = |app: &TestApp| {
+            let storage = app.state.storage.clone();
+            async move {
+                let storage = storage.lock().await;
+                storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap().into_iter().map(|c| c.title).collect::<Vec<_>>()
             }
         };
-        
-        // --- Prepare messages for API (system prompt + history UP TO last assistant) --- 
-        let mut api_messages = vec![system_prompt];
-        api_messages.extend(history_for_api.iter().cloned()); // Use the history before last assistant msg
-
-        // --- Get API Provider --- 
-        let api_provider = app_state_clone.api_provider.clone(); 
-
-        // --- Make the API call (Streaming) --- 
-        log::info!("Regeneration BG Task: Starting stream request for conversation {}", conversation_id_clone);
-        let delta_stream_result = api_provider
-            .send_chat_stream_request(&model_config, &api_key, &api_messages)
-            .await;
-
-        let mut delta_stream = match delta_stream_result {
-            Ok(stream) => stream,
-            Err(e) => {
-                log::error!("Regeneration BG Task: Failed to initiate stream request for {}: {:?}", conversation_id_clone, e);
-                return;
-            }
+
+        open_library(app.command_state(), work.clone()).await.unwrap();
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        app.state.storage.lock().await.rename_conversation(conversation.id, "Work chat".to_string()).await.unwrap();
+        assert_eq!(app.events.payloads("library_changed")[0]["path"], work);
+
+        open_library(app.command_state(), personal.clone()).await.unwrap();
+        assert!(titles(&app).await.is_empty());
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        app.state.storage.lock().await.rename_conversation(conversation.id, "Personal chat".to_string()).await.unwrap();
+        assert_eq!(titles(&app).await, vec!["Personal chat"]);
+
+        open_library(app.command_state(), work).await.unwrap();
+        assert_eq!(titles(&app).await, vec!["Work chat"]);
+        // Each library gets a model config to start with
+        assert!(!app.state.storage.lock().await.list_model_configs().await.unwrap().is_empty());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn find_in_conversation_matches_non_ascii_case() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let storage = app.state.storage.lock().await;
+        let conversation = test_support::conversation(&storage).await;
+        let upper = message(conversation.id, "user", "ÄPFEL oder Birnen?");
+        let lower = message(conversation.id, "assistant", "Äpfel, und zwar grüne äpfel.");
+        let other = message(conversation.id, "user", "Danke");
+        storage.save_messages(&[upper.clone(), lower.clone(), other]).await.unwrap();
+        drop(storage);
+
+        let found = find_in_conversation(app.command_state(), conversation.id.to_string(), "äpfel".to_string()).await.unwrap();
+        assert_eq!(found.total_matches, 3);
+        assert!(!found.truncated);
+        let ids: Vec<Uuid> = found.matches.iter().map(|m| m.message_id).collect();
+        assert_eq!(ids, vec![upper.id, lower.id]);
+
+        let hits = search_in_conversation(app.command_state(), conversation.id.to_string(), "ÄPFEL".to_string()).await.unwrap();
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[1].match_count, 2);
+    }
+
+    #[tokio::test]
+    async fn ephemeral_conversations_stream_without_writing_rows() {
+        let app = TestApp::new(MockProvider::new(vec![
+            MockStep::Delta("Not ".to_string()),
+            MockStep::Delta("saved".to_string()),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let conversation = conversation_for(&app).await;
+        app.state.storage.lock().await.set_conversation_ephemeral(conversation.id, true).await.unwrap();
+
+        let user_message = send_message(app.command_state(), conversation.id.to_string(), "Hello".to_string(), None).await.unwrap();
+        app.events.wait_for("assistant_stream_finished").await;
+
+        assert_eq!(app.events.streamed_text(), "Not saved");
+        assert_eq!(app.events.payloads(events::ASSISTANT_STREAM_STARTED)[0]["userMessageId"], user_message.id.to_string());
+        let storage = app.state.storage.lock().await;
+        assert!(storage.get_conversation_messages(conversation.id).await.unwrap().is_empty());
+        // The turn lives in memory only, for as long as the app runs
+        let unsaved = app.state.with_ephemeral_messages(conversation.id, Vec::new());
+        let unsaved: Vec<(&str, &str)> = unsaved.iter().map(|m| (m.role.as_str(), m.content.as_str())).collect();
+        assert_eq!(unsaved, vec![("user", "Hello"), ("assistant", "Not saved")]);
+        drop(storage);
+        // The sidebar still hears about the turn
+        let updated = app.events.wait_for(events::CONVERSATION_UPDATED).await;
+        assert_eq!(updated["summary"]["message_count"], 2);
+    }
+
+    #[tokio::test]
+    async fn stopping_a_stream_is_acknowledged_before_it_finishes() {
+        let app = TestApp::new(MockProvider::new(vec![
+            MockStep::Delta("One".to_string()),
+            MockStep::DelayMs(300),
+            MockStep::Delta(" two".to_string()),
+            MockStep::Delta(" three".to_string()),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let conversation = conversation_for(&app).await;
+
+        send_message(app.command_state(), conversation.id.to_string(), "Count".to_string(), None).await.unwrap();
+        let started = app.events.wait_for(events::ASSISTANT_STREAM_STARTED).await;
+        let message_id = started["messageId"].as_str().unwrap().to_string();
+        stop_generation(app.command_state(), message_id.clone()).await.unwrap();
+        let finished = app.events.wait_for("assistant_stream_finished").await;
+
+        let names = app.events.names();
+        let cancelled_at = names.iter().position(|n| n == events::GENERATION_CANCELLED).expect("cancellation acknowledged");
+        let finished_at = names.iter().position(|n| n == "assistant_stream_finished").unwrap();
+        assert!(cancelled_at < finished_at);
+        assert!(!names[cancelled_at..].iter().any(|n| n == "assistant_message_chunk"));
+        assert_eq!(app.events.payloads(events::GENERATION_CANCELLED)[0]["messageId"], message_id);
+        assert_eq!(finished["cancelled"], true);
+        assert_eq!(app.events.streamed_text(), "One");
+    }
+
+    #[tokio::test]
+    async fn model_configs_are_renamed_to_unique_non_empty_names() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let config = app.model_config("{}").await;
+        let other = app.model_config("{}").await;
+        let name_of = |id: Uuid| {
+            let storage = app.state.storage.clone();
+            async move { get_model_config(&*storage.lock().await, id).await.unwrap().name }
         };
-        
-        // --- Process Stream and Emit Chunks (identical logic to send_message) --- 
-        let mut full_content = String::new();
-        let assistant_message_id = Uuid::new_v4(); // Generate NEW ID for the regenerated message
-        let mut first_chunk = true;
-        let app_handle_clone = app_state_clone.app_handle.clone(); // Clone handle for emitting
-
-        while let Some(delta_result) = delta_stream.next().await {
-            
-            // Check for cancellation
-            if app_state_clone.cancelled_streams.contains_key(&assistant_message_id) {
-                log::warn!("Regeneration BG Task: Cancellation requested for message {}. Stopping stream.", assistant_message_id);
-                app_state_clone.cancelled_streams.remove(&assistant_message_id); 
-                break; 
-            }
 
-            match delta_result {
-                Ok(delta_content) => {
-                    full_content.push_str(&delta_content);
-                    let is_first = first_chunk;
-                    if first_chunk { first_chunk = false; }
-                    
-                    // Emit the chunk to the frontend
-                    let chunk_payload = serde_json::json!({
-                        "conversationId": conversation_id_clone,
-                        "messageId": assistant_message_id.to_string(),
-                        "delta": delta_content,
-                    });
-                    
-                    if let Err(e) = app_handle_clone.emit("assistant_message_chunk", chunk_payload) {
-                        log::error!("Regeneration BG Task: Failed to emit chunk event: {:?}", e);
-                        // Consider stopping the stream if emit fails repeatedly
-                    }
-                }
-                Err(e) => {
-                    log::error!("Regeneration BG Task: Error receiving stream delta: {:?}", e);
-                    break; // Stop processing on stream error
-                }
-            }
+        rename_model_config(app.command_state(), config.id.to_string(), "  Fast model ".to_string()).await.unwrap();
+        assert_eq!(name_of(config.id).await, "Fast model");
+
+        let empty = rename_model_config(app.command_state(), config.id.to_string(), "   ".to_string()).await.unwrap_err();
+        assert_eq!(empty.kind, ErrorKind::Validation);
+
+        let duplicate = rename_model_config(app.command_state(), other.id.to_string(), "Fast model".to_string()).await.unwrap_err();
+        assert!(duplicate.message.contains("A model config named 'Fast model' already exists"), "{}", duplicate.message);
+        assert_eq!(name_of(other.id).await, other.name);
+    }
+
+    #[tokio::test]
+    async fn sends_that_would_exceed_the_token_budget_are_refused() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::Finish("stop".to_string())])).await;
+        let conversation = conversation_for(&app).await;
+        {
+            let storage = app.state.storage.lock().await;
+            let mut earlier = answer(conversation.id, "Earlier answer", "stop");
+            earlier.set_metadata_field("prompt_tokens", serde_json::json!(900));
+            earlier.set_metadata_field("completion_tokens", serde_json::json!(98));
+            storage.save_message(&earlier).await.unwrap();
+            storage.set_conversation_token_budget(conversation.id, Some(1000)).await.unwrap();
         }
 
-        // --- Stream finished or cancelled ---
-        log::info!("Regeneration BG Task: Stream finished/cancelled for message {}", assistant_message_id);
-        
-        // Emit finished event regardless of cancellation status 
-        // Frontend handles state based on whether it received chunks
-        let finished_payload = serde_json::json!({ "messageId": assistant_message_id.to_string() });
-        if let Err(e) = app_handle_clone.emit("assistant_stream_finished", finished_payload) {
-             log::error!("Regeneration BG Task: Failed to emit finished event: {:?}", e);
-        }
-
-        // --- Save the complete assistant message (if content received) ---
-        if !full_content.is_empty() {
-            let assistant_message = Message {
-                id: assistant_message_id,
-                conversation_id: conv_uuid,
-                role: "assistant".to_string(),
-                content: full_content,
-                timestamp: Utc::now(),
-                metadata: None,
-            };
-            
-            let storage = app_state_clone.storage.lock().await;
-            if let Err(e) = storage.save_message(&assistant_message).await {
-                log::error!("Regeneration BG Task: Failed to save regenerated assistant message {}: {:?}", assistant_message_id, e);
-            }
-        } else {
-             log::warn!("Regeneration BG Task: No content received for message {}, not saving.", assistant_message_id);
+        // 998 tokens used; even a short message needs more than the two left
+        let refused = send_message(app.command_state(), conversation.id.to_string(), "Hello".to_string(), None).await.unwrap_err();
+        assert_eq!(refused.kind, ErrorKind::Validation);
+        assert!(refused.message.contains("token budget of 1000"), "{}", refused.message);
+        let exceeded = app.events.payloads(events::BUDGET_EXCEEDED);
+        assert_eq!(exceeded.len(), 1);
+        assert_eq!(exceeded[0]["tokensUsed"], 998);
+        assert_eq!(exceeded[0]["tokenBudget"], 1000);
+        assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, vec!["Earlier answer"]);
+
+        app.state.storage.lock().await.set_conversation_token_budget(conversation.id, Some(2000)).await.unwrap();
+        send_message(app.command_state(), conversation.id.to_string(), "Hello".to_string(), None).await.unwrap();
+        app.events.wait_for("assistant_stream_finished").await;
+    }
+
+    #[tokio::test]
+    async fn model_configs_without_a_model_are_rejected_at_save_time() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+
+        let missing = add_model_config(app.command_state(), model_config("No model", "{}")).await.unwrap_err();
+        assert_eq!(missing.kind, ErrorKind::Validation);
+        assert!(missing.message.contains("model: required"), "{}", missing.message);
+        let empty = add_model_config(app.command_state(), model_config("Empty model", r#"{"model": "  "}"#)).await.unwrap_err();
+        assert_eq!(empty.kind, ErrorKind::Validation);
+        assert!(empty.message.contains("model: cannot be empty"), "{}", empty.message);
+
+        let config = model_config("GPT", r#"{"model": "gpt-4o"}"#);
+        add_model_config(app.command_state(), config.clone()).await.unwrap();
+        let cleared = ModelConfig { provider_options: Some(r#"{"model": ""}"#.to_string()), ..config.clone() };
+        let err = update_model_config(app.command_state(), cleared).await.unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Validation);
+        let stored = get_model_config(&*app.state.storage.lock().await, config.id).await.unwrap();
+        assert_eq!(stored.provider_options.as_deref(), Some(r#"{"model": "gpt-4o"}"#));
+    }
+
+    #[tokio::test]
+    async fn appearance_round_trips_and_malformed_colors_are_rejected() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let id = conversation.id.to_string();
+        let listed = || async {
+            let conversations = list_conversations(app.command_state(), None, None).await.unwrap();
+            let listed = conversations.into_iter().find(|c| c.id == conversation.id).unwrap();
+            (listed.color, listed.icon)
+        };
+
+        set_conversation_appearance(app.command_state(), id.clone(), Some(" #3B82F6 ".to_string()), Some("🚀".to_string())).await.unwrap();
+        assert_eq!(listed().await, (Some("#3b82f6".to_string()), Some("🚀".to_string())));
+
+        for color in ["3b82f6", "#3b82f", "#ggg", "red"] {
+            let err = set_conversation_appearance(app.command_state(), id.clone(), Some(color.to_string()), None).await.unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Validation, "{}", color);
         }
-    });
+        assert_eq!(listed().await, (Some("#3b82f6".to_string()), Some("🚀".to_string())));
 
-    Ok(())
-}
+        set_conversation_appearance(app.command_state(), id, Some(String::new()), None).await.unwrap();
+        assert_eq!(listed().await, (None, None));
+    }
 
-// Tauri command to generate a title for a conversation (runs in background)
-#[tauri::command]
-pub async fn generate_conversation_title(
-    app_handle: tauri::AppHandle,
-    state: State<'_, AppState>,
-    conversation_id: String, 
-    utility_model_config_id: String,
-) -> Result<(), String> {
-    log::info!(
-        "Received request to generate title for conv: {} using model: {}",
-        conversation_id,
-        utility_model_config_id
-    );
+    #[tokio::test]
+    async fn conversation_models_resolve_or_report_a_dangling_config() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = conversation_for(&app).await;
 
-    // Parse IDs
-    let Ok(conv_uuid) = Uuid::parse_str(&conversation_id) else {
-        return Err(format!("Invalid conversation ID format: {}", conversation_id));
-    };
-    let Ok(util_model_uuid) = Uuid::parse_str(&utility_model_config_id) else {
-        return Err(format!("Invalid utility model ID format: {}", utility_model_config_id));
-    };
+        let resolved = get_conversation_model(app.command_state(), conversation.id.to_string()).await.unwrap();
+        let stored = app.state.storage.lock().await.get_conversation(conversation.id).await.unwrap().unwrap();
+        assert_eq!(resolved.id, stored.model_config_id);
+        assert!(resolved.name.starts_with("Test model"));
 
-    // Clone necessary state parts for the background task
-    let app_state_clone = state.inner().clone();
-    let handle_clone = app_handle.clone();
-
-    // Spawn the actual generation logic in a separate task
-    tauri::async_runtime::spawn(async move {
-        log::info!("[Title Gen BG Task {}] Started", conversation_id);
-        
-        // --- Get Messages (Prompt + Response) --- 
-        let messages = {
-            let storage = app_state_clone.storage.lock().await;
-            match storage.get_conversation_messages(conv_uuid).await {
-                Ok(msgs) => msgs,
-                Err(e) => {
-                    log::error!("[Title Gen BG Task {}] Failed to get messages: {:?}", conversation_id, e);
-                    return; // Cannot proceed without messages
-                }
+        let deleted = Uuid::new_v4();
+        app.state.storage.lock().await.update_conversation_model_id(conversation.id, deleted).await.unwrap();
+        let dangling = get_conversation_model(app.command_state(), conversation.id.to_string()).await.unwrap_err();
+        assert_eq!(dangling.kind, ErrorKind::NotFound);
+        assert!(dangling.message.contains(&format!("model config {}, which no longer exists", deleted)), "{}", dangling.message);
+
+        let missing = get_conversation_model(app.command_state(), Uuid::new_v4().to_string()).await.unwrap_err();
+        assert_eq!(missing.kind, ErrorKind::NotFound);
+    }
+
+    #[tokio::test]
+    async fn cancelling_a_conversations_tasks_stops_its_title_generation() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::DelayMs(300), MockStep::Delta("Trip planning".to_string())])).await;
+        let start = || async {
+            let conversation = conversation_for(&app).await;
+            let storage = app.state.storage.lock().await;
+            storage.save_message(&message(conversation.id, "user", "Where should we go in May?")).await.unwrap();
+            storage.save_message(&answer(conversation.id, "Lisbon is lovely in May.", "stop")).await.unwrap();
+            let state = app.state.clone();
+            let job = TitleJob { utility_model_config_id: Some(conversation.model_config_id), ..TitleJob::default() };
+            let task = tokio::spawn(async move { generate_title(&state, conversation.id, job).await });
+            (conversation, task)
+        };
+        let title_of = |id: Uuid| {
+            let storage = app.state.storage.clone();
+            async move { storage.lock().await.get_conversation(id).await.unwrap().unwrap().title }
+        };
+
+        let (conversation, task) = start().await;
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        cancel_conversation_tasks(app.command_state(), conversation.id.to_string()).await.unwrap();
+        assert!(matches!(task.await.unwrap(), Err(JobFailure::Drop(reason)) if reason == "cancelled while queued"));
+        assert_eq!(title_of(conversation.id).await, conversation.title);
+
+        // Tasks started after the cancellation run normally
+        let (conversation, task) = start().await;
+        task.await.unwrap().unwrap();
+        assert_eq!(title_of(conversation.id).await.to_lowercase(), "trip planning");
+    }
+
+
+    #[tokio::test]
+    async fn truncation_preview_splits_history_at_the_context_window() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = conversation_for(&app).await;
+        // 800 characters each, so 204 estimated tokens
+        let mut history: Vec<Message> = (0..6)
+            .map(|i| message_at(conversation.id, if i % 2 == 0 { "user" } else { "assistant" }, &format!("{:0>800}", i), 1_000 + i))
+            .collect();
+        history[0].set_metadata_field("context_pinned", serde_json::json!(true));
+        app.state.storage.lock().await.save_messages(&history).await.unwrap();
+        let ids: Vec<Uuid> = history.iter().map(|m| m.id).collect();
+
+        // Without a context window everything is sent
+        let preview = preview_truncation(app.command_state(), conversation.id.to_string()).await.unwrap();
+        assert_eq!(preview.included_message_ids, ids);
+        assert!(preview.excluded_message_ids.is_empty());
+        let system_tokens = preview.usage.system_tokens;
+
+        // Room for the pinned message and the two newest, after the completion's reserve and the
+        // summary's: older messages would be summarized
+        let model_configs = app.state.storage.lock().await.list_model_configs().await.unwrap();
+        let model_config = model_configs.into_iter().find(|config| config.id == conversation.model_config_id).unwrap();
+        let context_window = system_tokens + memory::SUMMARY_RESERVE_TOKENS + 3 * 204 + 100;
+        let options = format!(r#"{{"model": "test-model", "context_window": {}, "max_tokens": 100}}"#, context_window);
+        let model_config = ModelConfig { provider_options: Some(options), ..model_config };
+        app.state.storage.lock().await.update_model_config(&model_config).await.unwrap();
+
+        let preview = preview_truncation(app.command_state(), conversation.id.to_string()).await.unwrap();
+        assert_eq!(preview.included_message_ids, [ids[0], ids[4], ids[5]]);
+        assert_eq!(preview.excluded_message_ids, [ids[1], ids[2], ids[3]]);
+        assert!(preview.summarized);
+        assert_eq!(preview.estimated_tokens, system_tokens + memory::SUMMARY_RESERVE_TOKENS + 3 * 204);
+        assert_eq!((preview.usage.included_messages, preview.usage.omitted_messages), (3, 3));
+
+        // An ephemeral conversation is never summarized, so the summary's reserve goes to history
+        app.state.storage.lock().await.set_conversation_ephemeral(conversation.id, true).await.unwrap();
+        let preview = preview_truncation(app.command_state(), conversation.id.to_string()).await.unwrap();
+        assert!(!preview.summarized);
+        assert_eq!(preview.excluded_message_ids, [ids[1]]);
+    }
+
+
+    #[tokio::test]
+    async fn stored_title_models_are_used_when_none_is_requested() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = conversation_for(&app).await;
+        let app_wide = app.model_config(r#"{"model": "app-wide"}"#).await;
+        let own = app.model_config(r#"{"model": "own"}"#).await;
+        let requested = app.model_config(r#"{"model": "requested"}"#).await;
+        let resolved = |requested: Option<Uuid>| {
+            let state = app.state.clone();
+            async move {
+                let storage = state.storage.lock().await;
+                let conversation = storage.get_conversation(conversation.id).await.unwrap().unwrap();
+                resolve_title_model_config(&storage, &conversation, requested).await.unwrap().map(|config| config.id)
             }
         };
+        assert_eq!(resolved(None).await, None);
 
-        // We expect exactly two messages (user prompt, assistant response)
-        if messages.len() < 2 {
-            log::warn!("[Title Gen BG Task {}] Expected >= 2 messages, found {}. Skipping title generation.", conversation_id, messages.len());
-            return;
-        }
-        let user_prompt = &messages[0];
-        let assistant_response = &messages[1]; // Assuming the first two are user/assistant
-
-        // Truncate content (simple character limit for now)
-        const MAX_CHARS: usize = 1000;
-        let truncated_prompt = user_prompt.content.chars().take(MAX_CHARS).collect::<String>();
-        let truncated_response = assistant_response.content.chars().take(MAX_CHARS).collect::<String>();
-        
-        // --- Get Utility Model Config and API Key --- 
-        let utility_model_config = {
-            let storage = app_state_clone.storage.lock().await;
-            match get_model_config(&storage, util_model_uuid).await {
-                Ok(mc) => mc,
-                Err(e) => {
-                    log::error!("[Title Gen BG Task {}] Failed to get utility model config ({}): {}", conversation_id, util_model_uuid, e);
-                    return;
-                }
+        set_title_model_config_id(app.command_state(), None, Some(app_wide.id.to_string())).await.unwrap();
+        assert_eq!(get_title_model_config_id(app.command_state(), None).await.unwrap(), Some(app_wide.id.to_string()));
+        assert_eq!(resolved(None).await, Some(app_wide.id));
+
+        set_title_model_config_id(app.command_state(), Some(conversation.id.to_string()), Some(own.id.to_string())).await.unwrap();
+        let stored = get_title_model_config_id(app.command_state(), Some(conversation.id.to_string())).await.unwrap();
+        assert_eq!(stored, Some(own.id.to_string()));
+        assert_eq!(resolved(None).await, Some(own.id));
+        assert_eq!(resolved(Some(requested.id)).await, Some(requested.id));
+
+        // A deleted choice falls back to the next one
+        app.state.storage.lock().await.delete_model_config(own.id).await.unwrap();
+        assert_eq!(resolved(None).await, Some(app_wide.id));
+    }
+
+
+    #[tokio::test]
+    async fn wiping_needs_the_exact_token_and_leaves_a_usable_store() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = conversation_for(&app).await;
+        app.user_message(&conversation, "Keep me?").await;
+
+        for wrong in ["", "delete everything", "DELETE EVERYTHING "] {
+            let error = wipe_all_data(app.command_state(), wrong.to_string()).await.unwrap_err();
+            assert_eq!(error.kind, ErrorKind::Validation);
+        }
+        assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, ["Keep me?"]);
+
+        let report = wipe_all_data(app.command_state(), WIPE_CONFIRMATION.to_string()).await.unwrap();
+        assert_eq!((report.conversations_deleted, report.messages_deleted, report.model_configs_deleted), (1, 1, 2));
+        let storage = app.state.storage.lock().await;
+        assert!(storage.get_conversation(conversation.id).await.unwrap().is_none());
+        assert!(storage.get_conversation_messages(conversation.id).await.unwrap().is_empty());
+        // The default model config is back, so a new conversation can be started right away
+        let model_configs = storage.list_model_configs().await.unwrap();
+        assert_eq!(model_configs.len(), 1);
+        drop(storage);
+        let created = create_conversation(app.command_state()).await.unwrap();
+        assert_eq!(created.model_config_id, model_configs[0].id);
+    }
+
+    #[tokio::test]
+    async fn wiping_resets_in_memory_state_to_match_the_empty_store() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = conversation_for(&app).await;
+        let dir = test_support::temp_dir();
+        std::fs::write(dir.join("review.md"), "# Review this code").unwrap();
+        set_prompt_dir(app.command_state(), Some(dir.display().to_string())).await.unwrap();
+        set_offline_mode(app.command_state(), true).await.unwrap();
+        app.state.remember_ephemeral(test_support::message_at(conversation.id, "user", "Unsaved", 0));
+        app.state.size_warned_conversations.insert(conversation.id);
+        assert_eq!(list_file_prompts(app.command_state()).await.unwrap().len(), 1);
+
+        wipe_all_data(app.command_state(), WIPE_CONFIRMATION.to_string()).await.unwrap();
+        assert!(!app.state.is_offline());
+        assert!(app.state.ephemeral_messages.is_empty());
+        assert!(app.state.size_warned_conversations.is_empty());
+        assert!(list_file_prompts(app.command_state()).await.unwrap().is_empty());
+        assert!(!automation::is_listening(&app.state));
+        assert_eq!(get_prompt_dir(app.command_state()).await.unwrap(), None);
+    }
+
+
+    #[tokio::test]
+    async fn message_roles_are_validated_and_alternation_checked() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = conversation_for(&app).await;
+        let question = app.user_message(&conversation, "Question").await;
+        let answer = app.user_message(&conversation, "Answer, imported as user").await;
+
+        for invalid in ["tool", "admin", ""] {
+            let error = set_message_role(app.command_state(), answer.id.to_string(), invalid.to_string()).await.unwrap_err();
+            assert_eq!(error.kind, ErrorKind::Validation);
+        }
+        let roles = |app: &TestApp| {
+            let state = app.state.clone();
+            async move {
+                let messages = state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+                messages.into_iter().map(|m| m.role).collect::<Vec<_>>()
             }
         };
+        assert_eq!(roles(&app).await, ["user", "user"]);
 
-        let api_key = match config::get_api_key(&utility_model_config) {
-            Ok(key) => key,
-            Err(e) => {
-                 log::error!("[Title Gen BG Task {}] Failed to get API key for utility model: {:?}", conversation_id, e);
-                 return;
+        let warnings = set_message_role(app.command_state(), answer.id.to_string(), " Assistant ".to_string()).await.unwrap();
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(roles(&app).await, ["user", "assistant"]);
+
+        // The change is made even when it breaks alternation, with a warning
+        let warnings = set_message_role(app.command_state(), question.id.to_string(), "assistant".to_string()).await.unwrap();
+        assert_eq!(warnings, [
+            format!("Two assistant messages in a row ({} and {}).", question.id, answer.id),
+            "The conversation's first turn is now from the assistant.".to_string(),
+        ]);
+        assert_eq!(roles(&app).await, ["assistant", "assistant"]);
+    }
+
+
+    #[tokio::test]
+    async fn provider_capabilities_come_from_each_implementation() {
+        let app = TestApp::new(crate::api::OpenAICompatibleProvider::new()).await;
+        let capabilities = get_provider_capabilities(app.command_state()).await.unwrap();
+        let openai = capabilities["openai_compatible"];
+        assert!(openai.streaming && openai.tools);
+        assert!(!openai.vision && !openai.embeddings && !openai.reasoning);
+        // The mock provider declares nothing of its own, so it gets the trait's text-only default
+        let mock = capabilities[crate::mock::MOCK_PROVIDER];
+        assert!(mock.streaming && !mock.tools);
+        assert_eq!(capabilities.len(), supported_providers().len());
+    }
+
+
+    #[tokio::test]
+    async fn conversation_summaries_stream_without_being_saved() {
+        let app = TestApp::new(MockProvider::new(vec![
+            MockStep::Delta("You asked about ".to_string()),
+            MockStep::Delta("Rust.".to_string()),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let conversation = conversation_for(&app).await;
+        let error = summarize_conversation(app.command_state(), conversation.id.to_string(), None).await.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Validation);
+
+        app.user_message(&conversation, "What is Rust?").await;
+        app.state.storage.lock().await.save_message(&message(conversation.id, "assistant", "A language.")).await.unwrap();
+        let summary_id = summarize_conversation(app.command_state(), conversation.id.to_string(), None).await.unwrap();
+
+        let finished = app.events.wait_for(events::SUMMARY_FINISHED).await;
+        assert_eq!(finished["summaryId"], summary_id);
+        assert_eq!(finished["summary"], "You asked about Rust.");
+        assert_eq!(finished["cancelled"], false);
+        assert!(finished.get("error").is_none());
+        let chunks = app.events.payloads(events::SUMMARY_CHUNK);
+        let deltas: Vec<&str> = chunks.iter().map(|chunk| chunk["delta"].as_str().unwrap()).collect();
+        assert_eq!(deltas, ["You asked about ", "Rust."]);
+        assert!(chunks.iter().all(|chunk| chunk["summaryId"] == summary_id));
+
+        let storage = app.state.storage.lock().await;
+        assert_eq!(test_support::contents(&storage, conversation.id).await, ["What is Rust?", "A language."]);
+        assert!(storage.get_conversation_memory(conversation.id).await.unwrap().is_none());
+        assert!(!app.events.names().iter().any(|name| name == "assistant_message_chunk"));
+    }
+
+
+    #[tokio::test]
+    async fn a_conversations_streams_can_be_stopped_by_its_id_alone() {
+        let app = TestApp::new(MockProvider::new(vec![
+            MockStep::Delta("One".to_string()),
+            MockStep::DelayMs(300),
+            MockStep::Delta(" two".to_string()),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let stopped = conversation_for(&app).await;
+        let running = conversation_for(&app).await;
+        let events_of = |name: &str, conversation: &Conversation| -> Vec<serde_json::Value> {
+            app.events.payloads(name).into_iter().filter(|p| p["conversationId"] == conversation.id.to_string()).collect()
+        };
+
+        send_message(app.command_state(), stopped.id.to_string(), "Count".to_string(), None).await.unwrap();
+        send_message(app.command_state(), running.id.to_string(), "Count".to_string(), None).await.unwrap();
+        for _ in 0..500 {
+            if app.events.payloads(events::ASSISTANT_STREAM_STARTED).len() == 2 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let started = |conversation: &Conversation| events_of(events::ASSISTANT_STREAM_STARTED, conversation)[0]["messageId"].clone();
+        let stopped_ids = stop_conversation_generation(app.command_state(), stopped.id.to_string()).await.unwrap();
+        assert_eq!(stopped_ids, [started(&stopped).as_str().unwrap()]);
+
+        for _ in 0..500 {
+            if app.events.payloads("assistant_stream_finished").len() == 2 {
+                break;
             }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let finished = app.events.payloads("assistant_stream_finished");
+        let cancelled = |conversation: &Conversation| {
+            finished.iter().find(|p| p["messageId"] == started(conversation)).map(|p| p["cancelled"].clone())
         };
+        assert_eq!(cancelled(&stopped), Some(serde_json::json!(true)));
+        assert_eq!(cancelled(&running), Some(serde_json::json!(false)));
+        let storage = app.state.storage.lock().await;
+        assert_eq!(test_support::contents(&storage, stopped.id).await, ["Count", "One"]);
+        assert_eq!(test_support::contents(&storage, running.id).await, ["Count", "One two"]);
+        drop(storage);
+        // Nothing left to stop
+        assert!(stop_conversation_generation(app.command_state(), stopped.id.to_string()).await.unwrap().is_empty());
+    }
 
-        // --- Construct Prompt for Title Generation --- 
-        let title_gen_system_prompt = "You are an expert conversation summarizer. Generate a concise, relevant title for the following conversation exchange. The title must be lowercase except for proper nouns, maximum 30 characters long, and contain only the title itself with no extra text or quotes.".to_string();
-        let title_gen_user_prompt = format!(
-            "User: {}\nAssistant: {}\n\nTitle:",
-            truncated_prompt,
-            truncated_response
-        );
-
-        let title_gen_messages = vec![
-            Message { // System Prompt
-                id: Uuid::nil(), conversation_id: conv_uuid, role: "system".to_string(),
-                content: title_gen_system_prompt, timestamp: Utc::now(), metadata: None, 
-            },
-            Message { // User Prompt containing the exchange
-                 id: Uuid::nil(), conversation_id: conv_uuid, role: "user".to_string(),
-                 content: title_gen_user_prompt, timestamp: Utc::now(), metadata: None,
-            },
-        ];
-
-        // --- Call Utility Model (Non-Streaming) --- 
-        let api_provider = app_state_clone.api_provider.clone();
-        match api_provider.send_chat_request(&utility_model_config, &api_key, &title_gen_messages).await {
-            Ok(generated_title_raw) => {
-                // --- Sanitize and Update Title --- 
-                let generated_title = generated_title_raw.trim().trim_matches('"'); // Remove whitespace and quotes
-                log::info!("[Title Gen BG Task {}] Raw generated title: '{}'", conversation_id, generated_title_raw);
-                log::info!("[Title Gen BG Task {}] Sanitized generated title: '{}'", conversation_id, generated_title);
-                
-                // Basic validation (length)
-                if generated_title.is_empty() || generated_title.len() > 30 {
-                    log::warn!("[Title Gen BG Task {}] Generated title invalid (empty or too long). Length: {}. Keeping default.", conversation_id, generated_title.len());
-                    return;
-                }
 
-                // Rename the conversation in storage
-                {
-                    let storage = app_state_clone.storage.lock().await;
-                    match storage.rename_conversation(conv_uuid, generated_title.to_string()).await {
-                        Ok(_) => {
-                            log::info!("[Title Gen BG Task {}] Successfully renamed conversation to '{}'", conversation_id, generated_title);
-                            // --- Emit update event --- 
-                             if let Err(e) = handle_clone.emit("conversation_updated", serde_json::json!({ "conversationId": conversation_id })) {
-                                 log::error!("[Title Gen BG Task {}] Failed to emit conversation_updated event: {:?}", conversation_id, e);
-                             }
-                        }
-                        Err(e) => {
-                            log::error!("[Title Gen BG Task {}] Failed to rename conversation in storage: {:?}", conversation_id, e);
-                        }
-                    }
-                } // Release storage lock
-                
+    #[tokio::test]
+    async fn titlebar_styles_are_saved_and_applied_on_macos_only() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        assert_eq!(get_titlebar_style(app.command_state()).await.unwrap(), TitleBarPreference::Overlay);
+        let error = set_titlebar_style(app.command_state(), "hidden".to_string()).await.unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Validation);
+
+        #[cfg(target_os = "macos")]
+        tauri::WebviewWindowBuilder::new(&app.state.app_handle, "main", tauri::WebviewUrl::default()).build().unwrap();
+        let applied = set_titlebar_style(app.command_state(), "visible".to_string()).await.unwrap();
+        assert_eq!(applied, cfg!(target_os = "macos"));
+        assert_eq!(get_titlebar_style(app.command_state()).await.unwrap(), TitleBarPreference::Visible);
+    }
+
+
+    #[tokio::test]
+    async fn automatic_titles_start_once_when_the_threshold_is_reached() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::Delta("trip to lisbon".to_string()), MockStep::Finish("stop".to_string())])).await;
+        let conversation = conversation_for(&app).await;
+        let mut started = Vec::new();
+        for (role, content) in [("user", "Plan a trip"), ("assistant", "Where to?"), ("user", "Lisbon"), ("assistant", "Great choice")] {
+            app.state.storage.lock().await.save_message(&message(conversation.id, role, content)).await.unwrap();
+            let title_model = Some(conversation.model_config_id.to_string());
+            started.push(maybe_generate_title(app.command_state(), conversation.id.to_string(), title_model).await.unwrap());
+        }
+        // The default `title_after_messages` is 2
+        assert_eq!(started, [false, true, false, false]);
+
+        for _ in 0..500 {
+            let title = app.state.storage.lock().await.get_conversation(conversation.id).await.unwrap().unwrap().title;
+            if title != crate::title::DEFAULT_CONVERSATION_TITLE {
+                assert_eq!(title, "trip to lisbon");
+                return;
             }
-            Err(e) => {
-                 log::error!("[Title Gen BG Task {}] Utility model API call failed: {:?}", conversation_id, e);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("the conversation was not titled");
+    }
+
+
+    #[tokio::test]
+    async fn libraries_are_not_switched_under_queued_or_background_work() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::Delta("Hello".to_string()), MockStep::Finish("stop".to_string())])).await;
+        let conversation = conversation_for(&app).await;
+        let dir = test_support::temp_dir();
+        let other = dir.join("other.db").to_string_lossy().into_owned();
+
+        // Every stream slot is taken, so the answer waits in the queue
+        let slots = app.state.stream_permits.available_permits() as u32;
+        let permits = app.state.stream_permits.clone().acquire_many_owned(slots).await.unwrap();
+        send_message(app.command_state(), conversation.id.to_string(), "Hi".to_string(), None).await.unwrap();
+        assert!(app.state.active_streams.is_empty());
+        let refused = open_library(app.command_state(), other.clone()).await.unwrap_err();
+        assert_eq!(refused.kind, crate::error::ErrorKind::Validation);
+
+        drop(permits);
+        app.events.wait_for("assistant_stream_finished").await;
+        let title_task = app.state.begin_background_work();
+        // The spawned generation lets go of its work marker just after finishing
+        for _ in 0..100 {
+            if app.state.background_work.load(std::sync::atomic::Ordering::SeqCst) == 1 {
+                break;
             }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
         }
-        log::info!("[Title Gen BG Task {}] Finished", conversation_id);
-    }); // End of background task spawn
+        assert!(open_library(app.command_state(), other.clone()).await.is_err());
 
-    Ok(()) // Return immediately, task runs in background
-}
+        drop(title_task);
+        open_library(app.command_state(), other).await.unwrap();
+        assert!(app.events.payloads("library_changed").len() == 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 
-// Add other commands later (create_conversation, get_messages, etc.) 
 
-// Tauri command to open a URL in the default browser
-#[tauri::command]
-pub async fn open_url(app_handle: tauri::AppHandle, url: String) -> Result<(), String> {
-     log::info!("Frontend requested to open URL: {}", url);
-     // Use the method from tauri-plugin-opener
-     match app_handle.opener().open_url(&url, None::<&str>) { // Use plugin method
-         Ok(_) => {
-             log::info!("Successfully opened URL: {}", url);
-             Ok(())
-         }
-         Err(e) => {
-             log::error!("Failed to open URL {}: {:?}", url, e);
-             // Convert the plugin's error type (likely Display) to a String
-             Err(format!("Failed to open URL: {}", e.to_string())) 
-         }
-     }
-} 
\ No newline at end of file
+    #[tokio::test]
+    async fn hard_deleting_a_missing_conversation_is_not_found() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        delete_conversation(app.command_state(), conversation.id.to_string(), Some(true)).await.unwrap();
+        let again = delete_conversation(app.command_state(), conversation.id.to_string(), Some(true)).await.unwrap_err();
+        assert_eq!(again.kind, crate::error::ErrorKind::NotFound);
+    }
+}
@@ -1,18 +1,110 @@
 use crate::models::ModelConfig;
 use anyhow::{Context, Result};
 use keyring::Entry;
+use serde::Serialize;
 
 // Placeholder for general application settings loading/saving
 // pub fn load_settings() -> Result<AppSettings> { ... }
 // pub fn save_settings(settings: &AppSettings) -> Result<()> { ... }
 
+// --- Settings Keys ---
+
+// App-wide base instruction prepended to every system prompt
+pub const DEFAULT_SYSTEM_PROMPT_KEY: &str = "default_system_prompt";
+pub const SYSTEM_PROMPT_SUFFIX_KEY: &str = "system_prompt_suffix"; // Appended to every system prompt
+
+// Last sort chosen for the conversation list, and its direction ("true"/"false")
+pub const CONVERSATION_SORT_KEY: &str = "conversation_sort";
+pub const CONVERSATION_SORT_ASCENDING_KEY: &str = "conversation_sort_ascending";
+
+// Fallback `user` identifier for configs that don't set `user_id` in provider_options
+pub const DEFAULT_USER_ID_KEY: &str = "default_user_id";
+
+// Monthly spend limit in USD, and whether reaching it "warn"s or "block"s
+pub const MONTHLY_BUDGET_USD_KEY: &str = "monthly_budget_usd";
+pub const BUDGET_ENFORCEMENT_KEY: &str = "budget_enforcement";
+
+// How many generations may stream at once; read at startup
+pub const MAX_CONCURRENT_STREAMS_KEY: &str = "max_concurrent_streams";
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: usize = 4;
+
+// Pacing of oversized stream deltas ("true"/"false", off by default) and the target
+// characters per paced chunk event; see `smoothing`
+pub const STREAM_SMOOTHING_KEY: &str = "stream_smoothing";
+pub const STREAM_SMOOTHING_CHARS_KEY: &str = "stream_smoothing_chars_per_event";
+
+// Folder of `.md` prompt files offered as slash commands; unset turns them off. See `prompt_files`
+pub const PROMPT_DIR_KEY: &str = "prompt_dir";
+
+// Appearance override: "system" (the default), "light" or "dark"; see `theme`
+pub const THEME_KEY: &str = "theme";
+
+// macOS title bar style of the main window: "overlay" (the default), "transparent" or "visible"
+pub const TITLEBAR_STYLE_KEY: &str = "titlebar_style";
+
+// Whether whitespace-only deltas at the start of an answer are dropped ("true"/"false", on by default)
+pub const TRIM_LEADING_WHITESPACE_KEY: &str = "trim_leading_whitespace";
+
+// Longest message content `save_message` stores ("0" turns the limit off) and what happens to
+// longer messages: "truncate" (the default) or "reject"
+pub const MAX_MESSAGE_CHARS_KEY: &str = "max_message_chars";
+pub const OVERSIZED_MESSAGE_POLICY_KEY: &str = "oversized_message_policy";
+pub const DEFAULT_MAX_MESSAGE_CHARS: usize = 1_000_000;
+
+// Daily export of changed conversations to a folder ("true"/"false", off by default), the
+// folder, the file format ("markdown" or "json") and when the last run started (RFC 3339);
+// see `auto_export`
+pub const AUTO_EXPORT_ENABLED_KEY: &str = "auto_export_enabled";
+pub const AUTO_EXPORT_DIR_KEY: &str = "auto_export_dir";
+pub const AUTO_EXPORT_FORMAT_KEY: &str = "auto_export_format";
+pub const AUTO_EXPORT_LAST_RUN_KEY: &str = "auto_export_last_run";
+
+// Redaction of secrets from outgoing message content ("true"/"false", off by default) and
+// the patterns used, as a JSON array of {name, pattern}; the defaults while unset. See `redaction`
+pub const REDACTION_ENABLED_KEY: &str = "redact_outgoing_secrets";
+pub const REDACTION_PATTERNS_KEY: &str = "redaction_patterns";
+
+// Generated title constraints: maximum length in characters, casing ("lowercase",
+// "sentence" or "title") and whether titles start with an emoji. See `title::load_settings`
+pub const TITLE_MAX_CHARS_KEY: &str = "title_max_chars";
+pub const TITLE_STYLE_KEY: &str = "title_style";
+pub const TITLE_EMOJI_KEY: &str = "title_emoji";
+
+// Message count at which `maybe_generate_title` titles a conversation still named "new chat"
+pub const TITLE_AFTER_MESSAGES_KEY: &str = "title_after_messages";
+
+// Model config for background utility requests (titles, conversation summaries); summaries
+// fall back to the conversation's own model when unset
+pub const UTILITY_MODEL_CONFIG_ID_KEY: &str = "utility_model_config_id";
+
+// Model config for generated titles when the request and the conversation don't name one;
+// unset falls back to the utility model
+pub const TITLE_MODEL_CONFIG_ID_KEY: &str = "title_model_config_id";
+
+// Days conversation change history is kept for undo ("0" keeps it forever); see `changelog`
+pub const CHANGELOG_RETENTION_DAYS_KEY: &str = "changelog_retention_days";
+
+// Message count at which a conversation gets a `conversation_size_warning` suggesting a split ("0" turns it off)
+pub const CONVERSATION_SIZE_WARNING_KEY: &str = "conversation_size_warning_messages";
+pub const DEFAULT_CONVERSATION_SIZE_WARNING: i64 = 1000;
+
+// Blocks every outbound request (chat, titles, summaries, probes, update checks) while "true"; see `AppState::ensure_online`
+pub const OFFLINE_MODE_KEY: &str = "offline_mode";
+
+// Local automation API ("true"/"false", off by default) and the 127.0.0.1 port it listens on; see `automation`
+pub const AUTOMATION_ENABLED_KEY: &str = "automation_enabled";
+pub const AUTOMATION_PORT_KEY: &str = "automation_port";
+
+// URL of the JSON release manifest `check_for_updates` reads; unset turns the check off. See `about`
+pub const UPDATE_MANIFEST_URL_KEY: &str = "update_manifest_url";
+
 // --- API Key Retrieval ---
 
 const KEYRING_SERVICE_PREFIX: &str = "localchat_api_key";
 
 /// Retrieves the API key for a given model configuration.
 /// It checks the `api_key_ref` field to determine whether to read from
-/// environment variables or the OS keyring.
+/// environment variables, a secret file, or the OS keyring.
 pub fn get_api_key(config: &ModelConfig) -> Result<String> {
     match config.api_key_ref.as_deref() {
         Some(ref_str) if ref_str.starts_with("env:") => {
@@ -23,7 +115,8 @@ pub fn get_api_key(config: &ModelConfig) -> Result<String> {
                 env_var_name
             ))
         }
-        Some(ref_str) if ref_str == "keyring" => {
+        Some(ref_str) if ref_str.starts_with("file:") => read_api_key_file(ref_str.trim_start_matches("file:")),
+        Some("keyring") => {
             let service_name = format!("{}-{}", KEYRING_SERVICE_PREFIX, config.id);
             let entry = Entry::new(&service_name, &config.name) // Use config name as "username"
                 .context("Failed to create keyring entry")?;
@@ -41,6 +134,23 @@ pub fn get_api_key(config: &ModelConfig) -> Result<String> {
     }
 }
 
+// Reads a `file:` API key reference: the whole file, trimmed
+fn read_api_key_file(path: &str) -> Result<String> {
+    log::debug!("Retrieving API key from file: {}", path);
+    let contents = std::fs::read_to_string(path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => anyhow::anyhow!("API key file '{}' does not exist", path),
+        std::io::ErrorKind::PermissionDenied => {
+            anyhow::anyhow!("API key file '{}' is not readable (permission denied)", path)
+        }
+        _ => anyhow::anyhow!("Failed to read API key file '{}': {}", path, e),
+    })?;
+    let key = contents.trim();
+    if key.is_empty() {
+        return Err(anyhow::anyhow!("API key file '{}' is empty", path));
+    }
+    Ok(key.to_string())
+}
+
 /// Stores an API key in the OS keyring for the given model configuration.
 pub fn set_api_key_in_keyring(config: &ModelConfig, api_key: &str) -> Result<()> {
     let service_name = format!("{}-{}", KEYRING_SERVICE_PREFIX, config.id);
@@ -53,4 +163,199 @@ pub fn set_api_key_in_keyring(config: &ModelConfig, api_key: &str) -> Result<()>
     ))
 }
 
-// TODO: Add commands for getting/setting keys via keyring in commands.rs 
\ No newline at end of file
+/// Copies a keyring-stored API key to the entry used once the config is renamed to `new_name`
+/// (the config name is the keyring username). Returns false when there was nothing to copy.
+/// The old entry is left in place; remove it with `delete_keyring_entry` once the rename sticks.
+pub fn copy_keyring_entry_for_rename(config: &ModelConfig, new_name: &str) -> Result<bool> {
+    let service_name = format!("{}-{}", KEYRING_SERVICE_PREFIX, config.id);
+    let old_entry = Entry::new(&service_name, &config.name)
+        .context("Failed to create keyring entry")?;
+    let api_key = match old_entry.get_password() {
+        Ok(key) => key,
+        Err(keyring::Error::NoEntry) => return Ok(false),
+        Err(e) => return Err(anyhow::anyhow!("Failed to read API key from keyring: {}", e)),
+    };
+    let renamed = ModelConfig { name: new_name.to_string(), ..config.clone() };
+    set_api_key_in_keyring(&renamed, &api_key)?;
+    Ok(true)
+}
+
+/// Removes the keyring entry stored under `config`'s current name, if any.
+pub fn delete_keyring_entry(config: &ModelConfig) -> Result<()> {
+    let service_name = format!("{}-{}", KEYRING_SERVICE_PREFIX, config.id);
+    let entry = Entry::new(&service_name, &config.name)
+        .context("Failed to create keyring entry")?;
+    match entry.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(anyhow::anyhow!("Failed to delete keyring entry for '{}': {}", config.name, e)),
+    }
+}
+
+/// Whether the OS keyring can be reached, probed by looking up an entry that is never stored.
+pub fn keyring_available() -> bool {
+    let service_name = format!("{}-probe", KEYRING_SERVICE_PREFIX);
+    match Entry::new(&service_name, "probe").and_then(|entry| entry.get_password()) {
+        Ok(_) | Err(keyring::Error::NoEntry) => true,
+        Err(e) => {
+            log::debug!("Keyring unavailable: {}", e);
+            false
+        }
+    }
+}
+
+// --- Automation Token ---
+
+const AUTOMATION_KEYRING_SERVICE: &str = "localchat_automation";
+const AUTOMATION_KEYRING_USER: &str = "token";
+
+/// Bearer token the automation API requires. Generated and stored in the keyring on first use.
+pub fn automation_token() -> Result<String> {
+    let entry = Entry::new(AUTOMATION_KEYRING_SERVICE, AUTOMATION_KEYRING_USER)
+        .context("Failed to create keyring entry")?;
+    match entry.get_password() {
+        Ok(token) if !token.is_empty() => Ok(token),
+        Ok(_) | Err(keyring::Error::NoEntry) => regenerate_automation_token(),
+        Err(e) => Err(anyhow::anyhow!("Failed to read the automation token from the keyring: {}", e)),
+    }
+}
+
+/// Replaces the automation token with a new random one; the old one stops working.
+pub fn regenerate_automation_token() -> Result<String> {
+    let token = format!("{}{}", uuid::Uuid::new_v4().simple(), uuid::Uuid::new_v4().simple());
+    let entry = Entry::new(AUTOMATION_KEYRING_SERVICE, AUTOMATION_KEYRING_USER)
+        .context("Failed to create keyring entry")?;
+    log::info!("Storing a new automation token in the keyring");
+    entry.set_password(&token).context("Failed to store the automation token in the keyring")?;
+    Ok(token)
+}
+
+/// Removes the automation token from the keyring, if any. The next listener gets a new one.
+pub fn delete_automation_token() -> Result<()> {
+    let entry = Entry::new(AUTOMATION_KEYRING_SERVICE, AUTOMATION_KEYRING_USER)
+        .context("Failed to create keyring entry")?;
+    match entry.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(anyhow::anyhow!("Failed to delete the automation token from the keyring: {}", e)),
+    }
+}
+
+/// Where a config's API key comes from and whether it can be resolved.
+/// Never carries the key itself.
+#[derive(Serialize, Debug, Clone)]
+pub struct ApiKeyStatus {
+    pub source: &'static str, // "env" | "file" | "keyring" | "none"
+    pub available: bool,
+    pub detail: String,
+}
+
+/// Resolves the API key the same way as `get_api_key`, but only reports
+/// the outcome so it is safe to show in the UI.
+pub fn check_api_key(config: &ModelConfig) -> ApiKeyStatus {
+    match config.api_key_ref.as_deref() {
+        Some(ref_str) if ref_str.starts_with("env:") => {
+            let env_var_name = ref_str.trim_start_matches("env:");
+            let available = std::env::var(env_var_name).map(|v| !v.is_empty()).unwrap_or(false);
+            ApiKeyStatus {
+                source: "env",
+                available,
+                detail: if available {
+                    format!("Environment variable '{}' is set", env_var_name)
+                } else {
+                    format!("Environment variable '{}' is not set", env_var_name)
+                },
+            }
+        }
+        Some(ref_str) if ref_str.starts_with("file:") => {
+            let path = ref_str.trim_start_matches("file:");
+            let (available, detail) = match read_api_key_file(path) {
+                Ok(_) => (true, format!("API key file '{}' is readable", path)),
+                Err(e) => (false, e.to_string()),
+            };
+            ApiKeyStatus { source: "file", available, detail }
+        }
+        Some("keyring") => {
+            let service_name = format!("{}-{}", KEYRING_SERVICE_PREFIX, config.id);
+            let lookup = Entry::new(&service_name, &config.name).and_then(|entry| entry.get_password());
+            let (available, detail) = match lookup {
+                Ok(_) => (true, format!("Keyring entry found for '{}'", config.name)),
+                Err(keyring::Error::NoEntry) => (false, format!("No keyring entry for '{}'", config.name)),
+                Err(e) => (false, format!("Keyring unavailable: {}", e)),
+            };
+            ApiKeyStatus { source: "keyring", available, detail }
+        }
+        Some(other) => ApiKeyStatus {
+            source: "none",
+            available: false,
+            detail: format!("Unsupported api_key_ref format: {}", other),
+        },
+        None => ApiKeyStatus {
+            source: "none",
+            available: false,
+            detail: "No API key reference set".to_string(),
+        },
+    }
+}
+
+// TODO: Add commands for getting/setting keys via keyring in commands.rs
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::model_config;
+
+    fn with_key_ref(api_key_ref: Option<&str>) -> ModelConfig {
+        ModelConfig { api_key_ref: api_key_ref.map(str::to_string), ..model_config("Key test", "{}") }
+    }
+
+    #[test]
+    fn key_status_reports_each_source() {
+        std::env::set_var("LOCALCHAT_TEST_SET_KEY", "sk-test");
+        let set = check_api_key(&with_key_ref(Some("env:LOCALCHAT_TEST_SET_KEY")));
+        assert_eq!((set.source, set.available), ("env", true));
+        let unset = check_api_key(&with_key_ref(Some("env:LOCALCHAT_TEST_UNSET_KEY")));
+        assert_eq!((unset.source, unset.available), ("env", false));
+
+        let missing_file = check_api_key(&with_key_ref(Some("file:/nonexistent/localchat/key")));
+        assert_eq!((missing_file.source, missing_file.available), ("file", false));
+        assert!(missing_file.detail.contains("does not exist"), "{}", missing_file.detail);
+
+        // No entry is stored for a fresh config, whether or not a keyring is reachable
+        let keyring = check_api_key(&with_key_ref(Some("keyring")));
+        assert_eq!((keyring.source, keyring.available), ("keyring", false));
+
+        let unsupported = check_api_key(&with_key_ref(Some("vault:secret")));
+        assert_eq!((unsupported.source, unsupported.available), ("none", false));
+        assert!(unsupported.detail.contains("vault:secret"));
+        let none = check_api_key(&with_key_ref(None));
+        assert_eq!((none.source, none.available), ("none", false));
+    }
+
+    #[test]
+    fn keys_are_read_from_the_referenced_source() {
+        std::env::set_var("LOCALCHAT_TEST_READ_KEY", "sk-from-env");
+        assert_eq!(get_api_key(&with_key_ref(Some("env:LOCALCHAT_TEST_READ_KEY"))).unwrap(), "sk-from-env");
+        assert!(get_api_key(&with_key_ref(Some("env:LOCALCHAT_TEST_UNSET_KEY"))).is_err());
+        assert!(get_api_key(&with_key_ref(Some("keyring"))).is_err());
+        assert!(get_api_key(&with_key_ref(Some("vault:secret"))).unwrap_err().to_string().contains("Unsupported"));
+        assert!(get_api_key(&with_key_ref(None)).unwrap_err().to_string().contains("not set"));
+    }
+
+    #[test]
+    fn key_files_are_read_trimmed() {
+        let dir = crate::test_support::temp_dir();
+        let valid = dir.join("valid.key");
+        std::fs::write(&valid, "  sk-from-file\n").unwrap();
+        let empty = dir.join("empty.key");
+        std::fs::write(&empty, " \n").unwrap();
+        let file_ref = |path: &std::path::Path| with_key_ref(Some(&format!("file:{}", path.display())));
+
+        assert_eq!(get_api_key(&file_ref(&valid)).unwrap(), "sk-from-file");
+        let missing = get_api_key(&file_ref(&dir.join("missing.key"))).unwrap_err().to_string();
+        assert!(missing.contains("does not exist"), "{}", missing);
+        let blank = get_api_key(&file_ref(&empty)).unwrap_err().to_string();
+        assert!(blank.contains("is empty"), "{}", blank);
+        assert!(check_api_key(&file_ref(&valid)).available);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
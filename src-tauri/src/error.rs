@@ -0,0 +1,118 @@
+// Error type returned by Tauri commands. Serializes as {"kind": "...", "message": "..."}:
+// the frontend branches on `kind` and shows `message` to the user.
+
+use serde::Serialize;
+use std::fmt;
+
+/// What went wrong, coarsely enough for the UI to pick a reaction.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ErrorKind {
+    NotFound,   // The conversation, message or config doesn't exist
+    Validation, // Malformed IDs, empty names, values out of range, operations not allowed right now
+    Storage,    // Database or settings read/write failed
+    StorageUnavailable, // The database failed to open at startup; the app is in safe mode
+    ApiKey,     // Key missing, unreadable or rejected by the keyring
+    Provider,   // Provider unsupported, rejected the request or broke the stream
+    Cancelled,  // The user stopped it, or the request it answered is gone
+    Locked,     // The conversation is locked against new messages
+    OfflineMode, // Offline mode is on, so nothing is sent over the network
+    Internal,   // Anything else: files, clipboard, events
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CommandError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into() }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::NotFound, message)
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Validation, message)
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Storage, message)
+    }
+
+    pub fn storage_unavailable(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::StorageUnavailable, message)
+    }
+
+    pub fn api_key(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::ApiKey, message)
+    }
+
+    pub fn provider(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Provider, message)
+    }
+
+    pub fn cancelled(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Cancelled, message)
+    }
+
+    pub fn locked(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Locked, message)
+    }
+
+    pub fn offline_mode(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::OfflineMode, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorKind::Internal, message)
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_serialize_as_kind_and_message() {
+        let error = CommandError::not_found("Conversation 42 not found");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({ "kind": "notFound", "message": "Conversation 42 not found" })
+        );
+        assert_eq!(error.to_string(), "Conversation 42 not found");
+    }
+
+    #[test]
+    fn kinds_serialize_in_camel_case() {
+        let kinds = [
+            (ErrorKind::NotFound, "notFound"),
+            (ErrorKind::Validation, "validation"),
+            (ErrorKind::Storage, "storage"),
+            (ErrorKind::StorageUnavailable, "storageUnavailable"),
+            (ErrorKind::ApiKey, "apiKey"),
+            (ErrorKind::Provider, "provider"),
+            (ErrorKind::Cancelled, "cancelled"),
+            (ErrorKind::Locked, "locked"),
+            (ErrorKind::OfflineMode, "offlineMode"),
+            (ErrorKind::Internal, "internal"),
+        ];
+        for (kind, expected) in kinds {
+            let serialized = serde_json::to_value(CommandError::new(kind, "message")).unwrap();
+            assert_eq!(serialized["kind"], expected);
+            assert_eq!(serialized.as_object().unwrap().len(), 2);
+        }
+    }
+}
+
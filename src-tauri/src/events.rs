@@ -0,0 +1,208 @@
+// Typed payloads for events emitted to the frontend
+
+use crate::error::ErrorKind;
+use crate::models::{ConversationSummary, Message};
+use serde::Serialize;
+
+pub const ASSISTANT_STREAM_STARTED: &str = "assistant_stream_started";
+pub const REGENERATION_COMPLETE: &str = "regeneration_complete";
+pub const BUDGET_WARNING: &str = "budget_warning";
+pub const BUDGET_EXCEEDED: &str = "budget_exceeded";
+pub const GENERATION_CANCELLED: &str = "generation_cancelled";
+pub const GENERATION_FAILED: &str = "generation_failed";
+pub const HEALTH_REPORT: &str = "health_report"; // Payload: health::HealthReport
+pub const ASSISTANT_TOOL_REQUEST: &str = "assistant_tool_request";
+pub const ASSISTANT_ANNOTATIONS: &str = "assistant_annotations";
+pub const GENERATION_PROGRESS: &str = "generation_progress";
+pub const PROMPTS_CHANGED: &str = "prompts_changed"; // Payload: prompt_files::PromptsChanged
+pub const THEME_CHANGED: &str = "theme_changed"; // Payload: theme::ThemeInfo
+pub const CONVERSATION_UPDATED: &str = "conversation_updated"; // Sent via `AppState::notify_conversation_updated`
+pub const CONVERSATION_SIZE_WARNING: &str = "conversation_size_warning";
+pub const OFFLINE_MODE_CHANGED: &str = "offline_mode_changed"; // Payload: OfflineModeChanged
+pub const API_KEY_MISSING: &str = "api_key_missing";
+pub const SUMMARY_CHUNK: &str = "summary_chunk";
+pub const SUMMARY_FINISHED: &str = "summary_finished";
+pub const CONTENT_FILTERED: &str = "content_filtered";
+
+/// Which flow started an assistant stream.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamKind {
+    Send,
+    Regenerate,
+    Continue,
+    Compare,
+}
+
+/// Payload of `assistant_stream_started`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamStarted {
+    pub conversation_id: String,
+    pub message_id: String,
+    // The user message this response answers, so the UI can pair it with its optimistic bubble
+    pub user_message_id: Option<String>,
+    pub model_config_id: String,
+    pub model_name: String,
+    pub kind: StreamKind,
+    // Regenerations only: the assistant message being replaced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaces_message_id: Option<String>,
+    // Comparisons only: the `send_message_multi` fan-out this answer belongs to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comparison_id: Option<String>,
+}
+
+/// Payload of `regeneration_complete`: both answers, so the UI can diff them.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RegenerationComplete {
+    pub conversation_id: String,
+    pub previous_message_id: String,
+    pub message_id: String,
+    pub old_content: String,
+    pub new_content: String,
+}
+
+/// Payload of `budget_warning`, sent when a request is made at or above the warning threshold.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetWarning {
+    pub spent_usd: f64,
+    pub budget_usd: f64,
+    pub fraction_spent: f64,
+    pub blocked: bool, // The request was refused because the budget is exhausted
+}
+
+/// Payload of `budget_exceeded`, sent when a request is refused by the conversation's token budget.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenBudgetExceeded {
+    pub conversation_id: String,
+    pub tokens_used: i64,
+    pub token_budget: i64,
+}
+
+/// Payload of `generation_cancelled`, sent as soon as a stream loop sees the stop
+/// request. `assistant_stream_finished` still follows once the partial answer is saved, or
+/// with `saved: false` when the answer had no content yet and was dropped.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationCancelled {
+    pub conversation_id: String,
+    pub message_id: String,
+}
+
+/// Payload of `generation_failed`. `message` is the saved error message (with any partial
+/// content), so the UI can render it even if the stream never started. A regeneration that
+/// fails before producing output doesn't save it: the previous answer stays instead.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationFailed {
+    pub conversation_id: String,
+    pub message: Message,
+    pub category: String, // "api_key" | "request" | "stream" | "server" | "provider" | "empty" | "offline" | "content_filter"
+    pub kind: ErrorKind, // Same classification commands use for their errors
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>, // Server's error code, for "server" failures
+    pub retryable: bool, // Whether sending the request again may succeed, e.g. after a 429 or 5xx
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>, // How long the server asked to wait first (`Retry-After`)
+}
+
+/// Payload of `content_filtered`: the provider's moderation stopped an answer, either with
+/// `finish_reason: "content_filter"` or a moderation error. A blocked answer with no content
+/// is also reported through `generation_failed`; `partial` answers are saved as they are.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentFiltered {
+    pub conversation_id: String,
+    pub message_id: String,
+    pub partial: bool, // Some of the answer arrived before the filter stopped it
+}
+
+/// Payload of `generation_progress`, sent every few tokens while an answer streams.
+/// Token counts are estimates (about four characters per token).
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GenerationProgress {
+    pub message_id: String,
+    pub approx_tokens: usize,
+    pub max_tokens: Option<u32>, // The config's max_tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub percent: Option<u8>, // Only when max_tokens is set; capped at 100
+}
+
+/// Payload of `assistant_tool_request`, sent when a tool with an "ask" policy is requested.
+/// Answer with `respond_tool_permission(requestId, ...)` before `timeoutSecs` run out.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolPermissionRequest {
+    pub request_id: String,
+    pub conversation_id: String,
+    pub message_id: String,
+    pub tool_call_id: String,
+    pub tool_name: String,
+    pub arguments: String, // JSON-encoded, as produced by the model
+    pub timeout_secs: u64,
+}
+
+/// Payload of `conversation_size_warning`, sent once per session when a conversation
+/// reaches the configured message count, suggesting `split_conversation`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationSizeWarning {
+    pub conversation_id: String,
+    pub message_count: i64,
+    pub threshold: i64,
+}
+
+/// Payload of `offline_mode_changed`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OfflineModeChanged {
+    pub enabled: bool,
+}
+
+/// Payload of `api_key_missing`, sent when a generation can't start because the config's API
+/// key could not be read, so the UI can ask for it. Comes alongside the usual failure events.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKeyMissing {
+    pub conversation_id: String,
+    pub model_config_id: String,
+    pub model_config_name: String,
+    pub api_key_ref: Option<String>, // "keyring", "env:NAME" or "file:PATH", None when unset
+    pub error: String,
+}
+
+/// Payload of `summary_chunk`: the next piece of a `summarize_conversation` summary.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SummaryChunk {
+    pub conversation_id: String,
+    pub summary_id: String,
+    pub delta: String,
+    pub seq: u64,
+}
+
+/// Payload of `summary_finished`, sent once per summary however it ended.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SummaryFinished {
+    pub conversation_id: String,
+    pub summary_id: String,
+    pub summary: String, // Everything streamed, also when cancelled or failed part way
+    pub cancelled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Payload of `conversation_updated`. `summary` is None when the conversation no longer exists.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationUpdated {
+    pub conversation_id: String,
+    pub summary: Option<ConversationSummary>,
+}
@@ -0,0 +1,226 @@
+// Rendering of conversations for export (files, clipboard)
+
+use crate::models::{Conversation, Message};
+use crate::prompt;
+use std::collections::HashSet;
+
+/// Output formats supported by the export commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+    Text, // Plain "User:"/"Assistant:" transcript
+    Prompt, // OpenAI-style messages array, replayable against an API
+}
+
+impl ExportFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "json" => Ok(Self::Json),
+            "html" => Ok(Self::Html),
+            "text" | "txt" => Ok(Self::Text),
+            "prompt" => Ok(Self::Prompt),
+            other => Err(format!("Unsupported export format: {}", other)),
+        }
+    }
+}
+
+/// Narrows `messages` down to the requested ids, keeping chronological order.
+/// `None` selects everything. Ids that don't belong to the conversation are an error.
+pub fn select_messages(messages: Vec<Message>, message_ids: Option<&[String]>) -> Result<Vec<Message>, String> {
+    let Some(message_ids) = message_ids else {
+        return Ok(messages);
+    };
+
+    let known: HashSet<String> = messages.iter().map(|m| m.id.to_string()).collect();
+    let unknown: Vec<&str> = message_ids
+        .iter()
+        .filter(|id| !known.contains(id.as_str()))
+        .map(|id| id.as_str())
+        .collect();
+    if !unknown.is_empty() {
+        return Err(format!(
+            "Messages not found in this conversation: {}",
+            unknown.join(", ")
+        ));
+    }
+
+    let wanted: HashSet<&str> = message_ids.iter().map(|id| id.as_str()).collect();
+    // `messages` comes from storage already ordered by timestamp
+    Ok(messages
+        .into_iter()
+        .filter(|m| wanted.contains(m.id.to_string().as_str()))
+        .collect())
+}
+
+/// Renders the conversation in `format`. `excerpt` marks the output as a partial selection.
+/// `system_prompt` is the effective system prompt, used by `Prompt` only.
+pub fn render(
+    format: ExportFormat,
+    conversation: &Conversation,
+    messages: &[Message],
+    excerpt: bool,
+    system_prompt: Option<&str>,
+) -> Result<String, String> {
+    match format {
+        ExportFormat::Markdown => Ok(render_markdown(conversation, messages, excerpt)),
+        ExportFormat::Json => render_json(conversation, messages, excerpt),
+        ExportFormat::Html => Ok(render_html(conversation, messages, excerpt)),
+        ExportFormat::Text => Ok(render_text(messages)),
+        ExportFormat::Prompt => render_prompt(messages, system_prompt),
+    }
+}
+
+fn role_label(role: &str) -> String {
+    let mut chars = role.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn render_markdown(conversation: &Conversation, messages: &[Message], excerpt: bool) -> String {
+    let mut out = format!("# {}\n\n", conversation.title);
+    if excerpt {
+        out.push_str(&format!("_Excerpt of \"{}\"_\n\n", conversation.title));
+    }
+    for message in messages {
+        out.push_str(&format!(
+            "**{}** ({})\n\n{}\n\n",
+            role_label(&message.role),
+            message.timestamp.format("%Y-%m-%d %H:%M"),
+            message.content.trim_end()
+        ));
+    }
+    out
+}
+
+fn render_json(conversation: &Conversation, messages: &[Message], excerpt: bool) -> Result<String, String> {
+    let value = serde_json::json!({
+        "conversation": conversation,
+        "excerpt": excerpt,
+        "messages": messages,
+    });
+    serde_json::to_string_pretty(&value).map_err(|e| format!("Failed to serialize export: {}", e))
+}
+
+fn render_text(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|message| format!("{}: {}", role_label(&message.role), message.content.trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// Tool results, and assistant turns that only requested tools, are internal to the tool loop
+fn is_tool_internal(message: &Message) -> bool {
+    message.role == "tool"
+        || (message.content.trim().is_empty() && message.metadata_map().contains_key("tool_calls"))
+}
+
+fn render_prompt(messages: &[Message], system_prompt: Option<&str>) -> Result<String, String> {
+    let mut api_messages = Vec::new();
+    if let Some(system_prompt) = system_prompt.filter(|p| !p.trim().is_empty()) {
+        api_messages.push(serde_json::json!({ "role": "system", "content": system_prompt }));
+    }
+    for message in prompt::filter_history(messages.to_vec()) {
+        if is_tool_internal(&message) {
+            continue;
+        }
+        let mut entry = serde_json::json!({ "role": message.role, "content": message.content });
+        if let Some(name) = &message.name {
+            entry["name"] = serde_json::json!(name);
+        }
+        api_messages.push(entry);
+    }
+    serde_json::to_string_pretty(&api_messages).map_err(|e| format!("Failed to serialize export: {}", e))
+}
+
+// Roles the fine-tuning format accepts
+const FINE_TUNE_ROLES: [&str; 3] = ["system", "user", "assistant"];
+
+/// Renders the conversation as one line of an OpenAI fine-tuning JSONL file:
+/// `{"messages": [{"role": ..., "content": ...}, ...]}` without a trailing newline.
+/// Roles other than system, user and assistant are left out, as are tool-loop turns.
+pub fn render_fine_tune_line(messages: &[Message], system_prompt: Option<&str>) -> Result<String, String> {
+    let mut api_messages = Vec::new();
+    if let Some(system_prompt) = system_prompt.filter(|p| !p.trim().is_empty()) {
+        api_messages.push(serde_json::json!({ "role": "system", "content": system_prompt }));
+    }
+    for message in prompt::filter_history(messages.to_vec()) {
+        if is_tool_internal(&message) || !FINE_TUNE_ROLES.contains(&message.role.as_str()) {
+            continue;
+        }
+        api_messages.push(serde_json::json!({ "role": message.role, "content": message.content }));
+    }
+    // serde_json escapes newlines inside strings, so the compact form stays on one line
+    serde_json::to_string(&serde_json::json!({ "messages": api_messages }))
+        .map_err(|e| format!("Failed to serialize export: {}", e))
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn render_html(conversation: &Conversation, messages: &[Message], excerpt: bool) -> String {
+    let title = escape_html(&conversation.title);
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n</head>\n<body>\n<h1>{}</h1>\n",
+        title, title
+    );
+    if excerpt {
+        out.push_str(&format!("<p><em>Excerpt of \"{}\"</em></p>\n", title));
+    }
+    for message in messages {
+        out.push_str(&format!(
+            "<div class=\"message {}\">\n<h3>{} <small>{}</small></h3>\n<pre>{}</pre>\n</div>\n",
+            escape_html(&message.role),
+            escape_html(&role_label(&message.role)),
+            message.timestamp.format("%Y-%m-%d %H:%M"),
+            escape_html(&message.content)
+        ));
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::message;
+    use uuid::Uuid;
+
+    #[test]
+    fn fine_tune_lines_parse_back_into_the_messages() {
+        let conversation_id = Uuid::new_v4();
+        let mut failed = message(conversation_id, "assistant", "Request failed");
+        failed.set_metadata_field("error", serde_json::json!(true));
+        let mut tool_request = message(conversation_id, "assistant", "");
+        tool_request.set_metadata_field("tool_calls", serde_json::json!([{"id": "call_1"}]));
+        let messages = [
+            message(conversation_id, "user", "Say \"hi\"\nthen stop"),
+            failed,
+            tool_request,
+            message(conversation_id, "tool", "{\"result\": 1}"),
+            message(conversation_id, "note", "Remember this one"),
+            message(conversation_id, "assistant", "\"hi\" \\ done\t✓"),
+        ];
+
+        let line = render_fine_tune_line(&messages, Some("Be terse.")).unwrap();
+        assert!(!line.contains('\n'));
+        let parsed: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed, serde_json::json!({"messages": [
+            {"role": "system", "content": "Be terse."},
+            {"role": "user", "content": "Say \"hi\"\nthen stop"},
+            {"role": "assistant", "content": "\"hi\" \\ done\t✓"},
+        ]}));
+
+        let without_system: serde_json::Value = serde_json::from_str(&render_fine_tune_line(&messages, Some("  ")).unwrap()).unwrap();
+        assert_eq!(without_system["messages"][0]["role"], "user");
+    }
+}
@@ -0,0 +1,1816 @@
+// The background streaming flow shared by send, regenerate, continue and compare: resolve the
+// key and provider, open the stream, relay chunks (paced, with smoothing on) with cancellation
+// checks, then save the answer.
+// Commands gather a `GenerationRequest` while they hold the storage lock and spawn
+// `run_generation` with it.
+
+use crate::api::{ApiError, HttpStatusError, ParsedProviderOptions, ProviderStreamError, StreamEvent, TokenUsage, ToolCall};
+use crate::config;
+use crate::error::ErrorKind;
+use crate::events::{self, ApiKeyMissing, ContentFiltered, GenerationCancelled, GenerationFailed, GenerationProgress, RegenerationComplete, StreamKind, StreamStarted};
+use crate::memory;
+use crate::models::{Conversation, Message, ModelConfig, Persona};
+use crate::redaction;
+use crate::smoothing::{self, DeltaPacer, StreamSmoothing};
+use crate::state::{ActiveStream, AppState};
+use crate::tools;
+use chrono::Utc;
+use futures::StreamExt;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// Categories recorded on failed generations
+pub const ERROR_API_KEY: &str = "api_key"; // Key could not be resolved
+pub const ERROR_REQUEST: &str = "request"; // Provider rejected or never answered the request
+pub const ERROR_STREAM: &str = "stream"; // Stream broke after it started
+pub const ERROR_PROVIDER: &str = "provider"; // Config names a provider that isn't implemented
+pub const ERROR_SERVER: &str = "server"; // Server reported an error inside the stream body
+pub const ERROR_EMPTY: &str = "empty"; // Stream finished without any content
+pub const ERROR_OFFLINE: &str = "offline"; // Offline mode was turned on before the request went out
+pub const ERROR_CONTENT_FILTER: &str = "content_filter"; // Provider's moderation blocked the prompt or answer
+
+// How many times one answer is resumed after its connection dropped (`resume_on_disconnect`)
+const MAX_RESUME_ATTEMPTS: u32 = 2;
+
+// finish_reason (and error code) providers such as Azure report when moderation cut the answer
+const CONTENT_FILTER: &str = "content_filter";
+
+/// Everything a background generation needs, gathered by the command that starts it.
+pub struct GenerationRequest {
+    pub conversation: Conversation,
+    pub model_config: ModelConfig,
+    pub summarizer: Option<ModelConfig>, // Summarizes history that doesn't fit; None uses `model_config`
+    pub smoothing: StreamSmoothing,
+    pub trim_leading_whitespace: bool, // Drop whitespace the answer starts with
+    pub preview: bool, // Stop after the first sentence of the answer
+    pub system_message: Message,
+    pub persona: Option<Persona>, // Recorded on the answer
+    pub history: Vec<Message>, // Filtered history the answer follows
+    pub kind: StreamKind,
+    pub user_message_id: Option<Uuid>, // The user message being answered, if any
+    pub replaces: Option<ReplacedMessage>, // Regenerations only
+    pub extends: Option<ExtendedMessage>, // Continuations only
+    pub comparison: Option<ComparisonVariant>, // Comparisons only
+}
+
+/// The answer a regeneration replaces, kept so the UI can compare old and new.
+pub struct ReplacedMessage {
+    pub message_id: Uuid,
+    pub content: String,
+    pub variant_group: Option<Uuid>, // Set when the replaced answer is kept as an alternative in this group
+}
+
+/// The stored answer a continuation appends to. Chunks stream under its ID and the merged
+/// text replaces its content; when nothing arrives it stays as it was.
+pub struct ExtendedMessage {
+    pub message: Message,
+    pub separator: &'static str, // Put before the first delta
+}
+
+/// One model of a `send_message_multi` fan-out. The answer's ID is picked up front so the
+/// whole fan-out can be cancelled, and the answer is tagged with the comparison.
+pub struct ComparisonVariant {
+    pub comparison_id: Uuid,
+    pub message_id: Uuid,
+}
+
+/// Streams one assistant answer for `request` and saves it, failed or not.
+/// Waits for a stream permit first, so this is meant to be spawned.
+pub async fn run_generation(state: AppState, request: GenerationRequest) {
+    let GenerationRequest {
+        conversation,
+        model_config,
+        summarizer,
+        smoothing,
+        trim_leading_whitespace,
+        preview,
+        system_message,
+        persona,
+        history,
+        kind,
+        user_message_id,
+        replaces,
+        extends,
+        comparison,
+    } = request;
+    let conv_uuid = conversation.id;
+    log::info!("Generation ({:?}) started for conversation {}", kind, conv_uuid);
+    let Some(_stream_permit) = state.acquire_stream_permit(conv_uuid).await else {
+        return;
+    };
+
+    // --- Get API Key ---
+    let api_key = match config::get_api_key(&model_config) {
+        Ok(key) => key,
+        Err(e) => {
+            log::error!("Generation: Failed to get API key for {}: {:?}", conv_uuid, e);
+            emit_api_key_missing(&state, conv_uuid, &model_config, &e);
+            let failure = StreamFailure { category: ERROR_API_KEY, error: format!("{:#}", e), code: None, retryable: false, retry_after: None };
+            fail_before_stream(&state, &conversation, &failure, replaces.is_some(), extends.as_ref(), comparison.as_ref()).await;
+            return;
+        }
+    };
+
+    // --- Prepare messages for API (including system prompt) ---
+    let summarizer = summarizer.as_ref().unwrap_or(&model_config);
+    let mut api_messages =
+        memory::build_api_messages(&state, &conversation, system_message, history, &model_config, summarizer).await;
+    redaction::redact_outgoing(&state, &mut api_messages).await;
+
+    // --- Get API Provider ---
+    let api_provider = match state.provider_for(&model_config) {
+        Ok(provider) => provider,
+        Err(e) => {
+            log::error!("Generation: {} (conversation {})", e, conv_uuid);
+            let category = if e.kind == ErrorKind::OfflineMode { ERROR_OFFLINE } else { ERROR_PROVIDER };
+            let failure = StreamFailure { category, error: e.message, code: None, retryable: false, retry_after: None };
+            fail_before_stream(&state, &conversation, &failure, replaces.is_some(), extends.as_ref(), comparison.as_ref()).await;
+            return;
+        }
+    };
+
+    // --- Make the API call (Streaming) ---
+    log::info!("Generation: Starting stream request for conversation {}", conv_uuid);
+    let mut delta_stream = match api_provider.send_chat_stream_request(&model_config, &api_key, &api_messages).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::error!("Generation: Failed to initiate stream request for {}: {:?}", conv_uuid, e);
+            let failure = StreamFailure {
+                category: if is_content_filter_error(&e) { ERROR_CONTENT_FILTER } else { ERROR_REQUEST },
+                error: format!("{:#}", e),
+                code: None,
+                retryable: ApiError::classify(&e).is_retryable(),
+                retry_after: ApiError::retry_after(&e),
+            };
+            fail_before_stream(&state, &conversation, &failure, replaces.is_some(), extends.as_ref(), comparison.as_ref()).await;
+            return;
+        }
+    };
+
+    // A continuation streams into the message it extends, so the UI appends in place
+    let assistant_message_id = match (&extends, &comparison) {
+        (Some(extended), _) => extended.message.id,
+        (None, Some(variant)) => variant.message_id,
+        (None, None) => Uuid::new_v4(),
+    };
+    if let Err(e) = state.emit_to_conversation(
+        conv_uuid,
+        events::ASSISTANT_STREAM_STARTED,
+        StreamStarted {
+            conversation_id: conv_uuid.to_string(),
+            message_id: assistant_message_id.to_string(),
+            user_message_id: user_message_id.map(|id| id.to_string()),
+            model_config_id: model_config.id.to_string(),
+            model_name: model_display_name(&model_config),
+            kind,
+            replaces_message_id: replaces.as_ref().map(|r| r.message_id.to_string()),
+            comparison_id: comparison.as_ref().map(|c| c.comparison_id.to_string()),
+        },
+    ) {
+        log::error!("Generation [{}]: Failed to emit stream started event: {:?}. Aborting.", assistant_message_id, e);
+        return;
+    }
+
+    // Register the running buffer so a reloaded frontend can recover it
+    state.active_streams.insert(assistant_message_id, ActiveStream {
+        conversation_id: conv_uuid,
+        message_id: assistant_message_id,
+        content: extends.as_ref().map(|extended| extended.message.content.clone()).unwrap_or_default(),
+        seq: 0,
+        started_at: Utc::now(),
+    });
+    let mut seq: u64 = 0;
+    let mut full_content = String::new(); // Only the new text when continuing
+    let mut finish_reason: Option<String> = None;
+    let mut tool_calls: Option<Vec<ToolCall>> = None; // Those allowed to run
+    let mut denied_tool_calls: Vec<ToolCall> = Vec::new();
+    let mut usage: Option<TokenUsage> = None;
+    let mut annotations: Vec<serde_json::Value> = Vec::new();
+    let mut stream_error: Option<StreamFailure> = None;
+    let mut cancelled = false;
+    let mut received_output = false; // Set on the first delta or tool call; a replaced answer is deleted then
+
+    let mut progress = ProgressTracker::new(&model_config);
+    let options = ParsedProviderOptions::from_config(&model_config).unwrap_or_default();
+    let retry_on_empty = options.retry_on_empty.unwrap_or(false);
+    let resume_on_disconnect = options.resume_on_disconnect.unwrap_or(false);
+    let mut resumes: u32 = 0;
+    let mut disconnected = false; // Set when the inner loop left on a resumable network error
+
+    let mut pacer = DeltaPacer::new(smoothing);
+    let mut pace = tokio::time::interval(smoothing::EVENT_INTERVAL);
+    pace.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // The answer so far is saved every CHECKPOINT_INTERVAL so a crash mid-stream keeps it
+    let mut last_checkpoint = Instant::now();
+
+    let mut retried_empty = false;
+    loop {
+        loop {
+            // Paced pieces go out between stream items; `biased` keeps them ahead of newer deltas
+            let mut delta_result = tokio::select! {
+                biased;
+                _ = pace.tick(), if pacer.has_pending() => {
+                    if state.cancelled_streams.contains_key(&assistant_message_id) {
+                        log::warn!("Generation: Cancellation requested for message {}. Stopping stream.", assistant_message_id);
+                        acknowledge_cancellation(&state, conv_uuid, assistant_message_id);
+                        cancelled = true;
+                        break;
+                    }
+                    if let Some(piece) = pacer.next_piece() {
+                        emit_chunk(&state, conv_uuid, assistant_message_id, &mut seq, piece);
+                    }
+                    continue;
+                }
+                next = delta_stream.next() => match next {
+                    Some(delta_result) => delta_result,
+                    None => break,
+                },
+            };
+            if state.cancelled_streams.contains_key(&assistant_message_id) {
+                log::warn!("Generation: Cancellation requested for message {}. Stopping stream.", assistant_message_id);
+                acknowledge_cancellation(&state, conv_uuid, assistant_message_id);
+                cancelled = true;
+                break;
+            }
+            // Whitespace-only deltas before the first real content are dropped, so neither the
+            // chunks nor the saved answer start with blank lines
+            if trim_leading_whitespace && full_content.is_empty() {
+                if let Ok(StreamEvent::Delta(delta)) = &mut delta_result {
+                    let trimmed = delta.trim_start();
+                    if trimmed.is_empty() {
+                        continue;
+                    }
+                    if trimmed.len() < delta.len() {
+                        *delta = trimmed.to_string();
+                    }
+                }
+            }
+            if !received_output && matches!(delta_result, Ok(StreamEvent::Delta(_) | StreamEvent::ToolCalls(_))) {
+                received_output = true;
+                if let Some(replaced) = replaces.as_ref().filter(|replaced| replaced.variant_group.is_none()) {
+                    delete_replaced_message(&state, conv_uuid, replaced.message_id).await;
+                }
+            }
+            match delta_result {
+                Ok(StreamEvent::Finished(reason)) => {
+                    finish_reason = Some(reason);
+                }
+                Ok(StreamEvent::Usage(reported)) => {
+                    usage = Some(reported);
+                }
+                Ok(StreamEvent::Annotations(received)) => {
+                    emit_annotations(&state, conv_uuid, assistant_message_id, &received);
+                    annotations.extend(received);
+                }
+                Ok(StreamEvent::ToolCalls(calls)) => {
+                    // Text queued before the calls goes out first
+                    if let Some(rest) = pacer.flush() {
+                        emit_chunk(&state, conv_uuid, assistant_message_id, &mut seq, rest);
+                    }
+                    // Waits on the user for calls whose policy is "ask"; only allowed ones go out
+                    let (allowed, denied) = tools::authorize_tool_calls(&state, conv_uuid, assistant_message_id, calls).await;
+                    if !allowed.is_empty() {
+                        emit_tool_calls(&state, conv_uuid, assistant_message_id, &allowed);
+                    }
+                    tool_calls = Some(allowed);
+                    denied_tool_calls = denied;
+                }
+                Ok(StreamEvent::Delta(mut delta_content)) => {
+                    if let Some(extended) = extends.as_ref().filter(|_| full_content.is_empty()) {
+                        delta_content.insert_str(0, extended.separator);
+                    }
+                    let previous_len = full_content.len();
+                    full_content.push_str(&delta_content);
+                    // Previews stop like a user cancellation once the first sentence is complete.
+                    // The scan backs up one character since the ending may straddle two deltas.
+                    let sentence_end = preview.then(|| {
+                        let scan_from = full_content[..previous_len].char_indices().last().map_or(0, |(i, _)| i);
+                        first_sentence_end(&full_content[scan_from..]).map(|end| scan_from + end)
+                    }).flatten();
+                    if let Some(end) = sentence_end {
+                        full_content.truncate(end);
+                        delta_content.truncate(end.saturating_sub(previous_len));
+                    }
+                    if let Some(payload) = progress.record(assistant_message_id, &delta_content) {
+                        if let Err(e) = state.emit_to_conversation(conv_uuid, events::GENERATION_PROGRESS, payload) {
+                            log::error!("Generation [{}]: Failed to emit progress event: {:?}", assistant_message_id, e);
+                        }
+                    }
+                    if let Some(delta) = pacer.push(delta_content).filter(|delta| !delta.is_empty()) {
+                        emit_chunk(&state, conv_uuid, assistant_message_id, &mut seq, delta);
+                    }
+                    if !conversation.ephemeral && last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                        last_checkpoint = Instant::now();
+                        save_checkpoint(&state, conv_uuid, assistant_message_id, &full_content, &model_config, extends.as_ref()).await;
+                    }
+                    if sentence_end.is_some() {
+                        log::info!("Generation [{}]: Preview reached the end of its first sentence. Stopping stream.", assistant_message_id);
+                        acknowledge_cancellation(&state, conv_uuid, assistant_message_id);
+                        cancelled = true;
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::error!("Generation [{}]: Error receiving stream delta: {:?}. Breaking loop.", assistant_message_id, e);
+                    if resume_on_disconnect && resumes < MAX_RESUME_ATTEMPTS && ApiError::classify(&e) == ApiError::Network {
+                        disconnected = true;
+                    } else {
+                        stream_error = Some(StreamFailure::from_error(&e));
+                    }
+                    break;
+                }
+            }
+        }
+        // A dropped connection is resumed by asking again with the partial answer appended,
+        // so the model continues it; the new deltas extend the same message. A truncated
+        // answer being continued already ends the request, so the partial text joins it.
+        if std::mem::take(&mut disconnected) {
+            resumes += 1;
+            log::warn!("Generation [{}]: Connection dropped. Resuming ({}/{}).", assistant_message_id, resumes, MAX_RESUME_ATTEMPTS);
+            let mut resume_messages = api_messages.clone();
+            if let Some(last) = resume_messages.last_mut().filter(|m| m.id == assistant_message_id) {
+                last.content.push_str(&full_content);
+            } else if !full_content.is_empty() {
+                resume_messages.push(Message {
+                    id: assistant_message_id,
+                    conversation_id: conv_uuid,
+                    role: "assistant".to_string(),
+                    content: full_content.clone(),
+                    timestamp: Utc::now(),
+                    metadata: None,
+                    name: None,
+                    variant_group: None,
+                });
+            }
+            match api_provider.send_chat_stream_request(&model_config, &api_key, &resume_messages).await {
+                Ok(stream) => {
+                    delta_stream = stream;
+                    continue;
+                }
+                Err(e) => {
+                    log::error!("Generation [{}]: Failed to resume the stream: {:?}", assistant_message_id, e);
+                    stream_error = Some(StreamFailure {
+                        category: ERROR_REQUEST,
+                        error: format!("{:#}", e),
+                        code: None,
+                        retryable: ApiError::classify(&e).is_retryable(),
+                        retry_after: ApiError::retry_after(&e),
+                    });
+                    break;
+                }
+            }
+        }
+        // A stream that ended normally without text or tool calls is retried once when the
+        // config sets `retry_on_empty`
+        // Moderation stopping the answer is not a hiccup, so it isn't retried
+        let filtered = finish_reason.as_deref() == Some(CONTENT_FILTER);
+        let empty = full_content.is_empty() && tool_calls.is_none() && stream_error.is_none() && !cancelled && !filtered;
+        if !empty || !retry_on_empty || retried_empty {
+            break;
+        }
+        retried_empty = true;
+        log::warn!("Generation [{}]: Provider returned an empty response. Retrying once.", assistant_message_id);
+        match api_provider.send_chat_stream_request(&model_config, &api_key, &api_messages).await {
+            Ok(stream) => delta_stream = stream,
+            Err(e) => {
+                log::error!("Generation [{}]: Failed to retry the empty response: {:?}", assistant_message_id, e);
+                stream_error = Some(StreamFailure {
+                    category: ERROR_REQUEST,
+                    error: format!("{:#}", e),
+                    code: None,
+                    retryable: ApiError::classify(&e).is_retryable(),
+                    retry_after: ApiError::retry_after(&e),
+                });
+                break;
+            }
+        }
+        finish_reason = None;
+        usage = None;
+        annotations.clear();
+    }
+    let filtered = finish_reason.as_deref() == Some(CONTENT_FILTER)
+        || stream_error.as_ref().is_some_and(|failure| failure.category == ERROR_CONTENT_FILTER);
+    // Still empty: report it as a failure rather than saving a blank answer. A regeneration
+    // keeps the answer it was replacing.
+    if full_content.is_empty() && tool_calls.is_none() && stream_error.is_none() && !cancelled {
+        stream_error = Some(if filtered {
+            log::warn!("Generation [{}]: The provider's content filter blocked the response", assistant_message_id);
+            StreamFailure {
+                category: ERROR_CONTENT_FILTER,
+                error: "The provider's content filter blocked the response.".to_string(),
+                code: None,
+                retryable: false,
+                retry_after: None,
+            }
+        } else {
+            log::warn!("Generation [{}]: Provider returned an empty response", assistant_message_id);
+            StreamFailure {
+                category: ERROR_EMPTY,
+                error: "The provider returned an empty response.".to_string(),
+                code: None,
+                retryable: true, // Empty answers are usually a hiccup of the server
+                retry_after: None,
+            }
+        });
+    }
+    // Whatever smoothing still holds goes out at once, however the stream ended
+    if let Some(rest) = pacer.flush() {
+        emit_chunk(&state, conv_uuid, assistant_message_id, &mut seq, rest);
+    }
+
+    // A regeneration that failed or was stopped before any output keeps the previous answer
+    // instead of saving an empty one in its place. A continuation that added nothing leaves
+    // its message as it was.
+    let keeps_previous = (replaces.is_some() && !received_output && (cancelled || stream_error.is_some()))
+        || (extends.is_some() && full_content.is_empty() && tool_calls.is_none());
+    // Likewise a new answer stopped before any content, or that came back empty, is dropped
+    // rather than saved blank
+    let empty = stream_error.as_ref().is_some_and(|failure| failure.category == ERROR_EMPTY);
+    let discarded = replaces.is_none() && (cancelled || empty) && full_content.is_empty() && tool_calls.is_none();
+    let kept_variant = replaces.as_ref().filter(|_| !keeps_previous).and_then(|replaced| Some((replaced.message_id, replaced.variant_group?)));
+    if let Some(replaced) = replaces.as_ref().filter(|_| !received_output && !keeps_previous && kept_variant.is_none()) {
+        delete_replaced_message(&state, conv_uuid, replaced.message_id).await;
+    }
+
+    // --- Save the assistant message, even when cancelled or failed, so the turn stays answered ---
+    let mut assistant_message = match &extends {
+        Some(extended) => {
+            let mut merged = extended.message.clone();
+            merged.content.push_str(&full_content);
+            merged
+        }
+        None => {
+            let mut message = Message {
+                id: assistant_message_id,
+                conversation_id: conv_uuid,
+                role: "assistant".to_string(),
+                content: full_content,
+                timestamp: Utc::now(),
+                metadata: None,
+                name: None,
+                variant_group: None,
+            };
+            record_model(&mut message, &model_config);
+            if let Some(persona) = &persona {
+                record_persona(&mut message, persona);
+            }
+            message
+        }
+    };
+    // The reason a continued answer stopped before no longer applies to the merged one
+    let finish_reason = finish_reason.or_else(|| extends.as_ref().map(|_| "unknown".to_string()));
+    if let Some(reason) = finish_reason {
+        assistant_message.set_metadata_field("finish_reason", serde_json::json!(reason));
+    }
+    if let Some(calls) = tool_calls.filter(|calls| !calls.is_empty()) {
+        assistant_message.set_metadata_field("tool_calls", serde_json::json!(calls));
+    }
+    if !denied_tool_calls.is_empty() {
+        assistant_message.set_metadata_field("denied_tool_calls", serde_json::json!(denied_tool_calls));
+    }
+    if !annotations.is_empty() {
+        // Citations from a continuation are added after those of the answer it extends
+        let mut merged = match assistant_message.metadata_map().remove("annotations") {
+            Some(serde_json::Value::Array(existing)) => existing,
+            _ => Vec::new(),
+        };
+        merged.extend(annotations);
+        assistant_message.set_metadata_field("annotations", serde_json::Value::Array(merged));
+    }
+    if let Some(variant) = &comparison {
+        assistant_message.set_metadata_field("comparison_id", serde_json::json!(variant.comparison_id.to_string()));
+    }
+    if filtered {
+        assistant_message.set_metadata_field("content_filtered", serde_json::json!(true));
+    }
+    if resumes > 0 {
+        assistant_message.set_metadata_field("resumed", serde_json::json!(resumes));
+    }
+    if let Some(usage) = usage {
+        record_usage(&mut assistant_message, &model_config, &usage);
+    }
+    if let Some(failure) = &stream_error {
+        if extends.is_some() {
+            // The message keeps its earlier text, so it is reported but not flagged as failed
+            emit_generation_failed(&state, &assistant_message, failure.category, &failure.error, failure.retryable);
+        } else {
+            failure.record(&state, &mut assistant_message);
+        }
+    }
+    if preview {
+        assistant_message.set_metadata_field("preview", serde_json::json!(true));
+    }
+    if let Some((_, group)) = kept_variant {
+        assistant_message.variant_group = Some(group);
+    }
+    let saved_content = assistant_message.content.clone();
+    let completion = replaces.map(|replaced| RegenerationComplete {
+        conversation_id: conv_uuid.to_string(),
+        previous_message_id: replaced.message_id.to_string(),
+        message_id: assistant_message_id.to_string(),
+        old_content: replaced.content,
+        new_content: saved_content.clone(),
+    });
+    if keeps_previous {
+        log::info!("Generation [{}]: Ended before any output; keeping the previous answer", assistant_message_id);
+    } else if discarded {
+        log::info!("Generation [{}]: Ended without any content; nothing saved", assistant_message_id);
+    } else if conversation.ephemeral {
+        state.remember_ephemeral(assistant_message);
+    } else {
+        let storage = state.storage.lock().await;
+        let saved = match &extends {
+            Some(_) => {
+                storage
+                    .update_message_content(assistant_message_id, &assistant_message.content, assistant_message.metadata.as_deref())
+                    .await
+            }
+            None => storage.save_message(&assistant_message).await,
+        };
+        if let Err(e) = saved {
+            log::error!("Generation: Failed to save assistant message {}: {:?}", assistant_message_id, e);
+        } else if let Some((previous_message_id, group)) = kept_variant {
+            // The previous answer stays as an alternative; the new one becomes the selected one
+            let grouped = match storage.set_message_variant_group(previous_message_id, group).await {
+                Ok(()) => storage.select_message_variant(group, assistant_message_id).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = grouped {
+                log::error!("Generation: Failed to group answer {} with {}: {:?}", assistant_message_id, previous_message_id, e);
+            }
+        }
+    }
+    state.notify_conversation_updated(conv_uuid);
+    state.active_streams.remove(&assistant_message_id);
+    if let Some(variant) = &comparison {
+        forget_comparison_variant(&state, variant);
+    }
+
+    if filtered {
+        let payload = ContentFiltered {
+            conversation_id: conv_uuid.to_string(),
+            message_id: assistant_message_id.to_string(),
+            partial: !saved_content.is_empty(),
+        };
+        if let Err(e) = state.emit_to_conversation(conv_uuid, events::CONTENT_FILTERED, payload) {
+            log::error!("Generation [{}]: Failed to emit content filtered event: {:?}", assistant_message_id, e);
+        }
+    }
+    // `saved` is false when no message was stored under `messageId`
+    let mut finished = serde_json::json!({
+        "messageId": assistant_message_id.to_string(),
+        "cancelled": cancelled,
+        "saved": !keeps_previous && !discarded,
+    });
+    if extends.is_some() {
+        finished["extended"] = serde_json::json!(!keeps_previous); // The message grew in place
+    }
+    if let Some(variant) = &comparison {
+        finished["comparisonId"] = serde_json::json!(variant.comparison_id.to_string());
+        finished["modelConfigId"] = serde_json::json!(model_config.id.to_string());
+    }
+    if let Err(e) = state.emit_to_conversation(conv_uuid, "assistant_stream_finished", finished) {
+        log::error!("Generation: Failed to emit finished event for {}: {:?}", assistant_message_id, e);
+    }
+    if let Some(completion) = completion.filter(|_| stream_error.is_none() && !keeps_previous) {
+        if let Err(e) = state.emit_to_conversation(conv_uuid, events::REGENERATION_COMPLETE, completion) {
+            log::error!("Generation: Failed to emit regeneration complete event: {:?}", e);
+        }
+    }
+    log::info!("Generation [{}] finished for conversation {}", assistant_message_id, conv_uuid);
+}
+
+// Byte offset just past the first sentence-ending `.`, `!` or `?` that is followed by a
+// space or newline, if `text` has one
+fn first_sentence_end(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        if matches!(c, '.' | '!' | '?') && matches!(chars.peek(), Some((_, ' ' | '\n'))) {
+            return Some(index + c.len_utf8());
+        }
+    }
+    None
+}
+
+// Time between saves of an answer that is still streaming
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(5);
+
+// Saves the answer streamed so far, marked `incomplete`; a continuation saves the message it
+// extends with `content` appended. The final save replaces the row.
+async fn save_checkpoint(
+    state: &AppState,
+    conversation_id: Uuid,
+    message_id: Uuid,
+    content: &str,
+    model_config: &ModelConfig,
+    extends: Option<&ExtendedMessage>,
+) {
+    let mut message = match extends {
+        Some(extended) => {
+            let mut message = extended.message.clone();
+            message.content.push_str(content);
+            message
+        }
+        None => {
+            let mut message = Message {
+                id: message_id,
+                conversation_id,
+                role: "assistant".to_string(),
+                content: content.to_string(),
+                timestamp: Utc::now(),
+                metadata: None,
+                name: None,
+                variant_group: None,
+            };
+            record_model(&mut message, model_config);
+            message
+        }
+    };
+    message.set_metadata_field("incomplete", serde_json::json!(true));
+    let storage = state.storage.lock().await;
+    if let Err(e) = storage.save_message(&message).await {
+        log::warn!("Generation [{}]: Failed to save partial answer: {:?}", message_id, e);
+    }
+}
+
+// Estimated tokens between `generation_progress` events
+const PROGRESS_INTERVAL_TOKENS: usize = 20;
+
+// Counts streamed characters for `generation_progress`, using the same four-characters-per-token
+// estimate as `prompt::estimate_tokens`
+struct ProgressTracker {
+    max_tokens: Option<u32>,
+    chars: usize,
+    reported_tokens: usize,
+}
+
+impl ProgressTracker {
+    fn new(model_config: &ModelConfig) -> Self {
+        let max_tokens = ParsedProviderOptions::from_config(model_config)
+            .ok()
+            .and_then(|options| options.max_tokens)
+            .filter(|max| *max > 0);
+        Self { max_tokens, chars: 0, reported_tokens: 0 }
+    }
+
+    // Adds `delta`; returns a payload once another PROGRESS_INTERVAL_TOKENS have streamed
+    fn record(&mut self, message_id: Uuid, delta: &str) -> Option<GenerationProgress> {
+        self.chars += delta.chars().count();
+        let approx_tokens = self.chars / 4;
+        if approx_tokens < self.reported_tokens + PROGRESS_INTERVAL_TOKENS {
+            return None;
+        }
+        self.reported_tokens = approx_tokens;
+        Some(GenerationProgress {
+            message_id: message_id.to_string(),
+            approx_tokens,
+            max_tokens: self.max_tokens,
+            percent: self.max_tokens.map(|max| (approx_tokens * 100 / max as usize).min(100) as u8),
+        })
+    }
+}
+
+/// Model identifier shown to the user: the configured `model`, else the config name.
+pub fn model_display_name(model_config: &ModelConfig) -> String {
+    ParsedProviderOptions::from_config(model_config)
+        .ok()
+        .and_then(|options| options.model)
+        .unwrap_or_else(|| model_config.name.clone())
+}
+
+// Records which config and model produced an assistant message, and the request parameters
+// it was sent with; the conversation's model and the config's options may change later
+pub fn record_model(message: &mut Message, model_config: &ModelConfig) {
+    message.set_metadata_field("model_config_id", serde_json::json!(model_config.id.to_string()));
+    message.set_metadata_field("model_name", serde_json::json!(model_display_name(model_config)));
+    if let Ok(options) = ParsedProviderOptions::from_config(model_config) {
+        message.set_metadata_field("request_params", serde_json::Value::Object(options.request_params()));
+    }
+}
+
+// Records the persona an assistant message was written as, so old chats still show who was speaking
+pub fn record_persona(message: &mut Message, persona: &Persona) {
+    message.set_metadata_field("persona", serde_json::json!({ "id": persona.id.to_string(), "name": persona.name }));
+}
+
+// Adds reported token usage (and its cost, when the config is priced) to a message's metadata.
+// Adds onto existing values so a continued message accumulates both requests.
+pub fn record_usage(message: &mut Message, model_config: &ModelConfig, usage: &TokenUsage) {
+    let metadata = message.metadata_map();
+    let existing_u64 = |key: &str| metadata.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+    let prompt_tokens = existing_u64("prompt_tokens") + usage.prompt_tokens;
+    let completion_tokens = existing_u64("completion_tokens") + usage.completion_tokens;
+    let existing_cost = metadata.get("cost_usd").and_then(|v| v.as_f64());
+
+    message.set_metadata_field("prompt_tokens", serde_json::json!(prompt_tokens));
+    message.set_metadata_field("completion_tokens", serde_json::json!(completion_tokens));
+    let cost = ParsedProviderOptions::from_config(model_config)
+        .ok()
+        .and_then(|options| options.cost_usd(usage));
+    if let Some(cost) = cost {
+        message.set_metadata_field("cost_usd", serde_json::json!(existing_cost.unwrap_or(0.0) + cost));
+    }
+}
+
+// Notifies the frontend of the tool calls an assistant message ended with
+pub fn emit_tool_calls(state: &AppState, conversation_id: Uuid, message_id: Uuid, tool_calls: &[ToolCall]) {
+    let payload = serde_json::json!({
+        "conversationId": conversation_id.to_string(),
+        "messageId": message_id.to_string(),
+        "toolCalls": tool_calls,
+    });
+    if let Err(e) = state.emit_to_conversation(conversation_id, "assistant_tool_call", payload) {
+        log::error!("Failed to emit tool call event for message {}: {:?}", message_id, e);
+    }
+}
+
+// Sends citations as they arrive; the saved message carries all of them under `annotations`
+pub fn emit_annotations(state: &AppState, conversation_id: Uuid, message_id: Uuid, annotations: &[serde_json::Value]) {
+    let payload = serde_json::json!({
+        "conversationId": conversation_id.to_string(),
+        "messageId": message_id.to_string(),
+        "annotations": annotations,
+    });
+    if let Err(e) = state.emit_to_conversation(conversation_id, events::ASSISTANT_ANNOTATIONS, payload) {
+        log::error!("Failed to emit annotations event for message {}: {:?}", message_id, e);
+    }
+}
+
+// Tells the UI the config's API key could not be read, so it can prompt for one
+pub fn emit_api_key_missing(state: &AppState, conversation_id: Uuid, model_config: &ModelConfig, error: &anyhow::Error) {
+    let payload = ApiKeyMissing {
+        conversation_id: conversation_id.to_string(),
+        model_config_id: model_config.id.to_string(),
+        model_config_name: model_config.name.clone(),
+        api_key_ref: model_config.api_key_ref.clone(),
+        error: format!("{:#}", error),
+    };
+    if let Err(e) = state.emit_to_conversation(conversation_id, events::API_KEY_MISSING, payload) {
+        log::error!("Failed to emit API key missing event for config {}: {:?}", model_config.id, e);
+    }
+}
+
+// Clears the stop request for `message_id` and tells the frontend the stream loop has stopped
+pub fn acknowledge_cancellation(state: &AppState, conversation_id: Uuid, message_id: Uuid) {
+    state.cancelled_streams.remove(&message_id);
+    let payload = GenerationCancelled {
+        conversation_id: conversation_id.to_string(),
+        message_id: message_id.to_string(),
+    };
+    if let Err(e) = state.emit_to_conversation(conversation_id, events::GENERATION_CANCELLED, payload) {
+        log::error!("Failed to emit cancellation event for message {}: {:?}", message_id, e);
+    }
+}
+
+pub fn emit_generation_failed(state: &AppState, message: &Message, category: &str, error: &str, retryable: bool) {
+    let payload = GenerationFailed {
+        conversation_id: message.conversation_id.to_string(),
+        message: message.clone(),
+        category: category.to_string(),
+        kind: failure_kind(category),
+        error: error.to_string(),
+        code: message.metadata_map().get("error_code").and_then(|c| c.as_str()).map(str::to_string),
+        retryable,
+        retry_after_secs: message.metadata_map().get("retry_after_secs").and_then(|s| s.as_u64()),
+    };
+    if let Err(e) = state.emit_to_conversation(message.conversation_id, events::GENERATION_FAILED, payload) {
+        log::error!("Failed to emit generation failed event for message {}: {:?}", message.id, e);
+    }
+}
+
+// The command error kind matching a failure category
+pub fn failure_kind(category: &str) -> ErrorKind {
+    match category {
+        ERROR_API_KEY => ErrorKind::ApiKey,
+        ERROR_OFFLINE => ErrorKind::OfflineMode,
+        _ => ErrorKind::Provider,
+    }
+}
+
+// Whether `e` is the provider's moderation rejecting the request or answer: an error payload
+// with the `content_filter` code, or a 400 whose body names it (Azure)
+fn is_content_filter_error(e: &anyhow::Error) -> bool {
+    if let Some(server) = e.downcast_ref::<ProviderStreamError>() {
+        return server.code.as_deref() == Some(CONTENT_FILTER);
+    }
+    e.downcast_ref::<HttpStatusError>()
+        .is_some_and(|status| status.status == 400 && status.body.contains(CONTENT_FILTER))
+}
+
+/// Why a stream broke after it started.
+pub struct StreamFailure {
+    pub category: &'static str,
+    pub error: String,
+    pub code: Option<String>,
+    pub retryable: bool, // See `ApiError::is_retryable`
+    pub retry_after: Option<Duration>, // See `ApiError::retry_after`
+}
+
+impl StreamFailure {
+    pub fn from_error(e: &anyhow::Error) -> Self {
+        let retryable = ApiError::classify(e).is_retryable();
+        let retry_after = ApiError::retry_after(e);
+        if is_content_filter_error(e) {
+            let (error, code) = match e.downcast_ref::<ProviderStreamError>() {
+                Some(server) => (server.message.clone(), server.code.clone()),
+                None => (format!("{:#}", e), None),
+            };
+            return Self { category: ERROR_CONTENT_FILTER, error, code, retryable: false, retry_after: None };
+        }
+        match e.downcast_ref::<ProviderStreamError>() {
+            Some(server) => Self { category: ERROR_SERVER, error: server.message.clone(), code: server.code.clone(), retryable, retry_after },
+            None => Self { category: ERROR_STREAM, error: format!("{:#}", e), code: None, retryable, retry_after },
+        }
+    }
+
+    // Flags `message` as failed and emits `generation_failed` for it
+    pub fn record(&self, state: &AppState, message: &mut Message) {
+        message.mark_failed(self.category, &self.error);
+        if let Some(code) = &self.code {
+            message.set_metadata_field("error_code", serde_json::json!(code));
+        }
+        record_retry_after(message, self.retry_after);
+        emit_generation_failed(state, message, self.category, &self.error, self.retryable);
+    }
+}
+
+// Keeps the server's requested wait with the failure, rounded up to whole seconds
+fn record_retry_after(message: &mut Message, retry_after: Option<Duration>) {
+    if let Some(retry_after) = retry_after {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        message.set_metadata_field("retry_after_secs", serde_json::json!(secs));
+    }
+}
+
+// Reports a generation that failed before streaming. A continuation reports it against the
+// message it extends, which stays as it was; anything else goes to `record_failed_generation`.
+async fn fail_before_stream(
+    state: &AppState,
+    conversation: &Conversation,
+    failure: &StreamFailure,
+    keeps_previous: bool,
+    extends: Option<&ExtendedMessage>,
+    comparison: Option<&ComparisonVariant>,
+) {
+    match extends {
+        Some(extended) => emit_generation_failed(state, &extended.message, failure.category, &failure.error, failure.retryable),
+        None => record_failed_generation(state, conversation, failure, keeps_previous, comparison).await,
+    }
+}
+
+// Saves an assistant message recording a generation that failed before streaming,
+// so the transcript still shows it after a restart. Regenerations (`keeps_previous`)
+// only report the failure: the answer they were replacing stays in place. A comparison
+// variant's failure is saved under its ID, tagged with the comparison.
+pub async fn record_failed_generation(
+    state: &AppState,
+    conversation: &Conversation,
+    failure: &StreamFailure,
+    keeps_previous: bool,
+    comparison: Option<&ComparisonVariant>,
+) {
+    let StreamFailure { category, error, retryable, retry_after, .. } = failure;
+    let mut message = Message {
+        id: comparison.map_or_else(Uuid::new_v4, |variant| variant.message_id),
+        conversation_id: conversation.id,
+        role: "assistant".to_string(),
+        content: String::new(),
+        timestamp: Utc::now(),
+        metadata: None,
+        name: None,
+        variant_group: None,
+    };
+    message.mark_failed(category, error);
+    record_retry_after(&mut message, *retry_after);
+    if let Some(variant) = comparison {
+        message.set_metadata_field("comparison_id", serde_json::json!(variant.comparison_id.to_string()));
+    }
+    if keeps_previous {
+        emit_generation_failed(state, &message, category, error, *retryable);
+        return;
+    }
+    if conversation.ephemeral {
+        state.remember_ephemeral(message.clone());
+    } else {
+        let storage = state.storage.lock().await;
+        if let Err(e) = storage.save_message(&message).await {
+            log::error!("Failed to save error message for conversation {}: {:?}", conversation.id, e);
+        }
+    }
+    state.notify_conversation_updated(conversation.id);
+    emit_generation_failed(state, &message, category, error, *retryable);
+}
+
+// Drops a finished comparison variant from its fan-out, and the fan-out once it is empty
+fn forget_comparison_variant(state: &AppState, variant: &ComparisonVariant) {
+    let fan_out_done = match state.comparison_streams.get_mut(&variant.comparison_id) {
+        Some(mut ids) => {
+            ids.retain(|id| *id != variant.message_id);
+            ids.is_empty()
+        }
+        None => false,
+    };
+    if fan_out_done {
+        state.comparison_streams.remove(&variant.comparison_id);
+    }
+}
+
+// Removes the answer a regeneration replaces, once the new one is known to produce output
+async fn delete_replaced_message(state: &AppState, conversation_id: Uuid, message_id: Uuid) {
+    state.forget_ephemeral(conversation_id, message_id);
+    let storage = state.storage.lock().await;
+    match storage.delete_message(message_id).await {
+        Ok(()) => log::info!("Deleted replaced assistant message {}", message_id),
+        Err(e) => log::error!("Failed to delete replaced assistant message {}: {:?}", message_id, e),
+    }
+}
+
+// Emits one `assistant_message_chunk` and records it in the in-flight buffer
+fn emit_chunk(state: &AppState, conversation_id: Uuid, message_id: Uuid, seq: &mut u64, delta: String) {
+    *seq += 1;
+    record_active_chunk(state, message_id, &delta, *seq);
+    let chunk_payload = serde_json::json!({
+        "conversationId": conversation_id.to_string(),
+        "messageId": message_id.to_string(),
+        "delta": delta,
+        "seq": *seq,
+    });
+    if let Err(e) = state.emit_to_conversation(conversation_id, "assistant_message_chunk", chunk_payload) {
+        log::error!("Generation [{}]: Failed to emit chunk event: {:?}", message_id, e);
+    }
+}
+
+// Appends a received delta to the in-flight buffer for `message_id`
+pub fn record_active_chunk(state: &AppState, message_id: Uuid, delta: &str, seq: u64) {
+    if let Some(mut active) = state.active_streams.get_mut(&message_id) {
+        active.content.push_str(delta);
+        active.seq = seq;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockProvider, MockStep};
+    use crate::api::OpenAICompatibleProvider;
+    use crate::test_support::{self, MockResponse, MockServer, TestApp};
+
+    fn delta(text: &str) -> MockStep {
+        MockStep::Delta(text.to_string())
+    }
+
+    // A stream ending in calls to `tools`, each with id `call_<name>`
+    fn tool_call_script(tools: &[&str]) -> Vec<MockStep> {
+        let calls = tools
+            .iter()
+            .map(|name| ToolCall {
+                id: format!("call_{}", name),
+                kind: "function".to_string(),
+                function: crate::api::ToolCallFunction { name: name.to_string(), arguments: r#"{"path": "notes.txt"}"#.to_string() },
+            })
+            .collect();
+        vec![MockStep::ToolCalls(calls), MockStep::Finish("tool_calls".to_string())]
+    }
+
+    // Names of the tools in a `tool_calls`-shaped JSON array
+    fn tool_names(calls: &serde_json::Value) -> Vec<&str> {
+        calls.as_array().into_iter().flatten().map(|call| call["function"]["name"].as_str().unwrap()).collect()
+    }
+
+    #[tokio::test]
+    async fn streamed_chunks_are_emitted_in_order_and_saved() {
+        let app = TestApp::new(MockProvider::new(vec![
+            delta("Hello"),
+            delta(", "),
+            delta("world"),
+            MockStep::Usage(TokenUsage { prompt_tokens: 12, completion_tokens: 3 }),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message.clone()])).await;
+
+        let names = app.events.names();
+        let started = names.iter().position(|n| n == events::ASSISTANT_STREAM_STARTED).expect("stream started");
+        let finished = names.iter().position(|n| n == "assistant_stream_finished").expect("stream finished");
+        assert!(names[started + 1..finished].iter().all(|n| n == "assistant_message_chunk"));
+        let seqs: Vec<u64> = app.events.payloads("assistant_message_chunk").iter().map(|c| c["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+        assert_eq!(app.events.streamed_text(), "Hello, world");
+        assert_eq!(app.events.payloads(events::ASSISTANT_STREAM_STARTED)[0]["userMessageId"], user_message.id.to_string());
+        let finished = &app.events.payloads("assistant_stream_finished")[0];
+        assert_eq!(finished["cancelled"], false);
+        assert_eq!(finished["saved"], true);
+        assert!(app.events.payloads(events::GENERATION_FAILED).is_empty());
+
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        let answer = &messages[1];
+        assert_eq!(answer.id.to_string(), finished["messageId"].as_str().unwrap());
+        assert_eq!(answer.content, "Hello, world");
+        assert!(!answer.is_error());
+        assert_eq!(answer.finish_reason().as_deref(), Some("stop"));
+        let metadata = answer.metadata_map();
+        assert_eq!(metadata["model_name"], "test-model");
+        assert_eq!(metadata["model_config_id"], model_config.id.to_string());
+        assert_eq!(answer.tokens_used(), 15);
+        assert!(app.state.active_streams.is_empty());
+    }
+
+    #[tokio::test]
+    async fn mid_stream_error_keeps_the_partial_answer() {
+        let app = TestApp::new(MockProvider::new(vec![delta("Partial"), MockStep::Error("connection reset".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let failed = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["category"], ERROR_STREAM);
+        assert_eq!(failed[0]["message"]["content"], "Partial");
+        let names = app.events.names();
+        let failed_at = names.iter().position(|n| n == events::GENERATION_FAILED).unwrap();
+        let finished_at = names.iter().position(|n| n == "assistant_stream_finished").unwrap();
+        assert!(failed_at < finished_at);
+
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        let answer = messages.last().unwrap();
+        assert_eq!(answer.role, "assistant");
+        assert_eq!(answer.content, "Partial");
+        assert!(answer.is_error());
+        assert_eq!(answer.metadata_map()["error_category"], ERROR_STREAM);
+        assert_eq!(answer.metadata_map()["error_message"], "connection reset");
+    }
+
+    #[tokio::test]
+    async fn cancellation_stops_the_stream_and_saves_what_arrived() {
+        let app = TestApp::new(MockProvider::new(vec![
+            delta("Before"),
+            MockStep::DelayMs(200),
+            delta(" after"),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        let generation = tokio::spawn(run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])));
+        let started = app.events.wait_for(events::ASSISTANT_STREAM_STARTED).await;
+        let message_id = Uuid::parse_str(started["messageId"].as_str().unwrap()).unwrap();
+        app.state.cancelled_streams.insert(message_id, true);
+        generation.await.unwrap();
+
+        let names = app.events.names();
+        let cancelled_at = names.iter().position(|n| n == events::GENERATION_CANCELLED).expect("cancellation acknowledged");
+        let finished_at = names.iter().position(|n| n == "assistant_stream_finished").unwrap();
+        assert!(cancelled_at < finished_at);
+        let finished = &app.events.payloads("assistant_stream_finished")[0];
+        assert_eq!(finished["cancelled"], true);
+        assert_eq!(finished["saved"], true);
+        assert_eq!(app.events.streamed_text(), "Before");
+        assert!(!app.state.cancelled_streams.contains_key(&message_id));
+
+        let answer = app.state.storage.lock().await.get_message(message_id).await.unwrap().expect("partial answer saved");
+        assert_eq!(answer.content, "Before");
+        assert!(!answer.is_error());
+    }
+
+    #[tokio::test]
+    async fn continuation_appends_to_the_extended_message() {
+        let app = TestApp::new(MockProvider::new(vec![delta("ld"), delta("!"), MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+        let mut truncated = test_support::message(conversation.id, "assistant", "Hello wor");
+        truncated.set_metadata_field("finish_reason", serde_json::json!("length"));
+        app.state.storage.lock().await.save_message(&truncated).await.unwrap();
+
+        let mut request = app.request(&conversation, &model_config, vec![user_message, truncated.clone()]);
+        request.kind = StreamKind::Continue;
+        request.trim_leading_whitespace = false;
+        request.extends = Some(ExtendedMessage { message: truncated.clone(), separator: "" });
+        run_generation(app.state.clone(), request).await;
+
+        let started = &app.events.payloads(events::ASSISTANT_STREAM_STARTED)[0];
+        assert_eq!(started["messageId"], truncated.id.to_string());
+        assert_eq!(started["kind"], "continue");
+        assert!(app.events.payloads("assistant_message_chunk").iter().all(|c| c["messageId"] == truncated.id.to_string()));
+        let finished = &app.events.payloads("assistant_stream_finished")[0];
+        assert_eq!(finished["extended"], true);
+
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].id, truncated.id);
+        assert_eq!(messages[1].content, "Hello world!");
+        assert_eq!(messages[1].finish_reason().as_deref(), Some("stop"));
+        assert!(!messages[1].metadata_map().contains_key("incomplete"));
+    }
+
+    #[tokio::test]
+    async fn continuation_separator_precedes_the_first_delta() {
+        let app = TestApp::new(MockProvider::new(vec![delta("  More."), MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let answer = test_support::message(conversation.id, "assistant", "Done.");
+        app.state.storage.lock().await.save_message(&answer).await.unwrap();
+
+        let mut request = app.request(&conversation, &model_config, vec![answer.clone()]);
+        request.extends = Some(ExtendedMessage { message: answer.clone(), separator: "\n\n" });
+        run_generation(app.state.clone(), request).await;
+
+        assert_eq!(app.events.streamed_text(), "\n\nMore.");
+        assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, vec!["Done.\n\nMore."]);
+    }
+
+    #[tokio::test]
+    async fn continuation_failures_leave_the_message_unchanged() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let answer = test_support::message(conversation.id, "assistant", "Kept");
+        app.state.storage.lock().await.save_message(&answer).await.unwrap();
+
+        // No key: reported against the message, nothing new saved
+        let keyless = test_support::model_config("No key", "{}");
+        let mut request = app.request(&conversation, &keyless, vec![answer.clone()]);
+        request.extends = Some(ExtendedMessage { message: answer.clone(), separator: "" });
+        run_generation(app.state.clone(), request).await;
+        let failed = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failed[0]["category"], ERROR_API_KEY);
+        assert_eq!(failed[0]["message"]["id"], answer.id.to_string());
+
+        // Empty continuation: reported, message kept as it was and not flagged
+        let model_config = app.model_config("{}").await;
+        let mut request = app.request(&conversation, &model_config, vec![answer.clone()]);
+        request.extends = Some(ExtendedMessage { message: answer.clone(), separator: "" });
+        run_generation(app.state.clone(), request).await;
+        let failed = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failed[1]["category"], ERROR_EMPTY);
+        assert_eq!(app.events.payloads("assistant_stream_finished")[0]["extended"], false);
+
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "Kept");
+        assert!(!messages[0].is_error());
+    }
+
+    #[tokio::test]
+    async fn comparison_variants_stream_and_fail_independently() {
+        let app = TestApp::new(MockProvider::scripted_by_config()).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let good = app.model_config(r#"{"script": [{"delta": "Answer"}, {"finish": "stop"}]}"#).await;
+        let bad = app.model_config(r#"{"script": [{"delta": "Half"}, {"error": "boom"}]}"#).await;
+        let user_message = app.user_message(&conversation, "Compare").await;
+
+        let comparison_id = Uuid::new_v4();
+        let variants = [(good.clone(), Uuid::new_v4()), (bad.clone(), Uuid::new_v4())];
+        app.state.comparison_streams.insert(comparison_id, variants.iter().map(|(_, id)| *id).collect());
+        let runs = variants.iter().map(|(config, message_id)| {
+            let mut request = app.request(&conversation, config, vec![user_message.clone()]);
+            request.kind = StreamKind::Compare;
+            request.comparison = Some(ComparisonVariant { comparison_id, message_id: *message_id });
+            run_generation(app.state.clone(), request)
+        });
+        futures::future::join_all(runs).await;
+
+        let started = app.events.payloads(events::ASSISTANT_STREAM_STARTED);
+        assert_eq!(started.len(), 2);
+        assert!(started.iter().all(|s| s["kind"] == "compare" && s["comparisonId"] == comparison_id.to_string()));
+        assert!(app.state.comparison_streams.is_empty());
+
+        let storage = app.state.storage.lock().await;
+        let answer = storage.get_message(variants[0].1).await.unwrap().expect("good variant saved");
+        assert_eq!(answer.content, "Answer");
+        assert_eq!(answer.comparison_id(), Some(comparison_id.to_string()));
+        let failed = storage.get_message(variants[1].1).await.unwrap().expect("failed variant saved");
+        assert_eq!(failed.content, "Half");
+        assert!(failed.is_error());
+        assert_eq!(failed.comparison_id(), Some(comparison_id.to_string()));
+    }
+
+    #[tokio::test]
+    async fn continuation_request_failures_are_reported_against_the_message() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::HttpStatus { status: 500, retry_after: None }])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let answer = test_support::message(conversation.id, "assistant", "Kept");
+        app.state.storage.lock().await.save_message(&answer).await.unwrap();
+
+        let mut request = app.request(&conversation, &model_config, vec![answer.clone()]);
+        request.extends = Some(ExtendedMessage { message: answer.clone(), separator: "" });
+        run_generation(app.state.clone(), request).await;
+
+        let failed = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["category"], ERROR_REQUEST);
+        assert_eq!(failed[0]["message"]["id"], answer.id.to_string());
+        assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, vec!["Kept"]);
+    }
+
+    #[tokio::test]
+    async fn regeneration_reports_the_replaced_content() {
+        let app = TestApp::new(MockProvider::new(vec![delta("Better answer"), MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+        let old_answer = test_support::message(conversation.id, "assistant", "First answer");
+        app.state.storage.lock().await.save_message(&old_answer).await.unwrap();
+
+        let request = GenerationRequest {
+            kind: StreamKind::Regenerate,
+            replaces: Some(ReplacedMessage { message_id: old_answer.id, content: old_answer.content.clone(), variant_group: None }),
+            ..app.request(&conversation, &model_config, vec![user_message])
+        };
+        run_generation(app.state.clone(), request).await;
+
+        let completions = app.events.payloads(events::REGENERATION_COMPLETE);
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0]["previousMessageId"], old_answer.id.to_string());
+        assert_eq!(completions[0]["oldContent"], "First answer");
+        assert_eq!(completions[0]["newContent"], "Better answer");
+        let finished = &app.events.payloads("assistant_stream_finished")[0];
+        assert_eq!(completions[0]["messageId"], finished["messageId"]);
+        assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, vec!["Hi", "Better answer"]);
+    }
+
+    #[tokio::test]
+    async fn streams_beyond_the_limit_queue() {
+        let app = TestApp::with_stream_limit(
+            MockProvider::new(vec![MockStep::DelayMs(100), delta("Done"), MockStep::Finish("stop".to_string())]),
+            2,
+        )
+        .await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        let generations: Vec<_> = (0..5)
+            .map(|_| tokio::spawn(run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message.clone()]))))
+            .collect();
+        for generation in generations {
+            generation.await.unwrap();
+        }
+
+        // Streams start only once one holding a slot has finished
+        let mut running = 0;
+        let mut most_running = 0;
+        for name in app.events.names() {
+            if name == events::ASSISTANT_STREAM_STARTED {
+                running += 1;
+                most_running = most_running.max(running);
+            } else if name == "assistant_stream_finished" {
+                running -= 1;
+            }
+        }
+        assert_eq!(most_running, 2);
+        assert_eq!(app.events.payloads("assistant_stream_finished").len(), 5);
+        assert_eq!(app.state.stream_permits.available_permits(), 2);
+    }
+
+    #[tokio::test]
+    async fn answers_keep_the_model_that_produced_them() {
+        let app = TestApp::new(MockProvider::new(vec![delta("Answer"), MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let first_model = app.model_config(r#"{"model": "first-model"}"#).await;
+        let second_model = app.model_config(r#"{"model": "second-model"}"#).await;
+
+        let question = app.user_message(&conversation, "One").await;
+        run_generation(app.state.clone(), app.request(&conversation, &first_model, vec![question])).await;
+        // The conversation switches models; the earlier answer keeps its record
+        let question = app.user_message(&conversation, "Two").await;
+        let request = GenerationRequest {
+            kind: StreamKind::Regenerate,
+            ..app.request(&conversation, &second_model, vec![question])
+        };
+        run_generation(app.state.clone(), request).await;
+
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        let models: Vec<(String, String)> = messages
+            .iter()
+            .filter(|m| m.role == "assistant")
+            .map(|m| {
+                let metadata = m.metadata_map();
+                (metadata["model_name"].as_str().unwrap().to_string(), metadata["model_config_id"].as_str().unwrap().to_string())
+            })
+            .collect();
+        assert_eq!(
+            models,
+            vec![
+                ("first-model".to_string(), first_model.id.to_string()),
+                ("second-model".to_string(), second_model.id.to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_providers_fail_with_a_structured_error() {
+        let app = TestApp::new(MockProvider::new(vec![delta("Unreachable"), MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = ModelConfig { provider: "bogus".to_string(), ..app.model_config("{}").await };
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let failures = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0]["category"], "provider");
+        assert_eq!(failures[0]["kind"], "provider");
+        assert_eq!(failures[0]["retryable"], false);
+        let error = failures[0]["error"].as_str().unwrap();
+        assert!(error.contains("'bogus'") && error.contains(&model_config.name), "{}", error);
+        assert!(app.events.streamed_text().is_empty());
+        // The failure is kept in the transcript, flagged as an error
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        assert!(messages.last().unwrap().is_error());
+        assert!(app.state.active_streams.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_regenerations_keep_the_previous_answer() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::HttpStatus { status: 503, retry_after: None }])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+        let old_answer = test_support::message(conversation.id, "assistant", "First answer");
+        app.state.storage.lock().await.save_message(&old_answer).await.unwrap();
+
+        let request = GenerationRequest {
+            kind: StreamKind::Regenerate,
+            replaces: Some(ReplacedMessage { message_id: old_answer.id, content: old_answer.content.clone(), variant_group: None }),
+            ..app.request(&conversation, &model_config, vec![user_message])
+        };
+        run_generation(app.state.clone(), request).await;
+
+        let failures = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0]["retryable"], true);
+        assert!(app.events.payloads(events::REGENERATION_COMPLETE).is_empty());
+        assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, vec!["Hi", "First answer"]);
+        let kept = app.state.storage.lock().await.get_message(old_answer.id).await.unwrap().expect("previous answer kept");
+        assert!(!kept.is_error());
+    }
+
+    #[test]
+    fn sentences_end_at_punctuation_followed_by_whitespace() {
+        assert_eq!(first_sentence_end("Hi there. More"), Some(9));
+        assert_eq!(first_sentence_end("Really?\nYes"), Some(7));
+        assert_eq!(first_sentence_end("Version 1.5 is out"), None);
+        assert_eq!(first_sentence_end("No space yet."), None);
+    }
+
+    #[tokio::test]
+    async fn previews_stop_after_the_first_sentence() {
+        let app = TestApp::new(MockProvider::new(vec![
+            delta("Rust is fast"),
+            delta(". It is"),
+            delta(" also safe! Really."),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        let user_message = app.user_message(&conversation, "Tell me about Rust").await;
+
+        let request = GenerationRequest { preview: true, ..app.request(&conversation, &model_config, vec![user_message]) };
+        run_generation(app.state.clone(), request).await;
+
+        assert_eq!(app.events.streamed_text(), "Rust is fast.");
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content, "Rust is fast.");
+        assert_eq!(messages[1].metadata_map()["preview"], true);
+        assert!(app.state.active_streams.is_empty());
+    }
+
+    #[tokio::test]
+    async fn progress_is_reported_with_increasing_counts() {
+        // 40 characters is about 10 tokens, so every other delta crosses the 20-token interval
+        let chunk = "word ".repeat(8);
+        let mut steps: Vec<MockStep> = (0..8).map(|_| delta(&chunk)).collect();
+        steps.push(MockStep::Finish("stop".to_string()));
+        let app = TestApp::new(MockProvider::new(steps.clone())).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model", "max_tokens": 100}"#).await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let progress = app.events.payloads(events::GENERATION_PROGRESS);
+        let counts: Vec<u64> = progress.iter().map(|p| p["approxTokens"].as_u64().unwrap()).collect();
+        assert_eq!(counts, [20, 40, 60, 80]);
+        let percents: Vec<u64> = progress.iter().map(|p| p["percent"].as_u64().unwrap()).collect();
+        assert_eq!(percents, [20, 40, 60, 80]);
+        assert!(progress.iter().all(|p| p["maxTokens"] == 100));
+
+        // Without max_tokens the percent is left out
+        let app = TestApp::new(MockProvider::new(steps)).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+        let progress = app.events.payloads(events::GENERATION_PROGRESS);
+        assert_eq!(progress.len(), 4);
+        assert!(progress.iter().all(|p| p.get("percent").is_none() && p["maxTokens"].is_null()));
+    }
+
+    #[tokio::test]
+    async fn empty_responses_are_retried_once_when_configured() {
+        let finish = || MockStep::Finish("stop".to_string());
+        let app = TestApp::new(MockProvider::sequence(vec![vec![finish()], vec![delta("Second try"), finish()]])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model", "retry_on_empty": true}"#).await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        assert_eq!(app.events.streamed_text(), "Second try");
+        assert!(app.events.payloads(events::GENERATION_FAILED).is_empty());
+        assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, ["Hi", "Second try"]);
+
+        // Without the option, and when the retry is empty too, nothing blank is saved
+        for options in [r#"{"model": "test-model"}"#, r#"{"model": "test-model", "retry_on_empty": true}"#] {
+            let app = TestApp::new(MockProvider::sequence(vec![vec![finish()], vec![finish()], vec![delta("Third try"), finish()]])).await;
+            let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+            let model_config = app.model_config(options).await;
+            let user_message = app.user_message(&conversation, "Hi").await;
+
+            run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+            let failed = app.events.payloads(events::GENERATION_FAILED);
+            assert_eq!(failed.len(), 1, "{}", options);
+            assert_eq!(failed[0]["category"], ERROR_EMPTY);
+            assert_eq!(app.events.payloads("assistant_stream_finished")[0]["saved"], false);
+            assert_eq!(test_support::contents(&*app.state.storage.lock().await, conversation.id).await, ["Hi"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn leading_whitespace_deltas_are_dropped() {
+        let steps = vec![delta("\n"), delta("  \n"), delta("\n  Hello"), delta("\n\n world"), MockStep::Finish("stop".to_string())];
+        for (trim, expected) in [(true, "Hello\n\n world"), (false, "\n  \n\n  Hello\n\n world")] {
+            let app = TestApp::new(MockProvider::new(steps.clone())).await;
+            let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+            let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+            let user_message = app.user_message(&conversation, "Hi").await;
+
+            let request = GenerationRequest { trim_leading_whitespace: trim, ..app.request(&conversation, &model_config, vec![user_message]) };
+            run_generation(app.state.clone(), request).await;
+
+            assert_eq!(app.events.streamed_text(), expected);
+            let contents = test_support::contents(&*app.state.storage.lock().await, conversation.id).await;
+            assert_eq!(contents, ["Hi", expected]);
+        }
+    }
+
+    #[tokio::test]
+    async fn checkpoints_leave_a_readable_partial_answer() {
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        app.user_message(&conversation, "Hi").await;
+        let message_id = Uuid::new_v4();
+
+        save_checkpoint(&app.state, conversation.id, message_id, "Once upon", &model_config, None).await;
+        save_checkpoint(&app.state, conversation.id, message_id, "Once upon a time", &model_config, None).await;
+
+        // As after a crash: the latest checkpoint is there, flagged incomplete
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!((messages[1].id, messages[1].content.as_str()), (message_id, "Once upon a time"));
+        let metadata = messages[1].metadata_map();
+        assert_eq!(metadata["incomplete"], true);
+        assert_eq!(metadata["model_name"], "test-model");
+
+        // A continuation's checkpoint holds the message it extends plus what streamed so far
+        let extended = ExtendedMessage { message: messages[1].clone(), separator: "" };
+        save_checkpoint(&app.state, conversation.id, message_id, ", there was", &model_config, Some(&extended)).await;
+        let contents = test_support::contents(&*app.state.storage.lock().await, conversation.id).await;
+        assert_eq!(contents, ["Hi", "Once upon a time, there was"]);
+    }
+
+
+    #[tokio::test]
+    async fn citations_are_emitted_and_saved_with_the_answer() {
+        let citation = serde_json::json!({"type": "url_citation", "url_citation": {"url": "https://example.com/a", "title": "A"}});
+        let app = TestApp::new(MockProvider::new(vec![
+            delta("See "),
+            MockStep::Annotations(vec![citation.clone()]),
+            delta("the source."),
+            MockStep::Finish("stop".to_string()),
+        ]))
+        .await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let emitted = app.events.payloads(events::ASSISTANT_ANNOTATIONS);
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0]["annotations"], serde_json::json!([citation]));
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        let answer = &messages[1];
+        assert_eq!(emitted[0]["messageId"], answer.id.to_string());
+        assert_eq!(answer.content, "See the source.");
+        assert_eq!(answer.metadata_map()["annotations"], serde_json::json!([citation]));
+    }
+
+
+    #[tokio::test]
+    async fn failure_events_say_whether_a_retry_may_help() {
+        let app = TestApp::new(MockProvider::sequence(vec![
+            vec![MockStep::HttpStatus { status: 503, retry_after: None }],
+            vec![MockStep::HttpStatus { status: 401, retry_after: None }],
+            vec![MockStep::ServerError { message: "Invalid request".to_string(), code: Some("invalid_request_error".to_string()) }],
+        ]))
+        .await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config(r#"{"model": "test-model"}"#).await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        for _ in 0..3 {
+            run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message.clone()])).await;
+        }
+
+        let retryable: Vec<bool> = app.events.payloads(events::GENERATION_FAILED).iter().map(|failed| failed["retryable"].as_bool().unwrap()).collect();
+        assert_eq!(retryable, [true, false, false]);
+    }
+
+
+    #[tokio::test]
+    async fn single_turn_conversations_send_only_the_newest_question() {
+        let server = MockServer::start(vec![MockResponse::sse(&[serde_json::json!({
+            "id": "c1", "object": "chat.completion.chunk", "created": 1718000000, "model": "m",
+            "choices": [{"index": 0, "delta": {"content": "Bonjour"}, "finish_reason": "stop"}]
+        })])])
+        .await;
+        let app = TestApp::new(OpenAICompatibleProvider::new()).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let conversation = Conversation { single_turn: true, ..conversation };
+        let model_config = ModelConfig { api_url: server.url.clone(), ..app.model_config(r#"{"model": "m"}"#).await };
+        let mut history = Vec::new();
+        for (role, content) in [("user", "Translate: cat"), ("assistant", "chat"), ("user", "Translate: hello")] {
+            let message = test_support::message(conversation.id, role, content);
+            app.state.storage.lock().await.save_message(&message).await.unwrap();
+            history.push(message);
+        }
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, history)).await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].json()["messages"], serde_json::json!([
+            {"role": "system", "content": "You are helpful."},
+            {"role": "user", "content": "Translate: hello"},
+        ]));
+        // Everything is still saved
+        let contents = test_support::contents(&*app.state.storage.lock().await, conversation.id).await;
+        assert_eq!(contents, ["Translate: cat", "chat", "Translate: hello", "Bonjour"]);
+    }
+
+
+    #[tokio::test]
+    async fn missing_keyring_entries_are_reported_before_streaming() {
+        let app = TestApp::new(MockProvider::new(vec![delta("Never sent"), MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        // A fresh config ID has no keyring entry
+        let model_config = ModelConfig { api_key_ref: Some("keyring".to_string()), ..app.model_config("{}").await };
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let missing = app.events.payloads(events::API_KEY_MISSING);
+        assert_eq!(missing.len(), 1);
+        assert_eq!(missing[0]["modelConfigId"], model_config.id.to_string());
+        assert_eq!(missing[0]["modelConfigName"], model_config.name);
+        assert_eq!(missing[0]["apiKeyRef"], "keyring");
+        let failed = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["category"], ERROR_API_KEY);
+        assert_eq!(app.events.streamed_text(), "");
+    }
+
+
+    #[tokio::test]
+    async fn answers_record_the_parameters_they_were_sent_with() {
+        let server = MockServer::start(vec![MockResponse::sse(&[serde_json::json!({
+            "id": "c1", "object": "chat.completion.chunk", "created": 1718000000, "model": "m",
+            "choices": [{"index": 0, "delta": {"content": "Hi"}, "finish_reason": "stop"}]
+        })])])
+        .await;
+        let app = TestApp::new(OpenAICompatibleProvider::new()).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let options = r#"{"model": "m", "max_tokens": 64, "logit_bias": {"50256": -100}, "system_role": "developer", "context_window": 8000}"#;
+        let model_config = ModelConfig { api_url: server.url.clone(), ..app.model_config(options).await };
+        let user_message = app.user_message(&conversation, "Hello").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let sent = server.requests()[0].json();
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        let recorded = messages[1].metadata_map()["request_params"].clone();
+        assert_eq!(recorded, serde_json::json!({"model": "m", "max_tokens": 64, "logit_bias": {"50256": -100.0}, "system_role": "developer"}));
+        for key in ["model", "max_tokens", "logit_bias"] {
+            assert_eq!(recorded[key], sent[key], "{}", key);
+        }
+        assert_eq!(sent["messages"][0]["role"], "developer");
+    }
+
+
+    #[tokio::test]
+    async fn rate_limits_report_the_retry_after_wait() {
+        let server = MockServer::start(vec![MockResponse {
+            status: 429,
+            headers: vec![("Retry-After".to_string(), "2".to_string())],
+            body: r#"{"error": {"message": "Rate limit reached"}}"#.to_string(),
+        }])
+        .await;
+        let app = TestApp::new(OpenAICompatibleProvider::new()).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = ModelConfig { api_url: server.url.clone(), ..app.model_config(r#"{"model": "m"}"#).await };
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let failed = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["retryable"], true);
+        assert_eq!(failed[0]["retryAfterSecs"], 2);
+    }
+
+
+    #[tokio::test]
+    async fn a_content_filter_finish_is_reported_instead_of_a_blank_answer() {
+        let chunk = |delta: serde_json::Value, finish_reason: Option<&str>| serde_json::json!({
+            "id": "c1", "object": "chat.completion.chunk", "created": 1718000000, "model": "m",
+            "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]
+        });
+        let server = MockServer::start(vec![
+            MockResponse::sse(&[chunk(serde_json::json!({"content": "Here is how"}), None), chunk(serde_json::json!({}), Some("content_filter"))]),
+            MockResponse::sse(&[chunk(serde_json::json!({}), Some("content_filter"))]),
+        ])
+        .await;
+        let app = TestApp::new(OpenAICompatibleProvider::new()).await;
+        let model_config = ModelConfig { api_url: server.url.clone(), ..app.model_config(r#"{"model": "m"}"#).await };
+
+        let partial = test_support::conversation(&*app.state.storage.lock().await).await;
+        let question = app.user_message(&partial, "Hi").await;
+        run_generation(app.state.clone(), app.request(&partial, &model_config, vec![question])).await;
+        let blocked = test_support::conversation(&*app.state.storage.lock().await).await;
+        let question = app.user_message(&blocked, "Hi").await;
+        run_generation(app.state.clone(), app.request(&blocked, &model_config, vec![question])).await;
+
+        let filtered = app.events.payloads(events::CONTENT_FILTERED);
+        assert_eq!(filtered.len(), 2);
+        assert_eq!((&filtered[0]["conversationId"], &filtered[0]["partial"]), (&serde_json::json!(partial.id.to_string()), &serde_json::json!(true)));
+        assert_eq!((&filtered[1]["conversationId"], &filtered[1]["partial"]), (&serde_json::json!(blocked.id.to_string()), &serde_json::json!(false)));
+
+        let storage = app.state.storage.lock().await;
+        let answer = storage.get_conversation_messages(partial.id).await.unwrap().pop().unwrap();
+        assert_eq!(answer.content, "Here is how");
+        assert_eq!(answer.metadata_map()["content_filtered"], true);
+        assert!(!answer.is_error());
+        let answer = storage.get_conversation_messages(blocked.id).await.unwrap().pop().unwrap();
+        assert_eq!(answer.metadata_map()["content_filtered"], true);
+        assert!(answer.is_error());
+        drop(storage);
+        let failed = app.events.payloads(events::GENERATION_FAILED);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0]["category"], ERROR_CONTENT_FILTER);
+    }
+
+
+    #[tokio::test]
+    async fn a_dropped_stream_is_resumed_into_the_same_message() {
+        let chunk = |content: &str, finish_reason: Option<&str>| serde_json::json!({
+            "id": "c1", "object": "chat.completion.chunk", "created": 1718000000, "model": "m",
+            "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": finish_reason}]
+        });
+        let server = MockServer::start(vec![
+            MockResponse::sse_dropped(&[chunk("The capital ", None), chunk("of France ", None)]),
+            MockResponse::sse(&[chunk("is Paris.", Some("stop"))]),
+        ])
+        .await;
+        let app = TestApp::new(OpenAICompatibleProvider::new()).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = ModelConfig {
+            api_url: server.url.clone(),
+            ..app.model_config(r#"{"model": "m", "resume_on_disconnect": true}"#).await
+        };
+        let user_message = app.user_message(&conversation, "What is the capital of France?").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        // The resumed request carries the partial answer for the model to continue
+        let resumed = requests[1].json()["messages"].as_array().unwrap().last().unwrap().clone();
+        assert_eq!(resumed, serde_json::json!({"role": "assistant", "content": "The capital of France "}));
+        assert!(app.events.payloads(events::GENERATION_FAILED).is_empty());
+        assert_eq!(app.events.streamed_text(), "The capital of France is Paris.");
+
+        let storage = app.state.storage.lock().await;
+        let messages = storage.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].content, "The capital of France is Paris.");
+        assert_eq!(messages[1].metadata_map()["resumed"], 1);
+    }
+
+
+    #[tokio::test]
+    async fn cancelling_before_any_content_saves_no_answer() {
+        let app = TestApp::new(MockProvider::new(vec![MockStep::DelayMs(200), delta("Too late"), MockStep::Finish("stop".to_string())])).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Hi").await;
+
+        let generation = tokio::spawn(run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])));
+        let started = app.events.wait_for(events::ASSISTANT_STREAM_STARTED).await;
+        let message_id = Uuid::parse_str(started["messageId"].as_str().unwrap()).unwrap();
+        app.state.cancelled_streams.insert(message_id, true);
+        generation.await.unwrap();
+
+        let finished = &app.events.payloads("assistant_stream_finished")[0];
+        assert_eq!((&finished["cancelled"], &finished["saved"]), (&serde_json::json!(true), &serde_json::json!(false)));
+        assert_eq!(app.events.streamed_text(), "");
+        let storage = app.state.storage.lock().await;
+        assert!(storage.get_message(message_id).await.unwrap().is_none());
+        assert_eq!(test_support::contents(&storage, conversation.id).await, vec!["Hi"]);
+    }
+
+
+    #[tokio::test]
+    async fn only_tool_calls_allowed_by_policy_are_handed_on() {
+        let app = TestApp::new(MockProvider::new(tool_call_script(&["read_file", "delete_file"]))).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        {
+            let storage = app.state.storage.lock().await;
+            storage.set_tool_policy("read_file", None, tools::ToolPolicy::Allow).await.unwrap();
+            storage.set_tool_policy("delete_file", Some(conversation.id), tools::ToolPolicy::Deny).await.unwrap();
+        }
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Tidy my notes").await;
+
+        run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])).await;
+
+        assert!(app.events.payloads(events::ASSISTANT_TOOL_REQUEST).is_empty());
+        let emitted = app.events.payloads("assistant_tool_call");
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(tool_names(&emitted[0]["toolCalls"]), ["read_file"]);
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        let metadata = messages[1].metadata_map();
+        assert_eq!(tool_names(&metadata["tool_calls"]), ["read_file"]);
+        assert_eq!(tool_names(&metadata["denied_tool_calls"]), ["delete_file"]);
+    }
+
+    #[tokio::test]
+    async fn tool_calls_without_a_policy_wait_for_the_user() {
+        let app = TestApp::new(MockProvider::new(tool_call_script(&["read_file"]))).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Read my notes").await;
+
+        let generation = tokio::spawn(run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])));
+        let request = app.events.wait_for(events::ASSISTANT_TOOL_REQUEST).await;
+        assert_eq!((request["toolName"].as_str(), request["arguments"].as_str()), (Some("read_file"), Some(r#"{"path": "notes.txt"}"#)));
+        assert!(app.events.payloads("assistant_tool_call").is_empty());
+
+        let request_id = request["requestId"].as_str().unwrap().to_string();
+        crate::commands::respond_tool_permission(app.command_state(), request_id.clone(), true, Some(true)).await.unwrap();
+        generation.await.unwrap();
+
+        assert_eq!(tool_names(&app.events.payloads("assistant_tool_call")[0]["toolCalls"]), ["read_file"]);
+        let storage = app.state.storage.lock().await;
+        assert_eq!(storage.get_tool_policy("read_file", conversation.id).await.unwrap(), Some(tools::ToolPolicy::Allow));
+        assert!(crate::commands::respond_tool_permission(app.command_state(), request_id, true, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn unanswered_tool_requests_are_denied_after_the_timeout() {
+        let app = TestApp::new(MockProvider::new(tool_call_script(&["delete_file"]))).await;
+        let conversation = test_support::conversation(&*app.state.storage.lock().await).await;
+        let model_config = app.model_config("{}").await;
+        let user_message = app.user_message(&conversation, "Clean up").await;
+
+        let generation = tokio::spawn(run_generation(app.state.clone(), app.request(&conversation, &model_config, vec![user_message])));
+        app.events.wait_for(events::ASSISTANT_TOOL_REQUEST).await;
+        tokio::time::pause();
+        tokio::time::advance(tools::PERMISSION_TIMEOUT + Duration::from_secs(1)).await;
+        tokio::time::resume();
+        generation.await.unwrap();
+
+        assert!(app.events.payloads("assistant_tool_call").is_empty());
+        assert!(app.state.pending_tool_requests.is_empty());
+        let messages = app.state.storage.lock().await.get_conversation_messages(conversation.id).await.unwrap();
+        let metadata = messages[1].metadata_map();
+        assert!(!metadata.contains_key("tool_calls"));
+        assert_eq!(tool_names(&metadata["denied_tool_calls"]), ["delete_file"]);
+    }
+}
+
@@ -0,0 +1,187 @@
+// Startup/settings health check: can each model config actually be used? Also the
+// connection diagnostics that time back-to-back requests to one endpoint.
+
+use crate::api::RawMethod;
+use crate::config::{self, ApiKeyStatus};
+use crate::events;
+use crate::models::ModelConfig;
+use crate::state::AppState;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tauri::Emitter;
+
+// Per-endpoint timeout for the `/models` probe, and how many probes run at once
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_CONCURRENT_PROBES: usize = 4;
+
+// Bounds on the number of back-to-back requests `connection_diagnostics` times
+pub const DEFAULT_DIAGNOSTIC_REQUESTS: u32 = 2;
+pub const MAX_DIAGNOSTIC_REQUESTS: u32 = 5;
+
+/// Outcome of a `GET /models` probe. Only the status code is kept; response bodies are dropped.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointStatus {
+    pub reachable: bool,
+    pub status: Option<u16>,
+    pub detail: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConfigHealth {
+    pub model_config_id: String,
+    pub name: String,
+    pub api_key: ApiKeyStatus,
+    pub endpoint: Option<EndpointStatus>, // None when probing was skipped or the key is unavailable
+}
+
+impl ConfigHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.api_key.available && self.endpoint.as_ref().is_none_or(|e| e.reachable)
+    }
+}
+
+/// Payload of `health_report`. Never contains key material.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthReport {
+    pub checked_at: DateTime<Utc>,
+    pub configs: Vec<ConfigHealth>,
+}
+
+async fn probe_endpoint(state: &AppState, config: &ModelConfig) -> EndpointStatus {
+    let api_key = match config::get_api_key(config) {
+        Ok(key) => key,
+        Err(_) => {
+            return EndpointStatus { reachable: false, status: None, detail: "Skipped: API key unavailable".to_string() }
+        }
+    };
+    let provider = match state.provider_for(config) {
+        Ok(provider) => provider,
+        Err(e) => return EndpointStatus { reachable: false, status: None, detail: e.message },
+    };
+    let request = provider.send_raw_request(config, &api_key, RawMethod::Get, "models", None);
+    match tokio::time::timeout(PROBE_TIMEOUT, request).await {
+        Ok(Ok(response)) => EndpointStatus {
+            // Auth failures still prove the endpoint is there; report them via the status code
+            reachable: (200..300).contains(&response.status),
+            status: Some(response.status),
+            detail: format!("GET /models returned {}", response.status),
+        },
+        Ok(Err(e)) => EndpointStatus { reachable: false, status: None, detail: format!("Request failed: {}", e) },
+        Err(_) => EndpointStatus {
+            reachable: false,
+            status: None,
+            detail: format!("No response within {}s", PROBE_TIMEOUT.as_secs()),
+        },
+    }
+}
+
+/// Checks every model config's API key and, if `probe_endpoints`, its `/models` endpoint.
+/// Probes run concurrently (capped) and each is time-limited, so one dead endpoint can't stall the rest.
+pub async fn run_health_check(state: &AppState, probe_endpoints: bool) -> Result<HealthReport, String> {
+    let model_configs = {
+        let storage = state.storage.lock().await;
+        storage.list_model_configs().await
+            .map_err(|e| format!("Failed to load model configs: {}", e))?
+    };
+
+    let configs = futures::stream::iter(model_configs)
+        .map(|model_config| async move {
+            let api_key = config::check_api_key(&model_config);
+            let endpoint = if probe_endpoints && api_key.available {
+                Some(probe_endpoint(state, &model_config).await)
+            } else {
+                None
+            };
+            ConfigHealth {
+                model_config_id: model_config.id.to_string(),
+                name: model_config.name.clone(),
+                api_key,
+                endpoint,
+            }
+        })
+        .buffered(MAX_CONCURRENT_PROBES)
+        .collect::<Vec<_>>()
+        .await;
+
+    for health in configs.iter().filter(|h| !h.is_healthy()) {
+        log::warn!(
+            "Health check: model config '{}' has problems (key: {}; endpoint: {})",
+            health.name,
+            health.api_key.detail,
+            health.endpoint.as_ref().map_or("not probed", |e| e.detail.as_str())
+        );
+    }
+
+    let report = HealthReport { checked_at: Utc::now(), configs };
+    if let Err(e) = state.app_handle.emit(events::HEALTH_REPORT, &report) {
+        log::error!("Failed to emit health report: {:?}", e);
+    }
+    Ok(report)
+}
+
+/// Payload of `get_connection_diagnostics`: latencies of back-to-back `GET /models`
+/// requests through the provider's pooled client.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionDiagnostics {
+    pub model_config_id: String,
+    pub latencies_ms: Vec<u64>, // In request order
+    pub statuses: Vec<u16>,
+    // Whether later requests were fast enough that the first one's TCP/TLS handshake was
+    // evidently skipped. The HTTP client doesn't expose reuse directly, so this compares
+    // timings: every later request must take at most half as long as the first.
+    pub likely_reused: bool,
+}
+
+/// Sends `requests` sequential `GET /models` requests to `config`'s endpoint and times each.
+/// Fails on the first request that errors or exceeds the probe timeout.
+pub async fn connection_diagnostics(state: &AppState, config: &ModelConfig, requests: u32) -> Result<ConnectionDiagnostics, String> {
+    let api_key = config::get_api_key(config).map_err(|e| format!("API key unavailable: {}", e))?;
+    let provider = state.provider_for(config).map_err(|e| e.message)?;
+    let mut latencies_ms = Vec::new();
+    let mut statuses = Vec::new();
+    for attempt in 1..=requests {
+        let started = Instant::now();
+        let request = provider.send_raw_request(config, &api_key, RawMethod::Get, "models", None);
+        let response = tokio::time::timeout(PROBE_TIMEOUT, request).await
+            .map_err(|_| format!("Request {} got no response within {}s", attempt, PROBE_TIMEOUT.as_secs()))?
+            .map_err(|e| format!("Request {} failed: {}", attempt, e))?;
+        latencies_ms.push(started.elapsed().as_millis() as u64);
+        statuses.push(response.status);
+    }
+    let likely_reused = match latencies_ms.split_first() {
+        Some((first, rest)) if !rest.is_empty() => rest.iter().all(|later| later * 2 <= *first),
+        _ => false,
+    };
+    log::info!("Connection diagnostics for '{}': {:?} ms (likely reused: {})", config.name, latencies_ms, likely_reused);
+    Ok(ConnectionDiagnostics { model_config_id: config.id.to_string(), latencies_ms, statuses, likely_reused })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::OpenAICompatibleProvider;
+    use crate::test_support::{MockResponse, MockServer, TestApp};
+
+    #[tokio::test]
+    async fn diagnostics_time_each_back_to_back_request() {
+        let server = MockServer::start(vec![MockResponse::json(serde_json::json!({"object": "list", "data": []}))]).await;
+        let app = TestApp::new(OpenAICompatibleProvider::new()).await;
+        let config = ModelConfig { api_url: server.url.clone(), ..app.model_config(r#"{"model": "m"}"#).await };
+
+        let diagnostics = connection_diagnostics(&app.state, &config, 2).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|request| request.path.ends_with("/models")), "{:?}", requests);
+        assert_eq!(diagnostics.model_config_id, config.id.to_string());
+        assert_eq!(diagnostics.statuses, [200, 200]);
+        assert_eq!(diagnostics.latencies_ms.len(), 2);
+        assert!(diagnostics.latencies_ms.iter().all(|&ms| ms < PROBE_TIMEOUT.as_millis() as u64));
+    }
+}
@@ -0,0 +1,448 @@
+// Project context: a folder ingested into a conversation ("chat with my repo"). The walk
+// honours `.gitignore` files and the caller's include/exclude globs, and reads text files
+// within a per-file and a total size budget. Every file seen is stored in the conversation's
+// manifest (`project_files`), including the binary or oversized ones the model can't see, so
+// the user knows what was left out. The digest built from the manifest (a file tree plus the
+// included contents) is added to the system prompt of every later request. Re-ingesting only
+// re-reads files whose size or modification time changed, and those left out for lack of
+// budget or because they couldn't be read.
+
+use crate::storage::StorageManager;
+use chrono::{DateTime, Utc};
+use glob::{MatchOptions, Pattern};
+use ignore::WalkBuilder;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use uuid::Uuid;
+
+// Files larger than this are listed as oversized
+pub const MAX_FILE_BYTES: u64 = 64 * 1024;
+// Contents included across all files; later files are listed as over budget
+pub const MAX_TOTAL_BYTES: u64 = 512 * 1024;
+// Files considered per ingestion, so pointing at a huge tree can't stall the app
+const MAX_FILES: usize = 5_000;
+// Bytes inspected for NUL bytes when telling text from binary files
+const BINARY_SNIFF_BYTES: usize = 8 * 1024;
+
+pub const STATUS_INCLUDED: &str = "included";
+pub const STATUS_BINARY: &str = "binary";
+pub const STATUS_OVERSIZED: &str = "oversized";
+pub const STATUS_OVER_BUDGET: &str = "over_budget";
+pub const STATUS_UNREADABLE: &str = "unreadable";
+
+/// The folder ingested into a conversation and the globs it was filtered with.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectContext {
+    pub conversation_id: Uuid,
+    pub root: String,
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// One manifest entry. `content` is only kept for included files and never serialized.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectFile {
+    pub path: String, // Relative to the root, with `/` separators
+    pub status: String, // STATUS_INCLUDED, or why the file was skipped
+    pub size: i64,
+    pub modified: Option<i64>, // Unix seconds
+    pub hash: Option<String>, // SHA-256 of included contents, lowercase hex
+    #[serde(skip)]
+    pub content: Option<String>,
+}
+
+impl ProjectFile {
+    pub fn is_included(&self) -> bool {
+        self.status == STATUS_INCLUDED
+    }
+}
+
+/// A project context with its manifest, as returned by `get_project_manifest`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectManifest {
+    #[serde(flatten)]
+    pub context: ProjectContext,
+    pub files: Vec<ProjectFile>,
+}
+
+/// Result of `ingest_directory`.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestSummary {
+    pub included: usize,
+    pub skipped: usize,
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    pub included_bytes: u64,
+    pub truncated: bool, // More than MAX_FILES files matched; the rest were not considered
+}
+
+/// What a walk produced: the new manifest, and which entries changed since `previous`.
+pub struct IngestResult {
+    pub files: Vec<ProjectFile>,
+    pub changed: Vec<ProjectFile>,
+    pub removed: Vec<String>,
+    pub summary: IngestSummary,
+}
+
+/// Compiles include/exclude globs, reporting the first invalid one.
+pub fn compile_globs(globs: &[String]) -> Result<Vec<Pattern>, String> {
+    globs
+        .iter()
+        .map(|glob| Pattern::new(glob.trim()).map_err(|e| format!("Invalid glob '{}': {}", glob, e)))
+        .collect()
+}
+
+struct Candidate {
+    path: String,
+    full_path: PathBuf,
+    size: u64,
+    modified: Option<i64>,
+}
+
+// Lists files under `root` in path order, honouring `.gitignore` and the globs. `.git` is always skipped.
+fn walk(root: &Path, include: &[Pattern], exclude: &[Pattern]) -> (Vec<Candidate>, bool) {
+    let relative = |root: &Path, path: &Path| -> String {
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        relative.components().map(|part| part.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+    };
+    let filter_root = root.to_path_buf();
+    let filter_exclude = exclude.to_vec();
+    // Only the folder's own `.gitignore` files apply, whether or not it is a git checkout
+    let walker = WalkBuilder::new(root)
+        .standard_filters(false)
+        .git_ignore(true)
+        .require_git(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+            let path = relative(&filter_root, entry.path());
+            let skipped = is_dir && entry.file_name() == ".git";
+            !skipped && !filter_exclude.iter().any(|glob| glob.matches_with(&path, MatchOptions::new()))
+        })
+        .build();
+
+    let mut candidates = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                log::warn!("Skipping unreadable entry during ingestion: {}", e);
+                continue;
+            }
+        };
+        // Symlinks could leave the root or loop; the root itself is depth 0
+        if entry.depth() == 0 || !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+            continue;
+        }
+        let path = relative(root, entry.path());
+        if !include.is_empty() && !include.iter().any(|glob| glob.matches_with(&path, MatchOptions::new())) {
+            continue;
+        }
+        if candidates.len() >= MAX_FILES {
+            return (candidates, true);
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs() as i64);
+        candidates.push(Candidate { path, full_path: entry.into_path(), size: metadata.len(), modified });
+    }
+    candidates.sort_by(|a, b| a.path.cmp(&b.path));
+    (candidates, false)
+}
+
+// Reads a candidate that fits the budgets; None for binary or unreadable files
+fn read_text(path: &Path) -> Result<Option<String>, std::io::Error> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+    if bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+        return Ok(None);
+    }
+    Ok(String::from_utf8(bytes).ok())
+}
+
+/// Walks `root` and builds its manifest, reusing entries of `previous` whose size and
+/// modification time are unchanged. Blocking; run it on a blocking thread.
+pub fn ingest(root: &Path, include: &[Pattern], exclude: &[Pattern], previous: &[ProjectFile]) -> IngestResult {
+    let previous: HashMap<&str, &ProjectFile> = previous.iter().map(|file| (file.path.as_str(), file)).collect();
+    let (candidates, truncated) = walk(root, include, exclude);
+    let mut summary = IngestSummary { truncated, ..Default::default() };
+    let mut files = Vec::with_capacity(candidates.len());
+    let mut changed = Vec::new();
+
+    for candidate in candidates {
+        let size = candidate.size as i64;
+        let known = previous.get(candidate.path.as_str()).copied();
+        let unchanged = known.filter(|file| file.size == size && file.modified == candidate.modified);
+        let fits = summary.included_bytes + candidate.size <= MAX_TOTAL_BYTES;
+
+        let file = match unchanged {
+            // An unchanged file keeps its entry, unless it depends on the budget: an included
+            // file whose share is now taken, or one left out that may fit now
+            Some(file) if [STATUS_BINARY, STATUS_OVERSIZED].contains(&file.status.as_str()) || (file.is_included() && fits) => {
+                file.clone()
+            }
+            _ => {
+                let status_only = |status: &str| ProjectFile {
+                    path: candidate.path.clone(),
+                    status: status.to_string(),
+                    size,
+                    modified: candidate.modified,
+                    hash: None,
+                    content: None,
+                };
+                if candidate.size > MAX_FILE_BYTES {
+                    status_only(STATUS_OVERSIZED)
+                } else {
+                    match read_text(&candidate.full_path) {
+                        Ok(Some(_)) if !fits => status_only(STATUS_OVER_BUDGET),
+                        Ok(Some(content)) => ProjectFile {
+                            path: candidate.path.clone(),
+                            status: STATUS_INCLUDED.to_string(),
+                            size,
+                            modified: candidate.modified,
+                            hash: Some(format!("{:x}", Sha256::digest(content.as_bytes()))),
+                            content: Some(content),
+                        },
+                        Ok(None) => status_only(STATUS_BINARY),
+                        Err(e) => {
+                            log::warn!("Failed to read {} during ingestion: {}", candidate.full_path.display(), e);
+                            status_only(STATUS_UNREADABLE)
+                        }
+                    }
+                }
+            }
+        };
+
+        if file.is_included() {
+            summary.included += 1;
+            summary.included_bytes += file.size as u64;
+        } else {
+            summary.skipped += 1;
+        }
+        match known {
+            None => {
+                summary.added += 1;
+                changed.push(file.clone());
+            }
+            Some(known) if known.status != file.status || known.hash != file.hash || known.modified != file.modified => {
+                summary.updated += 1;
+                changed.push(file.clone());
+            }
+            Some(_) => summary.unchanged += 1,
+        }
+        files.push(file);
+    }
+
+    let seen: HashSet<&str> = files.iter().map(|file| file.path.as_str()).collect();
+    let removed: Vec<String> = previous.keys().filter(|path| !seen.contains(*path)).map(|path| path.to_string()).collect();
+    summary.removed = removed.len();
+    IngestResult { files, changed, removed, summary }
+}
+
+/// The text added to the system prompt: a tree of the manifest, then the included files.
+pub fn digest(context: &ProjectContext, files: &[ProjectFile]) -> String {
+    let root_name = Path::new(&context.root)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| context.root.clone());
+    let included = files.iter().filter(|file| file.is_included()).count();
+    let mut out = format!(
+        "Project files from \"{}\" ({} included, {} skipped and not visible to you):\n\n",
+        root_name,
+        included,
+        files.len() - included
+    );
+
+    // Directory lines are written the first time a path below them appears
+    let mut written_dirs: HashSet<String> = HashSet::new();
+    for file in files {
+        let parts: Vec<&str> = file.path.split('/').collect();
+        for depth in 0..parts.len() - 1 {
+            let dir = parts[..=depth].join("/");
+            if written_dirs.insert(dir) {
+                out.push_str(&format!("{}{}/\n", "  ".repeat(depth), parts[depth]));
+            }
+        }
+        let depth = parts.len() - 1;
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(parts[depth]);
+        if !file.is_included() {
+            out.push_str(&format!(" (skipped: {})", file.status.replace('_', " ")));
+        }
+        out.push('\n');
+    }
+
+    for file in files.iter().filter(|file| file.is_included()) {
+        out.push_str(&format!("\n--- {} ---\n", file.path));
+        out.push_str(file.content.as_deref().unwrap_or_default());
+        if !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// The digest of the conversation's project context; None when it has none or it can't be read.
+pub async fn load_digest(storage: &StorageManager, conversation_id: Uuid) -> Option<String> {
+    let loaded = async {
+        let Some(context) = storage.get_project_context(conversation_id).await? else {
+            return Ok(None);
+        };
+        let files = storage.get_project_files(conversation_id, true).await?;
+        Ok::<_, anyhow::Error>(Some(digest(&context, &files)))
+    };
+    match loaded.await {
+        Ok(digest) => digest,
+        Err(e) => {
+            log::warn!("Failed to read project context of conversation {}, continuing without it: {:?}", conversation_id, e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::temp_dir;
+
+    fn write(root: &Path, path: &str, contents: &[u8]) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn statuses(files: &[ProjectFile]) -> Vec<(&str, &str)> {
+        files.iter().map(|file| (file.path.as_str(), file.status.as_str())).collect()
+    }
+
+    #[test]
+    fn gitignore_rules_follow_git_semantics() {
+        let root = temp_dir();
+        write(&root, ".gitignore", b"*.log\nlogs/*\n!logs/keep.log\n**/generated/**\n");
+        write(&root, "src/.gitignore", b"/local.rs\n");
+        for path in ["main.rs", "debug.log", "logs/keep.log", "logs/old.txt", "src/local.rs", "src/lib.rs",
+            "src/nested/local.rs", "a/b/generated/out.rs", ".git/config"] {
+            write(&root, path, b"text\n");
+        }
+
+        let result = ingest(&root, &[], &[], &[]);
+        let paths: Vec<&str> = result.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(paths, [".gitignore", "logs/keep.log", "main.rs", "src/.gitignore", "src/lib.rs", "src/nested/local.rs"]);
+    }
+
+    #[test]
+    fn globs_filter_the_walk() {
+        let root = temp_dir();
+        for path in ["src/main.rs", "src/vendor/dep.rs", "README.md"] {
+            write(&root, path, b"text\n");
+        }
+        let include = compile_globs(&["**/*.rs".to_string()]).unwrap();
+        let exclude = compile_globs(&["src/vendor".to_string()]).unwrap();
+
+        let result = ingest(&root, &include, &exclude, &[]);
+        assert_eq!(statuses(&result.files), [("src/main.rs", STATUS_INCLUDED)]);
+        assert!(compile_globs(&["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn binary_and_oversized_files_are_listed_as_skipped() {
+        let root = temp_dir();
+        write(&root, "text.txt", b"hello\n");
+        write(&root, "image.png", b"\x89PNG\0\0\0");
+        write(&root, "big.txt", &vec![b'a'; MAX_FILE_BYTES as usize + 1]);
+
+        let result = ingest(&root, &[], &[], &[]);
+        assert_eq!(statuses(&result.files), [("big.txt", STATUS_OVERSIZED), ("image.png", STATUS_BINARY), ("text.txt", STATUS_INCLUDED)]);
+        assert_eq!((result.summary.included, result.summary.skipped, result.summary.included_bytes), (1, 2, 6));
+        assert_eq!(result.files[2].content.as_deref(), Some("hello\n"));
+    }
+
+    #[test]
+    fn reingesting_only_updates_changed_files() {
+        let root = temp_dir();
+        write(&root, "a.txt", b"one\n");
+        write(&root, "b.txt", b"two\n");
+        write(&root, "c.txt", b"three\n");
+        let first = ingest(&root, &[], &[], &[]);
+
+        write(&root, "b.txt", b"two, edited\n");
+        std::fs::remove_file(root.join("c.txt")).unwrap();
+        write(&root, "d.txt", b"four\n");
+        let second = ingest(&root, &[], &[], &first.files);
+
+        let summary = &second.summary;
+        assert_eq!((summary.added, summary.updated, summary.removed, summary.unchanged), (1, 1, 1, 1));
+        let changed: Vec<&str> = second.changed.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(changed, ["b.txt", "d.txt"]);
+        assert_eq!(second.removed, ["c.txt"]);
+    }
+
+    #[test]
+    fn files_over_budget_are_included_once_budget_frees_up() {
+        let root = temp_dir();
+        // Eight of these fit the total budget; the ninth doesn't
+        let file_bytes = MAX_TOTAL_BYTES as usize / 8 - 1;
+        for i in 0..9 {
+            write(&root, &format!("file{}.txt", i), &vec![b'a'; file_bytes]);
+        }
+        let first = ingest(&root, &[], &[], &[]);
+        assert_eq!(first.files[8].status, STATUS_OVER_BUDGET);
+        assert_eq!(first.summary.included, 8);
+
+        std::fs::remove_file(root.join("file0.txt")).unwrap();
+        let second = ingest(&root, &[], &[], &first.files);
+        assert!(second.files.iter().all(ProjectFile::is_included));
+        assert_eq!((second.summary.updated, second.summary.unchanged, second.summary.removed), (1, 7, 1));
+        assert_eq!(second.changed[0].path, "file8.txt");
+    }
+
+    #[test]
+    fn digest_lists_the_tree_then_included_contents() {
+        let context = ProjectContext {
+            conversation_id: Uuid::new_v4(),
+            root: "/home/me/project".to_string(),
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            updated_at: Utc::now(),
+        };
+        let file = |path: &str, status: &str, content: Option<&str>| ProjectFile {
+            path: path.to_string(),
+            status: status.to_string(),
+            size: 0,
+            modified: None,
+            hash: None,
+            content: content.map(str::to_string),
+        };
+        let files = [
+            file("README.md", STATUS_INCLUDED, Some("# Project")),
+            file("src/assets/logo.png", STATUS_BINARY, None),
+            file("src/main.rs", STATUS_INCLUDED, Some("fn main() {}\n")),
+            file("src/vendor.rs", STATUS_OVER_BUDGET, None),
+        ];
+
+        assert_eq!(
+            digest(&context, &files),
+            "Project files from \"project\" (2 included, 2 skipped and not visible to you):\n\n\
+             README.md\n\
+             src/\n  assets/\n    logo.png (skipped: binary)\n  main.rs\n  vendor.rs (skipped: over budget)\n\
+             \n--- README.md ---\n# Project\n\
+             \n--- src/main.rs ---\nfn main() {}\n"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// Detection and repair of rows left dangling by deletes (only `messages` has a cascading foreign
+// key, so rows in the other tables can outlive their conversation),
+// plus SQLite's own consistency checks of the database file
+
+use serde::{Deserialize, Serialize};
+
+/// Findings of `check_data_integrity`. Ids are listed so the user can inspect them before repairing.
+/// The schema has no attachment or search-index tables, so messages, conversations and
+/// metadata are the only things that can dangle.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IntegrityReport {
+    pub orphaned_message_ids: Vec<String>, // Messages whose conversation no longer exists
+    pub conversations_missing_model_config: Vec<String>,
+    pub invalid_metadata_message_ids: Vec<String>, // Metadata that isn't valid JSON
+}
+
+impl IntegrityReport {
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_message_ids.is_empty()
+            && self.conversations_missing_model_config.is_empty()
+            && self.invalid_metadata_message_ids.is_empty()
+    }
+}
+
+/// Which repairs `repair_data_integrity` should apply. Everything defaults to off.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RepairActions {
+    pub delete_orphaned_messages: bool,
+    pub reassign_missing_model_configs: bool, // Points them at the default model config
+    pub clear_invalid_metadata: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RepairSummary {
+    pub deleted_messages: u64,
+    pub reassigned_conversations: u64,
+    pub cleared_metadata: u64,
+}
+
+/// A row whose foreign key points at a missing parent, per `PRAGMA foreign_key_check`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ForeignKeyViolation {
+    pub table: String,
+    pub rowid: Option<i64>, // None for WITHOUT ROWID tables
+    pub parent: String, // Table the key refers to
+}
+
+/// Findings of `check_database_integrity`.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DatabaseIntegrityReport {
+    pub ok: bool,
+    pub integrity_check: Vec<String>, // `PRAGMA integrity_check` output; just "ok" for a sound file
+    pub foreign_key_violations: Vec<ForeignKeyViolation>,
+}
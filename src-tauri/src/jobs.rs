@@ -0,0 +1,158 @@
+// Persistent retry queue for background utility requests (titles, summaries). A request
+// that fails is stored in `pending_jobs` and retried with exponential backoff by
+// `run_job_loop`, started at setup, until it succeeds or runs out of attempts. Jobs of
+// conversations that were deleted in the meantime are dropped.
+
+use crate::state::AppState;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const JOB_TITLE: &str = "title";
+pub const JOB_SUMMARY: &str = "summary";
+
+// Attempts (including the original request) before a job is given up
+pub const MAX_JOB_ATTEMPTS: i64 = 6;
+// Delay before the first retry; doubles with every failed attempt up to MAX_RETRY_DELAY
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(30);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+// How often the loop looks for due jobs, and how many it runs per pass
+const POLL_INTERVAL: Duration = Duration::from_secs(15);
+const JOBS_PER_POLL: i64 = 10;
+
+/// A utility request waiting to be retried.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingJob {
+    pub id: Uuid,
+    pub job_type: String, // JOB_TITLE or JOB_SUMMARY
+    pub conversation_id: Uuid,
+    pub payload: serde_json::Value, // Job parameters, e.g. a `TitleJob`
+    pub attempts: i64, // Failed attempts so far
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Payload of a JOB_TITLE job: the parameters the title was originally requested with.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TitleJob {
+    pub utility_model_config_id: Option<Uuid>, // None uses the utility model setting
+    pub instruction: Option<String>,
+    pub max_chars: Option<usize>, // None uses the title length setting
+}
+
+/// Why a utility request didn't succeed.
+#[derive(Debug)]
+pub enum JobFailure {
+    Retry(String), // Worth trying again later, e.g. the endpoint was unreachable
+    Drop(String), // Retrying won't help, e.g. the conversation is gone
+}
+
+// Delay before the next attempt once `attempts` attempts have failed
+fn retry_delay(attempts: i64) -> Duration {
+    let exponent = attempts.saturating_sub(1).clamp(0, 16) as u32;
+    BASE_RETRY_DELAY.saturating_mul(2u32.saturating_pow(exponent)).min(MAX_RETRY_DELAY)
+}
+
+fn next_attempt_after(attempts: i64) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::from_std(retry_delay(attempts)).unwrap_or_else(|_| chrono::Duration::hours(1))
+}
+
+/// Stores a request that just failed so the job loop retries it. A conversation has at
+/// most one pending job of each type; later failures of the same kind are not added.
+pub async fn enqueue(state: &AppState, job_type: &str, conversation_id: Uuid, payload: serde_json::Value, error: &str) {
+    let job = PendingJob {
+        id: Uuid::new_v4(),
+        job_type: job_type.to_string(),
+        conversation_id,
+        payload,
+        attempts: 1,
+        next_attempt_at: next_attempt_after(1),
+        last_error: Some(error.to_string()),
+        created_at: Utc::now(),
+    };
+    let storage = state.storage.lock().await;
+    match storage.enqueue_job(&job).await {
+        Ok(true) => log::info!(
+            "Queued '{}' retry for conversation {} at {}",
+            job_type, conversation_id, job.next_attempt_at
+        ),
+        Ok(false) => log::debug!("A '{}' retry for conversation {} is already queued", job_type, conversation_id),
+        Err(e) => log::error!("Failed to queue '{}' retry for conversation {}: {:?}", job_type, conversation_id, e),
+    }
+}
+
+/// Runs due jobs every POLL_INTERVAL for the life of the app. Jobs are stored, so
+/// anything still pending at shutdown is picked up after the next start.
+pub async fn run_job_loop(state: AppState) {
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        process_due_jobs(&state).await;
+    }
+}
+
+async fn process_due_jobs(state: &AppState) {
+    let _work = state.begin_background_work();
+    let jobs = {
+        let storage = state.storage.lock().await;
+        match storage.due_jobs(Utc::now(), JOBS_PER_POLL).await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                log::error!("Failed to load due jobs: {:?}", e);
+                return;
+            }
+        }
+    };
+    for job in jobs {
+        let outcome = run_job(state, &job).await;
+        let storage = state.storage.lock().await;
+        let result = match outcome {
+            Ok(()) => {
+                log::info!("Retried '{}' job {} succeeded", job.job_type, job.id);
+                storage.delete_job(job.id).await.map(|_| ())
+            }
+            Err(JobFailure::Drop(reason)) => {
+                log::info!("Dropping '{}' job {}: {}", job.job_type, job.id, reason);
+                storage.delete_job(job.id).await.map(|_| ())
+            }
+            Err(JobFailure::Retry(error)) if job.attempts + 1 >= MAX_JOB_ATTEMPTS => {
+                log::warn!("Giving up on '{}' job {} after {} attempts: {}", job.job_type, job.id, job.attempts + 1, error);
+                storage.delete_job(job.id).await.map(|_| ())
+            }
+            Err(JobFailure::Retry(error)) => {
+                let attempts = job.attempts + 1;
+                log::info!("'{}' job {} failed again (attempt {}): {}", job.job_type, job.id, attempts, error);
+                storage.reschedule_job(job.id, attempts, next_attempt_after(attempts), &error).await
+            }
+        };
+        if let Err(e) = result {
+            log::error!("Failed to update job {}: {:?}", job.id, e);
+        }
+    }
+}
+
+async fn run_job(state: &AppState, job: &PendingJob) -> Result<(), JobFailure> {
+    let conversation = {
+        let storage = state.storage.lock().await;
+        storage.get_conversation(job.conversation_id).await
+            .map_err(|e| JobFailure::Retry(format!("Failed to load conversation: {}", e)))?
+    };
+    match conversation {
+        Some(conversation) if conversation.deleted_at.is_none() => {}
+        _ => return Err(JobFailure::Drop("conversation was deleted".to_string())),
+    }
+
+    match job.job_type.as_str() {
+        JOB_TITLE => {
+            let title_job: TitleJob = serde_json::from_value(job.payload.clone())
+                .map_err(|e| JobFailure::Drop(format!("Invalid title job payload: {}", e)))?;
+            crate::commands::generate_title(state, job.conversation_id, title_job).await
+        }
+        JOB_SUMMARY => crate::commands::refresh_conversation_summary(state, job.conversation_id).await,
+        other => Err(JobFailure::Drop(format!("Unknown job type '{}'", other))),
+    }
+}
@@ -0,0 +1,105 @@
+// Lightweight language detection for conversation language hints. Scripts with a single
+// dominant language are recognized from their Unicode ranges; Latin-script text is
+// scored against short stopword lists. Anything ambiguous yields None.
+
+// (ISO 639-1 code, English name) of every language `detect_language` can return
+const LANGUAGE_NAMES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("th", "Thai"),
+    ("zh", "Chinese"),
+];
+
+const STOPWORDS: &[(&str, &[&str])] = &[
+    ("en", &["the", "and", "is", "are", "what", "how", "you", "this", "that", "with", "for", "can", "of"]),
+    ("es", &["el", "la", "los", "las", "que", "es", "por", "para", "con", "una", "cómo", "qué", "del"]),
+    ("fr", &["le", "la", "les", "est", "que", "pour", "avec", "une", "des", "je", "vous", "comment", "dans"]),
+    ("de", &["der", "die", "das", "und", "ist", "nicht", "ich", "mit", "wie", "ein", "eine", "für", "auf"]),
+    ("it", &["il", "che", "è", "per", "con", "una", "sono", "come", "della", "non", "gli", "questo", "del"]),
+    ("pt", &["o", "que", "é", "para", "com", "uma", "não", "como", "os", "do", "da", "você", "em"]),
+    ("nl", &["de", "het", "een", "en", "is", "niet", "ik", "met", "hoe", "van", "voor", "dat", "wat"]),
+];
+
+// Minimum stopword hits, and lead over the runner-up, for a Latin-script guess
+const MIN_STOPWORD_HITS: usize = 2;
+const MIN_STOPWORD_LEAD: usize = 2;
+
+/// English name for a language code, for use in prompts.
+pub fn language_name(code: &str) -> Option<&'static str> {
+    LANGUAGE_NAMES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(code))
+        .map(|(_, name)| *name)
+}
+
+fn script_language(c: char) -> Option<&'static str> {
+    match c as u32 {
+        0x3040..=0x30FF => Some("ja"), // Hiragana, Katakana
+        0xAC00..=0xD7AF | 0x1100..=0x11FF => Some("ko"),
+        0x4E00..=0x9FFF => Some("zh"), // Han; counted as Japanese when kana are present too
+        0x0400..=0x04FF => Some("ru"),
+        0x0600..=0x06FF => Some("ar"),
+        0x0590..=0x05FF => Some("he"),
+        0x0370..=0x03FF => Some("el"),
+        0x0900..=0x097F => Some("hi"),
+        0x0E00..=0x0E7F => Some("th"),
+        _ => None,
+    }
+}
+
+/// Best guess at the dominant language of `text` as an ISO 639-1 code, or None when unsure.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let letters = text.chars().filter(|c| c.is_alphabetic()).count();
+    if letters == 0 {
+        return None;
+    }
+
+    let mut script_counts: Vec<(&'static str, usize)> = Vec::new();
+    for language in text.chars().filter_map(script_language) {
+        match script_counts.iter_mut().find(|(known, _)| *known == language) {
+            Some((_, count)) => *count += 1,
+            None => script_counts.push((language, 1)),
+        }
+    }
+    let count_of = |language: &str| script_counts.iter().find(|(l, _)| *l == language).map(|(_, c)| *c).unwrap_or(0);
+    if count_of("ja") > 0 {
+        // Japanese mixes kana with Han characters
+        let japanese = count_of("ja") + count_of("zh");
+        if japanese * 2 >= letters {
+            return Some("ja");
+        }
+    }
+    if let Some((language, count)) = script_counts.iter().copied().max_by_key(|(_, count)| *count) {
+        if count * 2 >= letters {
+            return Some(language);
+        }
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect();
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| (*language, words.iter().filter(|w| stopwords.contains(&w.as_str())).count()))
+        .collect();
+    scores.sort_by_key(|&(_, hits)| std::cmp::Reverse(hits));
+    match scores.as_slice() {
+        [(language, best), (_, runner_up), ..]
+            if *best >= MIN_STOPWORD_HITS && best - runner_up >= MIN_STOPWORD_LEAD => Some(language),
+        _ => None,
+    }
+}
@@ -1,17 +1,52 @@
 // Learn more about Tauri commands at https://tauri.app/develop/calling-rust/
 
 // Declare the modules
+pub mod about;
 pub mod api;
+pub mod auto_export;
+pub mod automation;
+pub mod budget;
+pub mod bundle;
+pub mod changelog;
+pub mod codeblocks;
 pub mod commands;
 pub mod config;
+pub mod diagnostics;
+pub mod error;
+pub mod events;
+pub mod export;
+pub mod generation;
+pub mod health;
+pub mod ingest;
+pub mod integrity;
+pub mod jobs;
+pub mod language;
+pub mod logs;
+pub mod memory;
+#[cfg(any(test, feature = "testing"))]
+pub mod mock;
 pub mod models;
+pub mod prompt;
+pub mod prompt_files;
+pub mod redaction;
+pub mod safe_mode;
+pub mod search;
+pub mod smoothing;
 pub mod state;
 pub mod storage;
+pub mod theme;
+pub mod title;
+pub mod tools;
+pub mod transcript;
+pub mod usage;
+pub mod utility;
+#[cfg(test)]
+mod test_support;
 
 use state::AppState;
 use storage::StorageManager;
+use safe_mode::StorageStatus;
 use tauri::Manager;
-use tauri::TitleBarStyle;
 use tauri_plugin_opener::OpenerExt; // Import the correct trait
 use commands::{list_conversations, create_conversation, get_conversation_messages, delete_conversation, send_message, rename_conversation, list_model_configs, add_model_config, update_model_config, delete_model_config, update_conversation_model, stop_generation}; // Import commands
 use commands::regenerate_last_response; // Import regenerate command
@@ -24,74 +59,338 @@ use std::sync::Arc;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    // Initialize logging
-    env_logger::init();
+    // Initialize logging (stderr now, plus a rotating file once setup knows the log directory)
+    logs::init();
 
-    tauri::Builder::default()
+    tauri::Builder::<state::AppRuntime>::new()
         .setup(|app| {
             // Initialize the StorageManager and create the AppState
             // We block here because setup is synchronous, but StorageManager::new is async.
             // This is generally okay for one-time setup.
             let app_handle = app.handle().clone();
-            let storage_manager = tauri::async_runtime::block_on(
-                async { StorageManager::new(&app_handle).await }
-            )?;
+            match app_handle.path().app_log_dir() {
+                Ok(log_dir) => {
+                    if let Err(e) = logs::attach_log_dir(&log_dir) {
+                        log::error!("Failed to open log file in {:?}: {}", log_dir, e);
+                    }
+                }
+                Err(e) => log::error!("Failed to resolve log directory: {}", e),
+            }
+            // Open the database (adding the default model config if none exist). When that
+            // fails the window still comes up, in safe mode with an empty placeholder, so the
+            // user can retry or reset it instead of facing nothing
+            let db_path = StorageManager::default_path(&app_handle)?;
+            let (storage_manager, storage_status) = match tauri::async_runtime::block_on(safe_mode::open(&db_path)) {
+                Ok(storage_manager) => (storage_manager, StorageStatus::Ready),
+                Err(e) => {
+                    log::error!("Failed to open the database at {:?}, starting in safe mode: {:#}", db_path, e);
+                    let placeholder = tauri::async_runtime::block_on(safe_mode::placeholder())?;
+                    (placeholder, StorageStatus::failed(&e, &db_path))
+                }
+            };
+            let storage_ready = storage_status.is_ready();
 
-            // Add default model config if none exist
-            tauri::async_runtime::block_on(
-                async { storage_manager.add_default_model_config_if_none().await }
-            )?;
+            // Report (but don't repair) dangling rows; repairs are left to the user
+            match tauri::async_runtime::block_on(storage_manager.check_data_integrity()) {
+                Ok(report) if !report.is_clean() => log::warn!(
+                    "Data integrity check: {} orphaned messages, {} conversations with missing model configs, {} messages with invalid metadata",
+                    report.orphaned_message_ids.len(),
+                    report.conversations_missing_model_config.len(),
+                    report.invalid_metadata_message_ids.len()
+                ),
+                Ok(_) => log::info!("Data integrity check found no problems"),
+                Err(e) => log::error!("Data integrity check failed: {:?}", e),
+            }
+
+            // Scrub the configured secret patterns from log output from here on
+            let redaction_settings = tauri::async_runtime::block_on(redaction::load_settings(&storage_manager));
+            logs::set_pattern_redactor(redaction::compile(&redaction_settings));
 
             // Create the API provider instance
             let api_provider: Arc<dyn LLMApiProvider> = Arc::new(OpenAICompatibleProvider::new());
 
+            let max_concurrent_streams = tauri::async_runtime::block_on(
+                storage_manager.get_setting(config::MAX_CONCURRENT_STREAMS_KEY)
+            )
+            .ok()
+            .flatten()
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|limit| *limit > 0)
+            .unwrap_or(config::DEFAULT_MAX_CONCURRENT_STREAMS);
+
+            let offline_mode = tauri::async_runtime::block_on(storage_manager.get_setting(config::OFFLINE_MODE_KEY))
+                .ok()
+                .flatten()
+                .is_some_and(|value| value == "true");
+
             // Pass AppHandle to AppState
-            let app_state = AppState::new(storage_manager, api_provider, app_handle.clone());
+            let app_state = AppState::new(storage_manager, storage_status, api_provider, app_handle.clone(), max_concurrent_streams);
+            app_state.offline_mode.store(offline_mode, std::sync::atomic::Ordering::Relaxed);
+
+            // Check keys and endpoints in the background so a dead endpoint can't delay startup
+            if storage_ready {
+                let health_state = app_state.clone();
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = health::run_health_check(&health_state, true).await {
+                        log::error!("Startup health check failed: {}", e);
+                    }
+                });
+            }
+
+            // Retry utility requests that failed earlier, including before the last restart
+            tauri::async_runtime::spawn(jobs::run_job_loop(app_state.clone()));
+
+            // Daily export of changed conversations, when turned on
+            tauri::async_runtime::spawn(auto_export::run_auto_export_loop(app_state.clone()));
+
+            // Drop conversation change history past its retention window
+            tauri::async_runtime::spawn(changelog::run_changelog_prune_loop(app_state.clone()));
+
+            // Keep the prompt file list in step with the prompt folder
+            tauri::async_runtime::spawn(prompt_files::run_prompt_watch_loop(app_state.clone()));
+
+            // Apply the saved light/dark override before the window first paints. The window
+            // still follows the OS at this point, so its theme is the system one.
+            if let Some(os_theme) = app.get_webview_window("main").and_then(|window| window.theme().ok()) {
+                theme::record_system_theme(os_theme);
+            }
+            let (theme_preference, titlebar_preference) = tauri::async_runtime::block_on(async {
+                let storage = app_state.storage.lock().await;
+                (theme::load_preference(&storage).await, theme::load_titlebar_preference(&storage).await)
+            });
+            theme::apply(&app_handle, theme_preference);
 
             // Add the AppState to Tauri's managed state
+            let automation_state = app_state.clone();
             app.manage(app_state);
 
-            // ---- Apply macOS specific window styles at the end of setup ----
-            #[cfg(target_os = "macos")]
-            {
-                println!("Applying macOS window styles...");
-                if let Some(window) = app.get_webview_window("main") {
-                    // Set title bar style to Overlay
-                    match window.set_title_bar_style(TitleBarStyle::Overlay) {
-                        Ok(_) => println!("Successfully set title bar style to Overlay."),
-                        Err(e) => eprintln!("Error setting title bar style: {}", e),
+            // The automation API calls commands through the managed state, so start it after
+            if storage_ready {
+                tauri::async_runtime::spawn(async move {
+                    if let Err(e) = automation::restart(&automation_state).await {
+                        log::error!("Automation API not started: {}", e);
                     }
-                } else {
-                    eprintln!("Main window not found during setup.");
-                }
+                });
+            }
+
+            // ---- Apply the saved macOS title bar style at the end of setup (no-op elsewhere) ----
+            if let Err(e) = theme::apply_titlebar(&app_handle, titlebar_preference) {
+                log::error!("Error setting title bar style: {}", e);
             }
-            // ---- End macOS specific styles ----
 
             Ok(())
         })
+        // Closed windows stop receiving conversation events; OS theme switches are passed on
+        .on_window_event(|window, event| match event {
+            tauri::WindowEvent::Destroyed => {
+                if let Some(state) = window.try_state::<AppState>() {
+                    state.forget_window(window.label());
+                }
+            }
+            tauri::WindowEvent::ThemeChanged(new_theme) => {
+                let Some(state) = window.try_state::<AppState>() else {
+                    return;
+                };
+                let state = state.inner().clone();
+                let new_theme = *new_theme;
+                tauri::async_runtime::spawn(async move {
+                    let preference = {
+                        let storage = state.storage.lock().await;
+                        theme::load_preference(&storage).await
+                    };
+                    // With an override in place the window reports the forced theme, not the OS's
+                    if preference == theme::ThemePreference::System {
+                        theme::record_system_theme(new_theme);
+                        theme::emit_changed(&state.app_handle, preference);
+                    }
+                });
+            }
+            _ => {}
+        })
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_clipboard_manager::init())
         .plugin(tauri_plugin_global_shortcut::Builder::new().build())
         .plugin(tauri_plugin_store::Builder::default().build())
         // Register the command(s) with the handler
-        .invoke_handler(tauri::generate_handler![
+        // In safe mode, commands that need the database are refused before they run
+        .invoke_handler(safe_mode::guard(tauri::generate_handler![
             list_conversations,
+            crate::commands::list_conversations_between,
+            crate::commands::get_conversation_sort,
+            crate::commands::set_conversation_sort,
             create_conversation,
+            crate::commands::import_transcript,
+            crate::commands::import_markdown_conversation,
             get_conversation_messages,
+            crate::commands::get_messages_since,
+            crate::commands::get_all_messages_since,
+            crate::commands::get_conversation_message_counts,
+            crate::commands::find_in_conversation,
+            crate::commands::search_in_conversation,
+            crate::commands::export_conversation,
+            crate::commands::export_conversation_jsonl,
+            crate::commands::copy_conversation_to_clipboard,
+            crate::commands::extract_code_blocks,
+            crate::commands::save_code_block,
             delete_conversation,
+            crate::commands::merge_conversations,
+            crate::commands::split_conversation,
+            crate::commands::list_deleted_conversations,
+            crate::commands::list_oldest_conversations,
+            crate::commands::list_conversations_with_missing_model,
+            crate::commands::restore_conversation,
+            crate::commands::purge_deleted_conversations,
+            crate::commands::check_data_integrity,
+            crate::commands::get_storage_status,
+            crate::commands::retry_storage_init,
+            crate::commands::reset_database,
+            crate::commands::wipe_all_data,
+            crate::commands::check_database_integrity,
+            crate::commands::get_message_debug,
+            crate::commands::compact_database,
+            crate::commands::get_conversation_sizes,
+            crate::commands::run_maintenance,
+            crate::commands::repair_data_integrity,
             send_message,
+            crate::commands::send_message_multi,
+            crate::commands::keep_comparison_result,
+            crate::commands::select_message_variant,
+            crate::commands::get_conversation_history,
+            crate::commands::undo_last_event,
+            crate::commands::set_message_context_pinned,
+            crate::commands::set_message_role,
+            crate::commands::reorder_messages,
+            crate::commands::stop_comparison,
             rename_conversation,
             update_conversation_model,
+            crate::commands::set_conversation_model,
+            crate::commands::set_conversation_token_budget,
+            crate::commands::set_conversation_language,
+            crate::commands::get_conversation_metadata,
+            crate::commands::set_conversation_metadata,
+            crate::commands::set_conversation_system_prompt,
+            crate::commands::set_conversation_persona,
+            crate::commands::ingest_directory,
+            crate::commands::get_project_manifest,
+            crate::commands::clear_project_context,
+            crate::commands::set_conversation_style_preset,
+            crate::commands::set_conversation_appearance,
+            crate::commands::set_conversation_locked,
+            crate::commands::set_conversation_single_turn,
+            crate::commands::set_conversation_ephemeral,
+            crate::commands::get_default_system_prompt,
+            crate::commands::set_default_system_prompt,
+            crate::commands::get_system_prompt_suffix,
+            crate::commands::set_system_prompt_suffix,
+            crate::commands::get_utility_model_config_id,
+            crate::commands::set_utility_model_config_id,
+            crate::commands::get_title_model_config_id,
+            crate::commands::set_title_model_config_id,
+            crate::commands::get_default_user_id,
+            crate::commands::set_default_user_id,
+            crate::commands::get_budget_status,
+            crate::commands::set_monthly_budget,
+            crate::commands::get_message_size_limit,
+            crate::commands::set_message_size_limit,
+            crate::commands::export_usage_csv,
+            crate::commands::save_usage_csv,
+            crate::commands::get_context_usage,
+            crate::commands::estimate_message_cost,
+            crate::commands::preview_truncation,
+            crate::commands::list_pending_jobs,
+            crate::commands::cancel_pending_job,
+            crate::commands::get_auto_export,
+            crate::commands::set_auto_export,
+            crate::commands::run_auto_export_now,
+            crate::commands::get_activity_stats,
+            crate::commands::get_activity_by_day,
+            crate::commands::export_conversation_bundle,
+            crate::commands::import_conversation_bundle,
+            crate::commands::get_conversation_model,
+            crate::commands::get_title_settings,
+            crate::commands::set_title_settings,
+            crate::commands::get_redaction_settings,
+            crate::commands::set_redaction_settings,
+            crate::commands::preview_redaction,
+            crate::commands::get_system_theme,
+            crate::commands::get_theme_preference,
+            crate::commands::set_theme_preference,
+            crate::commands::get_titlebar_style,
+            crate::commands::set_titlebar_style,
+            crate::commands::get_prompt_dir,
+            crate::commands::set_prompt_dir,
+            crate::commands::list_file_prompts,
+            crate::commands::get_file_prompt,
+            crate::commands::get_trim_leading_whitespace,
+            crate::commands::set_trim_leading_whitespace,
+            crate::commands::get_offline_mode,
+            crate::commands::set_offline_mode,
+            crate::commands::get_automation_settings,
+            crate::commands::set_automation_settings,
+            crate::commands::get_automation_token,
+            crate::commands::regenerate_automation_token,
+            crate::commands::get_stream_smoothing,
+            crate::commands::set_stream_smoothing,
             list_model_configs,
             add_model_config,
+            crate::commands::create_starter_configs,
             update_model_config,
+            crate::commands::rename_model_config,
             delete_model_config,
+            crate::commands::list_personas,
+            crate::commands::add_persona,
+            crate::commands::update_persona,
+            crate::commands::delete_persona,
+            crate::commands::get_provider_options_schema,
+            crate::commands::get_provider_options_schemas,
+            crate::commands::get_provider_capabilities,
+            crate::commands::validate_provider_options,
+            crate::commands::check_api_key,
+            crate::commands::run_health_check,
+            crate::commands::get_connection_diagnostics,
+            crate::commands::export_diagnostics,
+            crate::commands::get_diagnostics,
+            crate::commands::get_app_info,
+            crate::commands::check_for_updates,
+            crate::commands::migrate_api_key_to_keyring,
+            crate::commands::reorder_model_configs,
+            crate::commands::set_default_model_config,
+            crate::commands::provider_raw_request,
+            crate::commands::open_library,
             stop_generation,
+            crate::commands::stop_conversation_generation,
+            crate::commands::cancel_conversation_tasks,
+            crate::commands::get_active_streams,
+            crate::commands::subscribe_conversation,
+            crate::commands::unsubscribe_conversation,
+            crate::commands::get_recent_errors,
+            crate::commands::get_conversation_memory,
+            crate::commands::clear_conversation_memory,
+            crate::commands::summarize_conversation,
+            crate::commands::create_conversation_snapshot,
+            crate::commands::list_conversation_snapshots,
+            crate::commands::restore_conversation_snapshot,
+            crate::commands::delete_conversation_snapshot,
+            crate::commands::respond_tool_permission,
+            crate::commands::list_tool_permissions,
+            crate::commands::set_tool_permission,
             regenerate_last_response,
+            crate::commands::continue_truncated_response,
+            crate::commands::continue_response,
             crate::commands::open_url,
-            crate::commands::generate_conversation_title
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+            crate::commands::generate_conversation_title,
+            crate::commands::maybe_generate_title,
+            crate::commands::generate_title_with_instruction
+        ]))
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app_handle, event| {
+            // Close the automation port before the process goes away
+            if let tauri::RunEvent::Exit = event {
+                if let Some(state) = app_handle.try_state::<AppState>() {
+                    tauri::async_runtime::block_on(automation::stop(&state));
+                }
+            }
+        });
 }
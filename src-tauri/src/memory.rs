@@ -0,0 +1,381 @@
+// Summarized memory for long conversations: history that no longer fits the context
+// window is folded into a cached summary instead of being dropped outright.
+
+use crate::api::StreamEvent;
+use crate::config;
+use crate::events::{self, SummaryChunk, SummaryFinished};
+use crate::jobs;
+use crate::models::{Conversation, Message, ModelConfig};
+use crate::prompt;
+use crate::redaction;
+use crate::state::AppState;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use serde::Serialize;
+use uuid::Uuid;
+
+// Room kept in the context window for the summary itself
+pub const SUMMARY_RESERVE_TOKENS: usize = 512;
+// Per-message cap when quoting messages to the summarizer
+const SUMMARY_INPUT_CHARS: usize = 2000;
+// Most messages quoted when summarizing a conversation that is being split
+const SPLIT_SUMMARY_MESSAGES: usize = 200;
+
+// Most messages quoted in a catch-up summary; older ones are left out
+const CATCH_UP_MESSAGES: usize = 200;
+
+const CATCH_UP_PROMPT: &str = "Summarize this conversation between a user and an AI assistant for someone catching up on it. Cover what was asked, what was concluded or decided, and what is still open. Use short paragraphs or bullet points.";
+
+const SUMMARIZER_PROMPT: &str = "You maintain a running summary of a conversation between a user and an AI assistant. Update the summary with the new messages. Keep facts, decisions, names, open questions and anything the user asked to be remembered. Reply with the summary only, in under 300 words.";
+
+/// Cached summary of a conversation's oldest messages, up to and including `last_message_id`.
+/// Editing or deleting a covered message clears it (see `StorageManager`).
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationMemory {
+    pub conversation_id: Uuid,
+    pub summary: String,
+    pub last_message_id: Uuid,
+    pub covered_until: DateTime<Utc>, // Timestamp of `last_message_id`
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Like `prompt::build_api_messages`, but unpinned messages that don't fit are summarized
+/// with `summarizer` and the summary is appended to the system message. Ephemeral
+/// conversations, and any failure to summarize, fall back to plain trimming. Single-turn
+/// conversations send only the newest user message.
+pub async fn build_api_messages(
+    state: &AppState,
+    conversation: &Conversation,
+    system_message: Message,
+    history: Vec<Message>,
+    model_config: &ModelConfig,
+    summarizer: &ModelConfig,
+) -> Vec<Message> {
+    if conversation.single_turn {
+        return prompt::build_api_messages(system_message, prompt::single_turn_history(history), model_config);
+    }
+    if conversation.ephemeral {
+        return prompt::build_api_messages(system_message, history, model_config);
+    }
+    let Some(cut) = overflow_cut(&system_message, &history, model_config) else {
+        return prompt::build_api_messages(system_message, history, model_config);
+    };
+    let older: Vec<&Message> = history[..cut].iter().filter(|m| !m.is_context_pinned()).collect();
+
+    let summary = match summary_for(state, conversation.id, &older, summarizer).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            log::warn!("Summarizing conversation {} failed, trimming instead: {}", conversation.id, e);
+            // Build the summary later so the next request doesn't have to trim
+            jobs::enqueue(state, jobs::JOB_SUMMARY, conversation.id, serde_json::json!({}), &e).await;
+            return prompt::build_api_messages(system_message, history, model_config);
+        }
+    };
+    log::info!("Replaced {} old messages of conversation {} with a summary", older.len(), conversation.id);
+
+    let system_message = with_summary(system_message, &summary);
+    let mut history = history;
+    let recent = history.split_off(cut);
+    history.retain(|m| m.is_context_pinned());
+    let mut api_messages = vec![system_message];
+    api_messages.extend(history);
+    api_messages.extend(recent);
+    api_messages
+}
+
+/// Estimated usage of the request `build_api_messages` would build now, without summarizing
+/// anything: the cached summary stands in for the one the request would use, and a summary
+/// that doesn't exist yet is counted at SUMMARY_RESERVE_TOKENS.
+pub async fn context_usage(
+    state: &AppState,
+    conversation: &Conversation,
+    system_message: &Message,
+    history: &[Message],
+    model_config: &ModelConfig,
+) -> prompt::ContextUsage {
+    plan_request(state, conversation, system_message, history, model_config).await.1
+}
+
+/// The messages of `history` the request `build_api_messages` would build now keeps, and its
+/// estimated usage (see `context_usage`). Also whether older messages would be summarized.
+pub async fn plan_request(
+    state: &AppState,
+    conversation: &Conversation,
+    system_message: &Message,
+    history: &[Message],
+    model_config: &ModelConfig,
+) -> (Vec<Message>, prompt::ContextUsage, bool) {
+    if conversation.single_turn {
+        let sent = prompt::fit_history(prompt::single_turn_history(history.to_vec()), prompt::history_budget(system_message, model_config));
+        let usage = prompt::ContextUsage::measure(system_message, &sent, history.len(), model_config);
+        return (sent, usage, false);
+    }
+    let cut = if conversation.ephemeral { None } else { overflow_cut(system_message, history, model_config) };
+    let Some(cut) = cut else {
+        let sent = prompt::fit_history(history.to_vec(), prompt::history_budget(system_message, model_config));
+        let usage = prompt::ContextUsage::measure(system_message, &sent, history.len(), model_config);
+        return (sent, usage, false);
+    };
+
+    let cached = {
+        let storage = state.storage.lock().await;
+        storage.get_conversation_memory(conversation.id).await
+            .map_err(|e| log::warn!("Failed to read memory of conversation {}: {:?}", conversation.id, e))
+            .ok()
+            .flatten()
+    };
+    let sent: Vec<Message> = history[..cut]
+        .iter()
+        .filter(|m| m.is_context_pinned())
+        .chain(&history[cut..])
+        .cloned()
+        .collect();
+    let usage = match cached {
+        Some(memory) => {
+            let system_message = with_summary(system_message.clone(), &memory.summary);
+            prompt::ContextUsage::measure(&system_message, &sent, history.len(), model_config)
+        }
+        None => {
+            let mut usage = prompt::ContextUsage::measure(system_message, &sent, history.len(), model_config);
+            usage.system_tokens += SUMMARY_RESERVE_TOKENS;
+            usage.prompt_tokens += SUMMARY_RESERVE_TOKENS;
+            usage
+        }
+    };
+    (sent, usage, true)
+}
+
+// Appends a history summary to the system message
+fn with_summary(mut system_message: Message, summary: &str) -> Message {
+    system_message.content.push_str("\n\n[Summary of earlier conversation]\n");
+    system_message.content.push_str(summary);
+    system_message
+}
+
+/// Brings the cached summary up to date with the history a request would send now, without
+/// sending one. Used to retry a summary that failed; Ok when there is nothing to summarize.
+pub async fn refresh_summary(
+    state: &AppState,
+    conversation: &Conversation,
+    system_message: &Message,
+    history: &[Message],
+    model_config: &ModelConfig,
+    summarizer: &ModelConfig,
+) -> Result<(), String> {
+    let Some(cut) = overflow_cut(system_message, history, model_config) else {
+        return Ok(());
+    };
+    let older: Vec<&Message> = history[..cut].iter().filter(|m| !m.is_context_pinned()).collect();
+    summary_for(state, conversation.id, &older, summarizer).await.map(|_| ())
+}
+
+/// Summary of `history` for seeding a conversation split off after it: the cached summary,
+/// when it covers a prefix of `history`, extended with the messages after it. At most
+/// SPLIT_SUMMARY_MESSAGES of the newest messages are quoted.
+pub async fn summarize_for_split(
+    state: &AppState,
+    conversation_id: Uuid,
+    history: &[Message],
+    summarizer: &ModelConfig,
+) -> Result<String, String> {
+    let cached = {
+        let storage = state.storage.lock().await;
+        storage.get_conversation_memory(conversation_id).await
+            .map_err(|e| format!("Failed to read conversation memory: {}", e))?
+    };
+    let messages: Vec<&Message> = history.iter().collect();
+    let (previous, pending) = match cached.and_then(|memory| {
+        messages.iter().position(|m| m.id == memory.last_message_id).map(|index| (memory.summary, index))
+    }) {
+        Some((summary, index)) => (Some(summary), &messages[index + 1..]),
+        None => (None, &messages[..]),
+    };
+    if pending.is_empty() {
+        return previous.ok_or_else(|| "Nothing to summarize".to_string());
+    }
+    let pending = &pending[pending.len().saturating_sub(SPLIT_SUMMARY_MESSAGES)..];
+    summarize(state, summarizer, previous.as_deref(), pending).await
+}
+
+// Where the kept recent window starts when `history` doesn't fit the config's context
+// window and some unpinned messages before it need summarizing; None when nothing does
+fn overflow_cut(system_message: &Message, history: &[Message], model_config: &ModelConfig) -> Option<usize> {
+    let budget = prompt::history_budget(system_message, model_config)?;
+    let total: usize = history.iter().map(prompt::estimate_tokens).sum();
+    if total <= budget {
+        return None;
+    }
+    let cut = prompt::recent_window_start(history, budget.saturating_sub(SUMMARY_RESERVE_TOKENS));
+    history[..cut].iter().any(|m| !m.is_context_pinned()).then_some(cut)
+}
+
+// The summary of `older`, reusing the cached one when it already covers them. A cache that
+// covers only a prefix is extended with the remaining messages rather than rebuilt.
+async fn summary_for(
+    state: &AppState,
+    conversation_id: Uuid,
+    older: &[&Message],
+    summarizer: &ModelConfig,
+) -> Result<String, String> {
+    let Some(last) = older.last() else {
+        return Err("Nothing to summarize".to_string());
+    };
+    let cached = {
+        let storage = state.storage.lock().await;
+        storage.get_conversation_memory(conversation_id).await
+            .map_err(|e| format!("Failed to read conversation memory: {}", e))?
+    };
+    let (previous, pending) = match cached {
+        Some(memory) if memory.last_message_id == last.id => return Ok(memory.summary),
+        Some(memory) => match older.iter().position(|m| m.id == memory.last_message_id) {
+            Some(index) => (Some(memory.summary), &older[index + 1..]),
+            None => (None, older),
+        },
+        None => (None, older),
+    };
+
+    let summary = summarize(state, summarizer, previous.as_deref(), pending).await?;
+    let memory = ConversationMemory {
+        conversation_id,
+        summary: summary.clone(),
+        last_message_id: last.id,
+        covered_until: last.timestamp,
+        updated_at: Utc::now(),
+    };
+    let storage = state.storage.lock().await;
+    if let Err(e) = storage.save_conversation_memory(&memory).await {
+        log::error!("Failed to cache summary for conversation {}: {:?}", conversation_id, e);
+    }
+    Ok(summary)
+}
+
+// Messages quoted to a summarizer, one `role: content` block each, every one capped at
+// SUMMARY_INPUT_CHARS
+fn transcript(messages: &[&Message]) -> String {
+    messages
+        .iter()
+        .map(|m| {
+            let content: String = m.content.chars().take(SUMMARY_INPUT_CHARS).collect();
+            format!("{}: {}", m.role, content)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+async fn summarize(
+    state: &AppState,
+    summarizer: &ModelConfig,
+    previous: Option<&str>,
+    messages: &[&Message],
+) -> Result<String, String> {
+    let api_key = config::get_api_key(summarizer).map_err(|e| format!("Failed to get API key: {}", e))?;
+    let provider = state.provider_for(summarizer).map_err(|e| e.message)?;
+
+    let transcript = transcript(messages);
+    let request = match previous {
+        Some(previous) => format!("Summary so far:\n{}\n\nNew messages:\n{}", previous, transcript),
+        None => format!("Messages:\n{}", transcript),
+    };
+    let conversation_id = messages.first().map(|m| m.conversation_id).unwrap_or_default();
+    let mut summary_messages = vec![
+        prompt::system_message(conversation_id, SUMMARIZER_PROMPT.to_string()),
+        Message {
+            id: Uuid::nil(),
+            conversation_id,
+            role: "user".to_string(),
+            content: request,
+            timestamp: Utc::now(),
+            metadata: None,
+            name: None,
+            variant_group: None,
+        },
+    ];
+
+    redaction::redact_outgoing(state, &mut summary_messages).await;
+    let request = provider.send_chat_request(summarizer, &api_key, &summary_messages);
+    let summary = state
+        .utility_queue
+        .run(conversation_id, "summary", request)
+        .await
+        .ok_or_else(|| "Summary request cancelled".to_string())?
+        .map_err(|e| format!("Summary request failed: {}", e))?;
+    let summary = summary.trim();
+    if summary.is_empty() {
+        return Err("Summarizer returned an empty summary".to_string());
+    }
+    Ok(summary.to_string())
+}
+
+/// Streams a catch-up summary of `history` from `summarizer` as `summary_chunk` events and
+/// ends with `summary_finished`. Nothing is saved. `summary_id` stops it through
+/// `stop_generation` like a message stream.
+pub async fn stream_catch_up_summary(
+    state: AppState,
+    conversation_id: Uuid,
+    summary_id: Uuid,
+    history: Vec<Message>,
+    summarizer: ModelConfig,
+) {
+    let Some(_stream_permit) = state.acquire_stream_permit(conversation_id).await else {
+        return;
+    };
+    let mut summary = String::new();
+    let mut seq: u64 = 0;
+    let mut cancelled = false;
+    let result = async {
+        let api_key = config::get_api_key(&summarizer).map_err(|e| format!("Failed to get API key: {:#}", e))?;
+        let provider = state.provider_for(&summarizer).map_err(|e| e.message)?;
+        let quoted: Vec<&Message> = history[history.len().saturating_sub(CATCH_UP_MESSAGES)..].iter().collect();
+        let mut request = vec![
+            prompt::system_message(conversation_id, CATCH_UP_PROMPT.to_string()),
+            Message {
+                id: Uuid::nil(),
+                conversation_id,
+                role: "user".to_string(),
+                content: format!("Messages:\n{}", transcript(&quoted)),
+                timestamp: Utc::now(),
+                metadata: None,
+                name: None,
+                variant_group: None,
+            },
+        ];
+        redaction::redact_outgoing(&state, &mut request).await;
+        let mut stream = provider.send_chat_stream_request(&summarizer, &api_key, &request).await
+            .map_err(|e| format!("Failed to start the summary: {:#}", e))?;
+        while let Some(event) = stream.next().await {
+            if state.cancelled_streams.contains_key(&summary_id) {
+                cancelled = true;
+                break;
+            }
+            match event {
+                Ok(StreamEvent::Delta(delta)) => {
+                    seq += 1;
+                    summary.push_str(&delta);
+                    let chunk = SummaryChunk { conversation_id: conversation_id.to_string(), summary_id: summary_id.to_string(), delta, seq };
+                    if let Err(e) = state.emit_to_conversation(conversation_id, events::SUMMARY_CHUNK, chunk) {
+                        log::error!("Summary [{}]: Failed to emit chunk event: {:?}", summary_id, e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => return Err(format!("Summary stream failed: {:#}", e)),
+            }
+        }
+        Ok(())
+    }
+    .await;
+    state.cancelled_streams.remove(&summary_id);
+    if let Err(e) = &result {
+        log::error!("Summary [{}] of conversation {} failed: {}", summary_id, conversation_id, e);
+    }
+    let finished = SummaryFinished {
+        conversation_id: conversation_id.to_string(),
+        summary_id: summary_id.to_string(),
+        summary,
+        cancelled,
+        error: result.err(),
+    };
+    if let Err(e) = state.emit_to_conversation(conversation_id, events::SUMMARY_FINISHED, finished) {
+        log::error!("Summary [{}]: Failed to emit finished event: {:?}", summary_id, e);
+    }
+}
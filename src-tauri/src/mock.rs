@@ -0,0 +1,164 @@
+// Scripted provider for exercising the send/regenerate pipelines without a real API.
+// Built for unit tests and with the `testing` feature; model configs select it with provider "mock"
+// and describe the stream in provider_options, e.g.
+// {"script": [{"delta": "Hel"}, {"delay_ms": 50}, {"delta": "lo"}, {"finish": "stop"}]}
+
+use crate::api::{DeltaStream, HttpStatusError, LLMApiProvider, ProviderStreamError, RawMethod, RawResponse, StreamEvent, TokenUsage, ToolCall};
+use crate::models::{Message, ModelConfig};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::stream;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub const MOCK_PROVIDER: &str = "mock";
+
+/// One step of a scripted stream.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MockStep {
+    Delta(String),
+    DelayMs(u64), // Pause before the next step
+    Usage(TokenUsage),
+    Annotations(Vec<serde_json::Value>), // Citations, as a provider would send alongside content
+    ToolCalls(Vec<ToolCall>), // Assembled calls, as sent before a "tool_calls" finish
+    Finish(String), // finish_reason, e.g. "stop" or "length"
+    Error(String), // Yields a stream error and ends the stream
+    ServerError { message: String, code: Option<String> }, // Like an `{"error": ...}` SSE payload
+    // As the first step, fails the request itself with this status, e.g.
+    // {"http_status": {"status": 429, "retry_after": "2"}}
+    HttpStatus { status: u16, retry_after: Option<String> },
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct MockOptions {
+    #[serde(default)]
+    script: Vec<MockStep>,
+}
+
+/// Plays back a fixed script. `new` scripts every request and `sequence` one script per
+/// request; with `scripted_by_config` each request reads the script from the config's
+/// provider_options instead.
+#[derive(Debug, Default)]
+pub struct MockProvider {
+    script: Option<Vec<MockStep>>,
+    queued: Mutex<VecDeque<Vec<MockStep>>>, // Played before `script`, one per request
+}
+
+impl MockProvider {
+    pub fn new(script: Vec<MockStep>) -> Self {
+        Self { script: Some(script), ..Self::default() }
+    }
+
+    /// Plays `scripts` in turn, one per request; the last one repeats.
+    pub fn sequence(mut scripts: Vec<Vec<MockStep>>) -> Self {
+        let script = scripts.pop();
+        Self { script, queued: Mutex::new(scripts.into()) }
+    }
+
+    pub fn scripted_by_config() -> Self {
+        Self::default()
+    }
+
+    fn script_for(&self, config: &ModelConfig) -> Result<Vec<MockStep>> {
+        if let Some(script) = self.queued.lock().unwrap().pop_front() {
+            return Ok(script);
+        }
+        if let Some(script) = &self.script {
+            return Ok(script.clone());
+        }
+        let options: MockOptions = serde_json::from_str(config.provider_options.as_deref().unwrap_or("{}"))
+            .context("Failed to parse mock provider_options")?;
+        Ok(options.script)
+    }
+}
+
+// The error a script starting with `HttpStatus` fails its request with
+fn scripted_status_error(script: &[MockStep], request: &'static str) -> Option<anyhow::Error> {
+    let Some(MockStep::HttpStatus { status, retry_after }) = script.first() else {
+        return None;
+    };
+    Some(anyhow::Error::new(HttpStatusError {
+        request,
+        status: *status,
+        body: "Mock provider error".to_string(),
+        retry_after: retry_after.as_deref().and_then(|value| crate::api::parse_retry_after(value, chrono::Utc::now())),
+    }))
+}
+
+#[async_trait]
+impl LLMApiProvider for MockProvider {
+    async fn send_chat_stream_request(
+        &self,
+        config: &ModelConfig,
+        _api_key: &str,
+        messages: &[Message],
+    ) -> Result<DeltaStream> {
+        let script = self.script_for(config)?;
+        if let Some(e) = scripted_status_error(&script, "stream") {
+            return Err(e);
+        }
+        log::info!("Mock provider streaming {} scripted steps for {} messages", script.len(), messages.len());
+
+        let events = stream::unfold(script.into_iter(), |mut steps| async move {
+            loop {
+                let event = match steps.next()? {
+                    MockStep::DelayMs(ms) => {
+                        tokio::time::sleep(Duration::from_millis(ms)).await;
+                        continue;
+                    }
+                    MockStep::Delta(content) => Ok(StreamEvent::Delta(content)),
+                    MockStep::Usage(usage) => Ok(StreamEvent::Usage(usage)),
+                    MockStep::Annotations(annotations) => Ok(StreamEvent::Annotations(annotations)),
+                    MockStep::ToolCalls(calls) => Ok(StreamEvent::ToolCalls(calls)),
+                    MockStep::Finish(reason) => Ok(StreamEvent::Finished(reason)),
+                    MockStep::HttpStatus { .. } => continue, // Only meaningful as the first step
+                    MockStep::Error(message) => {
+                        steps = Vec::new().into_iter();
+                        Err(anyhow::anyhow!(message))
+                    }
+                    MockStep::ServerError { message, code } => {
+                        steps = Vec::new().into_iter();
+                        Err(anyhow::Error::new(ProviderStreamError { message, code }))
+                    }
+                };
+                return Some((event, steps));
+            }
+        });
+        Ok(Box::pin(events))
+    }
+
+    async fn send_chat_request(&self, config: &ModelConfig, _api_key: &str, _messages: &[Message]) -> Result<String> {
+        let script = self.script_for(config)?;
+        if let Some(e) = scripted_status_error(&script, "non-stream") {
+            return Err(e);
+        }
+        let mut content = String::new();
+        for step in script {
+            match step {
+                MockStep::Delta(delta) => content.push_str(&delta),
+                MockStep::DelayMs(ms) => tokio::time::sleep(Duration::from_millis(ms)).await,
+                MockStep::Error(message) => return Err(anyhow::anyhow!(message)),
+                MockStep::ServerError { message, code } => {
+                    return Err(anyhow::Error::new(ProviderStreamError { message, code }));
+                }
+                MockStep::Usage(_) | MockStep::Annotations(_) | MockStep::ToolCalls(_) | MockStep::Finish(_) | MockStep::HttpStatus { .. } => {}
+            }
+        }
+        Ok(content)
+    }
+
+    async fn send_raw_request(
+        &self,
+        _config: &ModelConfig,
+        _api_key: &str,
+        method: RawMethod,
+        path: &str,
+        _body: Option<serde_json::Value>,
+    ) -> Result<RawResponse> {
+        log::info!("Mock provider answering raw {:?} {}", method, path);
+        Ok(RawResponse { status: 200, body: "{}".to_string() })
+    }
+}
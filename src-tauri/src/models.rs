@@ -15,6 +15,103 @@ pub struct Message {
     // Optional metadata (e.g., model used, tokens, cost) - stored as JSON string in DB
     #[serde(skip_serializing_if = "Option::is_none")]
     pub metadata: Option<String>,
+    // Optional participant name (e.g. persona or tool name), sent as the API `name` field
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    // Alternative answers to the same turn share a group (the first answer's ID); see `is_unselected_variant`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant_group: Option<Uuid>,
+}
+
+impl Message {
+    // Parses the metadata JSON blob into an object (empty when unset or malformed)
+    pub fn metadata_map(&self) -> serde_json::Map<String, serde_json::Value> {
+        self.metadata
+            .as_deref()
+            .and_then(|raw| serde_json::from_str::<serde_json::Value>(raw).ok())
+            .and_then(|value| match value {
+                serde_json::Value::Object(map) => Some(map),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    // Sets a single metadata key, preserving any other keys already stored
+    pub fn set_metadata_field(&mut self, key: &str, value: serde_json::Value) {
+        let mut map = self.metadata_map();
+        map.insert(key.to_string(), value);
+        self.metadata = Some(serde_json::Value::Object(map).to_string());
+    }
+
+    // The comparison fan-out this message belongs to, if it came from send_message_multi
+    pub fn comparison_id(&self) -> Option<String> {
+        self.metadata_map()
+            .get("comparison_id")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    }
+
+    // Whether the user kept this variant of a comparison
+    pub fn is_kept_comparison(&self) -> bool {
+        self.metadata_map()
+            .get("comparison_kept")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+
+    // Whether this answer belongs to a variant group but another answer is the selected one
+    pub fn is_unselected_variant(&self) -> bool {
+        self.variant_group.is_some()
+            && self.metadata_map().get("variant_selected").and_then(|v| v.as_bool()) == Some(false)
+    }
+
+    // Whether the user pinned this message so it is always sent, whatever gets trimmed
+    pub fn is_context_pinned(&self) -> bool {
+        self.metadata_map()
+            .get("context_pinned")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+
+    // Whether this assistant message records a failed generation rather than an answer
+    pub fn is_error(&self) -> bool {
+        self.metadata_map()
+            .get("error")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+
+    // Flags the message as a failed generation; any partial content is kept as-is
+    pub fn mark_failed(&mut self, category: &str, error: &str) {
+        self.set_metadata_field("error", serde_json::json!(true));
+        self.set_metadata_field("error_category", serde_json::json!(category));
+        self.set_metadata_field("error_message", serde_json::json!(error));
+    }
+
+    // Prompt plus completion tokens recorded for this message's request(s)
+    pub fn tokens_used(&self) -> u64 {
+        let metadata = self.metadata_map();
+        let field = |key: &str| metadata.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+        field("prompt_tokens") + field("completion_tokens")
+    }
+
+    // Token estimate cached by `prompt::cache_token_estimate`, if the content hasn't changed since
+    pub fn cached_token_estimate(&self) -> Option<usize> {
+        let metadata = self.metadata_map();
+        let content_len = metadata.get("token_estimate_len").and_then(|v| v.as_u64())?;
+        if content_len != self.content.len() as u64 {
+            return None;
+        }
+        metadata.get("token_estimate").and_then(|v| v.as_u64()).map(|tokens| tokens as usize)
+    }
+
+    // The finish reason recorded when this (assistant) message was generated
+    pub fn finish_reason(&self) -> Option<String> {
+        self.metadata_map()
+            .get("finish_reason")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+    }
 }
 
 // Represents the metadata for a conversation thread
@@ -28,6 +125,111 @@ pub struct Conversation {
     #[serde(default = "Utc::now")]
     pub last_updated_at: DateTime<Utc>,
     pub model_config_id: Uuid, // Link to the model config used
+    // Conversation-specific instructions, composed after the global and model prompts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    // Set when the conversation is in the recycle bin
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+    // Messages are kept in memory only and never written to the database
+    #[serde(default)]
+    pub ephemeral: bool,
+    // Model name sent instead of the config's `model`, so one config can serve several models
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_override: Option<String>,
+    // Cap on the tokens (prompt + completion) all of this conversation's requests may use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_budget: Option<i64>,
+    // Language code (e.g. "de"), detected from the first user message or set by the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    // Read-only: sending, regenerating and continuing are refused until unlocked
+    #[serde(default)]
+    pub locked: bool,
+    // Answer style: a preset key ("concise", "detailed", ...) or custom instruction text
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub style_preset: Option<String>,
+    // Sidebar color (#RRGGBB or #RGB) and icon, for telling conversations apart at a glance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    // Persona whose instructions and parameters the conversation's requests use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persona_id: Option<Uuid>,
+    // Model config that writes this conversation's titles, ahead of the app-wide title model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title_model_config_id: Option<Uuid>,
+    // Each message is answered on its own: requests carry the system prompt and the newest user
+    // message only, though every message is still saved
+    #[serde(default)]
+    pub single_turn: bool,
+}
+
+// A conversation as the sidebar shows it: the row plus its message count and latest message.
+// Sent with `conversation_updated` so the list can be patched in place.
+#[derive(Serialize, Clone, Debug)]
+pub struct ConversationSummary {
+    #[serde(flatten)]
+    pub conversation: Conversation,
+    pub message_count: i64,
+    pub last_message_role: Option<String>,
+    pub last_message_preview: Option<String>, // Start of the latest message, whitespace collapsed
+}
+
+// A conversation with its message count, for cleanup suggestions
+#[derive(Serialize, Clone, Debug)]
+pub struct ConversationActivity {
+    #[serde(flatten)]
+    pub conversation: Conversation, // `last_updated_at` is its last activity
+    pub message_count: i64,
+}
+
+// A message with the title of its conversation, for views spanning conversations
+#[derive(Serialize, Clone, Debug)]
+pub struct MessageWithTitle {
+    #[serde(flatten)]
+    pub message: Message,
+    pub conversation_title: String,
+}
+
+// Messages saved after a cursor, oldest first, for views that poll for new messages. Cursors
+// are message `seq` values; messages edited in place keep theirs and are not returned again.
+#[derive(Serialize, Clone, Debug)]
+pub struct MessagesSince<T> {
+    pub messages: Vec<T>,
+    pub cursor: i64, // Pass as `after` on the next poll
+    pub has_more: bool, // The limit cut the result short; poll again right away
+}
+
+// A saved copy of a conversation's messages that the conversation can be restored to.
+// The messages themselves stay in storage; listings only carry the count.
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationSnapshot {
+    pub id: Uuid,
+    pub conversation_id: Uuid,
+    pub label: String,
+    pub message_count: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+// A switchable assistant identity ("strict code reviewer", "friendly explainer"). Its
+// instructions are composed after the model's and before the conversation's own, and its
+// params override the model config's provider_options (see `set_conversation_persona`)
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Persona {
+    #[serde(default = "Uuid::new_v4")]
+    pub id: Uuid,
+    pub name: String,
+    #[serde(default)]
+    pub system_prompt: String,
+    // Config a conversation switches to when it picks this persona
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_config_id: Option<Uuid>,
+    // provider_options overrides as a JSON object string, e.g. {"temperature": 0.2}
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<String>,
 }
 
 // Represents a configured API endpoint/model
@@ -45,4 +247,23 @@ pub struct ModelConfig {
     // e.g., default model string ('gpt-4o-mini'), temperature, etc.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub provider_options: Option<String>,
+    // Position in the model list (ascending); managed by reorder_model_configs
+    #[serde(default)]
+    pub sort_order: i64,
+    // The config new conversations start with; at most one config has this set
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+impl ModelConfig {
+    // Returns a copy with `key` overridden in provider_options, for single-request tweaks
+    pub fn with_provider_option(&self, key: &str, value: serde_json::Value) -> Result<ModelConfig, serde_json::Error> {
+        let mut options: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(self.provider_options.as_deref().unwrap_or("{}"))?;
+        options.insert(key.to_string(), value);
+        Ok(ModelConfig {
+            provider_options: Some(serde_json::Value::Object(options).to_string()),
+            ..self.clone()
+        })
+    }
 } 
\ No newline at end of file
@@ -0,0 +1,268 @@
+// Prompt assembly shared by the background generation tasks
+
+use crate::api::ParsedProviderOptions;
+use crate::models::{Conversation, Message, ModelConfig, Persona};
+use chrono::Utc;
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Prompt parts kept outside the conversation row: the app-wide settings and the
+/// conversation's persona, read for each request.
+#[derive(Debug, Clone, Default)]
+pub struct PromptSettings {
+    pub default_prompt: Option<String>, // Leads every system prompt
+    pub suffix: Option<String>, // Standing instruction appended after everything else
+    pub persona: Option<Persona>, // The conversation's persona, if it has one
+    pub project_digest: Option<String>, // Files ingested into the conversation; see `ingest`
+}
+
+// Answer style presets a conversation can pick, with the instruction each adds
+pub const STYLE_PRESETS: &[(&str, &str)] = &[
+    ("concise", "Answer concisely, leaving out anything that isn't needed."),
+    ("detailed", "Answer in detail, including relevant background, reasoning and caveats."),
+    ("bullet_points", "Format answers as bullet points wherever possible."),
+    ("eli5", "Explain things simply, as you would to a five-year-old."),
+];
+
+// Longest custom style instruction a conversation may store
+pub const MAX_CUSTOM_STYLE_CHARS: usize = 500;
+
+/// The instruction for a conversation's `style_preset`: the preset's sentence, or the custom
+/// text itself.
+pub fn style_instruction(style_preset: &str) -> &str {
+    STYLE_PRESETS
+        .iter()
+        .find(|(key, _)| *key == style_preset)
+        .map_or(style_preset, |(_, instruction)| instruction)
+}
+
+/// Composes the system prompt sent ahead of the conversation history.
+/// Parts are layered from most general to most specific: the global default
+/// from settings, then the model config, the persona, then the conversation itself
+/// and its style preset, with the global suffix last. Empty parts contribute nothing.
+pub fn compose_system_prompt(
+    settings: &PromptSettings,
+    model_config: &ModelConfig,
+    conversation: &Conversation,
+) -> String {
+    let model_prompt = format!("You are {}.", model_config.name);
+    [
+        settings.default_prompt.as_deref(),
+        Some(model_prompt.as_str()),
+        settings.persona.as_ref().map(|persona| persona.system_prompt.as_str()),
+        conversation.system_prompt.as_deref(),
+        conversation.style_preset.as_deref().map(style_instruction),
+        settings.project_digest.as_deref(),
+        settings.suffix.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .map(str::trim)
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n\n")
+}
+
+/// Wraps composed system prompt content in a (never persisted) system message.
+pub fn system_message(conversation_id: Uuid, content: String) -> Message {
+    Message {
+        id: Uuid::nil(), // API ignores the system message ID
+        conversation_id,
+        role: "system".to_string(),
+        content,
+        timestamp: Utc::now(),
+        metadata: None,
+        name: None,
+        variant_group: None,
+    }
+}
+
+/// Drops comparison variants the user has not kept, so only the chosen answer
+/// of a multi-model comparison becomes part of the conversation history. Likewise only the
+/// selected answer of a regeneration variant group is kept.
+/// Failed generations are dropped too; they only exist to show the error in the transcript.
+pub fn filter_history(messages: Vec<Message>) -> Vec<Message> {
+    messages
+        .into_iter()
+        .filter(|m| m.comparison_id().is_none() || m.is_kept_comparison())
+        .filter(|m| !m.is_unselected_variant())
+        .filter(|m| !m.is_error())
+        .collect()
+}
+
+/// The history a single-turn conversation sends: its newest user message alone.
+pub fn single_turn_history(history: Vec<Message>) -> Vec<Message> {
+    history.into_iter().rev().find(|m| m.role == "user").into_iter().collect()
+}
+
+// Rough token count (about four characters per token, plus per-message overhead).
+// Only used to decide what fits, so it errs on the generous side. Uses the estimate
+// cached in the message's metadata when it matches the current content.
+pub fn estimate_tokens(message: &Message) -> usize {
+    message.cached_token_estimate().unwrap_or_else(|| count_tokens(&message.content))
+}
+
+fn count_tokens(content: &str) -> usize {
+    content.chars().count() / 4 + 4
+}
+
+/// Stores the message's token estimate in its metadata, keyed to the content length so an
+/// edit invalidates it. Returns false when a valid estimate was already cached.
+pub fn cache_token_estimate(message: &mut Message) -> bool {
+    if message.cached_token_estimate().is_some() {
+        return false;
+    }
+    let tokens = count_tokens(&message.content);
+    let content_len = message.content.len();
+    message.set_metadata_field("token_estimate", serde_json::json!(tokens));
+    message.set_metadata_field("token_estimate_len", serde_json::json!(content_len));
+    true
+}
+
+/// Index of the oldest unpinned message that still fits in `budget` alongside the
+/// pinned ones; everything unpinned before it gets dropped. The newest message
+/// always fits, even if it alone is too big.
+pub fn recent_window_start(history: &[Message], budget: usize) -> usize {
+    let pinned_tokens: usize = history.iter().filter(|m| m.is_context_pinned()).map(estimate_tokens).sum();
+    let mut remaining = budget.saturating_sub(pinned_tokens);
+
+    // Walk back from the newest message until an unpinned one no longer fits
+    let mut cut = history.len();
+    for (index, message) in history.iter().enumerate().rev() {
+        if message.is_context_pinned() {
+            continue;
+        }
+        let tokens = estimate_tokens(message);
+        if tokens > remaining && cut != history.len() {
+            break;
+        }
+        remaining = remaining.saturating_sub(tokens);
+        cut = index;
+    }
+    cut
+}
+
+/// Trims `history` to `budget` estimated tokens, keeping the most recent messages.
+/// Pinned messages are counted first and always kept; pinned ones older than the kept
+/// window are moved to the front.
+pub fn fit_history(history: Vec<Message>, budget: Option<usize>) -> Vec<Message> {
+    let Some(budget) = budget else {
+        return history;
+    };
+    let cut = recent_window_start(&history, budget);
+    if cut == 0 {
+        return history;
+    }
+
+    let dropped = history[..cut].iter().filter(|m| !m.is_context_pinned()).count();
+    if dropped > 0 {
+        log::info!("Trimmed {} old messages to fit the context window", dropped);
+    }
+    let mut history = history;
+    let recent = history.split_off(cut);
+    history.retain(|m| m.is_context_pinned());
+    history.extend(recent);
+    history
+}
+
+/// Tokens left for history once the system message and the completion are accounted
+/// for, or None when the config sets no `context_window`.
+pub fn history_budget(system_message: &Message, model_config: &ModelConfig) -> Option<usize> {
+    let options = ParsedProviderOptions::from_config(model_config).unwrap_or_default();
+    options.context_window.map(|window| {
+        (window as usize)
+            .saturating_sub(options.max_tokens.unwrap_or(0) as usize)
+            .saturating_sub(estimate_tokens(system_message))
+    })
+}
+
+/// Builds the message list for a request: the system message, then as much of
+/// `history` as fits the config's `context_window` (minus room for the completion).
+pub fn build_api_messages(system_message: Message, history: Vec<Message>, model_config: &ModelConfig) -> Vec<Message> {
+    let budget = history_budget(&system_message, model_config);
+    let mut api_messages = vec![system_message];
+    api_messages.extend(fit_history(history, budget));
+    api_messages
+}
+
+/// Estimated size of the next request for a conversation, for the composer's usage meter.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ContextUsage {
+    pub prompt_tokens: usize, // Everything sent: system prompt (and summary), pinned and recent messages
+    pub system_tokens: usize, // System prompt, including any summary of older history
+    pub pinned_tokens: usize,
+    pub history_tokens: usize, // Unpinned messages that fit
+    pub included_messages: usize,
+    pub omitted_messages: usize, // Trimmed, or folded into the summary
+    pub context_window: Option<u32>, // None when the config sets no limit
+    pub reserved_completion_tokens: Option<u32>, // The config's max_tokens
+}
+
+impl ContextUsage {
+    /// Usage of a request made of `system_message` and `sent`, the part of a `total`-message
+    /// history that was kept.
+    pub fn measure(system_message: &Message, sent: &[Message], total: usize, model_config: &ModelConfig) -> Self {
+        let options = ParsedProviderOptions::from_config(model_config).unwrap_or_default();
+        let system_tokens = estimate_tokens(system_message);
+        let pinned_tokens: usize = sent.iter().filter(|m| m.is_context_pinned()).map(estimate_tokens).sum();
+        let history_tokens: usize = sent.iter().filter(|m| !m.is_context_pinned()).map(estimate_tokens).sum();
+        ContextUsage {
+            prompt_tokens: system_tokens + pinned_tokens + history_tokens,
+            system_tokens,
+            pinned_tokens,
+            history_tokens,
+            included_messages: sent.len(),
+            omitted_messages: total.saturating_sub(sent.len()),
+            context_window: options.context_window,
+            reserved_completion_tokens: options.max_tokens,
+        }
+    }
+}
+
+/// Which messages the next request for a conversation would send, as returned by `preview_truncation`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TruncationPreview {
+    pub included_message_ids: Vec<Uuid>, // In the order they would be sent
+    // Trimmed or summarized, plus messages never sent (failed generations, unkept comparison
+    // variants, unselected regeneration variants)
+    pub excluded_message_ids: Vec<Uuid>,
+    pub summarized: bool, // Excluded history would be replaced by a summary rather than dropped
+    pub estimated_tokens: usize,
+    pub usage: ContextUsage,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::model_config;
+
+    fn conversation(system_prompt: Option<&str>) -> Conversation {
+        let conversation: Conversation =
+            serde_json::from_value(serde_json::json!({ "title": "Chat", "model_config_id": Uuid::new_v4() })).unwrap();
+        Conversation { system_prompt: system_prompt.map(str::to_string), ..conversation }
+    }
+
+    fn with_suffix(suffix: &str) -> PromptSettings {
+        PromptSettings { suffix: Some(suffix.to_string()), ..PromptSettings::default() }
+    }
+
+    #[test]
+    fn suffix_follows_the_conversation_prompt() {
+        let settings = with_suffix("Always format code in fenced blocks.");
+        let prompt = compose_system_prompt(&settings, &model_config("Helper", "{}"), &conversation(Some("Answer in German.")));
+        assert_eq!(prompt, "You are Helper.\n\nAnswer in German.\n\nAlways format code in fenced blocks.");
+    }
+
+    #[test]
+    fn suffix_is_added_without_a_conversation_prompt() {
+        let settings = with_suffix("  Always format code in fenced blocks.\n");
+        let prompt = compose_system_prompt(&settings, &model_config("Helper", "{}"), &conversation(None));
+        assert_eq!(prompt, "You are Helper.\n\nAlways format code in fenced blocks.");
+        // A blank suffix adds nothing
+        let prompt = compose_system_prompt(&with_suffix("  "), &model_config("Helper", "{}"), &conversation(None));
+        assert_eq!(prompt, "You are Helper.");
+    }
+}
+
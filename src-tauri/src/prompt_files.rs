@@ -0,0 +1,300 @@
+// Reusable prompts kept as `.md` files in a folder (the `prompt_dir` setting), offered as
+// slash commands. While a folder is set it is polled every POLL_INTERVAL; files are only re-read
+// when their size or modification time changes, and `prompts_changed` is emitted whenever the
+// list does. The setting itself is read by `rescan`, when it is saved or the library changes.
+// When the folder goes missing (an unmounted drive) the last known prompts keep being served.
+
+use crate::config;
+use crate::events;
+use crate::state::AppState;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tauri::Emitter;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+// Files larger than this are skipped; prompts are meant to be short
+const MAX_PROMPT_BYTES: u64 = 256 * 1024;
+const MAX_DESCRIPTION_CHARS: usize = 120;
+
+/// A prompt file, as listed for the slash-command palette.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePrompt {
+    pub name: String, // File name without `.md`; what follows the slash
+    pub description: String, // First non-empty line, without Markdown heading marks
+    pub hash: String, // SHA-256 of the contents, lowercase hex
+}
+
+/// Payload of `prompts_changed`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptsChanged {
+    pub count: usize,
+    pub available: bool, // False while the folder is missing; the last known prompts are served
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+}
+
+struct CachedPrompt {
+    prompt: FilePrompt,
+    body: String,
+    size: u64,
+    modified: Option<SystemTime>,
+}
+
+/// The prompts of the configured folder, held in `AppState`.
+#[derive(Default)]
+pub struct PromptCache {
+    dir: Option<PathBuf>,
+    prompts: BTreeMap<String, CachedPrompt>, // By name, so listings are sorted
+    available: bool,
+}
+
+fn with_cache<T>(cache: &Mutex<PromptCache>, f: impl FnOnce(&mut PromptCache) -> T) -> T {
+    f(&mut cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+}
+
+/// The prompts from the last scan, sorted by name.
+pub fn list(state: &AppState) -> Vec<FilePrompt> {
+    with_cache(&state.prompt_cache, |cache| cache.prompts.values().map(|cached| cached.prompt.clone()).collect())
+}
+
+/// The body of the prompt called `name`, if it is known.
+pub fn body(state: &AppState, name: &str) -> Option<String> {
+    with_cache(&state.prompt_cache, |cache| cache.prompts.get(name).map(|cached| cached.body.clone()))
+}
+
+/// Polls the prompt folder for the life of the app. Ticks with no folder set do nothing.
+pub async fn run_prompt_watch_loop(state: AppState) {
+    rescan(&state).await;
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let dir = with_cache(&state.prompt_cache, |cache| cache.dir.clone());
+        if dir.is_some() {
+            refresh(&state, dir).await;
+        }
+    }
+}
+
+/// Reads the prompt folder setting, re-reads the folder now and emits `prompts_changed` if
+/// anything changed.
+pub async fn rescan(state: &AppState) {
+    let dir = {
+        let storage = state.storage.lock().await;
+        storage.get_setting(config::PROMPT_DIR_KEY).await
+            .map_err(|e| log::warn!("Failed to read setting '{}': {:?}", config::PROMPT_DIR_KEY, e))
+            .ok()
+            .flatten()
+            .map(|dir| dir.trim().to_string())
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+    };
+    refresh(state, dir).await;
+}
+
+// Scans `dir` off the async runtime and emits the resulting change, if any
+async fn refresh(state: &AppState, dir: Option<PathBuf>) {
+    let cache = state.prompt_cache.clone();
+    let changed = tokio::task::spawn_blocking(move || scan(&cache, dir)).await;
+    let changed = match changed {
+        Ok(changed) => changed,
+        Err(e) => {
+            log::error!("Prompt folder scan panicked: {:?}", e);
+            return;
+        }
+    };
+    let Some(payload) = changed else {
+        return;
+    };
+    if let Some(warning) = &payload.warning {
+        log::warn!("{}", warning);
+    }
+    if let Err(e) = state.app_handle.emit(events::PROMPTS_CHANGED, payload) {
+        log::error!("Failed to emit prompts changed event: {:?}", e);
+    }
+}
+
+// Updates the cache from `dir`; returns the event to send when something changed
+fn scan(cache: &Mutex<PromptCache>, dir: Option<PathBuf>) -> Option<PromptsChanged> {
+    let Some(dir) = dir else {
+        return with_cache(cache, |cache| {
+            let had_prompts = cache.dir.is_some() || !cache.prompts.is_empty();
+            *cache = PromptCache::default();
+            had_prompts.then_some(PromptsChanged { count: 0, available: true, warning: None })
+        });
+    };
+
+    let dir_changed = with_cache(cache, |cache| cache.dir.as_deref() != Some(dir.as_path()));
+    if dir_changed {
+        with_cache(cache, |cache| *cache = PromptCache { dir: Some(dir.clone()), available: true, ..Default::default() });
+    }
+
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            // Warn once when the folder goes away, then keep serving what we had
+            return with_cache(cache, |cache| {
+                let was_available = std::mem::replace(&mut cache.available, false);
+                (was_available || dir_changed).then(|| PromptsChanged {
+                    count: cache.prompts.len(),
+                    available: false,
+                    warning: Some(format!(
+                        "Prompt folder {} is unavailable ({}); using the last known prompts",
+                        dir.display(),
+                        e
+                    )),
+                })
+            });
+        }
+    };
+
+    let mut seen: Vec<String> = Vec::new();
+    let mut updates: Vec<CachedPrompt> = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(name) = prompt_name(&path) else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() || metadata.len() > MAX_PROMPT_BYTES {
+            continue;
+        }
+        let modified = metadata.modified().ok();
+        seen.push(name.clone());
+        let unchanged = with_cache(cache, |cache| {
+            cache.prompts.get(&name).is_some_and(|cached| cached.size == metadata.len() && cached.modified == modified)
+        });
+        if unchanged {
+            continue;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(body) => updates.push(CachedPrompt {
+                prompt: FilePrompt { description: description(&body), hash: format!("{:x}", Sha256::digest(body.as_bytes())), name },
+                body,
+                size: metadata.len(),
+                modified,
+            }),
+            Err(e) => log::warn!("Failed to read prompt file {}: {}", path.display(), e),
+        }
+    }
+
+    with_cache(cache, |cache| {
+        let came_back = !std::mem::replace(&mut cache.available, true);
+        let before: Vec<FilePrompt> = cache.prompts.values().map(|cached| cached.prompt.clone()).collect();
+        cache.prompts.retain(|name, _| seen.contains(name));
+        for update in updates {
+            cache.prompts.insert(update.prompt.name.clone(), update);
+        }
+        let after: Vec<FilePrompt> = cache.prompts.values().map(|cached| cached.prompt.clone()).collect();
+        (dir_changed || came_back || before != after).then_some(PromptsChanged {
+            count: after.len(),
+            available: true,
+            warning: None,
+        })
+    })
+}
+
+// `name` for `<dir>/name.md`; None for anything else, including hidden files
+fn prompt_name(path: &Path) -> Option<String> {
+    let extension = path.extension()?.to_str()?;
+    if !extension.eq_ignore_ascii_case("md") {
+        return None;
+    }
+    let name = path.file_stem()?.to_str()?.trim();
+    (!name.is_empty() && !name.starts_with('.')).then(|| name.to_string())
+}
+
+fn description(body: &str) -> String {
+    body.lines()
+        .map(|line| line.trim().trim_start_matches('#').trim())
+        .find(|line| !line.is_empty())
+        .unwrap_or_default()
+        .chars()
+        .take(MAX_DESCRIPTION_CHARS)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockProvider;
+    use crate::test_support::{self, TestApp};
+
+    fn names(cache: &Mutex<PromptCache>) -> Vec<(String, String)> {
+        with_cache(cache, |cache| cache.prompts.values().map(|c| (c.prompt.name.clone(), c.prompt.description.clone())).collect())
+    }
+
+    #[test]
+    fn scans_list_markdown_prompts_and_report_changes() {
+        let dir = test_support::temp_dir();
+        std::fs::write(dir.join("review.md"), "# Review this code\n\nLook for bugs.").unwrap();
+        std::fs::write(dir.join("Explain.MD"), "\n\nExplain simply").unwrap();
+        std::fs::write(dir.join(".hidden.md"), "secret").unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a prompt").unwrap();
+        std::fs::write(dir.join("huge.md"), "x".repeat(MAX_PROMPT_BYTES as usize + 1)).unwrap();
+        let cache = Mutex::new(PromptCache::default());
+
+        let changed = scan(&cache, Some(dir.clone())).unwrap();
+        assert_eq!((changed.count, changed.available), (2, true));
+        assert_eq!(
+            names(&cache),
+            [("Explain".to_string(), "Explain simply".to_string()), ("review".to_string(), "Review this code".to_string())]
+        );
+        assert!(scan(&cache, Some(dir.clone())).is_none());
+
+        std::fs::remove_file(dir.join("review.md")).unwrap();
+        assert_eq!(scan(&cache, Some(dir.clone())).unwrap().count, 1);
+        assert_eq!(scan(&cache, None).unwrap().count, 0);
+        assert!(names(&cache).is_empty());
+        assert!(scan(&cache, None).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_missing_folder_keeps_the_last_prompts() {
+        let dir = test_support::temp_dir();
+        std::fs::write(dir.join("review.md"), "Review").unwrap();
+        let cache = Mutex::new(PromptCache::default());
+        scan(&cache, Some(dir.clone())).unwrap();
+
+        let moved = dir.with_extension("away");
+        std::fs::rename(&dir, &moved).unwrap();
+        let gone = scan(&cache, Some(dir.clone())).unwrap();
+        assert_eq!((gone.count, gone.available), (1, false));
+        assert!(gone.warning.unwrap().contains("unavailable"));
+        assert!(scan(&cache, Some(dir.clone())).is_none()); // Warned once
+        assert_eq!(names(&cache).len(), 1);
+
+        std::fs::rename(&moved, &dir).unwrap();
+        let back = scan(&cache, Some(dir.clone())).unwrap();
+        assert_eq!((back.count, back.available), (1, true));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn each_app_state_reads_its_own_prompt_folder() {
+        let dir = test_support::temp_dir();
+        std::fs::write(dir.join("review.md"), "Review this").unwrap();
+        let app = TestApp::new(MockProvider::new(Vec::new())).await;
+        let other = TestApp::new(MockProvider::new(Vec::new())).await;
+
+        rescan(&app.state).await;
+        assert!(app.events.payloads(events::PROMPTS_CHANGED).is_empty());
+        app.state.storage.lock().await.set_setting(config::PROMPT_DIR_KEY, dir.to_str().unwrap()).await.unwrap();
+        rescan(&app.state).await;
+        assert_eq!(app.events.payloads(events::PROMPTS_CHANGED)[0]["count"], 1);
+        assert_eq!(list(&app.state).len(), 1);
+        assert_eq!(body(&app.state, "review").as_deref(), Some("Review this"));
+
+        rescan(&other.state).await;
+        assert!(list(&other.state).is_empty());
+        assert!(body(&other.state, "review").is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+// Safe mode: when the database can't be opened at startup (corrupt file, permissions, full
+// disk) the window still comes up. `AppState` then holds an empty in-memory placeholder so
+// background loops keep running harmlessly, and every command outside SAFE_MODE_COMMANDS is
+// refused with a `storageUnavailable` error. The user can retry opening the file or move it
+// aside and start over with `reset_database`; the old file is always kept.
+
+use crate::error::CommandError;
+use crate::state::AppState;
+use crate::storage::StorageManager;
+use chrono::Utc;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tauri::ipc::Invoke;
+use tauri::{Manager, Runtime};
+use uuid::Uuid;
+
+// Commands that keep working while the database is unavailable
+const SAFE_MODE_COMMANDS: &[&str] = &[
+    "get_storage_status",
+    "retry_storage_init",
+    "reset_database",
+    "get_recent_errors",
+    "get_system_theme",
+    "open_url",
+];
+
+/// Whether the database opened, as reported by `get_storage_status`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "camelCase")]
+pub enum StorageStatus {
+    Ready,
+    #[serde(rename_all = "camelCase")]
+    Failed {
+        reason: String,
+        db_path: String,
+        reset_token: String, // Must be passed back to `reset_database`, so a reset is deliberate
+    },
+}
+
+impl StorageStatus {
+    pub fn failed(error: &anyhow::Error, db_path: &Path) -> Self {
+        Self::Failed {
+            reason: format!("{:#}", error),
+            db_path: db_path.display().to_string(),
+            reset_token: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self, Self::Ready)
+    }
+}
+
+/// Opens the database at `db_path` the way startup does, adding the default model config to
+/// a new library.
+pub async fn open(db_path: &Path) -> Result<StorageManager, anyhow::Error> {
+    let storage_manager = StorageManager::open(db_path).await?;
+    storage_manager.add_default_model_config_if_none().await?;
+    Ok(storage_manager)
+}
+
+/// Stand-in manager while the real database is unavailable. Nothing written to it is kept.
+pub async fn placeholder() -> Result<StorageManager, anyhow::Error> {
+    StorageManager::new_with_url("sqlite::memory:").await
+}
+
+/// Renames the database file (and its journal files) to `<name>.corrupt-<timestamp>`.
+/// Returns the new path of the database file.
+pub fn quarantine(db_path: &Path) -> std::io::Result<PathBuf> {
+    let stamp = Utc::now().format("%Y%m%d-%H%M%S");
+    let quarantined = with_suffix(db_path, &format!(".corrupt-{}", stamp));
+    if db_path.exists() {
+        std::fs::rename(db_path, &quarantined)?;
+    }
+    for journal in ["-wal", "-shm", "-journal"] {
+        let path = with_suffix(db_path, journal);
+        if path.exists() {
+            std::fs::rename(&path, with_suffix(&quarantined, journal))?;
+        }
+    }
+    log::warn!("Moved database {} aside to {}", db_path.display(), quarantined.display());
+    Ok(quarantined)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Hands `invoke` back unless the app is in safe mode and the command needs the database,
+/// in which case it is rejected with `storageUnavailable` and None is returned.
+pub fn screen<R: Runtime>(invoke: Invoke<R>) -> Option<Invoke<R>> {
+    let command = invoke.message.command();
+    if SAFE_MODE_COMMANDS.contains(&command) {
+        return Some(invoke);
+    }
+    let reason = match invoke.message.webview().try_state::<AppState>() {
+        Some(state) => match &*state.storage_status.read().unwrap_or_else(|poisoned| poisoned.into_inner()) {
+            StorageStatus::Ready => None,
+            StorageStatus::Failed { reason, .. } => Some(reason.clone()),
+        },
+        None => None,
+    };
+    let Some(reason) = reason else {
+        return Some(invoke);
+    };
+    log::warn!("Refused '{}' in safe mode", command);
+    invoke.resolver.reject(CommandError::storage_unavailable(format!(
+        "The database could not be opened ({}). Retry or reset it to continue.",
+        reason
+    )));
+    None
+}
+
+/// Wraps the app's command handler so `screen` runs before every command.
+pub fn guard<R: Runtime>(
+    handler: impl Fn(Invoke<R>) -> bool + Send + Sync + 'static,
+) -> impl Fn(Invoke<R>) -> bool + Send + Sync + 'static {
+    move |invoke| match screen(invoke) {
+        Some(invoke) => handler(invoke),
+        None => true,
+    }
+}
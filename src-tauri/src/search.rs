@@ -0,0 +1,148 @@
+// Text matching helpers for searching within conversations
+
+use serde::Serialize;
+use uuid::Uuid;
+
+// Upper bound on match offsets returned by a single find; `total_matches` still counts all
+pub const MAX_FIND_RESULTS: usize = 500;
+
+// A single occurrence inside a message, in UTF-16 code units so the
+// frontend can use the offsets directly on JavaScript strings
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct MatchOffset {
+    pub start: usize,
+    pub length: usize,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageMatches {
+    pub message_id: Uuid,
+    pub offsets: Vec<MatchOffset>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FindResult {
+    pub matches: Vec<MessageMatches>, // In message order, capped at MAX_FIND_RESULTS offsets
+    pub total_matches: usize,
+    pub truncated: bool,
+}
+
+// Length in bytes of a case-insensitive match of `needle` (already lowercased)
+// at the start of `text`, if there is one
+fn folded_match_len(text: &str, needle: &[char]) -> Option<usize> {
+    let mut matched = 0;
+    for (offset, c) in text.char_indices() {
+        for lower in c.to_lowercase() {
+            if matched >= needle.len() || needle[matched] != lower {
+                return None;
+            }
+            matched += 1;
+        }
+        if matched == needle.len() {
+            return Some(offset + c.len_utf8());
+        }
+    }
+    None
+}
+
+/// Finds every non-overlapping, case-insensitive occurrence of `query` in `content`.
+pub fn find_match_offsets(content: &str, query: &str) -> Vec<MatchOffset> {
+    let needle: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    let mut offsets = Vec::new();
+    let mut utf16_pos = 0;
+    let mut skip_until = 0; // Byte index where the previous match ended
+    for (byte_idx, c) in content.char_indices() {
+        if byte_idx >= skip_until {
+            if let Some(len) = folded_match_len(&content[byte_idx..], &needle) {
+                let matched = &content[byte_idx..byte_idx + len];
+                offsets.push(MatchOffset {
+                    start: utf16_pos,
+                    length: matched.encode_utf16().count(),
+                });
+                skip_until = byte_idx + len;
+            }
+        }
+        utf16_pos += c.len_utf16();
+    }
+    offsets
+}
+
+// Characters of context kept on each side of the first match in a search snippet
+const SNIPPET_CONTEXT_CHARS: usize = 60;
+
+// One message matched by `search_in_conversation`
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchHit {
+    pub message_id: Uuid,
+    pub snippet: String, // Text around the first match on one line, with "…" where it was cut
+    pub highlights: Vec<MatchOffset>, // Matches within `snippet`
+    pub match_count: usize, // Matches in the whole message
+}
+
+/// Builds the search result for `content`, or None when `query` doesn't occur in it.
+pub fn search_hit(message_id: Uuid, content: &str, query: &str) -> Option<SearchHit> {
+    let needle: Vec<char> = query.chars().flat_map(char::to_lowercase).collect();
+    if needle.is_empty() {
+        return None;
+    }
+    let (first_char, first_len) = content
+        .char_indices()
+        .enumerate()
+        .find_map(|(char_idx, (byte_idx, _))| {
+            folded_match_len(&content[byte_idx..], &needle)
+                .map(|len| (char_idx, content[byte_idx..byte_idx + len].chars().count()))
+        })?;
+
+    let start = first_char.saturating_sub(SNIPPET_CONTEXT_CHARS);
+    let end = first_char + first_len + SNIPPET_CONTEXT_CHARS;
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.extend(content.chars().skip(start).take(end - start).map(|c| if c.is_whitespace() { ' ' } else { c }));
+    if content.chars().count() > end {
+        snippet.push('…');
+    }
+
+    Some(SearchHit {
+        message_id,
+        highlights: find_match_offsets(&snippet, query),
+        snippet,
+        match_count: find_match_offsets(content, query).len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_fold_non_ascii_case() {
+        assert_eq!(find_match_offsets("Ich mag ÄPFEL und Äpfel", "äpfel"), vec![
+            MatchOffset { start: 8, length: 5 },
+            MatchOffset { start: 18, length: 5 },
+        ]);
+        assert_eq!(find_match_offsets("ΣΟΦΙΑ", "σοφια").len(), 1);
+    }
+
+    #[test]
+    fn offsets_are_in_utf16_code_units() {
+        // The emoji takes two UTF-16 code units
+        assert_eq!(find_match_offsets("🙂 hello", "HELLO"), vec![MatchOffset { start: 3, length: 5 }]);
+    }
+
+    #[test]
+    fn like_metacharacters_match_literally() {
+        assert_eq!(find_match_offsets("50% off_now", "0% o").len(), 1);
+        assert!(find_match_offsets("50 percent", "%").is_empty());
+        assert_eq!(find_match_offsets("a_b", "_").len(), 1);
+    }
+}
+
@@ -0,0 +1,97 @@
+// Optional pacing of oversized stream deltas. Some providers send several paragraphs in one
+// delta, which makes the UI jump; with smoothing on, such deltas are split into word-sized
+// pieces that are emitted one per tick. Content and ordering are unchanged, and whatever is
+// still queued is flushed at once when the stream ends or is cancelled.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+pub const DEFAULT_CHARS_PER_EVENT: usize = 24;
+pub const MAX_CHARS_PER_EVENT: usize = 1000;
+
+// Deltas at or below this many characters are emitted as they arrive, even with smoothing on
+pub const MIN_SMOOTHED_DELTA_CHARS: usize = 120;
+
+// Pause between paced pieces
+pub const EVENT_INTERVAL: Duration = Duration::from_millis(15);
+
+/// Smoothing settings as stored in the settings table. Off by default.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamSmoothing {
+    pub enabled: bool,
+    pub chars_per_event: usize, // Target size of each paced piece
+}
+
+impl Default for StreamSmoothing {
+    fn default() -> Self {
+        Self { enabled: false, chars_per_event: DEFAULT_CHARS_PER_EVENT }
+    }
+}
+
+/// Queue of delta pieces waiting to be emitted.
+pub struct DeltaPacer {
+    smoothing: StreamSmoothing,
+    pending: VecDeque<String>,
+}
+
+impl DeltaPacer {
+    pub fn new(smoothing: StreamSmoothing) -> Self {
+        Self { smoothing, pending: VecDeque::new() }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Takes a received delta. Returns it back when it can be emitted right away; otherwise
+    /// it is queued (split, if oversized) behind anything already waiting.
+    pub fn push(&mut self, delta: String) -> Option<String> {
+        let oversized = self.smoothing.enabled && delta.chars().count() > MIN_SMOOTHED_DELTA_CHARS;
+        if !oversized {
+            if self.pending.is_empty() {
+                return Some(delta);
+            }
+            self.pending.push_back(delta);
+            return None;
+        }
+        self.pending.extend(split_delta(&delta, self.smoothing.chars_per_event));
+        None
+    }
+
+    /// The next queued piece, for the next tick.
+    pub fn next_piece(&mut self) -> Option<String> {
+        self.pending.pop_front()
+    }
+
+    /// Everything still queued, joined, for the final flush.
+    pub fn flush(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(self.pending.drain(..).collect())
+    }
+}
+
+// Splits `delta` into pieces of about `chars_per_event` characters, breaking after whitespace
+// where possible. Long words are cut between characters.
+fn split_delta(delta: &str, chars_per_event: usize) -> Vec<String> {
+    let chars_per_event = chars_per_event.max(1);
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut current_chars = 0;
+    for c in delta.chars() {
+        current.push(c);
+        current_chars += 1;
+        // Break after whitespace, or mid-word once a piece is twice its target
+        if (c.is_whitespace() && current_chars >= chars_per_event) || current_chars >= chars_per_event * 2 {
+            pieces.push(std::mem::take(&mut current));
+            current_chars = 0;
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
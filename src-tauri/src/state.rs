@@ -1,10 +1,48 @@
+use crate::models::{Message, ModelConfig};
 use crate::storage::StorageManager;
+use crate::safe_mode::StorageStatus;
 use crate::api::LLMApiProvider; // Import trait
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tauri::AppHandle; // For event emission
-use dashmap::DashMap; // Add import
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
+use tauri::{AppHandle, Emitter}; // For event emission
+use std::collections::HashSet;
+use dashmap::{DashMap, DashSet};
 use uuid::Uuid;      // Add import
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use crate::tools::PendingToolRequest;
+use crate::prompt_files::PromptCache;
+use crate::logs::{self, ErrorBuffer};
+use crate::utility::UtilityQueue;
+use crate::error::CommandError;
+use crate::automation::AutomationListener;
+use crate::events::{self, ConversationSizeWarning, ConversationUpdated};
+use crate::storage::message_preview;
+use std::time::Duration;
+
+// How long `notify_conversation_updated` gathers changes before emitting
+pub const CONVERSATION_UPDATE_DEBOUNCE: Duration = Duration::from_millis(100);
+// Length of the latest-message preview sent with `conversation_updated`
+pub const CONVERSATION_PREVIEW_CHARS: usize = 120;
+
+// Runtime of the handle `AppState` holds. Unit tests use Tauri's mock runtime, which needs no
+// window system, so they can build an `AppState` without starting the app.
+#[cfg(not(test))]
+pub type AppRuntime = tauri::Wry;
+#[cfg(test)]
+pub type AppRuntime = tauri::test::MockRuntime;
+
+// Snapshot of an in-flight generation, kept readable so a reloaded frontend can resume it
+#[derive(Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveStream {
+    pub conversation_id: Uuid,
+    pub message_id: Uuid,
+    pub content: String, // Content accumulated so far
+    pub seq: u64, // Sequence number of the last chunk folded into `content`
+    pub started_at: DateTime<Utc>,
+}
 
 // Core application state accessible by Tauri commands
 #[derive(Clone)] // Allow cloning for background tasks
@@ -12,22 +50,300 @@ pub struct AppState {
     // We wrap the StorageManager in a Mutex to allow safe concurrent access
     // from async Tauri commands.
     pub storage: Arc<Mutex<StorageManager>>, // Use Arc<Mutex<>> for shared ownership and mutation
+    pub storage_status: Arc<RwLock<StorageStatus>>, // Failed while `storage` is safe mode's placeholder
     // We can add more fields here later, like loaded conversations metadata
     // pub conversations: Mutex<Vec<crate::models::Conversation>>,
     // pub active_models: Mutex<Vec<crate::models::ModelConfig>>,
     pub api_provider: Arc<dyn LLMApiProvider>, // Hold the trait object
-    pub app_handle: AppHandle, // Store AppHandle for event emitting
+    pub app_handle: AppHandle<AppRuntime>, // Store AppHandle for event emitting
     pub cancelled_streams: Arc<DashMap<Uuid, bool>>, // Add map for cancellation
+    pub active_streams: Arc<DashMap<Uuid, ActiveStream>>, // Running buffers keyed by assistant message ID
+    pub comparison_streams: Arc<DashMap<Uuid, Vec<Uuid>>>, // Comparison ID -> assistant message IDs of its fan-out
+    pub ephemeral_messages: Arc<DashMap<Uuid, Vec<Message>>>, // Conversation ID -> unsaved messages of ephemeral chats
+    pub stream_permits: Arc<Semaphore>, // Caps how many generations stream at once; extra ones queue
+    pub pending_tool_requests: Arc<DashMap<Uuid, PendingToolRequest>>, // Tool calls waiting on the user, by request ID
+    pub recent_errors: Arc<ErrorBuffer>, // Newest error log records, filled by the logger
+    pub window_subscriptions: Arc<DashMap<Uuid, HashSet<String>>>, // Conversation ID -> labels of windows showing it
+    pub utility_queue: Arc<UtilityQueue>, // Throttles title and summary requests
+    pub pending_conversation_updates: Arc<DashSet<Uuid>>, // Conversations with a `conversation_updated` scheduled
+    pub size_warned_conversations: Arc<DashSet<Uuid>>, // Sent `conversation_size_warning` this session
+    pub offline_mode: Arc<AtomicBool>, // Mirrors the `offline_mode` setting; see `ensure_online`
+    pub automation: Arc<std::sync::Mutex<Option<AutomationListener>>>, // Local HTTP API, when running
+    pub background_work: Arc<AtomicUsize>, // Live `BackgroundWork` guards; see `begin_background_work`
+    pub prompt_cache: Arc<std::sync::Mutex<PromptCache>>, // Prompt files from the last scan of the prompt folder
+}
+
+// Marks work that will read or write the open library after the command that started it
+// returns: generations (queued or streaming), summaries, title tasks and job runs.
+// `open_library` refuses to switch while any is held. Cloning counts another piece of work.
+pub struct BackgroundWork(Arc<AtomicUsize>);
+
+impl Clone for BackgroundWork {
+    fn clone(&self) -> Self {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Self(self.0.clone())
+    }
+}
+
+impl Drop for BackgroundWork {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl AppState {
     // Constructor for AppState
-    pub fn new(storage_manager: StorageManager, api_provider: Arc<dyn LLMApiProvider>, app_handle: AppHandle) -> Self {
+    pub fn new(
+        storage_manager: StorageManager,
+        storage_status: StorageStatus,
+        api_provider: Arc<dyn LLMApiProvider>,
+        app_handle: AppHandle<AppRuntime>,
+        max_concurrent_streams: usize,
+    ) -> Self {
         Self {
             storage: Arc::new(Mutex::new(storage_manager)),
+            storage_status: Arc::new(RwLock::new(storage_status)),
             api_provider,
             app_handle,
             cancelled_streams: Arc::new(DashMap::new()), // Initialize map
+            active_streams: Arc::new(DashMap::new()),
+            comparison_streams: Arc::new(DashMap::new()),
+            ephemeral_messages: Arc::new(DashMap::new()),
+            stream_permits: Arc::new(Semaphore::new(max_concurrent_streams.max(1))),
+            pending_tool_requests: Arc::new(DashMap::new()),
+            recent_errors: logs::recent_errors(),
+            window_subscriptions: Arc::new(DashMap::new()),
+            utility_queue: Arc::new(UtilityQueue::default()),
+            pending_conversation_updates: Arc::new(DashSet::new()),
+            size_warned_conversations: Arc::new(DashSet::new()),
+            offline_mode: Arc::new(AtomicBool::new(false)),
+            automation: Arc::new(std::sync::Mutex::new(None)),
+            background_work: Arc::new(AtomicUsize::new(0)),
+            prompt_cache: Arc::new(std::sync::Mutex::new(PromptCache::default())),
+        }
+    }
+
+    // Keeps a message of an ephemeral conversation in memory instead of the database
+    pub fn remember_ephemeral(&self, message: Message) {
+        self.ephemeral_messages.entry(message.conversation_id).or_default().push(message);
+    }
+
+    // Drops an in-memory message (no-op for persisted conversations)
+    pub fn forget_ephemeral(&self, conversation_id: Uuid, message_id: Uuid) {
+        if let Some(mut unsaved) = self.ephemeral_messages.get_mut(&conversation_id) {
+            unsaved.retain(|m| m.id != message_id);
+        }
+    }
+
+    // Applies `update` to an in-memory message; false when the message isn't held in memory
+    pub fn update_ephemeral(&self, message_id: Uuid, update: impl Fn(&mut Message)) -> bool {
+        for mut unsaved in self.ephemeral_messages.iter_mut() {
+            if let Some(message) = unsaved.iter_mut().find(|m| m.id == message_id) {
+                update(message);
+                return true;
+            }
+        }
+        false
+    }
+
+    // Stored messages (in their `seq` order) followed by any in-memory ones, which are newer,
+    // in chronological order
+    pub fn with_ephemeral_messages(&self, conversation_id: Uuid, mut stored: Vec<Message>) -> Vec<Message> {
+        if let Some(unsaved) = self.ephemeral_messages.get(&conversation_id) {
+            let mut unsaved = unsaved.to_vec();
+            unsaved.sort_by_key(|m| m.timestamp);
+            stored.extend(unsaved);
+        }
+        stored
+    }
+
+    // Schedules one `conversation_updated` event for a changed conversation. Changes within
+    // CONVERSATION_UPDATE_DEBOUNCE of the first are coalesced, so a stream's saves send one event.
+    pub fn notify_conversation_updated(&self, conversation_id: Uuid) {
+        if !self.pending_conversation_updates.insert(conversation_id) {
+            return; // Already scheduled; that event will carry this change too
+        }
+        let state = self.clone();
+        tauri::async_runtime::spawn(async move {
+            tokio::time::sleep(CONVERSATION_UPDATE_DEBOUNCE).await;
+            state.pending_conversation_updates.remove(&conversation_id);
+            let summary = {
+                let storage = state.storage.lock().await;
+                storage.get_conversation_summary(conversation_id, CONVERSATION_PREVIEW_CHARS).await
+            };
+            let mut summary = match summary {
+                Ok(summary) => summary,
+                Err(e) => {
+                    log::error!("Failed to load summary of conversation {}: {:?}", conversation_id, e);
+                    return;
+                }
+            };
+            // Ephemeral conversations keep their messages in memory only
+            if let (Some(summary), Some(unsaved)) = (summary.as_mut(), state.ephemeral_messages.get(&conversation_id)) {
+                summary.message_count += unsaved.len() as i64;
+                if let Some(last) = unsaved.last() {
+                    summary.last_message_role = Some(last.role.clone());
+                    summary.last_message_preview = Some(message_preview(&last.content, CONVERSATION_PREVIEW_CHARS));
+                }
+            }
+            if let Some(summary) = summary.as_ref() {
+                state.check_conversation_size(conversation_id, summary.message_count).await;
+            }
+            let payload = ConversationUpdated { conversation_id: conversation_id.to_string(), summary };
+            if let Err(e) = state.app_handle.emit(events::CONVERSATION_UPDATED, payload) {
+                log::error!("Failed to emit conversation_updated for {}: {:?}", conversation_id, e);
+            }
+        });
+    }
+
+    // Sends `conversation_size_warning` when a conversation first reaches the configured message
+    // count this session. Falling back below it (after a split, say) re-arms the warning.
+    async fn check_conversation_size(&self, conversation_id: Uuid, message_count: i64) {
+        let threshold = {
+            let storage = self.storage.lock().await;
+            match storage.get_setting(crate::config::CONVERSATION_SIZE_WARNING_KEY).await {
+                Ok(Some(value)) => value.trim().parse::<i64>().unwrap_or_else(|_| {
+                    log::warn!("Ignoring invalid conversation size warning threshold '{}'", value);
+                    crate::config::DEFAULT_CONVERSATION_SIZE_WARNING
+                }),
+                Ok(None) => crate::config::DEFAULT_CONVERSATION_SIZE_WARNING,
+                Err(e) => {
+                    log::warn!("Failed to read setting '{}': {:?}", crate::config::CONVERSATION_SIZE_WARNING_KEY, e);
+                    crate::config::DEFAULT_CONVERSATION_SIZE_WARNING
+                }
+            }
+        };
+        if threshold <= 0 || message_count < threshold {
+            self.size_warned_conversations.remove(&conversation_id);
+            return;
+        }
+        if !self.size_warned_conversations.insert(conversation_id) {
+            return;
+        }
+        log::info!("Conversation {} has {} messages; suggesting a split", conversation_id, message_count);
+        let payload = ConversationSizeWarning {
+            conversation_id: conversation_id.to_string(),
+            message_count,
+            threshold,
+        };
+        if let Err(e) = self.app_handle.emit(events::CONVERSATION_SIZE_WARNING, payload) {
+            log::error!("Failed to emit conversation_size_warning for {}: {:?}", conversation_id, e);
+        }
+    }
+
+    // Emits a conversation-scoped event (stream chunks and the like) only to the windows
+    // subscribed to that conversation; broadcasts when none are. Sidebar-wide events such
+    // as `conversation_updated` go to every window.
+    pub fn emit_to_conversation<S: Serialize + Clone>(
+        &self,
+        conversation_id: Uuid,
+        event: &str,
+        payload: S,
+    ) -> tauri::Result<()> {
+        let labels: Vec<String> = self
+            .window_subscriptions
+            .get(&conversation_id)
+            .map(|labels| labels.iter().cloned().collect())
+            .unwrap_or_default();
+        if labels.is_empty() {
+            return self.app_handle.emit(event, payload);
+        }
+        for label in labels {
+            self.app_handle.emit_to(label.as_str(), event, payload.clone())?;
+        }
+        Ok(())
+    }
+
+    pub fn subscribe_window(&self, window_label: &str, conversation_id: Uuid) {
+        self.window_subscriptions.entry(conversation_id).or_default().insert(window_label.to_string());
+    }
+
+    pub fn unsubscribe_window(&self, window_label: &str, conversation_id: Uuid) {
+        self.window_subscriptions.remove_if_mut(&conversation_id, |_, labels| {
+            labels.remove(window_label);
+            labels.is_empty()
+        });
+    }
+
+    // Drops every subscription of a window, e.g. once it is closed
+    pub fn forget_window(&self, window_label: &str) {
+        self.window_subscriptions.retain(|_, labels| {
+            labels.remove(window_label);
+            !labels.is_empty()
+        });
+    }
+
+    // The provider implementation named by `config.provider`. Configs can outlive the
+    // provider they were created for, so an unknown name is an error rather than a fallback.
+    pub fn provider_for(&self, config: &ModelConfig) -> Result<Arc<dyn LLMApiProvider>, CommandError> {
+        self.ensure_online()?;
+        self.provider_named(&config.provider).ok_or_else(|| CommandError::provider(format!(
+            "Model config '{}' uses provider '{}', which this version of the app does not support",
+            config.name, config.provider
+        )))
+    }
+
+    // The implementation registered under a provider name. Skips the offline check, so it is
+    // only for inspecting a provider; requests go through `provider_for`.
+    pub fn provider_named(&self, provider: &str) -> Option<Arc<dyn LLMApiProvider>> {
+        match provider {
+            "openai_compatible" => Some(self.api_provider.clone()),
+            #[cfg(any(test, feature = "testing"))]
+            crate::mock::MOCK_PROVIDER => Some(Arc::new(crate::mock::MockProvider::scripted_by_config())),
+            _ => None,
+        }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.offline_mode.load(Ordering::Relaxed)
+    }
+
+    // The check in front of every outbound request: providers are only handed out through
+    // `provider_for`, and the update check calls this itself. Local features never do.
+    pub fn ensure_online(&self) -> Result<(), CommandError> {
+        if self.is_offline() {
+            return Err(CommandError::offline_mode("Offline mode is on. Turn it off to make network requests."));
+        }
+        Ok(())
+    }
+
+    // Updates the in-memory flag and tells every window; the caller persists the setting
+    pub fn set_offline(&self, enabled: bool) {
+        if self.offline_mode.swap(enabled, Ordering::Relaxed) == enabled {
+            return;
+        }
+        log::info!("Offline mode {}", if enabled { "enabled" } else { "disabled" });
+        if let Err(e) = self.app_handle.emit(events::OFFLINE_MODE_CHANGED, events::OfflineModeChanged { enabled }) {
+            log::error!("Failed to emit offline_mode_changed: {:?}", e);
+        }
+    }
+
+    // Waits for a free streaming slot. The permit is released when dropped, so holding it
+    // for the life of a background task covers every exit path.
+    pub async fn acquire_stream_permit(&self, conversation_id: Uuid) -> Option<OwnedSemaphorePermit> {
+        if self.stream_permits.available_permits() == 0 {
+            log::info!("Stream for conversation {} queued: concurrent stream limit reached", conversation_id);
         }
+        self.stream_permits.clone().acquire_owned().await.ok()
+    }
+
+    // Taken before a command first locks storage, so a library switch either sees the work
+    // or happens before the command reads anything
+    pub fn begin_background_work(&self) -> BackgroundWork {
+        self.background_work.fetch_add(1, Ordering::SeqCst);
+        BackgroundWork(self.background_work.clone())
+    }
+
+    // Spawns `task`, holding `work` until it finishes
+    pub fn spawn_background(&self, work: BackgroundWork, task: impl std::future::Future<Output = ()> + Send + 'static) {
+        tauri::async_runtime::spawn(async move {
+            task.await;
+            drop(work);
+        });
+    }
+
+    // Whether any generation, summary, title task or job run is queued or running
+    pub fn has_background_work(&self) -> bool {
+        self.background_work.load(Ordering::SeqCst) > 0
     }
-} 
\ No newline at end of file
+}
@@ -1,12 +1,21 @@
 use anyhow::Context;
-use sqlx::{migrate::MigrateDatabase, sqlite::SqlitePoolOptions, Sqlite, SqlitePool};
-use tauri::AppHandle;
-use tauri::Manager;
-use crate::models::Conversation;
+use serde::Serialize;
+use sqlx::{migrate::MigrateDatabase, sqlite::{SqlitePoolOptions, SqliteRow}, Column, Row, Sqlite, SqlitePool, Transaction, TypeInfo, ValueRef};
+use tauri::{AppHandle, Manager, Runtime};
+use crate::models::{Conversation, ConversationActivity, ConversationSnapshot, ConversationSummary, MessageWithTitle, MessagesSince, Persona};
 use uuid::Uuid;
 use chrono::{Utc};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use crate::models::Message;
 use crate::models::ModelConfig;
+use crate::integrity::{DatabaseIntegrityReport, ForeignKeyViolation, IntegrityReport, RepairActions, RepairSummary};
+use crate::tools::{ToolPermission, ToolPolicy};
+use crate::jobs::PendingJob;
+use crate::ingest::{ProjectContext, ProjectFile};
+use crate::changelog::{self, ConversationEvent, UndoOutcome};
+use crate::memory::ConversationMemory;
+use crate::usage::{UsageGrouping, UsageRow};
 
 // Define the database schema using CREATE TABLE IF NOT EXISTS statements
 const MIGRATIONS_SQL: &str = "
@@ -47,80 +56,740 @@ CREATE TABLE IF NOT EXISTS settings (
     key TEXT PRIMARY KEY NOT NULL,
     value TEXT NOT NULL
 );
+
+-- Tool Permissions Table: whether a tool may run without asking
+CREATE TABLE IF NOT EXISTS tool_permissions (
+    tool_name TEXT NOT NULL,
+    conversation_id TEXT NOT NULL DEFAULT '', -- '' for the global policy
+    policy TEXT NOT NULL, -- 'allow', 'deny' or 'ask'
+    PRIMARY KEY (tool_name, conversation_id)
+);
+
+-- Conversation Memory Table: cached summary of a long conversation's oldest messages
+CREATE TABLE IF NOT EXISTS conversation_memory (
+    conversation_id TEXT PRIMARY KEY NOT NULL,
+    summary TEXT NOT NULL,
+    last_message_id TEXT NOT NULL, -- Newest message folded into the summary
+    covered_until INTEGER NOT NULL, -- That message's timestamp (Unix seconds)
+    updated_at INTEGER NOT NULL -- Unix Timestamp (seconds)
+);
+
+-- Pending Jobs Table: failed utility requests waiting to be retried (see `jobs`)
+CREATE TABLE IF NOT EXISTS pending_jobs (
+    id TEXT PRIMARY KEY NOT NULL, -- UUID
+    job_type TEXT NOT NULL, -- 'title' or 'summary'
+    conversation_id TEXT NOT NULL,
+    payload TEXT NOT NULL, -- JSON blob with the job's parameters
+    attempts INTEGER NOT NULL, -- Failed attempts so far
+    next_attempt_at INTEGER NOT NULL, -- Unix Timestamp (seconds)
+    last_error TEXT,
+    created_at INTEGER NOT NULL -- Unix Timestamp (seconds)
+);
+CREATE INDEX IF NOT EXISTS idx_pending_jobs_next_attempt_at ON pending_jobs(next_attempt_at);
+
+-- Conversation Snapshots Table: immutable copies of a conversation's messages to restore later
+CREATE TABLE IF NOT EXISTS conversation_snapshots (
+    id TEXT PRIMARY KEY NOT NULL, -- UUID
+    conversation_id TEXT NOT NULL,
+    label TEXT NOT NULL,
+    messages TEXT NOT NULL, -- JSON array of the messages at snapshot time
+    message_count INTEGER NOT NULL,
+    created_at INTEGER NOT NULL -- Unix Timestamp (seconds)
+);
+CREATE INDEX IF NOT EXISTS idx_conversation_snapshots_conversation_id ON conversation_snapshots(conversation_id);
+
+-- Personas Table: switchable assistant identities (see `models::Persona`)
+CREATE TABLE IF NOT EXISTS personas (
+    id TEXT PRIMARY KEY NOT NULL, -- UUID
+    name TEXT NOT NULL UNIQUE,
+    system_prompt TEXT NOT NULL,
+    model_config_id TEXT, -- Config conversations switch to when picking the persona, NULL for none
+    params TEXT -- JSON object of provider_options overrides
+);
+
+-- Conversation Events Table: append-only log of changes, so they can be undone (see `changelog`)
+CREATE TABLE IF NOT EXISTS conversation_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    conversation_id TEXT NOT NULL,
+    event_type TEXT NOT NULL, -- 'rename', 'model_change', 'message_delete' or 'message_edit'
+    old_value TEXT, -- JSON of what the change replaced
+    new_value TEXT, -- JSON of what it left in place
+    created_at INTEGER NOT NULL, -- Unix Timestamp (seconds)
+    undone_at INTEGER -- Unix Timestamp (seconds) once reversed by `undo_last_event`
+);
+CREATE INDEX IF NOT EXISTS idx_conversation_events_conversation_id ON conversation_events(conversation_id, id);
+
+-- Project Contexts Table: the folder ingested into a conversation (see `ingest`)
+CREATE TABLE IF NOT EXISTS project_contexts (
+    conversation_id TEXT PRIMARY KEY NOT NULL,
+    root TEXT NOT NULL, -- Absolute path of the ingested folder
+    include_globs TEXT NOT NULL, -- JSON array
+    exclude_globs TEXT NOT NULL, -- JSON array
+    updated_at INTEGER NOT NULL -- Unix Timestamp (seconds)
+);
+
+-- Project Files Table: manifest of a project context, skipped files included
+CREATE TABLE IF NOT EXISTS project_files (
+    conversation_id TEXT NOT NULL,
+    path TEXT NOT NULL, -- Relative to the root, with '/' separators
+    status TEXT NOT NULL, -- 'included', 'binary', 'oversized', 'over_budget' or 'unreadable'
+    size INTEGER NOT NULL,
+    modified INTEGER, -- Unix Timestamp (seconds)
+    hash TEXT, -- SHA-256 of included contents
+    content TEXT, -- Included files only
+    PRIMARY KEY (conversation_id, path)
+);
 ";
 
+// Columns added after the initial schema, as (table, column, definition).
+// `run_migrations` adds any that are missing so existing databases are upgraded in place.
+const COLUMN_MIGRATIONS: &[(&str, &str, &str)] = &[
+    ("messages", "name", "TEXT"), // Optional participant name (multi-agent / tool roles)
+    ("conversations", "system_prompt", "TEXT"), // Per-conversation instructions
+    ("conversations", "deleted_at", "INTEGER"), // Soft-delete timestamp (Unix seconds), NULL when live
+    ("model_configs", "sort_order", "INTEGER NOT NULL DEFAULT 0"), // Favorites ordering
+    ("model_configs", "is_default", "INTEGER NOT NULL DEFAULT 0"), // 1 for the default config
+    ("conversations", "ephemeral", "INTEGER NOT NULL DEFAULT 0"), // 1 when messages must not be persisted
+    ("conversations", "model_override", "TEXT"), // Model name used instead of the config's `model`
+    ("conversations", "token_budget", "INTEGER"), // Token cap for the whole conversation, NULL for none
+    ("conversations", "language", "TEXT"), // Language code used for generated titles, NULL when unknown
+    ("messages", "seq", "INTEGER"), // Display order: global and increasing on insert, see `reorder_messages`
+    ("conversations", "metadata", "TEXT"), // User key-value JSON object (ticket links, notes), NULL when unset
+    ("conversations", "locked", "INTEGER NOT NULL DEFAULT 0"), // 1 when no more messages may be sent or regenerated
+    ("conversations", "style_preset", "TEXT"), // Answer style preset key, or custom instruction text
+    ("conversations", "color", "TEXT"), // Sidebar color as #RRGGBB (or #RGB), NULL for none
+    ("conversations", "icon", "TEXT"), // Sidebar icon name or emoji, NULL for none
+    ("conversations", "persona_id", "TEXT"), // Persona applied to requests, NULL for none
+    ("conversations", "title_model_config_id", "TEXT"), // Model config generating titles, NULL for the app-wide one
+    ("messages", "variant_group", "TEXT"), // Shared by alternative answers to one turn (the first answer's ID), NULL otherwise
+    ("conversations", "single_turn", "INTEGER NOT NULL DEFAULT 0"), // 1 when requests leave out earlier turns
+];
+
+/// Schema version reported in diagnostics: the number of column migrations this build applies.
+pub fn schema_version() -> usize {
+    COLUMN_MIGRATIONS.len()
+}
+
+/// Orderings available for the conversation list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConversationSort {
+    LastUpdated,
+    Created,
+    Title,
+    MessageCount,
+}
+
+impl ConversationSort {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "last_updated" | "recent" => Ok(Self::LastUpdated),
+            "created" => Ok(Self::Created),
+            "title" => Ok(Self::Title),
+            "message_count" => Ok(Self::MessageCount),
+            other => Err(format!("Unknown conversation sort: {}", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::LastUpdated => "last_updated",
+            Self::Created => "created",
+            Self::Title => "title",
+            Self::MessageCount => "message_count",
+        }
+    }
+
+    // ORDER BY clause for `list_conversations`; ties fall back to most recently updated
+    fn order_by(&self, ascending: bool) -> String {
+        let direction = if ascending { "ASC" } else { "DESC" };
+        let key = match self {
+            Self::LastUpdated => "c.last_updated_at",
+            Self::Created => "c.created_at",
+            Self::Title => "c.title COLLATE NOCASE",
+            Self::MessageCount => "COALESCE(mc.message_count, 0)",
+        };
+        format!("{} {}, c.last_updated_at DESC", key, direction)
+    }
+}
+
+/// What `save_message` does with content over the size limit.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedMessagePolicy {
+    Truncate, // Store the first `max_chars` characters and flag the message
+    Reject,
+}
+
+impl OversizedMessagePolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "truncate" => Ok(Self::Truncate),
+            "reject" => Ok(Self::Reject),
+            other => Err(format!("Unknown oversized message policy: {}", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Truncate => "truncate",
+            Self::Reject => "reject",
+        }
+    }
+}
+
+/// Size guard applied to every message `save_message` stores.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageSizeLimit {
+    pub max_chars: Option<usize>, // None when the limit is off
+    pub policy: OversizedMessagePolicy,
+}
+
+impl MessageSizeLimit {
+    // The message as it should be stored: None when it fits, a truncated copy (flagged in
+    // its metadata) when it doesn't, or an error when oversized messages are rejected
+    fn apply(&self, message: &Message) -> Result<Option<Message>, anyhow::Error> {
+        let Some(max_chars) = self.max_chars else {
+            return Ok(None);
+        };
+        // Byte length bounds the character count, so most messages skip the count
+        if message.content.len() <= max_chars {
+            return Ok(None);
+        }
+        let chars = message.content.chars().count();
+        if chars <= max_chars {
+            return Ok(None);
+        }
+        match self.policy {
+            OversizedMessagePolicy::Reject => Err(anyhow::anyhow!(
+                "Message is too large to save ({} characters, the limit is {}).",
+                chars, max_chars
+            )),
+            OversizedMessagePolicy::Truncate => {
+                log::warn!("Truncating message {} from {} to {} characters", message.id, chars, max_chars);
+                let mut truncated = message.clone();
+                truncated.content = message.content.chars().take(max_chars).collect();
+                truncated.set_metadata_field("content_truncated", serde_json::json!(true));
+                truncated.set_metadata_field("original_chars", serde_json::json!(chars));
+                Ok(Some(truncated))
+            }
+        }
+    }
+}
+
+/// First `max_chars` characters of `content` with runs of whitespace collapsed to one space.
+pub fn message_preview(content: &str, max_chars: usize) -> String {
+    content.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(max_chars).collect()
+}
+
+// File path named by a `sqlite:` URL, without the scheme or query string
+fn db_path_from_url(db_url: &str) -> PathBuf {
+    let path = db_url.strip_prefix("sqlite://").or_else(|| db_url.strip_prefix("sqlite:")).unwrap_or(db_url);
+    PathBuf::from(path.split('?').next().unwrap_or(path))
+}
+
+// Maps a `conversations` row selected by a runtime query to a Conversation
+fn conversation_from_row(row: &SqliteRow) -> Result<Conversation, anyhow::Error> {
+    Ok(Conversation {
+        id: Uuid::parse_str(&row.try_get::<String, _>("id")?).context("Failed to parse conversation ID")?,
+        title: row.try_get("title")?,
+        created_at: chrono::DateTime::from_timestamp(row.try_get("created_at")?, 0)
+            .context("Invalid created_at timestamp")?,
+        last_updated_at: chrono::DateTime::from_timestamp(row.try_get("last_updated_at")?, 0)
+            .context("Invalid last_updated_at timestamp")?,
+        model_config_id: Uuid::parse_str(&row.try_get::<String, _>("model_config_id")?)
+            .context("Failed to parse model_config_id")?,
+        system_prompt: row.try_get("system_prompt")?,
+        deleted_at: row.try_get::<Option<i64>, _>("deleted_at")?
+            .map(|ts| chrono::DateTime::from_timestamp(ts, 0).context("Invalid deleted_at timestamp"))
+            .transpose()?,
+        ephemeral: row.try_get::<i64, _>("ephemeral")? != 0,
+        model_override: row.try_get("model_override")?,
+        token_budget: row.try_get("token_budget")?,
+        language: row.try_get("language")?,
+        locked: row.try_get::<i64, _>("locked")? != 0,
+        style_preset: row.try_get("style_preset")?,
+        color: row.try_get("color")?,
+        icon: row.try_get("icon")?,
+        persona_id: row.try_get::<Option<String>, _>("persona_id")?
+            .map(|id| Uuid::parse_str(&id).context("Failed to parse persona_id"))
+            .transpose()?,
+        title_model_config_id: row.try_get::<Option<String>, _>("title_model_config_id")?
+            .map(|id| Uuid::parse_str(&id).context("Failed to parse title_model_config_id"))
+            .transpose()?,
+        single_turn: row.try_get::<i64, _>("single_turn")? != 0,
+    })
+}
+
+// Maps a `messages` row selected by a runtime query to a Message
+fn message_from_row(row: &SqliteRow) -> Result<Message, anyhow::Error> {
+    Ok(Message {
+        id: Uuid::parse_str(&row.try_get::<String, _>("id")?).context("Failed to parse message ID")?,
+        conversation_id: Uuid::parse_str(&row.try_get::<String, _>("conversation_id")?)
+            .context("Failed to parse conversation ID for message")?,
+        role: row.try_get("role")?,
+        content: row.try_get("content")?,
+        timestamp: chrono::DateTime::from_timestamp(row.try_get("timestamp")?, 0)
+            .context("Invalid message timestamp")?,
+        metadata: row.try_get("metadata")?,
+        name: row.try_get("name")?,
+        variant_group: parse_variant_group(row.try_get("variant_group")?)?,
+    })
+}
+
+fn parse_variant_group(group: Option<String>) -> Result<Option<Uuid>, anyhow::Error> {
+    group.map(|group| Uuid::parse_str(&group)).transpose().context("Failed to parse message variant group")
+}
+
+fn pending_job_from_row(row: &SqliteRow) -> Result<PendingJob, anyhow::Error> {
+    Ok(PendingJob {
+        id: Uuid::parse_str(&row.try_get::<String, _>("id")?).context("Failed to parse job ID")?,
+        job_type: row.try_get("job_type")?,
+        conversation_id: Uuid::parse_str(&row.try_get::<String, _>("conversation_id")?)
+            .context("Failed to parse job conversation ID")?,
+        payload: serde_json::from_str(&row.try_get::<String, _>("payload")?).context("Invalid job payload")?,
+        attempts: row.try_get("attempts")?,
+        next_attempt_at: chrono::DateTime::from_timestamp(row.try_get("next_attempt_at")?, 0)
+            .context("Invalid next_attempt_at timestamp")?,
+        last_error: row.try_get("last_error")?,
+        created_at: chrono::DateTime::from_timestamp(row.try_get("created_at")?, 0)
+            .context("Invalid created_at timestamp")?,
+    })
+}
+
+fn persona_from_row(row: &SqliteRow) -> Result<Persona, anyhow::Error> {
+    Ok(Persona {
+        id: Uuid::parse_str(&row.try_get::<String, _>("id")?).context("Failed to parse persona ID")?,
+        name: row.try_get("name")?,
+        system_prompt: row.try_get("system_prompt")?,
+        model_config_id: row.try_get::<Option<String>, _>("model_config_id")?
+            .map(|id| Uuid::parse_str(&id).context("Failed to parse persona model_config_id"))
+            .transpose()?,
+        params: row.try_get("params")?,
+    })
+}
+
+fn snapshot_from_row(row: &SqliteRow) -> Result<ConversationSnapshot, anyhow::Error> {
+    Ok(ConversationSnapshot {
+        id: Uuid::parse_str(&row.try_get::<String, _>("id")?).context("Failed to parse snapshot ID")?,
+        conversation_id: Uuid::parse_str(&row.try_get::<String, _>("conversation_id")?)
+            .context("Failed to parse snapshot conversation ID")?,
+        label: row.try_get("label")?,
+        message_count: row.try_get("message_count")?,
+        created_at: chrono::DateTime::from_timestamp(row.try_get("created_at")?, 0)
+            .context("Invalid created_at timestamp")?,
+    })
+}
+
+fn conversation_event_from_row(row: &SqliteRow) -> Result<ConversationEvent, anyhow::Error> {
+    let json = |column: &str| -> Result<Option<serde_json::Value>, anyhow::Error> {
+        row.try_get::<Option<String>, _>(column)?
+            .map(|raw| serde_json::from_str(&raw).context("Invalid conversation event value"))
+            .transpose()
+    };
+    Ok(ConversationEvent {
+        id: row.try_get("id")?,
+        conversation_id: Uuid::parse_str(&row.try_get::<String, _>("conversation_id")?)
+            .context("Failed to parse event conversation ID")?,
+        event_type: row.try_get("event_type")?,
+        old_value: json("old_value")?,
+        new_value: json("new_value")?,
+        created_at: chrono::DateTime::from_timestamp(row.try_get("created_at")?, 0)
+            .context("Invalid created_at timestamp")?,
+        undone_at: row.try_get::<Option<i64>, _>("undone_at")?
+            .map(|ts| chrono::DateTime::from_timestamp(ts, 0).context("Invalid undone_at timestamp"))
+            .transpose()?,
+    })
+}
+
+/// Result of `compact_database`.
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactionReport {
+    pub size_before: u64, // Bytes, including any write-ahead log
+    pub size_after: u64,
+    pub optimized: bool, // Whether `PRAGMA optimize` ran
+}
+
+/// What `run_maintenance` (and each startup's migration pass) changed. All zero/empty when
+/// the database was already fully migrated.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MaintenanceReport {
+    pub columns_added: Vec<String>, // "table.column"
+    pub messages_sequenced: u64, // Messages that had no `seq` yet
+    pub model_configs_reordered: u64, // Configs renumbered because their sort_order collided
+}
+
+/// Approximate space one conversation's messages take, from `conversation_sizes`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ConversationSize {
+    pub conversation_id: Uuid,
+    pub title: String,
+    pub trashed: bool,
+    pub message_count: i64,
+    pub content_bytes: i64, // UTF-8 bytes of message content
+    pub metadata_bytes: i64, // Bytes of message metadata JSON
+    pub total_bytes: i64,
+}
+
+/// One column of a row as stored, from `get_message_row`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawColumn {
+    pub name: String,
+    pub sqlite_type: String, // Storage class of the value: "INTEGER", "REAL", "TEXT", "BLOB" or "NULL"
+    pub value: serde_json::Value, // Numbers and text as stored; blobs as lowercase hex
+}
+
+/// Result of `wipe_all_data`.
+#[derive(Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct WipeReport {
+    pub conversations_deleted: u64,
+    pub messages_deleted: u64,
+    pub model_configs_deleted: u64,
+    pub keyring_errors: Vec<String>, // API keys and the automation token that could not be removed
+}
+
+// Model configs offered during onboarding: name, API URL, key reference and provider_options.
+// All go through the OpenAI-compatible provider; Anthropic serves an OpenAI-compatible API
+// and Ollama ignores the key, so any value in OLLAMA_API_KEY works.
+const STARTER_CONFIGS: &[(&str, &str, &str, &str)] = &[
+    ("OpenAI GPT-4.1", "https://api.openai.com/v1", "env:OPENAI_API_KEY", r#"{"model": "gpt-4.1"}"#),
+    ("Anthropic Claude Sonnet", "https://api.anthropic.com/v1", "env:ANTHROPIC_API_KEY", r#"{"model": "claude-sonnet-4-5", "max_tokens": 4096}"#),
+    ("Ollama (localhost)", "http://localhost:11434/v1", "env:OLLAMA_API_KEY", r#"{"model": "llama3.2"}"#),
+];
+
+// Every table, children before parents, in the order `wipe_all_data` empties them
+const WIPED_TABLES: &[&str] = &[
+    "project_files",
+    "project_contexts",
+    "conversation_events",
+    "conversation_snapshots",
+    "conversation_memory",
+    "pending_jobs",
+    "tool_permissions",
+    "messages",
+    "conversations",
+    "personas",
+    "model_configs",
+    "settings",
+];
+
 #[derive(Debug)]
 pub struct StorageManager {
     pool: SqlitePool,
+    db_path: PathBuf, // Location of the SQLite file backing this manager
 }
 
 impl StorageManager {
     /// Creates a new StorageManager, connects to the database, and runs migrations.
-    pub async fn new(app_handle: &AppHandle) -> Result<Self, anyhow::Error> {
-        let db_path = app_handle
+    pub async fn new<R: Runtime>(app_handle: &AppHandle<R>) -> Result<Self, anyhow::Error> {
+        Self::open(&Self::default_path(app_handle)?).await
+    }
+
+    /// Where the app keeps its database.
+    pub fn default_path<R: Runtime>(app_handle: &AppHandle<R>) -> Result<PathBuf, anyhow::Error> {
+        app_handle
             .path()
             .resolve("localchat.sqlite", tauri::path::BaseDirectory::AppLocalData)
-            .context("Failed to resolve database path")?;
-        
+            .context("Failed to resolve database path")
+    }
+
+    /// Opens (creating if needed) the SQLite library at `db_path` and runs migrations.
+    pub async fn open(db_path: &Path) -> Result<Self, anyhow::Error> {
         // Ensure the parent directory exists
         if let Some(parent) = db_path.parent() {
             tokio::fs::create_dir_all(parent).await.context("Failed to create database directory")?;
         }
 
         let db_url = format!("sqlite://{}?mode=rwc", db_path.to_string_lossy());
+        Self::new_with_url(&db_url).await
+    }
+
+    /// Connects to `db_url` and runs migrations. `sqlite::memory:` gives a private
+    /// in-memory database that lives as long as the manager, which is what tests want.
+    pub async fn new_with_url(db_url: &str) -> Result<Self, anyhow::Error> {
         log::info!("Connecting to database: {}", db_url);
+        let in_memory = db_url.contains(":memory:") || db_url.contains("mode=memory");
 
         // Create the database file if it doesn't exist
-        if !Sqlite::database_exists(&db_url).await.unwrap_or(false) {
+        if !in_memory && !Sqlite::database_exists(db_url).await.unwrap_or(false) {
             log::info!("Database file not found, creating...");
-            Sqlite::create_database(&db_url).await.context("Failed to create database")?;
+            Sqlite::create_database(db_url).await.context("Failed to create database")?;
         }
 
-        // Connect to the database
-        let pool = SqlitePoolOptions::new()
-            .connect(&db_url)
+        // Connect to the database. Every connection to an in-memory URL opens its own empty
+        // database, so those get a single connection that is never closed.
+        let pool_options = if in_memory {
+            SqlitePoolOptions::new().max_connections(1).idle_timeout(None).max_lifetime(None)
+        } else {
+            SqlitePoolOptions::new()
+        };
+        let pool = pool_options
+            .connect(db_url)
             .await
             .context("Failed to connect to SQLite database")?;
 
         // Run migrations
         Self::run_migrations(&pool).await?;
 
-        Ok(Self { pool })
+        Ok(Self { pool, db_path: db_path_from_url(db_url) })
+    }
+
+    /// Path of the SQLite file this manager is connected to.
+    pub fn db_path(&self) -> &Path {
+        &self.db_path
+    }
+
+    /// Closes the connection pool, waiting for in-flight queries to finish.
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    /// Re-applies the migrations and backfills on an open database, for recovering from an
+    /// upgrade that was interrupted. Every step is idempotent, so running it again once the
+    /// database is current changes nothing.
+    pub async fn run_maintenance(&self) -> Result<MaintenanceReport, anyhow::Error> {
+        Self::run_migrations(&self.pool).await
     }
 
     /// Applies the database schema migrations.
-    async fn run_migrations(pool: &SqlitePool) -> Result<(), anyhow::Error> {
+    async fn run_migrations(pool: &SqlitePool) -> Result<MaintenanceReport, anyhow::Error> {
         log::info!("Running database migrations...");
+        let mut report = MaintenanceReport::default();
         // In a real app, use sqlx::migrate! macro with migration files.
         // For simplicity here, we execute the combined SQL string.
         sqlx::query(MIGRATIONS_SQL)
             .execute(pool)
             .await
             .context("Failed to run database migrations")?;
+
+        // Add columns introduced after the initial schema
+        for (table, column, definition) in COLUMN_MIGRATIONS {
+            let existing: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?)")
+                .bind(table)
+                .fetch_all(pool)
+                .await
+                .context(format!("Failed to inspect columns of table '{}'", table))?;
+            if !existing.iter().any(|name| name == column) {
+                log::info!("Adding column {}.{}", table, column);
+                sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition))
+                    .execute(pool)
+                    .await
+                    .context(format!("Failed to add column '{}' to table '{}'", column, table))?;
+                report.columns_added.push(format!("{}.{}", table, column));
+            }
+        }
+
+        // Rows written before `seq` existed get one in the order those builds showed them:
+        // by timestamp, then insertion (rowid). New rows take the next value on insert.
+        let seeded = sqlx::query(
+            "UPDATE messages SET seq = unsequenced.base + unsequenced.position
+            FROM (
+                SELECT rowid AS row_id,
+                       ROW_NUMBER() OVER (ORDER BY timestamp ASC, rowid ASC) AS position,
+                       (SELECT COALESCE(MAX(seq), 0) FROM messages) AS base
+                FROM messages WHERE seq IS NULL
+            ) AS unsequenced
+            WHERE messages.rowid = unsequenced.row_id",
+        )
+        .execute(pool)
+        .await
+        .context("Failed to seed message sequence numbers")?;
+        if seeded.rows_affected() > 0 {
+            log::info!("Assigned sequence numbers to {} messages", seeded.rows_affected());
+        }
+        report.messages_sequenced = seeded.rows_affected();
+
+        // Configs created before `sort_order` existed all share 0. They are numbered in the
+        // order the list already shows them (sort_order, then name) so reordering works.
+        let duplicates: i64 = sqlx::query_scalar("SELECT COUNT(*) - COUNT(DISTINCT sort_order) FROM model_configs")
+            .fetch_one(pool)
+            .await
+            .context("Failed to inspect model config ordering")?;
+        if duplicates > 0 {
+            let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM model_configs ORDER BY sort_order ASC, name ASC")
+                .fetch_all(pool)
+                .await
+                .context("Failed to list model configs")?;
+            let mut tx = pool.begin().await.context("Failed to begin transaction")?;
+            for (position, id) in ids.iter().enumerate() {
+                let updated = sqlx::query("UPDATE model_configs SET sort_order = ? WHERE id = ? AND sort_order != ?")
+                    .bind(position as i64)
+                    .bind(id)
+                    .bind(position as i64)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to renumber model configs")?;
+                report.model_configs_reordered += updated.rows_affected();
+            }
+            tx.commit().await.context("Failed to commit model config ordering")?;
+            log::info!("Renumbered {} model configs", report.model_configs_reordered);
+        }
+
+        // Databases from before `is_default` existed have no default; the first config in the
+        // list becomes it, as when the default config is deleted.
+        let promoted = sqlx::query(
+            "UPDATE model_configs SET is_default = 1
+            WHERE id = (SELECT id FROM model_configs ORDER BY sort_order ASC, name ASC LIMIT 1)
+              AND NOT EXISTS (SELECT 1 FROM model_configs WHERE is_default != 0)",
+        )
+        .execute(pool)
+        .await
+        .context("Failed to set a default model config")?;
+        if promoted.rows_affected() > 0 {
+            log::info!("Marked the first model config as the default");
+        }
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_seq ON messages(seq)")
+            .execute(pool)
+            .await
+            .context("Failed to create message sequence index")?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_conversation_seq ON messages(conversation_id, seq)")
+            .execute(pool)
+            .await
+            .context("Failed to create conversation message sequence index")?;
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_messages_variant_group ON messages(variant_group)")
+            .execute(pool)
+            .await
+            .context("Failed to create message variant group index")?;
         log::info!("Database migrations completed.");
-        Ok(())
+        Ok(report)
+    }
+
+    /// Fetches all live conversations in the requested order.
+    pub async fn list_conversations(
+        &self,
+        sort: ConversationSort,
+        ascending: bool,
+    ) -> Result<Vec<Conversation>, anyhow::Error> {
+        log::debug!("Fetching all conversations from database (sort: {}, ascending: {})", sort.as_str(), ascending);
+        // The ORDER BY varies with the sort, so this uses a runtime query instead of `query!`.
+        // The message count is only aggregated when it's the sort key.
+        let message_count_join = if sort == ConversationSort::MessageCount {
+            "LEFT JOIN (SELECT conversation_id, COUNT(*) AS message_count FROM messages GROUP BY conversation_id) mc
+                ON mc.conversation_id = c.id"
+        } else {
+            ""
+        };
+        let sql = format!(
+            "SELECT c.id, c.title, c.created_at, c.last_updated_at, c.model_config_id, c.system_prompt, c.deleted_at, c.ephemeral, c.model_override, c.token_budget, c.language, c.locked, c.style_preset, c.color, c.icon, c.persona_id, c.title_model_config_id, c.single_turn
+            FROM conversations c
+            {}
+            WHERE c.deleted_at IS NULL
+            ORDER BY {}",
+            message_count_join,
+            sort.order_by(ascending)
+        );
+        let rows = sqlx::query(&sql)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to fetch conversations from database")?;
+
+        let conversations = rows
+            .iter()
+            .map(conversation_from_row)
+            .collect::<Result<Vec<Conversation>, anyhow::Error>>()?;
+
+        log::info!("Fetched {} conversations", conversations.len());
+        Ok(conversations)
+    }
+
+    /// Fetches live conversations last updated within `from..=to`, most recent first.
+    /// Either bound may be omitted for an open-ended range.
+    pub async fn list_conversations_between(
+        &self,
+        from: Option<chrono::DateTime<Utc>>,
+        to: Option<chrono::DateTime<Utc>>,
+    ) -> Result<Vec<Conversation>, anyhow::Error> {
+        log::debug!("Fetching conversations updated between {:?} and {:?}", from, to);
+        let from = from.map(|t| t.timestamp());
+        let to = to.map(|t| t.timestamp());
+        let rows = sqlx::query(
+            "SELECT c.id, c.title, c.created_at, c.last_updated_at, c.model_config_id, c.system_prompt, c.deleted_at, c.ephemeral, c.model_override, c.token_budget, c.language, c.locked, c.style_preset, c.color, c.icon, c.persona_id, c.title_model_config_id, c.single_turn
+            FROM conversations c
+            WHERE c.deleted_at IS NULL
+              AND (?1 IS NULL OR c.last_updated_at >= ?1)
+              AND (?2 IS NULL OR c.last_updated_at <= ?2)
+            ORDER BY c.last_updated_at DESC"
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch conversations in date range")?;
+
+        rows.iter()
+            .map(conversation_from_row)
+            .collect::<Result<Vec<Conversation>, anyhow::Error>>()
+    }
+
+    /// Fetches conversations, including trashed ones, whose model config no longer exists,
+    /// most recently updated first.
+    pub async fn list_conversations_with_missing_model(&self) -> Result<Vec<Conversation>, anyhow::Error> {
+        log::debug!("Fetching conversations with a missing model config");
+        let rows = sqlx::query(
+            "SELECT c.id, c.title, c.created_at, c.last_updated_at, c.model_config_id, c.system_prompt, c.deleted_at, c.ephemeral, c.model_override, c.token_budget, c.language, c.locked, c.style_preset, c.color, c.icon, c.persona_id, c.title_model_config_id, c.single_turn
+            FROM conversations c
+            LEFT JOIN model_configs mc ON mc.id = c.model_config_id
+            WHERE mc.id IS NULL
+            ORDER BY c.last_updated_at DESC"
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch conversations with a missing model config")?;
+
+        rows.iter()
+            .map(conversation_from_row)
+            .collect::<Result<Vec<Conversation>, anyhow::Error>>()
+    }
+
+    /// Fetches the `limit` live conversations updated least recently, oldest first, with
+    /// their message counts.
+    pub async fn list_oldest_conversations(&self, limit: i64) -> Result<Vec<ConversationActivity>, anyhow::Error> {
+        log::debug!("Fetching the {} least recently updated conversations", limit);
+        let rows = sqlx::query(
+            "SELECT c.id, c.title, c.created_at, c.last_updated_at, c.model_config_id, c.system_prompt, c.deleted_at, c.ephemeral, c.model_override, c.token_budget, c.language, c.locked, c.style_preset, c.color, c.icon, c.persona_id, c.title_model_config_id, c.single_turn,
+                (SELECT COUNT(*) FROM messages m WHERE m.conversation_id = c.id) AS message_count
+            FROM conversations c
+            WHERE c.deleted_at IS NULL
+            ORDER BY c.last_updated_at ASC
+            LIMIT ?"
+        )
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch the oldest conversations")?;
+
+        rows.iter()
+            .map(|row| {
+                Ok(ConversationActivity {
+                    conversation: conversation_from_row(row)?,
+                    message_count: row.try_get("message_count")?,
+                })
+            })
+            .collect()
     }
 
-    /// Fetches all conversations, ordered by last updated descending.
-    pub async fn list_conversations(&self) -> Result<Vec<Conversation>, anyhow::Error> {
-        log::debug!("Fetching all conversations from database");
-        // Note: sqlx requires mapping the row to the struct.
-        // Timestamps are stored as INTEGER (Unix seconds) but need to be converted to DateTime<Utc>.
-        // UUIDs are stored as TEXT but need to be parsed.
+    /// Fetches soft-deleted conversations (the recycle bin), most recently deleted first.
+    pub async fn list_deleted_conversations(&self) -> Result<Vec<Conversation>, anyhow::Error> {
+        log::debug!("Fetching soft-deleted conversations from database");
         let rows = sqlx::query!(
             r#"
-            SELECT id, title, created_at, last_updated_at, model_config_id
+            SELECT id, title, created_at, last_updated_at, model_config_id, system_prompt, deleted_at, ephemeral, model_override, token_budget, language, locked, style_preset, color, icon, persona_id, title_model_config_id, single_turn
             FROM conversations
-            ORDER BY last_updated_at DESC
+            WHERE deleted_at IS NOT NULL
+            ORDER BY deleted_at DESC
             "#
         )
         .fetch_all(&self.pool)
         .await
-        .context("Failed to fetch conversations from database")?;
+        .context("Failed to fetch deleted conversations from database")?;
 
-        // Manually map rows to Conversation structs
-        let conversations = rows
-            .into_iter()
+        rows.into_iter()
             .map(|row| {
                 Ok(Conversation {
                     id: uuid::Uuid::parse_str(&row.id).context("Failed to parse conversation ID")?,
@@ -131,18 +800,35 @@ impl StorageManager {
                         .context("Invalid last_updated_at timestamp")?,
                     model_config_id: uuid::Uuid::parse_str(&row.model_config_id)
                         .context("Failed to parse model_config_id")?,
+                    system_prompt: row.system_prompt,
+                    deleted_at: row.deleted_at
+                        .map(|ts| chrono::DateTime::from_timestamp(ts, 0).context("Invalid deleted_at timestamp"))
+                        .transpose()?,
+                    ephemeral: row.ephemeral != 0,
+                    model_override: row.model_override,
+                    token_budget: row.token_budget,
+                    language: row.language,
+                    locked: row.locked != 0,
+                    style_preset: row.style_preset,
+                    color: row.color,
+                    icon: row.icon,
+                    persona_id: row.persona_id
+                        .map(|id| uuid::Uuid::parse_str(&id).context("Failed to parse persona_id"))
+                        .transpose()?,
+                    title_model_config_id: row.title_model_config_id
+                        .map(|id| uuid::Uuid::parse_str(&id).context("Failed to parse title_model_config_id"))
+                        .transpose()?,
+                    single_turn: row.single_turn != 0,
                 })
             })
-            .collect::<Result<Vec<Conversation>, anyhow::Error>>()?;
-
-        log::info!("Fetched {} conversations", conversations.len());
-        Ok(conversations)
+            .collect::<Result<Vec<Conversation>, anyhow::Error>>()
     }
 
     /// Fetches the ID of the first model config found in the database.
-    async fn get_first_model_config_id(&self) -> Result<Uuid, anyhow::Error> {
-        log::debug!("Fetching first model config ID");
-        let row = sqlx::query!("SELECT id FROM model_configs LIMIT 1")
+    async fn get_default_model_config_id(&self) -> Result<Uuid, anyhow::Error> {
+        log::debug!("Fetching default model config ID");
+        // Falls back to the first config in list order when none is flagged default
+        let row = sqlx::query!("SELECT id FROM model_configs ORDER BY is_default DESC, sort_order ASC, name ASC LIMIT 1")
             .fetch_optional(&self.pool)
             .await
             .context("Failed to query for first model config")?;
@@ -153,17 +839,30 @@ impl StorageManager {
         }
     }
 
-    /// Creates a new conversation with a default title and the first available model config.
+    /// Creates a new conversation with a default title and the default model config.
     pub async fn create_conversation(&self) -> Result<Conversation, anyhow::Error> {
         println!("RUST_STORAGE: create_conversation entered");
-        let default_model_id = self.get_first_model_config_id().await?;
+        let default_model_id = self.get_default_model_config_id().await?;
         
         let new_conversation = Conversation {
             id: Uuid::new_v4(),
-            title: "new chat".to_string(), // Default title
+            title: crate::title::DEFAULT_CONVERSATION_TITLE.to_string(),
             created_at: Utc::now(),
             last_updated_at: Utc::now(),
             model_config_id: default_model_id,
+            system_prompt: None,
+            deleted_at: None,
+            ephemeral: false,
+            model_override: None,
+            token_budget: None,
+            language: None,
+            locked: false,
+            style_preset: None,
+            color: None,
+            icon: None,
+            persona_id: None,
+            title_model_config_id: None,
+            single_turn: false,
         };
 
         // Convert Uuid and DateTime to types storable in SQLite (TEXT and INTEGER)
@@ -192,6 +891,69 @@ impl StorageManager {
         Ok(new_conversation)
     }
 
+    /// Creates a conversation already populated with `messages` (e.g. from an import),
+    /// in a single transaction. The messages are re-pointed at the new conversation.
+    pub async fn create_conversation_with_messages(
+        &self,
+        title: &str,
+        system_prompt: Option<String>,
+        messages: Vec<Message>,
+    ) -> Result<Conversation, anyhow::Error> {
+        let default_model_id = self.get_default_model_config_id().await?;
+        let now = Utc::now();
+        let conversation = Conversation {
+            id: Uuid::new_v4(),
+            title: title.to_string(),
+            created_at: now,
+            last_updated_at: now,
+            model_config_id: default_model_id,
+            system_prompt,
+            deleted_at: None,
+            ephemeral: false,
+            model_override: None,
+            token_budget: None,
+            language: None,
+            locked: false,
+            style_preset: None,
+            color: None,
+            icon: None,
+            persona_id: None,
+            title_model_config_id: None,
+            single_turn: false,
+        };
+        log::info!("[STORAGE] Creating conversation {} with {} messages", conversation.id, messages.len());
+
+        let id_text = conversation.id.to_string();
+        let model_config_id_text = conversation.model_config_id.to_string();
+        let now_ts = now.timestamp();
+
+        let mut tx = self.pool.begin().await.context("Failed to begin conversation transaction")?;
+        sqlx::query!(
+            r#"
+            INSERT INTO conversations (id, title, created_at, last_updated_at, model_config_id, system_prompt)
+            VALUES (?, ?, ?, ?, ?, ?)
+            "#,
+            id_text,
+            conversation.title,
+            now_ts,
+            now_ts,
+            model_config_id_text,
+            conversation.system_prompt
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to insert new conversation into database")?;
+
+        let messages: Vec<Message> = messages
+            .into_iter()
+            .map(|message| Message { conversation_id: conversation.id, ..message })
+            .collect();
+        Self::insert_messages(&mut tx, &messages).await?;
+        tx.commit().await.context("Failed to commit conversation transaction")?;
+
+        Ok(conversation)
+    }
+
     /// Adds a default OpenAI-compatible model config if no configs exist.
     pub async fn add_default_model_config_if_none(&self) -> Result<(), anyhow::Error> {
         log::debug!("Checking for existing model configurations");
@@ -214,8 +976,8 @@ impl StorageManager {
 
             sqlx::query!(
                 r#"
-                INSERT INTO model_configs (id, name, provider, api_url, api_key_ref, provider_options)
-                VALUES (?, ?, ?, ?, ?, ?)
+                INSERT INTO model_configs (id, name, provider, api_url, api_key_ref, provider_options, sort_order, is_default)
+                VALUES (?, ?, ?, ?, ?, ?, 0, 1)
                 "#,
                 id_text,
                 name,
@@ -234,7 +996,38 @@ impl StorageManager {
         Ok(())
     }
 
-    /// Fetches all messages for a given conversation, ordered by timestamp ascending.
+    /// Adds the `STARTER_CONFIGS` whose names aren't taken yet (ignoring case), for onboarding.
+    /// Returns the configs added; running it again adds nothing.
+    pub async fn create_starter_configs(&self) -> Result<Vec<ModelConfig>, anyhow::Error> {
+        let taken: HashSet<String> = self.list_model_configs().await?
+            .into_iter()
+            .map(|config| config.name.to_lowercase())
+            .collect();
+        let mut created = Vec::new();
+        for (name, api_url, api_key_ref, provider_options) in STARTER_CONFIGS {
+            if taken.contains(&name.to_lowercase()) {
+                log::debug!("Starter config '{}' already exists, skipping", name);
+                continue;
+            }
+            let config = ModelConfig {
+                id: Uuid::new_v4(),
+                name: name.to_string(),
+                provider: "openai_compatible".to_string(),
+                api_url: api_url.to_string(),
+                api_key_ref: Some(api_key_ref.to_string()),
+                provider_options: Some(provider_options.to_string()),
+                sort_order: 0, // Assigned by add_model_config
+                is_default: false,
+            };
+            self.add_model_config(&config).await?;
+            created.push(config);
+        }
+        log::info!("Added {} starter model configs", created.len());
+        Ok(created)
+    }
+
+    /// Fetches all messages for a given conversation in `seq` order: the order they were
+    /// saved in, unless `reorder_messages` rearranged them.
     pub async fn get_conversation_messages(
         &self,
         conversation_id: Uuid,
@@ -244,10 +1037,10 @@ impl StorageManager {
 
         let rows = sqlx::query!(
             r#"
-            SELECT id, conversation_id, role, content, timestamp, metadata
+            SELECT id, conversation_id, role, content, timestamp, metadata, name, variant_group
             FROM messages
             WHERE conversation_id = ?
-            ORDER BY timestamp ASC
+            ORDER BY seq ASC
             "#,
             conversation_id_text
         )
@@ -268,6 +1061,8 @@ impl StorageManager {
                     timestamp: chrono::DateTime::from_timestamp(row.timestamp, 0)
                         .context("Invalid message timestamp")?,
                     metadata: row.metadata,
+                    name: row.name,
+                    variant_group: parse_variant_group(row.variant_group)?,
                 })
             })
             .collect::<Result<Vec<Message>, anyhow::Error>>()?;
@@ -276,65 +1071,476 @@ impl StorageManager {
         Ok(messages)
     }
 
-    /// Deletes a conversation and its associated messages.
-    pub async fn delete_conversation(&self, conversation_id: Uuid) -> Result<(), anyhow::Error> {
-        log::info!("[STORAGE] Deleting conversation with ID: {}", conversation_id);
-        let conversation_id_text = conversation_id.to_string();
+    /// Sequence number of the newest message, or 0 when there are none.
+    pub async fn latest_message_seq(&self) -> Result<i64, anyhow::Error> {
+        sqlx::query_scalar("SELECT COALESCE(MAX(seq), 0) FROM messages")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to read the latest message sequence number")
+    }
+
+    /// A conversation's messages saved after sequence number `after`, via
+    /// `idx_messages_conversation_seq`. A cursor past the newest message returns nothing.
+    pub async fn get_messages_since(&self, conversation_id: Uuid, after: i64) -> Result<MessagesSince<Message>, anyhow::Error> {
+        let rows = sqlx::query(
+            "SELECT id, conversation_id, role, content, timestamp, metadata, name, variant_group, seq
+            FROM messages
+            WHERE conversation_id = ? AND seq > ?
+            ORDER BY seq ASC",
+        )
+        .bind(conversation_id.to_string())
+        .bind(after)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch new messages")?;
+        let cursor = match rows.last() {
+            Some(row) => row.try_get("seq")?,
+            None => after,
+        };
+        let messages = rows.iter().map(message_from_row).collect::<Result<Vec<_>, _>>()?;
+        Ok(MessagesSince { messages, cursor, has_more: false })
+    }
+
+    /// Up to `limit` messages from live conversations saved after sequence number `after`, with
+    /// their conversation's title, via `idx_messages_seq`.
+    pub async fn get_all_messages_since(&self, after: i64, limit: u32) -> Result<MessagesSince<MessageWithTitle>, anyhow::Error> {
+        // One extra row tells whether there is more to fetch
+        let mut rows = sqlx::query(
+            "SELECT m.id, m.conversation_id, m.role, m.content, m.timestamp, m.metadata, m.name, m.variant_group, m.seq, c.title
+            FROM messages m
+            JOIN conversations c ON c.id = m.conversation_id
+            WHERE m.seq > ? AND c.deleted_at IS NULL
+            ORDER BY m.seq ASC
+            LIMIT ?",
+        )
+        .bind(after)
+        .bind(i64::from(limit) + 1)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch new messages")?;
+        let has_more = rows.len() > limit as usize;
+        rows.truncate(limit as usize);
+        let cursor = match rows.last() {
+            Some(row) => row.try_get("seq")?,
+            None => after,
+        };
+        let messages = rows
+            .iter()
+            .map(|row| Ok(MessageWithTitle { message: message_from_row(row)?, conversation_title: row.try_get("title")? }))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        Ok(MessagesSince { messages, cursor, has_more })
+    }
 
-        // Execute the DELETE statement
-        log::debug!("[STORAGE] Executing DELETE FROM conversations WHERE id = {}", conversation_id_text);
-        let rows_affected = sqlx::query!(
-            "DELETE FROM conversations WHERE id = ?",
+    /// Fetches (id, content) of every message in a conversation, in display order, for
+    /// searching. Matching is left to the caller: SQLite's LIKE only folds ASCII case, so it
+    /// would drop matches such as "ÄPFEL" for "äpfel".
+    pub async fn list_message_contents(&self, conversation_id: Uuid) -> Result<Vec<(Uuid, String)>, anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        let rows = sqlx::query!(
+            r#"
+            SELECT id, content
+            FROM messages
+            WHERE conversation_id = ?
+            ORDER BY seq ASC
+            "#,
             conversation_id_text
         )
-        .execute(&self.pool)
+        .fetch_all(&self.pool)
         .await
-        .context("Failed to delete conversation from database")?
-        .rows_affected();
+        .context("Failed to load message contents from database")?;
+
+        rows.into_iter()
+            .map(|row| Ok((Uuid::parse_str(&row.id).context("Failed to parse message ID")?, row.content)))
+            .collect()
+    }
 
+    /// Deletes a conversation with its messages and every row kept for it, in one transaction.
+    /// Returns false when no conversation has that ID.
+    pub async fn delete_conversation(&self, conversation_id: Uuid) -> Result<bool, anyhow::Error> {
+        log::info!("[STORAGE] Deleting conversation with ID: {}", conversation_id);
+        let conversation_id_text = conversation_id.to_string();
+
+        let mut tx = self.pool.begin().await.context("Failed to begin conversation delete transaction")?;
+        for table in [
+            "messages",
+            "conversation_memory",
+            "pending_jobs",
+            "conversation_snapshots",
+            "conversation_events",
+            "project_contexts",
+            "project_files",
+            "tool_permissions",
+        ] {
+            sqlx::query(&format!("DELETE FROM {} WHERE conversation_id = ?", table))
+                .bind(&conversation_id_text)
+                .execute(&mut *tx)
+                .await
+                .context(format!("Failed to delete the conversation's rows from {}", table))?;
+        }
+        let rows_affected = sqlx::query!("DELETE FROM conversations WHERE id = ?", conversation_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete conversation from database")?
+            .rows_affected();
         if rows_affected == 0 {
+            // Nothing else can belong to a missing conversation, so there's nothing to keep
             log::warn!("Attempted to delete conversation {}, but it was not found.", conversation_id);
-            // Decide if not found should be an error or just a warning
-            // For idempotency, we might return Ok(()) even if not found.
-            // Err(anyhow::anyhow!("Conversation not found")) 
-        } else {
-            log::info!("[STORAGE] Successfully deleted conversation {} (rows affected: {})", conversation_id, rows_affected);
+            return Ok(false);
         }
+        tx.commit().await.context("Failed to commit conversation delete")?;
 
-        Ok(())
+        log::info!("[STORAGE] Successfully deleted conversation {}", conversation_id);
+        Ok(true)
     }
 
-    /// Saves a single message to the database.
-    pub async fn save_message(&self, message: &Message) -> Result<(), anyhow::Error> {
-        log::debug!("Saving message ID: {}", message.id);
-        
-        let id_text = message.id.to_string();
-        let conversation_id_text = message.conversation_id.to_string();
-        let timestamp_ts = message.timestamp.timestamp();
+    /// Moves a conversation to the recycle bin by stamping `deleted_at`.
+    /// Returns false when no conversation outside the recycle bin has that ID.
+    pub async fn soft_delete_conversation(&self, conversation_id: Uuid) -> Result<bool, anyhow::Error> {
+        log::info!("[STORAGE] Soft-deleting conversation with ID: {}", conversation_id);
+        let conversation_id_text = conversation_id.to_string();
+        let deleted_at_ts = Utc::now().timestamp();
 
-        sqlx::query!(
-            r#"
-            INSERT INTO messages (id, conversation_id, role, content, timestamp, metadata)
-            VALUES (?, ?, ?, ?, ?, ?)
-            "#,
-            id_text,
-            conversation_id_text,
-            message.role,
-            message.content,
-            timestamp_ts,
-            message.metadata // Already Option<String>
+        let result = sqlx::query!(
+            "UPDATE conversations SET deleted_at = ? WHERE id = ? AND deleted_at IS NULL",
+            deleted_at_ts,
+            conversation_id_text
         )
         .execute(&self.pool)
         .await
-        .context("Failed to insert message into database")?;
+        .context("Failed to soft-delete conversation in database")?;
 
-        // Also update the conversation's last_updated_at timestamp
-        let update_conv_ts = Utc::now().timestamp();
-        sqlx::query!(
-            "UPDATE conversations SET last_updated_at = ? WHERE id = ?",
-            update_conv_ts,
-            conversation_id_text
-        )
+        if result.rows_affected() == 0 {
+            log::warn!("Attempted to soft-delete conversation {}, but it was not found or already deleted.", conversation_id);
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Restores a soft-deleted conversation from the recycle bin.
+    pub async fn restore_conversation(&self, conversation_id: Uuid) -> Result<(), anyhow::Error> {
+        log::info!("[STORAGE] Restoring conversation with ID: {}", conversation_id);
+        let conversation_id_text = conversation_id.to_string();
+
+        let result = sqlx::query!(
+            "UPDATE conversations SET deleted_at = NULL WHERE id = ? AND deleted_at IS NOT NULL",
+            conversation_id_text
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to restore conversation in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found in the recycle bin."));
+        }
+        Ok(())
+    }
+
+    /// Permanently deletes conversations soft-deleted more than `older_than_days` days ago.
+    /// Returns the number of conversations removed.
+    pub async fn purge_deleted_conversations(&self, older_than_days: u32) -> Result<u64, anyhow::Error> {
+        let cutoff_ts = (Utc::now() - chrono::Duration::days(i64::from(older_than_days))).timestamp();
+        log::warn!("[STORAGE] Purging conversations soft-deleted before {}", cutoff_ts);
+
+        let mut tx = self.pool.begin().await.context("Failed to begin purge transaction")?;
+        // Only `messages` declares a cascading foreign key; the other per-conversation tables have
+        // none, so each is cleared explicitly before the conversation rows
+        sqlx::query!(
+            r#"
+            DELETE FROM messages WHERE conversation_id IN (
+                SELECT id FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at <= ?
+            )
+            "#,
+            cutoff_ts
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to purge messages of deleted conversations")?;
+        sqlx::query(
+            r#"
+            DELETE FROM conversation_memory WHERE conversation_id IN (
+                SELECT id FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at <= ?
+            )
+            "#,
+        )
+        .bind(cutoff_ts)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to purge memory of deleted conversations")?;
+        sqlx::query(
+            r#"
+            DELETE FROM pending_jobs WHERE conversation_id IN (
+                SELECT id FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at <= ?
+            )
+            "#,
+        )
+        .bind(cutoff_ts)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to purge pending jobs of deleted conversations")?;
+        sqlx::query(
+            r#"
+            DELETE FROM conversation_snapshots WHERE conversation_id IN (
+                SELECT id FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at <= ?
+            )
+            "#,
+        )
+        .bind(cutoff_ts)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to purge snapshots of deleted conversations")?;
+        sqlx::query(
+            r#"
+            DELETE FROM conversation_events WHERE conversation_id IN (
+                SELECT id FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at <= ?
+            )
+            "#,
+        )
+        .bind(cutoff_ts)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to purge change history of deleted conversations")?;
+        for table in ["project_contexts", "project_files"] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE conversation_id IN (SELECT id FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at <= ?)",
+                table
+            ))
+            .bind(cutoff_ts)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to purge project context of deleted conversations")?;
+        }
+        let purged = sqlx::query!(
+            "DELETE FROM conversations WHERE deleted_at IS NOT NULL AND deleted_at <= ?",
+            cutoff_ts
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to purge deleted conversations")?
+        .rows_affected();
+        tx.commit().await.context("Failed to commit purge transaction")?;
+
+        log::info!("[STORAGE] Purged {} deleted conversations", purged);
+        Ok(purged)
+    }
+
+    /// Appends the source conversation's messages to the target and deletes the source.
+    /// Source messages are renumbered to follow the target's last message, keeping their
+    /// relative order and their timestamps.
+    pub async fn merge_conversations(&self, source_id: Uuid, target_id: Uuid) -> Result<(), anyhow::Error> {
+        log::info!("[STORAGE] Merging conversation {} into {}", source_id, target_id);
+        let source_id_text = source_id.to_string();
+        let target_id_text = target_id.to_string();
+
+        let mut tx = self.pool.begin().await.context("Failed to begin merge transaction")?;
+
+        let found = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM conversations WHERE id IN (?, ?)"#,
+            source_id_text,
+            target_id_text
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to look up conversations to merge")?
+        .count;
+        if found != 2 {
+            return Err(anyhow::anyhow!("Both conversations must exist to merge them."));
+        }
+
+        // Source messages keep their timestamps; fresh sequence numbers put them after the
+        // target's messages, in their own order
+        let source_ids: Vec<String> = sqlx::query_scalar("SELECT id FROM messages WHERE conversation_id = ? ORDER BY seq ASC")
+            .bind(&source_id_text)
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to read source conversation's messages")?;
+        sqlx::query!(
+            "UPDATE messages SET conversation_id = ? WHERE conversation_id = ?",
+            target_id_text,
+            source_id_text
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to move messages into target conversation")?;
+        Self::resequence_messages(&mut tx, &source_ids).await?;
+
+        sqlx::query!("DELETE FROM conversations WHERE id = ?", source_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete merged source conversation")?;
+        sqlx::query("DELETE FROM conversation_memory WHERE conversation_id = ?")
+            .bind(&source_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete merged source conversation's memory")?;
+        sqlx::query("DELETE FROM conversation_snapshots WHERE conversation_id = ?")
+            .bind(&source_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete merged source conversation's snapshots")?;
+        sqlx::query("DELETE FROM conversation_events WHERE conversation_id = ?")
+            .bind(&source_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete merged source conversation's change history")?;
+        for table in ["project_contexts", "project_files"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE conversation_id = ?", table))
+                .bind(&source_id_text)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to delete merged source conversation's project context")?;
+        }
+
+        let update_conv_ts = Utc::now().timestamp();
+        sqlx::query!(
+            "UPDATE conversations SET last_updated_at = ? WHERE id = ?",
+            update_conv_ts,
+            target_id_text
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to update target conversation timestamp")?;
+
+        tx.commit().await.context("Failed to commit merge transaction")?;
+        log::info!("[STORAGE] Merged conversation {} into {}", source_id, target_id);
+        Ok(())
+    }
+
+    /// Moves `at_message_id` and every message after it into a new conversation titled `title`,
+    /// which takes over the source's model config, system prompt and other per-conversation
+    /// settings. `seed`, if given, is saved into the new conversation ahead of the moved messages.
+    pub async fn split_conversation(
+        &self,
+        source_id: Uuid,
+        at_message_id: Uuid,
+        title: &str,
+        seed: Option<Message>,
+    ) -> Result<Uuid, anyhow::Error> {
+        log::info!("[STORAGE] Splitting conversation {} at message {}", source_id, at_message_id);
+        let source_id_text = source_id.to_string();
+        let new_id = Uuid::new_v4();
+        let new_id_text = new_id.to_string();
+        let now_ts = Utc::now().timestamp();
+
+        let mut tx = self.pool.begin().await.context("Failed to begin split transaction")?;
+
+        let split_at = sqlx::query("SELECT timestamp, seq FROM messages WHERE id = ? AND conversation_id = ?")
+            .bind(at_message_id.to_string())
+            .bind(&source_id_text)
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to look up the message to split at")?
+            .ok_or_else(|| anyhow::anyhow!("Message {} is not in conversation {}", at_message_id, source_id))?;
+        let split_ts: i64 = split_at.try_get("timestamp")?;
+        let split_seq: i64 = split_at.try_get("seq")?;
+
+        let created = sqlx::query(
+            r#"
+            INSERT INTO conversations (id, title, created_at, last_updated_at, model_config_id, system_prompt,
+                model_override, token_budget, language, metadata, style_preset, color, icon, persona_id, title_model_config_id, single_turn)
+            SELECT ?, ?, ?, ?, model_config_id, system_prompt,
+                model_override, token_budget, language, metadata, style_preset, color, icon, persona_id, title_model_config_id, single_turn
+            FROM conversations WHERE id = ?
+            "#,
+        )
+        .bind(&new_id_text)
+        .bind(title)
+        .bind(now_ts)
+        .bind(now_ts)
+        .bind(&source_id_text)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to create the split-off conversation")?
+        .rows_affected();
+        if created == 0 {
+            return Err(anyhow::anyhow!("Conversation {} not found", source_id));
+        }
+
+        let moved_ids: Vec<String> =
+            sqlx::query_scalar("SELECT id FROM messages WHERE conversation_id = ? AND seq >= ? ORDER BY seq ASC")
+                .bind(&source_id_text)
+                .bind(split_seq)
+                .fetch_all(&mut *tx)
+                .await
+                .context("Failed to list the messages to split off")?;
+        let moved = sqlx::query("UPDATE messages SET conversation_id = ? WHERE conversation_id = ? AND seq >= ?")
+            .bind(&new_id_text)
+            .bind(&source_id_text)
+            .bind(split_seq)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to move messages into the split-off conversation")?
+            .rows_affected();
+
+        if let Some(seed) = seed {
+            // Dated just before the first moved message, and renumbered ahead of them
+            let timestamp = chrono::DateTime::from_timestamp(split_ts - 1, 0).unwrap_or(seed.timestamp);
+            Self::insert_messages(&mut tx, &[Message { conversation_id: new_id, timestamp, ..seed }]).await?;
+            Self::resequence_messages(&mut tx, &moved_ids).await?;
+        }
+
+        // A cached summary reaching into the moved messages no longer describes the source
+        sqlx::query(
+            "DELETE FROM conversation_memory WHERE conversation_id = ? AND last_message_id NOT IN (SELECT id FROM messages WHERE conversation_id = ?)",
+        )
+        .bind(&source_id_text)
+        .bind(&source_id_text)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to clear the source conversation's memory")?;
+        sqlx::query("UPDATE conversations SET last_updated_at = ? WHERE id = ?")
+            .bind(now_ts)
+            .bind(&source_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update source conversation timestamp")?;
+
+        tx.commit().await.context("Failed to commit split transaction")?;
+        log::info!("[STORAGE] Moved {} messages from conversation {} into {}", moved, source_id, new_id);
+        Ok(new_id)
+    }
+
+    /// Saves a single message to the database. A message whose ID is already stored is
+    /// updated in place and keeps its position, so a streamed answer can be saved repeatedly.
+    pub async fn save_message(&self, message: &Message) -> Result<(), anyhow::Error> {
+        log::debug!("Saving message ID: {}", message.id);
+        let truncated = self.message_size_limit().await?.apply(message)?;
+        let message = truncated.as_ref().unwrap_or(message);
+
+        let id_text = message.id.to_string();
+        let conversation_id_text = message.conversation_id.to_string();
+        let timestamp_ts = message.timestamp.timestamp();
+        let variant_group_text = message.variant_group.map(|group| group.to_string());
+
+        sqlx::query!(
+            r#"
+            INSERT INTO messages (id, conversation_id, role, content, timestamp, metadata, name, variant_group, seq)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, (SELECT COALESCE(MAX(seq), 0) + 1 FROM messages))
+            ON CONFLICT(id) DO UPDATE SET
+                content = excluded.content,
+                timestamp = excluded.timestamp,
+                metadata = excluded.metadata,
+                name = excluded.name,
+                variant_group = excluded.variant_group
+            "#,
+            id_text,
+            conversation_id_text,
+            message.role,
+            message.content,
+            timestamp_ts,
+            message.metadata, // Already Option<String>
+            message.name,
+            variant_group_text
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to save message to database")?;
+
+        // Also update the conversation's last_updated_at timestamp
+        let update_conv_ts = Utc::now().timestamp();
+        sqlx::query!(
+            "UPDATE conversations SET last_updated_at = ? WHERE id = ?",
+            update_conv_ts,
+            conversation_id_text
+        )
         .execute(&self.pool)
         .await
         .context("Failed to update conversation last_updated_at timestamp")?;
@@ -343,6 +1549,331 @@ impl StorageManager {
         Ok(())
     }
 
+    /// The size guard `save_message` applies, from settings. Malformed values fall back to the defaults.
+    pub async fn message_size_limit(&self) -> Result<MessageSizeLimit, anyhow::Error> {
+        let max_chars = match self.get_setting(crate::config::MAX_MESSAGE_CHARS_KEY).await? {
+            Some(value) => value.parse::<usize>().unwrap_or(crate::config::DEFAULT_MAX_MESSAGE_CHARS),
+            None => crate::config::DEFAULT_MAX_MESSAGE_CHARS,
+        };
+        let policy = self.get_setting(crate::config::OVERSIZED_MESSAGE_POLICY_KEY).await?
+            .and_then(|value| OversizedMessagePolicy::parse(&value).ok())
+            .unwrap_or(OversizedMessagePolicy::Truncate);
+        Ok(MessageSizeLimit { max_chars: Some(max_chars).filter(|max| *max > 0), policy })
+    }
+
+    /// Saves many messages in one transaction, bumping each affected conversation's
+    /// `last_updated_at` once at the end. Use for imports and other bulk writes.
+    pub async fn save_messages(&self, messages: &[Message]) -> Result<(), anyhow::Error> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        log::debug!("Saving {} messages in bulk", messages.len());
+
+        let mut tx = self.pool.begin().await.context("Failed to begin bulk insert transaction")?;
+        Self::insert_messages(&mut tx, messages).await?;
+
+        let update_conv_ts = Utc::now().timestamp();
+        let mut conversation_ids: Vec<String> = messages.iter().map(|m| m.conversation_id.to_string()).collect();
+        conversation_ids.sort();
+        conversation_ids.dedup();
+        for conversation_id_text in conversation_ids {
+            sqlx::query!(
+                "UPDATE conversations SET last_updated_at = ? WHERE id = ?",
+                update_conv_ts,
+                conversation_id_text
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update conversation last_updated_at timestamp")?;
+        }
+        tx.commit().await.context("Failed to commit bulk insert transaction")?;
+
+        log::info!("Successfully saved {} messages", messages.len());
+        Ok(())
+    }
+
+    // Inserts message rows within an open transaction (no conversation timestamp update)
+    async fn insert_messages(tx: &mut Transaction<'_, Sqlite>, messages: &[Message]) -> Result<(), anyhow::Error> {
+        for message in messages {
+            let id_text = message.id.to_string();
+            let conversation_id_text = message.conversation_id.to_string();
+            let timestamp_ts = message.timestamp.timestamp();
+            let variant_group_text = message.variant_group.map(|group| group.to_string());
+            sqlx::query!(
+                r#"
+                INSERT INTO messages (id, conversation_id, role, content, timestamp, metadata, name, variant_group, seq)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, (SELECT COALESCE(MAX(seq), 0) + 1 FROM messages))
+                "#,
+                id_text,
+                conversation_id_text,
+                message.role,
+                message.content,
+                timestamp_ts,
+                message.metadata,
+                message.name,
+                variant_group_text
+            )
+            .execute(&mut **tx)
+            .await
+            .context("Failed to insert message into database")?;
+        }
+        Ok(())
+    }
+
+    // Gives `message_ids` the next sequence numbers in the listed order, so they sort after
+    // every stored message
+    async fn resequence_messages(tx: &mut Transaction<'_, Sqlite>, message_ids: &[String]) -> Result<(), anyhow::Error> {
+        for id in message_ids {
+            sqlx::query("UPDATE messages SET seq = (SELECT COALESCE(MAX(seq), 0) + 1 FROM messages) WHERE id = ?")
+                .bind(id)
+                .execute(&mut **tx)
+                .await
+                .context("Failed to renumber messages")?;
+        }
+        Ok(())
+    }
+
+    /// Fetches a single message by its ID.
+    pub async fn get_message(&self, message_id: Uuid) -> Result<Option<Message>, anyhow::Error> {
+        let id_text = message_id.to_string();
+        log::debug!("Fetching message with ID: {}", id_text);
+
+        let row = sqlx::query!(
+            r#"
+            SELECT id, conversation_id, role, content, timestamp, metadata, name, variant_group
+            FROM messages
+            WHERE id = ?
+            "#,
+            id_text
+        )
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to fetch message from database")?;
+
+        match row {
+            Some(r) => Ok(Some(Message {
+                id: uuid::Uuid::parse_str(&r.id).context("Failed to parse message ID")?,
+                conversation_id: uuid::Uuid::parse_str(&r.conversation_id)
+                    .context("Failed to parse conversation ID for message")?,
+                role: r.role,
+                content: r.content,
+                timestamp: chrono::DateTime::from_timestamp(r.timestamp, 0)
+                    .context("Invalid message timestamp")?,
+                metadata: r.metadata,
+                name: r.name,
+                variant_group: parse_variant_group(r.variant_group)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Every column of the stored row for `message_id`, including `rowid`, exactly as SQLite
+    /// holds it: no JSON parsing of `metadata` and the timestamp as raw Unix seconds.
+    pub async fn get_message_row(&self, message_id: Uuid) -> Result<Option<Vec<RawColumn>>, anyhow::Error> {
+        let row = sqlx::query("SELECT rowid, * FROM messages WHERE id = ?")
+            .bind(message_id.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to fetch message row from database")?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        row.columns()
+            .iter()
+            .map(|column| {
+                let index = column.ordinal();
+                let raw = row.try_get_raw(index)?;
+                let (sqlite_type, value) = if raw.is_null() {
+                    ("NULL".to_string(), serde_json::Value::Null)
+                } else {
+                    let sqlite_type = raw.type_info().name().to_string();
+                    let value = match sqlite_type.as_str() {
+                        "INTEGER" => serde_json::json!(row.try_get::<i64, _>(index)?),
+                        "REAL" => serde_json::json!(row.try_get::<f64, _>(index)?),
+                        "BLOB" => serde_json::json!(row.try_get::<Vec<u8>, _>(index)?
+                            .iter()
+                            .map(|byte| format!("{:02x}", byte))
+                            .collect::<String>()),
+                        _ => serde_json::json!(row.try_get::<String, _>(index)?),
+                    };
+                    (sqlite_type, value)
+                };
+                Ok(RawColumn { name: column.name().to_string(), sqlite_type, value })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()
+            .map(Some)
+            .context("Failed to read message row")
+    }
+
+    /// Puts a message into variant group `group`, alongside the other answers to its turn.
+    pub async fn set_message_variant_group(&self, message_id: Uuid, group: Uuid) -> Result<(), anyhow::Error> {
+        sqlx::query("UPDATE messages SET variant_group = ? WHERE id = ?")
+            .bind(group.to_string())
+            .bind(message_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context(format!("Failed to add message {} to variant group {}", message_id, group))?;
+        Ok(())
+    }
+
+    /// Marks `message_id` as the selected answer of variant group `group` (`variant_selected`
+    /// in its metadata) and every other member as unselected. Copies of the group in forked
+    /// conversations are left alone.
+    pub async fn select_message_variant(&self, group: Uuid, message_id: Uuid) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            "UPDATE messages
+            SET metadata = json_set(COALESCE(metadata, '{}'), '$.variant_selected', json(CASE WHEN id = ? THEN 'true' ELSE 'false' END))
+            WHERE variant_group = ? AND conversation_id = (SELECT conversation_id FROM messages WHERE id = ?)",
+        )
+        .bind(message_id.to_string())
+        .bind(group.to_string())
+        .bind(message_id.to_string())
+        .execute(&self.pool)
+        .await
+        .context(format!("Failed to select message {} in variant group {}", message_id, group))?;
+        Ok(())
+    }
+
+    /// Message counts for every live conversation (zero included), in a single query.
+    pub async fn get_conversation_message_counts(&self) -> Result<HashMap<Uuid, i64>, anyhow::Error> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT c.id as "id!", COUNT(m.id) as "count!: i64"
+            FROM conversations c
+            LEFT JOIN messages m ON m.conversation_id = c.id
+            WHERE c.deleted_at IS NULL
+            GROUP BY c.id
+            "#
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to count messages per conversation")?;
+
+        rows.into_iter()
+            .map(|row| Ok((Uuid::parse_str(&row.id).context("Failed to parse conversation ID")?, row.count)))
+            .collect()
+    }
+
+    /// Replaces the metadata JSON of an existing message.
+    pub async fn update_message_metadata(
+        &self,
+        message_id: Uuid,
+        metadata: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let id_text = message_id.to_string();
+        let result = sqlx::query!("UPDATE messages SET metadata = ? WHERE id = ?", metadata, id_text)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update message metadata in database")?;
+
+        if result.rows_affected() == 0 {
+            log::warn!("Attempted to update metadata of non-existent message: {}", message_id);
+            return Err(anyhow::anyhow!("Message not found for updating."));
+        }
+        Ok(())
+    }
+
+    /// Replaces the metadata JSON of several messages in one transaction. Messages that no
+    /// longer exist are skipped.
+    pub async fn update_messages_metadata(&self, updates: &[(Uuid, Option<String>)]) -> Result<(), anyhow::Error> {
+        let mut tx = self.pool.begin().await.context("Failed to start transaction")?;
+        for (message_id, metadata) in updates {
+            sqlx::query("UPDATE messages SET metadata = ? WHERE id = ?")
+                .bind(metadata)
+                .bind(message_id.to_string())
+                .execute(&mut *tx)
+                .await
+                .context("Failed to update message metadata in database")?;
+        }
+        tx.commit().await.context("Failed to commit message metadata")?;
+        Ok(())
+    }
+
+    /// Changes the role of an existing message, leaving everything else as it is. Returns the
+    /// message's conversation.
+    pub async fn set_message_role(&self, message_id: Uuid, role: &str) -> Result<Uuid, anyhow::Error> {
+        let id_text = message_id.to_string();
+        self.invalidate_memory_covering(message_id).await?;
+
+        let mut tx = self.pool.begin().await.context("Failed to begin message role transaction")?;
+        let previous: Option<(String, String)> = sqlx::query_as("SELECT conversation_id, role FROM messages WHERE id = ?")
+            .bind(&id_text)
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to read message before changing its role")?;
+        let Some((conversation_id_text, previous_role)) = previous else {
+            return Err(anyhow::anyhow!("Message not found for changing its role."));
+        };
+        let conversation_id = Uuid::parse_str(&conversation_id_text).context("Failed to parse conversation ID for message")?;
+        if previous_role == role {
+            return Ok(conversation_id);
+        }
+        sqlx::query("UPDATE messages SET role = ? WHERE id = ?")
+            .bind(role)
+            .bind(&id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update message role in database")?;
+        Self::record_event(
+            &mut tx,
+            conversation_id,
+            changelog::EVENT_MESSAGE_ROLE,
+            Some(serde_json::json!({ "messageId": id_text, "role": previous_role })),
+            Some(serde_json::json!({ "messageId": id_text, "role": role })),
+        )
+        .await?;
+        tx.commit().await.context("Failed to commit message role")?;
+        log::info!("Changed role of message {} from {} to {}", message_id, previous_role, role);
+        Ok(conversation_id)
+    }
+
+    /// Replaces the content and metadata of an existing message (e.g. after a continuation).
+    pub async fn update_message_content(
+        &self,
+        message_id: Uuid,
+        content: &str,
+        metadata: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        log::debug!("Updating content of message ID: {}", message_id);
+        let id_text = message_id.to_string();
+        self.invalidate_memory_covering(message_id).await?;
+
+        let mut tx = self.pool.begin().await.context("Failed to begin message update transaction")?;
+        let previous: Option<(String, String, Option<String>)> =
+            sqlx::query_as("SELECT conversation_id, content, metadata FROM messages WHERE id = ?")
+                .bind(&id_text)
+                .fetch_optional(&mut *tx)
+                .await
+                .context("Failed to read message before updating")?;
+        let Some((conversation_id_text, previous_content, previous_metadata)) = previous else {
+            log::warn!("Attempted to update non-existent message: {}", message_id);
+            return Err(anyhow::anyhow!("Message not found for updating."));
+        };
+
+        sqlx::query!(
+            "UPDATE messages SET content = ?, metadata = ? WHERE id = ?",
+            content,
+            metadata,
+            id_text
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to update message content in database")?;
+
+        Self::record_event(
+            &mut tx,
+            Uuid::parse_str(&conversation_id_text).context("Failed to parse conversation ID for message")?,
+            changelog::EVENT_MESSAGE_EDIT,
+            Some(serde_json::json!({ "messageId": id_text, "content": previous_content, "metadata": previous_metadata })),
+            Some(serde_json::json!({ "messageId": id_text, "content": content, "metadata": metadata })),
+        )
+        .await?;
+        tx.commit().await.context("Failed to commit message update")?;
+
+        log::info!("Successfully updated message {}", message_id);
+        Ok(())
+    }
+
     /// Renames a conversation.
     pub async fn rename_conversation(
         &self,
@@ -358,24 +1889,41 @@ impl StorageManager {
 
         let update_conv_ts = Utc::now().timestamp();
 
-        let result = sqlx::query!(
+        let mut tx = self.pool.begin().await.context("Failed to begin rename transaction")?;
+        let previous_title: Option<String> = sqlx::query_scalar("SELECT title FROM conversations WHERE id = ?")
+            .bind(&conversation_id_text)
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to read conversation title")?;
+        let Some(previous_title) = previous_title else {
+            log::warn!(
+                "Attempted to rename non-existent conversation: {}",
+                conversation_id
+            );
+            return Err(anyhow::anyhow!("Conversation not found for renaming."));
+        };
+
+        sqlx::query!(
             "UPDATE conversations SET title = ?, last_updated_at = ? WHERE id = ?",
             new_title,
             update_conv_ts,
             conversation_id_text
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .context("Failed to update conversation title in database")?;
 
-        if result.rows_affected() == 0 {
-            log::warn!(
-                "Attempted to rename non-existent conversation: {}",
-                conversation_id
-            );
-            // Consider returning an error or just logging
-            return Err(anyhow::anyhow!("Conversation not found for renaming."));
+        if previous_title != new_title {
+            Self::record_event(
+                &mut tx,
+                conversation_id,
+                changelog::EVENT_RENAME,
+                Some(serde_json::json!({ "title": previous_title })),
+                Some(serde_json::json!({ "title": new_title })),
+            )
+            .await?;
         }
+        tx.commit().await.context("Failed to commit rename")?;
 
         log::info!("Successfully renamed conversation {}", conversation_id);
         Ok(())
@@ -388,7 +1936,7 @@ impl StorageManager {
 
         let row = sqlx::query!(
             r#"
-            SELECT id, title, created_at, last_updated_at, model_config_id
+            SELECT id, title, created_at, last_updated_at, model_config_id, system_prompt, deleted_at, ephemeral, model_override, token_budget, language, locked, style_preset, color, icon, persona_id, title_model_config_id, single_turn
             FROM conversations
             WHERE id = ?
             "#,
@@ -409,6 +1957,25 @@ impl StorageManager {
                         .context("Invalid last_updated_at timestamp")?,
                     model_config_id: uuid::Uuid::parse_str(&r.model_config_id)
                         .context("Failed to parse model_config_id")?,
+                    system_prompt: r.system_prompt,
+                    deleted_at: r.deleted_at
+                        .map(|ts| chrono::DateTime::from_timestamp(ts, 0).context("Invalid deleted_at timestamp"))
+                        .transpose()?,
+                    ephemeral: r.ephemeral != 0,
+                    model_override: r.model_override,
+                    token_budget: r.token_budget,
+                    language: r.language,
+                    locked: r.locked != 0,
+                    style_preset: r.style_preset,
+                    color: r.color,
+                    icon: r.icon,
+                    persona_id: r.persona_id
+                        .map(|id| Uuid::parse_str(&id).context("Failed to parse persona_id"))
+                        .transpose()?,
+                    title_model_config_id: r.title_model_config_id
+                        .map(|id| Uuid::parse_str(&id).context("Failed to parse title_model_config_id"))
+                        .transpose()?,
+                    single_turn: r.single_turn != 0,
                 };
                 Ok(Some(conversation))
             }
@@ -417,6 +1984,7 @@ impl StorageManager {
     }
 
     /// Updates the model config ID for a specific conversation.
+    /// Any model override is cleared, since it named a model of the previous config.
     pub async fn update_conversation_model_id(
         &self,
         conversation_id: Uuid,
@@ -431,26 +1999,43 @@ impl StorageManager {
             model_id_text
         );
 
-        let result = sqlx::query!(
-            r#"
+        let mut tx = self.pool.begin().await.context("Failed to begin model update transaction")?;
+        let previous: Option<(String, Option<String>)> =
+            sqlx::query_as("SELECT model_config_id, model_override FROM conversations WHERE id = ?")
+                .bind(&conversation_id_text)
+                .fetch_optional(&mut *tx)
+                .await
+                .context("Failed to read conversation model")?;
+        let Some((previous_model_id, previous_override)) = previous else {
+            log::warn!("Attempted to update model for non-existent conversation: {}", conversation_id);
+            return Err(anyhow::anyhow!("Conversation with ID {} not found for model update", conversation_id));
+        };
+
+        sqlx::query!(
+            r#"
             UPDATE conversations 
-            SET model_config_id = ?, last_updated_at = ?
+            SET model_config_id = ?, model_override = NULL, last_updated_at = ?
             WHERE id = ?
             "#,
             model_id_text,
             update_ts,
             conversation_id_text
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .context("Failed to update conversation model ID in database")?;
 
-        if result.rows_affected() == 0 {
-            log::warn!("Attempted to update model for non-existent conversation: {}", conversation_id);
-            // Consider returning an error or just logging
-            // For now, let's return an error to be safe
-            return Err(anyhow::anyhow!("Conversation with ID {} not found for model update", conversation_id));
+        if previous_model_id != model_id_text || previous_override.is_some() {
+            Self::record_event(
+                &mut tx,
+                conversation_id,
+                changelog::EVENT_MODEL_CHANGE,
+                Some(serde_json::json!({ "modelConfigId": previous_model_id, "modelOverride": previous_override })),
+                Some(serde_json::json!({ "modelConfigId": model_id_text, "modelOverride": null })),
+            )
+            .await?;
         }
+        tx.commit().await.context("Failed to commit model update")?;
 
         log::info!("Successfully updated model for conversation {}", conversation_id);
         Ok(())
@@ -462,9 +2047,9 @@ impl StorageManager {
 
         let rows = sqlx::query!(
             r#"
-            SELECT id, name, provider, api_url, api_key_ref, provider_options
+            SELECT id, name, provider, api_url, api_key_ref, provider_options, sort_order, is_default
             FROM model_configs
-            ORDER BY name ASC
+            ORDER BY sort_order ASC, name ASC
             "#
         )
         .fetch_all(&self.pool)
@@ -482,6 +2067,8 @@ impl StorageManager {
                     api_url: row.api_url,
                     api_key_ref: row.api_key_ref,
                     provider_options: row.provider_options,
+                    sort_order: row.sort_order,
+                    is_default: row.is_default != 0,
                 })
             })
             .collect::<Result<Vec<ModelConfig>, anyhow::Error>>()?;
@@ -490,32 +2077,126 @@ impl StorageManager {
         Ok(configs)
     }
 
-    /// Adds a new model configuration to the database.
+    /// Adds a new model configuration to the database, placed at the end of the list.
+    /// If it is flagged default, the previous default is cleared in the same transaction.
     pub async fn add_model_config(&self, config: &ModelConfig) -> Result<(), anyhow::Error> {
         log::info!("Adding new model config: {}", config.name);
         let id_text = config.id.to_string();
 
+        let mut tx = self.pool.begin().await.context("Failed to begin model config transaction")?;
+        let sort_order = sqlx::query!(
+            r#"SELECT COALESCE(MAX(sort_order) + 1, 0) as "next!: i64" FROM model_configs"#
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .context("Failed to compute model config sort order")?
+        .next;
+        if config.is_default {
+            sqlx::query!("UPDATE model_configs SET is_default = 0 WHERE is_default != 0")
+                .execute(&mut *tx)
+                .await
+                .context("Failed to clear previous default model config")?;
+        }
+
         sqlx::query!(
             r#"
-            INSERT INTO model_configs (id, name, provider, api_url, api_key_ref, provider_options)
-            VALUES (?, ?, ?, ?, ?, ?)
+            INSERT INTO model_configs (id, name, provider, api_url, api_key_ref, provider_options, sort_order, is_default)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
             "#,
             id_text,
             config.name,
             config.provider,
             config.api_url,
             config.api_key_ref,
-            config.provider_options
+            config.provider_options,
+            sort_order,
+            config.is_default
         )
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .context("Failed to insert new model config into database")?;
+        tx.commit().await.context("Failed to commit model config transaction")?;
 
         log::info!("Successfully added model config with ID: {}", config.id);
         Ok(())
     }
 
+    /// Puts a conversation's messages in `ordered_ids` order, which must list every one of
+    /// them exactly once. Only `seq` changes: the messages trade their sequence numbers, so
+    /// the message at position i takes the i-th lowest. Timestamps are left as sent.
+    pub async fn reorder_messages(&self, conversation_id: Uuid, ordered_ids: &[Uuid]) -> Result<(), anyhow::Error> {
+        log::info!("Reordering {} messages in conversation {}", ordered_ids.len(), conversation_id);
+        let conversation_id_text = conversation_id.to_string();
+        let mut tx = self.pool.begin().await.context("Failed to begin reorder transaction")?;
+        let slots: Vec<(String, i64)> = sqlx::query_as("SELECT id, seq FROM messages WHERE conversation_id = ? ORDER BY seq ASC")
+            .bind(&conversation_id_text)
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to load message order")?;
+        let stored: HashSet<&str> = slots.iter().map(|(id, _)| id.as_str()).collect();
+        let listed: HashSet<String> = ordered_ids.iter().map(Uuid::to_string).collect();
+        if listed.len() != ordered_ids.len() || listed.len() != stored.len() || !listed.iter().all(|id| stored.contains(id.as_str())) {
+            return Err(anyhow::anyhow!("The new order must list each message of the conversation exactly once."));
+        }
+
+        for (message_id, (_, seq)) in ordered_ids.iter().zip(&slots) {
+            sqlx::query("UPDATE messages SET seq = ? WHERE id = ?")
+                .bind(seq)
+                .bind(message_id.to_string())
+                .execute(&mut *tx)
+                .await
+                .context("Failed to update message order")?;
+        }
+        tx.commit().await.context("Failed to commit reorder transaction")?;
+        Ok(())
+    }
+
+    /// Rewrites `sort_order` to follow `ordered_ids`. Every id must exist.
+    pub async fn reorder_model_configs(&self, ordered_ids: &[Uuid]) -> Result<(), anyhow::Error> {
+        log::info!("Reordering {} model configs", ordered_ids.len());
+        let mut tx = self.pool.begin().await.context("Failed to begin reorder transaction")?;
+        for (position, config_id) in ordered_ids.iter().enumerate() {
+            let id_text = config_id.to_string();
+            let position = position as i64;
+            let result = sqlx::query!(
+                "UPDATE model_configs SET sort_order = ? WHERE id = ?",
+                position,
+                id_text
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update model config sort order")?;
+            if result.rows_affected() == 0 {
+                // Dropping the transaction rolls back the positions written so far
+                return Err(anyhow::anyhow!("Model config {} not found for reordering.", config_id));
+            }
+        }
+        tx.commit().await.context("Failed to commit reorder transaction")?;
+        Ok(())
+    }
+
+    /// Makes `config_id` the default model config, clearing the flag everywhere else.
+    pub async fn set_default_model_config(&self, config_id: Uuid) -> Result<(), anyhow::Error> {
+        log::info!("Setting default model config: {}", config_id);
+        let id_text = config_id.to_string();
+        let mut tx = self.pool.begin().await.context("Failed to begin default config transaction")?;
+        sqlx::query!("UPDATE model_configs SET is_default = 0 WHERE is_default != 0")
+            .execute(&mut *tx)
+            .await
+            .context("Failed to clear previous default model config")?;
+        let result = sqlx::query!("UPDATE model_configs SET is_default = 1 WHERE id = ?", id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to set default model config")?;
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Model config not found for setting default."));
+        }
+        tx.commit().await.context("Failed to commit default config transaction")?;
+        Ok(())
+    }
+
     /// Updates an existing model configuration.
+    /// `sort_order` and `is_default` are managed by their own methods and left untouched.
     pub async fn update_model_config(&self, config: &ModelConfig) -> Result<(), anyhow::Error> {
         let id_text = config.id.to_string();
         log::info!("Updating model config: {} ({})", config.name, id_text);
@@ -546,6 +2227,39 @@ impl StorageManager {
         Ok(())
     }
 
+    /// Renames a model configuration, reporting a clash with an existing name as a readable error.
+    pub async fn rename_model_config(&self, config_id: Uuid, new_name: &str) -> Result<(), anyhow::Error> {
+        let id_text = config_id.to_string();
+        log::info!("Renaming model config {} to '{}'", id_text, new_name);
+
+        let taken = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM model_configs WHERE name = ? AND id != ?"#,
+            new_name,
+            id_text
+        )
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to check model config names")?;
+        if taken.count > 0 {
+            return Err(anyhow::anyhow!("A model config named '{}' already exists.", new_name));
+        }
+
+        let result = sqlx::query!("UPDATE model_configs SET name = ? WHERE id = ?", new_name, id_text)
+            .execute(&self.pool)
+            .await
+            .map_err(|e| match &e {
+                sqlx::Error::Database(db) if db.is_unique_violation() => {
+                    anyhow::anyhow!("A model config named '{}' already exists.", new_name)
+                }
+                _ => anyhow::Error::new(e).context("Failed to rename model config"),
+            })?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Model config not found for renaming."));
+        }
+        Ok(())
+    }
+
     /// Deletes a model configuration.
     /// Note: This does NOT currently prevent deleting a config that is in use by conversations.
     /// Consider adding checks or constraints later.
@@ -553,8 +2267,9 @@ impl StorageManager {
         let id_text = config_id.to_string();
         log::warn!("Deleting model config with ID: {}", id_text);
 
+        let mut tx = self.pool.begin().await.context("Failed to begin model config transaction")?;
         let result = sqlx::query!("DELETE FROM model_configs WHERE id = ?", id_text)
-            .execute(&self.pool)
+            .execute(&mut *tx)
             .await
             .context("Failed to delete model config from database")?;
 
@@ -563,6 +2278,19 @@ impl StorageManager {
             // Don't error if not found, just log
         }
 
+        // Keep a default around: promote the first remaining config if the default was removed
+        sqlx::query!(
+            r#"
+            UPDATE model_configs SET is_default = 1
+            WHERE id = (SELECT id FROM model_configs ORDER BY sort_order ASC, name ASC LIMIT 1)
+              AND NOT EXISTS (SELECT 1 FROM model_configs WHERE is_default != 0)
+            "#
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to promote a new default model config")?;
+        tx.commit().await.context("Failed to commit model config transaction")?;
+
         log::info!("Successfully deleted model config {}", id_text);
         Ok(())
     }
@@ -602,12 +2330,24 @@ impl StorageManager {
     pub async fn delete_message(&self, message_id: Uuid) -> Result<(), anyhow::Error> {
         log::warn!("Deleting message with ID: {}", message_id);
         let message_id_text = message_id.to_string();
+        self.invalidate_memory_covering(message_id).await?;
+
+        let mut tx = self.pool.begin().await.context("Failed to begin message delete transaction")?;
+        let row = sqlx::query(
+            "SELECT id, conversation_id, role, content, timestamp, metadata, name, variant_group, seq FROM messages WHERE id = ?"
+        )
+        .bind(&message_id_text)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to read message before deleting")?;
+        let deleted = row.as_ref().map(message_from_row).transpose()?;
+        let deleted_seq: Option<i64> = row.as_ref().and_then(|row| row.try_get("seq").ok());
 
         let result = sqlx::query(
             "DELETE FROM messages WHERE id = ?"
         )
         .bind(message_id_text)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await
         .context(format!("Failed to delete message with ID: {}", message_id))?;
 
@@ -616,12 +2356,2380 @@ impl StorageManager {
             // Depending on requirements, you might return an error here or just log it.
             // For regeneration, proceeding might be okay if the message was already gone.
         }
+        if let Some(deleted) = deleted {
+            // The newest message left tells undo whether anything was added since
+            let latest_seq: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(seq), 0) FROM messages WHERE conversation_id = ?")
+                .bind(deleted.conversation_id.to_string())
+                .fetch_one(&mut *tx)
+                .await
+                .context("Failed to read the latest message sequence number")?;
+            Self::record_event(
+                &mut tx,
+                deleted.conversation_id,
+                changelog::EVENT_MESSAGE_DELETE,
+                Some(serde_json::to_value(&deleted).context("Failed to serialize deleted message")?),
+                Some(serde_json::json!({ "latestSeq": latest_seq, "seq": deleted_seq })),
+            )
+            .await?;
+        }
+        tx.commit().await.context("Failed to commit message delete")?;
 
         log::info!("Successfully deleted message {} (or it didn't exist).", message_id);
         Ok(())
     }
 
-    pub fn pool(&self) -> &SqlitePool {
-        &self.pool // Make the pool accessible if needed elsewhere (removes dead code warning for pool)
+    /// Sets (or clears, with `None`) the system prompt of a conversation.
+    pub async fn set_conversation_system_prompt(
+        &self,
+        conversation_id: Uuid,
+        system_prompt: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        log::info!("Updating system prompt for conversation {}", conversation_id_text);
+
+        let result = sqlx::query!(
+            "UPDATE conversations SET system_prompt = ? WHERE id = ?",
+            system_prompt,
+            conversation_id_text
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update conversation system prompt in database")?;
+
+        if result.rows_affected() == 0 {
+            log::warn!("Attempted to set system prompt on non-existent conversation: {}", conversation_id);
+            return Err(anyhow::anyhow!("Conversation not found for system prompt update."));
+        }
+        Ok(())
+    }
+
+    /// Sets (or clears, with `None`) the model name a conversation uses in place of its config's default.
+    pub async fn set_conversation_model_override(
+        &self,
+        conversation_id: Uuid,
+        model_override: Option<String>,
+    ) -> Result<(), anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        log::info!("Setting model override {:?} for conversation {}", model_override, conversation_id_text);
+
+        let result = sqlx::query!(
+            "UPDATE conversations SET model_override = ? WHERE id = ?",
+            model_override,
+            conversation_id_text
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update conversation model override in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for model update."));
+        }
+        Ok(())
+    }
+
+    /// Sets (or clears, with `None`) a conversation's token budget.
+    pub async fn set_conversation_token_budget(
+        &self,
+        conversation_id: Uuid,
+        token_budget: Option<i64>,
+    ) -> Result<(), anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        log::info!("Setting token budget {:?} for conversation {}", token_budget, conversation_id_text);
+
+        let result = sqlx::query("UPDATE conversations SET token_budget = ? WHERE id = ?")
+            .bind(token_budget)
+            .bind(&conversation_id_text)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation token budget in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for token budget update."));
+        }
+        Ok(())
+    }
+
+    /// Sidebar summary of a conversation, or None when it doesn't exist.
+    /// `preview_chars` caps the length of the latest message's preview.
+    pub async fn get_conversation_summary(
+        &self,
+        conversation_id: Uuid,
+        preview_chars: usize,
+    ) -> Result<Option<ConversationSummary>, anyhow::Error> {
+        let Some(conversation) = self.get_conversation(conversation_id).await? else {
+            return Ok(None);
+        };
+        let conversation_id_text = conversation_id.to_string();
+        let message_count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages WHERE conversation_id = ?")
+            .bind(&conversation_id_text)
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to count conversation messages")?;
+        let last: Option<(String, String)> = sqlx::query_as(
+            "SELECT role, content FROM messages WHERE conversation_id = ? ORDER BY seq DESC LIMIT 1",
+        )
+        .bind(&conversation_id_text)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to fetch latest conversation message")?;
+
+        let (last_message_role, last_message_preview) = match last {
+            Some((role, content)) => (Some(role), Some(message_preview(&content, preview_chars))),
+            None => (None, None),
+        };
+        Ok(Some(ConversationSummary { conversation, message_count, last_message_role, last_message_preview }))
+    }
+
+    /// Sets (or clears, with `None`) a conversation's language code.
+    pub async fn set_conversation_language(
+        &self,
+        conversation_id: Uuid,
+        language: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        log::info!("Setting language {:?} for conversation {}", language, conversation_id_text);
+
+        let result = sqlx::query("UPDATE conversations SET language = ? WHERE id = ?")
+            .bind(language)
+            .bind(&conversation_id_text)
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation language in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for language update."));
+        }
+        Ok(())
+    }
+
+    /// Sets (or clears, with `None`) a conversation's answer style preset.
+    pub async fn set_conversation_style_preset(
+        &self,
+        conversation_id: Uuid,
+        style_preset: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        log::info!("Setting style preset {:?} for conversation {}", style_preset, conversation_id);
+        let result = sqlx::query("UPDATE conversations SET style_preset = ? WHERE id = ?")
+            .bind(style_preset)
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation style preset in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for style preset update."));
+        }
+        Ok(())
+    }
+
+    /// Sets a conversation's sidebar color and icon; `None` clears either.
+    pub async fn set_conversation_appearance(
+        &self,
+        conversation_id: Uuid,
+        color: Option<&str>,
+        icon: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        log::info!("Setting appearance {:?}/{:?} for conversation {}", color, icon, conversation_id);
+        let result = sqlx::query("UPDATE conversations SET color = ?, icon = ? WHERE id = ?")
+            .bind(color)
+            .bind(icon)
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation appearance in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for appearance update."));
+        }
+        Ok(())
+    }
+
+    /// Sets (or clears, with `None`) the persona a conversation's requests use.
+    pub async fn set_conversation_persona(&self, conversation_id: Uuid, persona_id: Option<Uuid>) -> Result<(), anyhow::Error> {
+        log::info!("Setting persona {:?} for conversation {}", persona_id, conversation_id);
+        let result = sqlx::query("UPDATE conversations SET persona_id = ? WHERE id = ?")
+            .bind(persona_id.map(|id| id.to_string()))
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation persona in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for persona update."));
+        }
+        Ok(())
+    }
+
+    /// Sets (or with None clears) the model config that generates this conversation's titles.
+    pub async fn set_conversation_title_model(&self, conversation_id: Uuid, config_id: Option<Uuid>) -> Result<(), anyhow::Error> {
+        log::info!("Setting title model {:?} for conversation {}", config_id, conversation_id);
+        let result = sqlx::query("UPDATE conversations SET title_model_config_id = ? WHERE id = ?")
+            .bind(config_id.map(|id| id.to_string()))
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation title model in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for title model update."));
+        }
+        Ok(())
+    }
+
+    /// A conversation's metadata object, empty when none is set. None if the conversation doesn't exist.
+    pub async fn get_conversation_metadata(
+        &self,
+        conversation_id: Uuid,
+    ) -> Result<Option<serde_json::Map<String, serde_json::Value>>, anyhow::Error> {
+        let row: Option<(Option<String>,)> = sqlx::query_as("SELECT metadata FROM conversations WHERE id = ?")
+            .bind(conversation_id.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to read conversation metadata")?;
+        let Some((metadata,)) = row else {
+            return Ok(None);
+        };
+        match metadata {
+            Some(json) => serde_json::from_str(&json).map(Some).context("Invalid conversation metadata"),
+            None => Ok(Some(serde_json::Map::new())),
+        }
+    }
+
+    /// Merges `update` into a conversation's metadata and returns the result. Keys missing from
+    /// `update` are kept and null values remove keys (JSON merge patch, so nested objects merge too).
+    pub async fn merge_conversation_metadata(
+        &self,
+        conversation_id: Uuid,
+        update: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<serde_json::Map<String, serde_json::Value>, anyhow::Error> {
+        log::info!("Merging metadata keys {:?} into conversation {}", update.keys().collect::<Vec<_>>(), conversation_id);
+        let merged: Option<String> = sqlx::query_scalar(
+            "UPDATE conversations SET metadata = json_patch(COALESCE(metadata, '{}'), ?) WHERE id = ? RETURNING metadata",
+        )
+        .bind(serde_json::Value::Object(update.clone()).to_string())
+        .bind(conversation_id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to update conversation metadata in database")?;
+        let Some(merged) = merged else {
+            return Err(anyhow::anyhow!("Conversation not found for metadata update."));
+        };
+        serde_json::from_str(&merged).context("Invalid conversation metadata")
+    }
+
+    /// Total prompt and completion tokens recorded on a conversation's messages.
+    pub async fn conversation_tokens_used(&self, conversation_id: Uuid) -> Result<i64, anyhow::Error> {
+        sqlx::query_scalar(
+            r#"
+            SELECT COALESCE(SUM(
+                COALESCE(json_extract(metadata, '$.prompt_tokens'), 0)
+                + COALESCE(json_extract(metadata, '$.completion_tokens'), 0)
+            ), 0)
+            FROM messages
+            WHERE conversation_id = ? AND metadata IS NOT NULL AND json_valid(metadata)
+            "#,
+        )
+        .bind(conversation_id.to_string())
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to sum conversation token usage")
+    }
+
+    /// Locks a conversation against new messages and regeneration, or unlocks it.
+    pub async fn set_conversation_locked(&self, conversation_id: Uuid, locked: bool) -> Result<(), anyhow::Error> {
+        log::info!("Setting locked={} for conversation {}", locked, conversation_id);
+        let result = sqlx::query("UPDATE conversations SET locked = ? WHERE id = ?")
+            .bind(locked)
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation lock in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for lock update."));
+        }
+        Ok(())
+    }
+
+    /// Turns single-turn mode on or off; see `Conversation::single_turn`.
+    pub async fn set_conversation_single_turn(&self, conversation_id: Uuid, single_turn: bool) -> Result<(), anyhow::Error> {
+        log::info!("Setting single_turn={} for conversation {}", single_turn, conversation_id);
+        let result = sqlx::query("UPDATE conversations SET single_turn = ? WHERE id = ?")
+            .bind(single_turn)
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to update conversation single-turn mode in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for single-turn update."));
+        }
+        Ok(())
+    }
+
+    /// Marks a conversation ephemeral (messages kept in memory only) or persistent again.
+    pub async fn set_conversation_ephemeral(&self, conversation_id: Uuid, ephemeral: bool) -> Result<(), anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        log::info!("Setting ephemeral={} for conversation {}", ephemeral, conversation_id_text);
+
+        let result = sqlx::query!(
+            "UPDATE conversations SET ephemeral = ? WHERE id = ?",
+            ephemeral,
+            conversation_id_text
+        )
+        .execute(&self.pool)
+        .await
+        .context("Failed to update conversation ephemeral flag in database")?;
+
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Conversation not found for ephemeral update."));
+        }
+        Ok(())
+    }
+
+    /// Sums `cost_usd` from assistant message metadata since `since`.
+    /// Returns the total and how many assistant messages had no cost recorded.
+    pub async fn spend_since(&self, since: chrono::DateTime<Utc>) -> Result<(f64, i64), anyhow::Error> {
+        let since_ts = since.timestamp();
+        let row = sqlx::query(
+            r#"
+            SELECT
+                COALESCE(SUM(json_extract(metadata, '$.cost_usd')), 0.0) AS spent,
+                COALESCE(SUM(CASE WHEN json_extract(metadata, '$.cost_usd') IS NULL THEN 1 ELSE 0 END), 0) AS untracked
+            FROM messages
+            WHERE role = 'assistant' AND timestamp >= ? AND (metadata IS NULL OR json_valid(metadata))
+            "#,
+        )
+        .bind(since_ts)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to sum message costs")?;
+
+        Ok((row.try_get("spent")?, row.try_get("untracked")?))
+    }
+
+    /// Token and cost totals of assistant messages per conversation (or local day) and model,
+    /// newest first. Conversations in the recycle bin are included; their usage still happened.
+    pub async fn usage_rows(&self, grouping: UsageGrouping) -> Result<Vec<UsageRow>, anyhow::Error> {
+        let (key, label) = match grouping {
+            UsageGrouping::Conversation => ("m.conversation_id", "c.title"),
+            UsageGrouping::Day => (
+                "date(m.timestamp, 'unixepoch', 'localtime')",
+                "date(m.timestamp, 'unixepoch', 'localtime')",
+            ),
+        };
+        let sql = format!(
+            r#"
+            SELECT
+                {key} AS key,
+                {label} AS label,
+                COALESCE(json_extract(m.metadata, '$.model_name'), '') AS model,
+                COUNT(*) AS message_count,
+                COALESCE(SUM(json_extract(m.metadata, '$.prompt_tokens')), 0) AS prompt_tokens,
+                COALESCE(SUM(json_extract(m.metadata, '$.completion_tokens')), 0) AS completion_tokens,
+                SUM(json_extract(m.metadata, '$.cost_usd')) * 1.0 AS cost_usd
+            FROM messages m
+            JOIN conversations c ON c.id = m.conversation_id
+            WHERE m.role = 'assistant' AND (m.metadata IS NULL OR json_valid(m.metadata))
+            GROUP BY 1, 3
+            ORDER BY MAX(m.timestamp) DESC
+            "#
+        );
+        let rows = sqlx::query(&sql)
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to aggregate usage")?;
+        rows.iter()
+            .map(|row| {
+                Ok(UsageRow {
+                    key: row.try_get("key")?,
+                    label: row.try_get("label")?,
+                    model: row.try_get("model")?,
+                    message_count: row.try_get("message_count")?,
+                    prompt_tokens: row.try_get("prompt_tokens")?,
+                    completion_tokens: row.try_get("completion_tokens")?,
+                    cost_usd: row.try_get("cost_usd")?,
+                })
+            })
+            .collect()
     }
-} 
\ No newline at end of file
+
+    /// Messages sent and conversations created per day between `from` and `to` (Unix seconds,
+    /// end exclusive), bucketed by local day using `utc_offset_secs`. Trashed conversations and
+    /// their messages are left out. Days without activity are absent.
+    pub async fn activity_counts(
+        &self,
+        from: i64,
+        to: i64,
+        utc_offset_secs: i64,
+    ) -> Result<HashMap<chrono::NaiveDate, (i64, i64)>, anyhow::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT day, SUM(is_message) AS message_count, SUM(is_creation) AS conversations_created
+            FROM (
+                SELECT date(m.timestamp + ?3, 'unixepoch') AS day, 1 AS is_message, 0 AS is_creation
+                FROM messages m
+                JOIN conversations c ON c.id = m.conversation_id
+                WHERE c.deleted_at IS NULL AND m.timestamp >= ?1 AND m.timestamp < ?2
+                UNION ALL
+                SELECT date(c.created_at + ?3, 'unixepoch'), 0, 1
+                FROM conversations c
+                WHERE c.deleted_at IS NULL AND c.created_at >= ?1 AND c.created_at < ?2
+            )
+            GROUP BY day
+            "#,
+        )
+        .bind(from)
+        .bind(to)
+        .bind(utc_offset_secs)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to aggregate activity")?;
+
+        rows.iter()
+            .map(|row| {
+                let day: String = row.try_get("day")?;
+                let day = chrono::NaiveDate::parse_from_str(&day, "%Y-%m-%d").context("Invalid activity day")?;
+                Ok((day, (row.try_get("message_count")?, row.try_get("conversations_created")?)))
+            })
+            .collect()
+    }
+
+    /// Bytes of message content and metadata per conversation (trashed ones included),
+    /// largest first. Excludes SQLite's own overhead, so it only approximates disk use.
+    pub async fn conversation_sizes(&self) -> Result<Vec<ConversationSize>, anyhow::Error> {
+        let rows = sqlx::query(
+            r#"
+            SELECT c.id, c.title, c.deleted_at,
+                   COUNT(m.id) AS message_count,
+                   COALESCE(SUM(LENGTH(CAST(m.content AS BLOB))), 0) AS content_bytes,
+                   COALESCE(SUM(LENGTH(CAST(m.metadata AS BLOB))), 0) AS metadata_bytes
+            FROM conversations c
+            LEFT JOIN messages m ON m.conversation_id = c.id
+            GROUP BY c.id
+            ORDER BY content_bytes + metadata_bytes DESC, c.last_updated_at DESC
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to aggregate conversation sizes")?;
+
+        rows.iter()
+            .map(|row| {
+                let id: String = row.try_get("id")?;
+                let content_bytes: i64 = row.try_get("content_bytes")?;
+                let metadata_bytes: i64 = row.try_get("metadata_bytes")?;
+                Ok(ConversationSize {
+                    conversation_id: Uuid::parse_str(&id).context("Failed to parse conversation ID")?,
+                    title: row.try_get("title")?,
+                    trashed: row.try_get::<Option<i64>, _>("deleted_at")?.is_some(),
+                    message_count: row.try_get("message_count")?,
+                    content_bytes,
+                    metadata_bytes,
+                    total_bytes: content_bytes + metadata_bytes,
+                })
+            })
+            .collect()
+    }
+
+    // Size of the database file plus its write-ahead log; 0 for in-memory databases
+    pub async fn database_size(&self) -> u64 {
+        let mut size = 0;
+        for suffix in ["", "-wal"] {
+            let mut path = self.db_path.clone().into_os_string();
+            path.push(suffix);
+            if let Ok(metadata) = tokio::fs::metadata(&path).await {
+                size += metadata.len();
+            }
+        }
+        size
+    }
+
+    /// Rebuilds the database file with `VACUUM` so space freed by deletes is returned to the
+    /// OS, then optionally runs `PRAGMA optimize`. VACUUM fails while another connection has a
+    /// transaction open, so callers should hold the storage lock and run it when idle.
+    pub async fn compact_database(&self, optimize: bool) -> Result<CompactionReport, anyhow::Error> {
+        let size_before = self.database_size().await;
+        log::info!("Compacting database ({} bytes)", size_before);
+        let mut conn = self.pool.acquire().await.context("Failed to get a database connection")?;
+        sqlx::query("VACUUM").execute(&mut *conn).await.context("Failed to vacuum database")?;
+        // Fold the log back into the main file so the new size shows up on disk
+        sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+            .execute(&mut *conn)
+            .await
+            .context("Failed to checkpoint database")?;
+        if optimize {
+            sqlx::query("PRAGMA optimize").execute(&mut *conn).await.context("Failed to optimize database")?;
+        }
+        let size_after = self.database_size().await;
+        log::info!("Compacted database from {} to {} bytes", size_before, size_after);
+        Ok(CompactionReport { size_before, size_after, optimized: optimize })
+    }
+
+    /// Deletes every row of every table in one transaction, then vacuums so the deleted data
+    /// does not linger in free pages. The schema itself is kept, and the default model config
+    /// is added back as on first start. `keyring_errors` is left empty.
+    pub async fn wipe_all_data(&self) -> Result<WipeReport, anyhow::Error> {
+        let mut report = WipeReport::default();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+        for table in WIPED_TABLES {
+            let deleted = sqlx::query(&format!("DELETE FROM {}", table))
+                .execute(&mut *tx)
+                .await
+                .with_context(|| format!("Failed to clear table {}", table))?
+                .rows_affected();
+            match *table {
+                "conversations" => report.conversations_deleted = deleted,
+                "messages" => report.messages_deleted = deleted,
+                "model_configs" => report.model_configs_deleted = deleted,
+                _ => {}
+            }
+        }
+        tx.commit().await.context("Failed to commit wipe")?;
+        log::warn!(
+            "Wiped all data: {} conversations, {} messages, {} model configs",
+            report.conversations_deleted, report.messages_deleted, report.model_configs_deleted
+        );
+        self.compact_database(false).await?;
+        // New conversations need a model config to use
+        self.add_default_model_config_if_none().await?;
+        Ok(report)
+    }
+
+    /// Runs SQLite's `integrity_check` and `foreign_key_check` pragmas. Read-only.
+    pub async fn check_database_integrity(&self) -> Result<DatabaseIntegrityReport, anyhow::Error> {
+        let integrity_check = sqlx::query("PRAGMA integrity_check")
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to run integrity check")?
+            .iter()
+            .map(|row| row.try_get::<String, _>(0))
+            .collect::<Result<Vec<String>, _>>()?;
+        let foreign_key_violations = sqlx::query("PRAGMA foreign_key_check")
+            .fetch_all(&self.pool)
+            .await
+            .context("Failed to run foreign key check")?
+            .iter()
+            .map(|row| {
+                Ok(ForeignKeyViolation {
+                    table: row.try_get("table")?,
+                    rowid: row.try_get("rowid")?,
+                    parent: row.try_get("parent")?,
+                })
+            })
+            .collect::<Result<Vec<_>, sqlx::Error>>()?;
+
+        Ok(DatabaseIntegrityReport {
+            ok: integrity_check == ["ok"] && foreign_key_violations.is_empty(),
+            integrity_check,
+            foreign_key_violations,
+        })
+    }
+
+    /// Looks for rows left dangling by deletes. Read-only.
+    pub async fn check_data_integrity(&self) -> Result<IntegrityReport, anyhow::Error> {
+        let ids = |sql: &'static str| async move {
+            sqlx::query(sql)
+                .fetch_all(&self.pool)
+                .await?
+                .iter()
+                .map(|row| row.try_get::<String, _>("id"))
+                .collect::<Result<Vec<String>, _>>()
+        };
+
+        Ok(IntegrityReport {
+            orphaned_message_ids: ids(
+                "SELECT m.id FROM messages m LEFT JOIN conversations c ON c.id = m.conversation_id WHERE c.id IS NULL",
+            )
+            .await
+            .context("Failed to check for orphaned messages")?,
+            conversations_missing_model_config: ids(
+                "SELECT c.id FROM conversations c LEFT JOIN model_configs mc ON mc.id = c.model_config_id WHERE mc.id IS NULL",
+            )
+            .await
+            .context("Failed to check conversation model configs")?,
+            invalid_metadata_message_ids: ids(
+                "SELECT id FROM messages WHERE metadata IS NOT NULL AND NOT json_valid(metadata)",
+            )
+            .await
+            .context("Failed to check message metadata")?,
+        })
+    }
+
+    /// Applies the selected repairs in a single transaction.
+    pub async fn repair_data_integrity(&self, actions: &RepairActions) -> Result<RepairSummary, anyhow::Error> {
+        let mut summary = RepairSummary::default();
+        let mut tx = self.pool.begin().await.context("Failed to begin transaction")?;
+
+        if actions.delete_orphaned_messages {
+            summary.deleted_messages = sqlx::query(
+                "DELETE FROM messages WHERE conversation_id NOT IN (SELECT id FROM conversations)",
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete orphaned messages")?
+            .rows_affected();
+        }
+
+        if actions.reassign_missing_model_configs {
+            let default_id: Option<String> = sqlx::query_scalar(
+                "SELECT id FROM model_configs ORDER BY is_default DESC, sort_order ASC, name ASC LIMIT 1",
+            )
+            .fetch_optional(&mut *tx)
+            .await
+            .context("Failed to find default model config")?;
+            let default_id = default_id.ok_or_else(|| {
+                anyhow::anyhow!("No model configurations found to reassign conversations to.")
+            })?;
+            summary.reassigned_conversations = sqlx::query(
+                "UPDATE conversations SET model_config_id = ? WHERE model_config_id NOT IN (SELECT id FROM model_configs)",
+            )
+            .bind(default_id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to reassign conversation model configs")?
+            .rows_affected();
+        }
+
+        if actions.clear_invalid_metadata {
+            summary.cleared_metadata = sqlx::query(
+                "UPDATE messages SET metadata = NULL WHERE metadata IS NOT NULL AND NOT json_valid(metadata)",
+            )
+            .execute(&mut *tx)
+            .await
+            .context("Failed to clear invalid metadata")?
+            .rows_affected();
+        }
+
+        tx.commit().await.context("Failed to commit integrity repair")?;
+        log::info!("Integrity repair applied: {:?}", summary);
+        Ok(summary)
+    }
+
+    /// Row counts of every table, for diagnostics.
+    pub async fn table_row_counts(&self) -> Result<Vec<(String, i64)>, anyhow::Error> {
+        let mut counts = Vec::new();
+        for table in ["conversations", "messages", "model_configs", "settings", "tool_permissions", "conversation_memory", "pending_jobs", "conversation_snapshots", "personas", "conversation_events", "project_files"] {
+            let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {}", table))
+                .fetch_one(&self.pool)
+                .await
+                .context(format!("Failed to count rows in {}", table))?;
+            counts.push((table.to_string(), count));
+        }
+        Ok(counts)
+    }
+
+    pub async fn sqlite_version(&self) -> Result<String, anyhow::Error> {
+        sqlx::query_scalar("SELECT sqlite_version()")
+            .fetch_one(&self.pool)
+            .await
+            .context("Failed to read SQLite version")
+    }
+
+    /// The policy for `tool_name` in a conversation: its own entry first, then the global one.
+    pub async fn get_tool_policy(
+        &self,
+        tool_name: &str,
+        conversation_id: Uuid,
+    ) -> Result<Option<ToolPolicy>, anyhow::Error> {
+        let policy: Option<String> = sqlx::query_scalar(
+            r#"
+            SELECT policy FROM tool_permissions
+            WHERE tool_name = ? AND conversation_id IN (?, '')
+            ORDER BY conversation_id = '' ASC
+            LIMIT 1
+            "#,
+        )
+        .bind(tool_name)
+        .bind(conversation_id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .context(format!("Failed to read policy for tool '{}'", tool_name))?;
+        policy
+            .map(|p| ToolPolicy::parse(&p).map_err(|e| anyhow::anyhow!(e)))
+            .transpose()
+    }
+
+    /// Stores the policy for `tool_name`, for one conversation or globally when `conversation_id` is None.
+    pub async fn set_tool_policy(
+        &self,
+        tool_name: &str,
+        conversation_id: Option<Uuid>,
+        policy: ToolPolicy,
+    ) -> Result<(), anyhow::Error> {
+        log::info!("Setting policy for tool '{}' ({:?}): {}", tool_name, conversation_id, policy.as_str());
+        sqlx::query(
+            r#"
+            INSERT INTO tool_permissions (tool_name, conversation_id, policy) VALUES (?, ?, ?)
+            ON CONFLICT(tool_name, conversation_id) DO UPDATE SET policy = excluded.policy
+            "#,
+        )
+        .bind(tool_name)
+        .bind(conversation_id.map(|id| id.to_string()).unwrap_or_default())
+        .bind(policy.as_str())
+        .execute(&self.pool)
+        .await
+        .context(format!("Failed to set policy for tool '{}'", tool_name))?;
+        Ok(())
+    }
+
+    /// Removes a stored policy so the tool falls back to the global one (or to asking).
+    pub async fn delete_tool_policy(&self, tool_name: &str, conversation_id: Option<Uuid>) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM tool_permissions WHERE tool_name = ? AND conversation_id = ?")
+            .bind(tool_name)
+            .bind(conversation_id.map(|id| id.to_string()).unwrap_or_default())
+            .execute(&self.pool)
+            .await
+            .context(format!("Failed to delete policy for tool '{}'", tool_name))?;
+        Ok(())
+    }
+
+    /// Every stored tool policy, global entries first.
+    pub async fn list_tool_permissions(&self) -> Result<Vec<ToolPermission>, anyhow::Error> {
+        let rows: Vec<(String, String, String)> = sqlx::query_as(
+            "SELECT tool_name, conversation_id, policy FROM tool_permissions ORDER BY conversation_id, tool_name",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list tool permissions")?;
+        rows.into_iter()
+            .map(|(tool_name, conversation_id, policy)| {
+                Ok(ToolPermission {
+                    tool_name,
+                    conversation_id: if conversation_id.is_empty() { None } else { Some(conversation_id) },
+                    policy: ToolPolicy::parse(&policy).map_err(|e| anyhow::anyhow!(e))?,
+                })
+            })
+            .collect()
+    }
+
+    /// The cached summary of a conversation's oldest messages, if any.
+    pub async fn get_conversation_memory(&self, conversation_id: Uuid) -> Result<Option<ConversationMemory>, anyhow::Error> {
+        let row: Option<(String, String, i64, i64)> = sqlx::query_as(
+            "SELECT summary, last_message_id, covered_until, updated_at FROM conversation_memory WHERE conversation_id = ?",
+        )
+        .bind(conversation_id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read conversation memory")?;
+        let Some((summary, last_message_id, covered_until, updated_at)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(ConversationMemory {
+            conversation_id,
+            summary,
+            last_message_id: Uuid::parse_str(&last_message_id).context("Failed to parse summarized message ID")?,
+            covered_until: chrono::DateTime::from_timestamp(covered_until, 0).unwrap_or_default(),
+            updated_at: chrono::DateTime::from_timestamp(updated_at, 0).unwrap_or_default(),
+        }))
+    }
+
+    /// Stores `memory`, replacing the conversation's previous summary.
+    pub async fn save_conversation_memory(&self, memory: &ConversationMemory) -> Result<(), anyhow::Error> {
+        log::debug!("Saving memory of conversation {}", memory.conversation_id);
+        sqlx::query(
+            r#"
+            INSERT INTO conversation_memory (conversation_id, summary, last_message_id, covered_until, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(conversation_id) DO UPDATE SET
+                summary = excluded.summary,
+                last_message_id = excluded.last_message_id,
+                covered_until = excluded.covered_until,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(memory.conversation_id.to_string())
+        .bind(&memory.summary)
+        .bind(memory.last_message_id.to_string())
+        .bind(memory.covered_until.timestamp())
+        .bind(memory.updated_at.timestamp())
+        .execute(&self.pool)
+        .await
+        .context("Failed to save conversation memory")?;
+        Ok(())
+    }
+
+    /// Drops a conversation's summary. Returns whether there was one.
+    pub async fn clear_conversation_memory(&self, conversation_id: Uuid) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query("DELETE FROM conversation_memory WHERE conversation_id = ?")
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to clear conversation memory")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Stores a job for the retry loop, unless the conversation already has a pending job
+    /// of the same type. Returns whether it was added.
+    pub async fn enqueue_job(&self, job: &PendingJob) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO pending_jobs (id, job_type, conversation_id, payload, attempts, next_attempt_at, last_error, created_at)
+            SELECT ?, ?, ?, ?, ?, ?, ?, ?
+            WHERE NOT EXISTS (SELECT 1 FROM pending_jobs WHERE job_type = ? AND conversation_id = ?)
+            "#,
+        )
+        .bind(job.id.to_string())
+        .bind(&job.job_type)
+        .bind(job.conversation_id.to_string())
+        .bind(job.payload.to_string())
+        .bind(job.attempts)
+        .bind(job.next_attempt_at.timestamp())
+        .bind(&job.last_error)
+        .bind(job.created_at.timestamp())
+        .bind(&job.job_type)
+        .bind(job.conversation_id.to_string())
+        .execute(&self.pool)
+        .await
+        .context("Failed to enqueue job")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Every pending job, soonest first.
+    pub async fn list_pending_jobs(&self) -> Result<Vec<PendingJob>, anyhow::Error> {
+        let rows = sqlx::query(
+            "SELECT id, job_type, conversation_id, payload, attempts, next_attempt_at, last_error, created_at
+            FROM pending_jobs ORDER BY next_attempt_at ASC",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list pending jobs")?;
+        rows.iter().map(pending_job_from_row).collect()
+    }
+
+    /// Up to `limit` jobs whose next attempt is due at `now`, oldest due first.
+    pub async fn due_jobs(&self, now: chrono::DateTime<Utc>, limit: i64) -> Result<Vec<PendingJob>, anyhow::Error> {
+        let rows = sqlx::query(
+            "SELECT id, job_type, conversation_id, payload, attempts, next_attempt_at, last_error, created_at
+            FROM pending_jobs WHERE next_attempt_at <= ? ORDER BY next_attempt_at ASC LIMIT ?",
+        )
+        .bind(now.timestamp())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to load due jobs")?;
+        rows.iter().map(pending_job_from_row).collect()
+    }
+
+    /// Records another failed attempt of a job and when to try it next.
+    pub async fn reschedule_job(
+        &self,
+        job_id: Uuid,
+        attempts: i64,
+        next_attempt_at: chrono::DateTime<Utc>,
+        last_error: &str,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query("UPDATE pending_jobs SET attempts = ?, next_attempt_at = ?, last_error = ? WHERE id = ?")
+            .bind(attempts)
+            .bind(next_attempt_at.timestamp())
+            .bind(last_error)
+            .bind(job_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to reschedule job")?;
+        Ok(())
+    }
+
+    /// Removes a job. Returns whether it existed.
+    pub async fn delete_job(&self, job_id: Uuid) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query("DELETE FROM pending_jobs WHERE id = ?")
+            .bind(job_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete job")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Removes every pending job of a conversation.
+    pub async fn delete_conversation_jobs(&self, conversation_id: Uuid) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM pending_jobs WHERE conversation_id = ?")
+            .bind(conversation_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete conversation jobs")?;
+        Ok(())
+    }
+
+    /// Stores the conversation's current messages as a snapshot labelled `label`.
+    pub async fn create_conversation_snapshot(
+        &self,
+        conversation_id: Uuid,
+        label: &str,
+    ) -> Result<ConversationSnapshot, anyhow::Error> {
+        let messages = self.get_conversation_messages(conversation_id).await?;
+        let snapshot = ConversationSnapshot {
+            id: Uuid::new_v4(),
+            conversation_id,
+            label: label.to_string(),
+            message_count: messages.len() as i64,
+            created_at: Utc::now(),
+        };
+        log::info!("Saving snapshot {} of conversation {} ({} messages)", snapshot.id, conversation_id, messages.len());
+        sqlx::query(
+            "INSERT INTO conversation_snapshots (id, conversation_id, label, messages, message_count, created_at)
+            VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(snapshot.id.to_string())
+        .bind(conversation_id.to_string())
+        .bind(&snapshot.label)
+        .bind(serde_json::to_string(&messages).context("Failed to serialize snapshot messages")?)
+        .bind(snapshot.message_count)
+        .bind(snapshot.created_at.timestamp())
+        .execute(&self.pool)
+        .await
+        .context("Failed to save conversation snapshot")?;
+        Ok(snapshot)
+    }
+
+    /// A conversation's snapshots, newest first.
+    pub async fn list_conversation_snapshots(&self, conversation_id: Uuid) -> Result<Vec<ConversationSnapshot>, anyhow::Error> {
+        let rows = sqlx::query(
+            "SELECT id, conversation_id, label, message_count, created_at FROM conversation_snapshots
+            WHERE conversation_id = ? ORDER BY created_at DESC",
+        )
+        .bind(conversation_id.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list conversation snapshots")?;
+        rows.iter().map(snapshot_from_row).collect()
+    }
+
+    pub async fn get_conversation_snapshot(&self, snapshot_id: Uuid) -> Result<Option<ConversationSnapshot>, anyhow::Error> {
+        let row = sqlx::query(
+            "SELECT id, conversation_id, label, message_count, created_at FROM conversation_snapshots WHERE id = ?",
+        )
+        .bind(snapshot_id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read conversation snapshot")?;
+        row.as_ref().map(snapshot_from_row).transpose()
+    }
+
+    /// Replaces the conversation's messages with the snapshot's, in one transaction. The
+    /// cached summary goes too, since it describes the replaced messages. Returns the
+    /// conversation's ID.
+    pub async fn restore_conversation_snapshot(&self, snapshot_id: Uuid) -> Result<Uuid, anyhow::Error> {
+        let row = sqlx::query("SELECT conversation_id, messages FROM conversation_snapshots WHERE id = ?")
+            .bind(snapshot_id.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to read conversation snapshot")?
+            .ok_or_else(|| anyhow::anyhow!("Snapshot not found."))?;
+        let conversation_id_text: String = row.try_get("conversation_id")?;
+        let messages: Vec<Message> = serde_json::from_str(&row.try_get::<String, _>("messages")?)
+            .context("Invalid snapshot messages")?;
+        log::warn!("Restoring conversation {} to snapshot {} ({} messages)", conversation_id_text, snapshot_id, messages.len());
+
+        let mut tx = self.pool.begin().await.context("Failed to begin snapshot restore transaction")?;
+        sqlx::query("DELETE FROM messages WHERE conversation_id = ?")
+            .bind(&conversation_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to clear conversation messages")?;
+        Self::insert_messages(&mut tx, &messages).await?;
+        sqlx::query("DELETE FROM conversation_memory WHERE conversation_id = ?")
+            .bind(&conversation_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to clear conversation memory")?;
+        sqlx::query("UPDATE conversations SET last_updated_at = ? WHERE id = ?")
+            .bind(Utc::now().timestamp())
+            .bind(&conversation_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to update conversation last_updated_at timestamp")?;
+        tx.commit().await.context("Failed to commit snapshot restore")?;
+
+        Uuid::parse_str(&conversation_id_text).context("Failed to parse snapshot conversation ID")
+    }
+
+    /// Removes a snapshot. Returns whether it existed.
+    pub async fn delete_conversation_snapshot(&self, snapshot_id: Uuid) -> Result<bool, anyhow::Error> {
+        let result = sqlx::query("DELETE FROM conversation_snapshots WHERE id = ?")
+            .bind(snapshot_id.to_string())
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete conversation snapshot")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Every persona, by name.
+    pub async fn list_personas(&self) -> Result<Vec<Persona>, anyhow::Error> {
+        let rows = sqlx::query(
+            "SELECT id, name, system_prompt, model_config_id, params FROM personas ORDER BY name COLLATE NOCASE",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list personas")?;
+        rows.iter().map(persona_from_row).collect()
+    }
+
+    pub async fn get_persona(&self, persona_id: Uuid) -> Result<Option<Persona>, anyhow::Error> {
+        let row = sqlx::query("SELECT id, name, system_prompt, model_config_id, params FROM personas WHERE id = ?")
+            .bind(persona_id.to_string())
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to read persona")?;
+        row.as_ref().map(persona_from_row).transpose()
+    }
+
+    pub async fn add_persona(&self, persona: &Persona) -> Result<(), anyhow::Error> {
+        log::info!("Adding persona {} ('{}')", persona.id, persona.name);
+        sqlx::query("INSERT INTO personas (id, name, system_prompt, model_config_id, params) VALUES (?, ?, ?, ?, ?)")
+            .bind(persona.id.to_string())
+            .bind(&persona.name)
+            .bind(&persona.system_prompt)
+            .bind(persona.model_config_id.map(|id| id.to_string()))
+            .bind(&persona.params)
+            .execute(&self.pool)
+            .await
+            .context("Failed to insert persona")?;
+        Ok(())
+    }
+
+    pub async fn update_persona(&self, persona: &Persona) -> Result<(), anyhow::Error> {
+        log::info!("Updating persona {}", persona.id);
+        let result = sqlx::query(
+            "UPDATE personas SET name = ?, system_prompt = ?, model_config_id = ?, params = ? WHERE id = ?",
+        )
+        .bind(&persona.name)
+        .bind(&persona.system_prompt)
+        .bind(persona.model_config_id.map(|id| id.to_string()))
+        .bind(&persona.params)
+        .bind(persona.id.to_string())
+        .execute(&self.pool)
+        .await
+        .context("Failed to update persona")?;
+        if result.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("Persona not found for update."));
+        }
+        Ok(())
+    }
+
+    /// Deletes a persona and clears it from the conversations using it, in one transaction.
+    /// Returns the IDs of those conversations; empty when the persona didn't exist or was unused.
+    pub async fn delete_persona(&self, persona_id: Uuid) -> Result<Vec<Uuid>, anyhow::Error> {
+        let persona_id_text = persona_id.to_string();
+        log::warn!("Deleting persona {}", persona_id_text);
+        let mut tx = self.pool.begin().await.context("Failed to begin persona delete transaction")?;
+        let cleared: Vec<String> = sqlx::query_scalar("UPDATE conversations SET persona_id = NULL WHERE persona_id = ? RETURNING id")
+            .bind(&persona_id_text)
+            .fetch_all(&mut *tx)
+            .await
+            .context("Failed to clear persona from conversations")?;
+        sqlx::query("DELETE FROM personas WHERE id = ?")
+            .bind(&persona_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete persona")?;
+        tx.commit().await.context("Failed to commit persona delete")?;
+        cleared.iter()
+            .map(|id| Uuid::parse_str(id).context("Failed to parse conversation ID"))
+            .collect()
+    }
+
+    // Appends a changelog event within the transaction making the change
+    async fn record_event(
+        tx: &mut Transaction<'_, Sqlite>,
+        conversation_id: Uuid,
+        event_type: &str,
+        old_value: Option<serde_json::Value>,
+        new_value: Option<serde_json::Value>,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            "INSERT INTO conversation_events (conversation_id, event_type, old_value, new_value, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(conversation_id.to_string())
+        .bind(event_type)
+        .bind(old_value.map(|value| value.to_string()))
+        .bind(new_value.map(|value| value.to_string()))
+        .bind(Utc::now().timestamp())
+        .execute(&mut **tx)
+        .await
+        .context("Failed to record conversation event")?;
+        Ok(())
+    }
+
+    /// A conversation's recorded changes, newest first.
+    pub async fn get_conversation_events(&self, conversation_id: Uuid, limit: u32) -> Result<Vec<ConversationEvent>, anyhow::Error> {
+        let rows = sqlx::query(
+            "SELECT id, conversation_id, event_type, old_value, new_value, created_at, undone_at
+            FROM conversation_events
+            WHERE conversation_id = ?
+            ORDER BY id DESC
+            LIMIT ?",
+        )
+        .bind(conversation_id.to_string())
+        .bind(i64::from(limit))
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to fetch conversation events")?;
+        rows.iter().map(conversation_event_from_row).collect()
+    }
+
+    /// Reverses the newest event of the conversation that hasn't been undone, in one transaction.
+    /// Only renames, model changes and message deletes can be reversed, and only while the
+    /// conversation still looks the way the event left it.
+    pub async fn undo_last_event(&self, conversation_id: Uuid) -> Result<UndoOutcome, anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        let mut tx = self.pool.begin().await.context("Failed to begin undo transaction")?;
+        let row = sqlx::query(
+            "SELECT id, conversation_id, event_type, old_value, new_value, created_at, undone_at
+            FROM conversation_events
+            WHERE conversation_id = ? AND undone_at IS NULL
+            ORDER BY id DESC
+            LIMIT 1",
+        )
+        .bind(&conversation_id_text)
+        .fetch_optional(&mut *tx)
+        .await
+        .context("Failed to fetch the last conversation event")?;
+        let Some(mut event) = row.as_ref().map(conversation_event_from_row).transpose()? else {
+            return Ok(UndoOutcome::NothingToUndo);
+        };
+        let old_value = event.old_value.clone().unwrap_or_default();
+        let new_value = event.new_value.clone().unwrap_or_default();
+
+        match event.event_type.as_str() {
+            changelog::EVENT_RENAME => {
+                let current: Option<String> = sqlx::query_scalar("SELECT title FROM conversations WHERE id = ?")
+                    .bind(&conversation_id_text)
+                    .fetch_optional(&mut *tx)
+                    .await
+                    .context("Failed to read conversation title")?;
+                if current.as_deref() != new_value["title"].as_str() {
+                    return Ok(UndoOutcome::Refused("The title has changed since the rename.".to_string()));
+                }
+                let Some(title) = old_value["title"].as_str() else {
+                    return Ok(UndoOutcome::Refused("The rename did not record the previous title.".to_string()));
+                };
+                sqlx::query("UPDATE conversations SET title = ?, last_updated_at = ? WHERE id = ?")
+                    .bind(title)
+                    .bind(Utc::now().timestamp())
+                    .bind(&conversation_id_text)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to restore conversation title")?;
+            }
+            changelog::EVENT_MODEL_CHANGE => {
+                let current: Option<(String, Option<String>)> =
+                    sqlx::query_as("SELECT model_config_id, model_override FROM conversations WHERE id = ?")
+                        .bind(&conversation_id_text)
+                        .fetch_optional(&mut *tx)
+                        .await
+                        .context("Failed to read conversation model")?;
+                let unchanged = current.as_ref().is_some_and(|(model_id, model_override)| {
+                    new_value["modelConfigId"].as_str() == Some(model_id.as_str())
+                        && new_value["modelOverride"].as_str() == model_override.as_deref()
+                });
+                if !unchanged {
+                    return Ok(UndoOutcome::Refused("The model has changed since the switch.".to_string()));
+                }
+                let Some(model_id) = old_value["modelConfigId"].as_str() else {
+                    return Ok(UndoOutcome::Refused("The switch did not record the previous model.".to_string()));
+                };
+                let config_exists: Option<String> = sqlx::query_scalar("SELECT id FROM model_configs WHERE id = ?")
+                    .bind(model_id)
+                    .fetch_optional(&mut *tx)
+                    .await
+                    .context("Failed to look up the previous model config")?;
+                if config_exists.is_none() {
+                    return Ok(UndoOutcome::Refused("The previous model configuration no longer exists.".to_string()));
+                }
+                sqlx::query("UPDATE conversations SET model_config_id = ?, model_override = ?, last_updated_at = ? WHERE id = ?")
+                    .bind(model_id)
+                    .bind(old_value["modelOverride"].as_str())
+                    .bind(Utc::now().timestamp())
+                    .bind(&conversation_id_text)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to restore conversation model")?;
+            }
+            changelog::EVENT_MESSAGE_DELETE => {
+                let message: Message = serde_json::from_value(old_value).context("Invalid deleted message in event")?;
+                let latest_seq: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(seq), 0) FROM messages WHERE conversation_id = ?")
+                    .bind(&conversation_id_text)
+                    .fetch_one(&mut *tx)
+                    .await
+                    .context("Failed to read the latest message sequence number")?;
+                if new_value["latestSeq"].as_i64().is_none_or(|seq| latest_seq > seq) {
+                    return Ok(UndoOutcome::Refused("Messages were added after the delete.".to_string()));
+                }
+                let exists: Option<String> = sqlx::query_scalar("SELECT id FROM messages WHERE id = ?")
+                    .bind(message.id.to_string())
+                    .fetch_optional(&mut *tx)
+                    .await
+                    .context("Failed to look up the deleted message")?;
+                if exists.is_some() {
+                    return Ok(UndoOutcome::Refused("The message has already been restored.".to_string()));
+                }
+                Self::insert_messages(&mut tx, std::slice::from_ref(&message)).await?;
+                // The restored message is numbered last; the ones that followed it move after it
+                if let Some(seq) = new_value["seq"].as_i64() {
+                    let following: Vec<String> = sqlx::query_scalar(
+                        "SELECT id FROM messages WHERE conversation_id = ? AND seq > ? AND id != ? ORDER BY seq ASC",
+                    )
+                    .bind(&conversation_id_text)
+                    .bind(seq)
+                    .bind(message.id.to_string())
+                    .fetch_all(&mut *tx)
+                    .await
+                    .context("Failed to read the messages after the restored one")?;
+                    Self::resequence_messages(&mut tx, &following).await?;
+                }
+                // A summary covering the restored message would hide it
+                sqlx::query("DELETE FROM conversation_memory WHERE conversation_id = ? AND covered_until >= ?")
+                    .bind(&conversation_id_text)
+                    .bind(message.timestamp.timestamp())
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to invalidate conversation memory")?;
+                sqlx::query("UPDATE conversations SET last_updated_at = ? WHERE id = ?")
+                    .bind(Utc::now().timestamp())
+                    .bind(&conversation_id_text)
+                    .execute(&mut *tx)
+                    .await
+                    .context("Failed to update conversation last_updated_at timestamp")?;
+            }
+            other => {
+                return Ok(UndoOutcome::Refused(format!("The last change ({}) can't be undone.", other.replace('_', " "))));
+            }
+        }
+
+        let undone_at = Utc::now();
+        sqlx::query("UPDATE conversation_events SET undone_at = ? WHERE id = ?")
+            .bind(undone_at.timestamp())
+            .bind(event.id)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to mark conversation event as undone")?;
+        tx.commit().await.context("Failed to commit undo")?;
+        event.undone_at = Some(undone_at);
+        log::info!("Undid '{}' event {} of conversation {}", event.event_type, event.id, conversation_id);
+        Ok(UndoOutcome::Undone(event))
+    }
+
+    /// Deletes events recorded before `cutoff`. Returns how many were removed.
+    pub async fn prune_conversation_events(&self, cutoff: chrono::DateTime<Utc>) -> Result<u64, anyhow::Error> {
+        let result = sqlx::query("DELETE FROM conversation_events WHERE created_at < ?")
+            .bind(cutoff.timestamp())
+            .execute(&self.pool)
+            .await
+            .context("Failed to prune conversation events")?;
+        Ok(result.rows_affected())
+    }
+
+    /// The folder ingested into a conversation, if any.
+    pub async fn get_project_context(&self, conversation_id: Uuid) -> Result<Option<ProjectContext>, anyhow::Error> {
+        let row = sqlx::query(
+            "SELECT conversation_id, root, include_globs, exclude_globs, updated_at FROM project_contexts WHERE conversation_id = ?",
+        )
+        .bind(conversation_id.to_string())
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read project context")?;
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(ProjectContext {
+            conversation_id,
+            root: row.try_get("root")?,
+            include_globs: serde_json::from_str(&row.try_get::<String, _>("include_globs")?).context("Invalid include globs")?,
+            exclude_globs: serde_json::from_str(&row.try_get::<String, _>("exclude_globs")?).context("Invalid exclude globs")?,
+            updated_at: chrono::DateTime::from_timestamp(row.try_get("updated_at")?, 0)
+                .context("Invalid updated_at timestamp")?,
+        }))
+    }
+
+    /// The manifest of a conversation's project context, by path. Contents are only loaded
+    /// with `with_content`.
+    pub async fn get_project_files(&self, conversation_id: Uuid, with_content: bool) -> Result<Vec<ProjectFile>, anyhow::Error> {
+        let content_column = if with_content { "content" } else { "NULL AS content" };
+        let rows = sqlx::query(&format!(
+            "SELECT path, status, size, modified, hash, {} FROM project_files WHERE conversation_id = ? ORDER BY path",
+            content_column
+        ))
+        .bind(conversation_id.to_string())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to read project files")?;
+        rows.iter()
+            .map(|row| {
+                Ok(ProjectFile {
+                    path: row.try_get("path")?,
+                    status: row.try_get("status")?,
+                    size: row.try_get("size")?,
+                    modified: row.try_get("modified")?,
+                    hash: row.try_get("hash")?,
+                    content: row.try_get("content")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Stores an ingestion in one transaction: the context itself, the manifest entries that
+    /// changed and the removal of files that are gone. `replace_all` drops the old manifest first.
+    pub async fn save_project_context(
+        &self,
+        context: &ProjectContext,
+        changed: &[ProjectFile],
+        removed: &[String],
+        replace_all: bool,
+    ) -> Result<(), anyhow::Error> {
+        let conversation_id_text = context.conversation_id.to_string();
+        let mut tx = self.pool.begin().await.context("Failed to begin project context transaction")?;
+        sqlx::query(
+            "INSERT INTO project_contexts (conversation_id, root, include_globs, exclude_globs, updated_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT(conversation_id) DO UPDATE SET
+                root = excluded.root,
+                include_globs = excluded.include_globs,
+                exclude_globs = excluded.exclude_globs,
+                updated_at = excluded.updated_at",
+        )
+        .bind(&conversation_id_text)
+        .bind(&context.root)
+        .bind(serde_json::to_string(&context.include_globs)?)
+        .bind(serde_json::to_string(&context.exclude_globs)?)
+        .bind(context.updated_at.timestamp())
+        .execute(&mut *tx)
+        .await
+        .context("Failed to save project context")?;
+        if replace_all {
+            sqlx::query("DELETE FROM project_files WHERE conversation_id = ?")
+                .bind(&conversation_id_text)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to clear project files")?;
+        }
+        for path in removed {
+            sqlx::query("DELETE FROM project_files WHERE conversation_id = ? AND path = ?")
+                .bind(&conversation_id_text)
+                .bind(path)
+                .execute(&mut *tx)
+                .await
+                .context("Failed to remove project file")?;
+        }
+        for file in changed {
+            sqlx::query(
+                "INSERT OR REPLACE INTO project_files (conversation_id, path, status, size, modified, hash, content)
+                VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&conversation_id_text)
+            .bind(&file.path)
+            .bind(&file.status)
+            .bind(file.size)
+            .bind(file.modified)
+            .bind(&file.hash)
+            .bind(&file.content)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to save project file")?;
+        }
+        tx.commit().await.context("Failed to commit project context")?;
+        Ok(())
+    }
+
+    /// Removes a conversation's project context and manifest. Returns whether there was one.
+    pub async fn delete_project_context(&self, conversation_id: Uuid) -> Result<bool, anyhow::Error> {
+        let conversation_id_text = conversation_id.to_string();
+        let mut tx = self.pool.begin().await.context("Failed to begin project context transaction")?;
+        sqlx::query("DELETE FROM project_files WHERE conversation_id = ?")
+            .bind(&conversation_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete project files")?;
+        let result = sqlx::query("DELETE FROM project_contexts WHERE conversation_id = ?")
+            .bind(&conversation_id_text)
+            .execute(&mut *tx)
+            .await
+            .context("Failed to delete project context")?;
+        tx.commit().await.context("Failed to commit project context removal")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Drops the summary of the message's conversation if it covers the message, so an
+    // edit or delete isn't hidden behind a stale summary. Call before changing the message.
+    async fn invalidate_memory_covering(&self, message_id: Uuid) -> Result<(), anyhow::Error> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM conversation_memory
+            WHERE conversation_id IN (
+                SELECT m.conversation_id
+                FROM messages m JOIN conversation_memory cm ON cm.conversation_id = m.conversation_id
+                WHERE m.id = ? AND m.timestamp <= cm.covered_until
+            )
+            "#,
+        )
+        .bind(message_id.to_string())
+        .execute(&self.pool)
+        .await
+        .context("Failed to invalidate conversation memory")?;
+        if result.rows_affected() > 0 {
+            log::info!("Cleared conversation memory covering message {}", message_id);
+        }
+        Ok(())
+    }
+
+    /// Reads a value from the key-value settings table.
+    pub async fn get_setting(&self, key: &str) -> Result<Option<String>, anyhow::Error> {
+        log::debug!("Reading setting: {}", key);
+        let row = sqlx::query!("SELECT value FROM settings WHERE key = ?", key)
+            .fetch_optional(&self.pool)
+            .await
+            .context(format!("Failed to read setting '{}'", key))?;
+        Ok(row.map(|r| r.value))
+    }
+
+    /// Writes a value to the key-value settings table, replacing any existing value.
+    pub async fn set_setting(&self, key: &str, value: &str) -> Result<(), anyhow::Error> {
+        log::info!("Writing setting: {}", key);
+        sqlx::query!(
+            r#"
+            INSERT INTO settings (key, value) VALUES (?, ?)
+            ON CONFLICT(key) DO UPDATE SET value = excluded.value
+            "#,
+            key,
+            value
+        )
+        .execute(&self.pool)
+        .await
+        .context(format!("Failed to write setting '{}'", key))?;
+        Ok(())
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool // Make the pool accessible if needed elsewhere (removes dead code warning for pool)
+    }
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, contents, message, message_at, model_config};
+
+    #[tokio::test]
+    async fn new_with_url_migrates_an_in_memory_database() {
+        let storage = StorageManager::new_with_url("sqlite::memory:").await.unwrap();
+        assert!(storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap().is_empty());
+        assert!(storage.list_model_configs().await.unwrap().is_empty());
+        // Creating a conversation needs a model config to point at
+        assert!(storage.create_conversation().await.is_err());
+        // Migrating again finds nothing left to do
+        let report = storage.run_maintenance().await.unwrap();
+        assert!(report.columns_added.is_empty());
+    }
+
+    #[tokio::test]
+    async fn conversations_are_created_listed_and_deleted() {
+        let storage = test_support::storage().await;
+        let first = test_support::conversation(&storage).await;
+        let second = test_support::conversation(&storage).await;
+        let default_config = storage.list_model_configs().await.unwrap().remove(0);
+        assert_eq!(first.model_config_id, default_config.id);
+        assert_eq!(first.title, crate::title::DEFAULT_CONVERSATION_TITLE);
+
+        let listed: HashSet<Uuid> = storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(listed, HashSet::from([first.id, second.id]));
+        assert_eq!(storage.get_conversation(first.id).await.unwrap().unwrap().title, first.title);
+
+        assert!(storage.delete_conversation(first.id).await.unwrap());
+        assert!(storage.get_conversation(first.id).await.unwrap().is_none());
+        let remaining: Vec<Uuid> = storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect();
+        assert_eq!(remaining, vec![second.id]);
+        // A conversation that is already gone is reported as not found
+        assert!(!storage.delete_conversation(first.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn messages_are_returned_oldest_first() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let other = test_support::conversation(&storage).await;
+        storage.save_message(&message_at(conversation.id, "user", "first", 1_700_000_000)).await.unwrap();
+        storage.save_message(&message_at(other.id, "user", "elsewhere", 1_700_000_005)).await.unwrap();
+        storage.save_message(&message_at(conversation.id, "assistant", "second", 1_700_000_010)).await.unwrap();
+        storage.save_message(&message_at(conversation.id, "user", "third", 1_700_000_020)).await.unwrap();
+
+        assert_eq!(contents(&storage, conversation.id).await, ["first", "second", "third"]);
+        assert_eq!(contents(&storage, other.id).await, ["elsewhere"]);
+    }
+
+    #[tokio::test]
+    async fn saving_a_message_again_updates_it_in_place() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let mut answer = message(conversation.id, "assistant", "partial");
+        storage.save_message(&answer).await.unwrap();
+        storage.save_message(&message(conversation.id, "user", "next question")).await.unwrap();
+
+        answer.content = "partial, then complete".to_string();
+        storage.save_message(&answer).await.unwrap();
+
+        assert_eq!(contents(&storage, conversation.id).await, ["partial, then complete", "next question"]);
+        assert_eq!(storage.get_message(answer.id).await.unwrap().unwrap().content, answer.content);
+    }
+
+    #[tokio::test]
+    async fn model_configs_support_crud() {
+        let storage = test_support::storage().await;
+        let config = model_config("Local", r#"{"model": "llama3.2"}"#);
+        storage.add_model_config(&config).await.unwrap();
+
+        let configs = storage.list_model_configs().await.unwrap();
+        assert_eq!(configs.len(), 2);
+        let added = configs.iter().find(|c| c.id == config.id).unwrap();
+        assert_eq!(added.provider_options.as_deref(), Some(r#"{"model": "llama3.2"}"#));
+        // Added at the end of the list
+        assert_eq!(configs.last().unwrap().id, config.id);
+
+        let updated = ModelConfig { api_url: "http://localhost:11434/v1".to_string(), ..config.clone() };
+        storage.update_model_config(&updated).await.unwrap();
+        storage.rename_model_config(config.id, "Ollama").await.unwrap();
+        let configs = storage.list_model_configs().await.unwrap();
+        let stored = configs.iter().find(|c| c.id == config.id).unwrap();
+        assert_eq!(stored.name, "Ollama");
+        assert_eq!(stored.api_url, "http://localhost:11434/v1");
+
+        storage.delete_model_config(config.id).await.unwrap();
+        let configs = storage.list_model_configs().await.unwrap();
+        assert_eq!(configs.len(), 1);
+        assert!(configs.iter().all(|c| c.id != config.id));
+    }
+
+    #[tokio::test]
+    async fn updating_missing_rows_is_an_error() {
+        let storage = test_support::storage().await;
+        let missing = Uuid::new_v4();
+        let error = storage.rename_conversation(missing, "Renamed".to_string()).await.unwrap_err();
+        assert!(error.to_string().contains("not found"), "{}", error);
+        assert!(storage.rename_model_config(missing, "Renamed").await.is_err());
+        assert!(storage.update_model_config(&ModelConfig { id: missing, ..model_config("Ghost", "{}") }).await.is_err());
+        assert!(storage.update_conversation_model_id(missing, Uuid::new_v4()).await.is_err());
+        assert!(storage.update_message_content(missing, "text", None).await.is_err());
+        assert!(storage.update_message_metadata(missing, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn deleting_a_conversation_removes_what_belongs_to_it() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let kept = test_support::conversation(&storage).await;
+        let question = message(conversation.id, "user", "question");
+        storage.save_message(&question).await.unwrap();
+        storage.save_message(&message(conversation.id, "assistant", "answer")).await.unwrap();
+        storage.save_message(&message(kept.id, "user", "unrelated")).await.unwrap();
+        storage.save_conversation_memory(&ConversationMemory {
+            conversation_id: conversation.id,
+            summary: "A question was asked.".to_string(),
+            last_message_id: question.id,
+            covered_until: question.timestamp,
+            updated_at: Utc::now(),
+        }).await.unwrap();
+        storage.create_conversation_snapshot(conversation.id, "before").await.unwrap();
+        storage.rename_conversation(conversation.id, "Renamed".to_string()).await.unwrap();
+        storage.set_tool_policy("read_file", Some(conversation.id), crate::tools::ToolPolicy::Allow).await.unwrap();
+        storage.set_tool_policy("read_file", None, crate::tools::ToolPolicy::Ask).await.unwrap();
+        for id in [conversation.id, kept.id] {
+            sqlx::query("INSERT INTO project_contexts (conversation_id, root, include_globs, exclude_globs, updated_at) VALUES (?, '/src', '[]', '[]', 0)")
+                .bind(id.to_string())
+                .execute(&storage.pool)
+                .await
+                .unwrap();
+            sqlx::query("INSERT INTO project_files (conversation_id, path, status, size) VALUES (?, 'main.rs', 'included', 10)")
+                .bind(id.to_string())
+                .execute(&storage.pool)
+                .await
+                .unwrap();
+        }
+
+        assert!(storage.delete_conversation(conversation.id).await.unwrap());
+
+        let tables = [
+            "messages",
+            "conversation_memory",
+            "pending_jobs",
+            "conversation_snapshots",
+            "conversation_events",
+            "project_contexts",
+            "project_files",
+            "tool_permissions",
+        ];
+        for table in tables {
+            let left: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {} WHERE conversation_id = ?", table))
+                .bind(conversation.id.to_string())
+                .fetch_one(&storage.pool)
+                .await
+                .unwrap();
+            assert_eq!(left, 0, "{} still has rows of the deleted conversation", table);
+        }
+        assert!(storage.get_message(question.id).await.unwrap().is_none());
+        assert_eq!(contents(&storage, kept.id).await, ["unrelated"]);
+        assert_eq!(storage.get_project_files(kept.id, false).await.unwrap().len(), 1);
+        // The global policy isn't the conversation's to delete
+        assert_eq!(storage.get_tool_policy("read_file", kept.id).await.unwrap(), Some(crate::tools::ToolPolicy::Ask));
+        assert!(!storage.delete_conversation(conversation.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn message_name_round_trips() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let named = Message { name: Some("researcher".to_string()), ..message(conversation.id, "assistant", "Found it") };
+        let unnamed = message(conversation.id, "user", "Thanks");
+        storage.save_message(&named).await.unwrap();
+        storage.save_message(&unnamed).await.unwrap();
+
+        let messages = storage.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages[0].name.as_deref(), Some("researcher"));
+        assert_eq!(messages[1].name, None);
+    }
+
+    #[tokio::test]
+    async fn soft_deleted_conversations_can_be_restored() {
+        let storage = test_support::storage().await;
+        let kept = test_support::conversation(&storage).await;
+        let binned = test_support::conversation(&storage).await;
+        storage.save_message(&message(binned.id, "user", "Still here")).await.unwrap();
+
+        assert!(storage.soft_delete_conversation(binned.id).await.unwrap());
+        let listed: Vec<Uuid> = storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap().iter().map(|c| c.id).collect();
+        assert_eq!(listed, vec![kept.id]);
+        let deleted = storage.list_deleted_conversations().await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert!(deleted[0].deleted_at.is_some());
+        // Already in the bin, or never existed
+        assert!(!storage.soft_delete_conversation(binned.id).await.unwrap());
+        assert!(!storage.soft_delete_conversation(Uuid::new_v4()).await.unwrap());
+
+        storage.restore_conversation(binned.id).await.unwrap();
+        assert_eq!(storage.list_conversations(ConversationSort::LastUpdated, false).await.unwrap().len(), 2);
+        assert_eq!(contents(&storage, binned.id).await, vec!["Still here"]);
+        assert!(storage.restore_conversation(binned.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn purge_removes_only_conversations_binned_long_enough() {
+        let storage = test_support::storage().await;
+        let old = test_support::conversation(&storage).await;
+        let recent = test_support::conversation(&storage).await;
+        storage.save_message(&message(old.id, "user", "Old")).await.unwrap();
+        storage.soft_delete_conversation(old.id).await.unwrap();
+        storage.soft_delete_conversation(recent.id).await.unwrap();
+        let ten_days_ago = (Utc::now() - chrono::Duration::days(10)).timestamp();
+        sqlx::query("UPDATE conversations SET deleted_at = ? WHERE id = ?")
+            .bind(ten_days_ago)
+            .bind(old.id.to_string())
+            .execute(&storage.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(storage.purge_deleted_conversations(30).await.unwrap(), 0);
+        assert_eq!(storage.purge_deleted_conversations(7).await.unwrap(), 1);
+        assert!(storage.get_conversation(old.id).await.unwrap().is_none());
+        assert!(contents(&storage, old.id).await.is_empty());
+        let deleted: Vec<Uuid> = storage.list_deleted_conversations().await.unwrap().iter().map(|c| c.id).collect();
+        assert_eq!(deleted, vec![recent.id]);
+        // Zero days purges everything in the bin
+        assert_eq!(storage.purge_deleted_conversations(0).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn merging_appends_the_source_thread_and_removes_it() {
+        let storage = test_support::storage().await;
+        let target = test_support::conversation(&storage).await;
+        let source = test_support::conversation(&storage).await;
+        storage.save_message(&message_at(target.id, "user", "Target question", 2_000)).await.unwrap();
+        storage.save_message(&message_at(target.id, "assistant", "Target answer", 2_001)).await.unwrap();
+        // The source thread is older, yet lands after the target's last message
+        storage.save_message(&message_at(source.id, "user", "Source question", 1_000)).await.unwrap();
+        storage.save_message(&message_at(source.id, "assistant", "Source answer", 1_001)).await.unwrap();
+
+        storage.merge_conversations(source.id, target.id).await.unwrap();
+
+        assert_eq!(
+            contents(&storage, target.id).await,
+            vec!["Target question", "Target answer", "Source question", "Source answer"]
+        );
+        let timestamps: Vec<i64> = storage.get_conversation_messages(target.id).await.unwrap().iter().map(|m| m.timestamp.timestamp()).collect();
+        assert_eq!(timestamps, [2_000, 2_001, 1_000, 1_001]);
+        assert!(storage.get_conversation(source.id).await.unwrap().is_none());
+        assert!(contents(&storage, source.id).await.is_empty());
+        assert!(storage.merge_conversations(source.id, target.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn migrating_flags_the_first_config_as_default() {
+        let storage = StorageManager::new_with_url("sqlite::memory:").await.unwrap();
+        // Listed first by sort_order, though not by name
+        for name in ["Beta", "Alpha"] {
+            storage.add_model_config(&model_config(name, "{}")).await.unwrap();
+        }
+        // As saved by a build without the default flag
+        sqlx::query("UPDATE model_configs SET is_default = 0").execute(&storage.pool).await.unwrap();
+
+        storage.run_maintenance().await.unwrap();
+        let defaults: Vec<String> =
+            storage.list_model_configs().await.unwrap().into_iter().filter(|c| c.is_default).map(|c| c.name).collect();
+        assert_eq!(defaults, vec!["Beta"]);
+    }
+
+    #[tokio::test]
+    async fn deleting_the_default_config_promotes_the_next_one() {
+        let storage = StorageManager::new_with_url("sqlite::memory:").await.unwrap();
+        let first = ModelConfig { is_default: true, ..model_config("First", "{}") };
+        let second = model_config("Second", "{}");
+        let third = model_config("Another", "{}");
+        for config in [&first, &second, &third] {
+            storage.add_model_config(config).await.unwrap();
+        }
+        let default_name = |configs: Vec<ModelConfig>| configs.into_iter().find(|c| c.is_default).map(|c| c.name);
+
+        // Deleting another config leaves the flag where it is
+        storage.delete_model_config(third.id).await.unwrap();
+        assert_eq!(default_name(storage.list_model_configs().await.unwrap()).as_deref(), Some("First"));
+
+        storage.delete_model_config(first.id).await.unwrap();
+        assert_eq!(default_name(storage.list_model_configs().await.unwrap()).as_deref(), Some("Second"));
+        assert_eq!(storage.list_model_configs().await.unwrap().iter().filter(|c| c.is_default).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn bulk_saves_store_the_same_rows_as_single_saves() {
+        let storage = test_support::storage().await;
+        let one_by_one = test_support::conversation(&storage).await;
+        let bulk = test_support::conversation(&storage).await;
+        let thread = |conversation_id: Uuid| -> Vec<Message> {
+            (0..100)
+                .map(|i| {
+                    let role = if i % 2 == 0 { "user" } else { "assistant" };
+                    let mut message = message_at(conversation_id, role, &format!("Message {}", i), 1_000 + i / 3);
+                    if i % 10 == 0 {
+                        message.name = Some(format!("agent-{}", i));
+                        message.set_metadata_field("pinned", serde_json::json!(true));
+                    }
+                    message
+                })
+                .collect()
+        };
+        for message in thread(one_by_one.id) {
+            storage.save_message(&message).await.unwrap();
+        }
+        storage.save_messages(&thread(bulk.id)).await.unwrap();
+
+        let stored = |conversation_id: Uuid| {
+            let storage = &storage;
+            async move {
+                storage
+                    .get_conversation_messages(conversation_id)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|m| (m.role, m.content, m.timestamp, m.metadata, m.name, m.variant_group))
+                    .collect::<Vec<_>>()
+            }
+        };
+        let expected = stored(one_by_one.id).await;
+        assert_eq!(expected.len(), 100);
+        assert_eq!(stored(bulk.id).await, expected);
+        assert_eq!(expected[99].1, "Message 99");
+    }
+
+    #[tokio::test]
+    async fn message_counts_cover_every_live_conversation() {
+        let storage = test_support::storage().await;
+        let busy = test_support::conversation(&storage).await;
+        let quiet = test_support::conversation(&storage).await;
+        let empty = test_support::conversation(&storage).await;
+        let binned = test_support::conversation(&storage).await;
+        let mut messages: Vec<Message> = (0..3).map(|i| message(busy.id, "user", &format!("Busy {}", i))).collect();
+        messages.push(message(quiet.id, "user", "Quiet"));
+        messages.push(message(binned.id, "user", "Binned"));
+        storage.save_messages(&messages).await.unwrap();
+        storage.soft_delete_conversation(binned.id).await.unwrap();
+
+        let counts = storage.get_conversation_message_counts().await.unwrap();
+        assert_eq!(counts, HashMap::from([(busy.id, 3), (quiet.id, 1), (empty.id, 0)]));
+    }
+
+    #[tokio::test]
+    async fn messages_in_the_same_second_keep_insertion_order() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let expected: Vec<String> = (0..6).map(|i| format!("Turn {}", i)).collect();
+        for (i, content) in expected.iter().enumerate() {
+            let role = if i % 2 == 0 { "user" } else { "assistant" };
+            storage.save_message(&message_at(conversation.id, role, content, 1_700_000_000)).await.unwrap();
+        }
+        assert_eq!(contents(&storage, conversation.id).await, expected);
+
+        // Rows from before `seq` existed are numbered in the order they were inserted
+        sqlx::query("UPDATE messages SET seq = NULL").execute(&storage.pool).await.unwrap();
+        let report = storage.run_maintenance().await.unwrap();
+        assert_eq!(report.messages_sequenced, 6);
+        assert_eq!(contents(&storage, conversation.id).await, expected);
+        storage.save_message(&message_at(conversation.id, "user", "Turn 6", 1_700_000_000)).await.unwrap();
+        assert_eq!(contents(&storage, conversation.id).await.last().unwrap(), "Turn 6");
+    }
+
+    #[tokio::test]
+    async fn conversations_are_listed_in_each_sort_order() {
+        let storage = test_support::storage().await;
+        // (title, created_at, last_updated_at, message count)
+        let rows = [("banana", 100, 300, 1), ("Apple", 300, 100, 3), ("cherry", 200, 200, 0)];
+        for (title, created_at, last_updated_at, message_count) in rows {
+            let conversation = test_support::conversation(&storage).await;
+            let messages: Vec<Message> = (0..message_count).map(|i| message(conversation.id, "user", &i.to_string())).collect();
+            storage.save_messages(&messages).await.unwrap();
+            sqlx::query("UPDATE conversations SET title = ?, created_at = ?, last_updated_at = ? WHERE id = ?")
+                .bind(title)
+                .bind(created_at)
+                .bind(last_updated_at)
+                .bind(conversation.id.to_string())
+                .execute(&storage.pool)
+                .await
+                .unwrap();
+        }
+        let titles = |sort: ConversationSort, ascending: bool| {
+            let storage = &storage;
+            async move {
+                storage.list_conversations(sort, ascending).await.unwrap().into_iter().map(|c| c.title).collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(titles(ConversationSort::LastUpdated, false).await, ["banana", "cherry", "Apple"]);
+        assert_eq!(titles(ConversationSort::Created, false).await, ["Apple", "cherry", "banana"]);
+        assert_eq!(titles(ConversationSort::Created, true).await, ["banana", "cherry", "Apple"]);
+        // Titles sort case-insensitively
+        assert_eq!(titles(ConversationSort::Title, true).await, ["Apple", "banana", "cherry"]);
+        assert_eq!(titles(ConversationSort::MessageCount, false).await, ["Apple", "banana", "cherry"]);
+        assert_eq!(ConversationSort::parse("recent"), Ok(ConversationSort::LastUpdated));
+        assert!(ConversationSort::parse("pinned").is_err());
+    }
+
+    #[tokio::test]
+    async fn extending_a_message_keeps_its_place() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let answer = message_at(conversation.id, "assistant", "Once upon", 1_000);
+        storage.save_message(&message_at(conversation.id, "user", "Tell me a story", 999)).await.unwrap();
+        storage.save_message(&answer).await.unwrap();
+        storage.save_message(&message_at(conversation.id, "user", "Go on", 1_001)).await.unwrap();
+
+        storage.update_message_content(answer.id, "Once upon a time", Some(r#"{"finish_reason":"stop"}"#)).await.unwrap();
+
+        assert_eq!(contents(&storage, conversation.id).await, ["Tell me a story", "Once upon a time", "Go on"]);
+        let extended = storage.get_message(answer.id).await.unwrap().unwrap();
+        assert_eq!(extended.timestamp, answer.timestamp);
+        assert_eq!(extended.finish_reason().as_deref(), Some("stop"));
+        assert!(storage.update_message_content(Uuid::new_v4(), "Nothing", None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn date_ranges_are_inclusive_and_may_be_open_ended() {
+        let storage = test_support::storage().await;
+        for (title, last_updated_at) in [("Monday", 1_000), ("Tuesday", 2_000), ("Wednesday", 3_000)] {
+            let conversation = test_support::conversation(&storage).await;
+            sqlx::query("UPDATE conversations SET title = ?, last_updated_at = ? WHERE id = ?")
+                .bind(title)
+                .bind(last_updated_at)
+                .bind(conversation.id.to_string())
+                .execute(&storage.pool)
+                .await
+                .unwrap();
+        }
+        let between = |from: Option<i64>, to: Option<i64>| {
+            let storage = &storage;
+            async move {
+                storage
+                    .list_conversations_between(from.map(test_support::at), to.map(test_support::at))
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|c| c.title)
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        assert_eq!(between(Some(2_000), Some(3_000)).await, ["Wednesday", "Tuesday"]);
+        assert_eq!(between(Some(2_001), Some(2_999)).await, Vec::<String>::new());
+        assert_eq!(between(Some(2_000), None).await, ["Wednesday", "Tuesday"]);
+        assert_eq!(between(None, Some(2_000)).await, ["Tuesday", "Monday"]);
+        assert_eq!(between(None, None).await, ["Wednesday", "Tuesday", "Monday"]);
+    }
+
+    #[tokio::test]
+    async fn metadata_updates_merge_and_null_clears_a_key() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let object = |value: serde_json::Value| value.as_object().unwrap().clone();
+        assert_eq!(storage.get_conversation_metadata(conversation.id).await.unwrap(), Some(serde_json::Map::new()));
+
+        let ticket = object(serde_json::json!({"ticket": "https://example.com/T-1", "notes": "draft"}));
+        storage.merge_conversation_metadata(conversation.id, &ticket).await.unwrap();
+        let merged = storage.merge_conversation_metadata(conversation.id, &object(serde_json::json!({"notes": "final"}))).await.unwrap();
+        assert_eq!(merged, object(serde_json::json!({"ticket": "https://example.com/T-1", "notes": "final"})));
+
+        storage.merge_conversation_metadata(conversation.id, &object(serde_json::json!({"ticket": null}))).await.unwrap();
+        let stored = storage.get_conversation_metadata(conversation.id).await.unwrap();
+        assert_eq!(stored, Some(object(serde_json::json!({"notes": "final"}))));
+
+        assert_eq!(storage.get_conversation_metadata(Uuid::new_v4()).await.unwrap(), None);
+        assert!(storage.merge_conversation_metadata(Uuid::new_v4(), &object(serde_json::json!({"a": 1}))).await.is_err());
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected_or_truncated_by_policy() {
+        let long = message(Uuid::new_v4(), "user", "ééééé hello");
+        let short = message(long.conversation_id, "user", "ééééé");
+        let reject = MessageSizeLimit { max_chars: Some(5), policy: OversizedMessagePolicy::Reject };
+        let truncate = MessageSizeLimit { max_chars: Some(5), policy: OversizedMessagePolicy::Truncate };
+
+        // Five two-byte characters are over the limit in bytes but not in characters
+        assert!(reject.apply(&short).unwrap().is_none());
+        let err = reject.apply(&long).unwrap_err().to_string();
+        assert!(err.contains("11 characters, the limit is 5"), "{}", err);
+
+        let truncated = truncate.apply(&long).unwrap().expect("a truncated copy");
+        assert_eq!(truncated.content, "ééééé");
+        let metadata = truncated.metadata_map();
+        assert_eq!((metadata["content_truncated"].clone(), metadata["original_chars"].clone()), (serde_json::json!(true), serde_json::json!(11)));
+        assert!(truncate.apply(&short).unwrap().is_none());
+
+        let off = MessageSizeLimit { max_chars: None, policy: OversizedMessagePolicy::Reject };
+        assert!(off.apply(&long).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn snapshots_restore_the_messages_they_captured() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let question = message_at(conversation.id, "user", "Question", 1_000);
+        let first_answer = message_at(conversation.id, "assistant", "First answer", 1_001);
+        storage.save_messages(&[question.clone(), first_answer.clone()]).await.unwrap();
+
+        let snapshot = storage.create_conversation_snapshot(conversation.id, "Before edits").await.unwrap();
+        assert_eq!(snapshot.message_count, 2);
+
+        storage.update_message_content(first_answer.id, "Rewritten answer", None).await.unwrap();
+        storage.save_message(&message_at(conversation.id, "user", "Follow-up", 1_002)).await.unwrap();
+        assert_eq!(contents(&storage, conversation.id).await, ["Question", "Rewritten answer", "Follow-up"]);
+
+        assert_eq!(storage.restore_conversation_snapshot(snapshot.id).await.unwrap(), conversation.id);
+        assert_eq!(contents(&storage, conversation.id).await, ["Question", "First answer"]);
+        let restored = storage.get_message(first_answer.id).await.unwrap().unwrap();
+        assert_eq!(restored.timestamp, first_answer.timestamp);
+
+        // The snapshot is unchanged by the restore and can be restored again
+        storage.save_message(&message_at(conversation.id, "user", "Another try", 1_003)).await.unwrap();
+        storage.restore_conversation_snapshot(snapshot.id).await.unwrap();
+        assert_eq!(contents(&storage, conversation.id).await, ["Question", "First answer"]);
+        assert!(storage.restore_conversation_snapshot(Uuid::new_v4()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn a_fresh_database_passes_the_integrity_check() {
+        let storage = test_support::storage().await;
+        let report = storage.check_database_integrity().await.unwrap();
+        assert!(report.ok);
+        assert_eq!(report.integrity_check, ["ok"]);
+        assert!(report.foreign_key_violations.is_empty());
+
+        // A message whose conversation is gone, slipped in with enforcement off
+        sqlx::query("PRAGMA foreign_keys = OFF").execute(&storage.pool).await.unwrap();
+        storage.save_message(&message(Uuid::new_v4(), "user", "Orphan")).await.unwrap();
+        sqlx::query("PRAGMA foreign_keys = ON").execute(&storage.pool).await.unwrap();
+        let report = storage.check_database_integrity().await.unwrap();
+        assert!(!report.ok);
+        assert_eq!(report.integrity_check, ["ok"]);
+        assert_eq!(report.foreign_key_violations.len(), 1);
+        let violation = &report.foreign_key_violations[0];
+        assert_eq!((violation.table.as_str(), violation.parent.as_str()), ("messages", "conversations"));
+    }
+
+    #[tokio::test]
+    async fn compaction_shrinks_the_file_after_deletes() {
+        let dir = test_support::temp_dir();
+        let storage = StorageManager::new_with_url(&format!("sqlite://{}", dir.join("chats.db").display())).await.unwrap();
+        storage.add_default_model_config_if_none().await.unwrap();
+        let conversation = test_support::conversation(&storage).await;
+        let filler = "lorem ipsum ".repeat(400);
+        let messages: Vec<Message> = (0..200).map(|_| message(conversation.id, "user", &filler)).collect();
+        storage.save_messages(&messages).await.unwrap();
+        let filled = storage.database_size().await;
+        assert!(filled > 200 * filler.len() as u64, "{} bytes", filled);
+
+        sqlx::query("DELETE FROM messages").execute(&storage.pool).await.unwrap();
+        let report = storage.compact_database(true).await.unwrap();
+        assert!(report.size_before >= filled, "{:?}", report);
+        assert!(report.size_after > 0 && report.size_after < report.size_before / 4, "{:?}", report);
+        assert!(report.optimized);
+        assert_eq!(report.size_after, storage.database_size().await);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn answer_variants_are_grouped_and_selectable() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let question = message_at(conversation.id, "user", "Name a color", 1_000);
+        let first = message_at(conversation.id, "assistant", "Blue", 1_001);
+        storage.save_messages(&[question, first.clone()]).await.unwrap();
+
+        // A regeneration groups the old answer with the new one under the first answer's ID
+        let group = first.id;
+        let second = Message { variant_group: Some(group), ..message_at(conversation.id, "assistant", "Green", 1_002) };
+        storage.save_message(&second).await.unwrap();
+        storage.set_message_variant_group(first.id, group).await.unwrap();
+        storage.select_message_variant(group, second.id).await.unwrap();
+
+        let shown = |messages: Vec<Message>| -> Vec<String> {
+            messages.into_iter().filter(|m| !m.is_unselected_variant()).map(|m| m.content).collect()
+        };
+        let messages = storage.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(messages.iter().filter(|m| m.variant_group == Some(group)).count(), 2);
+        assert_eq!(shown(messages), ["Name a color", "Green"]);
+
+        storage.select_message_variant(group, first.id).await.unwrap();
+        assert_eq!(shown(storage.get_conversation_messages(conversation.id).await.unwrap()), ["Name a color", "Blue"]);
+        assert_eq!(contents(&storage, conversation.id).await, ["Name a color", "Blue", "Green"]);
+    }
+
+
+    #[tokio::test]
+    async fn conversation_search_matches_in_time_order_with_snippets() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let other = test_support::conversation(&storage).await;
+        let long = format!("{} the Borrow checker again {}", "a".repeat(100), "b".repeat(100));
+        let seeded = [
+            message_at(conversation.id, "user", "Why does the borrow checker complain?", 1_000),
+            message_at(conversation.id, "assistant", "Because of lifetimes.", 1_001),
+            message_at(other.id, "user", "borrow a cup of sugar", 1_002),
+            message_at(conversation.id, "user", &long, 1_003),
+            message_at(conversation.id, "assistant", "BORROW, borrow, borrow.", 1_004),
+        ];
+        for message in &seeded {
+            storage.save_message(message).await.unwrap();
+        }
+
+        let hits: Vec<_> = storage
+            .list_message_contents(conversation.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|(id, content)| crate::search::search_hit(id, &content, "borrow"))
+            .collect();
+        let ids: Vec<Uuid> = hits.iter().map(|hit| hit.message_id).collect();
+        assert_eq!(ids, [seeded[0].id, seeded[3].id, seeded[4].id]);
+        assert_eq!(hits.iter().map(|hit| hit.match_count).collect::<Vec<_>>(), [1, 1, 3]);
+
+        // The long message is cut to the context around its match, which is highlighted
+        let snippet = &hits[1].snippet;
+        assert_eq!(*snippet, format!("…{} the Borrow checker again {}…", "a".repeat(55), "b".repeat(45)));
+        let highlight = &hits[1].highlights[0];
+        assert_eq!((highlight.start, highlight.length), (61, 6));
+    }
+
+
+    #[tokio::test]
+    async fn role_updates_change_only_the_role() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let imported = message_at(conversation.id, "user", "I am the assistant's answer", 1_000);
+        storage.save_message(&imported).await.unwrap();
+
+        assert_eq!(storage.set_message_role(imported.id, "assistant").await.unwrap(), conversation.id);
+        let stored = storage.get_conversation_messages(conversation.id).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!((stored[0].id, stored[0].role.as_str(), stored[0].content.as_str()), (imported.id, "assistant", imported.content.as_str()));
+        assert_eq!((stored[0].timestamp, &stored[0].metadata), (imported.timestamp, &imported.metadata));
+        assert!(storage.set_message_role(Uuid::new_v4(), "assistant").await.is_err());
+    }
+
+
+    #[tokio::test]
+    async fn starter_configs_are_added_once() {
+        let storage = test_support::storage().await;
+        let created = storage.create_starter_configs().await.unwrap();
+        let names: Vec<&str> = created.iter().map(|config| config.name.as_str()).collect();
+        assert_eq!(names, ["OpenAI GPT-4.1", "Anthropic Claude Sonnet", "Ollama (localhost)"]);
+        for config in &created {
+            assert!(config.api_key_ref.as_deref().is_some_and(|key_ref| key_ref.starts_with("env:")));
+            assert!(crate::api::ProviderOptionsCheck::run(&config.provider, config.provider_options.as_deref()).ok, "{}", config.name);
+        }
+        assert_eq!(storage.list_model_configs().await.unwrap().len(), 4);
+
+        assert!(storage.create_starter_configs().await.unwrap().is_empty());
+        assert_eq!(storage.list_model_configs().await.unwrap().len(), 4);
+
+        // A config of the same name, in any case, is left in place of its starter
+        let storage = test_support::storage().await;
+        storage.add_model_config(&test_support::model_config("ollama (LOCALHOST)", r#"{"model": "qwen3"}"#)).await.unwrap();
+        let created = storage.create_starter_configs().await.unwrap();
+        assert_eq!(created.len(), 2);
+        assert!(created.iter().all(|config| config.name != "Ollama (localhost)"));
+    }
+
+
+    #[tokio::test]
+    async fn activity_is_bucketed_by_local_day() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let trashed = test_support::conversation(&storage).await;
+        let march_1 = 1_709_251_200; // 2024-03-01T00:00:00Z
+        let hour = 3600;
+        let day = 24 * hour;
+        for secs in [march_1 - 12 * hour, march_1 + hour, march_1 + 23 * hour, march_1 + day + 10 * hour, march_1 + 3 * day + 12 * hour] {
+            storage.save_message(&message_at(conversation.id, "user", "Hi", secs)).await.unwrap();
+        }
+        storage.save_message(&message_at(trashed.id, "user", "Gone", march_1 + hour)).await.unwrap();
+        storage.soft_delete_conversation(trashed.id).await.unwrap();
+        let date = |d: u32| chrono::NaiveDate::from_ymd_opt(2024, 3, d).unwrap();
+
+        let utc = storage.activity_counts(march_1, march_1 + 7 * day, 0).await.unwrap();
+        assert_eq!(utc, HashMap::from([(date(1), (2, 0)), (date(2), (1, 0)), (date(4), (1, 0))]));
+
+        // At UTC+2 the 23:00 message falls on the next day
+        let east = storage.activity_counts(march_1 - 2 * hour, march_1 + 7 * day - 2 * hour, 2 * hour).await.unwrap();
+        assert_eq!(east, HashMap::from([(date(1), (1, 0)), (date(2), (2, 0)), (date(4), (1, 0))]));
+    }
+
+
+    #[tokio::test]
+    async fn conversations_whose_model_config_is_gone_are_reported() {
+        let storage = test_support::storage().await;
+        let config = model_config("Gone", r#"{"model": "m"}"#);
+        storage.add_model_config(&config).await.unwrap();
+        test_support::conversation(&storage).await; // Keeps the default config
+        let orphaned = test_support::conversation(&storage).await;
+        storage.update_conversation_model_id(orphaned.id, config.id).await.unwrap();
+        assert!(storage.list_conversations_with_missing_model().await.unwrap().is_empty());
+
+        sqlx::query("DELETE FROM model_configs WHERE id = ?").bind(config.id.to_string()).execute(&storage.pool).await.unwrap();
+
+        let missing = storage.list_conversations_with_missing_model().await.unwrap();
+        assert_eq!(missing.iter().map(|conversation| conversation.id).collect::<Vec<_>>(), [orphaned.id]);
+    }
+
+
+    #[tokio::test]
+    async fn maintenance_run_again_changes_nothing() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        for content in ["One", "Two", "Three"] {
+            storage.save_message(&message(conversation.id, "user", content)).await.unwrap();
+        }
+        for name in ["Beta", "Alpha"] {
+            storage.add_model_config(&model_config(name, "{}")).await.unwrap();
+        }
+        // As left by an upgrade that stopped before its backfills
+        sqlx::query("UPDATE messages SET seq = NULL").execute(&storage.pool).await.unwrap();
+        sqlx::query("UPDATE model_configs SET sort_order = 0, is_default = 0").execute(&storage.pool).await.unwrap();
+
+        let first = storage.run_maintenance().await.unwrap();
+        assert_eq!((first.messages_sequenced, first.model_configs_reordered), (3, 2));
+        let state = || async {
+            let seqs: Vec<(String, i64)> = sqlx::query_as("SELECT id, seq FROM messages ORDER BY id").fetch_all(&storage.pool).await.unwrap();
+            let configs: Vec<(String, i64, bool)> =
+                sqlx::query_as("SELECT id, sort_order, is_default FROM model_configs ORDER BY id").fetch_all(&storage.pool).await.unwrap();
+            (seqs, configs)
+        };
+        let repaired = state().await;
+
+        let second = storage.run_maintenance().await.unwrap();
+        assert!(second.columns_added.is_empty());
+        assert_eq!((second.messages_sequenced, second.model_configs_reordered), (0, 0));
+        assert_eq!(state().await, repaired);
+        assert_eq!(contents(&storage, conversation.id).await, ["One", "Two", "Three"]);
+    }
+
+
+    #[tokio::test]
+    async fn reordering_messages_changes_only_their_order() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        let other = test_support::conversation(&storage).await;
+        let saved: Vec<Message> =
+            [("user", "Question", 1_000), ("assistant", "Answer", 1_001), ("note", "Move me up", 1_002)]
+                .into_iter()
+                .map(|(role, content, secs)| message_at(conversation.id, role, content, secs))
+                .collect();
+        for message in &saved {
+            storage.save_message(message).await.unwrap();
+        }
+        let stranger = message(other.id, "user", "Elsewhere");
+        storage.save_message(&stranger).await.unwrap();
+
+        storage.reorder_messages(conversation.id, &[saved[2].id, saved[0].id, saved[1].id]).await.unwrap();
+
+        let reordered = storage.get_conversation_messages(conversation.id).await.unwrap();
+        let order: Vec<(&str, i64)> = reordered.iter().map(|m| (m.content.as_str(), m.timestamp.timestamp())).collect();
+        assert_eq!(order, [("Move me up", 1_002), ("Question", 1_000), ("Answer", 1_001)]);
+        // Messages saved afterwards still come last
+        storage.save_message(&message_at(conversation.id, "user", "Follow-up", 900)).await.unwrap();
+        assert_eq!(contents(&storage, conversation.id).await, ["Move me up", "Question", "Answer", "Follow-up"]);
+
+        let ids: Vec<Uuid> = storage.get_conversation_messages(conversation.id).await.unwrap().iter().map(|m| m.id).collect();
+        for invalid in [&ids[..3], &[ids[0], ids[0], ids[1], ids[2]], &[ids[0], ids[1], ids[2], stranger.id]] {
+            assert!(storage.reorder_messages(conversation.id, invalid).await.is_err());
+        }
+        assert_eq!(contents(&storage, conversation.id).await, ["Move me up", "Question", "Answer", "Follow-up"]);
+    }
+
+
+    #[tokio::test]
+    async fn conversation_sizes_add_up_content_and_metadata_bytes() {
+        let storage = test_support::storage().await;
+        let large = test_support::conversation(&storage).await;
+        let small = test_support::conversation(&storage).await;
+        let empty = test_support::conversation(&storage).await;
+        storage.save_message(&message(large.id, "user", &"a".repeat(100))).await.unwrap();
+        let with_metadata = Message { metadata: Some(r#"{"a":1}"#.to_string()), ..message(large.id, "assistant", &"b".repeat(50)) };
+        storage.save_message(&with_metadata).await.unwrap();
+        // Counted in UTF-8 bytes, not characters
+        storage.save_message(&message(small.id, "user", &"é".repeat(10))).await.unwrap();
+        storage.soft_delete_conversation(small.id).await.unwrap();
+
+        let sizes = storage.conversation_sizes().await.unwrap();
+        let reported: Vec<(Uuid, i64, i64, i64, i64, bool)> = sizes
+            .iter()
+            .map(|size| (size.conversation_id, size.message_count, size.content_bytes, size.metadata_bytes, size.total_bytes, size.trashed))
+            .collect();
+        assert_eq!(reported, [(large.id, 2, 150, 7, 157, false), (small.id, 1, 20, 0, 20, true), (empty.id, 0, 0, 0, 0, false)]);
+    }
+
+
+    #[tokio::test]
+    async fn oldest_conversations_come_first_with_their_message_counts() {
+        let storage = test_support::storage().await;
+        let mut seeded = Vec::new();
+        for (message_count, last_updated_at) in [(1, 3_000), (3, 1_000), (0, 2_000), (2, 500)] {
+            let conversation = test_support::conversation(&storage).await;
+            for i in 0..message_count {
+                storage.save_message(&message(conversation.id, "user", &format!("Message {}", i))).await.unwrap();
+            }
+            seeded.push((conversation.id, last_updated_at));
+        }
+        let trashed = seeded[3].0;
+        storage.soft_delete_conversation(trashed).await.unwrap();
+        for (id, last_updated_at) in &seeded {
+            sqlx::query("UPDATE conversations SET last_updated_at = ? WHERE id = ?")
+                .bind(last_updated_at)
+                .bind(id.to_string())
+                .execute(&storage.pool)
+                .await
+                .unwrap();
+        }
+
+        let storage = &storage;
+        let listed = |limit| async move {
+            storage.list_oldest_conversations(limit).await.unwrap().iter()
+                .map(|oldest| (oldest.conversation.id, oldest.conversation.last_updated_at.timestamp(), oldest.message_count))
+                .collect::<Vec<_>>()
+        };
+        // The trashed conversation is left out, though it is the oldest
+        assert_eq!(listed(2).await, [(seeded[1].0, 1_000, 3), (seeded[2].0, 2_000, 0)]);
+        assert_eq!(listed(10).await, [(seeded[1].0, 1_000, 3), (seeded[2].0, 2_000, 0), (seeded[0].0, 3_000, 1)]);
+    }
+
+
+    #[tokio::test]
+    async fn message_rows_are_returned_exactly_as_stored() {
+        let storage = test_support::storage().await;
+        let conversation = test_support::conversation(&storage).await;
+        // Key order and spacing of the metadata survive, since it isn't parsed
+        let metadata = r#"{"b": 1,  "a": "ünïcode"}"#;
+        let saved = Message { metadata: Some(metadata.to_string()), ..message_at(conversation.id, "assistant", "Answer\nwith a newline", 1_700_000_000) };
+        storage.save_message(&saved).await.unwrap();
+
+        let row = storage.get_message_row(saved.id).await.unwrap().unwrap();
+        let column = |name: &str| {
+            let column = row.iter().find(|column| column.name == name).unwrap_or_else(|| panic!("no {} column", name));
+            (column.sqlite_type.as_str(), column.value.clone())
+        };
+        assert_eq!(row[0].name, "rowid");
+        assert_eq!(column("id"), ("TEXT", serde_json::json!(saved.id.to_string())));
+        assert_eq!(column("conversation_id"), ("TEXT", serde_json::json!(conversation.id.to_string())));
+        assert_eq!(column("role"), ("TEXT", serde_json::json!("assistant")));
+        assert_eq!(column("content"), ("TEXT", serde_json::json!("Answer\nwith a newline")));
+        assert_eq!(column("timestamp"), ("INTEGER", serde_json::json!(1_700_000_000)));
+        assert_eq!(column("metadata"), ("TEXT", serde_json::json!(metadata)));
+        assert_eq!(column("name"), ("NULL", serde_json::Value::Null));
+        assert_eq!(column("seq").0, "INTEGER");
+
+        assert!(storage.get_message_row(Uuid::new_v4()).await.unwrap().is_none());
+    }
+
+
+    fn refusal(outcome: UndoOutcome) -> String {
+        match outcome {
+            UndoOutcome::Refused(reason) => reason,
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn undo_restores_a_deleted_message_but_not_an_edit() {
+        let storage = test_support::storage().await;
+        let conv = test_support::conversation(&storage).await;
+        let (first, second, third) = (message_at(conv.id, "user", "a", 1), message_at(conv.id, "assistant", "b", 2), message_at(conv.id, "user", "c", 3));
+        storage.save_messages(&[first.clone(), second.clone(), third.clone()]).await.unwrap();
+
+        storage.delete_message(second.id).await.unwrap();
+        let UndoOutcome::Undone(event) = storage.undo_last_event(conv.id).await.unwrap() else {
+            panic!("the delete is undone");
+        };
+        assert_eq!(event.event_type, changelog::EVENT_MESSAGE_DELETE);
+        assert!(event.undone_at.is_some());
+        assert_eq!(contents(&storage, conv.id).await, ["a", "b", "c"]);
+        assert_eq!(storage.get_message(second.id).await.unwrap().unwrap().role, "assistant");
+        // The delete is spent; nothing older is left
+        assert!(matches!(storage.undo_last_event(conv.id).await.unwrap(), UndoOutcome::NothingToUndo));
+
+        storage.update_message_content(first.id, "a, edited", None).await.unwrap();
+        assert_eq!(refusal(storage.undo_last_event(conv.id).await.unwrap()), "The last change (message edit) can't be undone.");
+        assert_eq!(contents(&storage, conv.id).await, ["a, edited", "b", "c"]);
+        let events = storage.get_conversation_events(conv.id, 10).await.unwrap();
+        assert_eq!(events[0].event_type, changelog::EVENT_MESSAGE_EDIT);
+        assert!(events[0].undone_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn undo_refuses_changes_superseded_since() {
+        let storage = test_support::storage().await;
+        let conv = test_support::conversation(&storage).await;
+        let kept = message_at(conv.id, "user", "kept", 1);
+        let deleted = message_at(conv.id, "assistant", "deleted", 2);
+        storage.save_messages(&[kept.clone(), deleted.clone()]).await.unwrap();
+
+        // A message added after the delete makes restoring it ambiguous
+        storage.delete_message(deleted.id).await.unwrap();
+        storage.save_message(&message_at(conv.id, "user", "added later", 3)).await.unwrap();
+        assert_eq!(refusal(storage.undo_last_event(conv.id).await.unwrap()), "Messages were added after the delete.");
+        assert_eq!(contents(&storage, conv.id).await, ["kept", "added later"]);
+
+        // A title set without a rename event (as title generation does) supersedes the rename
+        storage.rename_conversation(conv.id, "Planning".to_string()).await.unwrap();
+        sqlx::query("UPDATE conversations SET title = 'Trip to Lisbon' WHERE id = ?").bind(conv.id.to_string()).execute(&storage.pool).await.unwrap();
+        assert_eq!(refusal(storage.undo_last_event(conv.id).await.unwrap()), "The title has changed since the rename.");
+        assert_eq!(storage.get_conversation(conv.id).await.unwrap().unwrap().title, "Trip to Lisbon");
+
+        let other = model_config("Other", "{}");
+        storage.add_model_config(&other).await.unwrap();
+        storage.update_conversation_model_id(conv.id, other.id).await.unwrap();
+        sqlx::query("UPDATE conversations SET model_override = 'gpt-4o' WHERE id = ?").bind(conv.id.to_string()).execute(&storage.pool).await.unwrap();
+        assert_eq!(refusal(storage.undo_last_event(conv.id).await.unwrap()), "The model has changed since the switch.");
+
+        // Refusals leave the event in place, to be undone once the conversation matches again
+        sqlx::query("UPDATE conversations SET model_override = NULL WHERE id = ?").bind(conv.id.to_string()).execute(&storage.pool).await.unwrap();
+        assert!(matches!(storage.undo_last_event(conv.id).await.unwrap(), UndoOutcome::Undone(_)));
+        assert_eq!(storage.get_conversation(conv.id).await.unwrap().unwrap().model_config_id, conv.model_config_id);
+    }
+}
+
@@ -0,0 +1,367 @@
+// Builders shared by the unit tests: an in-memory database set up like a fresh install,
+// unsaved messages to fill it with, and an `AppState` whose emitted events are recorded.
+
+use crate::api::LLMApiProvider;
+use crate::events::{self, StreamKind};
+use crate::generation::GenerationRequest;
+use crate::models::{Conversation, Message, ModelConfig};
+use crate::smoothing::StreamSmoothing;
+use crate::safe_mode::StorageStatus;
+use crate::state::{AppRuntime, AppState};
+use crate::storage::StorageManager;
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Listener, Manager, State};
+use uuid::Uuid;
+
+/// A private in-memory database holding the default model config, as after first start.
+pub async fn storage() -> StorageManager {
+    let storage = StorageManager::new_with_url("sqlite::memory:").await.expect("in-memory database opens");
+    storage.add_default_model_config_if_none().await.expect("default model config is added");
+    storage
+}
+
+/// A new conversation in `storage`.
+pub async fn conversation(storage: &StorageManager) -> Conversation {
+    storage.create_conversation().await.expect("conversation is created")
+}
+
+/// An unsaved message of `conversation_id`, timestamped now.
+pub fn message(conversation_id: Uuid, role: &str, content: &str) -> Message {
+    Message {
+        id: Uuid::new_v4(),
+        conversation_id,
+        role: role.to_string(),
+        content: content.to_string(),
+        timestamp: Utc::now(),
+        metadata: None,
+        name: None,
+        variant_group: None,
+    }
+}
+
+/// Like `message`, timestamped `secs` Unix seconds.
+pub fn message_at(conversation_id: Uuid, role: &str, content: &str, secs: i64) -> Message {
+    Message { timestamp: at(secs), ..message(conversation_id, role, content) }
+}
+
+/// A new empty directory under the system temp directory.
+pub fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localchat-test-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).expect("temp directory is created");
+    dir
+}
+
+/// The instant `secs` Unix seconds.
+pub fn at(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(secs, 0).expect("valid timestamp")
+}
+
+/// An unsaved OpenAI-compatible model config named `name` with `provider_options`.
+pub fn model_config(name: &str, provider_options: &str) -> ModelConfig {
+    ModelConfig {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        provider: "openai_compatible".to_string(),
+        api_url: "http://localhost:9/v1".to_string(),
+        api_key_ref: None,
+        provider_options: Some(provider_options.to_string()),
+        sort_order: 0,
+        is_default: false,
+    }
+}
+
+/// Contents of a conversation's stored messages, in display order.
+pub async fn contents(storage: &StorageManager, conversation_id: Uuid) -> Vec<String> {
+    storage
+        .get_conversation_messages(conversation_id)
+        .await
+        .expect("messages load")
+        .into_iter()
+        .map(|m| m.content)
+        .collect()
+}
+
+/// An `AppState` on Tauri's mock runtime, over an in-memory database, with the events it
+/// emits recorded. The state is managed by the app, so commands can be called directly.
+pub struct TestApp {
+    pub state: AppState,
+    pub events: EventLog,
+    app: tauri::App<AppRuntime>,
+}
+
+impl TestApp {
+    /// `provider` answers every model config, whatever its provider.
+    pub async fn new(provider: impl LLMApiProvider + 'static) -> Self {
+        Self::with_stream_limit(provider, 4).await
+    }
+
+    pub async fn with_stream_limit(provider: impl LLMApiProvider + 'static, max_concurrent_streams: usize) -> Self {
+        let app = tauri::test::mock_app();
+        let events = EventLog::listen(app.handle());
+        let state = AppState::new(
+            storage().await,
+            StorageStatus::Ready,
+            Arc::new(provider),
+            app.handle().clone(),
+            max_concurrent_streams,
+        );
+        app.manage(state.clone());
+        Self { state, events, app }
+    }
+
+    /// The managed state, as commands receive it.
+    pub fn command_state(&self) -> State<'_, AppState> {
+        self.app.state::<AppState>()
+    }
+
+    /// A model config whose key resolves, saved to storage under a unique name.
+    pub async fn model_config(&self, provider_options: &str) -> ModelConfig {
+        std::env::set_var(TEST_API_KEY_VAR, "test-key");
+        let config = model_config("", provider_options);
+        let config = ModelConfig {
+            name: format!("Test model {}", config.id),
+            api_key_ref: Some(format!("env:{}", TEST_API_KEY_VAR)),
+            ..config
+        };
+        self.state.storage.lock().await.add_model_config(&config).await.expect("model config is saved");
+        config
+    }
+
+    /// A request answering `history` in `conversation` with `model_config`, as `send_message` builds it.
+    pub fn request(&self, conversation: &Conversation, model_config: &ModelConfig, history: Vec<Message>) -> GenerationRequest {
+        GenerationRequest {
+            conversation: conversation.clone(),
+            model_config: model_config.clone(),
+            summarizer: None,
+            smoothing: StreamSmoothing::default(),
+            trim_leading_whitespace: true,
+            preview: false,
+            system_message: message(conversation.id, "system", "You are helpful."),
+            persona: None,
+            user_message_id: history.last().map(|m| m.id),
+            history,
+            kind: StreamKind::Send,
+            replaces: None,
+            extends: None,
+            comparison: None,
+        }
+    }
+
+    /// Saves a user message to `conversation` and returns it.
+    pub async fn user_message(&self, conversation: &Conversation, content: &str) -> Message {
+        let user_message = message(conversation.id, "user", content);
+        self.state.storage.lock().await.save_message(&user_message).await.expect("user message is saved");
+        user_message
+    }
+}
+
+// Environment variable the test model configs read their API key from
+const TEST_API_KEY_VAR: &str = "LOCALCHAT_TEST_API_KEY";
+
+// Events the log records
+const RECORDED_EVENTS: &[&str] = &[
+    events::ASSISTANT_STREAM_STARTED,
+    events::REGENERATION_COMPLETE,
+    events::BUDGET_WARNING,
+    events::BUDGET_EXCEEDED,
+    events::GENERATION_CANCELLED,
+    events::GENERATION_FAILED,
+    events::GENERATION_PROGRESS,
+    events::ASSISTANT_ANNOTATIONS,
+    events::CONVERSATION_UPDATED,
+    events::API_KEY_MISSING,
+    events::SUMMARY_CHUNK,
+    events::SUMMARY_FINISHED,
+    events::CONTENT_FILTERED,
+    "assistant_message_chunk",
+    "assistant_stream_finished",
+    "assistant_tool_call",
+    events::ASSISTANT_TOOL_REQUEST,
+    events::PROMPTS_CHANGED,
+    "library_changed",
+];
+
+/// Events emitted on an app handle, in order, with their JSON payloads.
+#[derive(Clone, Default)]
+pub struct EventLog {
+    events: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+}
+
+impl EventLog {
+    fn listen(app_handle: &AppHandle<AppRuntime>) -> Self {
+        let log = Self::default();
+        for &name in RECORDED_EVENTS {
+            let events = log.events.clone();
+            app_handle.listen_any(name, move |event| {
+                let payload = serde_json::from_str(event.payload()).unwrap_or(serde_json::Value::Null);
+                events.lock().unwrap().push((name.to_string(), payload));
+            });
+        }
+        log
+    }
+
+    /// Names of the events so far.
+    pub fn names(&self) -> Vec<String> {
+        self.events.lock().unwrap().iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    /// Payloads of the `name` events so far.
+    pub fn payloads(&self, name: &str) -> Vec<serde_json::Value> {
+        self.events.lock().unwrap().iter().filter(|(n, _)| n == name).map(|(_, p)| p.clone()).collect()
+    }
+
+    /// Concatenated `delta`s of the chunk events so far.
+    pub fn streamed_text(&self) -> String {
+        self.payloads("assistant_message_chunk")
+            .iter()
+            .filter_map(|chunk| chunk["delta"].as_str().map(str::to_string))
+            .collect()
+    }
+
+    /// Waits (up to five seconds) until a `name` event has been emitted.
+    pub async fn wait_for(&self, name: &str) -> serde_json::Value {
+        for _ in 0..500 {
+            if let Some(payload) = self.payloads(name).pop() {
+                return payload;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no {} event within five seconds; got {:?}", name, self.names());
+    }
+}
+
+/// A request the `MockServer` received.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub path: String, // With the query string, as sent
+    pub headers: Vec<(String, String)>, // Names lowercased
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str())
+    }
+
+    /// The body, parsed as JSON.
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).expect("request body is JSON")
+    }
+}
+
+/// A canned answer of the `MockServer`.
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl MockResponse {
+    pub fn json(body: serde_json::Value) -> Self {
+        Self { status: 200, headers: vec![("Content-Type".to_string(), "application/json".to_string())], body: body.to_string() }
+    }
+
+    /// A server-sent event stream of `chunks`, ended with `[DONE]`.
+    pub fn sse(chunks: &[serde_json::Value]) -> Self {
+        let mut body: String = chunks.iter().map(|chunk| format!("data: {}\n\n", chunk)).collect();
+        body.push_str("data: [DONE]\n\n");
+        Self { status: 200, headers: vec![("Content-Type".to_string(), "text/event-stream".to_string())], body }
+    }
+
+    /// Server-sent events of `chunks` after which the connection drops: the response announces
+    /// more bytes than it sends and never reaches `[DONE]`.
+    pub fn sse_dropped(chunks: &[serde_json::Value]) -> Self {
+        let body: String = chunks.iter().map(|chunk| format!("data: {}\n\n", chunk)).collect();
+        let announced = (body.len() + 1024).to_string();
+        let headers = vec![
+            ("Content-Type".to_string(), "text/event-stream".to_string()),
+            ("Content-Length".to_string(), announced),
+        ];
+        Self { status: 200, headers, body }
+    }
+}
+
+/// An HTTP server on a local port answering requests with `responses` in turn (the last one
+/// repeats) and recording them. One request per connection.
+pub struct MockServer {
+    pub url: String, // http://127.0.0.1:<port>
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockServer {
+    pub async fn start(responses: Vec<MockResponse>) -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("mock server binds");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests: Arc<Mutex<Vec<RecordedRequest>>> = Arc::default();
+        let recorded = requests.clone();
+        tokio::spawn(async move {
+            for served in 0.. {
+                let Ok((stream, _)) = listener.accept().await else {
+                    break;
+                };
+                let response = responses[served.min(responses.len() - 1)].clone();
+                tokio::spawn(answer(stream, response, recorded.clone()));
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// Requests received so far, in order.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+// Reads one request from `stream`, records it and writes `response`
+async fn answer(mut stream: tokio::net::TcpStream, response: MockResponse, recorded: Arc<Mutex<Vec<RecordedRequest>>>) -> Option<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let request = loop {
+        let read = stream.read(&mut chunk).await.ok()?;
+        if read == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..read]);
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let Ok(httparse::Status::Complete(header_len)) = parsed.parse(&buf) else {
+            continue;
+        };
+        let path = parsed.path.unwrap_or_default().to_string();
+        let headers: Vec<(String, String)> = parsed.headers.iter()
+            .map(|h| (h.name.to_ascii_lowercase(), String::from_utf8_lossy(h.value).to_string()))
+            .collect();
+        let content_length = headers.iter()
+            .find(|(name, _)| name == "content-length")
+            .and_then(|(_, value)| value.trim().parse().ok())
+            .unwrap_or(0);
+        while buf.len() < header_len + content_length {
+            let read = stream.read(&mut chunk).await.ok()?;
+            if read == 0 {
+                return None;
+            }
+            buf.extend_from_slice(&chunk[..read]);
+        }
+        let body = String::from_utf8_lossy(&buf[header_len..header_len + content_length]).to_string();
+        break RecordedRequest { path, headers, body };
+    };
+    recorded.lock().unwrap().push(request);
+
+    let mut head = format!("HTTP/1.1 {} Mock\r\nConnection: close\r\n", response.status);
+    // A `Content-Length` of the response's own overrides the body's, see `sse_dropped`
+    if !response.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("content-length")) {
+        head.push_str(&format!("Content-Length: {}\r\n", response.body.len()));
+    }
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await.ok()?;
+    stream.write_all(response.body.as_bytes()).await.ok()?;
+    stream.shutdown().await.ok()
+}
@@ -0,0 +1,174 @@
+// Light/dark appearance: the user's override ("system", "light" or "dark") is stored in
+// settings and applied to the native windows, including the macOS titlebar overlay. The OS
+// theme is tracked from window theme events so `get_system_theme` can report it even while
+// an override is active, and `theme_changed` tells the frontend when the result changes.
+// The macOS title bar style is stored and applied here too.
+
+use crate::config;
+use crate::events;
+use crate::state::AppRuntime;
+use crate::storage::StorageManager;
+use serde::Serialize;
+use std::sync::RwLock;
+use tauri::{AppHandle, Emitter, Theme};
+#[cfg(target_os = "macos")]
+use tauri::{Manager, TitleBarStyle};
+
+// Last theme the OS reported; None until a window has told us
+static SYSTEM_THEME: RwLock<Option<Theme>> = RwLock::new(None);
+
+/// The user's appearance choice, stored in the `theme` setting.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreference {
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+impl ThemePreference {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "system" => Ok(Self::System),
+            "light" => Ok(Self::Light),
+            "dark" => Ok(Self::Dark),
+            other => Err(format!("Unknown theme: {}", other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::Light => "light",
+            Self::Dark => "dark",
+        }
+    }
+
+    // The theme forced on windows; None follows the OS
+    fn window_theme(self) -> Option<Theme> {
+        match self {
+            Self::System => None,
+            Self::Light => Some(Theme::Light),
+            Self::Dark => Some(Theme::Dark),
+        }
+    }
+}
+
+/// Payload of `get_system_theme` and `theme_changed`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ThemeInfo {
+    pub preference: ThemePreference,
+    pub system: Option<&'static str>, // "light" | "dark"; None when the OS hasn't reported one
+    pub effective: &'static str, // What the app shows: the override, else the OS theme (light if unknown)
+}
+
+fn theme_name(theme: Theme) -> &'static str {
+    match theme {
+        Theme::Dark => "dark",
+        _ => "light",
+    }
+}
+
+pub fn system_theme() -> Option<Theme> {
+    SYSTEM_THEME.read().ok().and_then(|theme| *theme)
+}
+
+/// Records the theme the OS reports, e.g. from a window's `ThemeChanged` event.
+pub fn record_system_theme(theme: Theme) {
+    if let Ok(mut current) = SYSTEM_THEME.write() {
+        *current = Some(theme);
+    }
+}
+
+pub fn theme_info(preference: ThemePreference) -> ThemeInfo {
+    let system = system_theme().map(theme_name);
+    let effective = match preference {
+        ThemePreference::System => system.unwrap_or("light"),
+        ThemePreference::Light => "light",
+        ThemePreference::Dark => "dark",
+    };
+    ThemeInfo { preference, system, effective }
+}
+
+/// The saved override; System when unset or unreadable.
+pub async fn load_preference(storage: &StorageManager) -> ThemePreference {
+    storage.get_setting(config::THEME_KEY).await
+        .map_err(|e| log::warn!("Failed to read setting '{}', using the default: {:?}", config::THEME_KEY, e))
+        .ok()
+        .flatten()
+        .and_then(|value| ThemePreference::parse(&value).ok())
+        .unwrap_or_default()
+}
+
+/// Applies `preference` to every window.
+pub fn apply(app_handle: &AppHandle<AppRuntime>, preference: ThemePreference) {
+    app_handle.set_theme(preference.window_theme());
+}
+
+/// Sends `theme_changed` with the current theme info.
+pub fn emit_changed(app_handle: &AppHandle<AppRuntime>, preference: ThemePreference) {
+    if let Err(e) = app_handle.emit(events::THEME_CHANGED, theme_info(preference)) {
+        log::error!("Failed to emit theme changed event: {:?}", e);
+    }
+}
+
+/// Title bar style of the main window, stored in the `titlebar_style` setting. Only macOS
+/// applies it; other platforms keep their native title bar.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleBarPreference {
+    #[default]
+    Overlay,
+    Transparent,
+    Visible,
+}
+
+impl TitleBarPreference {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "overlay" => Ok(Self::Overlay),
+            "transparent" => Ok(Self::Transparent),
+            "visible" => Ok(Self::Visible),
+            other => Err(format!("Unknown title bar style: {}", other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Overlay => "overlay",
+            Self::Transparent => "transparent",
+            Self::Visible => "visible",
+        }
+    }
+}
+
+/// The saved title bar style; Overlay when unset or unreadable.
+pub async fn load_titlebar_preference(storage: &StorageManager) -> TitleBarPreference {
+    storage.get_setting(config::TITLEBAR_STYLE_KEY).await
+        .map_err(|e| log::warn!("Failed to read setting '{}', using the default: {:?}", config::TITLEBAR_STYLE_KEY, e))
+        .ok()
+        .flatten()
+        .and_then(|value| TitleBarPreference::parse(&value).ok())
+        .unwrap_or_default()
+}
+
+/// Applies `preference` to the main window. Returns false without doing anything on
+/// platforms other than macOS.
+#[cfg(target_os = "macos")]
+pub fn apply_titlebar(app_handle: &AppHandle<AppRuntime>, preference: TitleBarPreference) -> Result<bool, String> {
+    let window = app_handle.get_webview_window("main").ok_or("Main window not found")?;
+    let style = match preference {
+        TitleBarPreference::Overlay => TitleBarStyle::Overlay,
+        TitleBarPreference::Transparent => TitleBarStyle::Transparent,
+        TitleBarPreference::Visible => TitleBarStyle::Visible,
+    };
+    window.set_title_bar_style(style).map_err(|e| format!("Failed to set the title bar style: {}", e))?;
+    Ok(true)
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn apply_titlebar(_app_handle: &AppHandle<AppRuntime>, _preference: TitleBarPreference) -> Result<bool, String> {
+    Ok(false)
+}
@@ -0,0 +1,230 @@
+// Prompt and sanitation for generated conversation titles
+
+use crate::config;
+use crate::language;
+use crate::storage::StorageManager;
+use serde::Serialize;
+
+pub const DEFAULT_TITLE_MAX_CHARS: usize = 30;
+pub const TITLE_MAX_CHARS_LIMIT: usize = 200; // Upper bound accepted from the frontend
+
+// Title new conversations start with; only these get an automatic title
+pub const DEFAULT_CONVERSATION_TITLE: &str = "new chat";
+// Messages (user and assistant) a conversation has when it gets its automatic title
+pub const DEFAULT_TITLE_AFTER_MESSAGES: usize = 2;
+pub const TITLE_AFTER_MESSAGES_LIMIT: usize = 100;
+
+// Short words title case leaves lowercase unless they start the title
+const TITLE_CASE_MINOR_WORDS: &[&str] = &["a", "an", "and", "as", "at", "but", "by", "for", "in", "of", "on", "or", "the", "to", "vs"];
+
+/// Casing applied to generated titles, stored in the `title_style` setting.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TitleCase {
+    #[default]
+    Lowercase, // "rust borrow checker errors"
+    Sentence, // "Rust borrow checker errors"
+    #[serde(rename = "title")]
+    TitleCase, // "Rust Borrow Checker Errors"
+}
+
+impl TitleCase {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "lowercase" => Ok(Self::Lowercase),
+            "sentence" => Ok(Self::Sentence),
+            "title" => Ok(Self::TitleCase),
+            other => Err(format!("Unknown title style: {}", other)),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lowercase => "lowercase",
+            Self::Sentence => "sentence",
+            Self::TitleCase => "title",
+        }
+    }
+
+    // How the prompt describes the casing
+    fn rule(self) -> &'static str {
+        match self {
+            Self::Lowercase => "lowercase except for proper nouns",
+            Self::Sentence => "in sentence case (only the first word and proper nouns capitalized)",
+            Self::TitleCase => "in title case",
+        }
+    }
+}
+
+/// Title settings as stored in the settings table.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TitleSettings {
+    pub max_chars: usize,
+    pub style: TitleCase,
+    pub emoji: bool, // Start titles with an emoji
+    pub after_messages: usize, // Message count that triggers the automatic title
+}
+
+impl Default for TitleSettings {
+    fn default() -> Self {
+        Self {
+            max_chars: DEFAULT_TITLE_MAX_CHARS,
+            style: TitleCase::default(),
+            emoji: false,
+            after_messages: DEFAULT_TITLE_AFTER_MESSAGES,
+        }
+    }
+}
+
+/// The saved title settings; unset or unreadable values keep their defaults.
+pub async fn load_settings(storage: &StorageManager) -> TitleSettings {
+    let read = |key: &'static str| async move {
+        storage.get_setting(key).await
+            .map_err(|e| log::warn!("Failed to read setting '{}', using the default: {:?}", key, e))
+            .ok()
+            .flatten()
+    };
+    let defaults = TitleSettings::default();
+    TitleSettings {
+        max_chars: read(config::TITLE_MAX_CHARS_KEY).await
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|max| (1..=TITLE_MAX_CHARS_LIMIT).contains(max))
+            .unwrap_or(defaults.max_chars),
+        style: read(config::TITLE_STYLE_KEY).await
+            .and_then(|value| TitleCase::parse(&value).ok())
+            .unwrap_or(defaults.style),
+        emoji: read(config::TITLE_EMOJI_KEY).await.map_or(defaults.emoji, |value| value == "true"),
+        after_messages: read(config::TITLE_AFTER_MESSAGES_KEY).await
+            .and_then(|value| value.parse::<usize>().ok())
+            .filter(|count| (1..=TITLE_AFTER_MESSAGES_LIMIT).contains(count))
+            .unwrap_or(defaults.after_messages),
+    }
+}
+
+/// Whether a conversation is due its automatic title: it still has the default title and
+/// its message count has just reached `after_messages`, so later messages don't retrigger it.
+pub fn is_due_auto_title(title: &str, message_count: usize, after_messages: usize) -> bool {
+    message_count == after_messages && title.trim().eq_ignore_ascii_case(DEFAULT_CONVERSATION_TITLE)
+}
+
+/// How a generated title should be written.
+#[derive(Debug, Clone)]
+pub struct TitleStyle {
+    pub instruction: Option<String>, // Extra style instruction from the user, e.g. "prefix an emoji"
+    pub max_chars: usize,
+    pub case: TitleCase,
+    pub emoji: bool,
+    pub language: Option<String>, // Conversation language code; None leaves the choice to the model
+}
+
+impl TitleStyle {
+    pub fn from_settings(settings: &TitleSettings) -> Self {
+        Self {
+            instruction: None,
+            max_chars: settings.max_chars,
+            case: settings.style,
+            emoji: settings.emoji,
+            language: None,
+        }
+    }
+}
+
+/// System prompt for the utility model. A user instruction overrides the default style
+/// rules but not the length limit, which `sanitize_title` enforces anyway.
+pub fn title_system_prompt(style: &TitleStyle) -> String {
+    let mut prompt = format!(
+        "You are an expert conversation summarizer. Generate a concise, relevant title for the following conversation exchange. The title must be {}, maximum {} characters long, and contain only the title itself with no extra text or quotes.",
+        style.case.rule(),
+        style.max_chars
+    );
+    if style.emoji {
+        prompt.push_str(" Start the title with a single emoji that fits the topic, followed by a space.");
+    }
+    if let Some(code) = style.language.as_deref() {
+        let name = language::language_name(code).unwrap_or(code);
+        prompt.push_str(&format!(" Write the title in {}.", name));
+    }
+    if let Some(instruction) = style.instruction.as_deref().map(str::trim).filter(|i| !i.is_empty()) {
+        prompt.push_str(&format!(
+            "\n\nAlso follow this instruction from the user. It takes precedence over the style rules above, but the title must still be at most {} characters: {}",
+            style.max_chars, instruction
+        ));
+    }
+    prompt
+}
+
+/// Strips whitespace and quotes from the model's answer and applies the style's casing.
+/// None when the result is empty or longer than `max_chars` characters (not bytes).
+pub fn sanitize_title(raw: &str, style: &TitleStyle) -> Option<String> {
+    let title = raw.trim().trim_matches('"').trim();
+    if title.is_empty() || title.chars().count() > style.max_chars {
+        return None;
+    }
+    Some(apply_case(title, style.case))
+}
+
+/// Recases `title` word by word, so models that ignore the instruction still match the
+/// sidebar. Words with a capital past their first letter ("API", "GitHub") are kept as written.
+pub fn apply_case(title: &str, case: TitleCase) -> String {
+    let mut first_word = true;
+    title
+        .split(' ')
+        .map(|word| {
+            if !word.chars().any(char::is_alphabetic) {
+                return word.to_string();
+            }
+            let is_first = std::mem::replace(&mut first_word, false);
+            if word.chars().skip(1).any(char::is_uppercase) {
+                return word.to_string();
+            }
+            let lower = word.to_lowercase();
+            let capitalize = match case {
+                TitleCase::Lowercase => false,
+                TitleCase::Sentence => is_first,
+                TitleCase::TitleCase => is_first || !TITLE_CASE_MINOR_WORDS.contains(&lower.as_str()),
+            };
+            if capitalize { capitalize_first_letter(&lower) } else { lower }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Uppercases the first alphabetic character, leaving any leading emoji or punctuation alone
+fn capitalize_first_letter(word: &str) -> String {
+    match word.char_indices().find(|(_, c)| c.is_alphabetic()) {
+        Some((index, c)) => format!("{}{}{}", &word[..index], c.to_uppercase(), &word[index + c.len_utf8()..]),
+        None => word.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(instruction: Option<&str>, max_chars: usize) -> TitleStyle {
+        TitleStyle { instruction: instruction.map(str::to_string), max_chars, ..TitleStyle::from_settings(&TitleSettings::default()) }
+    }
+
+    #[test]
+    fn instruction_is_added_to_the_prompt() {
+        let prompt = title_system_prompt(&style(Some("  Write it in French. "), 40));
+        assert!(prompt.contains("maximum 40 characters"), "{}", prompt);
+        assert!(prompt.ends_with("must still be at most 40 characters: Write it in French."), "{}", prompt);
+
+        let plain = title_system_prompt(&style(None, 40));
+        assert!(!plain.contains("instruction from the user"));
+        assert_eq!(title_system_prompt(&style(Some("   "), 40)), plain);
+    }
+
+    #[test]
+    fn titles_over_the_cap_are_rejected() {
+        let style = style(None, 10);
+        assert_eq!(sanitize_title(" \"Rust tips\" ", &style).as_deref(), Some("rust tips"));
+        assert_eq!(sanitize_title("Rust borrowing", &style), None);
+        // The cap counts characters, not bytes
+        assert_eq!(sanitize_title("Été à Nîmes", &TitleStyle { max_chars: 11, ..style.clone() }).as_deref(), Some("été à nîmes"));
+        assert_eq!(sanitize_title("  \"\" ", &style), None);
+    }
+}
+
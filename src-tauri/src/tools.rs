@@ -0,0 +1,128 @@
+// Permission checks for tool calls requested by the model. Generation runs every call through
+// `authorize_tool_calls` as the stream hands it over, so `assistant_tool_call` only carries
+// calls the user's policies allow; denied ones are kept on the message as `denied_tool_calls`.
+
+use crate::api::ToolCall;
+use crate::events::{self, ToolPermissionRequest};
+use crate::state::AppState;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+// How long a tool call waits for the user before it is denied
+pub const PERMISSION_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Whether a tool may run. Tools without a stored policy are treated as `Ask`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolPolicy {
+    Allow,
+    Deny,
+    Ask,
+}
+
+impl ToolPolicy {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            "ask" => Ok(Self::Ask),
+            other => Err(format!("Unknown tool policy: {}", other)),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::Deny => "deny",
+            Self::Ask => "ask",
+        }
+    }
+}
+
+/// A stored policy; `conversation_id` is None for the global one.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolPermission {
+    pub tool_name: String,
+    pub conversation_id: Option<String>,
+    pub policy: ToolPolicy,
+}
+
+/// A tool call parked until the user answers its `assistant_tool_request`.
+pub struct PendingToolRequest {
+    pub conversation_id: Uuid,
+    pub tool_name: String,
+    pub responder: oneshot::Sender<bool>,
+}
+
+/// Splits `calls` into those that may run and those denied, asking the user one call at a time.
+pub async fn authorize_tool_calls(state: &AppState, conversation_id: Uuid, message_id: Uuid, calls: Vec<ToolCall>) -> (Vec<ToolCall>, Vec<ToolCall>) {
+    let mut allowed = Vec::new();
+    let mut denied = Vec::new();
+    for call in calls {
+        if authorize_tool_call(state, conversation_id, message_id, &call).await {
+            allowed.push(call);
+        } else {
+            denied.push(call);
+        }
+    }
+    (allowed, denied)
+}
+
+/// Resolves the policy for `call` and, when it is `Ask`, waits for `respond_tool_permission`.
+/// Returns whether the call may run; lookup failures, timeouts and dropped requests deny.
+async fn authorize_tool_call(state: &AppState, conversation_id: Uuid, message_id: Uuid, call: &ToolCall) -> bool {
+    let tool_name = call.function.name.as_str();
+    let policy = {
+        let storage = state.storage.lock().await;
+        storage.get_tool_policy(tool_name, conversation_id).await
+    };
+    let policy = match policy {
+        Ok(policy) => policy.unwrap_or(ToolPolicy::Ask),
+        Err(e) => {
+            log::error!("Failed to read policy for tool '{}', denying: {:?}", tool_name, e);
+            return false;
+        }
+    };
+    match policy {
+        ToolPolicy::Allow => return true,
+        ToolPolicy::Deny => {
+            log::info!("Tool '{}' denied by policy in conversation {}", tool_name, conversation_id);
+            return false;
+        }
+        ToolPolicy::Ask => {}
+    }
+
+    let request_id = Uuid::new_v4();
+    let (responder, response) = oneshot::channel();
+    state.pending_tool_requests.insert(
+        request_id,
+        PendingToolRequest { conversation_id, tool_name: tool_name.to_string(), responder },
+    );
+    let payload = ToolPermissionRequest {
+        request_id: request_id.to_string(),
+        conversation_id: conversation_id.to_string(),
+        message_id: message_id.to_string(),
+        tool_call_id: call.id.clone(),
+        tool_name: tool_name.to_string(),
+        arguments: call.function.arguments.clone(),
+        timeout_secs: PERMISSION_TIMEOUT.as_secs(),
+    };
+    if let Err(e) = state.emit_to_conversation(conversation_id, events::ASSISTANT_TOOL_REQUEST, payload) {
+        log::error!("Failed to emit tool request {}: {:?}", request_id, e);
+        state.pending_tool_requests.remove(&request_id);
+        return false;
+    }
+
+    match tokio::time::timeout(PERMISSION_TIMEOUT, response).await {
+        Ok(Ok(allowed)) => allowed,
+        Ok(Err(_)) => false, // Responder dropped without an answer
+        Err(_) => {
+            log::warn!("Tool request {} for '{}' timed out, denying", request_id, tool_name);
+            state.pending_tool_requests.remove(&request_id);
+            false
+        }
+    }
+}
@@ -0,0 +1,305 @@
+// Parsing of plain-text/Markdown chat transcripts for import
+
+use crate::models::Conversation;
+use serde::{Deserialize, Serialize};
+
+/// Section headings that mark whose turn follows. A line matches a heading
+/// when, trimmed, it equals the heading (case-insensitive).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TranscriptFormat {
+    pub user_heading: String,
+    pub assistant_heading: String,
+    pub system_heading: String,
+}
+
+impl Default for TranscriptFormat {
+    fn default() -> Self {
+        Self {
+            user_heading: "## User".to_string(),
+            assistant_heading: "## Assistant".to_string(),
+            system_heading: "## System".to_string(),
+        }
+    }
+}
+
+impl TranscriptFormat {
+    fn role_for_heading(&self, line: &str) -> Option<&'static str> {
+        let line = line.trim();
+        [
+            (self.user_heading.as_str(), "user"),
+            (self.assistant_heading.as_str(), "assistant"),
+            (self.system_heading.as_str(), "system"),
+        ]
+        .into_iter()
+        .find(|(heading, _)| !heading.trim().is_empty() && line.eq_ignore_ascii_case(heading.trim()))
+        .map(|(_, role)| role)
+    }
+}
+
+/// A single turn recovered from a transcript.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscriptEntry {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedTranscript {
+    pub system_prompt: Option<String>,
+    pub entries: Vec<TranscriptEntry>,
+}
+
+/// Splits `text` into role sections. Content before the first heading, and any
+/// system sections, become the system prompt. Empty sections are dropped.
+pub fn parse_transcript(text: &str, format: &TranscriptFormat) -> ParsedTranscript {
+    let mut system_parts: Vec<String> = Vec::new();
+    let mut entries = Vec::new();
+    let mut current_role: Option<&'static str> = None;
+    let mut current_lines: Vec<&str> = Vec::new();
+
+    let mut flush = |role: Option<&'static str>, lines: &mut Vec<&str>| {
+        let content = lines.join("\n").trim().to_string();
+        lines.clear();
+        if content.is_empty() {
+            return;
+        }
+        match role {
+            None | Some("system") => system_parts.push(content),
+            Some(role) => entries.push(TranscriptEntry { role: role.to_string(), content }),
+        }
+    };
+
+    for line in text.lines() {
+        if let Some(role) = format.role_for_heading(line) {
+            flush(current_role, &mut current_lines);
+            current_role = Some(role);
+        } else {
+            current_lines.push(line);
+        }
+    }
+    flush(current_role, &mut current_lines);
+
+    ParsedTranscript {
+        system_prompt: if system_parts.is_empty() { None } else { Some(system_parts.join("\n\n")) },
+        entries,
+    }
+}
+
+/// Role delimiters recognized when importing Markdown exported by other tools.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DelimiterPattern {
+    BoldPrefix, // `**User:** hello`
+    Heading,    // `### User`
+    Separator,  // `---` between turns; roles alternate unless a block names its own
+}
+
+pub const DEFAULT_DELIMITER_PATTERNS: &[DelimiterPattern] =
+    &[DelimiterPattern::BoldPrefix, DelimiterPattern::Heading];
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkdownTranscript {
+    pub title: Option<String>, // First top-level heading that isn't a role
+    pub system_prompt: Option<String>,
+    pub entries: Vec<TranscriptEntry>,
+    pub warnings: Vec<String>,
+}
+
+fn role_from_label(label: &str) -> Option<&'static str> {
+    match label.trim().trim_end_matches(':').trim().to_ascii_lowercase().as_str() {
+        "user" | "human" | "you" => Some("user"),
+        "assistant" | "ai" | "bot" => Some("assistant"),
+        "system" => Some("system"),
+        _ => None,
+    }
+}
+
+// `**User:** rest` or `**User**: rest` -> (role, rest). Our own Markdown export
+// writes `**User** (timestamp)`; the timestamp is dropped.
+fn parse_bold_prefix(line: &str) -> Option<(&'static str, &str)> {
+    let inner = line.trim_start().strip_prefix("**")?;
+    let end = inner.find("**")?;
+    let role = role_from_label(&inner[..end])?;
+    let rest = inner[end + 2..].trim_start();
+    let rest = rest.strip_prefix(':').unwrap_or(rest).trim_start();
+    if rest.starts_with('(') && rest.ends_with(')') {
+        return Some((role, ""));
+    }
+    Some((role, rest))
+}
+
+// `### User` -> (level, Some(role)); `# Title` -> (1, None)
+fn parse_heading(line: &str) -> Option<(usize, Option<&'static str>, &str)> {
+    let trimmed = line.trim();
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    if level == 0 || !trimmed[level..].starts_with(' ') {
+        return None;
+    }
+    let text = trimmed[level..].trim();
+    Some((level, role_from_label(text), text))
+}
+
+/// Result of `import_markdown_conversation`, so the user can check what was recovered.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MarkdownImportSummary {
+    pub conversation: Conversation,
+    pub user_messages: usize,
+    pub assistant_messages: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Parses a Markdown chat export. Content before the first turn becomes the
+/// system prompt; system sections found mid-conversation can't be represented,
+/// so they are appended to the preceding message with a warning.
+pub fn parse_markdown_transcript(text: &str, patterns: &[DelimiterPattern]) -> MarkdownTranscript {
+    let uses = |pattern: DelimiterPattern| patterns.contains(&pattern);
+    let mut result = MarkdownTranscript::default();
+    let mut current_role: Option<&'static str> = None;
+    let mut current_lines: Vec<String> = Vec::new();
+    let mut last_turn_role: Option<&'static str> = None;
+    let mut after_separator = false;
+
+    fn flush(
+        result: &mut MarkdownTranscript,
+        role: Option<&'static str>,
+        lines: &mut Vec<String>,
+        line_number: usize,
+    ) {
+        let content = lines.join("\n").trim().to_string();
+        lines.clear();
+        if content.is_empty() {
+            return;
+        }
+        match role {
+            None => {
+                result.system_prompt = Some(content);
+            }
+            Some("system") => match result.entries.last_mut() {
+                Some(previous) => {
+                    previous.content.push_str("\n\n");
+                    previous.content.push_str(&content);
+                    result.warnings.push(format!(
+                        "System section before line {} was appended to the preceding message",
+                        line_number
+                    ));
+                }
+                None => {
+                    result.system_prompt = Some(match result.system_prompt.take() {
+                        Some(existing) => format!("{}\n\n{}", existing, content),
+                        None => content,
+                    });
+                }
+            },
+            Some(role) => result.entries.push(TranscriptEntry { role: role.to_string(), content }),
+        }
+    }
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let mut starts: Option<(&'static str, String)> = None;
+
+        if uses(DelimiterPattern::Heading) {
+            if let Some((level, role, heading_text)) = parse_heading(line) {
+                match role {
+                    Some(role) => starts = Some((role, String::new())),
+                    None if level == 1 && result.title.is_none() && result.entries.is_empty() && current_role.is_none() => {
+                        result.title = Some(heading_text.to_string());
+                        continue;
+                    }
+                    None => {}
+                }
+            }
+        }
+        if starts.is_none() && uses(DelimiterPattern::BoldPrefix) {
+            if let Some((role, rest)) = parse_bold_prefix(line) {
+                starts = Some((role, rest.to_string()));
+            }
+        }
+        if starts.is_none() && uses(DelimiterPattern::Separator) && line.trim() == "---" {
+            flush(&mut result, current_role, &mut current_lines, line_number);
+            if current_role.is_some() {
+                after_separator = true;
+            }
+            continue;
+        }
+
+        match starts {
+            Some((role, rest)) => {
+                flush(&mut result, current_role, &mut current_lines, line_number);
+                current_role = Some(role);
+                if role != "system" {
+                    last_turn_role = Some(role);
+                }
+                after_separator = false;
+                if !rest.is_empty() {
+                    current_lines.push(rest);
+                }
+            }
+            None => {
+                if after_separator && !line.trim().is_empty() {
+                    // Unlabelled block after `---`: alternate from the previous turn
+                    let role = match last_turn_role {
+                        Some("user") => "assistant",
+                        _ => "user",
+                    };
+                    current_role = Some(role);
+                    last_turn_role = Some(role);
+                    after_separator = false;
+                }
+                current_lines.push(line.to_string());
+            }
+        }
+    }
+    flush(&mut result, current_role, &mut current_lines, text.lines().count() + 1);
+
+    if result.system_prompt.is_some() {
+        result.warnings.push("Text before the first turn was imported as the system prompt".to_string());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(role: &str, content: &str) -> TranscriptEntry {
+        TranscriptEntry { role: role.to_string(), content: content.to_string() }
+    }
+
+    #[test]
+    fn well_formed_transcripts_split_into_turns() {
+        let text = "## System\nBe brief.\n\n## User\nHi there\n\nSecond line\n## Assistant\nHello!\n## user\nBye";
+        let parsed = parse_transcript(text, &TranscriptFormat::default());
+        assert_eq!(parsed.system_prompt.as_deref(), Some("Be brief."));
+        assert_eq!(
+            parsed.entries,
+            vec![entry("user", "Hi there\n\nSecond line"), entry("assistant", "Hello!"), entry("user", "Bye")]
+        );
+    }
+
+    #[test]
+    fn preamble_and_empty_sections_are_handled() {
+        let text = "Exported chat\n## User\n\n   \n## Assistant\nOnly answer\n\n\n## User\n";
+        let parsed = parse_transcript(text, &TranscriptFormat::default());
+        // Text before the first heading is kept as the system prompt
+        assert_eq!(parsed.system_prompt.as_deref(), Some("Exported chat"));
+        assert_eq!(parsed.entries, vec![entry("assistant", "Only answer")]);
+
+        assert_eq!(parse_transcript("", &TranscriptFormat::default()), ParsedTranscript::default());
+    }
+
+    #[test]
+    fn custom_headings_are_recognized() {
+        let format = TranscriptFormat {
+            user_heading: "Q:".to_string(),
+            assistant_heading: "A:".to_string(),
+            system_heading: String::new(),
+        };
+        let parsed = parse_transcript("Q:\nWhy?\nA:\nBecause.\n## User\nliteral", &format);
+        assert_eq!(parsed.system_prompt, None);
+        assert_eq!(parsed.entries, vec![entry("user", "Why?"), entry("assistant", "Because.\n## User\nliteral")]);
+    }
+}
+
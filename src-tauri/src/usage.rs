@@ -0,0 +1,182 @@
+// Usage totals for export: assistant messages grouped by conversation or day and model,
+// with the tokens and cost recorded in their metadata. Also the per-day activity series
+// behind the history heatmap, and the cost estimate shown before sending.
+
+use crate::api::{ParsedProviderOptions, TokenUsage};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// What each usage row totals.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGrouping {
+    Conversation,
+    Day,
+}
+
+impl UsageGrouping {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "conversation" => Ok(Self::Conversation),
+            "day" => Ok(Self::Day),
+            other => Err(format!("Unknown usage grouping: {}", other)),
+        }
+    }
+}
+
+/// Totals for one conversation (or local day) and model.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageRow {
+    pub key: String, // Conversation ID, or the day as YYYY-MM-DD
+    pub label: String, // Conversation title; the day again when grouped by day
+    pub model: String, // Empty for messages saved before the model was recorded
+    pub message_count: i64, // Assistant messages
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost_usd: Option<f64>, // None when no message in the row has cost data
+}
+
+/// Renders `rows` as CSV with a header line. Lines end in CRLF, per RFC 4180.
+pub fn to_csv(rows: &[UsageRow], grouping: UsageGrouping) -> String {
+    let header = match grouping {
+        UsageGrouping::Conversation => "conversation_id,title",
+        UsageGrouping::Day => "date",
+    };
+    let mut csv = format!("{},model,message_count,prompt_tokens,completion_tokens,cost_usd\r\n", header);
+    for row in rows {
+        let leading = match grouping {
+            UsageGrouping::Conversation => format!("{},{}", csv_field(&row.key), csv_field(&row.label)),
+            UsageGrouping::Day => csv_field(&row.key),
+        };
+        let cost = row.cost_usd.map(|cost| format!("{:.6}", cost)).unwrap_or_default();
+        csv.push_str(&format!(
+            "{},{},{},{},{},{}\r\n",
+            leading,
+            csv_field(&row.model),
+            row.message_count,
+            row.prompt_tokens,
+            row.completion_tokens,
+            cost
+        ));
+    }
+    csv
+}
+
+// Quotes a field when it contains a comma, quote or line break, doubling inner quotes
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Longest range `get_activity_stats` covers in one call: two years, counting a leap day
+pub const MAX_ACTIVITY_DAYS: i64 = 731;
+
+/// Activity on one local calendar day.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityDay {
+    pub date: String, // YYYY-MM-DD in the caller's timezone
+    pub message_count: i64,
+    pub conversations_created: i64,
+}
+
+/// One entry per day from `from` to `to` inclusive, with zeros for days missing from
+/// `counts` (date -> (messages, conversations created)).
+pub fn fill_activity(from: NaiveDate, to: NaiveDate, counts: &HashMap<NaiveDate, (i64, i64)>) -> Vec<ActivityDay> {
+    from.iter_days()
+        .take_while(|day| *day <= to)
+        .map(|day| {
+            let (message_count, conversations_created) = counts.get(&day).copied().unwrap_or((0, 0));
+            ActivityDay { date: day.format("%Y-%m-%d").to_string(), message_count, conversations_created }
+        })
+        .collect()
+}
+
+/// Payload of `estimate_message_cost`: what sending a pending message would cost at most.
+/// Costs are `None` when the config has no pricing; the output bounds also need `max_tokens`.
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CostEstimate {
+    pub prompt_tokens: usize, // Estimated, as in `get_context_usage`
+    pub max_output_tokens: Option<u32>, // The config's max_tokens
+    pub prompt_cost_usd: Option<f64>,
+    pub max_output_cost_usd: Option<f64>,
+    pub max_total_cost_usd: Option<f64>,
+}
+
+pub fn estimate_cost(prompt_tokens: usize, options: &ParsedProviderOptions) -> CostEstimate {
+    let cost = |prompt_tokens: u64, completion_tokens: u64| options.cost_usd(&TokenUsage { prompt_tokens, completion_tokens });
+    let prompt_cost_usd = cost(prompt_tokens as u64, 0);
+    let max_output_cost_usd = options.max_tokens.and_then(|max| cost(0, u64::from(max)));
+    CostEstimate {
+        prompt_tokens,
+        max_output_tokens: options.max_tokens,
+        prompt_cost_usd,
+        max_output_cost_usd,
+        max_total_cost_usd: prompt_cost_usd.zip(max_output_cost_usd).map(|(prompt, output)| prompt + output),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(key: &str, label: &str, model: &str, cost_usd: Option<f64>) -> UsageRow {
+        UsageRow {
+            key: key.to_string(),
+            label: label.to_string(),
+            model: model.to_string(),
+            message_count: 3,
+            prompt_tokens: 120,
+            completion_tokens: 45,
+            cost_usd,
+        }
+    }
+
+    #[test]
+    fn csv_has_a_header_and_escapes_fields() {
+        let rows = [
+            row("c1", "Plans, \"draft\"", "llama-3, 8b", Some(0.0125)),
+            row("c2", "Notes", "gpt-4o", None),
+        ];
+        assert_eq!(
+            to_csv(&rows, UsageGrouping::Conversation),
+            "conversation_id,title,model,message_count,prompt_tokens,completion_tokens,cost_usd\r\n\
+             c1,\"Plans, \"\"draft\"\"\",\"llama-3, 8b\",3,120,45,0.012500\r\n\
+             c2,Notes,gpt-4o,3,120,45,\r\n"
+        );
+        assert_eq!(
+            to_csv(&[row("2026-10-01", "2026-10-01", "gpt-4o", Some(1.0))], UsageGrouping::Day),
+            "date,model,message_count,prompt_tokens,completion_tokens,cost_usd\r\n2026-10-01,gpt-4o,3,120,45,1.000000\r\n"
+        );
+    }
+
+
+    #[test]
+    fn cost_estimate_prices_the_prompt_and_the_longest_answer() {
+        let options = |json: &str| ParsedProviderOptions::from_config(&crate::test_support::model_config("Test", json)).unwrap();
+        let close = |actual: Option<f64>, expected: f64| assert!(actual.is_some_and(|actual| (actual - expected).abs() < 1e-12), "{:?}", actual);
+
+        let estimate = estimate_cost(
+            2000,
+            &options(r#"{"model": "m", "max_tokens": 1000, "input_cost_per_mtok": 2.5, "output_cost_per_mtok": 10.0}"#),
+        );
+        assert_eq!((estimate.prompt_tokens, estimate.max_output_tokens), (2000, Some(1000)));
+        close(estimate.prompt_cost_usd, 0.005);
+        close(estimate.max_output_cost_usd, 0.01);
+        close(estimate.max_total_cost_usd, 0.015);
+
+        // Without max_tokens the answer has no bound
+        let estimate = estimate_cost(2000, &options(r#"{"model": "m", "input_cost_per_mtok": 2.5, "output_cost_per_mtok": 10.0}"#));
+        close(estimate.prompt_cost_usd, 0.005);
+        assert_eq!((estimate.max_output_cost_usd, estimate.max_total_cost_usd), (None, None));
+
+        let estimate = estimate_cost(2000, &options(r#"{"model": "m", "max_tokens": 1000}"#));
+        assert_eq!((estimate.prompt_cost_usd, estimate.max_output_cost_usd), (None, None));
+    }
+}
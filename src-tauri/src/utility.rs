@@ -0,0 +1,110 @@
+// Queue for background utility-model requests (titles, summaries). They run at most
+// UTILITY_CONCURRENCY at a time and at least UTILITY_MIN_INTERVAL apart, so a burst of
+// them can't trip provider rate limits while a chat is streaming. Also the registry used to
+// cancel a conversation's background work: queued requests are dropped and tasks holding a
+// `TaskToken` stop at their next check.
+
+use dashmap::DashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{watch, Mutex, Semaphore};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+pub const UTILITY_CONCURRENCY: usize = 2;
+pub const UTILITY_MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct UtilityQueue {
+    permits: Semaphore,
+    queued: AtomicUsize, // Jobs waiting for a slot
+    next_start: Mutex<Instant>, // Earliest time the next job may start
+    cancellations: DashMap<Uuid, watch::Sender<bool>>, // Conversation ID -> cancel signal for its jobs
+    cancel_counts: DashMap<Uuid, u64>, // Conversation ID -> times its tasks were cancelled; see `TaskToken`
+}
+
+/// Lets a background task for a conversation (e.g. title generation) notice that the
+/// conversation's tasks were cancelled after it started. Check `is_cancelled` between steps.
+pub struct TaskToken {
+    conversation_id: Uuid,
+    cancel_count: u64,
+}
+
+// Counts a job as queued until it gets a slot or is dropped
+struct QueuedGuard<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Default for UtilityQueue {
+    fn default() -> Self {
+        Self {
+            permits: Semaphore::new(UTILITY_CONCURRENCY),
+            queued: AtomicUsize::new(0),
+            next_start: Mutex::new(Instant::now()),
+            cancellations: DashMap::new(),
+            cancel_counts: DashMap::new(),
+        }
+    }
+}
+
+impl UtilityQueue {
+    /// Runs `job` once a slot is free. Returns None if `conversation_id` is cancelled
+    /// (e.g. the conversation was deleted) while the job waits or runs.
+    pub async fn run<T>(&self, conversation_id: Uuid, label: &str, job: impl Future<Output = T>) -> Option<T> {
+        let mut cancelled = self
+            .cancellations
+            .entry(conversation_id)
+            .or_insert_with(|| watch::channel(false).0)
+            .subscribe();
+        let position = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        log::info!("Utility request '{}' for conversation {} queued at position {}", label, conversation_id, position);
+
+        let work = async {
+            let queued = QueuedGuard(&self.queued);
+            let _permit = self.permits.acquire().await.ok()?;
+            drop(queued);
+            {
+                let mut next_start = self.next_start.lock().await;
+                tokio::time::sleep_until(*next_start).await;
+                *next_start = Instant::now() + UTILITY_MIN_INTERVAL;
+            }
+            log::debug!("Utility request '{}' for conversation {} started", label, conversation_id);
+            Some(job.await)
+        };
+        let result = tokio::select! {
+            result = work => result,
+            _ = cancelled.wait_for(|cancelled| *cancelled) => {
+                log::info!("Utility request '{}' for conversation {} cancelled", label, conversation_id);
+                None
+            }
+        };
+
+        drop(cancelled);
+        self.cancellations.remove_if(&conversation_id, |_, sender| sender.receiver_count() == 0);
+        result
+    }
+
+    /// Cancels every queued or running job for `conversation_id`, and flags the tasks
+    /// holding a `TaskToken` for it.
+    pub fn cancel_conversation(&self, conversation_id: Uuid) {
+        *self.cancel_counts.entry(conversation_id).or_insert(0) += 1;
+        if let Some((_, sender)) = self.cancellations.remove(&conversation_id) {
+            let _ = sender.send(true);
+        }
+    }
+
+    /// A token for a task of `conversation_id` starting now.
+    pub fn task_token(&self, conversation_id: Uuid) -> TaskToken {
+        let cancel_count = self.cancel_counts.get(&conversation_id).map_or(0, |count| *count);
+        TaskToken { conversation_id, cancel_count }
+    }
+
+    /// Whether the token's conversation had its tasks cancelled since the token was taken.
+    pub fn is_cancelled(&self, token: &TaskToken) -> bool {
+        self.cancel_counts.get(&token.conversation_id).is_some_and(|count| *count != token.cancel_count)
+    }
+}